@@ -4,11 +4,13 @@
 //!
 use crate::{
     kinode::process::{
-        chain::{ChainRequest, ChainResponse},
+        chain::{ChainRequest, ChainResponse, PublishReviewRequest, ReleaseNotes},
         downloads::{
             DownloadRequest, DownloadResponse, Entry, LocalDownloadRequest, RemoveFileRequest,
         },
+        main::{TelemetryRequest, TelemetryResponse},
     },
+    licenses,
     state::{MirrorCheck, PackageState, State, Updates},
 };
 use kinode_process_lib::{
@@ -38,6 +40,9 @@ pub fn init_frontend(our: &Address, http_server: &mut server::HttpServer) {
         // actions
         "/apps/:id/download",     // download a listed app
         "/apps/:id/install",      // install a downloaded app
+        "/apps/:id/reviews",      // reviews of a listed app, and ours to publish
+        "/apps/:id/adoption",     // install counts of an app we publish
+        "/apps/:id/license",      // license to accept before installing an app
         "/downloads/:id/mirror",  // start mirroring a version of a downloaded app
         "/downloads/:id/remove",  // remove a downloaded app
         "/reset",                 // reset chain state, re-index
@@ -71,6 +76,9 @@ pub fn init_frontend(our: &Address, http_server: &mut server::HttpServer) {
         Some("/"),
         Some(&make_widget()),
     );
+
+    // answer searches from the homepage's launcher with listed apps
+    crate::search_api::register_search_provider(our, "App Store");
 }
 
 fn make_widget() -> String {
@@ -194,6 +202,7 @@ fn make_widget() -> String {
 /// - get all installed apps: GET /installed
 /// - get all apps we've published: GET /ourapps
 /// - get detail about a specific app: GET /apps/:id
+/// - get the reviews of a specific app: GET /apps/:id/reviews
 /// - get detail about a specific apps downloads: GET /downloads/:id
 /// - get manifest of a specific downloaded app: GET /manifest?id={id}&version_hash={version_hash}
 /// - remove a downloaded app: POST /downloads/:id/remove
@@ -206,6 +215,7 @@ fn make_widget() -> String {
 /// - stop mirroring a downloaded app: DELETE /apps/:id/mirror
 /// - start auto-updating a downloaded app: PUT /apps/:id/auto-update
 /// - stop auto-updating a downloaded app: DELETE /apps/:id/auto-update
+/// - publish our review of an app: POST /apps/:id/reviews
 ///
 /// - RebuildIndex: POST /apps/rebuild-index // TODO, this could be just terminal I think?
 pub fn handle_http_request(
@@ -241,6 +251,48 @@ fn get_package_id(params: &HashMap<String, String>) -> anyhow::Result<PackageId>
     Ok(id)
 }
 
+/// The release notes of the versions installing `version_hash` brings, newest
+/// first: from that version down to the one we have installed, if any. Empty
+/// if the app is not listed, or its publisher gave no notes.
+fn whats_new(
+    state: &State,
+    package_id: &crate::kinode::process::main::PackageId,
+    version_hash: &str,
+) -> Vec<ReleaseNotes> {
+    let Ok(Ok(resp)) = Request::to(("our", "chain", "app-store", "sys"))
+        .body(serde_json::to_vec(&ChainRequest::GetApp(package_id.clone())).unwrap())
+        .send_and_await_response(5)
+    else {
+        return vec![];
+    };
+    let Ok(ChainResponse::GetApp(Some(app))) = serde_json::from_slice(resp.body()) else {
+        return vec![];
+    };
+    let Some(metadata) = app.metadata else {
+        return vec![];
+    };
+    let version_of = |hash: &str| {
+        metadata
+            .properties
+            .code_hashes
+            .iter()
+            .find(|(_, code_hash)| code_hash == hash)
+            .map(|(version, _)| version.clone())
+    };
+    let Some(version) = version_of(version_hash) else {
+        return vec![];
+    };
+    let installed = state
+        .packages
+        .get(&package_id.clone().to_process_lib())
+        .and_then(|package| version_of(&package.our_version_hash));
+    app.changelog
+        .into_iter()
+        .skip_while(|release| release.version != version)
+        .take_while(|release| Some(&release.version) != installed.as_ref())
+        .collect()
+}
+
 fn gen_package_info(id: &PackageId, state: &PackageState) -> serde_json::Value {
     // installed package info
     json!({
@@ -325,6 +377,174 @@ fn serve_paths(
                 )),
             }
         }
+        // GET the reviews of an app, newest first
+        // POST our review of an app, with rating and text
+        "/apps/:id/reviews" => {
+            let Ok(package_id) = get_package_id(url_params) else {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    None,
+                    format!("Missing id").into_bytes(),
+                ));
+            };
+            let package_id = crate::kinode::process::main::PackageId::from_process_lib(package_id);
+            let chain_request = match method {
+                Method::GET => ChainRequest::GetReviews(package_id),
+                Method::POST => {
+                    let body = crate::get_blob()
+                        .ok_or(anyhow::anyhow!("missing blob"))?
+                        .bytes;
+                    let body_json: serde_json::Value =
+                        serde_json::from_slice(&body).unwrap_or_default();
+                    let Some(rating) = body_json
+                        .get("rating")
+                        .and_then(|v| v.as_u64())
+                        .and_then(|v| u8::try_from(v).ok())
+                    else {
+                        return Ok((
+                            StatusCode::BAD_REQUEST,
+                            None,
+                            format!("No rating specified!").into_bytes(),
+                        ));
+                    };
+                    let text = body_json
+                        .get("text")
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.to_string());
+                    ChainRequest::PublishReview(PublishReviewRequest {
+                        package_id,
+                        rating,
+                        text,
+                    })
+                }
+                _ => {
+                    return Ok((
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        None,
+                        format!("Invalid method {method} for {bound_path}").into_bytes(),
+                    ))
+                }
+            };
+
+            let resp = Request::to(("our", "chain", "app-store", "sys"))
+                .body(serde_json::to_vec(&chain_request)?)
+                .send_and_await_response(5)??;
+            let msg = serde_json::from_slice::<ChainResponse>(resp.body())?;
+            match msg {
+                ChainResponse::GetReviews(reviews) => {
+                    Ok((StatusCode::OK, None, serde_json::to_vec(&reviews)?))
+                }
+                ChainResponse::ReviewPublished => Ok((StatusCode::OK, None, vec![])),
+                ChainResponse::Err(e) => {
+                    Ok((StatusCode::BAD_REQUEST, None, serde_json::to_vec(&e)?))
+                }
+                _ => Err(anyhow::anyhow!("Invalid response from chain: {:?}", msg)),
+            }
+        }
+        // GET the install and update counts of each version of an app: ours,
+        // or those of the aggregator in the `aggregator` query param
+        "/apps/:id/adoption" => {
+            let Ok(package_id) = get_package_id(url_params) else {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    None,
+                    format!("Missing id").into_bytes(),
+                ));
+            };
+            let Some(aggregator) = query_params.get("aggregator") else {
+                return Ok((
+                    StatusCode::OK,
+                    None,
+                    serde_json::to_vec(&state.telemetry.adoption(&package_id))?,
+                ));
+            };
+            if package_id.publisher() != our.node() {
+                return Ok((
+                    StatusCode::FORBIDDEN,
+                    None,
+                    format!("Only the publisher may ask an aggregator").into_bytes(),
+                ));
+            }
+            let resp = Request::to((aggregator.as_str(), "main", "app-store", "sys"))
+                .body(serde_json::to_vec(&TelemetryRequest::GetAdoption(
+                    crate::kinode::process::main::PackageId::from_process_lib(package_id),
+                ))?)
+                .send_and_await_response(5)??;
+            match serde_json::from_slice::<TelemetryResponse>(resp.body())? {
+                TelemetryResponse::GetAdoption(adoption) => {
+                    Ok((StatusCode::OK, None, serde_json::to_vec(&adoption)?))
+                }
+                TelemetryResponse::NotPublisher => Ok((StatusCode::FORBIDDEN, None, vec![])),
+            }
+        }
+        // GET the license to accept before installing an app, with its text
+        // and our acceptance of it, if any
+        // POST our acceptance of the license with the `hash` in the body
+        "/apps/:id/license" => {
+            let Ok(package_id) = get_package_id(url_params) else {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    None,
+                    format!("Missing id").into_bytes(),
+                ));
+            };
+            let Some(license) = licenses::license_to_accept(&package_id)? else {
+                return Ok((
+                    StatusCode::NOT_FOUND,
+                    None,
+                    format!("No license to accept for {package_id}").into_bytes(),
+                ));
+            };
+            match method {
+                Method::GET => {
+                    let text = match licenses::fetch_license_text(&license) {
+                        Ok(text) => text,
+                        Err(e) => {
+                            return Ok((
+                                StatusCode::BAD_GATEWAY,
+                                None,
+                                format!("Failed to fetch license: {e}").into_bytes(),
+                            ))
+                        }
+                    };
+                    let body = json!({
+                        "uri": license.uri,
+                        "hash": license.hash,
+                        "text": text,
+                        "accepted": state.licenses.get(&package_id),
+                    });
+                    Ok((StatusCode::OK, None, serde_json::to_vec(&body)?))
+                }
+                Method::POST => {
+                    let body = crate::get_blob()
+                        .ok_or(anyhow::anyhow!("missing blob"))?
+                        .bytes;
+                    let body_json: serde_json::Value =
+                        serde_json::from_slice(&body).unwrap_or_default();
+                    let Some(hash) = body_json.get("hash").and_then(|v| v.as_str()) else {
+                        return Ok((
+                            StatusCode::BAD_REQUEST,
+                            None,
+                            format!("No hash specified!").into_bytes(),
+                        ));
+                    };
+                    if hash != license.hash {
+                        return Ok((
+                            StatusCode::CONFLICT,
+                            None,
+                            format!("The license has changed, review it again").into_bytes(),
+                        ));
+                    }
+                    state.licenses.accept(&package_id, hash);
+                    Ok((StatusCode::OK, None, vec![]))
+                }
+                _ => Ok((
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    None,
+                    format!("Invalid method {method} for {bound_path}").into_bytes(),
+                )),
+            }
+        }
         "/downloads" => {
             // get all local downloads!
             let resp = Request::to(("our", "downloads", "app-store", "sys"))
@@ -413,6 +633,7 @@ fn serve_paths(
                                 "package_id": package_id,
                                 "version_hash": version_hash,
                                 "manifest": file.manifest,
+                                "whats_new": whats_new(state, &package_id, version_hash),
                             });
                             return Ok((StatusCode::OK, None, serde_json::to_vec(&response)?));
                         }
@@ -544,6 +765,19 @@ fn serve_paths(
                 .map(|s| s.to_string())
                 .ok_or_else(|| anyhow::anyhow!("No version_hash specified!"))?;
 
+            // apps whose publisher asks for a license to be accepted are only
+            // installed once the user has accepted the license as it is now
+            if let Some(license) = licenses::license_to_accept(&package_id)? {
+                if !state.licenses.is_accepted(&package_id, &license.hash) {
+                    return Ok((
+                        StatusCode::FORBIDDEN,
+                        None,
+                        format!("License required: accept the license of {package_id} first")
+                            .into_bytes(),
+                    ));
+                }
+            }
+
             let process_package_id =
                 crate::kinode::process::main::PackageId::from_process_lib(package_id);
 
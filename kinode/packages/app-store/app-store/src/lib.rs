@@ -33,13 +33,15 @@ use crate::kinode::process::downloads::{
     AutoDownloadCompleteRequest, DownloadCompleteRequest, DownloadResponse, ProgressUpdate,
 };
 use crate::kinode::process::main::{
-    ApisResponse, GetApiResponse, InstallPackageRequest, InstallResponse, LocalRequest,
-    LocalResponse, NewPackageRequest, NewPackageResponse, UninstallResponse,
+    ApisResponse, GetApiResponse, InstallPackageRequest, InstallResponse, InstalledPackage,
+    LocalRequest, LocalResponse, NewPackageRequest, NewPackageResponse, OrphanedDrivesResponse,
+    TelemetryRequest, TelemetryResponse, UninstallResponse,
 };
 use kinode_process_lib::{
     await_message, call_init, get_blob, http, print_to_terminal, println, vfs, Address,
-    LazyLoadBlob, Message, PackageId, Response,
+    LazyLoadBlob, Message, PackageId, Request, Response,
 };
+use search_api::{SearchRequest, SearchResponse};
 use serde::{Deserialize, Serialize};
 use state::{State, UpdateInfo, Updates};
 
@@ -51,7 +53,10 @@ wit_bindgen::generate!({
 });
 
 mod http_api;
+pub mod licenses;
+mod search_api;
 pub mod state;
+pub mod telemetry;
 pub mod utils;
 
 const VFS_TIMEOUT: u64 = 10;
@@ -62,9 +67,11 @@ const VFS_TIMEOUT: u64 = 10;
 #[serde(untagged)] // untagged as a meta-type for all incoming requests
 pub enum Req {
     LocalRequest(LocalRequest),
+    Telemetry(TelemetryRequest),
     Progress(ProgressUpdate),
     DownloadComplete(DownloadCompleteRequest),
     AutoDownloadComplete(AutoDownloadCompleteRequest),
+    Search(SearchRequest),
     Http(http::server::HttpServerRequest),
 }
 
@@ -82,7 +89,7 @@ fn init(our: Address) {
 
     // state = state built from the filesystem, installed packages
     // updates = state saved with get/set_state(), auto_update metadata.
-    let mut state = State::load().expect("state loading failed");
+    let mut state = State::load(&our).expect("state loading failed");
     let mut updates = Updates::load();
     loop {
         match await_message() {
@@ -111,6 +118,12 @@ fn handle_message(
     http_server: &mut http::server::HttpServer,
     message: &Message,
 ) -> anyhow::Result<()> {
+    if message.is_local(our) && message.source().process == "timer:distro:sys" {
+        if message.context() == Some(telemetry::REPORT_TIMER) {
+            state.telemetry.report();
+        }
+        return Ok(());
+    }
     if message.is_request() {
         match message.body().try_into()? {
             Req::LocalRequest(local_request) => {
@@ -125,6 +138,31 @@ fn handle_message(
                     response.send()?;
                 }
             }
+            Req::Telemetry(request) => {
+                if message.source().process != "main:app-store:sys" {
+                    return Err(anyhow::anyhow!("telemetry not from an app store"));
+                }
+                match request {
+                    TelemetryRequest::Report(events) => {
+                        state.telemetry.count(&our.node, events);
+                    }
+                    TelemetryRequest::GetAdoption(package_id) => {
+                        // counts are only given to the publisher of the app
+                        let response = if message.is_local(our)
+                            || message.source().node() == package_id.publisher_node
+                        {
+                            TelemetryResponse::GetAdoption(
+                                state.telemetry.adoption(&package_id.to_process_lib()),
+                            )
+                        } else {
+                            TelemetryResponse::NotPublisher
+                        };
+                        Response::new()
+                            .body(serde_json::to_vec(&response)?)
+                            .send()?;
+                    }
+                }
+            }
             Req::Http(server_request) => {
                 if !message.is_local(&our) || message.source().process != "http-server:distro:sys" {
                     return Err(anyhow::anyhow!("http-server from non-local node"));
@@ -191,6 +229,13 @@ fn handle_message(
                                 utils::install(&package_id, None, &version_hash, state, &our.node)
                             {
                                 println!("error auto-installing package: {e}");
+                                notify(serde_json::json!({
+                                    "AutoUpdateFailed": {
+                                        "package_id": process_lib_package_id.to_string(),
+                                        "version_hash": version_hash,
+                                        "error": e.to_string(),
+                                    }
+                                }));
                                 // Get or create the outer map for this package
                                 updates
                                     .package_updates
@@ -211,6 +256,13 @@ fn handle_message(
                             }
                         } else {
                             // TODO.
+                            notify(serde_json::json!({
+                                "AutoUpdateFailed": {
+                                    "package_id": process_lib_package_id.to_string(),
+                                    "version_hash": version_hash,
+                                    "error": "it asks for new capabilities: approve them in the App Store",
+                                }
+                            }));
                             updates
                                 .package_updates
                                 .entry(package_id.to_process_lib())
@@ -227,6 +279,13 @@ fn handle_message(
                     }
                     AutoDownloadCompleteRequest::Err(err) => {
                         println!("error auto-downloading package: {err:?}");
+                        notify(serde_json::json!({
+                            "AutoUpdateFailed": {
+                                "package_id": err.package_id.clone().to_process_lib().to_string(),
+                                "version_hash": err.version_hash,
+                                "error": format!("no mirror could serve it: {:?}", err.tries),
+                            }
+                        }));
                         updates
                             .package_updates
                             .entry(err.package_id.to_process_lib())
@@ -242,6 +301,15 @@ fn handle_message(
                     }
                 }
             }
+            Req::Search(SearchRequest::Search(query)) => {
+                if !message.is_local(&our) || message.source().process != "homepage:homepage:sys" {
+                    return Err(anyhow::anyhow!("search not from homepage"));
+                }
+                let results = utils::search_listings(&query)?;
+                Response::new()
+                    .body(serde_json::to_vec(&SearchResponse::Search(results))?)
+                    .send()?;
+            }
             Req::DownloadComplete(req) => {
                 if !message.is_local(&our) {
                     return Err(anyhow::anyhow!("download complete from non-local node"));
@@ -340,9 +408,35 @@ fn handle_local_request(
         ),
         LocalRequest::Apis => (list_apis(state), None),
         LocalRequest::GetApi(package_id) => get_api(state, &package_id.to_process_lib()),
+        LocalRequest::Installed => (list_installed(state), None),
+        LocalRequest::OrphanedDrives(delete) => (
+            LocalResponse::OrphanedDrivesResponse(match utils::orphaned_drives(state, delete) {
+                Ok(drives) => OrphanedDrivesResponse::Success(drives),
+                Err(e) => OrphanedDrivesResponse::Failure(e.to_string()),
+            }),
+            None,
+        ),
+        LocalRequest::GetTelemetrySettings => (
+            LocalResponse::TelemetrySettingsResponse(state.telemetry.settings.clone()),
+            None,
+        ),
+        LocalRequest::SetTelemetrySettings(settings) => {
+            state.telemetry.set_settings(&our.node, settings);
+            (
+                LocalResponse::TelemetrySettingsResponse(state.telemetry.settings.clone()),
+                None,
+            )
+        }
     }
 }
 
+/// Report an event to notify:distro:sys, to be notified if the user wants.
+fn notify(event: serde_json::Value) {
+    let _ = Request::to(("our", "notify", "distro", "sys"))
+        .body(serde_json::to_vec(&serde_json::json!({ "Event": event })).unwrap())
+        .send();
+}
+
 pub fn get_api(state: &mut State, package_id: &PackageId) -> (LocalResponse, Option<LazyLoadBlob>) {
     if !state.installed_apis.contains(package_id) {
         return (LocalResponse::GetApiResponse(GetApiResponse::Failure), None);
@@ -364,6 +458,23 @@ pub fn get_api(state: &mut State, package_id: &PackageId) -> (LocalResponse, Opt
     )
 }
 
+pub fn list_installed(state: &mut State) -> LocalResponse {
+    LocalResponse::InstalledResponse(
+        state
+            .packages
+            .iter()
+            .map(|(package_id, package_state)| InstalledPackage {
+                package_id: crate::kinode::process::main::PackageId::from_process_lib(
+                    package_id.clone(),
+                ),
+                version_hash: package_state.our_version_hash.clone(),
+                verified: package_state.verified,
+                caps_approved: package_state.caps_approved,
+            })
+            .collect(),
+    )
+}
+
 pub fn list_apis(state: &mut State) -> LocalResponse {
     LocalResponse::ApisResponse(ApisResponse {
         apis: state
@@ -0,0 +1,109 @@
+//! Licenses that publishers ask users to accept before installing their apps.
+//!
+//! A publisher declares one in the `license_acceptance` property of their
+//! metadata, as the URL of its text and the hash of that text, so that the
+//! text can't change without the metadata, and so the listing, changing.
+//! We keep the hash of each license the user accepted, and when, and only
+//! install an app asked for over HTTP once its current license is accepted.
+use crate::{
+    kinode::process::chain::{ChainRequest, ChainResponse, LicenseAcceptance},
+    utils, VFS_TIMEOUT,
+};
+use kinode_process_lib::{get_blob, http, println, vfs, Address, PackageId, Request};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const LICENSES_FILE: &str = "/app-store:sys/licenses/accepted.json";
+/// the largest license text we fetch, in bytes
+const MAX_LICENSE_LENGTH: usize = 256 * 1024;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcceptedLicense {
+    /// hash of the text accepted
+    pub hash: String,
+    /// seconds since the epoch
+    pub timestamp: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Licenses {
+    /// package ID -> the license last accepted for it
+    accepted: HashMap<String, AcceptedLicense>,
+}
+
+impl Licenses {
+    /// Load from our licenses drive.
+    pub fn load(our: &Address) -> Self {
+        if let Err(e) = vfs::create_drive(our.package_id(), "licenses", Some(VFS_TIMEOUT)) {
+            println!("failed to create licenses drive: {e:?}");
+        }
+        vfs::open_file(LICENSES_FILE, false, Some(VFS_TIMEOUT))
+            .and_then(|file| file.read())
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Licenses>(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let result = vfs::create_file(LICENSES_FILE, Some(VFS_TIMEOUT))
+            .and_then(|file| file.write(&serde_json::to_vec(self).unwrap()));
+        if let Err(e) = result {
+            println!("failed to save licenses: {e:?}");
+        }
+    }
+
+    /// The license last accepted for an app, if any.
+    pub fn get(&self, package_id: &PackageId) -> Option<&AcceptedLicense> {
+        self.accepted.get(&package_id.to_string())
+    }
+
+    pub fn is_accepted(&self, package_id: &PackageId, hash: &str) -> bool {
+        self.get(package_id)
+            .map_or(false, |accepted| accepted.hash == hash)
+    }
+
+    /// Record that the user accepted the license with the given hash now,
+    /// in place of any they accepted before for the same app.
+    pub fn accept(&mut self, package_id: &PackageId, hash: &str) {
+        self.accepted.insert(
+            package_id.to_string(),
+            AcceptedLicense {
+                hash: hash.to_string(),
+                timestamp: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            },
+        );
+        self.save();
+    }
+}
+
+/// The license to accept before installing an app, as its listing now gives
+/// it, if any. Apps that are not listed, such as sideloaded ones, have none.
+pub fn license_to_accept(package_id: &PackageId) -> anyhow::Result<Option<LicenseAcceptance>> {
+    let package_id = crate::kinode::process::main::PackageId::from_process_lib(package_id.clone());
+    let resp = Request::to(("our", "chain", "app-store", "sys"))
+        .body(serde_json::to_vec(&ChainRequest::GetApp(package_id)).unwrap())
+        .send_and_await_response(5)??;
+    match serde_json::from_slice::<ChainResponse>(resp.body())? {
+        ChainResponse::GetApp(app) => Ok(app.and_then(|app| app.license_acceptance)),
+        _ => Err(anyhow::anyhow!("Invalid response from chain:app-store:sys")),
+    }
+}
+
+/// Fetch the text of a license, checking it against its hash.
+pub fn fetch_license_text(license: &LicenseAcceptance) -> anyhow::Result<String> {
+    let url = url::Url::parse(&license.uri)?;
+    let _ = http::client::send_request_await_response(http::Method::GET, url, None, 10, vec![])?;
+    let Some(body) = get_blob() else {
+        return Err(anyhow::anyhow!("no license text at {}", license.uri));
+    };
+    if body.bytes.len() > MAX_LICENSE_LENGTH {
+        return Err(anyhow::anyhow!("license text too long"));
+    }
+    if utils::keccak_256_hash(&body.bytes) != license.hash {
+        return Err(anyhow::anyhow!("license text does not match its hash"));
+    }
+    Ok(String::from_utf8(body.bytes)?)
+}
@@ -0,0 +1 @@
+../../../homepage/search-worker/src/search_api.rs
\ No newline at end of file
@@ -1,5 +1,8 @@
-use crate::{kinode::process::downloads::DownloadError, utils, VFS_TIMEOUT};
-use kinode_process_lib::{get_state, kimap, set_state, vfs, PackageId};
+use crate::{
+    kinode::process::downloads::DownloadError, licenses::Licenses, telemetry::Telemetry, utils,
+    VFS_TIMEOUT,
+};
+use kinode_process_lib::{get_state, kimap, set_state, vfs, Address, PackageId};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 
@@ -70,15 +73,21 @@ pub struct State {
     pub packages: HashMap<PackageId, PackageState>,
     /// the APIs we have
     pub installed_apis: HashSet<PackageId>,
+    /// install telemetry settings, pending reports and counts, kept in our telemetry drive
+    pub telemetry: Telemetry,
+    /// licenses the user accepted, kept in our licenses drive
+    pub licenses: Licenses,
 }
 
 impl State {
     /// To load state, we populate the downloaded_packages map
     /// with all packages parseable from our filesystem.
-    pub fn load() -> anyhow::Result<Self> {
+    pub fn load(our: &Address) -> anyhow::Result<Self> {
         let mut state = State {
             packages: HashMap::new(),
             installed_apis: HashSet::new(),
+            telemetry: Telemetry::load(our),
+            licenses: Licenses::load(our),
         };
         state.populate_packages_from_filesystem()?;
         Ok(state)
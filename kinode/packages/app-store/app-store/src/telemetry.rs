@@ -0,0 +1,219 @@
+//! Install telemetry, off unless turned on in settings:settings:sys.
+//!
+//! When on, the apps we install and update are reported to the main process
+//! of their publisher, or of the aggregator we chose, so that publishers can
+//! see adoption. Reports are batched and sent some hours late, carry no node
+//! identity, and the counting node keeps none: only how many installs and
+//! updates each version of an app has seen. The network still tells it which
+//! node a report came from, which is why reporting is opt-in.
+use crate::{
+    kinode::process::main::{
+        PackageId as WitPackageId, TelemetryEvent, TelemetryEventKind, TelemetryRequest,
+        TelemetrySettings, VersionAdoption,
+    },
+    VFS_TIMEOUT,
+};
+use kinode_process_lib::{println, timer, vfs, Address, PackageId, Request};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const TELEMETRY_FILE: &str = "/app-store:sys/telemetry/telemetry.json";
+/// how long after the first event of a report it is sent, at random, in ms
+const MIN_REPORT_DELAY: u64 = 60 * 60 * 1000;
+const MAX_REPORT_DELAY: u64 = 6 * 60 * 60 * 1000;
+/// the most events sent in, and taken from, one report
+const MAX_REPORT_EVENTS: usize = 100;
+/// the most events waiting to be reported, later ones dropped
+const MAX_PENDING_EVENTS: usize = 1000;
+/// the most apps, and versions of each, we keep counts of
+const MAX_COUNTED_APPS: usize = 1000;
+const MAX_COUNTED_VERSIONS: usize = 100;
+/// the context of the timer that sends the pending report
+pub const REPORT_TIMER: &[u8] = b"telemetry";
+
+#[derive(Serialize, Deserialize)]
+pub struct Telemetry {
+    pub settings: TelemetrySettings,
+    /// events not yet reported, oldest first
+    pending: Vec<TelemetryEvent>,
+    /// package ID -> counts of each version, of apps we publish or aggregate
+    counts: HashMap<String, Vec<VersionAdoption>>,
+    #[serde(skip)]
+    timer_set: bool,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            settings: TelemetrySettings {
+                enabled: false,
+                aggregator: None,
+                aggregate: false,
+            },
+            pending: vec![],
+            counts: HashMap::new(),
+            timer_set: false,
+        }
+    }
+}
+
+impl Telemetry {
+    /// Load from our telemetry drive, setting the timer for any events not yet
+    /// reported when we last stopped.
+    pub fn load(our: &Address) -> Self {
+        if let Err(e) = vfs::create_drive(our.package_id(), "telemetry", Some(VFS_TIMEOUT)) {
+            println!("failed to create telemetry drive: {e:?}");
+        }
+        let mut telemetry = vfs::open_file(TELEMETRY_FILE, false, Some(VFS_TIMEOUT))
+            .and_then(|file| file.read())
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Telemetry>(&bytes).ok())
+            .unwrap_or_default();
+        if !telemetry.pending.is_empty() {
+            telemetry.set_timer();
+        }
+        telemetry
+    }
+
+    fn save(&self) {
+        let result = vfs::create_file(TELEMETRY_FILE, Some(VFS_TIMEOUT))
+            .and_then(|file| file.write(&serde_json::to_vec(self).unwrap()));
+        if let Err(e) = result {
+            println!("failed to save telemetry: {e:?}");
+        }
+    }
+
+    /// Replace our settings. Turning reporting off drops the events not yet
+    /// reported, and turning aggregating off drops the counts of apps we
+    /// don't publish.
+    pub fn set_settings(&mut self, our_node: &str, mut settings: TelemetrySettings) {
+        settings.aggregator = settings
+            .aggregator
+            .map(|node| node.trim().to_string())
+            .filter(|node| !node.is_empty() && node != our_node);
+        if !settings.enabled {
+            self.pending.clear();
+        }
+        if !settings.aggregate {
+            self.counts
+                .retain(|package_id, _| is_ours(our_node, package_id));
+        }
+        self.settings = settings;
+        self.save();
+    }
+
+    /// Queue an install of a version of an app, given the version it
+    /// replaced if any, to be reported if we turned reporting on.
+    /// Reinstalls of the same version and apps we publish are not reported.
+    pub fn record(
+        &mut self,
+        our_node: &str,
+        package_id: &PackageId,
+        version_hash: &str,
+        previous_version_hash: Option<&str>,
+    ) {
+        if !self.settings.enabled || package_id.publisher() == our_node {
+            return;
+        }
+        let kind = match previous_version_hash {
+            None => TelemetryEventKind::Install,
+            Some(previous) if previous != version_hash => TelemetryEventKind::Update,
+            Some(_) => return,
+        };
+        if self.pending.len() >= MAX_PENDING_EVENTS {
+            return;
+        }
+        self.pending.push(TelemetryEvent {
+            package_id: WitPackageId::from_process_lib(package_id.clone()),
+            version_hash: version_hash.to_string(),
+            kind,
+        });
+        if !self.timer_set {
+            self.set_timer();
+        }
+        self.save();
+    }
+
+    fn set_timer(&mut self) {
+        let delay = rand::thread_rng().gen_range(MIN_REPORT_DELAY..=MAX_REPORT_DELAY);
+        timer::set_timer(delay, Some(REPORT_TIMER.to_vec()));
+        self.timer_set = true;
+    }
+
+    /// Send the pending events, once the timer pops, to our aggregator or to
+    /// the publisher of each app. Reports are not acknowledged: one sent to
+    /// an offline node is lost.
+    pub fn report(&mut self) {
+        self.timer_set = false;
+        if !self.settings.enabled {
+            self.pending.clear();
+            return;
+        }
+        let mut reports: HashMap<String, Vec<TelemetryEvent>> = HashMap::new();
+        for event in self.pending.drain(..) {
+            let target = match &self.settings.aggregator {
+                Some(aggregator) => aggregator.clone(),
+                None => event.package_id.publisher_node.clone(),
+            };
+            reports.entry(target).or_default().push(event);
+        }
+        for (target, events) in reports {
+            for chunk in events.chunks(MAX_REPORT_EVENTS) {
+                let _ = Request::to((target.as_str(), "main", "app-store", "sys"))
+                    .body(serde_json::to_vec(&TelemetryRequest::Report(chunk.to_vec())).unwrap())
+                    .send();
+            }
+        }
+        self.save();
+    }
+
+    /// Count the events of a report from another node: those of apps we
+    /// publish, and of any app if we aggregate.
+    pub fn count(&mut self, our_node: &str, events: Vec<TelemetryEvent>) {
+        for event in events.into_iter().take(MAX_REPORT_EVENTS) {
+            let package_id = event.package_id.to_process_lib().to_string();
+            if !self.settings.aggregate && !is_ours(our_node, &package_id) {
+                continue;
+            }
+            if !self.counts.contains_key(&package_id) && self.counts.len() >= MAX_COUNTED_APPS {
+                continue;
+            }
+            let versions = self.counts.entry(package_id).or_default();
+            let index = match versions
+                .iter()
+                .position(|v| v.version_hash == event.version_hash)
+            {
+                Some(index) => index,
+                None if versions.len() < MAX_COUNTED_VERSIONS => {
+                    versions.push(VersionAdoption {
+                        version_hash: event.version_hash,
+                        installs: 0,
+                        updates: 0,
+                    });
+                    versions.len() - 1
+                }
+                None => continue,
+            };
+            match event.kind {
+                TelemetryEventKind::Install => versions[index].installs += 1,
+                TelemetryEventKind::Update => versions[index].updates += 1,
+            }
+        }
+        self.save();
+    }
+
+    /// The counts of each version of an app.
+    pub fn adoption(&self, package_id: &PackageId) -> Vec<VersionAdoption> {
+        self.counts
+            .get(&package_id.to_string())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn is_ours(our_node: &str, package_id: &str) -> bool {
+    package_id
+        .parse::<PackageId>()
+        .map_or(false, |package_id| package_id.publisher() == our_node)
+}
@@ -4,6 +4,7 @@ use {
             chain::{ChainRequest, ChainResponse, OnchainMetadata},
             downloads::{AddDownloadRequest, DownloadRequest, DownloadResponse},
         },
+        search_api::{SearchQuery, SearchResult},
         state::{PackageState, State},
         VFS_TIMEOUT,
     },
@@ -93,6 +94,53 @@ pub fn fetch_package_metadata(
     Ok(metadata)
 }
 
+/// find the onchain apps whose name, package, publisher or description hold
+/// every word of a search from the homepage, linking to their pages in our UI
+pub fn search_listings(query: &SearchQuery) -> anyhow::Result<Vec<SearchResult>> {
+    let resp = Request::to(("our", "chain", "app-store", "sys"))
+        .body(serde_json::to_vec(&ChainRequest::GetApps).unwrap())
+        .send_and_await_response(5)??;
+    let ChainResponse::GetApps(apps) = serde_json::from_slice::<ChainResponse>(&resp.body())?
+    else {
+        return Err(anyhow::anyhow!(
+            "unexpected response from chain:app-store:sys"
+        ));
+    };
+    let words: Vec<String> = query
+        .query
+        .to_lowercase()
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    Ok(apps
+        .into_iter()
+        .filter_map(|app| {
+            let package_id = format!(
+                "{}:{}",
+                app.package_id.package_name, app.package_id.publisher_node
+            );
+            let name = app.metadata.as_ref().and_then(|m| m.name.clone());
+            let description = app.metadata.and_then(|m| m.description);
+            let haystack = format!(
+                "{} {} {}",
+                name.as_deref().unwrap_or_default(),
+                package_id,
+                description.as_deref().unwrap_or_default()
+            )
+            .to_lowercase();
+            if !words.iter().all(|word| haystack.contains(word)) {
+                return None;
+            }
+            Some(SearchResult {
+                title: name.unwrap_or_else(|| app.package_id.package_name.clone()),
+                description: Some(description.unwrap_or_else(|| package_id.clone())),
+                path: format!("/app/{package_id}"),
+            })
+        })
+        .take(query.limit as usize)
+        .collect())
+}
+
 pub fn new_package(
     package_id: crate::kinode::process::main::PackageId,
     mirror: bool,
@@ -138,14 +186,16 @@ pub fn create_package_drive(
     vfs_request(drive_name.clone(), vfs::VfsAction::CreateDrive)
         .send_and_await_response(VFS_TIMEOUT)??;
 
-    // DELETE the /pkg folder in the package drive
-    // in order to replace with the fresh one
-    vfs_request(drive_name.clone(), vfs::VfsAction::RemoveDirAll)
-        .send_and_await_response(VFS_TIMEOUT)??;
+    // unzip into a staging drive, clearing out any left by a failed install,
+    // then move it over the /pkg drive in one step, so that a bad zip
+    // leaves the previous version of the package intact
+    let staging_drive = format!("/{package_id}/pkg-install");
+    vfs_request(staging_drive.clone(), vfs::VfsAction::RemoveDirAll)
+        .send_and_await_response(VFS_TIMEOUT)?
+        .ok();
 
-    // convert the zip to a new package drive
     let vfs::VfsResponse::Ok = serde_json::from_slice::<vfs::VfsResponse>(
-        vfs_request(drive_name.clone(), vfs::VfsAction::AddZip)
+        vfs_request(staging_drive.clone(), vfs::VfsAction::AddZip)
             .blob(blob.clone())
             .send_and_await_response(VFS_TIMEOUT)??
             .body(),
@@ -156,6 +206,22 @@ pub fn create_package_drive(
         ));
     };
 
+    // replace the /pkg folder in the package drive with the fresh one
+    let vfs::VfsResponse::Ok = serde_json::from_slice::<vfs::VfsResponse>(
+        Request::to(("our", "vfs", "distro", "sys"))
+            .body(serde_json::to_vec(&serde_json::json!({
+                "path": staging_drive,
+                "action": { "MoveDir": { "new_path": drive_name } },
+            }))?)
+            .send_and_await_response(VFS_TIMEOUT)??
+            .body(),
+    )?
+    else {
+        return Err(anyhow::anyhow!(
+            "failed to move new package into {drive_name}"
+        ));
+    };
+
     // be careful, this is technically a duplicate.. but..
     // save the zip file itself in VFS for sharing with other nodes
     // call it <package_id>.zip
@@ -174,6 +240,125 @@ pub fn create_package_drive(
     Ok(manifest_hash)
 }
 
+/// snapshot every drive of an installed package but its `/pkg` drive,
+/// which an update replaces anyway, so that the package's data can be
+/// restored with `RestoreSnapshot` if the update goes wrong.
+/// returns each drive snapshotted along with the id of its snapshot.
+pub fn snapshot_data_drives(package_id: &PackageId) -> anyhow::Result<Vec<(String, u64)>> {
+    let response = Request::to(("our", "vfs", "distro", "sys"))
+        .body(serde_json::to_vec(&serde_json::json!({
+            "path": "/",
+            "action": "DriveUsage",
+        }))?)
+        .send_and_await_response(VFS_TIMEOUT)??;
+    let response = serde_json::from_slice::<serde_json::Value>(response.body())?;
+    let Some(drives) = response["DriveUsage"].as_array() else {
+        return Err(anyhow::anyhow!("failed to list drives: {response}"));
+    };
+
+    let prefix = format!("/{package_id}/");
+    let mut snapshots = Vec::new();
+    for drive in drives.iter().filter_map(|drive| drive["drive"].as_str()) {
+        // skip drives being built or torn down by the VFS, which are hidden
+        match drive.strip_prefix(&prefix) {
+            Some("pkg") | Some("pkg-install") | None => continue,
+            Some(name) if name.starts_with('.') => continue,
+            Some(_) => {}
+        }
+        let response = Request::to(("our", "vfs", "distro", "sys"))
+            .body(serde_json::to_vec(&serde_json::json!({
+                "path": drive,
+                "action": "Snapshot",
+            }))?)
+            .send_and_await_response(VFS_TIMEOUT)??;
+        let response = serde_json::from_slice::<serde_json::Value>(response.body())?;
+        let Some(id) = response["Snapshot"]["id"].as_u64() else {
+            return Err(anyhow::anyhow!("failed to snapshot {drive}: {response}"));
+        };
+        snapshots.push((drive.to_string(), id));
+    }
+    Ok(snapshots)
+}
+
+/// restore each drive to its snapshot, as taken by `snapshot_data_drives`,
+/// carrying on past any that fail so that as much data as possible is rolled back.
+pub fn restore_data_drives(snapshots: &[(String, u64)]) {
+    for (drive, id) in snapshots {
+        let response = Request::to(("our", "vfs", "distro", "sys"))
+            .body(
+                serde_json::to_vec(&serde_json::json!({
+                    "path": drive,
+                    "action": { "RestoreSnapshot": id },
+                }))
+                .unwrap(),
+            )
+            .send_and_await_response(VFS_TIMEOUT);
+        match response {
+            Ok(Ok(response)) if response.body() == br#""Ok""# => {}
+            response => println!("failed to restore {drive} to snapshot {id}: {response:?}"),
+        }
+    }
+}
+
+/// find VFS drives whose package is neither installed nor has a running process,
+/// along with any half-built `pkg-install` drives and hidden VFS staging dirs,
+/// which are only left behind by an interrupted install or tree operation.
+/// if `delete`, remove each orphaned drive as it is found.
+pub fn orphaned_drives(state: &State, delete: bool) -> anyhow::Result<Vec<String>> {
+    let response = Request::to(("our", "vfs", "distro", "sys"))
+        .body(serde_json::to_vec(&serde_json::json!({
+            "path": "/",
+            "action": "DriveUsage",
+        }))?)
+        .send_and_await_response(VFS_TIMEOUT)??;
+    let response = serde_json::from_slice::<serde_json::Value>(response.body())?;
+    let Some(drives) = response["DriveUsage"].as_array() else {
+        return Err(anyhow::anyhow!("failed to list drives: {response}"));
+    };
+
+    let kt::KernelResponse::Debug(kt::KernelPrintResponse::ProcessMap(process_map)) =
+        serde_json::from_slice(
+            kernel_request(kt::KernelCommand::Debug(kt::KernelPrint::ProcessMap))
+                .send_and_await_response(VFS_TIMEOUT)??
+                .body(),
+        )?
+    else {
+        return Err(anyhow::anyhow!("got malformed process map from kernel"));
+    };
+    let running: HashSet<PackageId> = process_map
+        .keys()
+        .map(|process_id| process_id.package_id())
+        .collect();
+
+    let mut orphans = vec![];
+    for drive in drives.iter().filter_map(|drive| drive["drive"].as_str()) {
+        let Some((package, name)) = drive.trim_start_matches('/').split_once('/') else {
+            continue;
+        };
+        let orphaned = name == "pkg-install"
+            || name.starts_with('.')
+            || match package.parse::<PackageId>() {
+                Ok(package_id) => {
+                    !state.packages.contains_key(&package_id) && !running.contains(&package_id)
+                }
+                Err(_) => false,
+            };
+        if !orphaned {
+            continue;
+        }
+        if delete {
+            let response = vfs_request(drive, vfs::VfsAction::RemoveDirAll)
+                .send_and_await_response(VFS_TIMEOUT)??;
+            let response = serde_json::from_slice::<serde_json::Value>(response.body())?;
+            if let Some(e) = response.get("Err") {
+                return Err(anyhow::anyhow!("failed to remove {drive}: {e}"));
+            }
+        }
+        orphans.push(drive.to_string());
+    }
+    Ok(orphans)
+}
+
 pub fn extract_api(package_id: &PackageId) -> anyhow::Result<bool> {
     // get `pkg/api.zip` if it exists
     if let vfs::VfsResponse::Read = serde_json::from_slice(
@@ -212,13 +397,66 @@ pub fn install(
     our_node: &str,
 ) -> anyhow::Result<()> {
     let process_package_id = package_id.clone().to_process_lib();
+
     let file = vfs::open_file(
         &format!("/app-store:sys/downloads/{process_package_id}/{version_hash}.zip"),
         false,
         Some(VFS_TIMEOUT),
     )?;
     let bytes = file.read()?;
-    let manifest_hash = create_package_drive(&process_package_id, bytes)?;
+
+    let previous_version_hash = state
+        .packages
+        .get(&process_package_id)
+        .map(|package| package.our_version_hash.clone());
+
+    // keep the data of the version being replaced, so it can be rolled back to
+    // if the update fails partway
+    let snapshots = match previous_version_hash {
+        Some(_) => snapshot_data_drives(&process_package_id).unwrap_or_else(|e| {
+            println!("failed to snapshot data of {process_package_id}: {e}");
+            vec![]
+        }),
+        None => vec![],
+    };
+
+    if let Err(e) = install_version(
+        package_id,
+        &process_package_id,
+        bytes,
+        metadata,
+        version_hash,
+        state,
+        our_node,
+    ) {
+        if !snapshots.is_empty() {
+            println!("failed to update {process_package_id}, restoring its data: {e}");
+            restore_data_drives(&snapshots);
+        }
+        return Err(e);
+    }
+
+    state.telemetry.record(
+        our_node,
+        &process_package_id,
+        version_hash,
+        previous_version_hash.as_deref(),
+    );
+    Ok(())
+}
+
+/// create the package drive of a version of a package, then initialize,
+/// grant capabilities to, and start the processes in its manifest.
+fn install_version(
+    package_id: &crate::kinode::process::main::PackageId,
+    process_package_id: &PackageId,
+    bytes: Vec<u8>,
+    metadata: Option<OnchainMetadata>,
+    version_hash: &str,
+    state: &mut State,
+    our_node: &str,
+) -> anyhow::Result<()> {
+    let manifest_hash = create_package_drive(process_package_id, bytes)?;
 
     let package_state = PackageState {
         our_version_hash: version_hash.to_string(),
@@ -304,7 +542,12 @@ pub fn install(
             });
         }
 
-        // always grant read/write to their drive, which we created for them
+        // always grant read to their code drive, which we created for them.
+        // the package's own processes may write to it regardless, but the
+        // capability is read-only so that they cannot share write access
+        // to their code with other processes.
+        // narrower access, e.g. to a path within a drive, is requested in
+        // the manifest as `{"kind": "read", "drive": "/pkg:pub/data", "path": "/public"}`
         requested_capabilities.push(kt::Capability {
             issuer: Address::new(our_node, ("vfs", "distro", "sys")),
             params: serde_json::json!({
@@ -313,14 +556,6 @@ pub fn install(
             })
             .to_string(),
         });
-        requested_capabilities.push(kt::Capability {
-            issuer: Address::new(our_node, ("vfs", "distro", "sys")),
-            params: serde_json::json!({
-                "kind": "write",
-                "drive": drive_path,
-            })
-            .to_string(),
-        });
 
         kernel_request(kt::KernelCommand::GrantCapabilities {
             target: process_id.clone(),
@@ -0,0 +1,58 @@
+//! apps:app-store:sys
+//! terminal script for listing the packages installed by the app store.
+//!
+//! Usage:
+//!     apps:app-store:sys [--json]
+//!
+//! Arguments:
+//!     --json    Print the list as JSON
+//!
+use crate::kinode::process::main::{LocalRequest, LocalResponse};
+use kinode_process_lib::{script, Address, Message, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    generate_unused_types: true,
+    world: "app-store-sys-v1",
+    additional_derives: [PartialEq, serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
+});
+
+script!(init);
+fn init(our: Address, args: String) -> String {
+    let json = args.split_whitespace().any(|arg| arg == "--json");
+
+    let Ok(Ok(Message::Response { body, .. })) =
+        Request::to((our.node(), ("main", "app-store", "sys")))
+            .body(LocalRequest::Installed)
+            .send_and_await_response(5)
+    else {
+        return "apps: failed to get a response from app-store..!".to_string();
+    };
+
+    let Ok(LocalResponse::InstalledResponse(mut packages)) = body.try_into() else {
+        return "apps: failed to parse response from app-store..!".to_string();
+    };
+    packages.sort_by(|a, b| {
+        (&a.package_id.package_name, &a.package_id.publisher_node)
+            .cmp(&(&b.package_id.package_name, &b.package_id.publisher_node))
+    });
+
+    if json {
+        return serde_json::to_string(&packages).unwrap();
+    }
+    let packages = packages
+        .iter()
+        .map(|package| {
+            format!(
+                "{}:{}:\n    version: {}\n    verified: {}\n    capabilities approved: {}",
+                package.package_id.package_name,
+                package.package_id.publisher_node,
+                package.version_hash,
+                package.verified,
+                package.caps_approved,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("installed packages:\n{packages}")
+}
@@ -9,11 +9,15 @@
 //! 2. Manage subscriptions to relevant blockchain events.
 //! 3. Provide up-to-date information about available apps and their metadata.
 //! 4. Handle auto-update settings for apps.
+//! 5. Keep the reviews of apps and exchange them with other nodes.
+//! 6. Leave out the listings our filter policy denies, such as those of spammers.
 //!
 //! ## Key Components:
 //!
 //! - `handle_eth_log`: Processes blockchain events related to app metadata updates.
 //! - `fetch_and_subscribe_logs`: Initializes and maintains blockchain event subscriptions.
+//! - `add_reviews`: Verifies and keeps reviews sent by other nodes, passing on the new ones.
+//! - `fetch_metadata_from_url`: Fetches the metadata of an app and its release notes.
 //!
 //! ## Interaction Flow:
 //!
@@ -22,24 +26,31 @@
 //! 3. Other processes (like main) can request information about apps.
 //! 4. The chain process responds with the most up-to-date information from its local state.
 //!
+//! Reviews are signed by the networking key of the node that wrote them, and sent to
+//! the chain processes of the publisher and mirrors of the app. Each of those keeps
+//! the ones it can verify and passes on the ones new to it, so that they meet in the
+//! nodes that serve the app, which other nodes fetch them from when they look at it.
+//! The only requests taken from other nodes are those for reviews.
+//!
 //! Note: This process does not handle app binaries or installation. It focuses solely on
 //! metadata management and providing information about available apps.
 //!
 use crate::kinode::process::chain::{
-    ChainError, ChainRequest, OnchainApp, OnchainMetadata, OnchainProperties,
+    ChainError, ChainRequest, FilterPolicy, LicenseAcceptance, OnchainApp, OnchainMetadata,
+    OnchainProperties, PublishReviewRequest, RatingSummary, ReleaseNotes, Review,
 };
 use crate::kinode::process::downloads::{AutoUpdateRequest, DownloadRequest};
-use alloy_primitives::keccak256;
+use alloy_primitives::{hex, keccak256};
 use alloy_sol_types::SolEvent;
 use kinode::process::chain::ChainResponse;
 use kinode_process_lib::{
-    await_message, call_init, eth, get_blob, http, kernel_types as kt, kimap, print_to_terminal,
-    println,
+    await_message, call_init, eth, get_blob, get_state, http, kernel_types as kt, kimap, net,
+    print_to_terminal, println, set_state,
     sqlite::{self, Sqlite},
-    timer, Address, Message, PackageId, Request, Response,
+    timer, vfs, Address, Message, PackageId, Request, Response,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
 wit_bindgen::generate!({
@@ -63,6 +74,24 @@ const KIMAP_ADDRESS: &str = "0x9CE8cCD2932DC727c70f9ae4f8C2b68E6Abed58C";
 
 const DELAY_MS: u64 = 1_000; // 1s
 
+const NET_TIMEOUT: u64 = 5; // 5s
+/// longest text of a review, in bytes
+const MAX_REVIEW_LENGTH: usize = 2_000;
+/// most reviews of an app sent in a response, newest first, or taken from a request
+const MAX_REVIEWS: usize = 500;
+/// reviews are taken from at most this far in the future, to allow for clock drift
+const MAX_REVIEW_CLOCK_DRIFT: u64 = 60 * 60; // 1h
+/// least time between fetching the reviews of an app from its publisher and mirrors
+const REVIEW_SYNC_INTERVAL: u64 = 10 * 60; // 10m
+/// longest release notes of a version kept, in bytes
+const MAX_RELEASE_NOTES_LENGTH: usize = 10_000;
+/// most versions whose release notes are taken from one metadata
+const MAX_CHANGELOG_VERSIONS: usize = 100;
+/// largest changelog taken from a `changelog_uri`, in bytes
+const MAX_CHANGELOG_LENGTH: usize = MAX_CHANGELOG_VERSIONS * MAX_RELEASE_NOTES_LENGTH;
+/// longest a changelog may take to fetch from a `changelog_uri`, in seconds
+const CHANGELOG_TIMEOUT: u64 = 10;
+
 pub struct State {
     /// the kimap helper we are using
     pub kimap: kimap::Kimap,
@@ -70,8 +99,16 @@ pub struct State {
     /// when we boot, we can read logs starting from this block and
     /// rebuild latest state.
     pub last_saved_block: u64,
-    /// tables: listings: <packade_id, listing>, published: vec<package_id>
+    /// tables: listings: <packade_id, listing>, published: vec<package_id>,
+    /// reviews: <package_id, reviewer, review>, changelogs: <package_id, version, notes>,
+    /// licenses: <package_id, license_acceptance>
     pub db: DB,
+    /// when we last fetched the reviews of each app from its publisher and
+    /// mirrors, in seconds
+    pub review_syncs: HashMap<PackageId, u64>,
+    /// which listings to leave out, kept in process state rather than the DB
+    /// so that it outlives a reset
+    pub filter_policy: FilterPolicy,
 }
 
 /// listing information derived from metadata hash in listing event
@@ -85,6 +122,31 @@ pub struct PackageListing {
     pub block: u64,
 }
 
+/// the properties a publisher may give in their metadata which
+/// `kt::Erc721Metadata` leaves out
+#[derive(Debug, Default, Deserialize)]
+struct ExtraMetadata {
+    #[serde(default)]
+    properties: ExtraProperties,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ExtraProperties {
+    /// version -> notes
+    #[serde(default)]
+    changelog: HashMap<String, String>,
+    /// URL of a JSON object of version -> notes
+    changelog_uri: Option<String>,
+    license_acceptance: Option<LicenseAcceptance>,
+}
+
+/// what we keep of metadata beyond `kt::Erc721Metadata`
+#[derive(Debug, Default)]
+pub struct MetadataExtras {
+    pub changelog: Vec<ReleaseNotes>,
+    pub license_acceptance: Option<LicenseAcceptance>,
+}
+
 #[derive(Debug, Serialize, Deserialize, process_macros::SerdeJsonInto)]
 #[serde(untagged)] // untagged as a meta-type for all incoming requests
 pub enum Req {
@@ -99,10 +161,18 @@ pub struct DB {
 impl DB {
     pub fn connect(our: &Address) -> anyhow::Result<Self> {
         let inner = sqlite::open(our.package_id(), "app_store_chain.sqlite", Some(10))?;
-        // create tables
-        inner.write(CREATE_META_TABLE.into(), vec![], None)?;
-        inner.write(CREATE_LISTINGS_TABLE.into(), vec![], None)?;
-        inner.write(CREATE_PUBLISHED_TABLE.into(), vec![], None)?;
+        // bring the tables up to date
+        let response = Request::to(("our", "sqlite", "distro", "sys"))
+            .body(serde_json::to_vec(&serde_json::json!({
+                "package_id": our.package_id(),
+                "db": "app_store_chain.sqlite",
+                "action": { "Migrate": { "migrations": MIGRATIONS } },
+            }))?)
+            .send_and_await_response(10)??;
+        let response: serde_json::Value = serde_json::from_slice(response.body())?;
+        if response.get("Migrated").is_none() {
+            return Err(anyhow::anyhow!("failed to migrate DB: {response}"));
+        }
 
         Ok(Self { inner })
     }
@@ -176,6 +246,13 @@ impl DB {
             package_id.package_name.clone().into(),
             package_id.publisher_node.clone().into(),
         ];
+        self.inner.write(query.into(), params.clone(), None)?;
+        // reviews and release notes of apps no longer listed are of no use
+        let query = "DELETE FROM reviews WHERE package_name = ? AND publisher_node = ?";
+        self.inner.write(query.into(), params.clone(), None)?;
+        let query = "DELETE FROM changelogs WHERE package_name = ? AND publisher_node = ?";
+        self.inner.write(query.into(), params.clone(), None)?;
+        let query = "DELETE FROM licenses WHERE package_name = ? AND publisher_node = ?";
         self.inner.write(query.into(), params, None)?;
         Ok(())
     }
@@ -316,6 +393,216 @@ impl DB {
         Ok(())
     }
 
+    pub fn insert_or_update_review(&self, review: &Review) -> anyhow::Result<()> {
+        let query = "INSERT INTO reviews (package_name, publisher_node, reviewer, rating, text, timestamp, signature)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(package_name, publisher_node, reviewer)
+            DO UPDATE SET
+              rating=excluded.rating,
+              text=excluded.text,
+              timestamp=excluded.timestamp,
+              signature=excluded.signature";
+        let params = vec![
+            review.package_id.package_name.clone().into(),
+            review.package_id.publisher_node.clone().into(),
+            review.reviewer.clone().into(),
+            review.rating.into(),
+            review.text.clone().into(),
+            review.timestamp.into(),
+            hex::encode(&review.signature).into(),
+        ];
+        self.inner.write(query.into(), params, None)?;
+        Ok(())
+    }
+
+    pub fn get_review(
+        &self,
+        package_id: &PackageId,
+        reviewer: &str,
+    ) -> anyhow::Result<Option<Review>> {
+        let query = "SELECT reviewer, rating, text, timestamp, signature FROM reviews
+            WHERE package_name = ? AND publisher_node = ? AND reviewer = ?";
+        let params = vec![
+            package_id.package_name.clone().into(),
+            package_id.publisher_node.clone().into(),
+            reviewer.into(),
+        ];
+        let rows = self.inner.read(query.into(), params)?;
+        Ok(rows
+            .get(0)
+            .and_then(|row| self.row_to_review(package_id, row)))
+    }
+
+    /// the reviews of an app, newest first
+    pub fn get_reviews(&self, package_id: &PackageId, limit: usize) -> anyhow::Result<Vec<Review>> {
+        let query = format!(
+            "SELECT reviewer, rating, text, timestamp, signature FROM reviews
+             WHERE package_name = ? AND publisher_node = ?
+             ORDER BY timestamp DESC
+             LIMIT {}",
+            limit
+        );
+        let params = vec![
+            package_id.package_name.clone().into(),
+            package_id.publisher_node.clone().into(),
+        ];
+        let rows = self.inner.read(query, params)?;
+        Ok(rows
+            .iter()
+            .filter_map(|row| self.row_to_review(package_id, row))
+            .collect())
+    }
+
+    pub fn row_to_review(
+        &self,
+        package_id: &PackageId,
+        row: &HashMap<String, serde_json::Value>,
+    ) -> Option<Review> {
+        Some(Review {
+            package_id: crate::kinode::process::main::PackageId::from_process_lib(
+                package_id.clone(),
+            ),
+            reviewer: row["reviewer"].as_str()?.to_string(),
+            rating: row["rating"].as_u64()? as u8,
+            text: row["text"].as_str().map(|text| text.to_string()),
+            timestamp: row["timestamp"].as_u64()?,
+            signature: hex::decode(row["signature"].as_str()?).ok()?,
+        })
+    }
+
+    pub fn get_rating_summary(
+        &self,
+        package_id: &PackageId,
+    ) -> anyhow::Result<Option<RatingSummary>> {
+        let query = "SELECT COUNT(*) AS count, AVG(rating) AS average FROM reviews
+            WHERE package_name = ? AND publisher_node = ?";
+        let params = vec![
+            package_id.package_name.clone().into(),
+            package_id.publisher_node.clone().into(),
+        ];
+        let rows = self.inner.read(query.into(), params)?;
+        Ok(rows.get(0).and_then(|row| self.row_to_rating_summary(row)))
+    }
+
+    pub fn get_all_rating_summaries(&self) -> anyhow::Result<HashMap<PackageId, RatingSummary>> {
+        let query = "SELECT package_name, publisher_node, COUNT(*) AS count, AVG(rating) AS average
+            FROM reviews
+            GROUP BY package_name, publisher_node";
+        let rows = self.inner.read(query.into(), vec![])?;
+        let mut summaries = HashMap::new();
+        for row in rows {
+            let pid = PackageId {
+                package_name: row["package_name"].as_str().unwrap_or("").to_string(),
+                publisher_node: row["publisher_node"].as_str().unwrap_or("").to_string(),
+            };
+            if let Some(summary) = self.row_to_rating_summary(&row) {
+                summaries.insert(pid, summary);
+            }
+        }
+        Ok(summaries)
+    }
+
+    /// none if there are no reviews to summarize
+    pub fn row_to_rating_summary(
+        &self,
+        row: &HashMap<String, serde_json::Value>,
+    ) -> Option<RatingSummary> {
+        let count = row["count"].as_u64().filter(|count| *count > 0)?;
+        Some(RatingSummary {
+            count: count as u32,
+            average: row["average"].as_f64()? as f32,
+        })
+    }
+
+    /// keep the release notes of versions, in place of those we had of them.
+    /// those of versions left out of newer metadata are kept.
+    pub fn insert_release_notes(
+        &self,
+        package_id: &PackageId,
+        changelog: &[ReleaseNotes],
+    ) -> anyhow::Result<()> {
+        let query = "INSERT INTO changelogs (package_name, publisher_node, version, notes)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT(package_name, publisher_node, version)
+            DO UPDATE SET notes=excluded.notes";
+        for release in changelog {
+            let params = vec![
+                package_id.package_name.clone().into(),
+                package_id.publisher_node.clone().into(),
+                release.version.clone().into(),
+                release.notes.clone().into(),
+            ];
+            self.inner.write(query.into(), params, None)?;
+        }
+        Ok(())
+    }
+
+    /// the release notes of an app, newest version first
+    pub fn get_changelog(&self, package_id: &PackageId) -> anyhow::Result<Vec<ReleaseNotes>> {
+        let query = "SELECT version, notes FROM changelogs
+            WHERE package_name = ? AND publisher_node = ?";
+        let params = vec![
+            package_id.package_name.clone().into(),
+            package_id.publisher_node.clone().into(),
+        ];
+        let rows = self.inner.read(query.into(), params)?;
+        let mut changelog: Vec<ReleaseNotes> = rows
+            .iter()
+            .filter_map(|row| {
+                Some(ReleaseNotes {
+                    version: row["version"].as_str()?.to_string(),
+                    notes: row["notes"].as_str()?.to_string(),
+                })
+            })
+            .collect();
+        changelog.sort_by(|a, b| version_key(&b.version).cmp(&version_key(&a.version)));
+        Ok(changelog)
+    }
+
+    /// keep the license to accept of an app, as given by its latest metadata
+    pub fn set_license_acceptance(
+        &self,
+        package_id: &PackageId,
+        license: Option<&LicenseAcceptance>,
+    ) -> anyhow::Result<()> {
+        let params = vec![
+            package_id.package_name.clone().into(),
+            package_id.publisher_node.clone().into(),
+        ];
+        let Some(license) = license else {
+            let query = "DELETE FROM licenses WHERE package_name = ? AND publisher_node = ?";
+            self.inner.write(query.into(), params, None)?;
+            return Ok(());
+        };
+        let query = "INSERT INTO licenses (package_name, publisher_node, uri, hash)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT(package_name, publisher_node)
+            DO UPDATE SET uri=excluded.uri, hash=excluded.hash";
+        let mut params = params;
+        params.push(license.uri.clone().into());
+        params.push(license.hash.clone().into());
+        self.inner.write(query.into(), params, None)?;
+        Ok(())
+    }
+
+    pub fn get_license_acceptance(
+        &self,
+        package_id: &PackageId,
+    ) -> anyhow::Result<Option<LicenseAcceptance>> {
+        let query = "SELECT uri, hash FROM licenses WHERE package_name = ? AND publisher_node = ?";
+        let params = vec![
+            package_id.package_name.clone().into(),
+            package_id.publisher_node.clone().into(),
+        ];
+        let rows = self.inner.read(query.into(), params)?;
+        Ok(rows.get(0).and_then(|row| {
+            Some(LicenseAcceptance {
+                uri: row["uri"].as_str()?.to_string(),
+                hash: row["hash"].as_str()?.to_string(),
+            })
+        }))
+    }
+
     pub fn get_all_published(&self) -> anyhow::Result<Vec<PackageId>> {
         let query = "SELECT package_name, publisher_node FROM published";
         let rows = self.inner.read(query.into(), vec![])?;
@@ -331,13 +618,15 @@ impl DB {
     }
 }
 
-const CREATE_META_TABLE: &str = "
+/// The schema of the DB, one migration per version. Released migrations are never
+/// edited: the schema is changed by appending a migration.
+const MIGRATIONS: &[&str] = &[
+    // 1: tables as first created, before migrations, hence `IF NOT EXISTS`
+    "
 CREATE TABLE IF NOT EXISTS meta (
     key TEXT PRIMARY KEY,
     value TEXT
-);";
-
-const CREATE_LISTINGS_TABLE: &str = "
+);
 CREATE TABLE IF NOT EXISTS listings (
     package_name TEXT NOT NULL,
     publisher_node TEXT NOT NULL,
@@ -348,14 +637,43 @@ CREATE TABLE IF NOT EXISTS listings (
     auto_update INTEGER NOT NULL DEFAULT 0,
     block INTEGER NOT NULL DEFAULT 0,
     PRIMARY KEY (package_name, publisher_node)
-);";
-
-const CREATE_PUBLISHED_TABLE: &str = "
+);
 CREATE TABLE IF NOT EXISTS published (
     package_name TEXT NOT NULL,
     publisher_node TEXT NOT NULL,
     PRIMARY KEY (package_name, publisher_node)
-);";
+);",
+    // 2: reviews of apps, one per reviewer, with the signature in hex
+    "
+CREATE TABLE reviews (
+    package_name TEXT NOT NULL,
+    publisher_node TEXT NOT NULL,
+    reviewer TEXT NOT NULL,
+    rating INTEGER NOT NULL,
+    text TEXT,
+    timestamp INTEGER NOT NULL,
+    signature TEXT NOT NULL,
+    PRIMARY KEY (package_name, publisher_node, reviewer)
+);",
+    // 3: release notes of each version of apps
+    "
+CREATE TABLE changelogs (
+    package_name TEXT NOT NULL,
+    publisher_node TEXT NOT NULL,
+    version TEXT NOT NULL,
+    notes TEXT NOT NULL,
+    PRIMARY KEY (package_name, publisher_node, version)
+);",
+    // 4: licenses to accept before installing apps, from their latest metadata
+    "
+CREATE TABLE licenses (
+    package_name TEXT NOT NULL,
+    publisher_node TEXT NOT NULL,
+    uri TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    PRIMARY KEY (package_name, publisher_node)
+);",
+];
 
 call_init!(init);
 fn init(our: Address) {
@@ -370,6 +688,14 @@ fn init(our: Address) {
         kimap: kimap_helper,
         last_saved_block,
         db,
+        review_syncs: HashMap::new(),
+        filter_policy: get_state()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or(FilterPolicy {
+                deny: vec![],
+                allow: vec![],
+                deny_missing_code_hashes: false,
+            }),
     };
 
     fetch_and_subscribe_logs(&our, &mut state, last_saved_block);
@@ -398,6 +724,15 @@ fn handle_message(our: &Address, state: &mut State, message: &Message) -> anyhow
             handle_eth_log(our, state, log, false)?;
             return Ok(());
         }
+        // reviews fetched by `sync_reviews`
+        if !message.is_local(&our) && message.source().process == "chain:app-store:sys" {
+            if let Ok(ChainResponse::GetReviews(reviews)) =
+                serde_json::from_slice::<ChainResponse>(message.body())
+            {
+                merge_reviews(state, reviews)?;
+            }
+            return Ok(());
+        }
     } else {
         match serde_json::from_slice::<Req>(message.body())? {
             Req::Eth(eth_result) => {
@@ -425,7 +760,11 @@ fn handle_message(our: &Address, state: &mut State, message: &Message) -> anyhow
                 }
             }
             Req::Request(chains) => {
-                handle_local_request(our, state, chains)?;
+                if message.is_local(our) {
+                    handle_local_request(our, state, chains)?;
+                } else {
+                    handle_remote_request(our, state, message.source(), chains)?;
+                }
             }
         }
     }
@@ -438,15 +777,27 @@ fn handle_local_request(our: &Address, state: &mut State, req: ChainRequest) ->
         ChainRequest::GetApp(package_id) => {
             let pid = package_id.clone().to_process_lib();
             let listing = state.db.get_listing(&pid)?;
-            let onchain_app = listing.map(|app| app.to_onchain_app(&pid));
+            let rating = state.db.get_rating_summary(&pid)?;
+            let onchain_app = match listing {
+                Some(listing) => Some(OnchainApp {
+                    changelog: state.db.get_changelog(&pid)?,
+                    license_acceptance: state.db.get_license_acceptance(&pid)?,
+                    ..listing.to_onchain_app(&pid, rating)
+                }),
+                None => None,
+            };
             let response = ChainResponse::GetApp(onchain_app);
             Response::new().body(&response).send()?;
         }
         ChainRequest::GetApps => {
             let listings = state.db.get_all_listings()?;
+            let mut ratings = state.db.get_all_rating_summaries()?;
             let apps: Vec<OnchainApp> = listings
                 .into_iter()
-                .map(|(pid, listing)| listing.to_onchain_app(&pid))
+                .map(|(pid, listing)| {
+                    let rating = ratings.remove(&pid);
+                    listing.to_onchain_app(&pid, rating)
+                })
                 .collect();
             let response = ChainResponse::GetApps(apps);
             Response::new().body(&response).send()?;
@@ -456,7 +807,8 @@ fn handle_local_request(our: &Address, state: &mut State, req: ChainRequest) ->
             let mut apps = Vec::new();
             for pid in published_list {
                 if let Some(listing) = state.db.get_listing(&pid)? {
-                    apps.push(listing.to_onchain_app(&pid));
+                    let rating = state.db.get_rating_summary(&pid)?;
+                    apps.push(listing.to_onchain_app(&pid, rating));
                 }
             }
             let response = ChainResponse::GetOurApps(apps);
@@ -491,10 +843,382 @@ fn handle_local_request(our: &Address, state: &mut State, req: ChainRequest) ->
             Response::new().body(&ChainResponse::ResetOk).send()?;
             panic!("resetting state, restarting!");
         }
+        ChainRequest::GetReviews(package_id) => {
+            let pid = package_id.to_process_lib();
+            let reviews = state.db.get_reviews(&pid, MAX_REVIEWS)?;
+            Response::new()
+                .body(&ChainResponse::GetReviews(reviews))
+                .send()?;
+            sync_reviews(our, state, &pid)?;
+        }
+        ChainRequest::PublishReview(request) => {
+            let response = match publish_review(our, state, request)? {
+                Ok(()) => ChainResponse::ReviewPublished,
+                Err(e) => ChainResponse::Err(e),
+            };
+            Response::new().body(&response).send()?;
+        }
+        ChainRequest::AddReviews(reviews) => {
+            add_reviews(our, state, reviews)?;
+        }
+        ChainRequest::SetFilterPolicy(policy) => {
+            let loosened = loosens(&state.filter_policy, &policy);
+            set_state(&serde_json::to_vec(&policy)?);
+            state.filter_policy = policy;
+            for (pid, listing) in state.db.get_all_listings()? {
+                if is_filtered_out(our, &state.filter_policy, &pid, listing.metadata.as_ref()) {
+                    state.db.delete_listing(&pid)?;
+                }
+            }
+            Response::new()
+                .body(&ChainResponse::FilterPolicySet)
+                .send()?;
+            // the listings left out before were removed, so are found again in the logs
+            if loosened {
+                reindex_listings(our, state);
+            }
+        }
+        ChainRequest::GetFilterPolicy => {
+            Response::new()
+                .body(&ChainResponse::GetFilterPolicy(state.filter_policy.clone()))
+                .send()?;
+        }
     }
     Ok(())
 }
 
+/// Whether our filter policy leaves out a listing, by its package ID, and by
+/// its metadata if we have it.
+fn is_filtered_out(
+    our: &Address,
+    policy: &FilterPolicy,
+    package_id: &PackageId,
+    metadata: Option<&kt::Erc721Metadata>,
+) -> bool {
+    if package_id.publisher() == our.node() {
+        return false;
+    }
+    let id = package_id.to_string();
+    if policy
+        .deny
+        .iter()
+        .any(|pattern| matches_pattern(pattern, &id))
+    {
+        return true;
+    }
+    if !policy.allow.is_empty()
+        && !policy
+            .allow
+            .iter()
+            .any(|pattern| matches_pattern(pattern, &id))
+    {
+        return true;
+    }
+    match metadata {
+        Some(metadata) => {
+            policy.deny_missing_code_hashes
+                && !metadata
+                    .properties
+                    .code_hashes
+                    .contains_key(&metadata.properties.current_version)
+        }
+        None => false,
+    }
+}
+
+/// Whether a new filter policy may let in listings the old one left out.
+fn loosens(old: &FilterPolicy, new: &FilterPolicy) -> bool {
+    old.deny.iter().any(|pattern| !new.deny.contains(pattern))
+        || (!old.allow.is_empty()
+            && (new.allow.is_empty()
+                || new.allow.iter().any(|pattern| !old.allow.contains(pattern))))
+        || (old.deny_missing_code_hashes && !new.deny_missing_code_hashes)
+}
+
+/// Index every listing again from the logs from block 0, bringing back those
+/// a stricter filter policy left out.
+fn reindex_listings(our: &Address, state: &mut State) {
+    let filter = app_store_filter(state).from_block(0);
+    for log in fetch_logs(&state.kimap.provider, &filter) {
+        if let Err(e) = handle_eth_log(our, state, log, true) {
+            print_to_terminal(1, &format!("error ingesting log: {e}"));
+        };
+    }
+    update_all_metadata(our, state, 0);
+}
+
+/// Whether text matches a pattern in which `*` stands for any run of characters.
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.peekable();
+    if parts.peek().is_none() {
+        // no `*`: the whole text must match
+        return rest.is_empty();
+    }
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            // the last part must end the text
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Drop a listing our filter policy leaves out, if we have it, having
+/// handled the log at the given block.
+fn leave_out_listing(
+    state: &mut State,
+    package_id: &PackageId,
+    block_number: u64,
+    startup: bool,
+) -> anyhow::Result<()> {
+    print_to_terminal(1, &format!("leaving out listing {package_id}"));
+    state.db.delete_listing(package_id)?;
+    if !startup {
+        state.last_saved_block = block_number;
+        state.db.set_last_saved_block(block_number)?;
+    }
+    Ok(())
+}
+
+/// Requests from the chain processes of other nodes, which may only exchange reviews.
+fn handle_remote_request(
+    our: &Address,
+    state: &mut State,
+    source: &Address,
+    req: ChainRequest,
+) -> anyhow::Result<()> {
+    match req {
+        ChainRequest::GetReviews(package_id) => {
+            let reviews = state
+                .db
+                .get_reviews(&package_id.to_process_lib(), MAX_REVIEWS)?;
+            Response::new()
+                .body(&ChainResponse::GetReviews(reviews))
+                .send()?;
+        }
+        ChainRequest::AddReviews(reviews) => {
+            add_reviews(our, state, reviews)?;
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "got request from {source} that is only accepted from our node"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Sign our review of an app, keep it, and send it to the nodes that serve the app.
+fn publish_review(
+    our: &Address,
+    state: &mut State,
+    request: PublishReviewRequest,
+) -> anyhow::Result<Result<(), ChainError>> {
+    let pid = request.package_id.clone().to_process_lib();
+    let Some(listing) = state.db.get_listing(&pid)? else {
+        return Ok(Err(ChainError::NoPackage));
+    };
+    let mut review = Review {
+        package_id: request.package_id,
+        reviewer: our.node().to_string(),
+        rating: request.rating,
+        text: request.text.filter(|text| !text.trim().is_empty()),
+        timestamp: now(),
+        signature: vec![],
+    };
+    if !is_valid_review(&review) {
+        return Ok(Err(ChainError::InvalidReview));
+    }
+    let Ok(signature) = sign(&review_message(&review)) else {
+        return Ok(Err(ChainError::SignFailed));
+    };
+    review.signature = signature;
+    state.db.insert_or_update_review(&review)?;
+    send_reviews(our, &pid, &listing, vec![review]);
+    Ok(Ok(()))
+}
+
+/// Keep the reviews sent to us that are new, and pass them on to the nodes that
+/// serve their apps, which do the same, until every one of those has them.
+fn add_reviews(our: &Address, state: &mut State, reviews: Vec<Review>) -> anyhow::Result<()> {
+    let mut new_reviews: HashMap<PackageId, Vec<Review>> = HashMap::new();
+    for review in merge_reviews(state, reviews)? {
+        new_reviews
+            .entry(review.package_id.clone().to_process_lib())
+            .or_default()
+            .push(review);
+    }
+    for (pid, reviews) in new_reviews {
+        if let Some(listing) = state.db.get_listing(&pid)? {
+            send_reviews(our, &pid, &listing, reviews);
+        }
+    }
+    Ok(())
+}
+
+/// Keep the reviews of listed apps that are newer than the ones we have from their
+/// reviewers and whose signatures verify. Returns the ones kept.
+fn merge_reviews(state: &State, reviews: Vec<Review>) -> anyhow::Result<Vec<Review>> {
+    let mut listed: HashMap<PackageId, bool> = HashMap::new();
+    let mut kept = vec![];
+    for review in reviews.into_iter().take(MAX_REVIEWS) {
+        let pid = review.package_id.clone().to_process_lib();
+        if !is_valid_review(&review) {
+            continue;
+        }
+        let is_listed = match listed.get(&pid) {
+            Some(is_listed) => *is_listed,
+            None => {
+                let is_listed = state.db.get_listing(&pid)?.is_some();
+                listed.insert(pid.clone(), is_listed);
+                is_listed
+            }
+        };
+        if !is_listed {
+            continue;
+        }
+        if state
+            .db
+            .get_review(&pid, &review.reviewer)?
+            .is_some_and(|ours| ours.timestamp >= review.timestamp)
+        {
+            continue;
+        }
+        if !verify(&review) {
+            print_to_terminal(
+                1,
+                &format!(
+                    "chain: dropping review of {pid} by {} with a bad signature",
+                    review.reviewer
+                ),
+            );
+            continue;
+        }
+        state.db.insert_or_update_review(&review)?;
+        kept.push(review);
+    }
+    Ok(kept)
+}
+
+/// Ask the nodes that serve an app for its reviews, unless we did so lately.
+/// They respond in their own time, and are handled in `handle_message`.
+fn sync_reviews(our: &Address, state: &mut State, pid: &PackageId) -> anyhow::Result<()> {
+    let now = now();
+    if state
+        .review_syncs
+        .get(pid)
+        .is_some_and(|synced| now < synced + REVIEW_SYNC_INTERVAL)
+    {
+        return Ok(());
+    }
+    state.review_syncs.insert(pid.clone(), now);
+    let Some(listing) = state.db.get_listing(pid)? else {
+        return Ok(());
+    };
+    for node in review_peers(our, pid, &listing) {
+        Request::to((node.as_str(), "chain", "app-store", "sys"))
+            .body(&ChainRequest::GetReviews(
+                crate::kinode::process::main::PackageId::from_process_lib(pid.clone()),
+            ))
+            .expects_response(NET_TIMEOUT)
+            .send()?;
+    }
+    Ok(())
+}
+
+fn send_reviews(our: &Address, pid: &PackageId, listing: &PackageListing, reviews: Vec<Review>) {
+    for node in review_peers(our, pid, listing) {
+        let _ = Request::to((node.as_str(), "chain", "app-store", "sys"))
+            .body(&ChainRequest::AddReviews(reviews.clone()))
+            .send();
+    }
+}
+
+/// The nodes that serve an app, and so keep its reviews: its publisher and mirrors.
+fn review_peers(our: &Address, pid: &PackageId, listing: &PackageListing) -> HashSet<String> {
+    let mut peers: HashSet<String> = listing
+        .metadata
+        .as_ref()
+        .map(|metadata| metadata.properties.mirrors.iter().cloned().collect())
+        .unwrap_or_default();
+    peers.insert(pid.publisher().to_string());
+    peers.remove(our.node());
+    peers
+}
+
+fn is_valid_review(review: &Review) -> bool {
+    (1..=5).contains(&review.rating)
+        && review
+            .text
+            .as_ref()
+            .map_or(true, |text| text.len() <= MAX_REVIEW_LENGTH)
+        && review.timestamp <= now() + MAX_REVIEW_CLOCK_DRIFT
+}
+
+/// The bytes of a review that its reviewer signs.
+fn review_message(review: &Review) -> Vec<u8> {
+    serde_json::to_vec(&(
+        &review.package_id,
+        review.rating,
+        &review.text,
+        review.timestamp,
+    ))
+    .unwrap()
+}
+
+/// Sign with the networking key of our node, as our process.
+fn sign(message: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let response = Request::to(("our", "net", "distro", "sys"))
+        .body(rmp_serde::to_vec(&net::NetAction::Sign)?)
+        .blob_bytes(message)
+        .send_and_await_response(NET_TIMEOUT)??;
+    let Ok(net::NetResponse::Signed) = rmp_serde::from_slice(response.body()) else {
+        return Err(anyhow::anyhow!("unexpected response from net"));
+    };
+    get_blob()
+        .map(|blob| blob.bytes)
+        .ok_or(anyhow::anyhow!("no signature from net"))
+}
+
+/// Whether a review was signed by the chain process of its reviewer. False if the
+/// reviewer is not a node we know of.
+fn verify(review: &Review) -> bool {
+    let Ok(Ok(response)) = Request::to(("our", "net", "distro", "sys"))
+        .body(
+            rmp_serde::to_vec(&net::NetAction::Verify {
+                from: Address::new(&review.reviewer, ("chain", "app-store", "sys")),
+                signature: review.signature.clone(),
+            })
+            .unwrap(),
+        )
+        .blob_bytes(review_message(review))
+        .send_and_await_response(NET_TIMEOUT)
+    else {
+        return false;
+    };
+    matches!(
+        rmp_serde::from_slice(response.body()),
+        Ok(net::NetResponse::Verified(true))
+    )
+}
+
+/// UNIX timestamp in seconds
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
 fn handle_eth_log(
     our: &Address,
     state: &mut State,
@@ -528,6 +1252,11 @@ fn handle_eth_log(
     let metadata_uri = String::from_utf8_lossy(&note.data).to_string();
     let is_our_package = package_id.publisher() == our.node();
 
+    // spare fetching the metadata of listings left out by their package ID
+    if is_filtered_out(our, &state.filter_policy, &package_id, None) {
+        return leave_out_listing(state, &package_id, block_number, startup);
+    }
+
     let (tba, metadata_hash) = if !startup {
         // generate ~metadata-hash full-path
         let hash_note = format!("~metadata-hash.{}", note.parent_path);
@@ -577,11 +1306,15 @@ fn handle_eth_log(
     // we'll loop over all listings after processing all logs and fetch them as needed.
     // fetch metadata from the URI (currently only handling HTTP(S) URLs!)
     // assert that the metadata hash matches the fetched data
-    let metadata = if !startup {
-        Some(fetch_metadata_from_url(&metadata_uri, &metadata_hash, 30)?)
+    let (metadata, extras) = if !startup {
+        let (metadata, extras) = fetch_metadata_from_url(&metadata_uri, &metadata_hash, 30)?;
+        (Some(metadata), Some(extras))
     } else {
-        None
+        (None, None)
     };
+    if is_filtered_out(our, &state.filter_policy, &package_id, metadata.as_ref()) {
+        return leave_out_listing(state, &package_id, block_number, startup);
+    }
 
     let mut listing = state
         .db
@@ -601,6 +1334,20 @@ fn handle_eth_log(
     listing.metadata = metadata.clone();
 
     state.db.insert_or_update_listing(&package_id, &listing)?;
+    if let Some(extras) = &extras {
+        state
+            .db
+            .insert_release_notes(&package_id, &extras.changelog)?;
+        state
+            .db
+            .set_license_acceptance(&package_id, extras.license_acceptance.as_ref())?;
+    }
+
+    if !startup && !listing.auto_update && !is_our_package {
+        if let (Some(metadata), Some(extras)) = (&metadata, &extras) {
+            notify_update_available(&package_id, metadata, &extras.changelog);
+        }
+    }
 
     if !startup && listing.auto_update {
         println!("kicking off auto-update for: {}", package_id);
@@ -623,10 +1370,41 @@ fn handle_eth_log(
     Ok(())
 }
 
+/// Tell notify:distro:sys that a new version of a package is published,
+/// along with what is new in it, if we have the package installed.
+fn notify_update_available(
+    package_id: &PackageId,
+    metadata: &kt::Erc721Metadata,
+    changelog: &[ReleaseNotes],
+) {
+    if vfs::metadata(&format!("/{package_id}/pkg/manifest.json"), Some(5)).is_err() {
+        return;
+    }
+    let version = &metadata.properties.current_version;
+    let notes = changelog
+        .iter()
+        .find(|release| &release.version == version)
+        .map(|release| release.notes.clone());
+    let _ = Request::to(("our", "notify", "distro", "sys"))
+        .body(
+            serde_json::to_vec(&serde_json::json!({
+                "Event": {
+                    "UpdateAvailable": {
+                        "package_id": package_id.to_string(),
+                        "version": version,
+                        "notes": notes,
+                    }
+                }
+            }))
+            .unwrap(),
+        )
+        .send();
+}
+
 /// after startup, fetch metadata for all listings
 /// we do this as a separate step to not repeatedly fetch outdated metadata
 /// as we process logs.
-fn update_all_metadata(state: &mut State, last_saved_block: u64) {
+fn update_all_metadata(our: &Address, state: &mut State, last_saved_block: u64) {
     let updated_listings = match state.db.get_listings_since_block(last_saved_block) {
         Ok(listings) => listings,
         Err(e) => {
@@ -704,19 +1482,38 @@ fn update_all_metadata(state: &mut State, last_saved_block: u64) {
         listing.tba = tba;
         listing.metadata_hash = metadata_hash;
 
-        let metadata =
+        let (metadata, extras) =
             match fetch_metadata_from_url(&listing.metadata_uri, &listing.metadata_hash, 30) {
-                Ok(md) => Some(md),
+                Ok((md, extras)) => (Some(md), Some(extras)),
                 Err(err) => {
                     print_to_terminal(1, &format!("error fetching metadata for {}: {err}", pid));
-                    None
+                    (None, None)
                 }
             };
         listing.metadata = metadata.clone();
 
+        if is_filtered_out(our, &state.filter_policy, &pid, metadata.as_ref()) {
+            print_to_terminal(1, &format!("leaving out listing {pid}"));
+            if let Err(e) = state.db.delete_listing(&pid) {
+                print_to_terminal(1, &format!("error deleting listing: {e}"));
+            }
+            continue;
+        }
+
         if let Err(e) = state.db.insert_or_update_listing(&pid, &listing) {
             print_to_terminal(1, &format!("error updating listing {}: {e}", pid));
         }
+        if let Some(extras) = extras {
+            if let Err(e) = state.db.insert_release_notes(&pid, &extras.changelog) {
+                print_to_terminal(1, &format!("error updating changelog {}: {e}", pid));
+            }
+            if let Err(e) = state
+                .db
+                .set_license_acceptance(&pid, extras.license_acceptance.as_ref())
+            {
+                print_to_terminal(1, &format!("error updating license {}: {e}", pid));
+            }
+        }
 
         if listing.auto_update {
             if let Some(md) = metadata {
@@ -765,7 +1562,7 @@ pub fn fetch_and_subscribe_logs(our: &Address, state: &mut State, last_saved_blo
         };
     }
 
-    update_all_metadata(state, last_saved_block);
+    update_all_metadata(our, state, last_saved_block);
     // save updated last_saved_block
     if let Ok(block_number) = state.kimap.provider.get_block_number() {
         state.last_saved_block = block_number;
@@ -788,12 +1585,13 @@ fn fetch_logs(eth_provider: &eth::Provider, filter: &eth::Filter) -> Vec<eth::Lo
     }
 }
 
-/// fetch metadata from url and verify it matches metadata_hash
+/// fetch metadata from url and verify it matches metadata_hash,
+/// along with the release notes and license to accept it gives
 pub fn fetch_metadata_from_url(
     metadata_url: &str,
     metadata_hash: &str,
     timeout: u64,
-) -> Result<kt::Erc721Metadata, anyhow::Error> {
+) -> Result<(kt::Erc721Metadata, MetadataExtras), anyhow::Error> {
     if let Ok(url) = url::Url::parse(metadata_url) {
         if let Ok(_) =
             http::client::send_request_await_response(http::Method::GET, url, None, timeout, vec![])
@@ -801,8 +1599,20 @@ pub fn fetch_metadata_from_url(
             if let Some(body) = get_blob() {
                 let hash = keccak_256_hash(&body.bytes);
                 if &hash == metadata_hash {
-                    return Ok(serde_json::from_slice::<kt::Erc721Metadata>(&body.bytes)
-                        .map_err(|_| anyhow::anyhow!("metadata not found"))?);
+                    let metadata = serde_json::from_slice::<kt::Erc721Metadata>(&body.bytes)
+                        .map_err(|_| anyhow::anyhow!("metadata not found"))?;
+                    let properties = serde_json::from_slice::<ExtraMetadata>(&body.bytes)
+                        .unwrap_or_default()
+                        .properties;
+                    let extras = MetadataExtras {
+                        changelog: fetch_changelog(
+                            properties.changelog,
+                            properties.changelog_uri,
+                            timeout,
+                        ),
+                        license_acceptance: properties.license_acceptance,
+                    };
+                    return Ok((metadata, extras));
                 } else {
                     return Err(anyhow::anyhow!("metadata hash mismatch"));
                 }
@@ -812,6 +1622,56 @@ pub fn fetch_metadata_from_url(
     Err(anyhow::anyhow!("metadata not found"))
 }
 
+/// the release notes given in metadata, and at its `changelog_uri`, if any.
+/// those at the URI are not covered by the metadata hash: the embedded ones
+/// win where both give a version.
+fn fetch_changelog(
+    embedded: HashMap<String, String>,
+    changelog_uri: Option<String>,
+    timeout: u64,
+) -> Vec<ReleaseNotes> {
+    let mut changelog = HashMap::new();
+    if let Some(url) = changelog_uri.and_then(|uri| url::Url::parse(&uri).ok()) {
+        if let Ok(_) = http::client::send_request_await_response(
+            http::Method::GET,
+            url,
+            None,
+            timeout.min(CHANGELOG_TIMEOUT),
+            vec![],
+        ) {
+            if let Some(body) = get_blob() {
+                if body.bytes.len() > MAX_CHANGELOG_LENGTH {
+                    print_to_terminal(1, "changelog too large, leaving it out");
+                } else {
+                    match serde_json::from_slice::<HashMap<String, String>>(&body.bytes) {
+                        Ok(fetched) => changelog = fetched,
+                        Err(e) => print_to_terminal(1, &format!("malformed changelog: {e}")),
+                    }
+                }
+            }
+        }
+    }
+    changelog.extend(embedded);
+    let mut changelog: Vec<ReleaseNotes> = changelog
+        .into_iter()
+        .filter(|(version, notes)| !version.is_empty() && notes.len() <= MAX_RELEASE_NOTES_LENGTH)
+        .map(|(version, notes)| ReleaseNotes { version, notes })
+        .collect();
+    // the newest versions are kept
+    changelog.sort_by(|a, b| version_key(&b.version).cmp(&version_key(&a.version)));
+    changelog.truncate(MAX_CHANGELOG_VERSIONS);
+    changelog
+}
+
+/// the parts of a version such as `1.10.2`, numbers compared as numbers,
+/// to order versions newest first
+fn version_key(version: &str) -> Vec<(u64, String)> {
+    version
+        .split('.')
+        .map(|part| (part.parse().unwrap_or(0), part.to_string()))
+        .collect()
+}
+
 /// generate a Keccak-256 hash string (with 0x prefix) of the metadata bytes
 pub fn keccak_256_hash(bytes: &[u8]) -> String {
     use sha3::{Digest, Keccak256};
@@ -839,7 +1699,11 @@ impl crate::kinode::process::main::PackageId {
 }
 
 impl PackageListing {
-    pub fn to_onchain_app(&self, package_id: &PackageId) -> OnchainApp {
+    pub fn to_onchain_app(
+        &self,
+        package_id: &PackageId,
+        rating: Option<RatingSummary>,
+    ) -> OnchainApp {
         OnchainApp {
             package_id: crate::kinode::process::main::PackageId::from_process_lib(
                 package_id.clone(),
@@ -849,6 +1713,9 @@ impl PackageListing {
             metadata_hash: self.metadata_hash.clone(),
             metadata: self.metadata.as_ref().map(|m| m.clone().into()),
             auto_update: self.auto_update,
+            rating,
+            changelog: vec![],
+            license_acceptance: None,
         }
     }
 }
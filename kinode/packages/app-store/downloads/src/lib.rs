@@ -10,6 +10,7 @@
 //! 3. Coordinate file transfers between nodes using the File Transfer (FT) worker.
 //! 4. Handle mirroring settings for apps.
 //! 5. Manage auto-updates for installed apps.
+//! 6. Notify subscribed local processes when downloads complete.
 //!
 //! ## Key Components:
 //!
@@ -36,24 +37,26 @@
 //! 2. For remote downloads, the process spawns an FT worker to handle the transfer.
 //! 3. For HTTP downloads, the process handles the download directly.
 //! 4. Downloaded files are stored locally and their integrity is verified.
-//! 5. Progress and completion status are reported back to the requester.
+//! 5. Progress and completion status are reported back to the requester,
+//!    as well as to any local processes subscribed to the package.
 //!
 //! Note: While this process coordinates file transfers, the actual chunked transfer
 //! mechanism is implemented in the FT worker for improved modularity and performance.
 //!
 use crate::kinode::process::downloads::{
-    AutoDownloadCompleteRequest, AutoDownloadError, AutoUpdateRequest, DirEntry,
-    DownloadCompleteRequest, DownloadError, DownloadRequest, DownloadResponse, Entry, FileEntry,
-    HashMismatch, LocalDownloadRequest, RemoteDownloadRequest, RemoveFileRequest,
+    AutoDownloadCompleteRequest, AutoDownloadError, AutoUpdateRequest,
+    Capability as DownloadsCapability, DirEntry, DownloadCompleteRequest, DownloadError,
+    DownloadRequest, DownloadResponse, DownloadSubscription, Entry, FileEntry, HashMismatch,
+    LocalDownloadRequest, RemoteDownloadRequest, RemoveFileRequest,
 };
-use ft_worker_lib::{spawn_receive_transfer, spawn_send_transfer};
+use ft_worker_lib::{check_zip, spawn_receive_transfer, spawn_send_transfer};
 use kinode::process::downloads::AutoDownloadSuccess;
 use kinode_process_lib::{
     await_message, call_init, get_blob, get_state,
     http::client,
-    print_to_terminal, println, set_state,
+    print_to_terminal, println, save_capabilities, set_state,
     vfs::{self, Directory},
-    Address, Message, PackageId, ProcessId, Request, Response, SendErrorKind,
+    Address, Capability, Message, PackageId, ProcessId, Request, Response, SendErrorKind,
 };
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -90,10 +93,40 @@ pub struct AutoUpdateStatus {
 
 type AutoUpdates = HashMap<(PackageId, String), AutoUpdateStatus>;
 
+/// A local process that wants to be told when downloads complete.
+/// `None` for package or version matches every package or version.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Subscription {
+    subscriber: Address,
+    package_id: Option<PackageId>,
+    version_hash: Option<String>,
+}
+
+impl Subscription {
+    fn new(subscriber: Address, subscription: DownloadSubscription) -> Self {
+        Subscription {
+            subscriber,
+            package_id: subscription.package_id.map(|id| id.to_process_lib()),
+            version_hash: subscription.version_hash,
+        }
+    }
+
+    fn matches(&self, package_id: &PackageId, version_hash: &str) -> bool {
+        self.package_id.as_ref().map_or(true, |id| id == package_id)
+            && self
+                .version_hash
+                .as_ref()
+                .map_or(true, |hash| hash == version_hash)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct State {
     // persisted metadata about which packages we are mirroring
     mirroring: HashSet<PackageId>,
+    // persisted download-complete subscriptions of local processes
+    #[serde(default)]
+    subscriptions: HashSet<Subscription>,
     // note, pending auto_updates are not persisted.
 }
 
@@ -104,10 +137,12 @@ impl State {
                 Ok(state) => state,
                 Err(_) => State {
                     mirroring: HashSet::new(),
+                    subscriptions: HashSet::new(),
                 },
             },
             None => State {
                 mirroring: HashSet::new(),
+                subscriptions: HashSet::new(),
             },
         }
     }
@@ -292,10 +327,9 @@ fn handle_message(
                     return Err(anyhow::anyhow!("got non local download complete"));
                 }
 
-                // forward to main:app-store:sys, pushed to UI via websockets
-                Request::to(("our", "main", "app-store", "sys"))
-                    .body(serde_json::to_vec(&req)?)
-                    .send()?;
+                // forward to main:app-store:sys, pushed to UI via websockets,
+                // and to any subscribers
+                notify_download_complete(state, &req)?;
 
                 // Check if this is an auto-update download
                 let key = (
@@ -371,6 +405,15 @@ fn handle_message(
                 };
                 let bytes = blob.bytes;
 
+                if let Err(e) = check_zip(std::io::Cursor::new(&bytes)) {
+                    Response::new()
+                        .body(Resp::Download(DownloadResponse::Err(
+                            DownloadError::InvalidArchive(e),
+                        )))
+                        .send()?;
+                    return Ok(());
+                }
+
                 let package_dir = format!(
                     "{}/{}",
                     downloads.path,
@@ -380,8 +423,7 @@ fn handle_message(
 
                 // Write the zip file
                 let zip_path = format!("{}/{}.zip", package_dir, add_req.version_hash);
-                let file = vfs::create_file(&zip_path, None)?;
-                file.write(bytes.as_slice())?;
+                write_atomic(&zip_path, &bytes)?;
 
                 // Extract and write the manifest
                 let manifest_path = format!("{}/{}.json", package_dir, add_req.version_hash);
@@ -413,6 +455,48 @@ fn handle_message(
                     .body(Resp::Download(DownloadResponse::Success))
                     .send()?;
             }
+            DownloadRequest::Subscribe(subscription) => {
+                if !message.is_local(our) {
+                    return Err(anyhow::anyhow!("not local"));
+                }
+                let required_capability = Capability::new(
+                    our,
+                    serde_json::to_string(&DownloadsCapability::Subscribe).unwrap(),
+                );
+                if !message.capabilities().contains(&required_capability) {
+                    Response::new()
+                        .body(Resp::Download(DownloadResponse::Err(
+                            DownloadError::MissingCapability,
+                        )))
+                        .send()?;
+                    return Ok(());
+                }
+                // keep the subscriber's messaging capability, if attached,
+                // so that notifications can be sent to it
+                let messaging_capability = Capability::new(message.source(), "\"messaging\"");
+                if message.capabilities().contains(&messaging_capability) {
+                    save_capabilities(&[messaging_capability]);
+                }
+                state
+                    .subscriptions
+                    .insert(Subscription::new(message.source().clone(), subscription));
+                set_state(&serde_json::to_vec(&state)?);
+                Response::new()
+                    .body(Resp::Download(DownloadResponse::Success))
+                    .send()?;
+            }
+            DownloadRequest::Unsubscribe(subscription) => {
+                if !message.is_local(our) {
+                    return Err(anyhow::anyhow!("not local"));
+                }
+                state
+                    .subscriptions
+                    .remove(&Subscription::new(message.source().clone(), subscription));
+                set_state(&serde_json::to_vec(&state)?);
+                Response::new()
+                    .body(Resp::Download(DownloadResponse::Success))
+                    .send()?;
+            }
             DownloadRequest::AutoUpdate(auto_update_request) => {
                 if !message.is_local(&our)
                     && message.source().process != ProcessId::new(Some("chain"), "app-store", "sys")
@@ -509,13 +593,14 @@ fn handle_message(
                                 try_next_mirror(metadata, key, auto_updates, e);
                             } else {
                                 // If not an auto-update, forward error normally
-                                Request::to(("our", "main", "app-store", "sys"))
-                                    .body(DownloadCompleteRequest {
+                                notify_download_complete(
+                                    state,
+                                    &DownloadCompleteRequest {
                                         package_id: download_request.package_id,
                                         version_hash: download_request.desired_version_hash,
                                         err: Some(e),
-                                    })
-                                    .send()?;
+                                    },
+                                )?;
                             }
                         }
                         DownloadResponse::Success => {
@@ -560,6 +645,7 @@ fn handle_message(
 
                 if resp.status != 200 {
                     handle_download_error(
+                        state,
                         is_auto_update,
                         metadata,
                         key,
@@ -571,9 +657,10 @@ fn handle_message(
                 }
 
                 // Handle successful download
-                if let Err(e) = handle_receive_http_download(&download_request) {
+                if let Err(e) = handle_receive_http_download(state, &download_request) {
                     print_to_terminal(1, &format!("error handling http-client response: {:?}", e));
                     handle_download_error(
+                        state,
                         is_auto_update,
                         metadata,
                         key,
@@ -674,6 +761,7 @@ fn try_next_mirror(
 }
 
 fn handle_receive_http_download(
+    state: &State,
     download_request: &LocalDownloadRequest,
 ) -> anyhow::Result<(), DownloadError> {
     let package_id = download_request.package_id.clone().to_process_lib();
@@ -700,31 +788,32 @@ fn handle_receive_http_download(
             actual: calculated_hash,
         }));
     }
+    check_zip(std::io::Cursor::new(&bytes)).map_err(DownloadError::InvalidArchive)?;
 
     // Write the zip file
     let zip_path = format!("{}/{}.zip", package_dir, version_hash);
-    let file = vfs::create_file(&zip_path, None).map_err(|_| DownloadError::VfsError)?;
-    file.write(bytes.as_slice())
-        .map_err(|_| DownloadError::VfsError)?;
+    write_atomic(&zip_path, &bytes).map_err(|_| DownloadError::VfsError)?;
 
     // Write the manifest file
     // Extract and write the manifest
     let manifest_path = format!("{}/{}.json", package_dir, version_hash);
     extract_and_write_manifest(&bytes, &manifest_path).map_err(|_| DownloadError::VfsError)?;
 
-    Request::to(("our", "main", "app-store", "sys"))
-        .body(DownloadCompleteRequest {
+    notify_download_complete(
+        state,
+        &DownloadCompleteRequest {
             package_id: download_request.package_id.clone(),
             version_hash,
             err: None,
-        })
-        .send()
-        .unwrap();
+        },
+    )
+    .unwrap();
 
     Ok(())
 }
 
 fn handle_download_error(
+    state: &State,
     is_auto_update: bool,
     metadata: Option<AutoUpdateStatus>,
     key: (PackageId, String),
@@ -738,13 +827,30 @@ fn handle_download_error(
             try_next_mirror(meta, key, auto_updates, error);
         }
     } else {
-        Request::to(("our", "main", "app-store", "sys"))
-            .body(DownloadCompleteRequest {
+        notify_download_complete(
+            state,
+            &DownloadCompleteRequest {
                 package_id: download_request.package_id.clone(),
                 version_hash: download_request.desired_version_hash.clone(),
                 err: Some(error),
-            })
-            .send()?;
+            },
+        )?;
+    }
+    Ok(())
+}
+
+/// Send a completed (or failed) download to main:app-store:sys,
+/// and to every local process subscribed to its package and version
+fn notify_download_complete(state: &State, req: &DownloadCompleteRequest) -> anyhow::Result<()> {
+    Request::to(("our", "main", "app-store", "sys"))
+        .body(req)
+        .send()?;
+
+    let package_id = req.package_id.clone().to_process_lib();
+    for subscription in &state.subscriptions {
+        if subscription.matches(&package_id, &req.version_hash) {
+            Request::to(&subscription.subscriber).body(req).send()?;
+        }
     }
     Ok(())
 }
@@ -808,8 +914,7 @@ fn extract_and_write_manifest(file_contents: &[u8], manifest_path: &str) -> anyh
             let mut contents = String::new();
             file.read_to_string(&mut contents)?;
 
-            let manifest_file = vfs::open_file(&manifest_path, true, None)?;
-            manifest_file.write(contents.as_bytes())?;
+            write_atomic(manifest_path, contents.as_bytes())?;
 
             print_to_terminal(1, &format!("Extracted and wrote manifest.json"));
             break;
@@ -819,6 +924,23 @@ fn extract_and_write_manifest(file_contents: &[u8], manifest_path: &str) -> anyh
     Ok(())
 }
 
+/// Write a VFS file in one step, so that a crash mid-write
+/// leaves its previous contents rather than a partial file.
+fn write_atomic(path: &str, bytes: &[u8]) -> anyhow::Result<()> {
+    let response = Request::to(("our", "vfs", "distro", "sys"))
+        .body(serde_json::to_vec(&serde_json::json!({
+            "path": path,
+            "action": "WriteAtomic",
+        }))?)
+        .blob_bytes(bytes)
+        .send_and_await_response(VFS_TIMEOUT)??;
+    match serde_json::from_slice::<vfs::VfsResponse>(response.body())? {
+        vfs::VfsResponse::Ok => Ok(()),
+        vfs::VfsResponse::Err(e) => Err(anyhow::anyhow!("vfs: {e:?}")),
+        _ => Err(anyhow::anyhow!("vfs: unexpected response to WriteAtomic")),
+    }
+}
+
 /// Check if a download zip exists for a given package and version hash.
 /// Used to check if we can share a package or not!
 fn download_zip_exists(package_id: &PackageId, version_hash: &str) -> bool {
@@ -1,12 +1,19 @@
 //! Helper functions for spawning file transfer workers.
 //! These functions are used to initiate send and receive operations
-//! for file transfers in the App Store system
+//! for file transfers in the App Store system,
+//! and to check the zips they receive before those are accepted.
 //!
 use crate::kinode::process::downloads::{
-    DownloadRequest, LocalDownloadRequest, PackageId, RemoteDownloadRequest,
+    DownloadRequest, InvalidArchive, LocalDownloadRequest, PackageId, RemoteDownloadRequest,
 };
 
 use kinode_process_lib::*;
+use std::io::{Read, Seek};
+
+/// most entries a package zip may have
+const MAX_ZIP_ENTRIES: usize = 10_000;
+/// most bytes a package zip may hold once unzipped
+const MAX_UNZIPPED_SIZE: u64 = 1024 * 1024 * 1024; // 1GiB
 
 /// Spawns a worker process to send a file transfer.
 ///
@@ -82,3 +89,39 @@ pub fn spawn_receive_transfer(
     req.send()?;
     Ok(Address::new(&our.node, worker_process_id))
 }
+
+/// Checks that a downloaded zip is one we accept as a package: that no entry
+/// path is absolute or leaves the package, that its entries, counted by what
+/// they really unzip to rather than what they claim, stay within bounds, and
+/// that it has a manifest. Mirrors are not trusted: a zip bomb or a `../`
+/// path would otherwise only be met on install.
+#[allow(dead_code)]
+pub fn check_zip<R: Read + Seek>(reader: R) -> Result<(), InvalidArchive> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(|_| InvalidArchive::Unreadable)?;
+    if archive.len() > MAX_ZIP_ENTRIES {
+        return Err(InvalidArchive::TooLarge);
+    }
+    let mut has_manifest = false;
+    let mut remaining = MAX_UNZIPPED_SIZE;
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|_| InvalidArchive::Unreadable)?;
+        if file.enclosed_name().is_none() {
+            return Err(InvalidArchive::UnsafePath(file.name().to_string()));
+        }
+        if file.name() == "manifest.json" {
+            has_manifest = true;
+        }
+        let unzipped = std::io::copy(&mut (&mut file).take(remaining + 1), &mut std::io::sink())
+            .map_err(|_| InvalidArchive::Unreadable)?;
+        if unzipped > remaining {
+            return Err(InvalidArchive::TooLarge);
+        }
+        remaining -= unzipped;
+    }
+    if !has_manifest {
+        return Err(InvalidArchive::MissingManifest);
+    }
+    Ok(())
+}
@@ -22,6 +22,8 @@
 //!    - A new file is created to store the incoming data.
 //!    - Chunks are received and written to the file.
 //!    - The file's integrity is verified using a SHA256 hash.
+//!    - The zip is checked for unsafe paths, excessive size and a missing manifest,
+//!      and removed if it fails.
 //!    - The manifest is extracted and saved separately.
 //! 4. Upon completion or error, a status message is sent to the parent process.
 //!
@@ -44,12 +46,9 @@ use crate::kinode::process::downloads::{
     LocalDownloadRequest, ProgressUpdate, RemoteDownloadRequest, SizeUpdate,
 };
 use kinode_process_lib::*;
-use kinode_process_lib::{
-    print_to_terminal, println, timer,
-    vfs::{File, SeekFrom},
-};
+use kinode_process_lib::{print_to_terminal, println, timer, vfs::File};
 use sha2::{Digest, Sha256};
-use std::io::Read;
+use std::io::{BufReader, Read, Seek};
 use std::str::FromStr;
 
 pub mod ft_worker_lib;
@@ -63,6 +62,7 @@ wit_bindgen::generate!({
 
 const CHUNK_SIZE: u64 = 262144; // 256KB
 const KILL_SWITCH_MS: u64 = 120000; // 2 minutes
+const VFS_TIMEOUT: u64 = 5;
 
 call_init!(init);
 fn init(our: Address) {
@@ -156,7 +156,7 @@ fn handle_sender(worker: &str, package_id: &PackageId, version_hash: &str) -> an
         package_id.package_name, package_id.publisher_node, version_hash
     );
 
-    let mut file = vfs::open_file(&filename, false, None)?;
+    let file = vfs::open_file(&filename, false, None)?;
     let size = file.metadata()?.len;
     let num_chunks = (size as f64 / CHUNK_SIZE as f64).ceil() as u64;
 
@@ -167,10 +167,9 @@ fn handle_sender(worker: &str, package_id: &PackageId, version_hash: &str) -> an
         }))
         .target(target_worker.clone())
         .send()?;
-    file.seek(SeekFrom::Start(0))?;
 
     for i in 0..num_chunks {
-        send_chunk(&mut file, i, size, &target_worker, package_id, version_hash)?;
+        send_chunk(&file, i, size, &target_worker, package_id, version_hash)?;
     }
 
     Ok(())
@@ -265,13 +264,35 @@ fn handle_receiver(
                                 .body(DownloadRequest::DownloadComplete(req))
                                 .target(parent_process.clone())
                                 .send()?;
+                            return Ok(());
+                        }
+
+                        let zip_path = &file.as_ref().unwrap().path;
+                        if let Err(e) = ft_worker_lib::check_zip(zip_reader(zip_path, s)) {
+                            print_to_terminal(
+                                1,
+                                &format!("ft_worker: {package_id} rejected zip: {e:?}"),
+                            );
+                            let _ = vfs::remove_file(zip_path, None);
+                            Request::new()
+                                .body(DownloadRequest::DownloadComplete(DownloadCompleteRequest {
+                                    package_id: package_id.clone().into(),
+                                    version_hash: version_hash.to_string(),
+                                    err: Some(DownloadError::InvalidArchive(e)),
+                                }))
+                                .target(parent_process.clone())
+                                .send()?;
+                            return Ok(());
                         }
 
                         let manifest_filename =
                             format!("{}{}.json", package_dir.path, version_hash);
 
-                        let contents = file.as_mut().unwrap().read()?;
-                        extract_and_write_manifest(&contents, &manifest_filename)?;
+                        extract_and_write_manifest(
+                            &file.as_ref().unwrap().path,
+                            s,
+                            &manifest_filename,
+                        )?;
 
                         Request::new()
                             .body(DownloadRequest::DownloadComplete(DownloadCompleteRequest {
@@ -294,7 +315,7 @@ fn handle_receiver(
 }
 
 fn send_chunk(
-    file: &mut File,
+    file: &File,
     chunk_index: u64,
     total_size: u64,
     target: &Address,
@@ -304,10 +325,7 @@ fn send_chunk(
     let offset = chunk_index * CHUNK_SIZE;
     let length = CHUNK_SIZE.min(total_size - offset);
 
-    let mut buffer = vec![0; length as usize];
-    // this extra seek might be unnecessary. fix multireads per process in vfs
-    file.seek(SeekFrom::Start(offset))?;
-    file.read_at(&mut buffer)?;
+    let buffer = read_at(&file.path, offset, length)?;
 
     Request::new()
         .body(DownloadRequest::Chunk(ChunkRequest {
@@ -350,9 +368,77 @@ fn handle_chunk(
     Ok(())
 }
 
-fn extract_and_write_manifest(file_contents: &[u8], manifest_path: &str) -> anyhow::Result<()> {
-    let reader = std::io::Cursor::new(file_contents);
-    let mut archive = zip::ZipArchive::new(reader)?;
+/// Read up to `length` bytes of a VFS file, starting at `offset`,
+/// without loading the rest of the file.
+fn read_at(path: &str, offset: u64, length: u64) -> anyhow::Result<Vec<u8>> {
+    let response = Request::to(("our", "vfs", "distro", "sys"))
+        .body(serde_json::to_vec(&serde_json::json!({
+            "path": path,
+            "action": { "ReadAt": { "offset": offset, "length": length } },
+        }))?)
+        .send_and_await_response(VFS_TIMEOUT)??;
+    match serde_json::from_slice::<vfs::VfsResponse>(response.body())? {
+        vfs::VfsResponse::Read => Ok(get_blob().map(|blob| blob.bytes).unwrap_or_default()),
+        vfs::VfsResponse::Err(e) => Err(anyhow::anyhow!("vfs: {e:?}")),
+        _ => Err(anyhow::anyhow!("vfs: unexpected response to ReadAt")),
+    }
+}
+
+/// A `Read + Seek` view of a VFS file that fetches it a chunk at a time,
+/// so that a large zip can be inspected without loading all of it.
+struct VfsReader {
+    path: String,
+    len: u64,
+    position: u64,
+}
+
+impl Read for VfsReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let length = (buf.len() as u64).min(CHUNK_SIZE);
+        let bytes = read_at(&self.path, self.position, length)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        buf[..bytes.len()].copy_from_slice(&bytes);
+        self.position += bytes.len() as u64;
+        Ok(bytes.len())
+    }
+}
+
+impl Seek for VfsReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            std::io::SeekFrom::Start(offset) => offset as i64,
+            std::io::SeekFrom::End(offset) => self.len as i64 + offset,
+            std::io::SeekFrom::Current(offset) => self.position as i64 + offset,
+        };
+        if position < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek before start of file",
+            ));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+/// A buffered reader of a zip in the VFS, of the given length.
+fn zip_reader(zip_path: &str, zip_len: u64) -> BufReader<VfsReader> {
+    BufReader::with_capacity(
+        CHUNK_SIZE as usize,
+        VfsReader {
+            path: zip_path.to_string(),
+            len: zip_len,
+            position: 0,
+        },
+    )
+}
+
+fn extract_and_write_manifest(
+    zip_path: &str,
+    zip_len: u64,
+    manifest_path: &str,
+) -> anyhow::Result<()> {
+    let mut archive = zip::ZipArchive::new(zip_reader(zip_path, zip_len))?;
 
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
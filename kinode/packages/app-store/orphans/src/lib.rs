@@ -0,0 +1,55 @@
+//! orphans:app-store:sys
+//! terminal script for finding the VFS drives left behind by packages that were
+//! uninstalled or failed mid-install.
+//!
+//! Usage:
+//!     orphans:app-store:sys [--delete]
+//!
+//! Arguments:
+//!     --delete    Remove the orphaned drives rather than only listing them
+//!
+use crate::kinode::process::main::{LocalRequest, LocalResponse, OrphanedDrivesResponse};
+use kinode_process_lib::{script, Address, Message, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    generate_unused_types: true,
+    world: "app-store-sys-v1",
+    additional_derives: [PartialEq, serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
+});
+
+script!(init);
+fn init(our: Address, args: String) -> String {
+    let delete = args.split_whitespace().any(|arg| arg == "--delete");
+
+    let Ok(Ok(Message::Response { body, .. })) =
+        Request::to((our.node(), ("main", "app-store", "sys")))
+            .body(LocalRequest::OrphanedDrives(delete))
+            .send_and_await_response(30)
+    else {
+        return "orphans: failed to get a response from app-store..!".to_string();
+    };
+
+    let Ok(LocalResponse::OrphanedDrivesResponse(response)) = body.try_into() else {
+        return "orphans: failed to parse response from app-store..!".to_string();
+    };
+    let mut drives = match response {
+        OrphanedDrivesResponse::Success(drives) => drives,
+        OrphanedDrivesResponse::Failure(e) => return format!("orphans: {e}"),
+    };
+    if drives.is_empty() {
+        return "no orphaned drives".to_string();
+    }
+    drives.sort();
+
+    let drives = drives
+        .iter()
+        .map(|drive| format!("    {drive}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if delete {
+        format!("removed orphaned drives:\n{drives}")
+    } else {
+        format!("orphaned drives:\n{drives}\nrun `orphans:app-store:sys --delete` to remove them")
+    }
+}
@@ -102,6 +102,17 @@ const ManifestDisplay: React.FC<ManifestDisplayProps> = ({ manifestResponse }) =
     }
     return (
         <div className="manifest-display">
+            {manifestResponse.whats_new && manifestResponse.whats_new.length > 0 && (
+                <div className="whats-new">
+                    <h4>What's New</h4>
+                    {manifestResponse.whats_new.map((release) => (
+                        <div key={release.version} className="release-notes">
+                            <h5>{release.version}</h5>
+                            <p>{release.notes}</p>
+                        </div>
+                    ))}
+                </div>
+            )}
             {parsedManifests.map((manifest, index) => (
                 <ProcessManifest key={index} manifest={manifest} />
             ))}
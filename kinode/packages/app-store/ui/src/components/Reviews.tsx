@@ -0,0 +1,122 @@
+import React, { useCallback, useEffect, useState } from 'react';
+import { FaSpinner, FaStar, FaRegStar } from 'react-icons/fa';
+import useAppsStore from '../store';
+import { RatingSummary, Review } from '../types/Apps';
+
+const MAX_REVIEW_LENGTH = 2000;
+
+export const RatingStars: React.FC<{ rating: number }> = ({ rating }) => (
+    <span className="rating-stars" title={`${rating.toFixed(1)} out of 5`}>
+        {[1, 2, 3, 4, 5].map(star =>
+            star <= Math.round(rating) ? <FaStar key={star} /> : <FaRegStar key={star} />
+        )}
+    </span>
+);
+
+export const RatingBadge: React.FC<{ rating?: RatingSummary }> = ({ rating }) => {
+    if (!rating) return <span className="rating-badge">No reviews</span>;
+    return (
+        <span className="rating-badge">
+            <RatingStars rating={rating.average} /> {rating.average.toFixed(1)} ({rating.count})
+        </span>
+    );
+};
+
+interface ReviewsProps {
+    id: string;
+    rating?: RatingSummary;
+}
+
+const Reviews: React.FC<ReviewsProps> = ({ id, rating }) => {
+    const { fetchReviews, publishReview } = useAppsStore();
+    const [reviews, setReviews] = useState<Review[]>([]);
+    const [newRating, setNewRating] = useState(0);
+    const [text, setText] = useState('');
+    const [isPublishing, setIsPublishing] = useState(false);
+    const [error, setError] = useState<string | null>(null);
+
+    const loadReviews = useCallback(async () => {
+        const reviews = await fetchReviews(id);
+        setReviews(reviews);
+        const ours = reviews.find(review => review.reviewer === window.our?.node);
+        if (ours) {
+            setNewRating(ours.rating);
+            setText(ours.text || '');
+        }
+    }, [id, fetchReviews]);
+
+    useEffect(() => {
+        loadReviews();
+    }, [loadReviews]);
+
+    const handlePublish = async (e: React.FormEvent) => {
+        e.preventDefault();
+        if (newRating < 1) return;
+        setIsPublishing(true);
+        setError(null);
+        try {
+            await publishReview(id, newRating, text);
+            await loadReviews();
+        } catch (error) {
+            setError(error instanceof Error ? error.message : String(error));
+        } finally {
+            setIsPublishing(false);
+        }
+    };
+
+    return (
+        <div className="app-reviews">
+            <h3>Reviews <RatingBadge rating={rating} /></h3>
+            <p className="reviews-note">
+                Reviews are signed by the nodes that wrote them, and fetched from the publisher and mirrors of the app.
+            </p>
+
+            <form className="review-form" onSubmit={handlePublish}>
+                <div className="rating-input">
+                    {[1, 2, 3, 4, 5].map(star => (
+                        <button
+                            key={star}
+                            type="button"
+                            className="star-button"
+                            onClick={() => setNewRating(star)}
+                            aria-label={`${star} star${star > 1 ? 's' : ''}`}
+                        >
+                            {star <= newRating ? <FaStar /> : <FaRegStar />}
+                        </button>
+                    ))}
+                </div>
+                <textarea
+                    placeholder="What do you think of this app? (optional)"
+                    value={text}
+                    maxLength={MAX_REVIEW_LENGTH}
+                    onChange={e => setText(e.target.value)}
+                />
+                <button type="submit" className="primary" disabled={newRating < 1 || isPublishing}>
+                    {isPublishing ? <FaSpinner className="fa-spin" /> : null} Publish Review
+                </button>
+                {error && <div className="error-message">{error}</div>}
+            </form>
+
+            {reviews.length === 0 ? (
+                <p>No reviews yet.</p>
+            ) : (
+                <ul className="review-list">
+                    {reviews.map(review => (
+                        <li key={review.reviewer} className="review">
+                            <div className="review-header">
+                                <RatingStars rating={review.rating} />
+                                <span className="review-author">{review.reviewer}</span>
+                                <span className="review-date">
+                                    {new Date(review.timestamp * 1000).toLocaleDateString()}
+                                </span>
+                            </div>
+                            {review.text && <p className="review-text">{review.text}</p>}
+                        </li>
+                    ))}
+                </ul>
+            )}
+        </div>
+    );
+};
+
+export default Reviews;
@@ -3,4 +3,5 @@ export { default as MirrorSelector } from './MirrorSelector';
 export { default as PackageSelector } from './PackageSelector';
 export { default as ManifestDisplay } from './ManifestDisplay';
 export { default as NotificationBay } from './NotificationBay';
-export { default as ResetButton } from './ResetButton';
\ No newline at end of file
+export { default as ResetButton } from './ResetButton';
+export { default as Reviews, RatingBadge } from './Reviews';
\ No newline at end of file
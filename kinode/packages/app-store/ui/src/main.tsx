@@ -5,8 +5,10 @@ import '@rainbow-me/rainbowkit/styles.css';
 
 import {
   getDefaultConfig,
+  getDefaultWallets,
   RainbowKitProvider,
 } from '@rainbow-me/rainbowkit';
+import { ledgerWallet } from '@rainbow-me/rainbowkit/wallets';
 import { WagmiProvider, http } from 'wagmi';
 import {
   optimism,
@@ -19,9 +21,20 @@ import {
 
 import './index.css'
 
+// publish with a hardware wallet, so the publisher key never touches a node.
+// Trezor has no connector of its own, but signs through MetaMask or Rabby.
+const { wallets } = getDefaultWallets();
+
 const config = getDefaultConfig({
   appName: 'Kinode App Store',
   projectId: '1307513d16d359871023f1f78ac03361',
+  wallets: [
+    ...wallets,
+    {
+      groupName: 'Hardware',
+      wallets: [ledgerWallet],
+    },
+  ],
   chains: [optimism],
   ssr: false,
   transports: {
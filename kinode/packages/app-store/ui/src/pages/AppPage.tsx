@@ -4,6 +4,7 @@ import { FaDownload, FaCheck, FaTimes, FaPlay, FaSpinner, FaTrash, FaSync } from
 import useAppsStore from "../store";
 import { AppListing, PackageState } from "../types/Apps";
 import { compareVersions } from "../utils/compareVersions";
+import { Reviews } from "../components";
 
 export default function AppPage() {
   const { id } = useParams();
@@ -161,6 +162,10 @@ export default function AppPage() {
             </li>
           )}
           <li><span>Publisher:</span> <span>{app.package_id.publisher_node}</span></li>
+          <li>
+            <span>Rating:</span>
+            <span>{app.rating ? `${app.rating.average.toFixed(1)} / 5 (${app.rating.count})` : "No reviews"}</span>
+          </li>
           {app.metadata?.properties?.license ? <li><span>License:</span> <span>app.metadata?.properties?.license</span></li> : <></>}
           <li>
             <span>Auto Update:</span>
@@ -205,6 +210,8 @@ export default function AppPage() {
           </div>
         </div>
       )}
+
+      <Reviews id={`${app.package_id.package_name}:${app.package_id.publisher_node}`} rating={app.rating} />
     </section>
   );
 }
\ No newline at end of file
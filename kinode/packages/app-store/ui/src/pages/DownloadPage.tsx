@@ -3,7 +3,7 @@ import { useParams } from "react-router-dom";
 import { FaDownload, FaSpinner, FaChevronDown, FaChevronUp, FaRocket, FaTrash, FaPlay } from "react-icons/fa";
 import useAppsStore from "../store";
 import { MirrorSelector, ManifestDisplay } from '../components';
-import { ManifestResponse } from "../types/Apps";
+import { License, ManifestResponse } from "../types/Apps";
 
 export default function DownloadPage() {
     const { id } = useParams<{ id: string }>();
@@ -15,6 +15,8 @@ export default function DownloadPage() {
         fetchData,
         downloadApp,
         installApp,
+        fetchLicense,
+        acceptLicense,
         removeDownload,
         clearAllActiveDownloads,
         fetchHomepageApps,
@@ -29,6 +31,9 @@ export default function DownloadPage() {
     const [showCapApproval, setShowCapApproval] = useState(false);
     const [manifestResponse, setManifestResponse] = useState<ManifestResponse | null>(null);
     const [isInstalling, setIsInstalling] = useState(false);
+    const [license, setLicense] = useState<License | null>(null);
+    const [licenseAccepted, setLicenseAccepted] = useState(false);
+    const [licenseError, setLicenseError] = useState<string | null>(null);
     const [isCheckingLaunch, setIsCheckingLaunch] = useState(false);
     const [launchPath, setLaunchPath] = useState<string | null>(null);
 
@@ -152,12 +157,32 @@ export default function DownloadPage() {
         const download = appDownloads.find(d => d.File && d.File.name === `${hash}.zip`);
         if (download?.File?.manifest) {
             try {
+                // the notes of the versions since the installed one, as the backend gives them
+                const versions = sortedVersions.map(v => v.version);
+                const installedVersion = sortedVersions.find(v => v.hash === installedApp?.our_version_hash)?.version;
+                const newVersions = versions.slice(
+                    versions.indexOf(version),
+                    installedVersion ? versions.indexOf(installedVersion) : undefined,
+                );
                 const manifest_response: ManifestResponse = {
                     package_id: app.package_id,
                     version_hash: hash,
-                    manifest: download.File.manifest
+                    manifest: download.File.manifest,
+                    whats_new: (app.changelog || []).filter(release => newVersions.includes(release.version)),
                 };
                 setManifestResponse(manifest_response);
+                setLicense(null);
+                setLicenseAccepted(false);
+                setLicenseError(null);
+                // apps whose publisher asks for a license to be accepted only install once it is
+                if (app.license_acceptance) {
+                    fetchLicense(id)
+                        .then(license => {
+                            setLicense(license);
+                            setLicenseAccepted(license.accepted?.hash === license.hash);
+                        })
+                        .catch(error => setLicenseError(error instanceof Error ? error.message : String(error)));
+                }
                 setShowCapApproval(true);
             } catch (error) {
                 console.error('Failed to parse manifest:', error);
@@ -165,7 +190,7 @@ export default function DownloadPage() {
         } else {
             console.error('Manifest not found for the selected version');
         }
-    }, [id, app, appDownloads]);
+    }, [id, app, appDownloads, sortedVersions, installedApp, fetchLicense]);
 
     const confirmInstall = useCallback(() => {
         if (!id || !selectedVersion) return;
@@ -173,13 +198,24 @@ export default function DownloadPage() {
         if (versionData) {
             setIsInstalling(true);
             setLaunchPath(null);
-            installApp(id, versionData.hash).then(() => {
-                setShowCapApproval(false);
-                setManifestResponse(null);
-                fetchData(id);
-            });
+            const accept = license && license.accepted?.hash !== license.hash
+                ? acceptLicense(id, license.hash)
+                : Promise.resolve();
+            accept
+                .then(() => installApp(id, versionData.hash))
+                .then(() => {
+                    setShowCapApproval(false);
+                    setManifestResponse(null);
+                    fetchData(id);
+                })
+                .catch(error => {
+                    setIsInstalling(false);
+                    setLicenseError(error instanceof Error ? error.message : String(error));
+                });
         }
-    }, [id, selectedVersion, sortedVersions, installApp, fetchData]);
+    }, [id, selectedVersion, sortedVersions, license, acceptLicense, installApp, fetchData]);
+
+    const needsLicense = !!app?.license_acceptance && !(license && licenseAccepted);
 
     const handleLaunch = useCallback(() => {
         if (launchPath) {
@@ -347,9 +383,28 @@ export default function DownloadPage() {
                     <div className="cap-approval-content">
                         <h3>Approve Capabilities</h3>
                         <ManifestDisplay manifestResponse={manifestResponse} />
+                        {app.license_acceptance && (
+                            <div className="license-acceptance">
+                                <h4>License</h4>
+                                {license ? (
+                                    <>
+                                        <pre className="license-text">{license.text}</pre>
+                                        <label>
+                                            <input
+                                                type="checkbox"
+                                                checked={licenseAccepted}
+                                                onChange={e => setLicenseAccepted(e.target.checked)}
+                                            />
+                                            I accept the license of this app
+                                        </label>
+                                    </>
+                                ) : !licenseError && <p><FaSpinner className="fa-spin" /> Fetching license...</p>}
+                                {licenseError && <div className="error-message">{licenseError}</div>}
+                            </div>
+                        )}
                         <div className="approval-buttons">
                             <button onClick={() => setShowCapApproval(false)}>Cancel</button>
-                            <button onClick={confirmInstall}>
+                            <button onClick={confirmInstall} disabled={needsLicense}>
                                 Approve and Install
                             </button>
                         </div>
@@ -4,6 +4,7 @@ import { useNavigate } from "react-router-dom";
 import useAppsStore from "../store";
 import { ResetButton } from "../components";
 import { DownloadItem, PackageManifestEntry, PackageState, Updates, DownloadError, UpdateInfo } from "../types/Apps";
+import { formatInvalidArchive } from "../utils/downloadErrors";
 
 // Core packages that cannot be uninstalled
 const CORE_PACKAGES = [
@@ -88,6 +89,8 @@ export default function MyAppsPage() {
             return error;
         } else if ('HashMismatch' in error) {
             return `Hash mismatch (expected ${error.HashMismatch.desired.slice(0, 8)}, got ${error.HashMismatch.actual.slice(0, 8)})`;
+        } else if ('InvalidArchive' in error) {
+            return `Rejected zip: ${formatInvalidArchive(error.InvalidArchive)}`;
         } else if ('Timeout' in error) {
             return 'Connection timed out';
         }
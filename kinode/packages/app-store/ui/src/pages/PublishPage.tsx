@@ -8,6 +8,7 @@ import { kinohash } from '../utils/kinohash';
 import useAppsStore from "../store";
 import { PackageSelector } from "../components";
 import { Tooltip } from '../components/Tooltip';
+import { VersionAdoption } from "../types/Apps";
 
 const NAME_INVALID = "Package name must contain only valid characters (a-z, 0-9, -, and .)";
 
@@ -31,6 +32,7 @@ export default function PublishPage() {
 
   const [nameValidity, setNameValidity] = useState<string | null>(null);
   const [metadataError, setMetadataError] = useState<string | null>(null);
+  const [aggregator, setAggregator] = useState<string>("");
 
   useEffect(() => {
     fetchOurApps();
@@ -323,6 +325,16 @@ export default function PublishPage() {
 
       <div className="my-packages">
         <h2>Packages You Own</h2>
+        <p className="adoption-note">
+          Installs and updates are counted from nodes that turned on install telemetry in their settings.
+        </p>
+        <input
+          type="text"
+          className="adoption-aggregator"
+          placeholder="Counts from an aggregator (optional), e.g. counter.os"
+          value={aggregator}
+          onChange={(e) => setAggregator(e.target.value.trim())}
+        />
         {Object.keys(ourApps).length > 0 ? (
           <ul className="package-list">
             {Object.values(ourApps).map((app) => (
@@ -334,6 +346,11 @@ export default function PublishPage() {
                   <span>{app.metadata?.name || app.package_id.package_name}</span>
                 </Link>
 
+                <AdoptionCount
+                  id={`${app.package_id.package_name}:${app.package_id.publisher_node}`}
+                  aggregator={aggregator}
+                />
+
                 <button onClick={() => unpublishPackage(app.package_id.package_name, app.package_id.publisher_node)} className="danger">
                   Unpublish
                 </button>
@@ -346,4 +363,24 @@ export default function PublishPage() {
       </div>
     </div>
   );
-}
\ No newline at end of file
+}
+
+const AdoptionCount: React.FC<{ id: string, aggregator: string }> = ({ id, aggregator }) => {
+  const { fetchAdoption } = useAppsStore();
+  const [adoption, setAdoption] = useState<VersionAdoption[]>([]);
+
+  useEffect(() => {
+    fetchAdoption(id, aggregator || undefined).then(setAdoption);
+  }, [id, aggregator, fetchAdoption]);
+
+  const installs = adoption.reduce((sum, version) => sum + version.installs, 0);
+  const updates = adoption.reduce((sum, version) => sum + version.updates, 0);
+  return (
+    <span
+      className="adoption-count"
+      title={adoption.map(v => `${v.version_hash.slice(0, 10)}: ${v.installs} installs, ${v.updates} updates`).join('\n')}
+    >
+      {installs} installs, {updates} updates
+    </span>
+  );
+};
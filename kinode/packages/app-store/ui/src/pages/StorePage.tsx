@@ -3,6 +3,7 @@ import useAppsStore from "../store";
 import { AppListing } from "../types/Apps";
 import { Link } from "react-router-dom";
 import { FaSearch } from "react-icons/fa";
+import { RatingBadge } from "../components";
 
 export default function StorePage() {
   const { listings, fetchListings, fetchUpdates } = useAppsStore();
@@ -50,6 +51,7 @@ export default function StorePage() {
                 <th>Name</th>
                 <th>Description</th>
                 <th>Publisher</th>
+                <th>Rating</th>
               </tr>
             </thead>
             <tbody>
@@ -87,6 +89,7 @@ const AppRow: React.FC<{ app: AppListing }> = ({ app }) => {
       </td>
       <td>{app.metadata?.description || "No description available"}</td>
       <td>{app.package_id.publisher_node}</td>
+      <td><RatingBadge rating={app.rating} /></td>
     </tr>
   );
 };
\ No newline at end of file
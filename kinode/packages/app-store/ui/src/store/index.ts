@@ -1,9 +1,10 @@
 import { create } from 'zustand'
 import { persist } from 'zustand/middleware'
-import { PackageState, AppListing, MirrorCheckFile, DownloadItem, HomepageApp, ManifestResponse, Notification, UpdateInfo } from '../types/Apps'
+import { PackageState, AppListing, MirrorCheckFile, DownloadItem, HomepageApp, License, ManifestResponse, Notification, Review, UpdateInfo, VersionAdoption } from '../types/Apps'
 import { HTTP_STATUS } from '../constants/http'
 import KinodeClientApi from "@kinode/client-api"
 import { WEBSOCKET_URL } from '../utils/ws'
+import { formatInvalidArchive } from '../utils/downloadErrors'
 
 const BASE_URL = '/main:app-store:sys'
 
@@ -46,6 +47,12 @@ interface AppsStore {
   stopMirroring: (id: string) => Promise<void>
   setAutoUpdate: (id: string, version_hash: string, autoUpdate: boolean) => Promise<void>
 
+  fetchReviews: (id: string) => Promise<Review[]>
+  publishReview: (id: string, rating: number, text: string) => Promise<void>
+  fetchAdoption: (id: string, aggregator?: string) => Promise<VersionAdoption[]>
+  fetchLicense: (id: string) => Promise<License>
+  acceptLicense: (id: string, hash: string) => Promise<void>
+
   setActiveDownload: (appId: string, downloaded: number, total: number) => void
   clearActiveDownload: (appId: string) => void
   clearAllActiveDownloads: () => void;
@@ -356,6 +363,60 @@ const useAppsStore = create<AppsStore>()((set, get) => ({
     }
   },
 
+  fetchReviews: async (id: string) => {
+    try {
+      const res = await fetch(`${BASE_URL}/apps/${id}/reviews`);
+      if (res.status === HTTP_STATUS.OK) {
+        return await res.json() as Review[];
+      }
+    } catch (error) {
+      console.error("Error fetching reviews:", error);
+    }
+    return [];
+  },
+
+  publishReview: async (id: string, rating: number, text: string) => {
+    const res = await fetch(`${BASE_URL}/apps/${id}/reviews`, {
+      method: 'POST',
+      body: JSON.stringify({ rating, text: text.trim() || null })
+    });
+    if (res.status !== HTTP_STATUS.OK) {
+      throw new Error(`Failed to publish review: ${await res.text()}`);
+    }
+    await get().fetchListing(id);
+  },
+
+  fetchAdoption: async (id: string, aggregator?: string) => {
+    try {
+      const query = aggregator ? `?aggregator=${encodeURIComponent(aggregator)}` : '';
+      const res = await fetch(`${BASE_URL}/apps/${id}/adoption${query}`);
+      if (res.status === HTTP_STATUS.OK) {
+        return await res.json() as VersionAdoption[];
+      }
+    } catch (error) {
+      console.error("Error fetching adoption:", error);
+    }
+    return [];
+  },
+
+  fetchLicense: async (id: string) => {
+    const res = await fetch(`${BASE_URL}/apps/${id}/license`);
+    if (res.status !== HTTP_STATUS.OK) {
+      throw new Error(`Failed to fetch license: ${await res.text()}`);
+    }
+    return await res.json() as License;
+  },
+
+  acceptLicense: async (id: string, hash: string) => {
+    const res = await fetch(`${BASE_URL}/apps/${id}/license`, {
+      method: 'POST',
+      body: JSON.stringify({ hash })
+    });
+    if (res.status !== HTTP_STATUS.OK) {
+      throw new Error(`Failed to accept license: ${await res.text()}`);
+    }
+  },
+
   setActiveDownload: (appId, downloaded, total) => {
     set((state) => ({
       activeDownloads: {
@@ -478,6 +539,9 @@ const useAppsStore = create<AppsStore>()((set, get) => ({
                   const { actual, desired } = error.HashMismatch;
                   return `Hash mismatch: expected ${desired.slice(0, 8)}..., got ${actual.slice(0, 8)}...`;
                 }
+                if ('InvalidArchive' in error) {
+                  return `Rejected zip: ${formatInvalidArchive(error.InvalidArchive)}`;
+                }
                 // Try to serialize the error object if it's not a HashMismatch
                 try {
                   return JSON.stringify(error);
@@ -13,6 +13,47 @@ export interface AppListing {
     metadata_hash: string
     metadata?: OnchainPackageMetadata
     auto_update: boolean
+    rating?: RatingSummary
+    changelog: ReleaseNotes[] // newest first, only given for a single app
+    license_acceptance?: LicenseAcceptance // only given for a single app
+}
+
+export interface LicenseAcceptance {
+    uri: string;
+    hash: string;
+}
+
+export interface License extends LicenseAcceptance {
+    text: string;
+    accepted?: {
+        hash: string;
+        timestamp: number; // seconds
+    };
+}
+
+export interface ReleaseNotes {
+    version: string;
+    notes: string;
+}
+
+export interface RatingSummary {
+    count: number;
+    average: number;
+}
+
+export interface Review {
+    package_id: PackageId;
+    reviewer: string;
+    rating: number; // 1 to 5
+    text?: string;
+    timestamp: number; // seconds
+    signature: number[];
+}
+
+export interface VersionAdoption {
+    version_hash: string;
+    installs: number;
+    updates: number;
 }
 
 export type DownloadItem = {
@@ -79,6 +120,7 @@ export interface ManifestResponse {
     package_id: PackageId;
     version_hash: string;
     manifest: string;
+    whats_new?: ReleaseNotes[]; // newest first, since the installed version
 }
 
 export interface HomepageApp {
@@ -110,7 +152,15 @@ export type DownloadError =
     | "VfsError"
     | "Timeout"
     | "InvalidManifest"
-    | "Offline";
+    | "Offline"
+    | "MissingCapability"
+    | { InvalidArchive: InvalidArchive };
+
+export type InvalidArchive =
+    | "Unreadable"
+    | { UnsafePath: string }
+    | "TooLarge"
+    | "MissingManifest";
 
 export interface UpdateInfo {
     errors: [string, DownloadError][]; // [url/node, error]
@@ -0,0 +1,16 @@
+import { InvalidArchive } from '../types/Apps';
+
+// Why a downloaded zip was rejected, for display
+export const formatInvalidArchive = (reason: InvalidArchive) => {
+    if (typeof reason === 'object') {
+        return `unsafe path ${reason.UnsafePath}`;
+    }
+    switch (reason) {
+        case 'Unreadable':
+            return 'not a readable zip';
+        case 'TooLarge':
+            return 'too large once unzipped';
+        case 'MissingManifest':
+            return 'no manifest.json';
+    }
+};
@@ -1,7 +1,13 @@
+//! contacts:contacts:sys
+//!
+//! The frontend of the address book of the node, which is kept by the
+//! contacts:distro:sys runtime module so that every app can share it. Requests
+//! made here with the capabilities of this process are passed on to the module,
+//! as are those of our UI, once the names they add are checked on kimap.
 use crate::kinode::process::contacts;
 use kinode_process_lib::{
-    await_message, call_init, eth, get_blob, get_typed_state, homepage, http, kimap, set_state,
-    Address, Capability, LazyLoadBlob, Message, NodeId, Response,
+    await_message, call_init, clear_state, eth, get_blob, get_typed_state, homepage, http, kimap,
+    println, Address, Capability, LazyLoadBlob, Message, NodeId, Request, Response,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -22,126 +28,28 @@ const CHAIN_ID: u64 = kimap::KIMAP_CHAIN_ID;
 const CHAIN_ID: u64 = 31337; // local
 
 const CHAIN_TIMEOUT: u64 = 60; // 60s
+const CONTACTS_TIMEOUT: u64 = 5;
 
 #[cfg(not(feature = "simulation-mode"))]
 const KIMAP_ADDRESS: &'static str = kimap::KIMAP_ADDRESS; // optimism
 #[cfg(feature = "simulation-mode")]
 const KIMAP_ADDRESS: &str = "0xEce71a05B36CA55B895427cD9a440eEF7Cf3669D";
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Contact(HashMap<String, serde_json::Value>);
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Contacts(HashMap<NodeId, Contact>);
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ContactsStateV1 {
-    our: Address,
-    contacts: Contacts,
-}
-
+/// The state this process kept its contacts in before contacts:distro:sys
+/// did, read once to move them there.
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "version")]
 enum VersionedState {
-    /// State fully stored in memory, persisted using serde_json.
-    /// Future state version will use SQLite.
-    V1(ContactsStateV1),
-}
-
-impl VersionedState {
-    fn new(our: Address) -> Self {
-        get_typed_state(|bytes| serde_json::from_slice(bytes)).unwrap_or(Self::V1(
-            ContactsStateV1 {
-                our,
-                contacts: Contacts(HashMap::new()),
-            },
-        ))
-    }
-
-    fn save(&self) {
-        set_state(&serde_json::to_vec(&self).expect("Failed to serialize contacts state!"));
-    }
-
-    fn contacts(&self) -> &Contacts {
-        match self {
-            VersionedState::V1(state) => &state.contacts,
-        }
-    }
-
-    fn get_contact(&self, node: NodeId) -> Option<&Contact> {
-        match self {
-            VersionedState::V1(state) => state.contacts.0.get(&node),
-        }
-    }
-
-    fn add_contact(&mut self, node: NodeId) {
-        match self {
-            VersionedState::V1(state) => {
-                state.contacts.0.insert(node, Contact(HashMap::new()));
-            }
-        }
-        self.save();
-    }
-
-    fn remove_contact(&mut self, node: NodeId) {
-        match self {
-            VersionedState::V1(state) => {
-                state.contacts.0.remove(&node);
-            }
-        }
-        self.save();
-    }
-
-    fn add_field(&mut self, node: NodeId, field: String, value: serde_json::Value) {
-        match self {
-            VersionedState::V1(state) => {
-                state
-                    .contacts
-                    .0
-                    .entry(node)
-                    .or_insert_with(|| Contact(HashMap::new()))
-                    .0
-                    .insert(field, value);
-            }
-        }
-        self.save();
-    }
-
-    fn remove_field(&mut self, node: NodeId, field: String) {
-        match self {
-            VersionedState::V1(state) => {
-                if let Some(contact) = state.contacts.0.get_mut(&node) {
-                    contact.0.remove(&field);
-                }
-            }
-        }
-        self.save();
-    }
-
-    fn ws_update(&self, http_server: &mut http::server::HttpServer) {
-        http_server.ws_push_all_channels(
-            "/",
-            http::server::WsMessageType::Text,
-            LazyLoadBlob::new(
-                Some("application/json"),
-                serde_json::to_vec(self.contacts()).unwrap(),
-            ),
-        );
-    }
-
-    fn our(&self) -> &Address {
-        match self {
-            VersionedState::V1(state) => &state.our,
-        }
-    }
+    V1 {
+        contacts: HashMap<NodeId, HashMap<String, serde_json::Value>>,
+    },
 }
 
 call_init!(initialize);
 fn initialize(our: Address) {
     homepage::add_to_homepage("Contacts", Some(ICON), Some("/"), None);
 
-    let mut state: VersionedState = get_typed_state(|bytes| serde_json::from_slice(bytes))
-        .unwrap_or_else(|| VersionedState::new(our));
+    migrate_state();
 
     let kimap = kimap::Kimap::new(
         eth::Provider::new(CHAIN_ID, CHAIN_TIMEOUT),
@@ -153,7 +61,7 @@ fn initialize(our: Address) {
     // serve the frontend on a secure subdomain
     http_server
         .serve_ui(
-            state.our(),
+            &our,
             "ui",
             vec!["/"],
             http::server::HttpBindingConfig::default().secure_subdomain(true),
@@ -162,14 +70,36 @@ fn initialize(our: Address) {
     http_server.secure_bind_http_path("/ask").unwrap();
     http_server.secure_bind_ws_path("/").unwrap();
 
-    main_loop(&mut state, &kimap, &mut http_server);
+    main_loop(&our, &kimap, &mut http_server);
 }
 
-fn main_loop(
-    state: &mut VersionedState,
-    kimap: &kimap::Kimap,
-    http_server: &mut http::server::HttpServer,
-) {
+/// Move the contacts kept in our state to contacts:distro:sys, then forget
+/// them. If it fails, it is tried again on the next boot.
+fn migrate_state() {
+    let Some(VersionedState::V1 { contacts }) =
+        get_typed_state(|bytes| serde_json::from_slice(bytes))
+    else {
+        return;
+    };
+    for (node, contact) in contacts {
+        let mut result = contacts_action(serde_json::json!({ "Add": node }));
+        for (field, value) in contact {
+            if result.is_err() {
+                break;
+            }
+            result = contacts_action(serde_json::json!({
+                "SetField": { "node": node, "field": field, "value": value }
+            }));
+        }
+        if let Err(e) = result {
+            println!("failed to move contact {node} to contacts:distro:sys: {e}");
+            return;
+        }
+    }
+    clear_state();
+}
+
+fn main_loop(our: &Address, kimap: &kimap::Kimap, http_server: &mut http::server::HttpServer) {
     loop {
         match await_message() {
             Err(_send_error) => {
@@ -184,10 +114,10 @@ fn main_loop(
             }) => {
                 // ignore messages from other nodes -- technically superfluous check
                 // since manifest does not acquire networking capability
-                if source.node() != state.our().node {
+                if source.node() != our.node {
                     continue;
                 }
-                handle_request(&source, &body, capabilities, state, kimap, http_server);
+                handle_request(our, &source, &body, capabilities, kimap, http_server);
             }
             _ => continue, // ignore responses
         }
@@ -195,10 +125,10 @@ fn main_loop(
 }
 
 fn handle_request(
+    our: &Address,
     source: &Address,
     body: &[u8],
     capabilities: Vec<Capability>,
-    state: &mut VersionedState,
     kimap: &kimap::Kimap,
     http_server: &mut http::server::HttpServer,
 ) {
@@ -209,41 +139,61 @@ fn handle_request(
 
         http_server.handle_request(
             server_request,
-            |req| handle_http_request(state, kimap, &req),
+            |req| handle_http_request(our, kimap, &req),
             |_channel_id, _message_type, _blob| {
                 // we don't expect websocket messages
             },
         );
     } else {
         // if request is not from frontend, check that it has the required capabilities
-        let (response, blob) = handle_contacts_request(state, kimap, body, Some(capabilities));
+        let (response, blob) = handle_contacts_request(our, kimap, body, Some(capabilities));
         let mut response = Response::new().body(response);
         if let Some(blob) = blob {
             response = response.blob(blob);
         }
         response.send().unwrap();
     }
-    state.ws_update(http_server);
+    ws_update(http_server);
+}
+
+fn ws_update(http_server: &mut http::server::HttpServer) {
+    let Ok(contacts) = all_contacts() else {
+        return;
+    };
+    http_server.ws_push_all_channels(
+        "/",
+        http::server::WsMessageType::Text,
+        LazyLoadBlob::new(
+            Some("application/json"),
+            serde_json::to_vec(&contacts).unwrap(),
+        ),
+    );
 }
 
 /// Handle HTTP requests from our own frontend.
 fn handle_http_request(
-    state: &mut VersionedState,
+    our: &Address,
     kimap: &kimap::Kimap,
     http_request: &http::server::IncomingHttpRequest,
 ) -> (http::server::HttpResponse, Option<LazyLoadBlob>) {
     match http_request.method().unwrap().as_str() {
-        "GET" => (
-            http::server::HttpResponse::new(http::StatusCode::OK)
-                .header("Content-Type", "application/json"),
-            Some(LazyLoadBlob::new(
-                Some("application/json"),
-                serde_json::to_vec(state.contacts()).unwrap(),
-            )),
-        ),
+        "GET" => match all_contacts() {
+            Ok(contacts) => (
+                http::server::HttpResponse::new(http::StatusCode::OK)
+                    .header("Content-Type", "application/json"),
+                Some(LazyLoadBlob::new(
+                    Some("application/json"),
+                    serde_json::to_vec(&contacts).unwrap(),
+                )),
+            ),
+            Err(_) => (
+                http::server::HttpResponse::new(http::StatusCode::SERVICE_UNAVAILABLE),
+                None,
+            ),
+        },
         "POST" => {
             let blob = get_blob().unwrap();
-            let (response, blob) = handle_contacts_request(state, kimap, blob.bytes(), None);
+            let (response, blob) = handle_contacts_request(our, kimap, blob.bytes(), None);
             if let contacts::Response::Err(e) = response {
                 return (
                     http::server::HttpResponse::new(http::StatusCode::BAD_REQUEST)
@@ -275,7 +225,7 @@ fn handle_http_request(
 }
 
 fn handle_contacts_request(
-    state: &mut VersionedState,
+    our: &Address,
     kimap: &kimap::Kimap,
     request_bytes: &[u8],
     capabilities: Option<Vec<Capability>>,
@@ -290,7 +240,7 @@ fn handle_contacts_request(
     // each request requires one of read-name-only, read, add, or remove
     if let Some(capabilities) = capabilities {
         let required_capability = Capability::new(
-            state.our(),
+            our,
             serde_json::to_string(&match request {
                 contacts::Request::GetNames => contacts::Capability::ReadNameOnly,
                 contacts::Request::GetAllContacts | contacts::Request::GetContact(_) => {
@@ -313,38 +263,42 @@ fn handle_contacts_request(
         }
     }
 
-    match request {
-        contacts::Request::GetNames => (
-            contacts::Response::GetNames(
-                state
-                    .contacts()
-                    .0
-                    .keys()
-                    .map(|node| node.to_string())
-                    .collect(),
-            ),
-            None,
-        ),
-        contacts::Request::GetAllContacts => (
-            contacts::Response::GetAllContacts,
-            Some(LazyLoadBlob::new(
-                Some("application/json"),
-                serde_json::to_vec(state.contacts()).unwrap(),
-            )),
-        ),
-        contacts::Request::GetContact(node) => (
-            contacts::Response::GetContact,
-            Some(LazyLoadBlob::new(
-                Some("application/json"),
-                serde_json::to_vec(&state.get_contact(node)).unwrap(),
-            )),
-        ),
+    let result = match request {
+        contacts::Request::GetNames => {
+            contacts_action(serde_json::json!("GetNames")).map(|response| {
+                (
+                    contacts::Response::GetNames(
+                        serde_json::from_value(response["Names"].clone()).unwrap_or_default(),
+                    ),
+                    None,
+                )
+            })
+        }
+        contacts::Request::GetAllContacts => all_contacts().map(|contacts| {
+            (
+                contacts::Response::GetAllContacts,
+                Some(LazyLoadBlob::new(
+                    Some("application/json"),
+                    serde_json::to_vec(&contacts).unwrap(),
+                )),
+            )
+        }),
+        contacts::Request::GetContact(node) => contacts_action(serde_json::json!({ "Get": node }))
+            .map(|response| {
+                (
+                    contacts::Response::GetContact,
+                    Some(LazyLoadBlob::new(
+                        Some("application/json"),
+                        serde_json::to_vec(&response["Contact"]).unwrap(),
+                    )),
+                )
+            }),
         contacts::Request::AddContact(node) => {
             if let Some((response, blob)) = invalid_node(kimap, &node) {
                 return (response, blob);
             }
-            state.add_contact(node);
-            (contacts::Response::AddContact, None)
+            contacts_action(serde_json::json!({ "Add": node }))
+                .map(|_| (contacts::Response::AddContact, None))
         }
         contacts::Request::AddField((node, field, value)) => {
             if let Some((response, blob)) = invalid_node(kimap, &node) {
@@ -353,18 +307,42 @@ fn handle_contacts_request(
             let Ok(value) = serde_json::from_str::<serde_json::Value>(&value) else {
                 return (contacts::Response::Err("Malformed value".to_string()), None);
             };
-            state.add_field(node, field, value);
-            (contacts::Response::AddField, None)
+            contacts_action(serde_json::json!({
+                "SetField": { "node": node, "field": field, "value": value }
+            }))
+            .map(|_| (contacts::Response::AddField, None))
         }
         contacts::Request::RemoveContact(node) => {
-            state.remove_contact(node);
-            (contacts::Response::RemoveContact, None)
-        }
-        contacts::Request::RemoveField((node, field)) => {
-            state.remove_field(node, field);
-            (contacts::Response::RemoveField, None)
+            contacts_action(serde_json::json!({ "Remove": node }))
+                .map(|_| (contacts::Response::RemoveContact, None))
         }
+        contacts::Request::RemoveField((node, field)) => contacts_action(serde_json::json!({
+            "RemoveField": { "node": node, "field": field }
+        }))
+        .map(|_| (contacts::Response::RemoveField, None)),
+    };
+    result.unwrap_or_else(|e| (contacts::Response::Err(e), None))
+}
+
+fn all_contacts() -> Result<serde_json::Value, String> {
+    contacts_action(serde_json::json!("GetAll")).map(|response| response["Contacts"].clone())
+}
+
+/// Send an action to contacts:distro:sys, which keeps our contacts, and take
+/// its response, unless it is an error.
+fn contacts_action(action: serde_json::Value) -> Result<serde_json::Value, String> {
+    let Ok(Ok(message)) = Request::to(("our", "contacts", "distro", "sys"))
+        .body(serde_json::to_vec(&action).unwrap())
+        .send_and_await_response(CONTACTS_TIMEOUT)
+    else {
+        return Err("no response from contacts:distro:sys".to_string());
+    };
+    let response: serde_json::Value =
+        serde_json::from_slice(message.body()).map_err(|e| e.to_string())?;
+    if let Some(e) = response.get("Err") {
+        return Err(e.to_string());
     }
+    Ok(response)
 }
 
 fn invalid_node(
@@ -1,14 +1,20 @@
 use crate::kinode::process::homepage;
 use kinode_process_lib::{
-    await_message, call_init, get_blob, http, http::server, println, Address, Capability,
-    LazyLoadBlob,
+    await_message, call_init, get_blob, http, http::server, println, save_capabilities, Address,
+    Capability, LazyLoadBlob,
 };
-use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+mod search_worker_lib;
+use search_worker_lib::{search_score, spawn_search_worker, SearchHit, SearchJob};
 
 /// Fetching OS version from main package
 const CARGO_TOML: &str = include_str!("../../../../Cargo.toml");
 
+/// most search results given, unless fewer are asked for
+const MAX_SEARCH_RESULTS: usize = 100;
+
 const DEFAULT_FAVES: &[&str] = &[
     "chess:chess:sys",
     "main:app-store:sys",
@@ -27,9 +33,68 @@ struct HomepageApp {
     widget: Option<String>,
     order: u32,
     favorite: bool, // **not currently used on frontend**
+    /// count shown over the icon, if not 0
+    badge: u32,
+    /// a widget filled in with data, with its data path made absolute
+    widget_config: Option<homepage::WidgetConfig>,
+    pinned: bool,
+    hidden: bool,
+    folder: Option<String>,
 }
 
-type PersistedAppOrder = HashMap<String, u32>;
+/// How the user laid out their apps, persisted across restarts.
+/// Apps are keyed by process ID, and kept while uninstalled but for
+/// those that remove themselves.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Layout {
+    /// position of each app, lowest first
+    order: HashMap<String, u32>,
+    /// apps shown before all others
+    pinned: BTreeSet<String>,
+    /// apps left out of the grid, though still reachable at their path
+    hidden: BTreeSet<String>,
+    /// the folder each app is grouped in, if any
+    folders: HashMap<String, String>,
+}
+
+impl Layout {
+    fn load() -> Self {
+        let Some(bytes) = kinode_process_lib::get_state() else {
+            return Self::default();
+        };
+        // before the layout, only the order was persisted
+        if let Ok(order) = serde_json::from_slice::<HashMap<String, u32>>(&bytes) {
+            return Self {
+                order,
+                ..Self::default()
+            };
+        }
+        serde_json::from_slice(&bytes).unwrap_or_default()
+    }
+
+    fn save(&self) {
+        kinode_process_lib::set_state(&serde_json::to_vec(self).unwrap());
+    }
+
+    /// set the layout of an app, as added, to ours
+    fn apply(&self, app: &mut HomepageApp) {
+        if let Some(order) = self.order.get(&app.id) {
+            app.order = *order;
+        }
+        app.pinned = self.pinned.contains(&app.id);
+        app.hidden = self.hidden.contains(&app.id);
+        app.folder = self.folders.get(&app.id).cloned();
+    }
+
+    fn forget(&mut self, id: &str) {
+        self.order.remove(id);
+        self.pinned.remove(id);
+        self.hidden.remove(id);
+        self.folders.remove(id);
+        self.save();
+    }
+}
 
 wit_bindgen::generate!({
     path: "target/wit",
@@ -160,15 +225,31 @@ fn init(our: Address) {
         .bind_http_path("/favorite", http_config.clone())
         .expect("failed to bind /favorite");
     http_server
-        .bind_http_path("/order", http_config)
+        .bind_http_path("/order", http_config.clone())
         .expect("failed to bind /order");
+    http_server
+        .bind_http_path("/pin", http_config.clone())
+        .expect("failed to bind /pin");
+    http_server
+        .bind_http_path("/hide", http_config.clone())
+        .expect("failed to bind /hide");
+    http_server
+        .bind_http_path("/folder", http_config.clone())
+        .expect("failed to bind /folder");
+    http_server
+        .bind_http_path("/layout", http_config.clone())
+        .expect("failed to bind /layout");
+    http_server
+        .bind_http_path("/search", http_config)
+        .expect("failed to bind /search");
 
     kinode_process_lib::homepage::add_to_homepage("Clock", None, None, Some(&make_clock_widget()));
 
-    // load persisted app order
-    let mut persisted_app_order =
-        kinode_process_lib::get_typed_state(|bytes| serde_json::from_slice(bytes))
-            .unwrap_or(PersistedAppOrder::new());
+    // load persisted app layout
+    let mut layout = Layout::load();
+
+    // processes that answer searches, and the labels of their results
+    let mut search_providers: HashMap<Address, String> = HashMap::new();
 
     loop {
         let Ok(ref message) = await_message() else {
@@ -180,6 +261,13 @@ fn init(our: Address) {
                 let Ok(request) = http_server.parse_request(message.body()) else {
                     continue;
                 };
+                // searches are answered by a worker, which responds in our stead
+                if let server::HttpServerRequest::Http(ref incoming) = request {
+                    if incoming.bound_path(None) == "/search" {
+                        search(&our, &app_data, &search_providers, incoming);
+                        continue;
+                    }
+                }
                 http_server.handle_request(
                     request,
                     |incoming| {
@@ -199,7 +287,12 @@ fn init(our: Address) {
                                 // hacky way to ensure that the homepage has populated itself before
                                 // loading in after boot
                                 if app_data.len() >= 4
-                                    && app_data.values().filter(|app| app.widget.is_some()).count()
+                                    && app_data
+                                        .values()
+                                        .filter(|app| {
+                                            app.widget.is_some() || app.widget_config.is_some()
+                                        })
+                                        .count()
                                         >= 3
                                 {
                                     (
@@ -214,27 +307,11 @@ fn init(our: Address) {
                                 }
                             }
                             "/favorite" => {
-                                let Ok(http::Method::POST) = incoming.method() else {
-                                    return (
-                                        server::HttpResponse::new(
-                                            http::StatusCode::METHOD_NOT_ALLOWED,
-                                        ),
-                                        None,
-                                    );
-                                };
-                                let Some(body) = get_blob() else {
-                                    return (
-                                        server::HttpResponse::new(http::StatusCode::BAD_REQUEST),
-                                        None,
-                                    );
-                                };
-                                let Ok(favorite_toggle) =
-                                    serde_json::from_slice::<(String, bool)>(&body.bytes)
-                                else {
-                                    return (
-                                        server::HttpResponse::new(http::StatusCode::BAD_REQUEST),
-                                        None,
-                                    );
+                                let favorite_toggle = match post_body::<(String, bool)>(&incoming) {
+                                    Ok(body) => body,
+                                    Err(status) => {
+                                        return (server::HttpResponse::new(status), None)
+                                    }
                                 };
                                 if let Some(app) = app_data.get_mut(&favorite_toggle.0) {
                                     app.favorite = favorite_toggle.1;
@@ -242,39 +319,109 @@ fn init(our: Address) {
                                 (server::HttpResponse::new(http::StatusCode::OK), None)
                             }
                             "/order" => {
-                                let Ok(http::Method::POST) = incoming.method() else {
-                                    return (
-                                        server::HttpResponse::new(
-                                            http::StatusCode::METHOD_NOT_ALLOWED,
-                                        ),
-                                        None,
-                                    );
-                                };
-                                let Some(body) = get_blob() else {
-                                    return (
-                                        server::HttpResponse::new(http::StatusCode::BAD_REQUEST),
-                                        None,
-                                    );
-                                };
-                                let Ok(order_list) =
-                                    serde_json::from_slice::<Vec<(String, u32)>>(&body.bytes)
-                                else {
-                                    return (
-                                        server::HttpResponse::new(http::StatusCode::BAD_REQUEST),
-                                        None,
-                                    );
+                                let order_list = match post_body::<Vec<(String, u32)>>(&incoming) {
+                                    Ok(body) => body,
+                                    Err(status) => {
+                                        return (server::HttpResponse::new(status), None)
+                                    }
                                 };
                                 for (app_id, order) in &order_list {
                                     if let Some(app) = app_data.get_mut(app_id) {
                                         app.order = *order;
                                     }
                                 }
-                                persisted_app_order = order_list.into_iter().collect();
-                                kinode_process_lib::set_state(
-                                    &serde_json::to_vec(&persisted_app_order).unwrap(),
-                                );
+                                layout.order = order_list.into_iter().collect();
+                                layout.save();
+                                (server::HttpResponse::new(http::StatusCode::OK), None)
+                            }
+                            // `[app_id, pinned]`
+                            "/pin" => {
+                                let (app_id, pinned) = match post_body::<(String, bool)>(&incoming)
+                                {
+                                    Ok(body) => body,
+                                    Err(status) => {
+                                        return (server::HttpResponse::new(status), None)
+                                    }
+                                };
+                                if pinned {
+                                    layout.pinned.insert(app_id.clone());
+                                } else {
+                                    layout.pinned.remove(&app_id);
+                                }
+                                if let Some(app) = app_data.get_mut(&app_id) {
+                                    app.pinned = pinned;
+                                }
+                                layout.save();
+                                (server::HttpResponse::new(http::StatusCode::OK), None)
+                            }
+                            // `[app_id, hidden]`
+                            "/hide" => {
+                                let (app_id, hidden) = match post_body::<(String, bool)>(&incoming)
+                                {
+                                    Ok(body) => body,
+                                    Err(status) => {
+                                        return (server::HttpResponse::new(status), None)
+                                    }
+                                };
+                                if hidden {
+                                    layout.hidden.insert(app_id.clone());
+                                } else {
+                                    layout.hidden.remove(&app_id);
+                                }
+                                if let Some(app) = app_data.get_mut(&app_id) {
+                                    app.hidden = hidden;
+                                }
+                                layout.save();
+                                (server::HttpResponse::new(http::StatusCode::OK), None)
+                            }
+                            // `[app_id, folder]`, with a null folder to take the app out of its
+                            // own. folders exist while they hold an app
+                            "/folder" => {
+                                let (app_id, folder) =
+                                    match post_body::<(String, Option<String>)>(&incoming) {
+                                        Ok(body) => body,
+                                        Err(status) => {
+                                            return (server::HttpResponse::new(status), None)
+                                        }
+                                    };
+                                let folder = folder
+                                    .map(|folder| folder.trim().to_string())
+                                    .filter(|folder| !folder.is_empty());
+                                match &folder {
+                                    Some(folder) => {
+                                        layout.folders.insert(app_id.clone(), folder.clone())
+                                    }
+                                    None => layout.folders.remove(&app_id),
+                                };
+                                if let Some(app) = app_data.get_mut(&app_id) {
+                                    app.folder = folder;
+                                }
+                                layout.save();
                                 (server::HttpResponse::new(http::StatusCode::OK), None)
                             }
+                            // GET the whole layout, or DELETE it to go back to the default
+                            "/layout" => match incoming.method() {
+                                Ok(http::Method::GET) => (
+                                    server::HttpResponse::new(http::StatusCode::OK),
+                                    Some(LazyLoadBlob::new(
+                                        Some("application/json"),
+                                        serde_json::to_vec(&layout).unwrap(),
+                                    )),
+                                ),
+                                Ok(http::Method::DELETE) => {
+                                    layout = Layout::default();
+                                    layout.save();
+                                    for (position, app) in app_data.values_mut().enumerate() {
+                                        app.order = position as u32;
+                                        layout.apply(app);
+                                    }
+                                    (server::HttpResponse::new(http::StatusCode::OK), None)
+                                }
+                                _ => (
+                                    server::HttpResponse::new(http::StatusCode::METHOD_NOT_ALLOWED),
+                                    None,
+                                ),
+                            },
                             _ => (server::HttpResponse::new(http::StatusCode::NOT_FOUND), None),
                         }
                     },
@@ -295,37 +442,44 @@ fn init(our: Address) {
                         widget,
                     }) => {
                         let id = message.source().process.to_string();
-                        app_data.insert(
-                            id.clone(),
-                            HomepageApp {
-                                id: id.clone(),
-                                process: message.source().process().to_string(),
-                                package: message.source().package().to_string(),
-                                publisher: message.source().publisher().to_string(),
-                                path: path.map(|path| {
-                                    format!(
-                                        "/{}/{}",
-                                        message.source().process,
-                                        path.strip_prefix('/').unwrap_or(&path)
-                                    )
-                                }),
-                                label,
-                                base64_icon: icon,
-                                widget,
-                                order: if let Some(order) = persisted_app_order.get(&id) {
-                                    *order
-                                } else {
-                                    app_data.len() as u32
-                                },
-                                favorite: DEFAULT_FAVES
-                                    .contains(&message.source().process.to_string().as_str()),
-                            },
-                        );
+                        // apps re-add themselves to update their widget, keeping their badge
+                        // and any widget set with SetWidget
+                        let (badge, widget_config) = app_data
+                            .remove(&id)
+                            .map(|app| (app.badge, app.widget_config))
+                            .unwrap_or_default();
+                        let mut app = HomepageApp {
+                            id: id.clone(),
+                            process: message.source().process().to_string(),
+                            package: message.source().package().to_string(),
+                            publisher: message.source().publisher().to_string(),
+                            path: path.map(|path| {
+                                format!(
+                                    "/{}/{}",
+                                    message.source().process,
+                                    path.strip_prefix('/').unwrap_or(&path)
+                                )
+                            }),
+                            label,
+                            base64_icon: icon,
+                            widget,
+                            order: app_data.len() as u32,
+                            favorite: DEFAULT_FAVES
+                                .contains(&message.source().process.to_string().as_str()),
+                            badge,
+                            widget_config,
+                            pinned: false,
+                            hidden: false,
+                            folder: None,
+                        };
+                        layout.apply(&mut app);
+                        app_data.insert(id, app);
                     }
                     homepage::Request::Remove => {
                         let id = message.source().process.to_string();
                         app_data.remove(&id);
-                        persisted_app_order.remove(&id);
+                        layout.forget(&id);
+                        search_providers.remove(message.source());
                     }
                     homepage::Request::RemoveOther(id) => {
                         // caps check
@@ -338,7 +492,7 @@ fn init(our: Address) {
                         }
                         // end caps check
                         app_data.remove(&id);
-                        persisted_app_order.remove(&id);
+                        layout.forget(&id);
                     }
                     homepage::Request::SetStylesheet(new_stylesheet_string) => {
                         // caps check
@@ -368,12 +522,109 @@ fn init(our: Address) {
                             .expect("failed to bind /kinode.css");
                         println!("updated kinode.css!");
                     }
+                    homepage::Request::SetBadge(badge) => {
+                        let id = message.source().process.to_string();
+                        if let Some(app) = app_data.get_mut(&id) {
+                            app.badge = badge;
+                        }
+                    }
+                    homepage::Request::SetWidget(homepage::WidgetConfig {
+                        template,
+                        data_path,
+                        refresh_secs,
+                    }) => {
+                        let id = message.source().process.to_string();
+                        if let Some(app) = app_data.get_mut(&id) {
+                            app.widget_config = Some(homepage::WidgetConfig {
+                                template,
+                                data_path: format!(
+                                    "/{}/{}",
+                                    message.source().process,
+                                    data_path.strip_prefix('/').unwrap_or(&data_path)
+                                ),
+                                refresh_secs,
+                            });
+                        }
+                    }
+                    homepage::Request::AddSearchProvider(label) => {
+                        // keep the provider's messaging capability, if attached,
+                        // so that searches can be sent to it
+                        let messaging_capability =
+                            Capability::new(message.source(), "\"messaging\"");
+                        if message.capabilities().contains(&messaging_capability) {
+                            save_capabilities(&[messaging_capability]);
+                        }
+                        search_providers.insert(message.source().clone(), label);
+                    }
                 }
             }
         }
     }
 }
 
+/// Answer a search of the launcher: rank our apps, and hand the search to a
+/// worker, which asks every provider at once and responds with all results.
+fn search(
+    our: &Address,
+    app_data: &BTreeMap<String, HomepageApp>,
+    search_providers: &HashMap<Address, String>,
+    incoming: &server::IncomingHttpRequest,
+) {
+    // GET `?q=<query>&limit=<n>`
+    let params = incoming.query_params();
+    let Some(query) = params.get("q").map(|q| q.trim()) else {
+        server::send_response(http::StatusCode::BAD_REQUEST, None, vec![]);
+        return;
+    };
+    let limit = params
+        .get("limit")
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(20)
+        .min(MAX_SEARCH_RESULTS);
+    if query.is_empty() {
+        server::send_response(http::StatusCode::OK, None, b"[]".to_vec());
+        return;
+    }
+    let hits = app_data
+        .values()
+        .filter_map(|app| {
+            Some(SearchHit {
+                provider: "Apps".to_string(),
+                title: app.label.clone(),
+                description: Some(app.id.clone()),
+                path: app.path.clone()?,
+                score: search_score(query, &app.label, Some(&app.id)),
+            })
+        })
+        .filter(|hit| hit.score > 0)
+        .collect();
+    let job = SearchJob {
+        query: query.to_string(),
+        limit,
+        hits,
+        providers: search_providers
+            .iter()
+            .map(|(provider, label)| (provider.clone(), label.clone()))
+            .collect(),
+    };
+    if let Err(e) = spawn_search_worker(our, job) {
+        println!("failed to search: {e}");
+        server::send_response(http::StatusCode::INTERNAL_SERVER_ERROR, None, vec![]);
+    }
+}
+
+/// The JSON body of a POST request.
+fn post_body<T: DeserializeOwned>(
+    incoming: &server::IncomingHttpRequest,
+) -> Result<T, http::StatusCode> {
+    let Ok(http::Method::POST) = incoming.method() else {
+        return Err(http::StatusCode::METHOD_NOT_ALLOWED);
+    };
+    get_blob()
+        .and_then(|body| serde_json::from_slice(&body.bytes).ok())
+        .ok_or(http::StatusCode::BAD_REQUEST)
+}
+
 fn version_from_cargo_toml() -> String {
     let version = CARGO_TOML
         .lines()
@@ -0,0 +1 @@
+../../search-worker/src/search_worker_lib.rs
\ No newline at end of file
@@ -0,0 +1,212 @@
+//! notifications:homepage:sys
+//!
+//! The inbox of notifications that local processes post for the user, so that
+//! apps need not invent their own ways to alert them. The homepage lists them,
+//! and counts the unread ones over the icon of the app that posted them.
+//!
+//! Posting takes messaging access to us, which a package requests in its
+//! manifest. A process lists and marks read only its own notifications; the
+//! homepage reads and marks them all over HTTP, at paths bound with auth.
+//!
+//! The node's own notifications, of app updates, low disk space and new logins,
+//! are posted by the notify:distro:sys runtime module, and listed under the
+//! settings app, where they are set up.
+use crate::kinode::process::notifications::{
+    Notification, PushRequest, Request as NotificationsRequest, Response as NotificationsResponse,
+};
+use kinode_process_lib::{
+    await_message, call_init, get_blob, get_typed_state, http, println, set_state, Address,
+    LazyLoadBlob, Message, Response,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "homepage-sys-v1",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+/// the most notifications kept, the oldest dropped first
+const MAX_NOTIFICATIONS: usize = 500;
+/// posts the node's own notifications, which have no UI of their own
+const NOTIFY_PROCESS: &str = "notify:distro:sys";
+/// lists the node's own notifications, as the app they are set up in
+const SETTINGS_PROCESS: &str = "settings:settings:sys";
+
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    /// oldest first
+    notifications: VecDeque<Notification>,
+    next_id: u64,
+}
+
+impl State {
+    fn load() -> Self {
+        get_typed_state(|bytes| serde_json::from_slice(bytes)).unwrap_or_default()
+    }
+
+    fn save(&self) {
+        set_state(&serde_json::to_vec(self).unwrap());
+    }
+
+    fn push(&mut self, source: &Address, request: PushRequest) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let process = match source.process.to_string() {
+            process if process == NOTIFY_PROCESS => SETTINGS_PROCESS.to_string(),
+            process => process,
+        };
+        self.notifications.push_back(Notification {
+            id,
+            title: request.title,
+            body: request.body,
+            path: request
+                .path
+                .map(|path| format!("/{process}/{}", path.strip_prefix('/').unwrap_or(&path))),
+            source: process,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            read: false,
+        });
+        while self.notifications.len() > MAX_NOTIFICATIONS {
+            self.notifications.pop_front();
+        }
+        self.save();
+        id
+    }
+
+    /// newest first, of the given process if any
+    fn list(&self, source: Option<&str>) -> Vec<Notification> {
+        self.notifications
+            .iter()
+            .rev()
+            .filter(|n| source.map_or(true, |source| n.source == source))
+            .cloned()
+            .collect()
+    }
+
+    /// mark the given notifications read, or all if none are given, of the
+    /// given process if any
+    fn mark_read(&mut self, source: Option<&str>, ids: &[u64]) {
+        for notification in self.notifications.iter_mut() {
+            if source.map_or(true, |source| notification.source == source)
+                && (ids.is_empty() || ids.contains(&notification.id))
+            {
+                notification.read = true;
+            }
+        }
+        self.save();
+    }
+}
+
+call_init!(init);
+fn init(our: Address) {
+    let mut state = State::load();
+
+    let mut http_server = http::server::HttpServer::new(5);
+    http_server
+        .bind_http_path("/notifications", http::server::HttpBindingConfig::default())
+        .unwrap();
+    http_server
+        .bind_http_path("/read", http::server::HttpBindingConfig::default())
+        .unwrap();
+
+    loop {
+        let message = match await_message() {
+            Err(send_error) => {
+                println!("got send error: {send_error:?}");
+                continue;
+            }
+            Ok(message) => message,
+        };
+        let Message::Request {
+            ref source,
+            ref body,
+            expects_response,
+            ..
+        } = message
+        else {
+            continue; // ignore responses
+        };
+        if source.node() != our.node {
+            continue; // ignore messages from other nodes
+        }
+        if source.process == "http-server:distro:sys" {
+            let Ok(server_request) = http_server.parse_request(body) else {
+                continue;
+            };
+            http_server.handle_request(
+                server_request,
+                |req| handle_http_request(&mut state, &req),
+                |_channel_id, _message_type, _blob| {
+                    // we don't expect websocket messages
+                },
+            );
+        } else {
+            let Ok(request) = serde_json::from_slice::<NotificationsRequest>(body) else {
+                println!("got malformed request from {source}");
+                continue;
+            };
+            let source_process = source.process.to_string();
+            let response = match request {
+                NotificationsRequest::Push(push) => {
+                    NotificationsResponse::Push(state.push(source, push))
+                }
+                NotificationsRequest::List => {
+                    NotificationsResponse::List(state.list(Some(&source_process)))
+                }
+                NotificationsRequest::MarkRead(ids) => {
+                    state.mark_read(Some(&source_process), &ids);
+                    NotificationsResponse::MarkRead
+                }
+            };
+            if expects_response.is_some() {
+                Response::new()
+                    .body(serde_json::to_vec(&response).unwrap())
+                    .send()
+                    .unwrap();
+            }
+        }
+    }
+}
+
+/// GET `/notifications` lists every notification, newest first, and POST
+/// `/read` marks read the IDs in its body, or every notification if empty.
+fn handle_http_request(
+    state: &mut State,
+    req: &http::server::IncomingHttpRequest,
+) -> (http::server::HttpResponse, Option<LazyLoadBlob>) {
+    match (
+        req.bound_path(None),
+        req.method().as_ref().map(|m| m.as_str()),
+    ) {
+        ("/notifications", Ok("GET")) => (
+            http::server::HttpResponse::new(http::StatusCode::OK)
+                .header("Content-Type", "application/json"),
+            Some(LazyLoadBlob::new(
+                Some("application/json"),
+                serde_json::to_vec(&state.list(None)).unwrap(),
+            )),
+        ),
+        ("/read", Ok("POST")) => {
+            let Some(ids) =
+                get_blob().and_then(|blob| serde_json::from_slice::<Vec<u64>>(&blob.bytes).ok())
+            else {
+                return (
+                    http::server::HttpResponse::new(http::StatusCode::BAD_REQUEST),
+                    None,
+                );
+            };
+            state.mark_read(None, &ids);
+            (http::server::HttpResponse::new(http::StatusCode::OK), None)
+        }
+        _ => (
+            http::server::HttpResponse::new(http::StatusCode::METHOD_NOT_ALLOWED),
+            None,
+        ),
+    }
+}
@@ -0,0 +1,109 @@
+//! {search_id}:homepage:sys
+//! Answers a search of the homepage's launcher, on behalf of the homepage.
+//!
+//! The homepage spawns a worker for each search and hands it a [`SearchJob`],
+//! inheriting the HTTP request for the search. The worker sends the search to
+//! every provider at once, so that all share one deadline, ranks what comes
+//! back before it along with the homepage's own apps, responds to the HTTP
+//! request, and exits.
+use kinode_process_lib::{await_message, call_init, http, Address, Message, Request};
+use std::collections::HashMap;
+
+mod search_api;
+mod search_worker_lib;
+use search_api::{SearchQuery, SearchRequest, SearchResponse, SearchResult};
+use search_worker_lib::{search_score, SearchHit, SearchJob};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "homepage-sys-v1",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+/// how long search providers have to answer
+const SEARCH_TIMEOUT: u64 = 2;
+
+call_init!(init);
+fn init(our: Address) {
+    let Ok(Message::Request { source, body, .. }) = await_message() else {
+        panic!("search-worker: got bad init message");
+    };
+    if source.node() != our.node() || source.package_id() != our.package_id() {
+        panic!("search-worker: got bad init message source");
+    }
+    let Ok(SearchJob {
+        query,
+        limit,
+        mut hits,
+        providers,
+    }) = serde_json::from_slice(&body)
+    else {
+        panic!("search-worker: got bad init message body");
+    };
+
+    let request = serde_json::to_vec(&SearchRequest::Search(SearchQuery {
+        query: query.clone(),
+        limit: limit as u32,
+    }))
+    .unwrap();
+    // each search is answered by a response or a timeout, at most SEARCH_TIMEOUT from now
+    let mut waiting = 0;
+    for (i, (provider, _)) in providers.iter().enumerate() {
+        if Request::to(provider)
+            .body(request.clone())
+            .context(i.to_string())
+            .expects_response(SEARCH_TIMEOUT)
+            .send()
+            .is_ok()
+        {
+            waiting += 1;
+        }
+    }
+    while waiting > 0 {
+        let message = await_message();
+        waiting -= 1;
+        let Ok(message @ Message::Response { .. }) = message else {
+            continue;
+        };
+        let Some((provider, label)) = message
+            .context()
+            .and_then(|context| std::str::from_utf8(context).ok()?.parse::<usize>().ok())
+            .and_then(|i| providers.get(i))
+        else {
+            continue;
+        };
+        let Ok(SearchResponse::Search(results)) = serde_json::from_slice(message.body()) else {
+            continue;
+        };
+        hits.extend(results.into_iter().take(limit).map(
+            |SearchResult {
+                 title,
+                 description,
+                 path,
+             }| SearchHit {
+                provider: label.clone(),
+                // the provider found it, maybe by what it holds rather than its name
+                score: search_score(&query, &title, description.as_deref()).max(1),
+                path: format!(
+                    "/{}/{}",
+                    provider.process,
+                    path.strip_prefix('/').unwrap_or(&path)
+                ),
+                title,
+                description,
+            },
+        ));
+    }
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.title.cmp(&b.title)));
+    hits.truncate(limit);
+
+    http::server::send_response(
+        http::StatusCode::OK,
+        Some(HashMap::from([(
+            "Content-Type".to_string(),
+            "application/json".to_string(),
+        )])),
+        serde_json::to_vec(&hits).unwrap(),
+    );
+}
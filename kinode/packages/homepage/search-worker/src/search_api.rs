@@ -0,0 +1,43 @@
+//! The `search` interface of homepage:sys-v1, defined once in Rust for the
+//! search providers of our own packages, into which this file is symlinked,
+//! as they don't import the homepage's WIT. It must match that interface.
+use kinode_process_lib::{Address, Capability, Request};
+use serde::{Deserialize, Serialize};
+
+/// a search from the homepage's launcher
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SearchRequest {
+    Search(SearchQuery),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchQuery {
+    /// as typed by the user
+    pub query: String,
+    pub limit: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SearchResponse {
+    /// the best matches, at most as many as the limit
+    Search(Vec<SearchResult>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub title: String,
+    /// shown under the title, such as where a file is or what an app does
+    pub description: Option<String>,
+    /// opened when the result is picked, relative to the provider's path
+    pub path: String,
+}
+
+/// Ask the homepage to send us searches, listing our results under `label`.
+/// It is given our messaging capability to do so.
+#[allow(dead_code)]
+pub fn register_search_provider(our: &Address, label: &str) {
+    let _ = Request::to(("our", "homepage", "homepage", "sys"))
+        .body(serde_json::to_vec(&serde_json::json!({ "AddSearchProvider": label })).unwrap())
+        .capabilities(vec![Capability::new(our, "\"messaging\"")])
+        .send();
+}
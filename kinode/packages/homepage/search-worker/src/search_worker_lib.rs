@@ -0,0 +1,82 @@
+//! Helpers for spawning search workers, shared with the homepage.
+//! The homepage hands each search of its launcher to a worker, which asks
+//! every search provider at once and answers the search itself, so that
+//! slow providers never hold up the homepage.
+use kinode_process_lib::{our_capabilities, spawn, Address, OnExit, Request};
+use serde::{Deserialize, Serialize};
+
+/// Sent by the homepage to a search worker it has spawned, inheriting the
+/// HTTP request for the search, so that the worker responds to it.
+#[derive(Serialize, Deserialize)]
+pub struct SearchJob {
+    pub query: String,
+    pub limit: usize,
+    /// our own apps that match
+    pub hits: Vec<SearchHit>,
+    /// processes that answer searches, and the labels of their results
+    pub providers: Vec<(Address, String)>,
+}
+
+/// A search result, as given to our frontend, best first.
+#[derive(Serialize, Deserialize)]
+pub struct SearchHit {
+    /// the label of the provider that found it
+    pub provider: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub path: String,
+    pub score: u32,
+}
+
+/// Spawns a worker process and hands it `job`, inheriting the HTTP request
+/// being handled. The worker responds to that request and then exits.
+#[allow(dead_code)]
+pub fn spawn_search_worker(our: &Address, job: SearchJob) -> anyhow::Result<()> {
+    let worker_id: u64 = rand::random();
+    let Ok(worker_process_id) = spawn(
+        Some(&format!("search-{worker_id}")),
+        &format!("{}/pkg/search-worker.wasm", our.package_id()),
+        OnExit::None,
+        // including the messaging capabilities search providers gave us
+        our_capabilities(),
+        vec![],
+        false,
+    ) else {
+        return Err(anyhow::anyhow!("failed to spawn search-worker!"));
+    };
+    Request::new()
+        .target((&our.node, worker_process_id))
+        .inherit(true)
+        .body(serde_json::to_vec(&job)?)
+        .send()?;
+    Ok(())
+}
+
+/// How well a title matches a query, by each of its words, from 0 for not at
+/// all: best when the title is the word, then when it starts with the word,
+/// then when one of its words does, then when it holds the word anywhere.
+/// Matching the description counts for least.
+pub fn search_score(query: &str, title: &str, description: Option<&str>) -> u32 {
+    let title = title.to_lowercase();
+    let description = description.map(|d| d.to_lowercase());
+    let mut score = 0;
+    for word in query.to_lowercase().split_whitespace() {
+        score += if title == word {
+            100
+        } else if title.starts_with(word) {
+            80
+        } else if title
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|w| w.starts_with(word))
+        {
+            60
+        } else if title.contains(word) {
+            40
+        } else if description.as_ref().is_some_and(|d| d.contains(word)) {
+            20
+        } else {
+            return 0;
+        };
+    }
+    score
+}
@@ -1,13 +1,17 @@
 import React, { useState, useEffect, useMemo } from "react";
 import useHomepageStore, { HomepageApp } from "../store/homepageStore";
 import AppDisplay from "./AppDisplay";
+import { Modal } from "./Modal";
+
+// an app, or a folder of apps shown where its first app would be
+type GridEntry = { app: HomepageApp } | { folder: string, apps: HomepageApp[] };
 
 const AllApps: React.FC = () => {
-  const { apps } = useHomepageStore();
+  const { apps, setApps } = useHomepageStore();
   const [expanded, setExpanded] = useState(false);
   const [isMobile, setIsMobile] = useState(false);
   const [visibleApps, setVisibleApps] = useState(5);
-  const [orderedApps, setOrderedApps] = useState<HomepageApp[]>([]);
+  const [openFolder, setOpenFolder] = useState<string | null>(null);
   const [draggedIndex, setDraggedIndex] = useState<number | null>(null);
   const [dragOverIndex, setDragOverIndex] = useState<number | null>(null);
 
@@ -23,19 +27,30 @@ const AllApps: React.FC = () => {
     return () => window.removeEventListener("resize", handleResize);
   }, []);
 
-  // Sort apps based on persisted order
-  const sortedApps = useMemo(() => {
-    // Use orderedApps if we have them, otherwise fall back to apps
-    const appsToSort = orderedApps.length ? orderedApps : apps;
-    return [...appsToSort]
-      .sort((a, b) => a.order - b.order)
-      .filter(app => app.path !== null);
-  }, [apps, orderedApps]);
-
-  const displayedApps = expanded
-    ? sortedApps
-    : sortedApps.slice(0, visibleApps);
-  const hasMoreApps = sortedApps.length > visibleApps;
+  // Sort apps based on persisted order, pinned apps first, leaving out hidden ones
+  const entries = useMemo(() => {
+    const sortedApps = [...apps]
+      .filter(app => app.path !== null && !app.hidden)
+      .sort((a, b) => Number(b.pinned) - Number(a.pinned) || a.order - b.order);
+    const entries: GridEntry[] = [];
+    for (const app of sortedApps) {
+      const folder = app.folder && entries.find((entry) => 'folder' in entry && entry.folder === app.folder);
+      if (folder && 'apps' in folder) {
+        folder.apps.push(app);
+      } else if (app.folder) {
+        entries.push({ folder: app.folder, apps: [app] });
+      } else {
+        entries.push({ app });
+      }
+    }
+    return entries;
+  }, [apps]);
+
+  const displayedEntries = expanded
+    ? entries
+    : entries.slice(0, visibleApps);
+  const hasMoreApps = entries.length > visibleApps;
+  const folderApps = entries.find((entry) => 'folder' in entry && entry.folder === openFolder);
 
   const handleExpandClick = () => {
     setExpanded(!expanded);
@@ -61,16 +76,19 @@ const AllApps: React.FC = () => {
     const dragIndex = parseInt(e.dataTransfer.getData("text/plain"), 10);
     if (dragIndex === dropIndex) return;
 
-    const newSortedApps = [...sortedApps];
-    const [movedApp] = newSortedApps.splice(dragIndex, 1);
-    newSortedApps.splice(dropIndex, 0, movedApp);
+    const newEntries = [...entries];
+    const [movedEntry] = newEntries.splice(dragIndex, 1);
+    newEntries.splice(dropIndex, 0, movedEntry);
 
+    // apps in folders keep their places among each other, and hidden apps go last
+    const shownApps = newEntries.flatMap((entry) => 'app' in entry ? [entry.app] : entry.apps);
+    const newSortedApps = [...shownApps, ...apps.filter((app) => !shownApps.includes(app))];
     const updatedApps = newSortedApps.map((app, index) => ({
       ...app,
       order: index
     }));
 
-    setOrderedApps(updatedApps);
+    setApps(updatedApps);
 
     handleDragEnd();
 
@@ -90,11 +108,11 @@ const AllApps: React.FC = () => {
       <div
         className={`apps-grid ${expanded ? "expanded" : ""} ${isMobile ? "mobile" : ""
           }`}
-        style={{ gridTemplateColumns: `repeat(${Math.min(displayedApps.length, 5)}, 1fr)` }}
+        style={{ gridTemplateColumns: `repeat(${Math.min(displayedEntries.length, 5)}, 1fr)` }}
       >
-        {displayedApps.map((app, index) => (
+        {displayedEntries.map((entry, index) => (
           <div
-            key={`${app.id}-${app.order}`}
+            key={'app' in entry ? `${entry.app.id}-${entry.app.order}` : `folder-${entry.folder}`}
             draggable
             onDragStart={(e) => handleDragStart(e, index)}
             onDragOver={(e) => handleDragOver(e, index)}
@@ -103,7 +121,20 @@ const AllApps: React.FC = () => {
             className={`app-wrapper ${draggedIndex === index ? "dragging" : ""
               } ${dragOverIndex === index ? "drag-over" : ""}`}
           >
-            <AppDisplay app={app} />
+            {'app' in entry
+              ? <AppDisplay app={entry.app} />
+              : <a
+                href="#"
+                className="app-display app-folder"
+                title={entry.folder}
+                onClick={(e) => {
+                  e.preventDefault();
+                  setOpenFolder(entry.folder);
+                }}
+              >
+                <span className="app-icon">📁</span>
+                <h6 id="app-name">{entry.folder} ({entry.apps.length})</h6>
+              </a>}
             <div className="drag-handle">⋮⋮</div>
           </div>
         ))}
@@ -112,9 +143,16 @@ const AllApps: React.FC = () => {
         <button className="expand-button" onClick={handleExpandClick}>
           {expanded
             ? "Hide Apps"
-            : `Show ${isMobile ? "Apps" : `All (${sortedApps.length})`}`}
+            : `Show ${isMobile ? "Apps" : `All (${entries.length})`}`}
         </button>
       )}
+      {folderApps && 'apps' in folderApps && (
+        <Modal title={folderApps.folder} onClose={() => setOpenFolder(null)}>
+          <div className="folder-apps">
+            {folderApps.apps.map((app) => <AppDisplay key={app.id} app={app} />)}
+          </div>
+        </Modal>
+      )}
     </div>
   );
 };
@@ -1,10 +1,14 @@
-import { HomepageApp } from "../store/homepageStore";
+import useHomepageStore, { HomepageApp } from "../store/homepageStore";
 
 interface AppDisplayProps {
   app?: HomepageApp;
 }
 
 const AppDisplay: React.FC<AppDisplayProps> = ({ app }) => {
+  const { notifications } = useHomepageStore();
+  // the app's own badge, and its unread notifications in the inbox
+  const badge = (app?.badge || 0)
+    + notifications.filter((n) => !n.read && n.source === app?.id).length;
   return (
     <a
       id={app?.package_name}
@@ -26,6 +30,7 @@ const AppDisplay: React.FC<AppDisplayProps> = ({ app }) => {
       ) : (
         <img className="app-icon" src="/bird-orange.svg" />
       )}
+      {!!badge && <span className="app-badge">{badge}</span>}
       <h6 id="app-name">{app?.label || app?.package_name}</h6>
     </a>
   );
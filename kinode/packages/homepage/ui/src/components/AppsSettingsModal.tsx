@@ -0,0 +1,57 @@
+import useHomepageStore from "../store/homepageStore"
+import { Modal } from "./Modal"
+
+const AppsSettingsModal = () => {
+  const { apps, setAppLayout, resetAppLayout, setShowAppsSettings } = useHomepageStore()
+  const folders = [...new Set(apps.map((app) => app.folder).filter((folder) => folder))]
+
+  return <Modal
+    title='App Settings'
+    onClose={() => setShowAppsSettings(false)}
+  >
+    <div className="apps-settings">
+      <datalist id="app-folders">
+        {folders.map((folder) => <option key={folder} value={folder} />)}
+      </datalist>
+      {apps.filter((app) => app.path).map((app) => (
+        <div key={app.id} className="apps-settings-item">
+          <h4>{app.label}</h4>
+          <label>
+            <input
+              type="checkbox"
+              checked={app.pinned}
+              onChange={(e) => setAppLayout(app.id, { pinned: e.target.checked })}
+            />
+            pinned
+          </label>
+          <label>
+            <input
+              type="checkbox"
+              checked={!app.hidden}
+              onChange={(e) => setAppLayout(app.id, { hidden: !e.target.checked })}
+            />
+            shown
+          </label>
+          <input
+            key={app.folder || ''}
+            type="text"
+            list="app-folders"
+            placeholder="folder"
+            defaultValue={app.folder || ''}
+            onBlur={(e) => {
+              const folder = e.target.value.trim()
+              if (folder !== (app.folder || '')) {
+                setAppLayout(app.id, { folder: folder || null })
+              }
+            }}
+          />
+        </div>
+      ))}
+      <button onClick={() => resetAppLayout()}>
+        Reset layout
+      </button>
+    </div>
+  </Modal>
+}
+
+export default AppsSettingsModal
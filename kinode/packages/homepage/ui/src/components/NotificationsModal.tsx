@@ -0,0 +1,45 @@
+import useHomepageStore, { HomepageNotification } from "../store/homepageStore"
+import { Modal } from "./Modal"
+
+const NotificationsModal = () => {
+  const { apps, notifications, markNotificationsRead, setShowNotifications } = useHomepageStore()
+
+  const open = async (notification: HomepageNotification) => {
+    if (!notification.read) {
+      await markNotificationsRead([notification.id])
+    }
+    if (notification.path) {
+      window.location.href = notification.path
+    }
+  }
+
+  return <Modal
+    title='Notifications'
+    onClose={() => setShowNotifications(false)}
+  >
+    <div className="notifications">
+      {notifications.length === 0 && <p>(none)</p>}
+      {notifications.map((notification) => (
+        <div
+          key={notification.id}
+          className={`notification${notification.read ? '' : ' unread'}`}
+          onClick={() => open(notification)}
+        >
+          <h4>{notification.title}</h4>
+          <p>{notification.body}</p>
+          <p>
+            <small>
+              {apps.find((app) => app.id === notification.source)?.label || notification.source}
+              {' · '}{new Date(notification.timestamp).toLocaleString()}
+            </small>
+          </p>
+        </div>
+      ))}
+    </div>
+    {notifications.some((n) => !n.read) && (
+      <button onClick={() => markNotificationsRead([])}>Mark all read</button>
+    )}
+  </Modal>
+}
+
+export default NotificationsModal
@@ -0,0 +1,94 @@
+import { useEffect, useState } from "react"
+import useHomepageStore from "../store/homepageStore"
+import { Modal } from "./Modal"
+
+interface SearchHit {
+  provider: string,
+  title: string,
+  description?: string,
+  path: string,
+  score: number,
+}
+
+// how long to wait for typing to pause before searching
+const SEARCH_DELAY_MS = 200
+
+const SearchModal = () => {
+  const { setShowSearch } = useHomepageStore()
+  const [query, setQuery] = useState('')
+  const [hits, setHits] = useState<SearchHit[]>([])
+  const [selected, setSelected] = useState(0)
+  const [searching, setSearching] = useState(false)
+
+  useEffect(() => {
+    if (!query.trim()) {
+      setHits([])
+      return
+    }
+    let cancelled = false
+    const timeout = setTimeout(() => {
+      setSearching(true)
+      fetch(`/search?q=${encodeURIComponent(query)}`, { credentials: 'include' })
+        .then((res) => res.json())
+        .catch(() => [])
+        .then((hits) => {
+          if (cancelled) return
+          setHits(hits)
+          setSelected(0)
+          setSearching(false)
+        })
+    }, SEARCH_DELAY_MS)
+    return () => {
+      cancelled = true
+      clearTimeout(timeout)
+    }
+  }, [query])
+
+  const open = (hit?: SearchHit) => {
+    if (hit) window.location.href = hit.path
+  }
+
+  const onKeyDown = (e: React.KeyboardEvent) => {
+    if (e.key === 'ArrowDown') {
+      e.preventDefault()
+      setSelected(Math.min(selected + 1, hits.length - 1))
+    } else if (e.key === 'ArrowUp') {
+      e.preventDefault()
+      setSelected(Math.max(selected - 1, 0))
+    } else if (e.key === 'Enter') {
+      open(hits[selected])
+    }
+  }
+
+  return <Modal
+    title='Search'
+    onClose={() => setShowSearch(false)}
+  >
+    <div className="search">
+      <input
+        type="text"
+        placeholder="apps, settings, files..."
+        value={query}
+        onChange={(e) => setQuery(e.target.value)}
+        onKeyDown={onKeyDown}
+        autoFocus
+      />
+      <div className="search-results">
+        {query.trim() && !searching && hits.length === 0 && <p>(no results)</p>}
+        {hits.map((hit, index) => (
+          <div
+            key={`${hit.provider}-${hit.path}`}
+            className={`search-result${index === selected ? ' selected' : ''}`}
+            onMouseEnter={() => setSelected(index)}
+            onClick={() => open(hit)}
+          >
+            <h4>{hit.title}</h4>
+            <p><small>{hit.provider}{hit.description && ` · ${hit.description}`}</small></p>
+          </div>
+        ))}
+      </div>
+    </div>
+  </Modal>
+}
+
+export default SearchModal
@@ -1,15 +1,20 @@
 import { useEffect, useState } from "react"
 import usePersistentStore from "../store/persistentStore"
+import useHomepageStore, { WidgetConfig } from "../store/homepageStore"
+import { renderTemplate } from "../utils/template"
 
 interface WidgetProps {
   id: string
   label: string
-  widget: string
+  widget?: string
+  config?: WidgetConfig
 }
 
-const Widget: React.FC<WidgetProps> = ({ id, label, widget }) => {
+const Widget: React.FC<WidgetProps> = ({ id, label, widget, config }) => {
   const [_tallScreen, setTallScreen] = useState(window.innerHeight > window.innerWidth)
   const { toggleWidgetVisibility } = usePersistentStore();
+  const { widgetData, fetchWidgetData } = useHomepageStore();
+  const data = widgetData[id]
 
   useEffect(() => {
     setTallScreen(window.innerHeight > window.innerWidth)
@@ -22,9 +27,25 @@ const Widget: React.FC<WidgetProps> = ({ id, label, widget }) => {
     document.getElementById(`hide-widget-${id}`)?.addEventListener("click", hideWidget)
   }, [])
 
+  useEffect(() => {
+    if (!config) return
+    fetchWidgetData(id, config)
+    if (config.refresh_secs === 0) return
+    const interval = setInterval(() => fetchWidgetData(id, config), config.refresh_secs * 1000)
+    return () => clearInterval(interval)
+  }, [id, config?.data_path, config?.refresh_secs])
+
   return <div className="widget">
     <div className="bottom-bar"><p>{label}</p><p id={`hide-widget-${id}`}>[hide]</p></div>
-    <iframe srcDoc={widget} />
+    {config
+      ? data?.data !== undefined
+        ? <iframe srcDoc={renderTemplate(config.template, data.data)} />
+        : !data?.error && <p className="widget-loading">loading...</p>
+      : <iframe srcDoc={widget} />}
+    {config && data?.error && <p className="widget-error">
+      couldn't refresh: {data.error}
+      {data.fetched_at && `; showing data from ${new Date(data.fetched_at).toLocaleTimeString()}`}
+    </p>}
   </div>
 }
 
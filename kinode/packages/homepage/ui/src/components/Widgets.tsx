@@ -12,7 +12,7 @@ const Widgets = () => {
   const [dragOverIndex, setDragOverIndex] = useState<number | null>(null);
 
   useEffect(() => {
-    const visibleWidgets = apps.filter((app) => app.widget && !widgetSettings[app.id]?.hide);
+    const visibleWidgets = apps.filter((app) => (app.widget || app.widget_config) && !widgetSettings[app.id]?.hide);
     const orderedVisibleWidgets = visibleWidgets.sort((a, b) => {
       return widgetOrder.indexOf(a.id) - widgetOrder.indexOf(b.id);
     });
@@ -66,7 +66,8 @@ const Widgets = () => {
           <Widget
             id={wid.id}
             label={wid.label}
-            widget={wid.widget!}
+            widget={wid.widget}
+            config={wid.widget_config}
           />
           <div className="drag-handle">⋮⋮</div>
         </div>
@@ -11,7 +11,7 @@ const WidgetsSettingsModal = () => {
     onClose={() => setShowWidgetsSettings(false)}
   >
     <div className="widget-settings">
-      {apps.filter((app) => app.widget).map((app) => {
+      {apps.filter((app) => app.widget || app.widget_config).map((app) => {
         return (
           <div className="widget-settings-item">
             <h4>{app.label}</h4>
@@ -4,12 +4,28 @@ import useHomepageStore from "../store/homepageStore";
 import AllApps from "../components/AllApps";
 import Widgets from "../components/Widgets";
 import WidgetsSettingsModal from "../components/WidgetsSettingsModal";
+import AppsSettingsModal from "../components/AppsSettingsModal";
+import SearchModal from "../components/SearchModal";
+import NotificationsModal from "../components/NotificationsModal";
+import { NOTIFICATIONS_PATH } from "../store/homepageStore";
 
 function Homepage() {
   const [our, setOur] = useState("");
   const [version, setVersion] = useState("");
-  const { setApps, showWidgetsSettings, setShowWidgetsSettings } =
-    useHomepageStore();
+  const {
+    setApps,
+    showWidgetsSettings,
+    setShowWidgetsSettings,
+    showAppsSettings,
+    setShowAppsSettings,
+    showSearch,
+    setShowSearch,
+    notifications,
+    setNotifications,
+    showNotifications,
+    setShowNotifications,
+  } = useHomepageStore();
+  const unread = notifications.filter((n) => !n.read).length;
 
   const getAppPathsAndIcons = () => {
     Promise.all([
@@ -29,6 +45,42 @@ function Homepage() {
     getAppPathsAndIcons();
   }, [our]);
 
+  // notifications may be posted at any time, so check for new ones now and then
+  useEffect(() => {
+    const getNotifications = () =>
+      fetch(`${NOTIFICATIONS_PATH}/notifications`, { credentials: "include" })
+        .then((res) => res.json())
+        .then(setNotifications)
+        .catch(() => {});
+    getNotifications();
+    const interval = setInterval(getNotifications, 30 * 1000);
+    return () => clearInterval(interval);
+  }, []);
+
+  // silently swap the login cookie for a fresh one while the homepage is open,
+  // so that the user is only logged out after being away for a while
+  useEffect(() => {
+    const refresh = () =>
+      fetch("/login/refresh", { method: "POST", credentials: "include" }).catch(
+        () => {}
+      );
+    refresh();
+    const interval = setInterval(refresh, 60 * 60 * 1000);
+    return () => clearInterval(interval);
+  }, []);
+
+  // open the launcher with ctrl+k or cmd+k
+  useEffect(() => {
+    const handleKeyDown = (e: KeyboardEvent) => {
+      if ((e.ctrlKey || e.metaKey) && e.key === "k") {
+        e.preventDefault();
+        setShowSearch(true);
+      }
+    };
+    window.addEventListener("keydown", handleKeyDown);
+    return () => window.removeEventListener("keydown", handleKeyDown);
+  }, []);
+
   useEffect(() => {
     fetch("/our", { credentials: "include" })
       .then((res) => res.text())
@@ -60,6 +112,25 @@ function Homepage() {
           >
             [kinode v{version}]
           </a>
+          <a
+            href="#"
+            title="Search (ctrl+k)"
+            onClick={(e) => {
+              e.preventDefault();
+              setShowSearch(true);
+            }}
+          >
+            [🔍]
+          </a>
+          <a
+            href="#"
+            onClick={(e) => {
+              e.preventDefault();
+              setShowNotifications(true);
+            }}
+          >
+            [🔔{unread > 0 ? ` ${unread}` : ""}]
+          </a>
           <a
             href="#"
             onClick={(e) => {
@@ -69,6 +140,16 @@ function Homepage() {
           >
             [⚙]
           </a>
+          <a
+            href="#"
+            title="Arrange apps"
+            onClick={(e) => {
+              e.preventDefault();
+              setShowAppsSettings(true);
+            }}
+          >
+            [▦]
+          </a>
         </header>
 
         <div id="widgets-container">
@@ -78,6 +159,9 @@ function Homepage() {
           <AllApps />
         </footer>
         {showWidgetsSettings && <WidgetsSettingsModal />}
+        {showAppsSettings && <AppsSettingsModal />}
+        {showSearch && <SearchModal />}
+        {showNotifications && <NotificationsModal />}
       </div>
     </div>
   );
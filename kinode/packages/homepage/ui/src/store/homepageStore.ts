@@ -10,18 +10,65 @@ export interface HomepageApp {
   label: string,
   base64_icon?: string,
   widget?: string
+  widget_config?: WidgetConfig
   order: number
   favorite: boolean
+  badge?: number
+  pinned: boolean
+  hidden: boolean
+  folder?: string
 }
 
+// the parts of an app's layout set one at a time, each at its own path
+export type AppLayoutChange = { pinned: boolean } | { hidden: boolean } | { folder: string | null }
+
+// a widget filled in with data the homepage fetches from its app
+export interface WidgetConfig {
+  template: string,
+  data_path: string,
+  refresh_secs: number,
+}
+
+// the data last fetched for a widget, and why fetching it since failed, if it has
+export interface WidgetData {
+  data?: any,
+  fetched_at?: number,
+  error?: string,
+}
+
+export interface HomepageNotification {
+  id: number,
+  source: string,
+  title: string,
+  body: string,
+  path?: string,
+  timestamp: number,
+  read: boolean,
+}
+
+export const NOTIFICATIONS_PATH = '/notifications:homepage:sys'
+
 export interface HomepageStore {
   get: () => HomepageStore
   set: (partial: HomepageStore | Partial<HomepageStore>) => void
 
   apps: HomepageApp[]
   setApps: (apps: HomepageApp[]) => void
+  notifications: HomepageNotification[]
+  setNotifications: (notifications: HomepageNotification[]) => void
+  markNotificationsRead: (ids: number[]) => Promise<void>
+  showNotifications: boolean
+  setShowNotifications: (showNotifications: boolean) => void
   showWidgetsSettings: boolean
   setShowWidgetsSettings: (showWidgetsSettings: boolean) => void
+  showAppsSettings: boolean
+  setShowAppsSettings: (showAppsSettings: boolean) => void
+  showSearch: boolean
+  setShowSearch: (showSearch: boolean) => void
+  setAppLayout: (id: string, change: AppLayoutChange) => Promise<void>
+  resetAppLayout: () => Promise<void>
+  widgetData: Record<string, WidgetData>
+  fetchWidgetData: (id: string, config: WidgetConfig) => Promise<void>
 }
 
 const useHomepageStore = create<HomepageStore>()(
@@ -31,8 +78,66 @@ const useHomepageStore = create<HomepageStore>()(
       set,
       apps: [],
       setApps: (apps: HomepageApp[]) => set({ apps }),
+      notifications: [],
+      setNotifications: (notifications: HomepageNotification[]) => set({ notifications }),
+      // an empty list marks every notification read
+      markNotificationsRead: async (ids: number[]) => {
+        await fetch(`${NOTIFICATIONS_PATH}/read`, {
+          method: 'POST',
+          credentials: 'include',
+          body: JSON.stringify(ids),
+        }).catch(() => {})
+        set({
+          notifications: get().notifications.map((n) =>
+            ids.length === 0 || ids.includes(n.id) ? { ...n, read: true } : n
+          ),
+        })
+      },
+      showNotifications: false,
+      setShowNotifications: (showNotifications: boolean) => set({ showNotifications }),
       showWidgetsSettings: false,
       setShowWidgetsSettings: (showWidgetsSettings: boolean) => set({ showWidgetsSettings }),
+      showAppsSettings: false,
+      setShowAppsSettings: (showAppsSettings: boolean) => set({ showAppsSettings }),
+      showSearch: false,
+      setShowSearch: (showSearch: boolean) => set({ showSearch }),
+      setAppLayout: async (id: string, change: AppLayoutChange) => {
+        const [path, value] = 'pinned' in change
+          ? ['/pin', change.pinned]
+          : 'hidden' in change
+            ? ['/hide', change.hidden]
+            : ['/folder', change.folder]
+        await fetch(path, {
+          method: 'POST',
+          credentials: 'include',
+          body: JSON.stringify([id, value]),
+        }).catch(() => {})
+        set({
+          apps: get().apps.map((app) =>
+            app.id === id ? { ...app, ...change, folder: 'folder' in change ? change.folder || undefined : app.folder } : app
+          ),
+        })
+      },
+      resetAppLayout: async () => {
+        await fetch('/layout', { method: 'DELETE', credentials: 'include' }).catch(() => {})
+        const apps = await fetch('/apps', { credentials: 'include' })
+          .then((res) => res.json())
+          .catch(() => get().apps)
+        set({ apps })
+      },
+      widgetData: {},
+      // on failure, the data last fetched is kept to be shown with the error
+      fetchWidgetData: async (id: string, config: WidgetConfig) => {
+        try {
+          const res = await fetch(config.data_path, { credentials: 'include' })
+          if (!res.ok) throw new Error(`${res.status} ${res.statusText}`)
+          const data = await res.json()
+          set({ widgetData: { ...get().widgetData, [id]: { data, fetched_at: Date.now() } } })
+        } catch (error) {
+          const message = error instanceof Error ? error.message : String(error)
+          set({ widgetData: { ...get().widgetData, [id]: { ...get().widgetData[id], error: message } } })
+        }
+      },
     }),
     {
       name: 'homepage_store', // unique name
@@ -0,0 +1,62 @@
+// Fills in the template of a widget with the data fetched for it, as in
+// Mustache: `{{name}}` is the value of `name`, HTML-escaped, `{{a.b}}` looks
+// into objects and `{{.}}` is the current item. `{{{name}}}` is the value
+// unescaped. `{{#name}}...{{/name}}` repeats for each item of an array, or is
+// shown once if the value is truthy, and `{{^name}}...{{/name}}` is shown if
+// it is falsy or empty.
+const TAG = /\{\{\{\s*([\w.]+)\s*\}\}\}|\{\{\s*([#^/]?)\s*([\w.]+)\s*\}\}/g
+
+const escapeHtml = (s: string) =>
+  s.replace(/[&<>"']/g, (c) => ({ '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;', "'": '&#39;' }[c]!))
+
+// look a name up in the innermost context that has it
+const lookup = (stack: any[], name: string): any => {
+  if (name === '.') return stack[stack.length - 1]
+  const [head, ...rest] = name.split('.')
+  for (let i = stack.length - 1; i >= 0; i--) {
+    const context = stack[i]
+    if (context !== null && typeof context === 'object' && head in context) {
+      return rest.reduce((value, key) => value?.[key], context[head])
+    }
+  }
+  return undefined
+}
+
+const show = (value: any) =>
+  value === undefined || value === null ? '' : typeof value === 'object' ? JSON.stringify(value) : String(value)
+
+const render = (template: string, stack: any[]): string => {
+  const tag = new RegExp(TAG.source, 'g')
+  let out = ''
+  let index = 0
+  let match: RegExpExecArray | null
+  while ((match = tag.exec(template)) !== null) {
+    out += template.slice(index, match.index)
+    index = tag.lastIndex
+    const [, raw, kind, name] = match
+    if (raw) {
+      out += show(lookup(stack, raw))
+    } else if (!kind) {
+      out += escapeHtml(show(lookup(stack, name)))
+    } else if (kind !== '/') {
+      const closing = `{{/${name}}}`
+      const end = template.indexOf(closing, index)
+      if (end === -1) continue
+      const inner = template.slice(index, end)
+      index = end + closing.length
+      tag.lastIndex = index
+      const value = lookup(stack, name)
+      const empty = !value || (Array.isArray(value) && value.length === 0)
+      if (kind === '^') {
+        if (empty) out += render(inner, stack)
+      } else if (!empty) {
+        out += Array.isArray(value)
+          ? value.map((item) => render(inner, [...stack, item])).join('')
+          : render(inner, [...stack, value])
+      }
+    }
+  }
+  return out + template.slice(index)
+}
+
+export const renderTemplate = (template: string, data: any) => render(template, [data])
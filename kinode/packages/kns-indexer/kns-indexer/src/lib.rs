@@ -15,6 +15,9 @@ use std::{
     str::FromStr,
 };
 
+mod resolve;
+mod resolve_worker_lib;
+
 wit_bindgen::generate!({
     path: "target/wit",
     world: "kns-indexer-sys-v0",
@@ -221,6 +224,10 @@ fn main(our: &Address, state: &mut State) -> anyhow::Result<()> {
     // let mut pending_requests: BTreeMap<u64, Vec<IndexerRequest>> = BTreeMap::new();
     let mut pending_notes: BTreeMap<u64, Vec<(kimap::contract::Note, u8)>> = BTreeMap::new();
 
+    // caches resolutions of names and addresses made for other processes
+    let mut resolver =
+        resolve::Resolver::new(state.chain_id, state.contract_address, KIMAP_FIRST_BLOCK);
+
     // if block in state is < current_block, get logs from that part.
     print_to_terminal(2, &format!("syncing old logs from block: {}", last_block));
     fetch_and_process_logs(
@@ -293,8 +300,16 @@ fn main(our: &Address, state: &mut State) -> anyhow::Result<()> {
                 &notes_filter,
                 &mut last_block,
             )?;
+        } else if let Ok(result) =
+            serde_json::from_slice::<resolve_worker_lib::ResolveJobResult>(&body)
+        {
+            // a resolve worker returning what it resolved
+            if source.node() == our.node() && source.package_id() == our.package_id() {
+                resolver.cache(result);
+            }
         } else {
-            let response_body = match serde_json::from_slice(&body)? {
+            let request: IndexerRequest = serde_json::from_slice(&body)?;
+            let response_body = match request {
                 IndexerRequest::NamehashToName(NamehashToNameRequest { ref hash, .. }) => {
                     // TODO: make sure we've seen the whole block, while actually
                     // sending a response to the proper place.
@@ -340,6 +355,16 @@ fn main(our: &Address, state: &mut State) -> anyhow::Result<()> {
                     }
                 }
                 IndexerRequest::GetState(_) => IndexerResponse::GetState(state.clone().into()),
+                IndexerRequest::ResolveName(_) | IndexerRequest::ResolveAddress(_) => {
+                    // resolving can take many calls to the chain, so is left
+                    // to a worker, which responds in our stead
+                    if expects_response.is_some() {
+                        if let Err(e) = resolver.handle(&our, &request) {
+                            println!("failed to resolve: {e}");
+                        }
+                    }
+                    continue;
+                }
             };
 
             if let IndexerResponse::Reset(ResetResult::Success) = response_body {
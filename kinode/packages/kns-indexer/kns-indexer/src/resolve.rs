@@ -0,0 +1,154 @@
+//! Resolution of names to addresses and back, for hypermap entries and ENS names,
+//! so that apps don't each implement their own lookups, caching and retries.
+//!
+//! Results, including names that don't exist, are cached in memory for a while.
+//! Anything not cached is resolved by a resolve worker, which responds to the
+//! request itself, so that calls to the chain never hold up the main loop.
+use crate::kinode::process::kns_indexer::{
+    IndexerRequest, IndexerResponse, NameSource, ResolveError, ResolvedName,
+};
+use crate::resolve_worker_lib::{spawn_resolve_worker, ResolveJob, ResolveJobResult, ResolveKind};
+use alloy_primitives::Address as EthAddress;
+use kinode_process_lib::{eth, Address, Response};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// how long a resolution is cached
+const CACHE_TTL_MS: u64 = 300_000; // 5 minutes
+/// how long a name that doesn't exist is cached, so that it is found soon after it is minted
+const NOT_FOUND_TTL_MS: u64 = 60_000; // 1 minute
+const MAX_CACHE_ENTRIES: usize = 1_000;
+
+pub struct Resolver {
+    chain_id: u64,
+    contract_address: eth::Address,
+    first_block: u64,
+    names: HashMap<String, (Option<ResolvedName>, u64)>,
+    addresses: HashMap<EthAddress, (Vec<ResolvedName>, u64)>,
+    /// the entry of each token-bound account seen, to find entries by their account
+    tbas: HashMap<EthAddress, String>,
+}
+
+impl Resolver {
+    pub fn new(chain_id: u64, contract_address: eth::Address, first_block: u64) -> Self {
+        Self {
+            chain_id,
+            contract_address,
+            first_block,
+            names: HashMap::new(),
+            addresses: HashMap::new(),
+            tbas: HashMap::new(),
+        }
+    }
+
+    /// Handle a `ResolveName` or `ResolveAddress` request: respond from the cache,
+    /// or else hand the request to a resolve worker, which responds in our stead.
+    pub fn handle(&self, our: &Address, request: &IndexerRequest) -> anyhow::Result<()> {
+        let kind = match request {
+            IndexerRequest::ResolveName(name) => {
+                let name = name.to_lowercase();
+                if let Some((resolved, expires)) = self.names.get(&name) {
+                    if now_ms() < *expires {
+                        Response::new()
+                            .body(IndexerResponse::ResolvedName(Ok(resolved.clone())))
+                            .send()?;
+                        return Ok(());
+                    }
+                }
+                ResolveKind::Name(name)
+            }
+            IndexerRequest::ResolveAddress(address) => {
+                let Ok(parsed) = EthAddress::from_str(address) else {
+                    Response::new()
+                        .body(IndexerResponse::ResolvedAddress(Err(
+                            ResolveError::InvalidAddress,
+                        )))
+                        .send()?;
+                    return Ok(());
+                };
+                if let Some((resolved, expires)) = self.addresses.get(&parsed) {
+                    if now_ms() < *expires {
+                        Response::new()
+                            .body(IndexerResponse::ResolvedAddress(Ok(resolved.clone())))
+                            .send()?;
+                        return Ok(());
+                    }
+                }
+                ResolveKind::Address {
+                    address: address.clone(),
+                    tba_of: self.tbas.get(&parsed).cloned(),
+                }
+            }
+            _ => return Err(anyhow::anyhow!("not a resolve request")),
+        };
+        spawn_resolve_worker(
+            our,
+            ResolveJob {
+                chain_id: self.chain_id,
+                contract_address: self.contract_address.to_string(),
+                first_block: self.first_block,
+                kind,
+            },
+        )
+    }
+
+    /// Cache what a resolve worker resolved.
+    pub fn cache(&mut self, result: ResolveJobResult) {
+        match result {
+            ResolveJobResult::Name(name, resolved) => {
+                let ttl_ms = if resolved.is_some() {
+                    CACHE_TTL_MS
+                } else {
+                    NOT_FOUND_TTL_MS
+                };
+                if let Some(ref resolved) = resolved {
+                    self.remember_tba(resolved);
+                }
+                evict_expired(&mut self.names);
+                self.names.insert(name, (resolved, now_ms() + ttl_ms));
+            }
+            ResolveJobResult::Address(address, resolved) => {
+                let Ok(address) = EthAddress::from_str(&address) else {
+                    return;
+                };
+                for entry in &resolved {
+                    self.remember_tba(entry);
+                }
+                evict_expired(&mut self.addresses);
+                self.addresses
+                    .insert(address, (resolved, now_ms() + CACHE_TTL_MS));
+            }
+        }
+    }
+
+    fn remember_tba(&mut self, resolved: &ResolvedName) {
+        if !matches!(resolved.source, NameSource::Hypermap) {
+            return;
+        }
+        let Ok(tba) = EthAddress::from_str(&resolved.address) else {
+            return;
+        };
+        if self.tbas.len() >= MAX_CACHE_ENTRIES {
+            self.tbas.clear();
+        }
+        self.tbas.insert(tba, resolved.name.clone());
+    }
+}
+
+fn evict_expired<K, V>(cache: &mut HashMap<K, (V, u64)>) {
+    if cache.len() < MAX_CACHE_ENTRIES {
+        return;
+    }
+    let now = now_ms();
+    cache.retain(|_, (_, expires)| now < *expires);
+    if cache.len() >= MAX_CACHE_ENTRIES {
+        cache.clear();
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
@@ -0,0 +1 @@
+../../resolve-worker/src/resolve_worker_lib.rs
\ No newline at end of file
@@ -0,0 +1,325 @@
+//! {resolve_id}:kns-indexer:sys
+//! Resolves a name to an address, or an address to its names, for hypermap
+//! entries and ENS names, on behalf of kns-indexer.
+//!
+//! kns-indexer spawns a worker for each resolution not in its cache and hands
+//! it a [`ResolveJob`], inheriting the request it is resolving. The worker
+//! responds to that request directly, returns the result to kns-indexer to
+//! be cached, and exits. Calls that fail to reach the chain are retried a few
+//! times, backing off between tries.
+use crate::kinode::process::kns_indexer::{
+    IndexerRequest, IndexerResponse, NameSource, NamehashToNameRequest, ResolveError, ResolvedName,
+};
+use alloy_primitives::{keccak256, Address as EthAddress, B256};
+use alloy_sol_types::{sol, SolCall};
+use kinode_process_lib::{
+    await_message, call_init, eth, kimap, timer, Address, Message, Request, Response,
+};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+mod resolve_worker_lib;
+use resolve_worker_lib::{ResolveJob, ResolveJobResult, ResolveKind};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "kns-indexer-sys-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
+});
+
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY_MS: u64 = 500;
+const RESOLVE_TIMEOUT: u64 = 10;
+/// blocks of transfer logs asked for at once; halved, down to
+/// `MIN_LOGS_PAGE_BLOCKS`, while the provider refuses to return so many
+const LOGS_PAGE_BLOCKS: u64 = 1_000_000;
+const MIN_LOGS_PAGE_BLOCKS: u64 = 1_000;
+
+const ENS_CHAIN_ID: u64 = 1;
+const ENS_REGISTRY_ADDRESS: &str = "0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+
+sol! {
+    interface EnsRegistry {
+        function resolver(bytes32 node) external view returns (address);
+    }
+
+    interface EnsResolver {
+        function addr(bytes32 node) external view returns (address);
+        function name(bytes32 node) external view returns (string);
+    }
+}
+
+call_init!(init);
+fn init(our: Address) {
+    let Ok(Message::Request {
+        source: parent,
+        body,
+        ..
+    }) = await_message()
+    else {
+        panic!("resolve-worker: got bad init message");
+    };
+    if parent.node() != our.node() || parent.package_id() != our.package_id() {
+        panic!("resolve-worker: got bad init message source");
+    }
+    let Ok(job) = serde_json::from_slice::<ResolveJob>(&body) else {
+        panic!("resolve-worker: got bad init message body");
+    };
+    let Ok(contract_address) = eth::Address::from_str(&job.contract_address) else {
+        panic!("resolve-worker: got bad contract address");
+    };
+    let worker = Worker {
+        parent,
+        chain_id: job.chain_id,
+        contract_address,
+        first_block: job.first_block,
+    };
+
+    let (response, result) = match job.kind {
+        ResolveKind::Name(name) => {
+            let resolved = worker.resolve_name(&name);
+            let result = resolved
+                .clone()
+                .ok()
+                .map(|resolved| ResolveJobResult::Name(name, resolved));
+            (IndexerResponse::ResolvedName(resolved), result)
+        }
+        ResolveKind::Address { address, tba_of } => {
+            let resolved = worker.resolve_address(&address, tba_of);
+            let result = resolved
+                .clone()
+                .ok()
+                .map(|resolved| ResolveJobResult::Address(address, resolved));
+            (IndexerResponse::ResolvedAddress(resolved), result)
+        }
+    };
+
+    let _ = Response::new().body(response).send();
+    // failures to reach the chain are not cached, so the next request tries again
+    if let Some(result) = result {
+        let _ = Request::to(worker.parent.clone()).body(result).send();
+    }
+}
+
+struct Worker {
+    parent: Address,
+    chain_id: u64,
+    contract_address: eth::Address,
+    first_block: u64,
+}
+
+impl Worker {
+    /// Resolve a hypermap entry, or failing that, an ENS name.
+    fn resolve_name(&self, name: &str) -> Result<Option<ResolvedName>, ResolveError> {
+        match self.resolve_hypermap(name)? {
+            Some(resolved) => Ok(Some(resolved)),
+            None => resolve_ens(name),
+        }
+    }
+
+    /// Find the hypermap entries an address owns, or is the token-bound account of,
+    /// and its ENS primary name.
+    fn resolve_address(
+        &self,
+        address: &str,
+        tba_of: Option<String>,
+    ) -> Result<Vec<ResolvedName>, ResolveError> {
+        let address = EthAddress::from_str(address).map_err(|_| ResolveError::InvalidAddress)?;
+
+        // entries once transferred to the address, which may own them still
+        let transfers = eth::Filter::new()
+            .address(self.contract_address)
+            .event("Transfer(address,address,uint256)")
+            .topic2(address.into_word());
+        let mut candidates = BTreeSet::new();
+        for log in self.get_logs(transfers)? {
+            let Some(namehash) = log.topics().get(3) else {
+                continue;
+            };
+            if let Some(name) = self.namehash_to_name(&namehash.to_string()) {
+                candidates.insert(name);
+            }
+        }
+        candidates.extend(tba_of);
+
+        let mut resolved = vec![];
+        for name in candidates {
+            let Some(entry) = self.resolve_hypermap(&name)? else {
+                continue;
+            };
+            let is_tba = EthAddress::from_str(&entry.address).ok() == Some(address);
+            let is_owner = entry
+                .owner
+                .as_ref()
+                .is_some_and(|owner| EthAddress::from_str(owner).ok() == Some(address));
+            if is_tba || is_owner {
+                resolved.push(entry);
+            }
+        }
+        // without ENS, as when no provider for Ethereum mainnet is set, the entries still stand
+        if let Ok(Some(ens)) = reverse_resolve_ens(address) {
+            resolved.push(ens);
+        }
+        Ok(resolved)
+    }
+
+    fn resolve_hypermap(&self, name: &str) -> Result<Option<ResolvedName>, ResolveError> {
+        let kimap = kimap::Kimap::new(
+            eth::Provider::new(self.chain_id, RESOLVE_TIMEOUT),
+            self.contract_address,
+        );
+        // kimap reverts a lookup of an entry that doesn't exist
+        let Ok((tba, owner, _data)) = with_retries(|| kimap.get(name))? else {
+            return Ok(None);
+        };
+        if tba == eth::Address::ZERO {
+            return Ok(None);
+        }
+        Ok(Some(ResolvedName {
+            name: name.to_string(),
+            source: NameSource::Hypermap,
+            address: tba.to_string(),
+            owner: Some(owner.to_string()),
+        }))
+    }
+
+    /// All logs matching `filter` since hypermap was deployed, fetched a page of
+    /// blocks at a time, as providers limit how many blocks or logs they return.
+    fn get_logs(&self, filter: eth::Filter) -> Result<Vec<eth::Log>, ResolveError> {
+        let provider = eth::Provider::new(self.chain_id, RESOLVE_TIMEOUT);
+        let latest_block = match with_retries(|| provider.get_block_number())? {
+            Ok(block) => block,
+            Err(_) => return Err(ResolveError::QueryRefused),
+        };
+        let mut logs = vec![];
+        let mut page = LOGS_PAGE_BLOCKS;
+        let mut from_block = self.first_block;
+        while from_block <= latest_block {
+            let to_block = latest_block.min(from_block + page - 1);
+            let filter = filter.clone().from_block(from_block).to_block(to_block);
+            match with_retries(|| provider.get_logs(&filter))? {
+                Ok(page_logs) => {
+                    logs.extend(page_logs);
+                    from_block = to_block + 1;
+                }
+                Err(_) if page > MIN_LOGS_PAGE_BLOCKS => page /= 2,
+                Err(_) => return Err(ResolveError::QueryRefused),
+            }
+        }
+        Ok(logs)
+    }
+
+    /// The entry kns-indexer has indexed for a namehash, if any.
+    fn namehash_to_name(&self, hash: &str) -> Option<String> {
+        let Ok(Ok(Message::Response { body, .. })) = Request::to(self.parent.clone())
+            .body(IndexerRequest::NamehashToName(NamehashToNameRequest {
+                hash: hash.to_string(),
+                block: 0,
+            }))
+            .send_and_await_response(RESOLVE_TIMEOUT)
+        else {
+            return None;
+        };
+        match body.try_into() {
+            Ok(IndexerResponse::Name(name)) => name,
+            _ => None,
+        }
+    }
+}
+
+fn resolve_ens(name: &str) -> Result<Option<ResolvedName>, ResolveError> {
+    let provider = eth::Provider::new(ENS_CHAIN_ID, RESOLVE_TIMEOUT);
+    let node = ens_namehash(name);
+    let Some(resolver) = ens_resolver(&provider, node)? else {
+        return Ok(None);
+    };
+    let Some(address) = call(&provider, resolver, EnsResolver::addrCall { node })?
+        .map(|r| r._0)
+        .filter(|address| !address.is_zero())
+    else {
+        return Ok(None);
+    };
+    Ok(Some(ResolvedName {
+        name: name.to_string(),
+        source: NameSource::Ens,
+        address: address.to_string(),
+        owner: None,
+    }))
+}
+
+/// The ENS primary name of an address, if it has one that resolves back to it.
+fn reverse_resolve_ens(address: EthAddress) -> Result<Option<ResolvedName>, ResolveError> {
+    let provider = eth::Provider::new(ENS_CHAIN_ID, RESOLVE_TIMEOUT);
+    let node = ens_namehash(&format!("{}.addr.reverse", hex::encode(address)));
+    let Some(resolver) = ens_resolver(&provider, node)? else {
+        return Ok(None);
+    };
+    let Some(name) = call(&provider, resolver, EnsResolver::nameCall { node })?
+        .map(|r| r._0)
+        .filter(|name| !name.is_empty())
+    else {
+        return Ok(None);
+    };
+    // anyone can claim any primary name, so only one that resolves back counts
+    match resolve_ens(&name)? {
+        Some(resolved) if EthAddress::from_str(&resolved.address).ok() == Some(address) => {
+            Ok(Some(resolved))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn ens_resolver(provider: &eth::Provider, node: B256) -> Result<Option<EthAddress>, ResolveError> {
+    let registry = EthAddress::from_str(ENS_REGISTRY_ADDRESS).unwrap();
+    Ok(
+        call(provider, registry, EnsRegistry::resolverCall { node })?
+            .map(|r| r._0)
+            .filter(|resolver| !resolver.is_zero()),
+    )
+}
+
+/// Make a contract call. A revert, as for a name that doesn't exist, is
+/// taken to mean there is nothing to find.
+fn call<C: SolCall>(
+    provider: &eth::Provider,
+    to: EthAddress,
+    call: C,
+) -> Result<Option<C::Return>, ResolveError> {
+    let tx = eth::TransactionRequest::default()
+        .to(to)
+        .input(eth::TransactionInput::new(call.abi_encode().into()));
+    Ok(with_retries(|| provider.call(tx.clone(), None))?
+        .ok()
+        .and_then(|bytes| C::abi_decode_returns(&bytes, false).ok()))
+}
+
+/// Try a call to the chain a few times, waiting on a timer between tries.
+/// An error from the chain itself, such as a revert or a refusal to return
+/// so many logs, is not retried, but returned for the caller to interpret.
+fn with_retries<T>(
+    mut f: impl FnMut() -> Result<T, eth::EthError>,
+) -> Result<Result<T, eth::EthError>, ResolveError> {
+    for attempt in 0..ATTEMPTS {
+        match f() {
+            Ok(t) => return Ok(Ok(t)),
+            Err(e @ eth::EthError::RpcError(_)) => return Ok(Err(e)),
+            Err(eth::EthError::NoRpcForChain) => return Err(ResolveError::ChainUnreachable),
+            Err(_) if attempt + 1 < ATTEMPTS => {
+                let _ = timer::set_and_await_timer(RETRY_DELAY_MS << attempt);
+            }
+            Err(_) => {}
+        }
+    }
+    Err(ResolveError::ChainUnreachable)
+}
+
+/// The EIP-137 namehash of a name. Names are lowercased, but not otherwise
+/// normalized, so names needing full ENS normalization may not resolve.
+fn ens_namehash(name: &str) -> B256 {
+    let mut node = B256::ZERO;
+    for label in name.rsplit('.').filter(|label| !label.is_empty()) {
+        node = keccak256([node.as_slice(), keccak256(label).as_slice()].concat());
+    }
+    node
+}
@@ -0,0 +1,58 @@
+//! Helpers for spawning resolve workers, shared with kns-indexer.
+//! kns-indexer hands each resolution that misses its cache to a worker,
+//! so that calls to the chain never hold up its main loop.
+use crate::kinode::process::kns_indexer::ResolvedName;
+use kinode_process_lib::{our_capabilities, spawn, Address, Capability, OnExit, Request};
+use serde::{Deserialize, Serialize};
+
+/// Sent by kns-indexer to a resolve worker it has spawned, inheriting the
+/// resolve request being handled, so that the worker responds to its sender.
+#[derive(Debug, Serialize, Deserialize, process_macros::SerdeJsonInto)]
+pub struct ResolveJob {
+    pub chain_id: u64,
+    pub contract_address: String,
+    pub first_block: u64,
+    pub kind: ResolveKind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ResolveKind {
+    Name(String),
+    Address {
+        address: String,
+        /// the entry, if any, that the address is known to be the token-bound account of
+        tba_of: Option<String>,
+    },
+}
+
+/// Sent by a resolve worker back to kns-indexer once done, to be cached.
+#[derive(Debug, Serialize, Deserialize, process_macros::SerdeJsonInto)]
+pub enum ResolveJobResult {
+    Name(String, Option<ResolvedName>),
+    Address(String, Vec<ResolvedName>),
+}
+
+/// Spawns a worker process and hands it `job`, inheriting the request being
+/// handled. The worker responds to that request and then exits.
+pub fn spawn_resolve_worker(our: &Address, job: ResolveJob) -> anyhow::Result<()> {
+    let worker_id: u64 = rand::random();
+    let mut capabilities = our_capabilities();
+    // to ask kns-indexer for names, and return what it resolved to be cached
+    capabilities.push(Capability::new(our.clone(), "\"messaging\""));
+    let Ok(worker_process_id) = spawn(
+        Some(&format!("resolve-{worker_id}")),
+        &format!("{}/pkg/resolve-worker.wasm", our.package_id()),
+        OnExit::None,
+        capabilities,
+        vec![],
+        false,
+    ) else {
+        return Err(anyhow::anyhow!("failed to spawn resolve-worker!"));
+    };
+    Request::new()
+        .target((&our.node, worker_process_id))
+        .inherit(true)
+        .body(job)
+        .send()?;
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use crate::kinode::process::kns_indexer::{IndexerRequest, IndexerResponse, ResolvedName};
+use kinode_process_lib::{script, Address, Message, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "kns-indexer-sys-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
+});
+
+script!(init);
+fn init(_our: Address, args: String) -> String {
+    let Some(query) = args.split_whitespace().next() else {
+        return "usage: resolve <name or address>".to_string();
+    };
+    let request = if query.starts_with("0x") {
+        IndexerRequest::ResolveAddress(query.to_string())
+    } else {
+        IndexerRequest::ResolveName(query.to_string())
+    };
+
+    // resolving can take a few calls to the chain, with retries
+    let Ok(Ok(Message::Response { body, .. })) =
+        Request::to(("our", "kns-indexer", "kns-indexer", "sys"))
+            .body(request)
+            .send_and_await_response(60)
+    else {
+        return "failed to get a response from kns-indexer".to_string();
+    };
+    match body.try_into() {
+        Ok(IndexerResponse::ResolvedName(Ok(Some(resolved)))) => format_resolved(&resolved),
+        Ok(IndexerResponse::ResolvedName(Ok(None))) => format!("{query}: not found"),
+        Ok(IndexerResponse::ResolvedAddress(Ok(resolved))) if resolved.is_empty() => {
+            format!("{query}: no names found")
+        }
+        Ok(IndexerResponse::ResolvedAddress(Ok(resolved))) => resolved
+            .iter()
+            .map(format_resolved)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Ok(IndexerResponse::ResolvedName(Err(e)))
+        | Ok(IndexerResponse::ResolvedAddress(Err(e))) => format!("failed to resolve: {e:?}"),
+        _ => "failed to deserialize response".to_string(),
+    }
+}
+
+fn format_resolved(resolved: &ResolvedName) -> String {
+    match &resolved.owner {
+        Some(owner) => format!(
+            "{} ({:?}): {}, owned by {owner}",
+            resolved.name, resolved.source, resolved.address
+        ),
+        None => format!(
+            "{} ({:?}): {}",
+            resolved.name, resolved.source, resolved.address
+        ),
+    }
+}
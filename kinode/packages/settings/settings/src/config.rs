@@ -0,0 +1,129 @@
+//! The configuration of our node a user can tune, exported as one JSON
+//! document, to be imported onto another node to set it up the same way.
+//!
+//! Secrets are left out: the SMTP password of the notification settings, and
+//! the backup made by a scheduled auto-backup, passphrase and all. Importing
+//! keeps those the other node has.
+use crate::kinode::process::settings::{
+    AppStoreTelemetry, MaintenanceTask, NotifySettings, SettingsError,
+};
+use crate::{
+    app_store_telemetry, eth_config_action, notify_action, schedule, set_stylesheet, SettingsState,
+};
+use kinode_process_lib::eth;
+use serde::{Deserialize, Serialize};
+
+/// bumped when a field changes meaning, so that documents from newer nodes
+/// are refused rather than misread
+const CONFIG_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NodeConfig {
+    pub version: u32,
+    pub eth_providers: eth::SavedConfigs,
+    pub eth_access_settings: eth::AccessSettings,
+    pub stylesheet: Option<String>,
+    pub notify_settings: Option<NotifySettings>,
+    /// missing from documents exported before it was kept
+    #[serde(default)]
+    pub app_store_telemetry: Option<AppStoreTelemetry>,
+    /// the maintenance tasks run on a schedule, and their cron specs
+    pub schedule: Vec<(MaintenanceTask, String)>,
+}
+
+/// Export our configuration, as last fetched.
+pub fn export(state: &SettingsState) -> NodeConfig {
+    NodeConfig {
+        version: CONFIG_VERSION,
+        eth_providers: state.eth_rpc_providers.clone().unwrap_or_default(),
+        eth_access_settings: state
+            .eth_rpc_access_settings
+            .clone()
+            .unwrap_or(eth::AccessSettings {
+                public: false,
+                allow: Default::default(),
+                deny: Default::default(),
+            }),
+        stylesheet: state.stylesheet.clone(),
+        notify_settings: state.notify_settings.clone().map(|mut settings| {
+            if let Some(email) = settings.email.as_mut() {
+                email.password = String::new();
+            }
+            settings
+        }),
+        app_store_telemetry: state.app_store_telemetry.clone(),
+        schedule: state
+            .schedule
+            .tasks
+            .iter()
+            .map(|t| (t.task, t.cron.clone()))
+            .collect(),
+    }
+}
+
+/// Replace our configuration with an exported one. It is checked before any
+/// of it is applied, but if applying a part fails, the parts before it stay.
+pub fn import(state: &mut SettingsState, config: NodeConfig) -> Result<(), SettingsError> {
+    if config.version > CONFIG_VERSION
+        || config
+            .schedule
+            .iter()
+            .any(|(_, cron)| schedule::next_run(cron, schedule::now()).is_none())
+    {
+        return Err(SettingsError::MalformedRequest);
+    }
+
+    // eth: providers, then who may use them through us
+    eth_config_action(eth::EthConfigAction::SetProviders(config.eth_providers))?;
+    let current = state.eth_rpc_access_settings.take();
+    let access = config.eth_access_settings;
+    eth_config_action(if access.public {
+        eth::EthConfigAction::SetPublic
+    } else {
+        eth::EthConfigAction::SetPrivate
+    })?;
+    let (allowed, denied) = current
+        .map(|current| (current.allow, current.deny))
+        .unwrap_or_default();
+    for node in allowed.difference(&access.allow) {
+        eth_config_action(eth::EthConfigAction::UnallowNode(node.clone()))?;
+    }
+    for node in access.allow.difference(&allowed) {
+        eth_config_action(eth::EthConfigAction::AllowNode(node.clone()))?;
+    }
+    for node in denied.difference(&access.deny) {
+        eth_config_action(eth::EthConfigAction::UndenyNode(node.clone()))?;
+    }
+    for node in access.deny.difference(&denied) {
+        eth_config_action(eth::EthConfigAction::DenyNode(node.clone()))?;
+    }
+
+    if let Some(stylesheet) = config.stylesheet {
+        set_stylesheet(state, stylesheet)?;
+    }
+
+    // the empty SMTP password keeps the one we have
+    if let Some(settings) = config.notify_settings {
+        notify_action(serde_json::json!({ "SetSettings": settings }))?;
+    }
+
+    if let Some(settings) = config.app_store_telemetry {
+        app_store_telemetry(serde_json::json!({ "SetTelemetrySettings": settings }))?;
+    }
+
+    let scheduled: Vec<MaintenanceTask> = state.schedule.tasks.iter().map(|t| t.task).collect();
+    for task in scheduled {
+        if !config.schedule.iter().any(|(t, _)| *t == task) {
+            state.schedule.remove(task);
+        }
+    }
+    for (task, cron) in config.schedule {
+        match state.schedule.set(task, cron, None) {
+            // without a backup to make, an auto-backup can't be scheduled until
+            // one is given here
+            Err(SettingsError::MalformedRequest) if task == MaintenanceTask::AutoBackup => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,12 @@
 use crate::kinode::process::settings::{
-    Direct, EthConfigRequest as SettingsEthConfigAction, HiRequest, Identity as SettingsIdentity,
-    NodeOrRpcUrl as SettingsNodeOrRpcUrl, NodeRouting as SettingsNodeRouting,
-    Request as SettingsRequest, Response as SettingsResponse, SettingsData, SettingsError,
+    AccessLogEntry, AccessLogQuery, ApiTokenInfo, ApiTokenRequest, AppStoreTelemetry, BackupInfo,
+    ChannelTest, CreateBackupRequest, DiagnosticCheck, Direct, DomainInfo, DomainRequest,
+    EthConfigRequest as SettingsEthConfigAction, HiRequest, HttpProxyInfo, HttpProxyRequest,
+    Identity as SettingsIdentity, MaintenanceTask, NodeOrRpcUrl as SettingsNodeOrRpcUrl,
+    NodeRouting as SettingsNodeRouting, NotifySettings, PackageStorage, ProcessMetrics,
+    ProcessPrintout, ProviderTest, Request as SettingsRequest, Response as SettingsResponse,
+    RestoreBackupRequest, RoutingMode, ScheduleRequest, SettingsData, SettingsError,
+    WalletApproval,
 };
 use kinode_process_lib::{
     await_message, call_init, eth, get_blob, get_capability, homepage, http, kernel_types, kimap,
@@ -9,9 +14,24 @@ use kinode_process_lib::{
     SendError, SendErrorKind,
 };
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, vec};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    vec,
+};
+
+mod config;
+mod routing;
+mod schedule;
+mod search;
+mod search_api;
+mod widget;
 
 const ICON: &str = include_str!("icon");
+/// how long a backup may take to make
+const BACKUP_TIMEOUT: u64 = 60 * 60;
+/// how long the user has to approve a transaction in the wallet
+const SIGN_TIMEOUT: u64 = 5 * 60;
 
 wit_bindgen::generate!({
     path: "target/wit",
@@ -20,7 +40,7 @@ wit_bindgen::generate!({
     additional_derives: [serde::Deserialize, serde::Serialize],
 });
 
-/// never gets persisted
+/// never gets persisted, but for the schedule, which saves itself
 #[derive(Debug, Serialize, Deserialize)]
 struct SettingsState {
     pub our: Address,
@@ -28,7 +48,20 @@ struct SettingsState {
     pub diagnostics: Option<String>,
     pub eth_rpc_providers: Option<eth::SavedConfigs>,
     pub eth_rpc_access_settings: Option<eth::AccessSettings>,
+    pub eth_rpc_provider_health: Option<Vec<ProviderHealth>>,
+    pub eth_rpc_provider_tests: Option<Vec<ProviderTest>>,
+    pub wallet_address: Option<WalletAddress>,
+    /// signatures processes asked the wallet for, as given by wallet:distro:sys
+    pub wallet_pending: Option<Vec<serde_json::Value>>,
+    pub backups: Option<Vec<BackupInfo>>,
+    /// how the last backup asked for went, as reported by backup:distro:sys
+    pub backup_status: Option<BackupStatus>,
+    pub notify_settings: Option<NotifySettings>,
+    /// install telemetry settings, as kept by main:app-store:sys
+    pub app_store_telemetry: Option<AppStoreTelemetry>,
+    pub schedule: schedule::Schedule,
     pub process_map: Option<kernel_types::ProcessMap>,
+    pub process_metrics: Option<Vec<ProcessMetrics>>,
     pub stylesheet: Option<String>,
     pub our_tba: eth::Address,
     pub our_owner: eth::Address,
@@ -37,6 +70,140 @@ struct SettingsState {
     pub ip: Option<eth::Bytes>,       // if direct
     pub ws_port: Option<eth::Bytes>,  // sometimes, if direct
     pub tcp_port: Option<eth::Bytes>, // sometimes, if direct
+    /// how the last routing change asked for went
+    pub routing_change: Option<RoutingChange>,
+    /// routers to go online with once the routing change is confirmed, none if direct
+    #[serde(skip)]
+    pending_routers: Option<Vec<String>>,
+    /// ID of the routing change transaction, as given by eth:distro:sys
+    #[serde(skip)]
+    routing_tx: Option<u64>,
+}
+
+/// a provider's health, as given by eth:distro:sys
+#[derive(Debug, Serialize, Deserialize)]
+struct ProviderHealth {
+    pub chain_id: u64,
+    pub provider: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+    pub latency_ms: Option<u64>,
+    pub block_number: Option<u64>,
+    pub blocks_behind: Option<u64>,
+    pub demoted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+enum EthProviderHealthResponse {
+    ProviderHealth(Vec<ProviderHealth>),
+}
+
+/// the net:distro:sys actions not yet in process_lib
+#[derive(Debug, Serialize)]
+enum NetExtraAction {
+    RunDiagnostics,
+    SetRouters(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+enum NetExtraResponse {
+    DiagnosticsReport(Vec<DiagnosticCheck>),
+    SetRouters(Result<(), String>),
+}
+
+#[derive(Debug, Deserialize)]
+enum EthProviderTestResponse {
+    ProviderTest(ProviderTest),
+    ProviderTests(Vec<ProviderTest>),
+}
+
+/// the key wallet:distro:sys signs with
+#[derive(Debug, Serialize, Deserialize)]
+struct WalletAddress {
+    pub address: String,
+    pub external: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum BackupStatus {
+    Started,
+    /// a stage of the backup, e.g. `{"Database":{"db":"chess:chess:sys/games","done":2,"total":5}}`
+    InProgress(serde_json::Value),
+    Created(BackupInfo),
+    Failed(serde_json::Value),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum RoutingChange {
+    /// waiting for the user to approve the transaction in the wallet
+    Signing,
+    /// sent, under this hash
+    Pending(String),
+    /// confirmed under this hash, and saved for the next boot
+    Confirmed(String),
+    Failed(serde_json::Value),
+}
+
+/// sent by eth:distro:sys as a transaction we sent progresses
+#[derive(Debug, Deserialize)]
+struct TxUpdate {
+    pub id: u64,
+    pub status: serde_json::Value,
+}
+
+/// sent by backup:distro:sys while it makes a backup
+#[derive(Debug, Deserialize)]
+struct BackupProgress {
+    pub stage: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+enum BackupResponse {
+    Ok,
+    Created(BackupInfo),
+    Backups(Vec<BackupInfo>),
+    Err(serde_json::Value),
+}
+
+#[derive(Debug, Deserialize)]
+enum NotifyResponse {
+    Ok,
+    Settings(NotifySettings),
+    Tested(Vec<ChannelTest>),
+    Err(serde_json::Value),
+}
+
+/// a process's metrics, as given by kernel:distro:sys
+#[derive(Debug, Deserialize)]
+struct KernelProcessMetrics {
+    pub queue_depth: u64,
+    pub queue_limit: Option<u64>,
+    pub dropped_messages: u64,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub last_activity: Option<u64>,
+    pub memory_bytes: Option<u64>,
+    pub suspended: bool,
+}
+
+#[derive(Debug, Deserialize)]
+enum TerminalResponse {
+    Printouts(Vec<ProcessPrintout>),
+}
+
+#[derive(Debug, Deserialize)]
+enum VfsStorageResponse {
+    StorageReport(Vec<PackageStorage>),
+}
+
+#[derive(Debug, Deserialize)]
+enum WalletResponse {
+    Ok,
+    Address(Option<WalletAddress>),
+    Pending(Vec<serde_json::Value>),
+    SignedTransaction(String),
+    Err(serde_json::Value),
 }
 
 impl SettingsState {
@@ -47,7 +214,17 @@ impl SettingsState {
             diagnostics: None,
             eth_rpc_providers: None,
             eth_rpc_access_settings: None,
+            eth_rpc_provider_health: None,
+            eth_rpc_provider_tests: None,
+            wallet_address: None,
+            wallet_pending: None,
+            backups: None,
+            backup_status: None,
+            notify_settings: None,
+            app_store_telemetry: None,
+            schedule: schedule::Schedule::load(),
             process_map: None,
+            process_metrics: None,
             stylesheet: None,
             our_tba: eth::Address::ZERO,
             our_owner: eth::Address::ZERO,
@@ -56,6 +233,9 @@ impl SettingsState {
             ip: None,
             ws_port: None,
             tcp_port: None,
+            routing_change: None,
+            pending_routers: None,
+            routing_tx: None,
         }
     }
 
@@ -74,7 +254,11 @@ impl SettingsState {
     /// - get Identity struct from net:distro:sys
     /// - get ETH RPC providers from eth:distro:sys
     /// - get ETH RPC access settings from eth:distro:sys
-    /// - get running processes from kernel:distro:sys
+    /// - get wallet key and pending signatures from wallet:distro:sys
+    /// - get backups from backup:distro:sys
+    /// - get notification settings from notify:distro:sys
+    /// - get install telemetry settings from main:app-store:sys
+    /// - get running processes and their metrics from kernel:distro:sys
     fn fetch(&mut self) -> anyhow::Result<()> {
         // identity
         let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "net", "distro", "sys"))
@@ -127,6 +311,66 @@ impl SettingsState {
         };
         self.eth_rpc_access_settings = Some(access_settings);
 
+        // eth rpc provider health
+        let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "eth", "distro", "sys"))
+            .body(serde_json::to_vec(&serde_json::json!("GetProviderHealth")).unwrap())
+            .send_and_await_response(5)
+        else {
+            return Err(anyhow::anyhow!("failed to get provider health from eth"));
+        };
+        let Ok(EthProviderHealthResponse::ProviderHealth(health)) = serde_json::from_slice(&body)
+        else {
+            return Err(anyhow::anyhow!("got malformed response from eth"));
+        };
+        self.eth_rpc_provider_health = Some(health);
+
+        // eth rpc provider benchmarks
+        let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "eth", "distro", "sys"))
+            .body(serde_json::to_vec(&serde_json::json!("GetProviderTests")).unwrap())
+            .send_and_await_response(5)
+        else {
+            return Err(anyhow::anyhow!(
+                "failed to get provider benchmarks from eth"
+            ));
+        };
+        let Ok(EthProviderTestResponse::ProviderTests(tests)) = serde_json::from_slice(&body)
+        else {
+            return Err(anyhow::anyhow!("got malformed response from eth"));
+        };
+        self.eth_rpc_provider_tests = Some(tests);
+
+        // wallet
+        let Ok(WalletResponse::Address(address)) = wallet_action(serde_json::json!("GetAddress"))
+        else {
+            return Err(anyhow::anyhow!("failed to get address from wallet"));
+        };
+        self.wallet_address = address;
+        let Ok(WalletResponse::Pending(pending)) = wallet_action(serde_json::json!("ListPending"))
+        else {
+            return Err(anyhow::anyhow!(
+                "failed to get pending signatures from wallet"
+            ));
+        };
+        self.wallet_pending = Some(pending);
+
+        // backups
+        let Ok(BackupResponse::Backups(backups)) = backup_action(serde_json::json!("List")) else {
+            return Err(anyhow::anyhow!("failed to get backups from backup"));
+        };
+        self.backups = Some(backups);
+
+        // notifications
+        let Ok(NotifyResponse::Settings(settings)) =
+            notify_action(serde_json::json!("GetSettings"))
+        else {
+            return Err(anyhow::anyhow!("failed to get settings from notify"));
+        };
+        self.notify_settings = Some(settings);
+
+        // install telemetry, left out if the App Store is not running
+        self.app_store_telemetry =
+            app_store_telemetry(serde_json::json!("GetTelemetrySettings")).ok();
+
         // running processes
         let Ok(Ok(Message::Response { body, .. })) =
             Request::to(("our", "kernel", "distro", "sys"))
@@ -149,6 +393,10 @@ impl SettingsState {
             return Err(anyhow::anyhow!("got malformed response from kernel"));
         };
         self.process_map = Some(process_map);
+        let Ok(metrics) = process_metrics() else {
+            return Err(anyhow::anyhow!("failed to get process metrics from kernel"));
+        };
+        self.process_metrics = Some(metrics);
 
         // stylesheet
         if let Ok(bytes) = (kinode_process_lib::vfs::File {
@@ -188,9 +436,6 @@ impl SettingsState {
         };
         self.tcp_port = bytes;
 
-        // update homepage widget
-        homepage::add_to_homepage("Settings", Some(ICON), Some("/"), Some(&make_widget(self)));
-
         Ok(())
     }
 }
@@ -214,18 +459,23 @@ fn initialize(our: Address) {
         .unwrap();
     http_server.secure_bind_http_path("/ask").unwrap();
     http_server.secure_bind_ws_path("/").unwrap();
-    // insecure to allow widget to call refresh
+    // not on our subdomain, for the homepage to fetch our widget's data
     http_server
-        .bind_http_path("/refresh", http::server::HttpBindingConfig::default())
+        .bind_http_path("/widget", http::server::HttpBindingConfig::default())
         .unwrap();
 
+    homepage::add_to_homepage("Settings", Some(ICON), Some("/"), None);
+    widget::register();
+    search_api::register_search_provider(&state.our, "Settings");
+
     // populate state
-    // this will add ourselves to the homepage
     if let Err(e) = state.fetch() {
         println!("failed to fetch settings: {e}");
-        homepage::add_to_homepage("Settings", Some(ICON), Some("/"), None);
     }
 
+    // timers don't outlive the node, so set the next one again
+    state.schedule.set_timer();
+
     main_loop(&mut state, &mut http_server);
 }
 
@@ -234,6 +484,20 @@ fn main_loop(state: &mut SettingsState, http_server: &mut http::server::HttpServ
         match await_message() {
             Err(send_error) => {
                 println!("got send error: {send_error:?}");
+                if send_error.target.process == "backup:distro:sys" {
+                    state.backup_status = Some(BackupStatus::Failed(serde_json::json!("Timeout")));
+                    if state.schedule.backup_running {
+                        state.schedule.backup_running = false;
+                        state
+                            .schedule
+                            .record(MaintenanceTask::AutoBackup, Err("timed out".to_string()));
+                    }
+                    state.ws_update(http_server);
+                } else if send_error.target.process == "wallet:distro:sys" {
+                    state.routing_change =
+                        Some(RoutingChange::Failed(serde_json::json!("Timeout")));
+                    state.ws_update(http_server);
+                }
                 continue;
             }
             Ok(Message::Request {
@@ -245,6 +509,18 @@ fn main_loop(state: &mut SettingsState, http_server: &mut http::server::HttpServ
                 if source.node() != state.our.node {
                     continue; // ignore messages from other nodes
                 }
+                if source.process == "homepage:homepage:sys" {
+                    // a search from the homepage's launcher, answered in its own format
+                    if let Ok(search_api::SearchRequest::Search(query)) =
+                        serde_json::from_slice(&body)
+                    {
+                        Response::new()
+                            .body(serde_json::to_vec(&search::search(&query)).unwrap())
+                            .send()
+                            .unwrap();
+                    }
+                    continue;
+                }
                 let response = handle_request(&source, &body, state, http_server);
                 state.ws_update(http_server);
                 if expects_response.is_some() {
@@ -254,7 +530,53 @@ fn main_loop(state: &mut SettingsState, http_server: &mut http::server::HttpServ
                         .unwrap();
                 }
             }
-            _ => continue, // ignore responses
+            // the end of a backup started by CreateBackup
+            Ok(Message::Response { source, body, .. }) if source.process == "backup:distro:sys" => {
+                state.backup_status = match serde_json::from_slice::<BackupResponse>(&body) {
+                    Ok(BackupResponse::Created(info)) => Some(BackupStatus::Created(info)),
+                    Ok(BackupResponse::Err(e)) => Some(BackupStatus::Failed(e)),
+                    _ => Some(BackupStatus::Failed(serde_json::json!("MalformedResponse"))),
+                };
+                if state.schedule.backup_running {
+                    state.schedule.backup_running = false;
+                    let result = match &state.backup_status {
+                        Some(BackupStatus::Failed(e)) => Err(e.to_string()),
+                        _ => Ok(()),
+                    };
+                    state.schedule.record(MaintenanceTask::AutoBackup, result);
+                }
+                if let Ok(BackupResponse::Backups(backups)) =
+                    backup_action(serde_json::json!("List"))
+                {
+                    state.backups = Some(backups);
+                }
+                state.ws_update(http_server);
+            }
+            // a timer set by our schedule
+            Ok(Message::Response { source, .. }) if source.process == "timer:distro:sys" => {
+                run_scheduled_tasks(state);
+                state.ws_update(http_server);
+            }
+            // the signature of a routing change started by ChangeRouting
+            Ok(Message::Response { source, body, .. }) if source.process == "wallet:distro:sys" => {
+                state.routing_change = match serde_json::from_slice::<WalletResponse>(&body) {
+                    Ok(WalletResponse::SignedTransaction(raw)) => {
+                        match routing::send_transaction(raw) {
+                            Ok((id, hash)) => {
+                                state.routing_tx = Some(id);
+                                Some(RoutingChange::Pending(hash))
+                            }
+                            Err(e) => Some(RoutingChange::Failed(serde_json::json!(e))),
+                        }
+                    }
+                    Ok(WalletResponse::Err(e)) => Some(RoutingChange::Failed(e)),
+                    _ => Some(RoutingChange::Failed(serde_json::json!(
+                        "MalformedResponse"
+                    ))),
+                };
+                state.ws_update(http_server);
+            }
+            _ => continue, // ignore other responses
         }
     }
 }
@@ -298,6 +620,18 @@ fn handle_request(
             },
         );
         Ok(None)
+    } else if source.process == "backup:distro:sys" {
+        let progress = serde_json::from_slice::<BackupProgress>(body)
+            .map_err(|_| SettingsError::MalformedRequest)?;
+        state.backup_status = Some(BackupStatus::InProgress(progress.stage));
+        Ok(None)
+    } else if source.process == "eth:distro:sys" {
+        let update = serde_json::from_slice::<TxUpdate>(body)
+            .map_err(|_| SettingsError::MalformedRequest)?;
+        if state.routing_tx == Some(update.id) {
+            handle_routing_update(state, update.status);
+        }
+        Ok(None)
     } else {
         let settings_request = serde_json::from_slice::<SettingsRequest>(body)
             .map_err(|_| SettingsError::MalformedRequest)?;
@@ -311,9 +645,16 @@ fn handle_http_request(
     http_request: &http::server::IncomingHttpRequest,
 ) -> anyhow::Result<(http::server::HttpResponse, Option<LazyLoadBlob>)> {
     if let Ok(path) = http_request.path() {
-        if &path == "/refresh" {
+        if &path == "/widget" {
             state.fetch()?;
-            return Ok((http::server::HttpResponse::new(http::StatusCode::OK), None));
+            return Ok((
+                http::server::HttpResponse::new(http::StatusCode::OK)
+                    .header("Content-Type", "application/json"),
+                Some(LazyLoadBlob::new(
+                    Some("application/json"),
+                    serde_json::to_vec(&widget::data(state))?,
+                )),
+            ));
         }
     }
     match http_request.method()?.as_str() {
@@ -430,27 +771,7 @@ fn handle_settings_request(
         }
         SettingsRequest::EthConfig(settings_eth_config_request) => {
             // convert SettingsEthConfigRequest to EthConfigRequest
-            let action = eth_config_convert(settings_eth_config_request)?;
-            match Request::to(("our", "eth", "distro", "sys"))
-                .body(serde_json::to_vec(&action).unwrap())
-                .send_and_await_response(30)
-                .unwrap()
-            {
-                Ok(msg) => match serde_json::from_slice::<eth::EthConfigResponse>(msg.body()) {
-                    Ok(eth::EthConfigResponse::PermissionDenied) => {
-                        return Err(SettingsError::KernelNonresponsive);
-                    }
-                    Ok(other) => {
-                        println!("eth config action succeeded: {other:?}");
-                    }
-                    Err(_) => {
-                        return Err(SettingsError::KernelNonresponsive);
-                    }
-                },
-                Err(_) => {
-                    return Err(SettingsError::KernelNonresponsive);
-                }
-            }
+            eth_config_action(eth_config_convert(settings_eth_config_request)?)?;
         }
         SettingsRequest::Shutdown => {
             // shutdown the node IMMEDIATELY!
@@ -475,7 +796,7 @@ fn handle_settings_request(
         SettingsRequest::KillProcess(pid_str) => {
             // kill a process
             let Ok(pid) = pid_str.parse::<ProcessId>() else {
-                return SettingsResponse::Err(SettingsError::MalformedRequest);
+                return Err(SettingsError::MalformedRequest);
             };
             if let Err(_) = Request::to(("our", "kernel", "distro", "sys"))
                 .body(serde_json::to_vec(&kernel_types::KernelCommand::KillProcess(pid)).unwrap())
@@ -486,34 +807,649 @@ fn handle_settings_request(
             }
         }
         SettingsRequest::SetStylesheet(stylesheet) => {
-            let Ok(()) = kinode_process_lib::vfs::File {
-                path: "/homepage:sys/pkg/kinode.css".to_string(),
-                timeout: 5,
+            set_stylesheet(state, stylesheet)?;
+            return SettingsResponse::Ok(None);
+        }
+        SettingsRequest::CreateApiToken(ApiTokenRequest {
+            name,
+            scopes,
+            read_only,
+        }) => {
+            let blob = http_server_action(serde_json::json!({
+                "CreateApiToken": {
+                    "name": name,
+                    "scopes": scopes,
+                    "read_only": read_only,
+                }
+            }))?
+            .ok_or(SettingsError::KernelNonresponsive)?;
+            let token =
+                String::from_utf8(blob.bytes).map_err(|_| SettingsError::KernelNonresponsive)?;
+            return SettingsResponse::Ok(Some(SettingsData::ApiToken(token)));
+        }
+        SettingsRequest::RevokeApiToken(name) => {
+            http_server_action(serde_json::json!({ "RevokeApiToken": { "name": name } }))?;
+            return SettingsResponse::Ok(None);
+        }
+        SettingsRequest::ListApiTokens => {
+            let blob = http_server_action(serde_json::json!("ListApiTokens"))?
+                .ok_or(SettingsError::KernelNonresponsive)?;
+            let tokens = serde_json::from_slice::<Vec<ApiTokenInfo>>(&blob.bytes)
+                .map_err(|_| SettingsError::KernelNonresponsive)?;
+            return SettingsResponse::Ok(Some(SettingsData::ApiTokens(tokens)));
+        }
+        SettingsRequest::RotateJwtSecret => {
+            http_server_action(serde_json::json!("RotateJwtSecret"))?;
+            return SettingsResponse::Ok(None);
+        }
+        SettingsRequest::SetDomain(DomainRequest {
+            host,
+            process,
+            certificate,
+        }) => {
+            let Ok(process) = process.parse::<ProcessId>() else {
+                return Err(SettingsError::MalformedRequest);
+            };
+            http_server_action(serde_json::json!({
+                "SetDomain": {
+                    "host": host,
+                    "process": process,
+                    "certificate": certificate,
+                }
+            }))?;
+            return SettingsResponse::Ok(None);
+        }
+        SettingsRequest::RemoveDomain(host) => {
+            http_server_action(serde_json::json!({ "RemoveDomain": { "host": host } }))?;
+            return SettingsResponse::Ok(None);
+        }
+        SettingsRequest::ListDomains => {
+            let blob = http_server_action(serde_json::json!("ListDomains"))?
+                .ok_or(SettingsError::KernelNonresponsive)?;
+            let domains = serde_json::from_slice::<Vec<DomainInfo>>(&blob.bytes)
+                .map_err(|_| SettingsError::KernelNonresponsive)?;
+            return SettingsResponse::Ok(Some(SettingsData::Domains(domains)));
+        }
+        SettingsRequest::QueryAccessLog(AccessLogQuery {
+            since,
+            path_prefix,
+            limit,
+        }) => {
+            let blob = http_server_action(serde_json::json!({
+                "QueryAccessLog": {
+                    "since": since,
+                    "path_prefix": path_prefix,
+                    "limit": limit,
+                }
+            }))?
+            .ok_or(SettingsError::KernelNonresponsive)?;
+            let entries = serde_json::from_slice::<Vec<AccessLogEntry>>(&blob.bytes)
+                .map_err(|_| SettingsError::KernelNonresponsive)?;
+            return SettingsResponse::Ok(Some(SettingsData::AccessLog(entries)));
+        }
+        SettingsRequest::SetHttpProxy(HttpProxyRequest { process, url }) => {
+            let process = match process {
+                Some(process) => match process.parse::<ProcessId>() {
+                    Ok(process) => Some(process),
+                    Err(_) => return Err(SettingsError::MalformedRequest),
+                },
+                None => None,
+            };
+            let proxies = http_client_action(serde_json::json!({
+                "SetProxy": { "process": process, "url": url }
+            }))?;
+            return SettingsResponse::Ok(Some(SettingsData::HttpProxies(proxies)));
+        }
+        SettingsRequest::ListHttpProxies => {
+            let proxies = http_client_action(serde_json::json!("ListProxies"))?;
+            return SettingsResponse::Ok(Some(SettingsData::HttpProxies(proxies)));
+        }
+        SettingsRequest::WalletGenerateKey => {
+            wallet_action(serde_json::json!("GenerateKey"))?;
+        }
+        SettingsRequest::WalletImportKey(private_key) => {
+            wallet_action(serde_json::json!({ "ImportKey": private_key }))?;
+        }
+        SettingsRequest::WalletSetExternal(address) => {
+            wallet_action(serde_json::json!({ "SetExternal": address }))?;
+        }
+        SettingsRequest::WalletRemoveKey => {
+            wallet_action(serde_json::json!("RemoveKey"))?;
+        }
+        SettingsRequest::WalletApprove(WalletApproval { id, signature }) => {
+            wallet_action(serde_json::json!({
+                "Approve": { "id": id, "signature": signature }
+            }))?;
+        }
+        SettingsRequest::WalletDeny(id) => {
+            wallet_action(serde_json::json!({ "Deny": id }))?;
+        }
+        SettingsRequest::GetStorageReport => {
+            // walks every drive and database, so may take a while on a large node
+            let Ok(Ok(message)) = Request::to(("our", "vfs", "distro", "sys"))
+                .body(
+                    serde_json::to_vec(&serde_json::json!({
+                        "path": "/",
+                        "action": "StorageReport",
+                    }))
+                    .unwrap(),
+                )
+                .send_and_await_response(60)
+            else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            let Ok(VfsStorageResponse::StorageReport(report)) =
+                serde_json::from_slice(message.body())
+            else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            return SettingsResponse::Ok(Some(SettingsData::StorageReport(report)));
+        }
+        SettingsRequest::SuspendProcess(pid_str) => {
+            let Ok(pid) = pid_str.parse::<ProcessId>() else {
+                return Err(SettingsError::MalformedRequest);
+            };
+            kernel_command(serde_json::json!({ "SuspendProcess": pid }))?;
+        }
+        SettingsRequest::ResumeProcess(pid_str) => {
+            let Ok(pid) = pid_str.parse::<ProcessId>() else {
+                return Err(SettingsError::MalformedRequest);
+            };
+            kernel_command(serde_json::json!({ "ResumeProcess": pid }))?;
+        }
+        SettingsRequest::RestartProcess(pid_str) => {
+            let Ok(pid) = pid_str.parse::<ProcessId>() else {
+                return Err(SettingsError::MalformedRequest);
+            };
+            restart_process(state, &pid)?;
+        }
+        SettingsRequest::RestartPackage(package_id) => {
+            let Some(ref process_map) = state.process_map else {
+                return Err(SettingsError::StateFetchFailed);
+            };
+            // runtime processes have no Wasm to restart from
+            let mut pids: Vec<ProcessId> = process_map
+                .iter()
+                .filter(|(pid, process)| {
+                    format!("{}:{}", pid.package(), pid.publisher()) == package_id
+                        && !process.wasm_bytes_handle.is_empty()
+                })
+                .map(|(pid, _)| pid.clone())
+                .collect();
+            if pids.is_empty() {
+                return Err(SettingsError::ProcessNotRunning);
             }
-            .write(stylesheet.as_bytes()) else {
-                return SettingsResponse::Err(SettingsError::KernelNonresponsive);
+            // restarting ourselves ends this request, so do so last
+            pids.sort_by_key(|pid| *pid == state.our.process);
+            for pid in pids {
+                restart_process(state, &pid)?;
+            }
+        }
+        SettingsRequest::GetProcessMetrics => {
+            return SettingsResponse::Ok(Some(SettingsData::ProcessMetrics(process_metrics()?)));
+        }
+        SettingsRequest::GetProcessLogs(pid_str) => {
+            let Ok(pid) = pid_str.parse::<ProcessId>() else {
+                return Err(SettingsError::MalformedRequest);
+            };
+            let Ok(Ok(message)) = Request::to(("our", "terminal", "distro", "sys"))
+                .body(serde_json::to_vec(&serde_json::json!({ "Printouts": pid })).unwrap())
+                .send_and_await_response(5)
+            else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            let Ok(TerminalResponse::Printouts(printouts)) = serde_json::from_slice(message.body())
+            else {
+                return Err(SettingsError::KernelNonresponsive);
             };
-            Request::to(("our", "homepage", "homepage", "sys"))
+            return SettingsResponse::Ok(Some(SettingsData::ProcessLogs(printouts)));
+        }
+        SettingsRequest::RunNetworkDiagnostics => {
+            // each port is given 5 seconds to accept a connection
+            let Ok(Ok(message)) = Request::to(("our", "net", "distro", "sys"))
+                .body(rmp_serde::to_vec(&NetExtraAction::RunDiagnostics).unwrap())
+                .send_and_await_response(30)
+            else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            let Ok(NetExtraResponse::DiagnosticsReport(checks)) =
+                rmp_serde::from_slice(message.body())
+            else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            return SettingsResponse::Ok(Some(SettingsData::NetworkDiagnostics(checks)));
+        }
+        SettingsRequest::TestProvider((chain_id, url)) => {
+            // connecting alone may take 10 seconds, then each request 5
+            let Ok(Ok(message)) = Request::to(("our", "eth", "distro", "sys"))
                 .body(
-                    serde_json::json!({ "SetStylesheet": stylesheet })
-                        .to_string()
-                        .as_bytes(),
+                    serde_json::to_vec(&serde_json::json!({ "TestProvider": (chain_id, url) }))
+                        .unwrap(),
                 )
-                .capabilities(vec![Capability::new(
-                    Address::new(&state.our.node, ("homepage", "homepage", "sys")),
-                    "\"SetStylesheet\"".to_string(),
-                )])
+                .send_and_await_response(60)
+            else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            let Ok(EthProviderTestResponse::ProviderTest(test)) =
+                serde_json::from_slice(message.body())
+            else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            return SettingsResponse::Ok(Some(SettingsData::ProviderTest(test)));
+        }
+        SettingsRequest::CreateBackup(request) => {
+            start_backup(state, &request)?;
+            return SettingsResponse::Ok(None);
+        }
+        SettingsRequest::RestoreBackup(RestoreBackupRequest { id, passphrase }) => {
+            backup_action(serde_json::json!({
+                "Restore": { "id": id, "passphrase": passphrase }
+            }))?;
+            return SettingsResponse::Ok(None);
+        }
+        SettingsRequest::ListBackups => {
+            let Ok(BackupResponse::Backups(backups)) = backup_action(serde_json::json!("List"))
+            else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            return SettingsResponse::Ok(Some(SettingsData::Backups(backups)));
+        }
+        SettingsRequest::RemoveBackup(id) => {
+            backup_action(serde_json::json!({ "Remove": id }))?;
+        }
+        SettingsRequest::ChangeRouting(mode) => {
+            if matches!(
+                state.routing_change,
+                Some(RoutingChange::Signing | RoutingChange::Pending(_))
+            ) {
+                return Err(SettingsError::RoutingChangeInProgress);
+            }
+            let Ok(WalletResponse::Address(address)) =
+                wallet_action(serde_json::json!("GetAddress"))
+            else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            // an external key can't sign transactions for us
+            let Some(owner) = address
+                .filter(|address| !address.external)
+                .and_then(|address| eth::Address::from_str(&address.address).ok())
+                .filter(|address| *address == state.our_owner)
+            else {
+                return Err(SettingsError::RoutingWalletNotOwner);
+            };
+            let (call, routers) = routing::build_call(&mode)?;
+            let tx = routing::build_transaction(owner, state.our_tba, call)?;
+            // answered in the main loop once the user approves it in the wallet
+            Request::to(("our", "wallet", "distro", "sys"))
+                .body(serde_json::to_vec(&serde_json::json!({ "SignTransaction": tx })).unwrap())
+                .expects_response(SIGN_TIMEOUT)
                 .send()
-                .unwrap();
-            state.stylesheet = Some(stylesheet);
+                .map_err(|_| SettingsError::KernelNonresponsive)?;
+            state.pending_routers = Some(routers);
+            state.routing_tx = None;
+            state.routing_change = Some(RoutingChange::Signing);
+            return SettingsResponse::Ok(None);
+        }
+        SettingsRequest::SetNotifySettings(settings) => {
+            notify_action(serde_json::json!({ "SetSettings": settings }))?;
+        }
+        SettingsRequest::TestNotifications => {
+            let Ok(NotifyResponse::Tested(tests)) = notify_action(serde_json::json!("Test")) else {
+                return Err(SettingsError::KernelNonresponsive);
+            };
+            return SettingsResponse::Ok(Some(SettingsData::NotifyTested(tests)));
+        }
+        SettingsRequest::SetAppStoreTelemetry(settings) => {
+            app_store_telemetry(serde_json::json!({ "SetTelemetrySettings": settings }))?;
+        }
+        SettingsRequest::SetSchedule(ScheduleRequest { task, cron, backup }) => {
+            state.schedule.set(task, cron, backup)?;
             return SettingsResponse::Ok(None);
         }
+        SettingsRequest::RemoveSchedule(task) => {
+            state.schedule.remove(task);
+            return SettingsResponse::Ok(None);
+        }
+        SettingsRequest::ExportConfig => {
+            state.fetch().map_err(|_| SettingsError::StateFetchFailed)?;
+            let config = config::export(state);
+            return SettingsResponse::Ok(Some(SettingsData::Config(
+                serde_json::to_string_pretty(&config).unwrap(),
+            )));
+        }
+        SettingsRequest::ImportConfig(config) => {
+            let config =
+                serde_json::from_str(&config).map_err(|_| SettingsError::MalformedRequest)?;
+            state.fetch().map_err(|_| SettingsError::StateFetchFailed)?;
+            config::import(state, config)?;
+        }
     }
 
     state.fetch().map_err(|_| SettingsError::StateFetchFailed)?;
     SettingsResponse::Ok(None)
 }
 
+fn eth_config_action(action: eth::EthConfigAction) -> Result<(), SettingsError> {
+    match Request::to(("our", "eth", "distro", "sys"))
+        .body(serde_json::to_vec(&action).unwrap())
+        .send_and_await_response(30)
+        .unwrap()
+    {
+        Ok(msg) => match serde_json::from_slice::<eth::EthConfigResponse>(msg.body()) {
+            Ok(eth::EthConfigResponse::PermissionDenied) => Err(SettingsError::KernelNonresponsive),
+            Ok(other) => {
+                println!("eth config action succeeded: {other:?}");
+                Ok(())
+            }
+            Err(_) => Err(SettingsError::KernelNonresponsive),
+        },
+        Err(_) => Err(SettingsError::KernelNonresponsive),
+    }
+}
+
+fn set_stylesheet(state: &mut SettingsState, stylesheet: String) -> Result<(), SettingsError> {
+    let Ok(()) = kinode_process_lib::vfs::File {
+        path: "/homepage:sys/pkg/kinode.css".to_string(),
+        timeout: 5,
+    }
+    .write(stylesheet.as_bytes()) else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    Request::to(("our", "homepage", "homepage", "sys"))
+        .body(
+            serde_json::json!({ "SetStylesheet": stylesheet })
+                .to_string()
+                .as_bytes(),
+        )
+        .capabilities(vec![Capability::new(
+            Address::new(&state.our.node, ("homepage", "homepage", "sys")),
+            "\"SetStylesheet\"".to_string(),
+        )])
+        .send()
+        .unwrap();
+    state.stylesheet = Some(stylesheet);
+    Ok(())
+}
+
+/// Start a backup, answered in the main loop once made, with progress
+/// reported meanwhile.
+fn start_backup(
+    state: &mut SettingsState,
+    CreateBackupRequest {
+        passphrase,
+        include_keyfile,
+    }: &CreateBackupRequest,
+) -> Result<(), SettingsError> {
+    if matches!(
+        state.backup_status,
+        Some(BackupStatus::Started | BackupStatus::InProgress(_))
+    ) {
+        return Err(SettingsError::BackupInProgress);
+    }
+    Request::to(("our", "backup", "distro", "sys"))
+        .body(
+            serde_json::to_vec(&serde_json::json!({
+                "Create": {
+                    "passphrase": passphrase,
+                    "include_keyfile": include_keyfile,
+                }
+            }))
+            .unwrap(),
+        )
+        .expects_response(BACKUP_TIMEOUT)
+        .send()
+        .map_err(|_| SettingsError::KernelNonresponsive)?;
+    state.backup_status = Some(BackupStatus::Started);
+    Ok(())
+}
+
+/// Run the maintenance tasks due, and set a timer for the next.
+fn run_scheduled_tasks(state: &mut SettingsState) {
+    for task in state.schedule.take_due() {
+        let result = match task {
+            MaintenanceTask::AutoBackup => match state.schedule.backup.clone() {
+                Some(request) => match start_backup(state, &request) {
+                    Ok(()) => {
+                        // recorded once made
+                        state.schedule.backup_running = true;
+                        continue;
+                    }
+                    Err(e) => Err(format!("{e:?}")),
+                },
+                None => Err(
+                    "no backup to make: its passphrase is not kept across restarts, \
+                     schedule it again"
+                        .to_string(),
+                ),
+            },
+            MaintenanceTask::LogRotation => schedule::rotate_log(),
+            MaintenanceTask::DownloadsCleanup => schedule::clean_downloads(),
+            MaintenanceTask::ChainReindex => schedule::reindex_chain(),
+        };
+        if let Err(e) = &result {
+            println!("scheduled {task:?} failed: {e}");
+        }
+        state.schedule.record(task, result);
+    }
+    state.schedule.set_timer();
+}
+
+/// Send an action to notify, returning its response if not an error.
+fn notify_action(action: serde_json::Value) -> Result<NotifyResponse, SettingsError> {
+    // a test notification waits on the SMTP server and the webhook
+    let Ok(Ok(message)) = Request::to(("our", "notify", "distro", "sys"))
+        .body(serde_json::to_vec(&action).unwrap())
+        .send_and_await_response(40)
+    else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    match serde_json::from_slice::<NotifyResponse>(message.body()) {
+        Ok(NotifyResponse::Err(e)) if e.as_str() == Some("MalformedRequest") => {
+            Err(SettingsError::MalformedRequest)
+        }
+        Ok(NotifyResponse::Err(_)) | Err(_) => Err(SettingsError::KernelNonresponsive),
+        Ok(response) => Ok(response),
+    }
+}
+
+/// Get or set the install telemetry settings of the App Store, returning them.
+fn app_store_telemetry(request: serde_json::Value) -> Result<AppStoreTelemetry, SettingsError> {
+    let response = schedule::app_store_request("main", request)
+        .map_err(|_| SettingsError::KernelNonresponsive)?;
+    response
+        .get("TelemetrySettingsResponse")
+        .and_then(|settings| serde_json::from_value(settings.clone()).ok())
+        .ok_or(SettingsError::MalformedRequest)
+}
+
+/// Follow the routing change transaction. Once confirmed, our routers are saved
+/// for net:distro:sys to go online with when the node next boots, as the KNS
+/// record other nodes find us by has changed.
+fn handle_routing_update(state: &mut SettingsState, status: serde_json::Value) {
+    if let Some(hash) = status
+        .get("Resent")
+        .or_else(|| status.get("Mined"))
+        .and_then(|status| status["hash"].as_str())
+    {
+        state.routing_change = Some(RoutingChange::Pending(hash.to_string()));
+        return;
+    }
+    state.routing_tx = None;
+    let Some(confirmed) = status.get("Confirmed") else {
+        state.routing_change = Some(RoutingChange::Failed(status));
+        return;
+    };
+    let hash = confirmed["hash"].as_str().unwrap_or_default().to_string();
+    if !confirmed["success"].as_bool().unwrap_or(false) {
+        state.routing_change = Some(RoutingChange::Failed(
+            serde_json::json!({ "Reverted": hash }),
+        ));
+        return;
+    }
+    let routers = state.pending_routers.take().unwrap_or_default();
+    let saved = Request::to(("our", "net", "distro", "sys"))
+        .body(rmp_serde::to_vec(&NetExtraAction::SetRouters(routers)).unwrap())
+        .send_and_await_response(5);
+    state.routing_change = match saved {
+        Ok(Ok(message)) => match rmp_serde::from_slice(message.body()) {
+            Ok(NetExtraResponse::SetRouters(Ok(()))) => Some(RoutingChange::Confirmed(hash)),
+            Ok(NetExtraResponse::SetRouters(Err(e))) => Some(RoutingChange::Failed(
+                serde_json::json!({ "SaveFailed": e }),
+            )),
+            _ => Some(RoutingChange::Failed(serde_json::json!(
+                "MalformedResponse"
+            ))),
+        },
+        _ => Some(RoutingChange::Failed(serde_json::json!(
+            "KernelNonresponsive"
+        ))),
+    };
+    // the KNS record changed
+    let _ = state.fetch();
+}
+
+/// Send an action to http-server, returning the blob of its response.
+fn http_server_action(action: serde_json::Value) -> Result<Option<LazyLoadBlob>, SettingsError> {
+    let Ok(Ok(message)) = Request::to(("our", "http-server", "distro", "sys"))
+        .body(serde_json::to_vec(&action).unwrap())
+        .send_and_await_response(5)
+    else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    match serde_json::from_slice::<Result<(), String>>(message.body()) {
+        Ok(Ok(())) => Ok(get_blob()),
+        Ok(Err(e)) if e == "ApiTokenExists" => Err(SettingsError::ApiTokenExists),
+        Ok(Err(e)) if e == "ApiTokenNotFound" => Err(SettingsError::ApiTokenNotFound),
+        Ok(Err(e)) if e == "JwtSecretNotRotated" => Err(SettingsError::JwtSecretNotRotated),
+        Ok(Err(e)) if e == "InvalidDomain" => Err(SettingsError::InvalidDomain),
+        Ok(Err(e)) if e == "DomainNotFound" => Err(SettingsError::DomainNotFound),
+        Ok(Err(e)) if e == "InvalidCertificate" => Err(SettingsError::InvalidCertificate),
+        Ok(Err(e)) if e == "AccessLogDisabled" => Err(SettingsError::AccessLogDisabled),
+        _ => Err(SettingsError::KernelNonresponsive),
+    }
+}
+
+/// Send a proxy action to http-client, returning the proxies it responds with.
+fn http_client_action(action: serde_json::Value) -> Result<Vec<HttpProxyInfo>, SettingsError> {
+    let Ok(Ok(message)) = Request::to(("our", "http-client", "distro", "sys"))
+        .body(serde_json::to_vec(&action).unwrap())
+        .send_and_await_response(5)
+    else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    match serde_json::from_slice::<Result<serde_json::Value, serde_json::Value>>(message.body()) {
+        Ok(Ok(response)) => serde_json::from_value(response["Proxies"].clone())
+            .map_err(|_| SettingsError::KernelNonresponsive),
+        Ok(Err(e)) if e.get("BadProxy").is_some() => Err(SettingsError::InvalidProxy),
+        _ => Err(SettingsError::KernelNonresponsive),
+    }
+}
+
+/// Send an action to wallet, returning its response if not an error.
+fn wallet_action(action: serde_json::Value) -> Result<WalletResponse, SettingsError> {
+    let Ok(Ok(message)) = Request::to(("our", "wallet", "distro", "sys"))
+        .body(serde_json::to_vec(&action).unwrap())
+        .send_and_await_response(5)
+    else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    match serde_json::from_slice::<WalletResponse>(message.body()) {
+        Ok(WalletResponse::Err(e)) if e.get("NoSuchRequest").is_some() => {
+            Err(SettingsError::WalletRequestNotFound)
+        }
+        Ok(WalletResponse::Err(e)) => Err(match e.as_str() {
+            Some("NoKey") => SettingsError::WalletNoKey,
+            Some("KeyExists") => SettingsError::WalletKeyExists,
+            Some("InvalidKey") => SettingsError::WalletInvalidKey,
+            Some("InvalidSignature") => SettingsError::WalletInvalidSignature,
+            Some("Unsupported") => SettingsError::WalletUnsupported,
+            _ => SettingsError::KernelNonresponsive,
+        }),
+        Ok(response) => Ok(response),
+        Err(_) => Err(SettingsError::KernelNonresponsive),
+    }
+}
+
+/// Send a command to the kernel, returning its response if not an error.
+fn kernel_command(command: serde_json::Value) -> Result<serde_json::Value, SettingsError> {
+    let Ok(Ok(message)) = Request::to(("our", "kernel", "distro", "sys"))
+        .body(serde_json::to_vec(&command).unwrap())
+        .send_and_await_response(30)
+    else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    match serde_json::from_slice::<serde_json::Value>(message.body()) {
+        Ok(response) if response == "SuspendProcessError" || response == "RestartProcessError" => {
+            Err(SettingsError::ProcessNotRunning)
+        }
+        Ok(response) => Ok(response),
+        Err(_) => Err(SettingsError::KernelNonresponsive),
+    }
+}
+
+/// Restart a process. If it is ourselves, the kernel stops us before it can
+/// answer, so we don't wait for it.
+fn restart_process(state: &SettingsState, pid: &ProcessId) -> Result<(), SettingsError> {
+    if *pid == state.our.process {
+        return Request::to(("our", "kernel", "distro", "sys"))
+            .body(serde_json::to_vec(&serde_json::json!({ "RestartProcess": pid })).unwrap())
+            .send()
+            .map_err(|_| SettingsError::KernelNonresponsive);
+    }
+    kernel_command(serde_json::json!({ "RestartProcess": pid }))?;
+    Ok(())
+}
+
+/// Get the metrics of every running process, by process ID.
+fn process_metrics() -> Result<Vec<ProcessMetrics>, SettingsError> {
+    let response = kernel_command(serde_json::json!({ "Debug": "Metrics" }))?;
+    let Ok(metrics) = serde_json::from_value::<BTreeMap<String, KernelProcessMetrics>>(
+        response["Debug"]["Metrics"].clone(),
+    ) else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    Ok(metrics
+        .into_iter()
+        .map(|(process, metrics)| ProcessMetrics {
+            process,
+            queue_depth: metrics.queue_depth,
+            queue_limit: metrics.queue_limit,
+            dropped_messages: metrics.dropped_messages,
+            messages_received: metrics.messages_received,
+            messages_sent: metrics.messages_sent,
+            last_activity: metrics.last_activity,
+            memory_bytes: metrics.memory_bytes,
+            suspended: metrics.suspended,
+        })
+        .collect())
+}
+
+/// Send an action to backup, returning its response if not an error.
+fn backup_action(action: serde_json::Value) -> Result<BackupResponse, SettingsError> {
+    // restoring decrypts the backup, which takes a moment
+    let Ok(Ok(message)) = Request::to(("our", "backup", "distro", "sys"))
+        .body(serde_json::to_vec(&action).unwrap())
+        .send_and_await_response(30)
+    else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    match serde_json::from_slice::<BackupResponse>(message.body()) {
+        Ok(BackupResponse::Err(e)) if e.get("NoBackup").is_some() => {
+            Err(SettingsError::BackupNotFound)
+        }
+        Ok(BackupResponse::Err(e)) if e.get("Failed").is_some() || e.get("IOError").is_some() => {
+            Err(SettingsError::BackupFailed)
+        }
+        Ok(BackupResponse::Err(e)) => Err(match e.as_str() {
+            Some("InProgress") => SettingsError::BackupInProgress,
+            Some("WrongPassphrase") => SettingsError::BackupWrongPassphrase,
+            _ => SettingsError::KernelNonresponsive,
+        }),
+        Ok(response) => Ok(response),
+        Err(_) => Err(SettingsError::KernelNonresponsive),
+    }
+}
+
 fn eth_config_convert(
     settings_eth_config_request: SettingsEthConfigAction,
 ) -> Result<eth::EthConfigAction, SettingsError> {
@@ -530,7 +1466,7 @@ fn eth_config_convert(
                                 name: node_str,
                                 public_key: "".to_string(),
                                 ips: vec![],
-                                ports: std::collections::BTreeMap::new(),
+                                ports: BTreeMap::new(),
                                 routers: vec![],
                             },
                             use_as_provider: true,
@@ -552,137 +1488,3 @@ fn eth_config_convert(
         SettingsEthConfigAction::UndenyNode(node) => Ok(eth::EthConfigAction::UndenyNode(node)),
     }
 }
-
-fn make_widget(state: &SettingsState) -> String {
-    let owner_string = state.our_owner.to_string();
-    let tba_string = state.our_tba.to_string();
-    return format!(
-        r#"<html>
-    <head>
-        <meta name="viewport" content="width=device-width, initial-scale=1">
-        <link rel="stylesheet" href="/kinode.css">
-    </head>
-    <body style="margin: 0; padding: 8px; width: 100%; height: 100%; padding-bottom: 30px;">
-        <article id="onchain-id">
-            <h3>{}</h3>
-            <details style="word-wrap: break-word;">
-                <summary><p style="display: inline;">{} processes running</p></summary>
-                <ul style="margin: 8px; list-style-type: none;">
-                    {}
-                </ul>
-            </details>
-            <details style="word-wrap: break-word;">
-                <summary><p style="display: inline;">{} RPC providers</p></summary>
-                <ul style="margin: 8px; list-style-type: none;">
-                    {}
-                </ul>
-            </details>
-        </article>
-
-        <br />
-
-        <article id="addrs">
-            <p>owner: <a href="https://etherscan.io/address/{}#multichain-portfolio" target="_blank">{}</a></p>
-            <p>token-bound account: <a href="https://etherscan.io/address/{}#multichain-portfolio" target="_blank">{}</a></p>
-        </article>
-
-        <br />
-
-        <article id="net">
-            <details style="word-wrap: break-word;">
-                <summary><p style="display: inline;">{}</p></summary>
-                <p style="white-space: pre; margin: 8px;">{}</p>
-            </details>
-        </article>
-
-        <br />
-
-        <button id="refresh" onclick="this.innerHTML='⌛'; fetch('/settings:settings:sys/refresh').then(() => setTimeout(() => window.location.reload(), 1000))" style="width: 30px; height: 30px; display: flex; align-items: center; justify-content: center; padding: 0; font-size: 24px;">⟳</button>
-
-        <br />
-
-        <a href="/settings:settings:sys/" target="_blank">Adjust Settings</a>
-    </body>
-    </html>"#,
-        state.our.node(),
-        state.process_map.as_ref().map(|m| m.len()).unwrap_or(0),
-        state
-            .process_map
-            .as_ref()
-            .map(|m| {
-                let mut v = m
-                    .keys()
-                    .map(|pid| format!("<li>{}</li>", pid))
-                    .collect::<Vec<_>>();
-                v.sort();
-                v.join("\n")
-            })
-            .unwrap_or_default(),
-        state
-            .eth_rpc_providers
-            .as_ref()
-            .map(|m| m.len())
-            .unwrap_or(0),
-        state
-            .eth_rpc_providers
-            .as_ref()
-            .map(|m| {
-                let mut v = m
-                    .iter()
-                    .filter_map(|config| {
-                        match &config.provider {
-                            eth::NodeOrRpcUrl::Node {
-                                kns_update,
-                                use_as_provider,
-                            } => {
-                                if *use_as_provider {
-                                    Some(format!(
-                                        "<li style=\"border-bottom: 1px solid black; padding: 2px;\">{}: Chain ID {}</li>",
-                                        kns_update.name,
-                                        config.chain_id
-                                    ))
-                                } else {
-                                    None
-                                }
-                            }
-                            eth::NodeOrRpcUrl::RpcUrl(url) => Some(format!(
-                                "<li style=\"border-bottom: 1px solid black; padding: 2px;\">{}: Chain ID {}</li>",
-                                url,
-                                config.chain_id
-                            )),
-                        }
-                    })
-                    .collect::<Vec<_>>();
-                v.sort();
-                v.join("\n")
-            })
-            .unwrap_or_default(),
-        owner_string,
-        format!(
-            "{}..{}",
-            &owner_string[..4],
-            &owner_string[owner_string.len() - 4..]
-        ),
-        tba_string,
-        format!(
-            "{}..{}",
-            &tba_string[..4],
-            &tba_string[tba_string.len() - 4..]
-        ),
-        match &state.identity.as_ref().expect("identity not set!!").routing {
-            net::NodeRouting::Direct { .. } => "direct node".to_string(),
-            net::NodeRouting::Routers(routers) => format!("indirect node with {} routers", routers.len()),
-        },
-        match &state.identity.as_ref().expect("identity not set!!").routing {
-            net::NodeRouting::Direct { ip, ports } => {
-                let mut v = ports
-                    .iter()
-                    .map(|p| format!("{}: {}", p.0, p.1))
-                    .collect::<Vec<_>>();
-                v.push(format!("ip: {}", ip));
-                v.join("\n")
-            }
-            net::NodeRouting::Routers(routers) => routers.join("\n"),
-        },
-    );
-}
@@ -0,0 +1,205 @@
+//! Changing how our node is reached, between directly and through routers.
+//!
+//! The change is made onchain, by the token-bound account of our node writing
+//! the notes other nodes find us by: `~ip` and `~ws-port` or `~tcp-port` for a
+//! direct node, or `~routers` for an indirect one. The account is called by its
+//! owner, so the transaction is signed by wallet:distro:sys, whose key must be
+//! the owner, and sent by eth:distro:sys.
+use crate::kinode::process::settings::{DirectRouting, RoutingMode, SettingsError};
+use alloy_primitives::{Address as EthAddress, Bytes, B256, U256};
+use alloy_sol_types::{sol, SolCall};
+use kinode_process_lib::{kimap, Request};
+use serde::Deserialize;
+use std::{net::IpAddr, str::FromStr};
+
+const MULTICALL_ADDRESS: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+/// headroom over the gas estimate, in percent
+const GAS_HEADROOM: u128 = 20;
+const ETH_TIMEOUT: u64 = 30;
+
+sol! {
+    function note(bytes calldata note, bytes calldata data) external returns (bytes32 notenode);
+
+    struct Call {
+        address target;
+        bytes callData;
+    }
+
+    function aggregate(
+        Call[] calldata calls
+    ) external payable returns (uint256 blockNumber, bytes[] memory returnData);
+
+    function execute(
+        address to,
+        uint256 value,
+        bytes calldata data,
+        uint8 operation
+    ) external payable returns (bytes memory returnData);
+}
+
+/// the eth:distro:sys responses not yet in process_lib
+#[derive(Debug, Deserialize)]
+pub enum EthResponse {
+    Response(serde_json::Value),
+    Err(serde_json::Value),
+    TransactionSent { id: u64, hash: String },
+    FeeSuggestion(FeeSuggestion),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeeSuggestion {
+    pub base_fee_per_gas: Option<u128>,
+    pub standard: FeeLevel,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeeLevel {
+    pub max_fee_per_gas: u128,
+    pub max_priority_fee_per_gas: u128,
+}
+
+/// Build the call our token-bound account makes to write the notes of a
+/// routing mode, returning it and the routers to go online with, none if direct.
+pub fn build_call(mode: &RoutingMode) -> Result<(Vec<u8>, Vec<String>), SettingsError> {
+    let kimap_address = *kimap::Kimap::default(ETH_TIMEOUT).address();
+    let note = |label: &str, data: Vec<u8>| Call {
+        target: kimap_address,
+        callData: Bytes::from(
+            noteCall {
+                note: Bytes::from(label.as_bytes().to_vec()),
+                data: Bytes::from(data),
+            }
+            .abi_encode(),
+        ),
+    };
+    let (calls, routers) = match mode {
+        RoutingMode::Direct(DirectRouting {
+            ip,
+            ws_port,
+            tcp_port,
+        }) => {
+            let ip = match IpAddr::from_str(ip).map_err(|_| SettingsError::MalformedRequest)? {
+                IpAddr::V4(ip) => ip.octets().to_vec(),
+                IpAddr::V6(ip) => ip.octets().to_vec(),
+            };
+            let mut calls = vec![note("~ws-port", ws_port.to_be_bytes().to_vec())];
+            if let Some(tcp_port) = tcp_port {
+                calls.push(note("~tcp-port", tcp_port.to_be_bytes().to_vec()));
+            }
+            calls.push(note("~ip", ip));
+            (calls, vec![])
+        }
+        RoutingMode::Routers(routers) => {
+            if routers.is_empty() {
+                return Err(SettingsError::MalformedRequest);
+            }
+            let mut hashes = vec![];
+            for router in routers {
+                let hash = B256::from_str(&kimap::namehash(router))
+                    .map_err(|_| SettingsError::MalformedRequest)?;
+                hashes.extend_from_slice(hash.as_slice());
+            }
+            (vec![note("~routers", hashes)], routers.clone())
+        }
+    };
+    let multicall = aggregateCall { calls }.abi_encode();
+    let call = executeCall {
+        to: EthAddress::from_str(MULTICALL_ADDRESS).unwrap(),
+        value: U256::ZERO,
+        data: Bytes::from(multicall),
+        // delegatecall, so that the notes are written by the account itself
+        operation: 1,
+    }
+    .abi_encode();
+    Ok((call, routers))
+}
+
+/// Build a transaction from our owner to our token-bound account making `call`,
+/// with every field the wallet needs to sign it set.
+pub fn build_transaction(
+    owner: EthAddress,
+    tba: EthAddress,
+    call: Vec<u8>,
+) -> Result<serde_json::Value, SettingsError> {
+    let chain_id = kimap::KIMAP_CHAIN_ID;
+    let input = Bytes::from(call);
+    let nonce = eth_number(
+        chain_id,
+        "eth_getTransactionCount",
+        serde_json::json!([owner, "pending"]),
+    )?;
+    let gas = eth_number(
+        chain_id,
+        "eth_estimateGas",
+        serde_json::json!([{ "from": owner, "to": tba, "input": input }]),
+    )?;
+    let EthResponse::FeeSuggestion(fees) =
+        eth_request(serde_json::json!({ "GetFeeSuggestion": chain_id }))?
+    else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    let mut tx = serde_json::json!({
+        "from": owner,
+        "to": tba,
+        "input": input,
+        "value": "0x0",
+        "nonce": format!("{nonce:#x}"),
+        "gas": format!("{:#x}", gas + gas * GAS_HEADROOM / 100),
+        "chainId": format!("{chain_id:#x}"),
+    });
+    if fees.base_fee_per_gas.is_some() {
+        tx["maxFeePerGas"] = format!("{:#x}", fees.standard.max_fee_per_gas).into();
+        tx["maxPriorityFeePerGas"] =
+            format!("{:#x}", fees.standard.max_priority_fee_per_gas).into();
+    } else {
+        tx["gasPrice"] = format!("{:#x}", fees.standard.max_fee_per_gas).into();
+    }
+    Ok(tx)
+}
+
+/// Send a signed transaction, returning its ID, by which eth:distro:sys sends
+/// us updates on it, and its hash.
+pub fn send_transaction(raw: String) -> Result<(u64, String), SettingsError> {
+    match eth_request(serde_json::json!({
+        "SendTransaction": {
+            "chain_id": kimap::KIMAP_CHAIN_ID,
+            "tx": { "Signed": raw },
+            "confirmations": 1,
+        }
+    }))? {
+        EthResponse::TransactionSent { id, hash } => Ok((id, hash)),
+        _ => Err(SettingsError::KernelNonresponsive),
+    }
+}
+
+fn eth_number(
+    chain_id: u64,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<u128, SettingsError> {
+    let EthResponse::Response(value) = eth_request(serde_json::json!({
+        "Request": { "chain_id": chain_id, "method": method, "params": params }
+    }))?
+    else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    value
+        .as_str()
+        .and_then(|number| u128::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+        .ok_or(SettingsError::KernelNonresponsive)
+}
+
+fn eth_request(action: serde_json::Value) -> Result<EthResponse, SettingsError> {
+    let Ok(Ok(message)) = Request::to(("our", "eth", "distro", "sys"))
+        .body(serde_json::to_vec(&action).unwrap())
+        .send_and_await_response(ETH_TIMEOUT)
+    else {
+        return Err(SettingsError::KernelNonresponsive);
+    };
+    match serde_json::from_slice::<EthResponse>(message.body()) {
+        // such as a failed gas estimate, if the wallet's key can't make the call
+        Ok(EthResponse::Err(_)) => Err(SettingsError::RoutingTransactionFailed),
+        Ok(response) => Ok(response),
+        Err(_) => Err(SettingsError::KernelNonresponsive),
+    }
+}
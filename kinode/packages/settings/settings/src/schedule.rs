@@ -0,0 +1,265 @@
+//! Maintenance tasks run on cron schedules.
+//!
+//! The schedule is the only part of our state saved across restarts. Timers
+//! are set with timer:distro:sys, which forgets them when the node stops, so
+//! the next one is set again on boot, and a task whose time passed while the
+//! node was down runs then, once.
+use crate::kinode::process::settings::{CreateBackupRequest, MaintenanceTask, SettingsError};
+use kinode_process_lib::{get_typed_state, set_state, timer, Request};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
+
+/// longest a timer is set for, so that the schedule keeps up with changes to
+/// the clock of the machine
+const MAX_TIMER_MS: u64 = 60 * 60 * 1000;
+const APP_STORE_TIMEOUT: u64 = 10;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScheduledTask {
+    pub task: MaintenanceTask,
+    pub cron: String,
+    /// UNIX timestamp in milliseconds at which the task next runs
+    pub next_run: u64,
+    pub last_run: Option<u64>,
+    /// why the last run failed, if it did
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    pub tasks: Vec<ScheduledTask>,
+    /// the backup made by [`MaintenanceTask::AutoBackup`]; never shown to our
+    /// frontend, as it holds the passphrase, and saved without it, so kept only
+    /// until the node restarts
+    #[serde(skip)]
+    pub backup: Option<CreateBackupRequest>,
+    /// whether the backup being made was started by [`MaintenanceTask::AutoBackup`],
+    /// to record how it went once made
+    #[serde(skip)]
+    pub backup_running: bool,
+    /// when the timer set last pops
+    #[serde(skip)]
+    timer_at: Option<u64>,
+}
+
+impl Schedule {
+    pub fn load() -> Self {
+        let Some((tasks, backup)) = get_typed_state(|bytes| {
+            serde_json::from_slice::<(Vec<ScheduledTask>, Option<CreateBackupRequest>)>(bytes)
+        }) else {
+            return Self::default();
+        };
+        // saved before passphrases were left out; used now, and saved again without
+        let backup = backup.filter(|backup| !backup.passphrase.is_empty());
+        let schedule = Self {
+            tasks,
+            backup,
+            ..Self::default()
+        };
+        if schedule.backup.is_some() {
+            schedule.save();
+        }
+        schedule
+    }
+
+    fn save(&self) {
+        let backup = self.backup.as_ref().map(|backup| CreateBackupRequest {
+            passphrase: String::new(),
+            include_keyfile: backup.include_keyfile,
+        });
+        set_state(&serde_json::to_vec(&(&self.tasks, &backup)).unwrap());
+    }
+
+    pub fn set(
+        &mut self,
+        task: MaintenanceTask,
+        cron: String,
+        backup: Option<CreateBackupRequest>,
+    ) -> Result<(), SettingsError> {
+        let next_run = next_run(&cron, now()).ok_or(SettingsError::InvalidSchedule)?;
+        if task == MaintenanceTask::AutoBackup {
+            if backup.is_some() {
+                self.backup = backup;
+            } else if self.backup.is_none() {
+                return Err(SettingsError::MalformedRequest);
+            }
+        }
+        self.tasks.retain(|t| t.task != task);
+        self.tasks.push(ScheduledTask {
+            task,
+            cron,
+            next_run,
+            last_run: None,
+            last_error: None,
+        });
+        self.save();
+        self.set_timer();
+        Ok(())
+    }
+
+    pub fn remove(&mut self, task: MaintenanceTask) {
+        self.tasks.retain(|t| t.task != task);
+        if task == MaintenanceTask::AutoBackup {
+            self.backup = None;
+        }
+        self.save();
+    }
+
+    /// Set a timer for the next task to run, unless one pops before then.
+    pub fn set_timer(&mut self) {
+        let Some(next_run) = self.tasks.iter().map(|t| t.next_run).min() else {
+            return;
+        };
+        let now = now();
+        let pop_at = next_run.min(now + MAX_TIMER_MS);
+        if self.timer_at.is_some_and(|timer_at| timer_at <= pop_at) {
+            return;
+        }
+        timer::set_timer(pop_at.saturating_sub(now), None);
+        self.timer_at = Some(pop_at);
+    }
+
+    /// When a timer pops: take the tasks due to run, and move them to their
+    /// next run. Report how each went with [`Schedule::record`].
+    pub fn take_due(&mut self) -> Vec<MaintenanceTask> {
+        let now = now();
+        if self.timer_at.is_some_and(|timer_at| timer_at <= now) {
+            self.timer_at = None;
+        }
+        let mut due = vec![];
+        for task in self.tasks.iter_mut().filter(|t| t.next_run <= now) {
+            due.push(task.task);
+            task.last_run = Some(now);
+            // a spec saved once parsed, so only fails to run again if it never will
+            task.next_run = next_run(&task.cron, now).unwrap_or(u64::MAX);
+        }
+        due
+    }
+
+    pub fn record(&mut self, task: MaintenanceTask, result: Result<(), String>) {
+        if let Some(task) = self.tasks.iter_mut().find(|t| t.task == task) {
+            task.last_error = result.err();
+        }
+        self.save();
+    }
+}
+
+/// Start a new terminal log file. There is no response.
+pub fn rotate_log() -> Result<(), String> {
+    Request::to(("our", "terminal", "distro", "sys"))
+        .body(serde_json::to_vec(&serde_json::json!("RotateLog")).unwrap())
+        .send()
+        .map_err(|e| e.to_string())
+}
+
+/// Remove the downloads of app versions neither installed nor mirrored.
+pub fn clean_downloads() -> Result<(), String> {
+    let installed: HashMap<String, String> =
+        match app_store_request("main", serde_json::json!("Installed"))?.get("InstalledResponse") {
+            Some(serde_json::Value::Array(packages)) => packages
+                .iter()
+                .filter_map(|p| {
+                    let id = &p["package_id"];
+                    Some((
+                        format!(
+                            "{}:{}",
+                            id["package_name"].as_str()?,
+                            id["publisher_node"].as_str()?
+                        ),
+                        p["version_hash"].as_str()?.to_string(),
+                    ))
+                })
+                .collect(),
+            _ => return Err("malformed response from the App Store".to_string()),
+        };
+    for dir in get_files(serde_json::Value::Null)? {
+        let Some(dir) = dir.get("Dir") else {
+            continue;
+        };
+        if dir["mirroring"].as_bool() != Some(false) {
+            continue;
+        }
+        let Some((package_name, publisher_node)) =
+            dir["name"].as_str().and_then(|name| name.split_once(':'))
+        else {
+            continue;
+        };
+        let package_id = serde_json::json!({
+            "package_name": package_name,
+            "publisher_node": publisher_node,
+        });
+        let installed_hash = installed.get(&format!("{package_name}:{publisher_node}"));
+        for file in get_files(package_id.clone())? {
+            let Some(version_hash) = file["File"]["name"]
+                .as_str()
+                .and_then(|name| name.strip_suffix(".zip"))
+            else {
+                continue;
+            };
+            if installed_hash.is_some_and(|hash| hash == version_hash) {
+                continue;
+            }
+            app_store_request(
+                "downloads",
+                serde_json::json!({
+                    "RemoveFile": {
+                        "package_id": package_id,
+                        "version_hash": version_hash,
+                    }
+                }),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Reset the App Store's index of onchain apps. The chain process restarts
+/// once it answers, and indexes them again.
+pub fn reindex_chain() -> Result<(), String> {
+    match app_store_request("chain", serde_json::json!("Reset"))?.as_str() {
+        Some("ResetOk") => Ok(()),
+        _ => Err("malformed response from the App Store".to_string()),
+    }
+}
+
+fn get_files(package_id: serde_json::Value) -> Result<Vec<serde_json::Value>, String> {
+    match app_store_request("downloads", serde_json::json!({ "GetFiles": package_id }))?
+        .get("GetFiles")
+    {
+        Some(serde_json::Value::Array(entries)) => Ok(entries.clone()),
+        _ => Err("malformed response from the App Store".to_string()),
+    }
+}
+
+pub fn app_store_request(
+    process: &str,
+    body: serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    let Ok(Ok(message)) = Request::to(("our", process, "app-store", "sys"))
+        .body(serde_json::to_vec(&body).unwrap())
+        .send_and_await_response(APP_STORE_TIMEOUT)
+    else {
+        return Err(format!("no response from {process}:app-store:sys"));
+    };
+    serde_json::from_slice(message.body()).map_err(|e| e.to_string())
+}
+
+/// When a cron spec next fires after `after`, both in milliseconds since the
+/// epoch. Standard specs of 5 fields are taken to fire at second 0.
+pub fn next_run(spec: &str, after: u64) -> Option<u64> {
+    let spec = match spec.split_whitespace().count() {
+        5 => format!("0 {spec}"),
+        _ => spec.to_string(),
+    };
+    let schedule = cron::Schedule::from_str(&spec).ok()?;
+    let after = chrono::DateTime::from_timestamp_millis(after as i64)?;
+    let next = schedule.after(&after).next()?;
+    Some(next.timestamp_millis() as u64)
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
@@ -0,0 +1,75 @@
+//! Our pages, as found by the homepage's launcher. Each section of our UI is
+//! a page, linked to by the ID of its article.
+use crate::search_api::{SearchQuery, SearchResponse, SearchResult};
+
+/// ID, title and words a user may search for, of each section of our UI
+const PAGES: &[(&str, &str, &str)] = &[
+    (
+        "net-diagnostics",
+        "Networking diagnostics",
+        "network connectivity peers ports",
+    ),
+    ("node-info", "Node info", "identity name version key"),
+    ("pings", "Ping a node", "fetch PKI data peer"),
+    (
+        "eth-rpc-providers",
+        "ETH RPC providers",
+        "ethereum node url benchmark health",
+    ),
+    (
+        "eth-rpc-settings",
+        "ETH RPC settings",
+        "ethereum public private allow deny access",
+    ),
+    ("wallet", "Wallet", "key sign signature approve transaction"),
+    (
+        "kernel",
+        "Running processes",
+        "kernel process stop restart logs metrics",
+    ),
+    ("storage", "Storage", "disk space drives packages usage"),
+    ("backups", "Backups", "backup restore export passphrase"),
+    (
+        "scheduler",
+        "Scheduled maintenance",
+        "cron schedule auto-backup logs downloads reindex",
+    ),
+    (
+        "notifications",
+        "Notifications",
+        "email webhook badge alerts smtp",
+    ),
+    (
+        "id-onchain",
+        "Identity onchain",
+        "owner token-bound account tba kimap",
+    ),
+    ("routing", "Routing", "direct indirect routers ip ports"),
+    ("kinode-css", "Stylesheet editor", "css theme style colors"),
+    ("node-config", "Node configuration", "export import config"),
+];
+
+/// The pages whose title or words hold every word of the query.
+pub fn search(query: &SearchQuery) -> SearchResponse {
+    let words: Vec<String> = query
+        .query
+        .to_lowercase()
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    SearchResponse::Search(
+        PAGES
+            .iter()
+            .filter(|(_, title, keywords)| {
+                let haystack = format!("{title} {keywords}").to_lowercase();
+                words.iter().all(|word| haystack.contains(word))
+            })
+            .take(query.limit as usize)
+            .map(|(id, title, _)| SearchResult {
+                title: title.to_string(),
+                description: Some("Settings".to_string()),
+                path: format!("/#{id}"),
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,145 @@
+//! Our homepage widget. The homepage fills in its template with the data it
+//! fetches from `/widget`, so the widget is given once, on boot.
+use crate::SettingsState;
+use kinode_process_lib::{eth, net, Request};
+
+const TEMPLATE: &str = include_str!("widget.html");
+/// how often the homepage fetches the data, while open
+const REFRESH_SECS: u32 = 60;
+
+/// Give the homepage our widget. We must have been added to it first.
+pub fn register() {
+    let _ = Request::to(("our", "homepage", "homepage", "sys"))
+        .body(
+            serde_json::to_vec(&serde_json::json!({
+                "SetWidget": {
+                    "template": TEMPLATE,
+                    "data_path": "/widget",
+                    "refresh_secs": REFRESH_SECS,
+                }
+            }))
+            .unwrap(),
+        )
+        .send();
+}
+
+/// The data the template is filled in with, as last fetched.
+pub fn data(state: &SettingsState) -> serde_json::Value {
+    let mut processes = state
+        .process_map
+        .as_ref()
+        .map(|m| m.keys().map(|pid| pid.to_string()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    processes.sort();
+    let mut providers = state
+        .eth_rpc_providers
+        .as_ref()
+        .map(|m| {
+            m.iter()
+                .filter_map(|config| match &config.provider {
+                    eth::NodeOrRpcUrl::Node {
+                        kns_update,
+                        use_as_provider,
+                    } => use_as_provider.then(|| {
+                        format!(
+                            "{}: Chain ID {}{}",
+                            kns_update.name,
+                            config.chain_id,
+                            provider_health_label(state, config.chain_id, &kns_update.name),
+                        )
+                    }),
+                    eth::NodeOrRpcUrl::RpcUrl(url) => Some(format!(
+                        "{}: Chain ID {}{}{}",
+                        url,
+                        config.chain_id,
+                        provider_health_label(state, config.chain_id, url),
+                        provider_test_label(state, config.chain_id, url),
+                    )),
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    providers.sort();
+    let owner = state.our_owner.to_string();
+    let tba = state.our_tba.to_string();
+    let (routing, routing_details) = match state.identity.as_ref().map(|i| &i.routing) {
+        Some(net::NodeRouting::Direct { ip, ports }) => {
+            let mut v = ports
+                .iter()
+                .map(|p| format!("{}: {}", p.0, p.1))
+                .collect::<Vec<_>>();
+            v.push(format!("ip: {}", ip));
+            ("direct node".to_string(), v.join("\n"))
+        }
+        Some(net::NodeRouting::Routers(routers)) => (
+            format!("indirect node with {} routers", routers.len()),
+            routers.join("\n"),
+        ),
+        None => (String::new(), String::new()),
+    };
+    serde_json::json!({
+        "node": state.our.node(),
+        "process_count": processes.len(),
+        "processes": processes,
+        "provider_count": state.eth_rpc_providers.as_ref().map(|m| m.len()).unwrap_or(0),
+        "providers": providers,
+        "owner_short": format!("{}..{}", &owner[..4], &owner[owner.len() - 4..]),
+        "owner": owner,
+        "tba_short": format!("{}..{}", &tba[..4], &tba[tba.len() - 4..]),
+        "tba": tba,
+        "routing": routing,
+        "routing_details": routing_details,
+    })
+}
+
+/// latency, error rate and sync status of a provider
+fn provider_health_label(state: &SettingsState, chain_id: u64, provider: &str) -> String {
+    let Some(health) = state.eth_rpc_provider_health.as_ref().and_then(|h| {
+        h.iter()
+            .find(|h| h.chain_id == chain_id && h.provider == provider)
+    }) else {
+        return String::new();
+    };
+    if health.requests == 0 {
+        return " (unused)".to_string();
+    }
+    let mut label = vec![];
+    if let Some(latency_ms) = health.latency_ms {
+        label.push(format!("{latency_ms}ms"));
+    }
+    label.push(format!("{:.0}% errors", health.error_rate * 100.0));
+    if let Some(blocks_behind) = health.blocks_behind.filter(|b| *b > 0) {
+        label.push(format!("{blocks_behind} blocks behind"));
+    }
+    if health.demoted {
+        label.push("demoted".to_string());
+    }
+    format!(" ({})", label.join(", "))
+}
+
+/// latency and archive support of an RPC URL in its latest benchmark
+fn provider_test_label(state: &SettingsState, chain_id: u64, url: &str) -> String {
+    let Some(test) = state
+        .eth_rpc_provider_tests
+        .as_ref()
+        .and_then(|t| t.iter().find(|t| t.chain_id == chain_id && t.url == url))
+    else {
+        return String::new();
+    };
+    if let Some(error) = &test.error {
+        return format!(" [benchmark failed: {error}]");
+    }
+    let mut label = vec![];
+    if let Some(latency_ms) = test.latency_ms {
+        label.push(format!("benchmark {latency_ms}ms"));
+    }
+    if let Some(block_number) = test.block_number {
+        label.push(format!("block {block_number}"));
+    }
+    match test.archive {
+        Some(true) => label.push("archive".to_string()),
+        Some(false) => label.push("not archive".to_string()),
+        None => {}
+    }
+    format!(" [{}]", label.join(", "))
+}
@@ -0,0 +1,75 @@
+//! share:settings:sys
+//! terminal script for choosing the VFS drives that webdav:settings:sys serves.
+//!
+//! Usage:
+//!     share:settings:sys                      List the shared drives
+//!     share:settings:sys <drive> [--write]    Share a drive, read-only unless --write
+//!     share:settings:sys --remove <drive>     Stop sharing a drive
+//!
+use crate::kinode::process::webdav::{Request as WebDavRequest, Response as WebDavResponse, Share};
+use kinode_process_lib::{script, Address, Message, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "settings-sys-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+const USAGE: &str = "\x1b[1mUsage:\x1b[0m
+    \nshare:settings:sys
+    \nshare:settings:sys <drive> [--write]
+    \nshare:settings:sys --remove <drive>";
+
+script!(init);
+fn init(our: Address, args: String) -> String {
+    let args = args.split_whitespace().collect::<Vec<_>>();
+    let request = match args.as_slice() {
+        [] => WebDavRequest::Shares,
+        ["--remove", drive] => WebDavRequest::Unshare(drive.to_string()),
+        [drive] | [drive, "--write"] | ["--write", drive] if !drive.starts_with("--") => {
+            WebDavRequest::Share(Share {
+                drive: drive.to_string(),
+                writable: args.contains(&"--write"),
+            })
+        }
+        _ => return format!("Failed to parse args.\n{USAGE}"),
+    };
+
+    let Ok(Ok(Message::Response { body, .. })) =
+        Request::to((our.node(), ("webdav", "settings", "sys")))
+            .body(serde_json::to_vec(&request).unwrap())
+            .send_and_await_response(10)
+    else {
+        return "share: failed to get a response from webdav..!".to_string();
+    };
+    let Ok(response) = serde_json::from_slice::<WebDavResponse>(&body) else {
+        return "share: failed to parse response from webdav..!".to_string();
+    };
+    let shares = match response {
+        Ok(shares) => shares,
+        Err(e) => return format!("share: {e}"),
+    };
+
+    if shares.is_empty() {
+        return "no drives are shared over WebDAV".to_string();
+    }
+    let shares = shares
+        .iter()
+        .map(|share| {
+            format!(
+                "    {} ({})",
+                share.drive,
+                if share.writable {
+                    "read-write"
+                } else {
+                    "read-only"
+                }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "drives shared over WebDAV at /webdav:settings:sys/, using an auth token as the password:\n{shares}"
+    )
+}
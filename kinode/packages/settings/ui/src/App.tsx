@@ -1,5 +1,5 @@
 import { ConnectButton } from '@rainbow-me/rainbowkit';
-import { useAccount } from 'wagmi';
+import { useAccount, useSignMessage, useSignTypedData } from 'wagmi';
 import EditNote from './components/EditNote';
 import { useEffect, useState } from 'react';
 
@@ -29,6 +29,206 @@ interface ProcessInfo {
   }>;
 }
 
+interface ProcessMetrics {
+  process: string;
+  queue_depth: number;
+  queue_limit: number | null;
+  dropped_messages: number;
+  messages_received: number;
+  messages_sent: number;
+  last_activity: number | null;
+  memory_bytes: number | null;
+  suspended: boolean;
+}
+
+interface ProcessPrintout {
+  timestamp: number;
+  verbosity: number;
+  content: string;
+}
+
+interface ProviderHealth {
+  chain_id: number;
+  provider: string;
+  requests: number;
+  errors: number;
+  error_rate: number;
+  latency_ms: number | null;
+  block_number: number | null;
+  blocks_behind: number | null;
+  demoted: boolean;
+}
+
+interface ProviderTest {
+  chain_id: number;
+  url: string;
+  tested_at: number;
+  error: string | null;
+  latency_ms: number | null;
+  block_number: number | null;
+  archive: boolean | null;
+}
+
+interface DiagnosticCheck {
+  name: string;
+  passed: boolean;
+  detail: string;
+}
+
+interface WalletAddress {
+  address: string;
+  external: boolean;
+}
+
+interface PendingSignature {
+  id: number;
+  process: string;
+  request:
+    | { SignMessage: number[] }
+    | { SignTypedData: any }
+    | { SignTransaction: any };
+  requested_at: number;
+}
+
+interface DbUsage {
+  db: string;
+  bytes: number;
+}
+
+interface PackageStorage {
+  package_id: string;
+  bytes: number;
+  drives: Array<{ drive: string; bytes: number; quota: number | null }>;
+  sqlite: DbUsage[];
+  kv: DbUsage[];
+}
+
+interface BackupInfo {
+  id: number;
+  bytes: number;
+  keyfile: boolean;
+  settings_files: number;
+  databases: number;
+  apps: number;
+}
+
+type BackupStatus =
+  | "Started"
+  | { InProgress: any }
+  | { Created: BackupInfo }
+  | { Failed: any };
+
+const describeBackupStatus = (status: BackupStatus) => {
+  if (status === "Started") {
+    return 'starting backup...';
+  } else if ('InProgress' in status) {
+    const stage = status.InProgress;
+    if (stage === "Settings") return 'backing up settings...';
+    if (stage === "Encrypting") return 'encrypting...';
+    if (stage === "Writing") return 'writing backup...';
+    if (stage.Database) {
+      return `backing up database ${stage.Database.db} (${stage.Database.done + 1} of ${stage.Database.total})...`;
+    }
+    if (stage.SkippedDatabase) {
+      return `skipped database ${stage.SkippedDatabase.db}: ${stage.SkippedDatabase.reason}`;
+    }
+    return JSON.stringify(stage);
+  } else if ('Created' in status) {
+    return `backed up to ${new Date(status.Created.id).toLocaleString()}`;
+  }
+  return `backup failed: ${JSON.stringify(status.Failed)}`;
+};
+
+type MaintenanceTask = "AutoBackup" | "LogRotation" | "DownloadsCleanup" | "ChainReindex";
+
+const MAINTENANCE_TASKS: [MaintenanceTask, string][] = [
+  ["AutoBackup", "back up node"],
+  ["LogRotation", "rotate terminal log"],
+  ["DownloadsCleanup", "clean up unused downloads"],
+  ["ChainReindex", "re-index App Store from chain"],
+];
+
+interface ScheduledTask {
+  task: MaintenanceTask;
+  cron: string;
+  next_run: number;
+  last_run: number | null;
+  last_error: string | null;
+}
+
+type NotifyEventKind = "UpdateAvailable" | "AutoUpdateFailed" | "LowDisk" | "NewLoginIp";
+
+const NOTIFY_EVENT_KINDS: [NotifyEventKind, string][] = [
+  ["UpdateAvailable", "update available"],
+  ["AutoUpdateFailed", "auto-update failed"],
+  ["LowDisk", "low disk space"],
+  ["NewLoginIp", "login from a new IP"],
+];
+
+interface EmailSettings {
+  smtp_host: string;
+  smtp_port: number;
+  username: string;
+  password: string;
+  from: string;
+  to: string;
+}
+
+interface NotifySettings {
+  events: NotifyEventKind[];
+  badge: boolean;
+  email: EmailSettings | null;
+  webhook: string | null;
+  low_disk_bytes: number;
+}
+
+interface AppStoreTelemetry {
+  enabled: boolean;
+  aggregator: string | null;
+  aggregate: boolean;
+}
+
+interface ChannelTest {
+  channel: string;
+  error: string | null;
+}
+
+type RoutingChange =
+  | "Signing"
+  | { Pending: string }
+  | { Confirmed: string }
+  | { Failed: any };
+
+const describeRoutingChange = (change: RoutingChange) => {
+  if (change === "Signing") {
+    return 'approve the transaction in the wallet above to change routing';
+  } else if ('Pending' in change) {
+    return `waiting for transaction ${change.Pending} to be confirmed...`;
+  } else if ('Confirmed' in change) {
+    return `routing changed in transaction ${change.Confirmed}: restart the node to go online with the new routing`;
+  }
+  return `routing change failed: ${JSON.stringify(change.Failed)}`;
+};
+
+const formatBytes = (bytes: number) => {
+  const units = ['B', 'KB', 'MB', 'GB', 'TB'];
+  let i = 0;
+  while (bytes >= 1024 && i < units.length - 1) {
+    bytes /= 1024;
+    i++;
+  }
+  return `${i === 0 ? bytes : bytes.toFixed(1)} ${units[i]}`;
+};
+
+const describeSignRequest = (request: PendingSignature['request']) => {
+  if ('SignMessage' in request) {
+    return `message: ${new TextDecoder().decode(new Uint8Array(request.SignMessage))}`;
+  } else if ('SignTypedData' in request) {
+    return `typed data: ${JSON.stringify(request.SignTypedData, undefined, 2)}`;
+  }
+  return `transaction: ${JSON.stringify(request.SignTransaction, undefined, 2)}`;
+};
+
 interface AppState {
   our_tba: string;
   our_owner: string;
@@ -41,7 +241,18 @@ interface AppState {
   diagnostics: string;
   eth_rpc_providers: any[];
   eth_rpc_access_settings: EthRpcSettings;
+  eth_rpc_provider_health: ProviderHealth[];
+  eth_rpc_provider_tests: ProviderTest[];
+  wallet_address: WalletAddress | null;
+  wallet_pending: PendingSignature[];
+  backups: BackupInfo[];
+  backup_status: BackupStatus | null;
+  schedule: { tasks: ScheduledTask[] };
+  routing_change: RoutingChange | null;
+  notify_settings: NotifySettings | null;
+  app_store_telemetry: AppStoreTelemetry | null;
   process_map: Record<string, ProcessInfo>;
+  process_metrics: ProcessMetrics[];
   stylesheet: string;
 }
 
@@ -49,8 +260,28 @@ function App() {
   const [appState, setAppState] = useState<Partial<AppState>>({});
   const [peerPkiResponse, setPeerPkiResponse] = useState('');
   const [peerPingResponse, setPeerPingResponse] = useState('');
+  const [walletResponse, setWalletResponse] = useState('');
+  const [storageReport, setStorageReport] = useState<PackageStorage[] | null>(null);
+  const [storageResponse, setStorageResponse] = useState('');
+  const [backupResponse, setBackupResponse] = useState('');
+  const [scheduleTask, setScheduleTask] = useState<MaintenanceTask>('AutoBackup');
+  const [scheduleResponse, setScheduleResponse] = useState('');
+  const [configResponse, setConfigResponse] = useState('');
+  const [routingResponse, setRoutingResponse] = useState('');
+  const [notifyResponse, setNotifyResponse] = useState('');
+  const [notifyTests, setNotifyTests] = useState<ChannelTest[] | null>(null);
+  const [telemetryResponse, setTelemetryResponse] = useState('');
+  const [routingDirect, setRoutingDirect] = useState(false);
+  const [processResponse, setProcessResponse] = useState('');
+  const [diagnosticChecks, setDiagnosticChecks] = useState<DiagnosticCheck[] | null>(null);
+  const [diagnosticsResponse, setDiagnosticsResponse] = useState('');
+  const [providerTest, setProviderTest] = useState<ProviderTest | null>(null);
+  const [providerTestResponse, setProviderTestResponse] = useState('');
+  const [processLogs, setProcessLogs] = useState<{ process: string, printouts: ProcessPrintout[] } | null>(null);
 
   const { address } = useAccount();
+  const { signMessageAsync } = useSignMessage();
+  const { signTypedDataAsync } = useSignTypedData();
 
   useEffect(() => {
     // Initial data fetch
@@ -65,8 +296,24 @@ function App() {
       const data = JSON.parse(event.data);
       setAppState(data);
     };
+
+    // processes ask the wallet for signatures at any time, so look for new ones
+    const interval = setInterval(() => {
+      fetch(APP_PATH)
+        .then(response => response.json())
+        .then(data => setAppState(data));
+    }, 10000);
+    return () => clearInterval(interval);
   }, []);
 
+  // sections are linked to by their IDs, as from the homepage's search, but
+  // only exist once our state has loaded
+  useEffect(() => {
+    if (appState.our_tba && location.hash) {
+      document.getElementById(location.hash.slice(1))?.scrollIntoView();
+    }
+  }, [appState.our_tba]);
+
   const apiCall = async (body: any) => {
     return await fetch(APP_PATH, {
       method: 'POST',
@@ -90,6 +337,36 @@ function App() {
     apiCall({ "SetStylesheet": stylesheet });
   };
 
+  const handleExportConfig = async () => {
+    const response = await apiCall("ExportConfig");
+    const data = await response.json();
+    if (!data?.Config) {
+      setConfigResponse(`error: ${JSON.stringify(data)}`);
+      return;
+    }
+    const link = document.createElement('a');
+    link.href = URL.createObjectURL(new Blob([data.Config], { type: 'application/json' }));
+    link.download = `${appState.identity?.name ?? 'node'}-config.json`;
+    link.click();
+    URL.revokeObjectURL(link.href);
+    setConfigResponse('');
+  };
+
+  const handleImportConfig = async (e: React.FormEvent<HTMLFormElement>) => {
+    e.preventDefault();
+    const file = (new FormData(e.currentTarget).get('config') as File | null);
+    if (!file) return;
+    e.currentTarget.reset();
+    const response = await apiCall({ "ImportConfig": await file.text() });
+    try {
+      const data = await response.json();
+      setConfigResponse(`error: ${JSON.stringify(data)}`);
+    } catch (err) {
+      // an empty response is a success
+      setConfigResponse('imported');
+    }
+  };
+
   const handlePeerPki = async (e: React.FormEvent<HTMLFormElement>) => {
     e.preventDefault();
     const formData = new FormData(e.currentTarget);
@@ -155,6 +432,43 @@ function App() {
 
   };
 
+  const handleRunDiagnostics = async () => {
+    setDiagnosticChecks(null);
+    setDiagnosticsResponse('running...');
+    const response = await apiCall("RunNetworkDiagnostics");
+    const data = await response.json();
+    if (data?.NetworkDiagnostics) {
+      setDiagnosticChecks(data.NetworkDiagnostics);
+      setDiagnosticsResponse('');
+    } else {
+      setDiagnosticsResponse(`error: ${JSON.stringify(data)}`);
+    }
+  };
+
+  const handleTestEthProvider = async (e: React.MouseEvent<HTMLButtonElement>) => {
+    const formData = new FormData(e.currentTarget.form!);
+    setProviderTest(null);
+    setProviderTestResponse('testing...');
+    const response = await apiCall({
+      "TestProvider": [Number(formData.get('chain-id')), formData.get('rpc-url') as string]
+    });
+    const data = await response.json();
+    if (data?.ProviderTest) {
+      setProviderTest(data.ProviderTest);
+      setProviderTestResponse('');
+    } else {
+      setProviderTestResponse(`error: ${JSON.stringify(data)}`);
+    }
+  };
+
+  const formatProviderTest = (test: ProviderTest) => {
+    if (test.error !== null) {
+      return `failed: ${test.error}`;
+    }
+    const archive = test.archive === null ? 'archive unknown' : test.archive ? 'archive' : 'not archive';
+    return `${test.latency_ms}ms, block ${test.block_number}, ${archive}`;
+  };
+
   const handleRemoveEthProvider = async (e: React.FormEvent<HTMLFormElement>) => {
     e.preventDefault();
     const formData = new FormData(e.currentTarget);
@@ -173,6 +487,233 @@ function App() {
     }
   };
 
+  const walletCall = async (body: any) => {
+    const response = await apiCall(body);
+    try {
+      const data = await response.json();
+      setWalletResponse(`error: ${JSON.stringify(data)}`);
+    } catch (err) {
+      // an empty response is a success
+      setWalletResponse('');
+    }
+  };
+
+  const handleWalletImportKey = async (e: React.FormEvent<HTMLFormElement>) => {
+    e.preventDefault();
+    const formData = new FormData(e.currentTarget);
+    e.currentTarget.reset();
+    await walletCall({ "WalletImportKey": formData.get('private-key') as string });
+  };
+
+  const handleWalletApprove = async (pending: PendingSignature) => {
+    let signature = null;
+    if (appState.wallet_address?.external) {
+      // the key is held by the connected wallet, which makes the signature itself
+      if (address?.toLowerCase() !== appState.wallet_address.address.toLowerCase()) {
+        setWalletResponse(`connect the wallet holding ${appState.wallet_address.address} to approve`);
+        return;
+      }
+      try {
+        if ('SignMessage' in pending.request) {
+          signature = await signMessageAsync({
+            message: { raw: new Uint8Array(pending.request.SignMessage) },
+          });
+        } else if ('SignTypedData' in pending.request) {
+          const { EIP712Domain, ...types } = pending.request.SignTypedData.types;
+          signature = await signTypedDataAsync({ ...pending.request.SignTypedData, types });
+        }
+      } catch (err) {
+        setWalletResponse(`signing failed: ${err}`);
+        return;
+      }
+    }
+    await walletCall({ "WalletApprove": { id: pending.id, signature } });
+  };
+
+  const processCall = async (body: any) => {
+    const response = await apiCall(body);
+    try {
+      const data = await response.json();
+      setProcessResponse(`error: ${JSON.stringify(data)}`);
+    } catch (err) {
+      // an empty response is a success
+      setProcessResponse('');
+    }
+  };
+
+  const handleProcessLogs = async (process: string) => {
+    const response = await apiCall({ "GetProcessLogs": process });
+    const data = await response.json();
+    if (data?.ProcessLogs) {
+      setProcessLogs({ process, printouts: data.ProcessLogs });
+    } else {
+      setProcessResponse(`error: ${JSON.stringify(data)}`);
+    }
+  };
+
+  const backupCall = async (body: any, success: string) => {
+    const response = await apiCall(body);
+    try {
+      const data = await response.json();
+      setBackupResponse(`error: ${JSON.stringify(data)}`);
+    } catch (err) {
+      // an empty response is a success
+      setBackupResponse(success);
+    }
+  };
+
+  const handleCreateBackup = async (e: React.FormEvent<HTMLFormElement>) => {
+    e.preventDefault();
+    const formData = new FormData(e.currentTarget);
+    e.currentTarget.reset();
+    await backupCall({
+      "CreateBackup": {
+        passphrase: formData.get('passphrase') as string,
+        include_keyfile: formData.get('include-keyfile') === 'on',
+      }
+    }, '');
+  };
+
+  const handleRestoreBackup = async (backup: BackupInfo) => {
+    const passphrase = prompt(`passphrase of the backup from ${new Date(backup.id).toLocaleString()}`);
+    if (passphrase === null) return;
+    await backupCall(
+      { "RestoreBackup": { id: backup.id, passphrase } },
+      'backup staged: restart the node and enter its passphrase to restore it',
+    );
+  };
+
+  const scheduleCall = async (body: any) => {
+    const response = await apiCall(body);
+    try {
+      const data = await response.json();
+      setScheduleResponse(`error: ${JSON.stringify(data)}`);
+    } catch (err) {
+      // an empty response is a success
+      setScheduleResponse('');
+    }
+  };
+
+  const handleSetSchedule = async (e: React.FormEvent<HTMLFormElement>) => {
+    e.preventDefault();
+    const formData = new FormData(e.currentTarget);
+    const passphrase = formData.get('passphrase') as string | null;
+    e.currentTarget.reset();
+    await scheduleCall({
+      "SetSchedule": {
+        task: scheduleTask,
+        cron: formData.get('cron') as string,
+        // left empty to keep the saved backup
+        backup: scheduleTask === 'AutoBackup' && passphrase
+          ? { passphrase, include_keyfile: formData.get('include-keyfile') === 'on' }
+          : null,
+      }
+    });
+  };
+
+  const handleChangeRouting = async (e: React.FormEvent<HTMLFormElement>) => {
+    e.preventDefault();
+    const formData = new FormData(e.currentTarget);
+    const tcpPort = formData.get('tcp-port') as string;
+    const mode = routingDirect
+      ? {
+        "Direct": {
+          ip: formData.get('ip') as string,
+          ws_port: parseInt(formData.get('ws-port') as string),
+          tcp_port: tcpPort ? parseInt(tcpPort) : null,
+        }
+      }
+      : {
+        "Routers": (formData.get('routers') as string)
+          .split(',')
+          .map((router) => router.trim())
+          .filter((router) => router),
+      };
+    const response = await apiCall({ "ChangeRouting": mode });
+    try {
+      const data = await response.json();
+      setRoutingResponse(`error: ${JSON.stringify(data)}`);
+    } catch (err) {
+      // an empty response is a success
+      setRoutingResponse('');
+    }
+  };
+
+  const handleSaveNotifySettings = async (e: React.FormEvent<HTMLFormElement>) => {
+    e.preventDefault();
+    const formData = new FormData(e.currentTarget);
+    const settings: NotifySettings = {
+      events: NOTIFY_EVENT_KINDS
+        .map(([kind]) => kind)
+        .filter((kind) => formData.get(`event-${kind}`) === 'on'),
+      badge: formData.get('badge') === 'on',
+      email: formData.get('email') === 'on'
+        ? {
+          smtp_host: formData.get('smtp-host') as string,
+          smtp_port: parseInt(formData.get('smtp-port') as string),
+          username: formData.get('smtp-username') as string,
+          // left empty to keep the saved password
+          password: formData.get('smtp-password') as string,
+          from: formData.get('email-from') as string,
+          to: formData.get('email-to') as string,
+        }
+        : null,
+      webhook: (formData.get('webhook') as string) || null,
+      low_disk_bytes: Math.round(parseFloat(formData.get('low-disk-gb') as string) * 1e9),
+    };
+    const response = await apiCall({ "SetNotifySettings": settings });
+    try {
+      const data = await response.json();
+      setNotifyResponse(`error: ${JSON.stringify(data)}`);
+    } catch (err) {
+      // an empty response is a success
+      setNotifyResponse('saved');
+    }
+  };
+
+  const handleSaveTelemetry = async (e: React.FormEvent<HTMLFormElement>) => {
+    e.preventDefault();
+    const formData = new FormData(e.currentTarget);
+    const settings: AppStoreTelemetry = {
+      enabled: formData.get('enabled') === 'on',
+      aggregator: (formData.get('aggregator') as string).trim() || null,
+      aggregate: formData.get('aggregate') === 'on',
+    };
+    const response = await apiCall({ "SetAppStoreTelemetry": settings });
+    try {
+      const data = await response.json();
+      setTelemetryResponse(`error: ${JSON.stringify(data)}`);
+    } catch (err) {
+      // an empty response is a success
+      setTelemetryResponse('saved');
+    }
+  };
+
+  const handleTestNotifications = async () => {
+    setNotifyTests(null);
+    setNotifyResponse('sending...');
+    const response = await apiCall("TestNotifications");
+    const data = await response.json();
+    if (data?.NotifyTested) {
+      setNotifyTests(data.NotifyTested);
+      setNotifyResponse(data.NotifyTested.length === 0 ? 'no email or webhook set' : '');
+    } else {
+      setNotifyResponse(`error: ${JSON.stringify(data)}`);
+    }
+  };
+
+  const handleStorageReport = async () => {
+    setStorageResponse('measuring...');
+    const response = await apiCall("GetStorageReport");
+    const data = await response.json();
+    if (data?.StorageReport) {
+      setStorageReport(data.StorageReport);
+      setStorageResponse('');
+    } else {
+      setStorageResponse(`error: ${JSON.stringify(data)}`);
+    }
+  };
+
   return (
     <div>
       <div id="header">
@@ -182,7 +723,25 @@ function App() {
       <main>
         <article id="net-diagnostics">
           <h2>networking diagnostics</h2>
-          <p id="diagnostics">{appState.diagnostics}</p>
+          <button onClick={handleRunDiagnostics}>run diagnostics</button>
+          <p id="diagnostics-response">{diagnosticsResponse}</p>
+          {diagnosticChecks && (
+            <table id="diagnostic-checks">
+              <tbody>
+                {diagnosticChecks.map((check, i) => (
+                  <tr key={i}>
+                    <td>{check.passed ? '✓' : '✗'}</td>
+                    <td>{check.name}</td>
+                    <td>{check.detail}</td>
+                  </tr>
+                ))}
+              </tbody>
+            </table>
+          )}
+          <details>
+            <summary>details</summary>
+            <p id="diagnostics">{appState.diagnostics}</p>
+          </details>
         </article>
 
         <article id="node-info">
@@ -231,8 +790,12 @@ function App() {
             <form id="add-eth-provider" onSubmit={handleAddEthProvider}>
               <input type="number" name="chain-id" placeholder="1" />
               <input type="text" name="rpc-url" placeholder="wss://rpc-url.com" />
+              <button type="button" onClick={handleTestEthProvider}>test provider</button>
               <button type="submit">add provider</button>
             </form>
+            <p id="provider-test-response">
+              {providerTest ? `${providerTest.url}: ${formatProviderTest(providerTest)}` : providerTestResponse}
+            </p>
             <form id="remove-eth-provider" onSubmit={handleRemoveEthProvider}>
               <input type="number" name="chain-id" placeholder="1" />
               <input type="text" name="rpc-url" placeholder="wss://rpc-url.com" />
@@ -244,6 +807,57 @@ function App() {
               <li key={i}>{JSON.stringify(provider, undefined, 2)}</li>
             ))}
           </ul>
+          <h3>provider health</h3>
+          <table id="provider-health">
+            <thead>
+              <tr>
+                <th>chain</th>
+                <th>provider</th>
+                <th>requests</th>
+                <th>error rate</th>
+                <th>latency</th>
+                <th>block</th>
+                <th>status</th>
+              </tr>
+            </thead>
+            <tbody>
+              {appState.eth_rpc_provider_health?.map((health, i) => (
+                <tr key={i}>
+                  <td>{health.chain_id}</td>
+                  <td>{health.provider}</td>
+                  <td>{health.requests} ({health.errors} failed)</td>
+                  <td>{(health.error_rate * 100).toFixed(0)}%</td>
+                  <td>{health.latency_ms === null ? '-' : `${health.latency_ms}ms`}</td>
+                  <td>
+                    {health.block_number ?? '-'}
+                    {health.blocks_behind ? ` (${health.blocks_behind} behind)` : ''}
+                  </td>
+                  <td>{health.demoted ? 'demoted' : 'ok'}</td>
+                </tr>
+              ))}
+            </tbody>
+          </table>
+          <h3>provider benchmarks</h3>
+          <table id="provider-tests">
+            <thead>
+              <tr>
+                <th>chain</th>
+                <th>provider</th>
+                <th>result</th>
+                <th>tested</th>
+              </tr>
+            </thead>
+            <tbody>
+              {appState.eth_rpc_provider_tests?.map((test, i) => (
+                <tr key={i}>
+                  <td>{test.chain_id}</td>
+                  <td>{test.url}</td>
+                  <td>{formatProviderTest(test)}</td>
+                  <td>{new Date(test.tested_at * 1000).toLocaleString()}</td>
+                </tr>
+              ))}
+            </tbody>
+          </table>
         </article>
 
         <article id="eth-rpc-settings">
@@ -277,16 +891,93 @@ function App() {
           </article>
         </article>
 
+        <article id="wallet">
+          <h2>wallet</h2>
+          {appState.wallet_address ? (
+            <>
+              <p id="wallet-address">
+                {appState.wallet_address.address}
+                {appState.wallet_address.external ? ' (external)' : ''}
+              </p>
+              <button onClick={() => walletCall("WalletRemoveKey")}>remove key</button>
+            </>
+          ) : (
+            <>
+              <p>no key set</p>
+              <button onClick={() => walletCall("WalletGenerateKey")}>generate key</button>
+              <form id="wallet-import-key" onSubmit={handleWalletImportKey}>
+                <input type="password" name="private-key" placeholder="0x..." />
+                <button type="submit">import key</button>
+              </form>
+              {address && (
+                <button onClick={() => walletCall({ "WalletSetExternal": address })}>
+                  sign with connected wallet {address}
+                </button>
+              )}
+            </>
+          )}
+          <p id="wallet-response">{walletResponse}</p>
+          <h3>signatures awaiting approval</h3>
+          <ul id="wallet-pending">
+            {appState.wallet_pending?.length === 0 ? (
+              <li>(none)</li>
+            ) : (
+              appState.wallet_pending?.map((pending) => (
+                <li key={pending.id}>
+                  <p>
+                    {pending.process} at {new Date(pending.requested_at * 1000).toLocaleString()}
+                  </p>
+                  <pre>{describeSignRequest(pending.request)}</pre>
+                  <button onClick={() => handleWalletApprove(pending)}>approve</button>
+                  <button onClick={() => walletCall({ "WalletDeny": pending.id })}>deny</button>
+                </li>
+              ))
+            )}
+          </ul>
+        </article>
+
         <article id="kernel">
           <h2>running processes</h2>
+          <p id="process-response">{processResponse}</p>
           <ul id="process-map">
-            {Object.entries(appState.process_map || {}).map(([id, process]) => (
+            {Object.entries(appState.process_map || {}).map(([id, process]) => {
+              const metrics = appState.process_metrics?.find((m) => m.process === id);
+              const packageId = id.split(':').slice(1).join(':');
+              return (
               <li key={id}>
                 <button onClick={(e) => {
                   const details = e.currentTarget.nextElementSibling as HTMLElement;
                   details.style.display = details.style.display === 'none' ? 'block' : 'none';
-                }}>{id}</button>
+                }}>{id}{metrics?.suspended ? ' (suspended)' : ''}</button>
                 <div style={{ display: 'none' }}>
+                  {metrics && (
+                    <p>
+                      queue: {metrics.queue_depth}{metrics.queue_limit !== null && `/${metrics.queue_limit}`}
+                      {metrics.dropped_messages > 0 && ` (${metrics.dropped_messages} dropped)`}, received: {metrics.messages_received}, sent: {metrics.messages_sent}
+                      {metrics.memory_bytes !== null && `, memory: ${formatBytes(metrics.memory_bytes)}`}
+                      {metrics.last_activity !== null && `, last active ${new Date(metrics.last_activity * 1000).toLocaleString()}`}
+                    </p>
+                  )}
+                  {process.wasm_bytes_handle && (
+                    <>
+                      {metrics?.suspended ? (
+                        <button onClick={() => processCall({ "ResumeProcess": id })}>resume</button>
+                      ) : (
+                        <button onClick={() => processCall({ "SuspendProcess": id })}>suspend</button>
+                      )}
+                      <button onClick={() => processCall({ "RestartProcess": id })}>restart</button>
+                      <button onClick={() => processCall({ "RestartPackage": packageId })}>restart {packageId}</button>
+                      <button onClick={() => processCall({ "KillProcess": id })}>kill</button>
+                    </>
+                  )}
+                  <button onClick={() => handleProcessLogs(id)}>logs</button>
+                  {processLogs?.process === id && (
+                    <pre className="process-logs">
+                      {processLogs.printouts.length === 0 ? '(none)' : processLogs.printouts.map((printout) =>
+                        `${new Date(printout.timestamp * 1000).toLocaleTimeString()} [${printout.verbosity}] ${printout.content}`
+                      ).join('\n')}
+                    </pre>
+                  )}
                   <p>public: {String(process.public)}</p>
                   <p>on_exit: {process.on_exit}</p>
                   {process.wit_version && <p>wit_version: {process.wit_version}</p>}
@@ -298,10 +989,222 @@ function App() {
                   </ul>
                 </div>
               </li>
-            ))}
+              );
+            })}
           </ul>
         </article>
 
+        <article id="storage">
+          <h2>storage</h2>
+          <button onClick={handleStorageReport}>report storage used by each package</button>
+          <p id="storage-response">{storageResponse}</p>
+          {storageReport && (
+            <ul id="storage-report">
+              {storageReport.map((pkg) => (
+                <li key={pkg.package_id}>
+                  <button onClick={(e) => {
+                    const details = e.currentTarget.nextElementSibling as HTMLElement;
+                    details.style.display = details.style.display === 'none' ? 'block' : 'none';
+                  }}>{pkg.package_id}: {formatBytes(pkg.bytes)}</button>
+                  <ul style={{ display: 'none' }}>
+                    {pkg.drives.map((drive) => (
+                      <li key={drive.drive}>
+                        drive {drive.drive}: {formatBytes(drive.bytes)}
+                        {drive.quota !== null ? ` of ${formatBytes(drive.quota)}` : ''}
+                      </li>
+                    ))}
+                    {pkg.sqlite.map((db) => (
+                      <li key={`sqlite-${db.db}`}>sqlite {db.db}: {formatBytes(db.bytes)}</li>
+                    ))}
+                    {pkg.kv.map((db) => (
+                      <li key={`kv-${db.db}`}>kv {db.db}: {formatBytes(db.bytes)}</li>
+                    ))}
+                  </ul>
+                </li>
+              ))}
+            </ul>
+          )}
+        </article>
+
+        <article id="backups">
+          <h2>backups</h2>
+          <form id="create-backup" onSubmit={handleCreateBackup}>
+            <input type="password" name="passphrase" placeholder="passphrase" required />
+            <label>
+              <input type="checkbox" name="include-keyfile" />
+              include keyfile
+            </label>
+            <button type="submit">back up node</button>
+          </form>
+          {appState.backup_status && (
+            <p id="backup-status">{describeBackupStatus(appState.backup_status)}</p>
+          )}
+          <p id="backup-response">{backupResponse}</p>
+          <ul id="backup-list">
+            {appState.backups?.length === 0 ? (
+              <li>(none)</li>
+            ) : (
+              appState.backups?.map((backup) => (
+                <li key={backup.id}>
+                  {new Date(backup.id).toLocaleString()}: {formatBytes(backup.bytes)},
+                  {' '}{backup.settings_files} settings files, {backup.databases} databases,
+                  {' '}{backup.apps} apps{backup.keyfile ? ', keyfile' : ''}
+                  <button onClick={() => handleRestoreBackup(backup)}>restore</button>
+                  <button onClick={() => backupCall({ "RemoveBackup": backup.id }, '')}>remove</button>
+                </li>
+              ))
+            )}
+          </ul>
+        </article>
+
+        <article id="scheduler">
+          <h2>scheduled maintenance</h2>
+          <ul id="schedule-list">
+            {!appState.schedule || appState.schedule.tasks.length === 0 ? (
+              <li>(none)</li>
+            ) : (
+              appState.schedule.tasks.map((task) => (
+                <li key={task.task}>
+                  {MAINTENANCE_TASKS.find(([t]) => t === task.task)?.[1]}: <code>{task.cron}</code>,
+                  {' '}next {new Date(task.next_run).toLocaleString()}
+                  {task.last_run && `, last ${new Date(task.last_run).toLocaleString()}`}
+                  {task.last_error && ` (failed: ${task.last_error})`}
+                  <button onClick={() => scheduleCall({ "RemoveSchedule": task.task })}>remove</button>
+                </li>
+              ))
+            )}
+          </ul>
+          <form id="set-schedule" onSubmit={handleSetSchedule}>
+            <select
+              value={scheduleTask}
+              onChange={(e) => setScheduleTask(e.target.value as MaintenanceTask)}
+            >
+              {MAINTENANCE_TASKS.map(([task, label]) => (
+                <option key={task} value={task}>{label}</option>
+              ))}
+            </select>
+            <input type="text" name="cron" placeholder="cron spec in UTC, e.g. 0 3 * * *" required />
+            {scheduleTask === 'AutoBackup' && (
+              <>
+                <input
+                  type="password"
+                  name="passphrase"
+                  placeholder={appState.schedule?.tasks.some((t) => t.task === 'AutoBackup')
+                    ? 'passphrase (unchanged)'
+                    : 'passphrase'}
+                />
+                <label>
+                  <input type="checkbox" name="include-keyfile" />
+                  include keyfile
+                </label>
+              </>
+            )}
+            <button type="submit">schedule</button>
+          </form>
+          <p id="schedule-response">{scheduleResponse}</p>
+        </article>
+
+        <article id="app-store-telemetry">
+          <h2>app store telemetry</h2>
+          <p>
+            Tell the publishers of the apps you install and update that someone did, so they can see how their apps are used.
+            Reports name the app and version only, and are sent in batches hours later, but the node receiving them can still tell they came from you.
+          </p>
+          {appState.app_store_telemetry ? (
+            <form
+              id="telemetry-settings"
+              key={JSON.stringify(appState.app_store_telemetry)}
+              onSubmit={handleSaveTelemetry}
+            >
+              <label>
+                <input type="checkbox" name="enabled" defaultChecked={appState.app_store_telemetry.enabled} />
+                report installs and updates
+              </label>
+              <input
+                type="text"
+                name="aggregator"
+                placeholder="aggregator node, in place of each publisher (optional)"
+                defaultValue={appState.app_store_telemetry.aggregator ?? ''}
+              />
+              <label>
+                <input type="checkbox" name="aggregate" defaultChecked={appState.app_store_telemetry.aggregate} />
+                count reports of nodes that chose this one as their aggregator
+              </label>
+              <button type="submit">save telemetry settings</button>
+            </form>
+          ) : (
+            <p>the App Store is not running</p>
+          )}
+          <p id="telemetry-response">{telemetryResponse}</p>
+        </article>
+
+        <article id="notifications">
+          <h2>notifications</h2>
+          <p>notifications are listed in the inbox on the homepage.</p>
+          {appState.notify_settings && (
+            <form
+              id="notify-settings"
+              key={JSON.stringify(appState.notify_settings)}
+              onSubmit={handleSaveNotifySettings}
+            >
+              <p>notify of:</p>
+              {NOTIFY_EVENT_KINDS.map(([kind, label]) => (
+                <label key={kind}>
+                  <input
+                    type="checkbox"
+                    name={`event-${kind}`}
+                    defaultChecked={appState.notify_settings?.events.includes(kind)}
+                  />
+                  {label}
+                </label>
+              ))}
+              <label>
+                disk space is low below
+                <input
+                  type="number"
+                  name="low-disk-gb"
+                  min={0}
+                  step={0.1}
+                  defaultValue={appState.notify_settings.low_disk_bytes / 1e9}
+                />
+                GB
+              </label>
+              <p>deliver by:</p>
+              <label>
+                <input type="checkbox" name="badge" defaultChecked={appState.notify_settings.badge} />
+                the homepage inbox
+              </label>
+              <label>
+                <input type="checkbox" name="email" defaultChecked={!!appState.notify_settings.email} />
+                email
+              </label>
+              <input type="text" name="smtp-host" placeholder="SMTP host" defaultValue={appState.notify_settings.email?.smtp_host} />
+              <input type="number" name="smtp-port" placeholder="SMTP port, e.g. 587" min={1} max={65535} defaultValue={appState.notify_settings.email?.smtp_port ?? 587} />
+              <input type="text" name="smtp-username" placeholder="SMTP username" defaultValue={appState.notify_settings.email?.username} />
+              <input
+                type="password"
+                name="smtp-password"
+                placeholder={appState.notify_settings.email ? 'SMTP password (unchanged)' : 'SMTP password'}
+              />
+              <input type="email" name="email-from" placeholder="from" defaultValue={appState.notify_settings.email?.from} />
+              <input type="email" name="email-to" placeholder="to" defaultValue={appState.notify_settings.email?.to} />
+              <input type="url" name="webhook" placeholder="webhook URL (optional)" defaultValue={appState.notify_settings.webhook ?? ''} />
+              <button type="submit">save notification settings</button>
+            </form>
+          )}
+          <button onClick={handleTestNotifications}>send test notification</button>
+          <p id="notify-response">{notifyResponse}</p>
+          {notifyTests && notifyTests.length > 0 && (
+            <ul id="notify-tests">
+              {notifyTests.map((test) => (
+                <li key={test.channel}>
+                  {test.channel}: {test.error ? `failed: ${test.error}` : 'sent'}
+                </li>
+              ))}
+            </ul>
+          )}
+        </article>
+
         <article id="id-onchain">
           <h2>identity onchain</h2>
           <p>Only use this utility if you *really* know what you're doing. If edited incorrectly, your node may be unable to connect to the network and require re-registration.</p>
@@ -322,11 +1225,55 @@ function App() {
           <EditNote tba={appState.our_tba || ''} field_placeholder="note content" />
         </article>
 
+        <article id="routing">
+          <h2>routing</h2>
+          <p>Change how other nodes reach yours: directly at a public IP, or through routers. The notes are updated in a transaction signed by the wallet above, whose key must own the node. The node goes online with the new routing once restarted.</p>
+          <form id="change-routing" onSubmit={handleChangeRouting}>
+            <label>
+              <input type="radio" name="mode" checked={!routingDirect} onChange={() => setRoutingDirect(false)} />
+              indirect
+            </label>
+            <label>
+              <input type="radio" name="mode" checked={routingDirect} onChange={() => setRoutingDirect(true)} />
+              direct
+            </label>
+            {routingDirect ? (
+              <>
+                <input type="text" name="ip" placeholder="public IP, e.g. 1.2.3.4" required />
+                <input type="number" name="ws-port" placeholder="ws port, e.g. 9000" min={1} max={65535} required />
+                <input type="number" name="tcp-port" placeholder="tcp port (optional)" min={1} max={65535} />
+              </>
+            ) : (
+              <input type="text" name="routers" placeholder="router names, separated by commas" required />
+            )}
+            <button type="submit">change routing</button>
+          </form>
+          {appState.routing_change && (
+            <p id="routing-status">{describeRoutingChange(appState.routing_change)}</p>
+          )}
+          <p id="routing-response">{routingResponse}</p>
+        </article>
+
         <article id="kinode-css">
           <h2>stylesheet editor</h2>
           <textarea id="stylesheet-editor" defaultValue={appState.stylesheet} />
           <button id="save-stylesheet" onClick={handleSaveStylesheet}>update kinode.css</button>
         </article>
+
+        <article id="node-config">
+          <h2>node configuration</h2>
+          <p>
+            ETH RPC providers and access, the stylesheet, notification settings and
+            scheduled maintenance, as one file to set up another node the same way.
+            The SMTP password and auto-backup passphrase are left out.
+          </p>
+          <button onClick={handleExportConfig}>export</button>
+          <form id="import-config" onSubmit={handleImportConfig}>
+            <input type="file" name="config" accept="application/json" required />
+            <button type="submit">import</button>
+          </form>
+          <p id="config-response">{configResponse}</p>
+        </article>
       </main>
     </div>
   );
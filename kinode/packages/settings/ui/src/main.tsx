@@ -6,7 +6,8 @@ import ReactDOM from 'react-dom/client'
 import { WagmiProvider } from 'wagmi'
 import { http, createConfig } from 'wagmi'
 import { optimism } from 'wagmi/chains'
-import { RainbowKitProvider } from '@rainbow-me/rainbowkit';
+import { connectorsForWallets, RainbowKitProvider } from '@rainbow-me/rainbowkit';
+import { ledgerWallet } from '@rainbow-me/rainbowkit/wallets';
 
 import App from './App.tsx'
 
@@ -14,7 +15,24 @@ import '@rainbow-me/rainbowkit/styles.css';
 
 globalThis.Buffer = Buffer
 
+// browser wallets are found on their own; hardware wallets need a connector.
+// Trezor has no connector of its own, but signs through MetaMask or Rabby.
+const connectors = connectorsForWallets(
+  [
+    {
+      groupName: 'Hardware',
+      wallets: [ledgerWallet],
+    },
+  ],
+  {
+    appName: 'Kinode Settings',
+    // served by the node, like the register UI, so shares its project
+    projectId: 'c6da298e8ee4e4b00ea32cd4c20c40af',
+  },
+);
+
 export const config = createConfig({
+  connectors,
   chains: [optimism],
   transports: {
     [optimism.id]: http(),
@@ -0,0 +1,600 @@
+//! webdav:settings:sys
+//!
+//! Serves the VFS drives that the user has shared over WebDAV, at
+//! `/webdav:settings:sys/<package-id>/<drive>/...`, so that they can be mounted
+//! as a network drive on a desktop and managed with drag and drop.
+//!
+//! Nothing is served until a drive is shared with a `share` request, as sent by
+//! the `share:settings:sys` terminal script. Each share is read-only unless it
+//! is made writable.
+//!
+//! The path is bound as authenticated, so a WebDAV client must present an auth
+//! token, such as one issued to a paired client by `/pair`, as the password of
+//! its credentials; the user name is ignored.
+//!
+//! The names of the files in shared drives are also searched by the homepage's
+//! launcher, which links to them here.
+use crate::kinode::process::webdav::{Request as WebDavRequest, Response as WebDavResponse, Share};
+use kinode_process_lib::{
+    await_message, call_init, get_blob, get_state, http, println, set_state, Address, LazyLoadBlob,
+    Message, Request, Response,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use search_api::{
+    register_search_provider, SearchQuery, SearchRequest, SearchResponse, SearchResult,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, VecDeque},
+};
+
+mod search_api;
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "settings-sys-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+const VFS_TIMEOUT: u64 = 15;
+/// most directories read for a search, so that large drives don't keep the
+/// homepage waiting past its timeout
+const MAX_SEARCHED_DIRS: usize = 200;
+
+/// characters escaped when a VFS path is written into an href
+const HREF: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+const ALLOWED_METHODS: &str =
+    "OPTIONS, PROPFIND, PROPPATCH, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// shared drives, mapped to whether they are writable
+    shares: BTreeMap<String, bool>,
+}
+
+impl State {
+    fn load() -> Self {
+        get_state()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        set_state(&serde_json::to_vec(self).unwrap());
+    }
+
+    fn shares(&self) -> Vec<Share> {
+        self.shares
+            .iter()
+            .map(|(drive, writable)| Share {
+                drive: drive.clone(),
+                writable: *writable,
+            })
+            .collect()
+    }
+
+    /// the share that a VFS path falls within, and whether it is writable
+    fn share_of(&self, path: &str) -> Option<(&str, bool)> {
+        let mut segments = path.trim_start_matches('/').splitn(3, '/');
+        let drive = format!("/{}/{}", segments.next()?, segments.next()?);
+        self.shares
+            .get_key_value(&drive)
+            .map(|(drive, writable)| (drive.as_str(), *writable))
+    }
+}
+
+/// how a resource appears in a PROPFIND listing
+struct Resource {
+    path: String,
+    collection: bool,
+    len: u64,
+}
+
+call_init!(init);
+fn init(our: Address) {
+    let mut state = State::load();
+    // incremented to give each LOCK a distinct token
+    let locks = Cell::new(0u64);
+
+    let mut http_server = http::server::HttpServer::new(5);
+    http_server
+        .bind_http_path("/", http::server::HttpBindingConfig::default())
+        .unwrap();
+
+    // have the homepage send us searches
+    register_search_provider(&our, "Files");
+
+    loop {
+        let message = match await_message() {
+            Err(send_error) => {
+                println!("got send error: {send_error:?}");
+                continue;
+            }
+            Ok(message) => message,
+        };
+        let Message::Request {
+            ref source,
+            ref body,
+            expects_response,
+            ..
+        } = message
+        else {
+            continue; // ignore responses
+        };
+        if source.node() != our.node {
+            continue; // ignore messages from other nodes
+        }
+        if source.process == "http-server:distro:sys" {
+            let Ok(server_request) = http_server.parse_request(body) else {
+                continue;
+            };
+            http_server.handle_request(
+                server_request,
+                |req| match handle_http_request(&our, &state, &locks, &req) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        println!("error handling WebDAV request: {e}");
+                        (
+                            http::server::HttpResponse::new(
+                                http::StatusCode::INTERNAL_SERVER_ERROR,
+                            ),
+                            Some(LazyLoadBlob::new(Some("text/plain"), e.to_string())),
+                        )
+                    }
+                },
+                |_channel_id, _message_type, _blob| {
+                    // we don't expect websocket messages
+                },
+            );
+        } else if source.process == "homepage:homepage:sys" {
+            if let Ok(SearchRequest::Search(query)) = serde_json::from_slice(body) {
+                Response::new()
+                    .body(
+                        serde_json::to_vec(&SearchResponse::Search(search(&state, &query)))
+                            .unwrap(),
+                    )
+                    .send()
+                    .unwrap();
+            }
+        } else {
+            let response = handle_request(&mut state, body);
+            if expects_response.is_some() {
+                Response::new()
+                    .body(serde_json::to_vec(&response).unwrap())
+                    .send()
+                    .unwrap();
+            }
+        }
+    }
+}
+
+fn handle_request(state: &mut State, body: &[u8]) -> WebDavResponse {
+    let request = serde_json::from_slice::<WebDavRequest>(body)
+        .map_err(|e| format!("malformed request: {e}"))?;
+    match request {
+        WebDavRequest::Share(Share { drive, writable }) => {
+            let drive = drive.trim_end_matches('/').to_string();
+            let segments = drive.trim_start_matches('/').split('/').collect::<Vec<_>>();
+            if segments.len() != 2 || segments[1].is_empty() || segments[1].starts_with('.') {
+                return Err(format!(
+                    "{drive} is not a drive: expected /<package-id>/<drive>"
+                ));
+            }
+            match metadata(&drive) {
+                Some(resource) if resource.collection => {}
+                _ => return Err(format!("drive {drive} does not exist")),
+            }
+            state.shares.insert(drive, writable);
+        }
+        WebDavRequest::Unshare(drive) => {
+            if state.shares.remove(drive.trim_end_matches('/')).is_none() {
+                return Err(format!("{drive} is not shared"));
+            }
+        }
+        WebDavRequest::Shares => return Ok(state.shares()),
+    }
+    state.save();
+    Ok(state.shares())
+}
+
+/// The files and directories in shared drives whose names hold every word of
+/// the query, nearest the top of their drive first.
+fn search(state: &State, query: &SearchQuery) -> Vec<SearchResult> {
+    let words: Vec<String> = query
+        .query
+        .to_lowercase()
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    let mut results = vec![];
+    let mut dirs: VecDeque<String> = state.shares.keys().cloned().collect();
+    let mut searched = 0;
+    while let Some(dir) = dirs.pop_front() {
+        if searched == MAX_SEARCHED_DIRS || results.len() >= query.limit as usize {
+            break;
+        }
+        searched += 1;
+        let Ok(entries) = vfs(&dir, serde_json::json!("ReadDir"), None) else {
+            continue;
+        };
+        for entry in entries["ReadDir"].as_array().into_iter().flatten() {
+            let Some(entry_path) = entry["path"].as_str() else {
+                continue;
+            };
+            let entry_path = format!("/{}", entry_path.trim_start_matches('/'));
+            if entry["file_type"] == "Directory" {
+                dirs.push_back(entry_path.clone());
+            }
+            let name = entry_path.rsplit('/').next().unwrap_or_default();
+            let lowercase = name.to_lowercase();
+            if words.iter().all(|word| lowercase.contains(word)) {
+                results.push(SearchResult {
+                    title: name.to_string(),
+                    description: Some(dir.clone()),
+                    path: utf8_percent_encode(&entry_path, HREF).to_string(),
+                });
+            }
+        }
+    }
+    results.truncate(query.limit as usize);
+    results
+}
+
+fn handle_http_request(
+    our: &Address,
+    state: &State,
+    locks: &Cell<u64>,
+    req: &http::server::IncomingHttpRequest,
+) -> anyhow::Result<(http::server::HttpResponse, Option<LazyLoadBlob>)> {
+    let method = req.method()?;
+    let path = vfs_path(&req.path()?)?;
+
+    if method.as_str() == "OPTIONS" {
+        return Ok((
+            http::server::HttpResponse::new(http::StatusCode::OK)
+                .header("DAV", "1, 2")
+                .header("Allow", ALLOWED_METHODS)
+                .header("MS-Author-Via", "DAV"),
+            None,
+        ));
+    }
+
+    // the root lists the shares, and can't be changed
+    if path == "/" {
+        return match method.as_str() {
+            "PROPFIND" => {
+                let mut resources = vec![Resource {
+                    path,
+                    collection: true,
+                    len: 0,
+                }];
+                if depth(req) != "0" {
+                    resources.extend(state.shares.keys().map(|drive| Resource {
+                        path: drive.clone(),
+                        collection: true,
+                        len: 0,
+                    }));
+                }
+                Ok(multistatus(our, &resources))
+            }
+            _ => Ok(status(http::StatusCode::METHOD_NOT_ALLOWED)),
+        };
+    }
+
+    let Some((drive, writable)) = state.share_of(&path) else {
+        return Ok(status(http::StatusCode::NOT_FOUND));
+    };
+    let changes = !matches!(
+        method.as_str(),
+        "PROPFIND" | "GET" | "HEAD" | "LOCK" | "UNLOCK"
+    );
+    if changes && !writable {
+        return Ok(status(http::StatusCode::FORBIDDEN));
+    }
+    // the drive itself can be written into, but not replaced or removed
+    if path == drive && matches!(method.as_str(), "PUT" | "DELETE" | "MKCOL" | "MOVE") {
+        return Ok(status(http::StatusCode::FORBIDDEN));
+    }
+    let existing = metadata(&path);
+
+    match method.as_str() {
+        "PROPFIND" => {
+            let Some(resource) = existing else {
+                return Ok(status(http::StatusCode::NOT_FOUND));
+            };
+            let mut resources = vec![];
+            if resource.collection && depth(req) != "0" {
+                let entries = vfs(&path, serde_json::json!("ReadDir"), None)?;
+                for entry in entries["ReadDir"].as_array().into_iter().flatten() {
+                    let Some(entry_path) = entry["path"].as_str() else {
+                        continue;
+                    };
+                    let entry_path = format!("/{}", entry_path.trim_start_matches('/'));
+                    if entry["file_type"] == "Directory" {
+                        resources.push(Resource {
+                            path: entry_path,
+                            collection: true,
+                            len: 0,
+                        });
+                    } else if let Some(entry) = metadata(&entry_path) {
+                        resources.push(entry);
+                    }
+                }
+            }
+            resources.insert(0, resource);
+            Ok(multistatus(our, &resources))
+        }
+        "GET" | "HEAD" => match existing {
+            None => Ok(status(http::StatusCode::NOT_FOUND)),
+            Some(resource) if resource.collection => {
+                Ok(status(http::StatusCode::METHOD_NOT_ALLOWED))
+            }
+            Some(_) if method.as_str() == "HEAD" => Ok((
+                http::server::HttpResponse::new(http::StatusCode::OK)
+                    .header("Content-Type", "application/octet-stream"),
+                None,
+            )),
+            Some(_) => {
+                vfs(&path, serde_json::json!("Read"), None)?;
+                let bytes = get_blob().map(|blob| blob.bytes).unwrap_or_default();
+                Ok((
+                    http::server::HttpResponse::new(http::StatusCode::OK)
+                        .header("Content-Type", "application/octet-stream"),
+                    Some(LazyLoadBlob::new(Some("application/octet-stream"), bytes)),
+                ))
+            }
+        },
+        "PUT" => {
+            if existing
+                .as_ref()
+                .is_some_and(|resource| resource.collection)
+            {
+                return Ok(status(http::StatusCode::METHOD_NOT_ALLOWED));
+            }
+            let bytes = get_blob().map(|blob| blob.bytes).unwrap_or_default();
+            vfs(&path, serde_json::json!("WriteAtomic"), Some(bytes))?;
+            Ok(status(match existing {
+                Some(_) => http::StatusCode::NO_CONTENT,
+                None => http::StatusCode::CREATED,
+            }))
+        }
+        "DELETE" => {
+            match existing {
+                None => return Ok(status(http::StatusCode::NOT_FOUND)),
+                Some(resource) if resource.collection => {
+                    vfs(&path, serde_json::json!("RemoveDirAll"), None)?
+                }
+                Some(_) => vfs(&path, serde_json::json!("RemoveFile"), None)?,
+            };
+            Ok(status(http::StatusCode::NO_CONTENT))
+        }
+        "MKCOL" => {
+            if existing.is_some() {
+                return Ok(status(http::StatusCode::METHOD_NOT_ALLOWED));
+            }
+            vfs(&path, serde_json::json!("CreateDir"), None)?;
+            Ok(status(http::StatusCode::CREATED))
+        }
+        "COPY" | "MOVE" => {
+            let Some(resource) = existing else {
+                return Ok(status(http::StatusCode::NOT_FOUND));
+            };
+            let Some(destination) = req
+                .headers()
+                .get("Destination")
+                .and_then(|value| value.to_str().ok())
+                .map(destination_path)
+            else {
+                return Ok(status(http::StatusCode::BAD_REQUEST));
+            };
+            let destination = vfs_path(&destination)?;
+            match state.share_of(&destination) {
+                Some((drive, true)) if destination != drive => {}
+                _ => return Ok(status(http::StatusCode::FORBIDDEN)),
+            }
+            let overwrite = req
+                .headers()
+                .get("Overwrite")
+                .and_then(|value| value.to_str().ok())
+                .map_or(true, |value| !value.eq_ignore_ascii_case("F"));
+            let replaced = match metadata(&destination) {
+                Some(_) if !overwrite => return Ok(status(http::StatusCode::PRECONDITION_FAILED)),
+                Some(target) if target.collection => {
+                    vfs(&destination, serde_json::json!("RemoveDirAll"), None)?;
+                    true
+                }
+                Some(_) => {
+                    vfs(&destination, serde_json::json!("RemoveFile"), None)?;
+                    true
+                }
+                None => false,
+            };
+            let action = match (method.as_str(), resource.collection) {
+                ("COPY", true) => serde_json::json!({
+                    "CopyDir": { "new_path": destination, "progress": false }
+                }),
+                ("COPY", false) => serde_json::json!({ "CopyFile": { "new_path": destination } }),
+                (_, true) => serde_json::json!({ "MoveDir": { "new_path": destination } }),
+                (_, false) => serde_json::json!({ "Rename": { "new_path": destination } }),
+            };
+            vfs(&path, action, None)?;
+            Ok(status(match replaced {
+                true => http::StatusCode::NO_CONTENT,
+                false => http::StatusCode::CREATED,
+            }))
+        }
+        // properties are derived from the files themselves, so changes to them
+        // are accepted and dropped, as clients set e.g. timestamps after a PUT
+        "PROPPATCH" => Ok(multistatus(
+            our,
+            &[Resource {
+                path,
+                collection: existing.is_some_and(|resource| resource.collection),
+                len: 0,
+            }],
+        )),
+        // locks are not enforced, but clients such as Finder only mount a drive
+        // writable if they can take them
+        "LOCK" => {
+            locks.set(locks.get() + 1);
+            let token = format!("opaquelocktoken:{}-{}", our.process, locks.get());
+            let body = format!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+                <D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+                <D:locktype><D:write/></D:locktype>\
+                <D:lockscope><D:exclusive/></D:lockscope>\
+                <D:depth>{}</D:depth>\
+                <D:timeout>Second-3600</D:timeout>\
+                <D:locktoken><D:href>{token}</D:href></D:locktoken>\
+                </D:activelock></D:lockdiscovery></D:prop>",
+                depth(req),
+            );
+            Ok((
+                http::server::HttpResponse::new(http::StatusCode::OK)
+                    .header("Content-Type", "application/xml; charset=utf-8")
+                    .header("Lock-Token", format!("<{token}>")),
+                Some(LazyLoadBlob::new(Some("application/xml"), body)),
+            ))
+        }
+        "UNLOCK" => Ok(status(http::StatusCode::NO_CONTENT)),
+        _ => Ok((
+            http::server::HttpResponse::new(http::StatusCode::METHOD_NOT_ALLOWED)
+                .header("Allow", ALLOWED_METHODS),
+            None,
+        )),
+    }
+}
+
+/// the VFS path named by a request path, which is relative to our bound path
+fn vfs_path(request_path: &str) -> anyhow::Result<String> {
+    let path = percent_decode_str(request_path).decode_utf8()?;
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect::<Vec<_>>();
+    if segments.contains(&"..") {
+        return Err(anyhow::anyhow!("path {path} leaves its drive"));
+    }
+    Ok(format!("/{}", segments.join("/")))
+}
+
+/// the request path of a `Destination` header, which is usually a full URL
+fn destination_path(destination: &str) -> String {
+    let path = match destination.split_once("://") {
+        Some((_scheme, rest)) => rest.find('/').map_or("/", |i| &rest[i..]),
+        None => destination,
+    };
+    // strip our bound path, leaving the part that names a VFS path
+    let path = path.trim_start_matches('/');
+    match path.split_once('/') {
+        Some((_process, rest)) => format!("/{rest}"),
+        None => "/".to_string(),
+    }
+}
+
+fn depth(req: &http::server::IncomingHttpRequest) -> String {
+    req.headers()
+        .get("Depth")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("infinity")
+        .to_string()
+}
+
+fn metadata(path: &str) -> Option<Resource> {
+    let response = vfs(path, serde_json::json!("Metadata"), None).ok()?;
+    let metadata = &response["Metadata"];
+    Some(Resource {
+        path: path.to_string(),
+        collection: metadata["file_type"] == "Directory",
+        len: metadata["len"].as_u64().unwrap_or_default(),
+    })
+}
+
+/// send an action to the VFS as JSON, since not all the actions used here are
+/// in process_lib's `VfsAction`, returning the response if it is not an error
+fn vfs(
+    path: &str,
+    action: serde_json::Value,
+    blob: Option<Vec<u8>>,
+) -> anyhow::Result<serde_json::Value> {
+    let mut request = Request::to(("our", "vfs", "distro", "sys")).body(serde_json::to_vec(
+        &serde_json::json!({ "path": path, "action": action }),
+    )?);
+    if let Some(bytes) = blob {
+        request = request.blob_bytes(bytes);
+    }
+    let response = request.send_and_await_response(VFS_TIMEOUT)??;
+    let response = serde_json::from_slice::<serde_json::Value>(response.body())?;
+    if let Some(e) = response.get("Err") {
+        return Err(anyhow::anyhow!("VFS error on {path}: {e}"));
+    }
+    Ok(response)
+}
+
+fn status(status: http::StatusCode) -> (http::server::HttpResponse, Option<LazyLoadBlob>) {
+    (http::server::HttpResponse::new(status), None)
+}
+
+fn multistatus(
+    our: &Address,
+    resources: &[Resource],
+) -> (http::server::HttpResponse, Option<LazyLoadBlob>) {
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    for resource in resources {
+        let name = resource.path.rsplit('/').next().unwrap_or_default();
+        let href = format!(
+            "/{}{}{}",
+            our.process,
+            utf8_percent_encode(&resource.path, HREF),
+            if resource.collection && resource.path != "/" {
+                "/"
+            } else {
+                ""
+            },
+        );
+        let properties = if resource.collection {
+            "<D:resourcetype><D:collection/></D:resourcetype>".to_string()
+        } else {
+            format!(
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+                <D:getcontenttype>application/octet-stream</D:getcontenttype>",
+                resource.len
+            )
+        };
+        body.push_str(&format!(
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+            <D:displayname>{}</D:displayname>{properties}</D:prop>\
+            <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+            escape_xml(&href),
+            escape_xml(name),
+        ));
+    }
+    body.push_str("</D:multistatus>\n");
+    (
+        http::server::HttpResponse::new(http::StatusCode::MULTI_STATUS)
+            .header("Content-Type", "application/xml; charset=utf-8"),
+        Some(LazyLoadBlob::new(Some("application/xml"), body)),
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,4 +1,6 @@
-use crate::kinode::process::terminal::{EditAliasRequest, Request as TerminalRequest};
+use crate::kinode::process::terminal::{
+    EditAliasRequest, EditMacroRequest, Request as TerminalRequest,
+};
 use kinode_process_lib::{script, Address, ProcessId, Request};
 
 wit_bindgen::generate!({
@@ -8,12 +10,13 @@ wit_bindgen::generate!({
     additional_derives: [serde::Deserialize, serde::Serialize],
 });
 
-const USAGE: &str = "\x1b[1mUsage:\x1b[0m alias <alias_name> <process_id>";
+const USAGE: &str = "\x1b[1mUsage:\x1b[0m alias <alias_name> <process_id>
+       alias <alias_name> = <command>";
 
 script!(init);
 fn init(_our: Address, args: String) -> String {
     if args.is_empty() {
-        return format!("Change alias for a process.\n{USAGE}");
+        return format!("Change alias for a process, or define a macro for a command.\n{USAGE}");
     }
 
     let (alias, process_str) = args.split_once(" ").unwrap_or((&args, ""));
@@ -22,6 +25,30 @@ fn init(_our: Address, args: String) -> String {
         return format!("No alias given.\n{USAGE}");
     }
 
+    if let Some(command) = process_str.strip_prefix("=") {
+        let command = command.trim();
+        // a command containing pipes or redirects must be quoted so that
+        // it is not itself piped: strip those quotes before saving it
+        let command = ['\'', '"']
+            .iter()
+            .find_map(|q| command.strip_prefix(*q)?.strip_suffix(*q))
+            .unwrap_or(command);
+        if command.is_empty() {
+            return format!("No command given.\n{USAGE}");
+        }
+        Request::to(("our", "terminal", "terminal", "sys"))
+            .body(
+                serde_json::to_vec(&TerminalRequest::EditMacro(EditMacroRequest {
+                    name: alias.to_string(),
+                    command: Some(command.to_string()),
+                }))
+                .unwrap(),
+            )
+            .send()
+            .unwrap();
+        return "macro set".to_string();
+    }
+
     if process_str.is_empty() {
         Request::to(("our", "terminal", "terminal", "sys"))
             .body(
@@ -0,0 +1,55 @@
+use kinode_process_lib::{script, Address, Message, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "process-v1",
+});
+
+const USAGE: &str = "\x1b[1mUsage:\x1b[0m find <drive> <query> [--text]";
+
+script!(init);
+fn init(_our: Address, args: String) -> String {
+    let full_text = args.split_whitespace().any(|arg| arg == "--text");
+    let args: Vec<&str> = args
+        .split_whitespace()
+        .filter(|arg| *arg != "--text")
+        .collect();
+    let [drive, query @ ..] = args.as_slice() else {
+        return format!("Find the files of a drive by name, or by contents with --text.\n{USAGE}");
+    };
+    if query.is_empty() {
+        return format!("no query given.\n{USAGE}");
+    }
+    let query = query.join(" ");
+
+    // the VFS keeps an index of the drive, so this does not walk it
+    let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "vfs", "distro", "sys"))
+        .body(
+            serde_json::to_vec(&serde_json::json!({
+                "path": drive,
+                "action": { "Search": { "query": query, "full_text": full_text } },
+            }))
+            .unwrap(),
+        )
+        .send_and_await_response(5)
+    else {
+        return "find: failed to get a response from vfs..!".to_string();
+    };
+    let Ok(response) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return "find: failed to parse response from vfs..!".to_string();
+    };
+    if let Some(e) = response.get("Err") {
+        return format!("find: {e}");
+    }
+    let Some(results) = response["Search"].as_array() else {
+        return "find: unexpected response from vfs..!".to_string();
+    };
+    if results.is_empty() {
+        return format!("nothing in {drive} matches {query}");
+    }
+    results
+        .iter()
+        .filter_map(|entry| entry["path"].as_str())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
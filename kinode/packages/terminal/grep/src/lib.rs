@@ -0,0 +1,25 @@
+use kinode_process_lib::{get_blob, script, Address};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "process-v1",
+});
+
+const USAGE: &str = "\x1b[1mUsage:\x1b[0m <command> | grep <pattern>";
+
+script!(init);
+fn init(_our: Address, args: String) -> String {
+    if args.is_empty() {
+        return format!("Print the lines of piped-in text that contain a pattern.\n{USAGE}");
+    }
+
+    let Some(blob) = get_blob() else {
+        return format!("no input to search: pipe the output of a command into grep.\n{USAGE}");
+    };
+
+    String::from_utf8_lossy(&blob.bytes)
+        .lines()
+        .filter(|line| line.contains(&args))
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
@@ -5,21 +5,28 @@ wit_bindgen::generate!({
     world: "process-v1",
 });
 
-const HELP_MESSAGES: [[&str; 2]; 11] = [
-    ["alias", "\n\x1b[1malias\x1b[0m <shorthand> <process-id>: create an alias for a script.\n    - Example: \x1b[1malias get-block get-block:kns-indexer:sys\x1b[0m\n    - note: all of these listed commands are just default aliases for terminal scripts."],
+const HELP_MESSAGES: [[&str; 2]; 18] = [
+    ["alias", "\n\x1b[1malias\x1b[0m <shorthand> <process-id>: create an alias for a script.\n    - Example: \x1b[1malias get-block get-block:kns-indexer:sys\x1b[0m\n    - note: all of these listed commands are just default aliases for terminal scripts.\n\x1b[1malias\x1b[0m <name> = <command>: create a macro that expands to a command. \x1b[1m$1\x1b[0m through \x1b[1m$9\x1b[0m in the command are replaced by the macro's arguments, and \x1b[1m$@\x1b[0m by all of them; without these, the arguments are appended to the command.\n    - Example: \x1b[1malias apps = 'top | grep $1'\x1b[0m\n    - a command containing \x1b[1m|\x1b[0m or \x1b[1m>\x1b[0m must be wrapped in quotes\n    - remove an alias or macro with \x1b[1malias <name>\x1b[0m"],
     ["cat", "\n\x1b[1mcat\x1b[0m <vfs-file-path>: print the contents of a file in the terminal.\n    - Example: \x1b[1mcat /terminal:sys/pkg/scripts.json\x1b[0m"],
     ["echo", "\n\x1b[1mecho\x1b[0m <text>: print text to the terminal.\n    - Example: \x1b[1mecho foo\x1b[0m"],
+    ["find", "\n\x1b[1mfind\x1b[0m <drive> <query>: print the files and directories of a drive whose names contain the query.\n    - Example: \x1b[1mfind /terminal:sys/pkg scripts\x1b[0m\n    - \x1b[1m--text\x1b[0m to also find the text files containing every word of the query\n    - the drive is indexed when first searched, so later searches are fast"],
+    ["grep", "\n\x1b[1mgrep\x1b[0m <pattern>: print the lines of piped-in text that contain the pattern.\n    - Example: \x1b[1mtop | grep app-store\x1b[0m"],
     ["hi", "\n\x1b[1mhi\x1b[0m <name> <string>: send a text message to another node's command line.\n    - Example: \x1b[1mhi mothu.kino hello world\x1b[0m"],
+    ["history", "\n\x1b[1mhistory\x1b[0m <filter>: print the commands entered in the terminal, oldest first. If a filter is given, print only the commands that contain it. History persists across restarts, up to the most recent 1000 commands.\n    - Example: \x1b[1mhistory alias\x1b[0m"],
+    ["json", "\n\x1b[1m--json\x1b[0m: print the output of a system command as a single line of JSON, for piping into scripts and external tooling. Supported by \x1b[1mtop\x1b[0m, \x1b[1mpeers\x1b[0m, and \x1b[1mapps:app-store:sys\x1b[0m.\n    - Example: \x1b[1mpeers --json > /terminal:sys/pkg/peers.json\x1b[0m"],
     ["kfetch", "\n\x1b[1mkfetch\x1b[0m: print system information a la neofetch. No arguments."],
-    ["kill", "\n\x1b[1mkill\x1b[0m <process-id>: terminate a running process. This will bypass any restart behavior; use judiciously.\n    - Example: \x1b[1mkill chess:chess:sys\x1b[0m"],
+    ["kill", "\n\x1b[1mkill\x1b[0m <process-id>: terminate a running process. This will bypass any restart behavior; use judiciously.\n    - Example: \x1b[1mkill chess:chess:sys\x1b[0m\n    - asks for confirmation first; pass \x1b[1m--yes\x1b[0m to skip it"],
+    ["less", "\n\x1b[1mless\x1b[0m: view piped-in text in a full-screen pager. Without piped-in text, view the most recently printed lines.\n    - Example: \x1b[1mtop | less\x1b[0m\n    - \x1b[1mj\x1b[0m/\x1b[1mk\x1b[0m or arrows to scroll by line, \x1b[1mSPACE\x1b[0m/\x1b[1mb\x1b[0m to scroll by page, \x1b[1mg\x1b[0m/\x1b[1mG\x1b[0m to jump to the top/bottom\n    - \x1b[1m/\x1b[0m to search, \x1b[1mn\x1b[0m/\x1b[1mN\x1b[0m to jump to the next/previous match, \x1b[1mq\x1b[0m to quit"],
     ["m", "\n\x1b[1mm\x1b[0m <address> '<json>': send an inter-process message. <address> is formatted as <node>@<process-id>. <process-id> is formatted as <process-name>:<package-name>:<publisher-node>. JSON containing spaces must be wrapped in single-quotes (\x1b[1m''\x1b[0m).\n    - Example: \x1b[1mm our@eth:distro:sys \"SetPublic\" -a 5\x1b[0m\n    - the '-a' flag is used to expect a response with a given timeout\n    - \x1b[1mour\x1b[0m will always be interpolated by the system as your node's name"],
     ["net-diagnostics", "\n\x1b[1mnet-diagnostics\x1b[0m: print some useful networking diagnostic data."],
     ["peer", "\n\x1b[1mpeer\x1b[0m <name>: print the peer's PKI info, if it exists."],
-    ["peers", "\n\x1b[1mpeers\x1b[0m: print the peers the node currently hold connections with."],
-    ["top", "\n\x1b[1mtop\x1b[0m <process-id>: display kernel debugging info about a process. Leave the process ID blank to display info about all processes and get the total number of running processes.\n    - Example: \x1b[1mtop net:distro:sys\x1b[0m\n    - Example: \x1b[1mtop\x1b[0m"],
+    ["peers", "\n\x1b[1mpeers\x1b[0m: print the peers the node currently hold connections with. \x1b[1m--json\x1b[0m to print them as JSON."],
+    ["pipes", "\n\x1b[1m|\x1b[0m and \x1b[1m>\x1b[0m: pipe the output of a command into the next command, and redirect the output of the last command into a VFS file.\n    - Example: \x1b[1mtop | grep app-store > /terminal:sys/pkg/top.txt\x1b[0m\n    - the piped output is given to the next command as its blob, alongside its arguments"],
+    ["tail", "\n\x1b[1mtail\x1b[0m <process-id>: follow a process's printouts live in a full-screen view.\n    - Example: \x1b[1mtail app-store:app-store:sys -f download -l 1\x1b[0m\n    - \x1b[1m-f\x1b[0m <regex> to only show lines that match, \x1b[1m-l\x1b[0m <level> to only show printouts up to that verbosity (0-3, default 3)\n    - scroll and search as in \x1b[1mless\x1b[0m; the view follows new lines while scrolled to the bottom. \x1b[1mq\x1b[0m to quit"],
+    ["top", "\n\x1b[1mtop\x1b[0m <process-id>: display kernel debugging info about a process. Leave the process ID blank to display info about all processes and get the total number of running processes.\n    - Example: \x1b[1mtop net:distro:sys\x1b[0m\n    - Example: \x1b[1mtop\x1b[0m\n\x1b[1mtop -l\x1b[0m: open a live, full-screen monitor of every process's queue depth, messages, last activity, and memory.\n    - \x1b[1mj\x1b[0m/\x1b[1mk\x1b[0m or arrows to select a process, \x1b[1mK\x1b[0m to kill it, \x1b[1ms\x1b[0m to suspend or resume it, \x1b[1mq\x1b[0m to quit"],
 ];
 
-const CONTROL_MESSAGES: [&str; 10] = [
+const CONTROL_MESSAGES: [&str; 11] = [
     "\n\x1b[1mCTRL+C\x1b[0m or \x1b[1mCTRL+D\x1b[0m to gracefully shutdown node",
     "\n\x1b[1mCTRL+V\x1b[0m to toggle through verbose modes (0-3, 0 is default and lowest verbosity)",
     "\n\x1b[1mCTRL+W\x1b[0m to toggle on/off Process Verbosity Mode, where individual process verbosities may be set",
@@ -30,6 +37,7 @@ const CONTROL_MESSAGES: [&str; 10] = [
     "\n\x1b[1mCTRL+E\x1b[0m to jump to end of input",
     "\n\x1b[1mCTRL+P\x1b[0m/\x1b[1mCTRL+N\x1b[0m or \x1b[1mUpArrow\x1b[0m/\x1b[1mDownArrow\x1b[0m to move up and down through command history",
     "\n\x1b[1mCTRL+R\x1b[0m to search history, \x1b[1mCTRL+R\x1b[0m again to step through search results, \x1b[1mCTRL+G\x1b[0m to cancel search",
+    "\n\x1b[1mTAB\x1b[0m to complete script names, process IDs, package IDs, and VFS paths",
 ];
 
 pub fn make_remote_link(url: &str, text: &str) -> String {
@@ -0,0 +1,27 @@
+use crate::kinode::process::terminal::{RuntimeRequest, RuntimeResponse};
+use kinode_process_lib::{script, Address, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "terminal-sys-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+script!(init);
+fn init(_our: Address, args: String) -> String {
+    let filter = if args.is_empty() { None } else { Some(args) };
+
+    let Ok(Ok(response)) = Request::to(("our", "terminal", "distro", "sys"))
+        .body(serde_json::to_vec(&RuntimeRequest::History(filter)).unwrap())
+        .send_and_await_response(5)
+    else {
+        return "did not receive history from the runtime terminal".to_string();
+    };
+
+    let Ok(RuntimeResponse::History(history)) = serde_json::from_slice(response.body()) else {
+        return "could not parse history from the runtime terminal".to_string();
+    };
+
+    history.join("\n")
+}
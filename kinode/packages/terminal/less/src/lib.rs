@@ -0,0 +1,22 @@
+use crate::kinode::process::terminal::RuntimeRequest;
+use kinode_process_lib::{get_blob, script, Address, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "terminal-sys-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+script!(init);
+fn init(_our: Address, _args: String) -> String {
+    let text = get_blob().map(|blob| String::from_utf8_lossy(&blob.bytes).to_string());
+
+    match Request::to(("our", "terminal", "distro", "sys"))
+        .body(serde_json::to_vec(&RuntimeRequest::Page(text)).unwrap())
+        .send()
+    {
+        Ok(()) => String::new(),
+        Err(_) => "could not open the pager".to_string(),
+    }
+}
@@ -6,7 +6,9 @@ wit_bindgen::generate!({
 });
 
 script!(init);
-fn init(_our: Address, _args: String) -> String {
+fn init(_our: Address, args: String) -> String {
+    let json = args.split_whitespace().any(|arg| arg == "--json");
+
     let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "net", "distro", "sys"))
         .body(rmp_serde::to_vec(&net::NetAction::GetPeers).unwrap())
         .send_and_await_response(10)
@@ -16,6 +18,9 @@ fn init(_our: Address, _args: String) -> String {
     let Ok(net::NetResponse::Peers(identities)) = rmp_serde::from_slice(&body) else {
         return "Got malformed response from networking module".to_string();
     };
+    if json {
+        return serde_json::to_string(&identities).unwrap();
+    }
     let identities = identities
         .iter()
         .map(|peer_id| {
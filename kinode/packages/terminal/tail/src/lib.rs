@@ -0,0 +1,57 @@
+use crate::kinode::process::terminal::{RuntimeRequest, TailRequest};
+use clap::{Arg, Command};
+use kinode_process_lib::{script, Address, ProcessId, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "terminal-sys-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+const USAGE: &str = "\x1b[1mUsage:\x1b[0m
+    \ntail <process_id> [-f <filter>] [-l <level>]
+    \n    -f, --filter <filter>: only show lines that match this regex
+    \n    -l, --level <level>: only show printouts up to this verbosity (0-3, default 3)";
+
+script!(init);
+fn init(_our: Address, args: String) -> String {
+    let body_string = format!("tail {args}");
+
+    let Ok(parsed) = Command::new("tail")
+        .disable_help_flag(true)
+        .arg(Arg::new("target").index(1).required(true))
+        .arg(Arg::new("filter").short('f').long("filter"))
+        .arg(
+            Arg::new("level")
+                .short('l')
+                .long("level")
+                .value_parser(clap::value_parser!(u8).range(0..=3))
+                .default_value("3"),
+        )
+        .try_get_matches_from(body_string.split_whitespace())
+    else {
+        return format!("Failed to parse args.\n{USAGE}");
+    };
+
+    let process = parsed.get_one::<String>("target").unwrap();
+    if let Err(e) = process.parse::<ProcessId>() {
+        return format!("invalid process id: {e}\n{USAGE}");
+    }
+
+    // the runtime terminal opens the follow view and streams matching printouts into it
+    match Request::to(("our", "terminal", "distro", "sys"))
+        .body(
+            serde_json::to_vec(&RuntimeRequest::Tail(TailRequest {
+                process: process.clone(),
+                filter: parsed.get_one::<String>("filter").cloned(),
+                level: *parsed.get_one::<u8>("level").unwrap(),
+            }))
+            .unwrap(),
+        )
+        .send()
+    {
+        Ok(()) => String::new(),
+        Err(_) => "could not open the follow view".to_string(),
+    }
+}
@@ -1,9 +1,10 @@
 use crate::kinode::process::terminal::{
-    EditAliasResponse, Request as TerminalRequest, Response as TerminalResponse,
+    EditAliasResponse, EditMacroResponse, Request as TerminalRequest, Response as TerminalResponse,
+    RuntimePrintout, RuntimeRequest,
 };
 use kinode_process_lib::{
-    await_message, call_init, get_typed_state, kernel_types as kt, our_capabilities, println,
-    set_state, vfs, Address, Capability, Message, ProcessId, Request, Response,
+    await_message, call_init, get_typed_state, http, kernel_types as kt, our_capabilities, println,
+    set_state, vfs, Address, Capability, LazyLoadBlob, Message, ProcessId, Request, Response,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -23,6 +24,9 @@ enum ScriptError {
     NoScriptInManifest,
     InvalidScriptsManifest,
     KernelUnresponsive,
+    MalformedPipeline,
+    FailedToRedirect(String),
+    NoJsonOutput(String),
 }
 
 impl std::fmt::Display for ScriptError {
@@ -36,16 +40,81 @@ impl std::fmt::Display for ScriptError {
             ScriptError::NoScriptInManifest => write!(f, "script not in scripts.json file"),
             ScriptError::InvalidScriptsManifest => write!(f, "could not parse scripts.json file"),
             ScriptError::KernelUnresponsive => write!(f, "kernel unresponsive"),
+            ScriptError::MalformedPipeline => {
+                write!(
+                    f,
+                    "malformed pipeline: each `|` and `>` must be followed by a command or path"
+                )
+            }
+            ScriptError::FailedToRedirect(path) => {
+                write!(f, "failed to redirect output into VFS file {path}")
+            }
+            ScriptError::NoJsonOutput(process) => {
+                write!(f, "{process} does not support --json output")
+            }
         }
     }
 }
 
 impl std::error::Error for ScriptError {}
 
+/// How long each command in a pipeline has to produce its output
+const PIPELINE_STAGE_TIMEOUT: u64 = 60;
+
+/// How long each process queried for tab-completion candidates has to respond
+const COMPLETION_QUERY_TIMEOUT: u64 = 1;
+
+/// Scripts that cannot be undone, and what they do. Before running, each asks
+/// for confirmation, unless given the `--yes` flag.
+const CONFIRM_COMMANDS: [(&str, &str); 3] = [
+    (
+        "kill:terminal:sys",
+        "terminate a process, bypassing its restart behavior",
+    ),
+    (
+        "reset:kns-indexer:sys",
+        "discard the KNS index and rebuild it from the chain",
+    ),
+    (
+        "uninstall:app-store:sys",
+        "uninstall a package, removing its processes",
+    ),
+];
+
+/// Scripts that, given the `--json` flag, print their output as a single line of
+/// JSON rather than formatted for reading, so that it can be piped into scripts
+/// and external tooling.
+const JSON_COMMANDS: [&str; 3] = [
+    "apps:app-store:sys",
+    "peers:terminal:sys",
+    "top:terminal:sys",
+];
+
+/// The remainder of a pipeline such as `top | grep chess > /terminal:sys/pkg/out.txt`.
+/// Carried as the context of the request to each piped command so that, when the
+/// command responds with its output, the terminal can pass that output as the blob
+/// of the next command, or write it to the redirect path once no commands remain.
+#[derive(Debug, Serialize, Deserialize)]
+struct Pipeline {
+    commands: Vec<String>,
+    redirect: Option<String>,
+}
+
+/// Where a command line was typed, so that a confirmation prompt is only
+/// answered from where it was raised.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum InputSource {
+    /// the runtime terminal
+    Tty,
+    /// the websocket of a web terminal, by channel ID
+    Web(u32),
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "version")]
 enum VersionedState {
     V1(TerminalStateV1),
+    V2(TerminalStateV2),
 }
 
 #[derive(Serialize, Deserialize)]
@@ -54,10 +123,18 @@ struct TerminalStateV1 {
     aliases: HashMap<String, ProcessId>,
 }
 
+#[derive(Serialize, Deserialize)]
+struct TerminalStateV2 {
+    our: Address,
+    aliases: HashMap<String, ProcessId>,
+    /// user-defined macros: names that expand to a whole command line
+    macros: HashMap<String, String>,
+}
+
 impl VersionedState {
     /// Create a new terminal state with the default system aliases
     fn new(our: Address) -> Self {
-        Self::V1(TerminalStateV1 {
+        Self::V2(TerminalStateV2 {
             our,
             aliases: HashMap::from([
                 (
@@ -72,6 +149,14 @@ impl VersionedState {
                     "echo".to_string(),
                     ProcessId::new(Some("echo"), "terminal", "sys"),
                 ),
+                (
+                    "find".to_string(),
+                    ProcessId::new(Some("find"), "terminal", "sys"),
+                ),
+                (
+                    "grep".to_string(),
+                    ProcessId::new(Some("grep"), "terminal", "sys"),
+                ),
                 (
                     "help".to_string(),
                     ProcessId::new(Some("help"), "terminal", "sys"),
@@ -80,6 +165,10 @@ impl VersionedState {
                     "hi".to_string(),
                     ProcessId::new(Some("hi"), "terminal", "sys"),
                 ),
+                (
+                    "history".to_string(),
+                    ProcessId::new(Some("history"), "terminal", "sys"),
+                ),
                 (
                     "kill".to_string(),
                     ProcessId::new(Some("kill"), "terminal", "sys"),
@@ -88,6 +177,10 @@ impl VersionedState {
                     "kfetch".to_string(),
                     ProcessId::new(Some("kfetch"), "terminal", "sys"),
                 ),
+                (
+                    "less".to_string(),
+                    ProcessId::new(Some("less"), "terminal", "sys"),
+                ),
                 (
                     "m".to_string(),
                     ProcessId::new(Some("m"), "terminal", "sys"),
@@ -104,23 +197,42 @@ impl VersionedState {
                     "peers".to_string(),
                     ProcessId::new(Some("peers"), "terminal", "sys"),
                 ),
+                (
+                    "tail".to_string(),
+                    ProcessId::new(Some("tail"), "terminal", "sys"),
+                ),
                 (
                     "top".to_string(),
                     ProcessId::new(Some("top"), "terminal", "sys"),
                 ),
             ]),
+            macros: HashMap::new(),
         })
     }
 
+    /// Migrate state saved by a previous version of the terminal
+    fn upgrade(self) -> Self {
+        match self {
+            VersionedState::V1(state) => VersionedState::V2(TerminalStateV2 {
+                our: state.our,
+                aliases: state.aliases,
+                macros: HashMap::new(),
+            }),
+            VersionedState::V2(_) => self,
+        }
+    }
+
     fn our(&self) -> &Address {
         match self {
             VersionedState::V1(state) => &state.our,
+            VersionedState::V2(state) => &state.our,
         }
     }
 
     fn aliases(&self) -> &HashMap<String, ProcessId> {
         match self {
             VersionedState::V1(state) => &state.aliases,
+            VersionedState::V2(state) => &state.aliases,
         }
     }
 
@@ -129,6 +241,9 @@ impl VersionedState {
             VersionedState::V1(state) => {
                 state.aliases.insert(alias, process);
             }
+            VersionedState::V2(state) => {
+                state.aliases.insert(alias, process);
+            }
         }
     }
 
@@ -137,6 +252,37 @@ impl VersionedState {
             VersionedState::V1(state) => {
                 state.aliases.remove(alias);
             }
+            VersionedState::V2(state) => {
+                state.aliases.remove(alias);
+            }
+        }
+    }
+
+    /// `None` for state from before macros, which should have been upgraded on load
+    fn macros(&self) -> Option<&HashMap<String, String>> {
+        match self {
+            VersionedState::V1(_) => None,
+            VersionedState::V2(state) => Some(&state.macros),
+        }
+    }
+
+    fn macro_insert(&mut self, name: String, command: String) -> anyhow::Result<()> {
+        match self {
+            VersionedState::V1(_) => Err(anyhow::anyhow!(
+                "terminal state is from before macros and was not upgraded"
+            )),
+            VersionedState::V2(state) => {
+                state.macros.insert(name, command);
+                Ok(())
+            }
+        }
+    }
+
+    /// the command of the removed macro, if there was one
+    fn macro_remove(&mut self, name: &str) -> Option<String> {
+        match self {
+            VersionedState::V1(_) => None,
+            VersionedState::V2(state) => state.macros.remove(name),
         }
     }
 }
@@ -145,18 +291,37 @@ call_init!(init);
 fn init(our: Address) {
     let mut state: VersionedState =
         match get_typed_state(|bytes| bincode::deserialize::<VersionedState>(bytes)) {
-            Some(mut s) => {
+            Some(s) => {
+                let mut s = s.upgrade();
                 // **add** the pre-installed scripts to the terminal state
                 // in case new ones have been added or if user has deleted aliases
-                let VersionedState::V1(default_state) = VersionedState::new(our);
-                for (alias, process) in default_state.aliases {
-                    s.alias_insert(alias, process);
+                for (alias, process) in VersionedState::new(our).aliases() {
+                    s.alias_insert(alias.clone(), process.clone());
                 }
                 s
             }
             None => VersionedState::new(our),
         };
 
+    // the web terminal: a page, served at the `terminal-sys` subdomain, whose
+    // websocket runs commands and receives the terminal's output
+    let mut http_server = http::server::HttpServer::new(5);
+    if let Err(e) = http_server
+        .serve_ui(
+            state.our(),
+            "ui",
+            vec!["/"],
+            http::server::HttpBindingConfig::default().secure_subdomain(true),
+        )
+        .and_then(|_| http_server.secure_bind_ws_path("/"))
+    {
+        println!("failed to serve web terminal: {e:?}");
+    }
+    let mut subscribed_to_printouts = false;
+    // the command line awaiting a yes or no from each input source,
+    // as required by CONFIRM_COMMANDS
+    let mut pending_confirmations: HashMap<InputSource, String> = HashMap::new();
+
     loop {
         let message = match await_message() {
             Err(e) => {
@@ -172,13 +337,73 @@ fn init(our: Address) {
                 expects_response,
                 ..
             } => {
-                // this is a message from the runtime terminal, parse as a command
+                // this is a message from the runtime terminal: a tab-completion
+                // query if it expects a response, otherwise parse as a command
                 if *state.our() == source {
-                    if let Err(e) =
-                        parse_command(&mut state, String::from_utf8_lossy(&body).to_string())
-                    {
+                    if expects_response.is_some() {
+                        let Ok(TerminalRequest::Complete(line)) =
+                            serde_json::from_slice::<TerminalRequest>(&body)
+                        else {
+                            println!("failed to parse completion query from runtime terminal");
+                            continue;
+                        };
+                        Response::new()
+                            .body(
+                                serde_json::to_vec(&TerminalResponse::Complete(complete(
+                                    &state, &line,
+                                )))
+                                .unwrap(),
+                            )
+                            .send()
+                            .unwrap();
+                    } else if let Err(e) = parse_command(
+                        &mut state,
+                        &mut pending_confirmations,
+                        InputSource::Tty,
+                        String::from_utf8_lossy(&body).to_string(),
+                    ) {
                         println!("error calling script: {e}");
                     }
+                } else if state.our().node == source.node
+                    && source.process == "http-server:distro:sys"
+                {
+                    handle_http_server_request(
+                        &mut state,
+                        &mut pending_confirmations,
+                        &mut http_server,
+                        &body,
+                    );
+                    // only have the runtime terminal forward printouts while
+                    // a web terminal is connected to show them
+                    let connected = http_server
+                        .get_ws_channels()
+                        .get("/")
+                        .is_some_and(|channels| !channels.is_empty());
+                    if connected != subscribed_to_printouts {
+                        subscribed_to_printouts = connected;
+                        let request = if connected {
+                            RuntimeRequest::SubscribePrintouts
+                        } else {
+                            RuntimeRequest::UnsubscribePrintouts
+                        };
+                        Request::to((state.our().node(), "terminal", "distro", "sys"))
+                            .body(serde_json::to_vec(&request).unwrap())
+                            .send()
+                            .unwrap();
+                    }
+                } else if state.our().node == source.node && source.process == "terminal:distro:sys"
+                {
+                    let Ok(printout) = serde_json::from_slice::<RuntimePrintout>(&body) else {
+                        continue;
+                    };
+                    http_server.ws_push_all_channels(
+                        "/",
+                        http::server::WsMessageType::Text,
+                        LazyLoadBlob {
+                            mime: Some("application/json".to_string()),
+                            bytes: serde_json::to_vec(&printout).unwrap(),
+                        },
+                    );
                 // checks for a request from a terminal script (different process, same package)
                 } else if state.our().node == source.node
                     && state.our().package() == source.package()
@@ -201,6 +426,32 @@ fn init(our: Address) {
                                     .unwrap();
                             }
                         }
+                        TerminalRequest::EditMacro(edit_macro_request) => {
+                            let terminal_response = handle_macro_change(
+                                &mut state,
+                                edit_macro_request.name,
+                                edit_macro_request.command,
+                            );
+                            if expects_response.is_some() {
+                                Response::new()
+                                    .body(serde_json::to_vec(&terminal_response).unwrap())
+                                    .send()
+                                    .unwrap();
+                            }
+                        }
+                        TerminalRequest::Complete(line) => {
+                            if expects_response.is_some() {
+                                Response::new()
+                                    .body(
+                                        serde_json::to_vec(&TerminalResponse::Complete(complete(
+                                            &state, &line,
+                                        )))
+                                        .unwrap(),
+                                    )
+                                    .send()
+                                    .unwrap();
+                            }
+                        }
                     }
                 } else {
                     kinode_process_lib::print_to_terminal(
@@ -209,8 +460,15 @@ fn init(our: Address) {
                     );
                 }
             }
-            Message::Response { body, .. } => {
-                if let Ok(txt) = std::str::from_utf8(&body) {
+            Message::Response { body, context, .. } => {
+                // responses carrying a pipeline context are output of a piped command
+                if let Some(pipeline) =
+                    context.and_then(|c| serde_json::from_slice::<Pipeline>(&c).ok())
+                {
+                    if let Err(e) = continue_pipeline(&state, pipeline, body) {
+                        println!("error in pipeline: {e}");
+                    }
+                } else if let Ok(txt) = std::str::from_utf8(&body) {
                     println!("{txt}");
                 } else {
                     println!("{body:?}");
@@ -220,22 +478,421 @@ fn init(our: Address) {
     }
 }
 
-fn parse_command(state: &mut VersionedState, line: String) -> Result<(), ScriptError> {
+/// the web terminal: each websocket message is a command line, run as if typed
+/// into the runtime terminal; its output arrives later as forwarded printouts
+fn handle_http_server_request(
+    state: &mut VersionedState,
+    pending_confirmations: &mut HashMap<InputSource, String>,
+    http_server: &mut http::server::HttpServer,
+    body: &[u8],
+) {
+    let Ok(server_request) = http_server.parse_request(body) else {
+        return;
+    };
+    http_server.handle_request(
+        server_request,
+        // the page itself is served statically by http-server
+        |_req| {
+            (
+                http::server::HttpResponse::new(http::StatusCode::NOT_FOUND),
+                None,
+            )
+        },
+        |channel_id, _message_type, blob| {
+            let line = String::from_utf8_lossy(&blob.bytes).trim().to_string();
+            let source = InputSource::Web(channel_id);
+            if let Err(e) = parse_command(state, pending_confirmations, source, line) {
+                println!("error calling script: {e}");
+            }
+        },
+    );
+}
+
+fn parse_command(
+    state: &mut VersionedState,
+    pending_confirmations: &mut HashMap<InputSource, String>,
+    source: InputSource,
+    line: String,
+) -> Result<(), ScriptError> {
+    // a line from where a confirmation prompt was raised answers it: yes runs the
+    // command, no or an empty line cancels it, and any other line cancels it and
+    // is run in its own right
+    if let Some(confirmed) = pending_confirmations.remove(&source) {
+        match line.trim() {
+            "y" | "yes" => return run_command_line(state, &confirmed),
+            "" | "n" | "no" => {
+                println!("cancelled: {confirmed}");
+                return Ok(());
+            }
+            _ => println!("cancelled: {confirmed}"),
+        }
+    }
     if line.is_empty() {
         return Ok(());
     }
-    let (head, args) = line.split_once(" ").unwrap_or((&line, ""));
+    let line = expand_macros(state, &line)?;
+    check_json_output(state, &line)?;
+    let (line, warnings) = check_confirmations(state, &line)?;
+    if !warnings.is_empty() {
+        println!("{}\nrun anyway? [y/N]", warnings.join("\n"));
+        pending_confirmations.insert(source, line);
+        return Ok(());
+    }
+    run_command_line(state, &line)
+}
+
+/// Run a command line, after macros have been expanded.
+fn run_command_line(state: &mut VersionedState, line: &str) -> Result<(), ScriptError> {
+    let (mut commands, redirect) = split_pipeline(line)?;
+    if commands.len() == 1 && redirect.is_none() {
+        let (process, args) = resolve_command(state, &commands[0])?;
+        return handle_run(state.our(), &process, args, None, None);
+    }
+    let first = commands.remove(0);
+    let (process, args) = resolve_command(state, &first)?;
+    let pipeline = if commands.is_empty() && redirect.is_none() {
+        None
+    } else {
+        Some(Pipeline { commands, redirect })
+    };
+    handle_run(state.our(), &process, args, None, pipeline)
+}
+
+/// Split a command line on `|` and a trailing `> <path>`, ignoring any
+/// such characters that appear inside single or double quotes.
+fn split_pipeline(line: &str) -> Result<(Vec<String>, Option<String>), ScriptError> {
+    let mut commands = vec![];
+    let mut redirect = None;
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (Some(q), c) if q == c => {
+                quote = None;
+                current.push(c);
+            }
+            (None, '|') => commands.push(std::mem::take(&mut current)),
+            (None, '>') => {
+                redirect = Some(chars.collect::<String>().trim().to_string());
+                break;
+            }
+            _ => current.push(c),
+        }
+    }
+    commands.push(current);
+    let commands: Vec<String> = commands.into_iter().map(|c| c.trim().to_string()).collect();
+    if commands.iter().any(|c| c.is_empty())
+        || redirect
+            .as_ref()
+            .is_some_and(|r| r.is_empty() || r.contains(char::is_whitespace))
+    {
+        return Err(ScriptError::MalformedPipeline);
+    }
+    Ok((commands, redirect))
+}
+
+/// Rejoin the commands of a line split by `split_pipeline`.
+fn join_pipeline(commands: &[String], redirect: Option<String>) -> String {
+    let line = commands.join(" | ");
+    match redirect {
+        Some(redirect) => format!("{line} > {redirect}"),
+        None => line,
+    }
+}
+
+/// Find the commands in a line that need confirmation, per CONFIRM_COMMANDS,
+/// and strip the `--yes` flag from those that were given it. Returns the line
+/// to run and a warning for each command that still needs confirmation.
+fn check_confirmations(
+    state: &VersionedState,
+    line: &str,
+) -> Result<(String, Vec<String>), ScriptError> {
+    let (commands, redirect) = split_pipeline(line)?;
+    let mut warnings = vec![];
+    let commands = commands
+        .into_iter()
+        .map(|command| {
+            let Ok((process, args)) = resolve_command(state, &command) else {
+                return command;
+            };
+            let process = process.to_string();
+            let Some((_, effect)) = CONFIRM_COMMANDS.iter().find(|(p, _)| *p == process) else {
+                return command;
+            };
+            if !args.split_whitespace().any(|arg| arg == "--yes") {
+                warnings.push(format!("{command}: this will {effect}."));
+                return command;
+            }
+            let (head, _) = command.split_once(" ").unwrap_or((&command, ""));
+            let args = args
+                .split_whitespace()
+                .filter(|arg| *arg != "--yes")
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("{head} {args}").trim_end().to_string()
+        })
+        .collect::<Vec<String>>();
+    Ok((join_pipeline(&commands, redirect), warnings))
+}
+
+/// Check that each command in a line given the `--json` flag supports it, per
+/// JSON_COMMANDS, rather than let the script fail to parse its arguments.
+fn check_json_output(state: &VersionedState, line: &str) -> Result<(), ScriptError> {
+    let (commands, _) = split_pipeline(line)?;
+    for command in commands {
+        let Ok((process, args)) = resolve_command(state, &command) else {
+            continue;
+        };
+        let process = process.to_string();
+        if args.split_whitespace().any(|arg| arg == "--json")
+            && !JSON_COMMANDS.contains(&process.as_str())
+        {
+            return Err(ScriptError::NoJsonOutput(process));
+        }
+    }
+    Ok(())
+}
+
+/// Expand each command in a line whose first word is a user-defined macro
+/// into the macro's command line. Expansion is not recursive.
+fn expand_macros(state: &VersionedState, line: &str) -> Result<String, ScriptError> {
+    let (commands, redirect) = split_pipeline(line)?;
+    let expanded = commands
+        .iter()
+        .map(|command| {
+            let (head, args) = command.split_once(" ").unwrap_or((command, ""));
+            match state.macros().and_then(|macros| macros.get(head)) {
+                Some(macro_command) => substitute_macro_args(macro_command, args.trim()),
+                None => command.clone(),
+            }
+        })
+        .collect::<Vec<String>>();
+    Ok(join_pipeline(&expanded, redirect))
+}
+
+/// Replace `$1` through `$9` in a macro's command line with the positional
+/// arguments it was called with, and `$@` with all of them. If the command
+/// line has no such parameters, the arguments are appended to it instead.
+fn substitute_macro_args(macro_command: &str, args: &str) -> String {
+    let positional: Vec<&str> = args.split_whitespace().collect();
+    let mut substituted = false;
+    let mut result = String::new();
+    let mut chars = macro_command.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' {
+            match chars.peek().copied() {
+                Some('@') => {
+                    chars.next();
+                    result.push_str(args);
+                    substituted = true;
+                    continue;
+                }
+                Some(d @ '1'..='9') => {
+                    chars.next();
+                    let index = d.to_digit(10).unwrap() as usize - 1;
+                    result.push_str(positional.get(index).unwrap_or(&""));
+                    substituted = true;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        result.push(c);
+    }
+    if !substituted && !args.is_empty() {
+        result = format!("{result} {args}");
+    }
+    result
+}
+
+/// Resolve the first word of a command to a script, either by alias or process ID
+fn resolve_command(
+    state: &VersionedState,
+    command: &str,
+) -> Result<(ProcessId, String), ScriptError> {
+    let (head, args) = command.split_once(" ").unwrap_or((command, ""));
     match state.aliases().get(head) {
-        Some(process) => handle_run(state.our(), process, args.to_string()),
+        Some(process) => Ok((process.clone(), args.to_string())),
         None => match head.parse::<ProcessId>() {
-            Ok(pid) => handle_run(state.our(), &pid, args.to_string()),
+            Ok(pid) => Ok((pid, args.to_string())),
             Err(_) => Err(ScriptError::UnknownName(head.to_string())),
         },
     }
 }
 
-/// Run a script by loading it from the VFS
-fn handle_run(our: &Address, process: &ProcessId, args: String) -> Result<(), ScriptError> {
+/// Produce the candidates for the last word of a partially-typed command line.
+///
+/// The first word of a command completes to an alias, a macro, or a running process.
+/// A word that starts with `/`, or follows a `>`, completes to a VFS path.
+/// Any other word completes to a running process or a package in the app store index.
+fn complete(state: &VersionedState, line: &str) -> Vec<String> {
+    let segment = line.rsplit('|').next().unwrap_or(line);
+    let (segment, redirect) = match segment.rsplit_once('>') {
+        Some((_, path)) => (path, true),
+        None => (segment, false),
+    };
+    let word = segment
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or(segment);
+    let mut candidates = if redirect || word.starts_with('/') {
+        vfs_candidates(word)
+    } else if segment.trim_start().len() == word.len() {
+        let mut candidates = process_candidates();
+        candidates.extend(state.aliases().keys().cloned());
+        candidates.extend(
+            state
+                .macros()
+                .into_iter()
+                .flat_map(|macros| macros.keys().cloned()),
+        );
+        candidates
+    } else {
+        let mut candidates = process_candidates();
+        candidates.extend(package_candidates());
+        candidates
+    };
+    candidates.retain(|candidate| candidate.starts_with(word));
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/// Every process in the kernel process map
+fn process_candidates() -> Vec<String> {
+    let Ok(Ok(response)) = Request::to(("our", "kernel", "distro", "sys"))
+        .body(serde_json::to_vec(&kt::KernelCommand::Debug(kt::KernelPrint::ProcessMap)).unwrap())
+        .send_and_await_response(COMPLETION_QUERY_TIMEOUT)
+    else {
+        return vec![];
+    };
+    match serde_json::from_slice::<kt::KernelResponse>(response.body()) {
+        Ok(kt::KernelResponse::Debug(kt::KernelPrintResponse::ProcessMap(process_map))) => {
+            process_map
+                .keys()
+                .map(|process| process.to_string())
+                .collect()
+        }
+        _ => vec![],
+    }
+}
+
+/// Every package in the app store index, as `package:publisher`
+fn package_candidates() -> Vec<String> {
+    let Ok(Ok(response)) = Request::to(("our", "chain", "app-store", "sys"))
+        .body(serde_json::to_vec("GetApps").unwrap())
+        .send_and_await_response(COMPLETION_QUERY_TIMEOUT)
+    else {
+        return vec![];
+    };
+    let Ok(response) = serde_json::from_slice::<serde_json::Value>(response.body()) else {
+        return vec![];
+    };
+    let Some(apps) = response.get("GetApps").and_then(|apps| apps.as_array()) else {
+        return vec![];
+    };
+    apps.iter()
+        .filter_map(|app| {
+            let package_id = app.get("package_id")?;
+            Some(format!(
+                "{}:{}",
+                package_id.get("package_name")?.as_str()?,
+                package_id.get("publisher_node")?.as_str()?,
+            ))
+        })
+        .collect()
+}
+
+/// The entries of the VFS directory that a partially-typed path is in.
+/// Directories are given a trailing `/` so that completion can continue into them.
+fn vfs_candidates(path: &str) -> Vec<String> {
+    let dir = match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    };
+    let Ok(Ok(response)) = Request::to(("our", "vfs", "distro", "sys"))
+        .body(
+            serde_json::to_vec(&vfs::VfsRequest {
+                path: dir.to_string(),
+                action: vfs::VfsAction::ReadDir,
+            })
+            .unwrap(),
+        )
+        .send_and_await_response(COMPLETION_QUERY_TIMEOUT)
+    else {
+        return vec![];
+    };
+    match serde_json::from_slice::<vfs::VfsResponse>(response.body()) {
+        Ok(vfs::VfsResponse::ReadDir(entries)) => entries
+            .into_iter()
+            .map(|entry| {
+                let path = format!("/{}", entry.path.trim_start_matches('/'));
+                match entry.file_type {
+                    vfs::FileType::Directory => format!("{path}/"),
+                    _ => path,
+                }
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Given the output of the previous command in a pipeline, either run
+/// the next command with that output as its blob, or write the output
+/// to the redirect path if no commands remain.
+fn continue_pipeline(
+    state: &VersionedState,
+    mut pipeline: Pipeline,
+    output: Vec<u8>,
+) -> Result<(), ScriptError> {
+    if pipeline.commands.is_empty() {
+        let Some(path) = pipeline.redirect else {
+            return Err(ScriptError::MalformedPipeline);
+        };
+        let response = Request::to(("our", "vfs", "distro", "sys"))
+            .body(
+                serde_json::to_vec(&vfs::VfsRequest {
+                    path: path.clone(),
+                    action: vfs::VfsAction::Write,
+                })
+                .unwrap(),
+            )
+            .blob_bytes(output)
+            .send_and_await_response(5)
+            .unwrap()
+            .map_err(|_| ScriptError::FailedToRedirect(path.clone()))?;
+        return match serde_json::from_slice::<vfs::VfsResponse>(response.body()) {
+            Ok(vfs::VfsResponse::Ok) => Ok(()),
+            _ => Err(ScriptError::FailedToRedirect(path)),
+        };
+    }
+    let next = pipeline.commands.remove(0);
+    let (process, args) = resolve_command(state, &next)?;
+    let pipeline = if pipeline.commands.is_empty() && pipeline.redirect.is_none() {
+        None
+    } else {
+        Some(pipeline)
+    };
+    handle_run(state.our(), &process, args, Some(output), pipeline)
+}
+
+/// Run a script by loading it from the VFS.
+///
+/// If `input` is given, it is passed to the script as the blob of its arguments.
+/// If `pipeline` is given, the script is asked to respond with its output rather
+/// than print it, so that the output can be fed into the rest of the pipeline.
+fn handle_run(
+    our: &Address,
+    process: &ProcessId,
+    args: String,
+    input: Option<Vec<u8>>,
+    pipeline: Option<Pipeline>,
+) -> Result<(), ScriptError> {
     let entry = get_entry(process)?;
     let wasm_path = format!(
         "/{}:{}/pkg/{}.wasm",
@@ -404,10 +1061,16 @@ fn handle_run(our: &Address, process: &ProcessId, args: String) -> Result<(), Sc
         .map_err(|_| ScriptError::KernelUnresponsive)?;
 
     // once process is running, send the arguments to it
-    Request::to(("our", process_id))
-        .body(args.into_bytes())
-        .send()
-        .unwrap();
+    let mut request = Request::to(("our", process_id)).body(args.into_bytes());
+    if let Some(input) = input {
+        request = request.blob_bytes(input);
+    }
+    if let Some(pipeline) = pipeline {
+        request = request
+            .expects_response(PIPELINE_STAGE_TIMEOUT)
+            .context(serde_json::to_vec(&pipeline).unwrap());
+    }
+    request.send().unwrap();
 
     Ok(())
 }
@@ -423,6 +1086,8 @@ fn handle_alias_change(
                 return TerminalResponse::EditAlias(EditAliasResponse::InvalidProcessId);
             };
             println!("alias {alias} set for {process}");
+            // an alias replaces any macro of the same name
+            state.macro_remove(&alias);
             state.alias_insert(alias, parsed_process);
             TerminalResponse::EditAlias(EditAliasResponse::AliasSet)
         }
@@ -431,6 +1096,9 @@ fn handle_alias_change(
                 state.alias_remove(&alias);
                 println!("alias {alias} removed");
                 TerminalResponse::EditAlias(EditAliasResponse::AliasRemoved)
+            } else if state.macro_remove(&alias).is_some() {
+                println!("macro {alias} removed");
+                TerminalResponse::EditAlias(EditAliasResponse::AliasRemoved)
             } else {
                 println!("alias {alias} not found");
                 TerminalResponse::EditAlias(EditAliasResponse::AliasNotFound)
@@ -441,6 +1109,40 @@ fn handle_alias_change(
     response
 }
 
+fn handle_macro_change(
+    state: &mut VersionedState,
+    name: String,
+    command: Option<String>,
+) -> TerminalResponse {
+    let response = match command {
+        Some(command) => {
+            // a macro replaces any alias of the same name
+            match state.macro_insert(name.clone(), command.clone()) {
+                Ok(()) => {
+                    state.alias_remove(&name);
+                    println!("macro {name} set for {command}");
+                    TerminalResponse::EditMacro(EditMacroResponse::MacroSet)
+                }
+                Err(e) => {
+                    println!("failed to set macro {name}: {e}");
+                    TerminalResponse::EditMacro(EditMacroResponse::MacroNotFound)
+                }
+            }
+        }
+        None => {
+            if state.macro_remove(&name).is_some() {
+                println!("macro {name} removed");
+                TerminalResponse::EditMacro(EditMacroResponse::MacroRemoved)
+            } else {
+                println!("macro {name} not found");
+                TerminalResponse::EditMacro(EditMacroResponse::MacroNotFound)
+            }
+        }
+    };
+    set_state(&bincode::serialize(&state).expect("failed to serialize terminal state"));
+    response
+}
+
 fn get_entry(process: &ProcessId) -> Result<kt::DotScriptsEntry, ScriptError> {
     let file = vfs::File::new(
         format!(
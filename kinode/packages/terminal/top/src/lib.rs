@@ -1,3 +1,4 @@
+use crate::kinode::process::terminal::RuntimeRequest;
 use clap::{Arg, Command};
 use kinode_process_lib::kernel_types::{
     KernelCommand, KernelPrint, KernelPrintResponse, KernelResponse, PersistedProcess,
@@ -6,12 +7,16 @@ use kinode_process_lib::{script, Address, Message, ProcessId, Request};
 
 wit_bindgen::generate!({
     path: "target/wit",
-    world: "process-v1",
+    world: "terminal-sys-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
 });
 
 const USAGE: &str = "\x1b[1mUsage:\x1b[0m
     \ntop [-c <show-caps>] <- to view all processes
-    \ntop <process_id> [-c <show-caps>] <- to view one process";
+    \ntop <process_id> [-c <show-caps>] <- to view one process
+    \ntop -l <live> <- to monitor all processes, live
+    \ntop [<process_id>] --json <- to print as JSON";
 
 script!(init);
 fn init(_our: Address, args: String) -> String {
@@ -26,6 +31,17 @@ fn init(_our: Address, args: String) -> String {
                 .long("show-caps")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("live")
+                .short('l')
+                .long("live")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(clap::ArgAction::SetTrue),
+        )
         .try_get_matches_from(body_string.split_whitespace())
     else {
         return format!("Failed to parse args.\n{USAGE}");
@@ -35,6 +51,18 @@ fn init(_our: Address, args: String) -> String {
         .get_one::<String>("target")
         .map(|s| s.parse::<ProcessId>());
     let show_caps = parsed.get_flag("show-caps");
+    let json = parsed.get_flag("json");
+
+    if parsed.get_flag("live") {
+        // the runtime terminal opens the monitor and keeps it up to date
+        return match Request::to(("our", "terminal", "distro", "sys"))
+            .body(serde_json::to_vec(&RuntimeRequest::Monitor).unwrap())
+            .send()
+        {
+            Ok(()) => String::new(),
+            Err(_) => "could not open the monitor".to_string(),
+        };
+    }
 
     let Ok(Message::Response { body, .. }) = Request::to(("our", "kernel", "distro", "sys"))
         .body(if let Some(target) = &target {
@@ -60,6 +88,10 @@ fn init(_our: Address, args: String) -> String {
     };
 
     match kernel_print_response {
+        KernelPrintResponse::ProcessMap(process_map) if json => {
+            serde_json::to_string(&process_map).unwrap()
+        }
+        KernelPrintResponse::Process(process) if json => serde_json::to_string(&process).unwrap(),
         KernelPrintResponse::ProcessMap(process_map) => {
             let len = process_map.len();
             let printout = process_map
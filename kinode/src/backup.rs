@@ -0,0 +1,795 @@
+//! Encrypted backups of a node's identity and settings, kept off the machine it runs on.
+//!
+//! `kinode <home> --backup <TARGET>` bundles the keyfile, the settings files in the
+//! home directory, the apps' SQLite databases, and the list of installed apps,
+//! encrypts the bundle with a passphrase, and uploads it to the target described in
+//! the JSON file `TARGET`. `kinode <home> --restore-backup <TARGET>` downloads and
+//! decrypts it into an empty home at boot, after which the node logs in with its
+//! password as before. Databases are copied with SQLite's online backup API, so each
+//! is consistent even if a node is writing to it, except encrypted ones, which are
+//! copied as stored. Other app data is not backed up:
+//! the apps listed on restore are reinstalled from the App Store.
+//!
+//! The keyfile is encrypted with the node password within the bundle too, so
+//! restoring a node takes both the passphrase and the password.
+//!
+//! While the node runs, the `backup:distro:sys` runtime module makes the same
+//! backups for the settings app, with or without the keyfile, and keeps them in
+//! `backups/` in the home directory. Restoring one of those stages it, still encrypted,
+//! to be decrypted and applied at the next boot, before the node logs in, since the
+//! settings and databases it replaces are in use while the node runs. Its passphrase
+//! is given again then, with `--backup-passphrase` or at the prompt.
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key,
+};
+use hmac::{Hmac, Mac};
+use lib::types::core::{
+    Address, BackupAction, BackupError, BackupInfo, BackupProgress, BackupResponse, BackupStage,
+    CapMessage, CapMessageSender, Capability, KernelMessage, Message, MessageReceiver,
+    MessageSender, Request, Response, BACKUP_PROCESS_ID,
+};
+use ring::pbkdf2;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// marks a file as a backup, followed by the format version
+const MAGIC: &[u8; 4] = b"KNBK";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+const KEYFILE: &str = ".keys";
+/// the directory in the home directory where the runtime module keeps backups
+const BACKUPS_DIR: &str = "backups";
+/// a backup staged to be restored at the next boot, in the backups directory
+const STAGED_RESTORE: &str = "restore";
+/// where a staged backup that failed to restore is set aside, in the backups directory
+const FAILED_RESTORE: &str = "restore.failed";
+/// settings kept in the home directory, backed up if present
+const SETTINGS_FILES: &[&str] = &[
+    ".eth_providers",
+    ".eth_access_settings",
+    ".eth_cache_ttls",
+    ".contacts",
+    ".net_peer_access",
+    ".net_offline_queue",
+    ".http_domains",
+    ".http_client_proxies",
+    ".http_api_tokens",
+    ".jwt_salt",
+    ".totp",
+    ".vfs_quotas",
+    ".vfs_compression",
+    ".vfs_encryption",
+    ".wallet",
+    ".notify",
+];
+
+/// Where a backup is kept.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum BackupTarget {
+    /// an object in an S3-compatible bucket, addressed path-style
+    S3 {
+        /// e.g. `https://s3.us-east-1.amazonaws.com`
+        endpoint: String,
+        region: String,
+        bucket: String,
+        key: String,
+        access_key_id: String,
+        secret_access_key: String,
+    },
+    /// a file on a WebDAV server, such as a drive another node shares with
+    /// `share:settings:sys <drive> --write`, authenticated with an auth token
+    /// of that node, such as one issued by pairing
+    WebDav { url: String, token: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Bundle {
+    /// UNIX timestamp in seconds of the backup
+    created: u64,
+    /// file name in the home directory -> contents
+    files: BTreeMap<String, Vec<u8>>,
+    /// the packages installed when backed up
+    apps: Vec<String>,
+    /// path under `sqlite/` of each database -> contents
+    #[serde(default)]
+    databases: BTreeMap<String, Vec<u8>>,
+}
+
+/// Back up `home_directory_path` to the target described in `target_path`.
+pub async fn backup(
+    home_directory_path: &Path,
+    target_path: &Path,
+    passphrase: &str,
+) -> anyhow::Result<()> {
+    let target = load_target(target_path).await?;
+    let bundle = collect(home_directory_path, true, &|stage| {
+        if let BackupStage::SkippedDatabase { db, reason } = stage {
+            println!("skipping database {db}: {reason}\r");
+        }
+    })
+    .await?;
+    let files = bundle.files.len();
+    let apps = bundle.apps.len();
+    let databases = bundle.databases.len();
+    let encrypted = encrypt(&rmp_serde::to_vec(&bundle)?, passphrase);
+    upload(&target, encrypted).await?;
+    println!(
+        "backed up keyfile, {} settings files, {databases} databases and a list of {apps} apps\r",
+        files - 1
+    );
+    Ok(())
+}
+
+/// Restore the backup described in `target_path` into `home_directory_path`,
+/// which must not hold a node already.
+pub async fn restore(
+    home_directory_path: &Path,
+    target_path: &Path,
+    passphrase: &str,
+) -> anyhow::Result<()> {
+    if tokio::fs::try_exists(home_directory_path.join(KEYFILE)).await? {
+        return Err(anyhow::anyhow!(
+            "{} already holds a node, restore into an empty home",
+            home_directory_path.display()
+        ));
+    }
+    let target = load_target(target_path).await?;
+    let encrypted = download(&target).await?;
+    let bundle: Bundle = rmp_serde::from_slice(&decrypt(&encrypted, passphrase)?)?;
+    if !bundle.files.contains_key(KEYFILE) {
+        return Err(anyhow::anyhow!(
+            "the backup does not include the keyfile, restore it from Settings on its node"
+        ));
+    }
+    apply(home_directory_path, &bundle).await?;
+    if !bundle.apps.is_empty() {
+        println!("apps installed when backed up, to reinstall from the App Store:\r");
+        for app in &bundle.apps {
+            println!("    {app}\r");
+        }
+    }
+    println!("log in with the node password to finish\r");
+    Ok(())
+}
+
+/// Decrypt and apply the backup staged by [`BackupAction::Restore`], if any, to
+/// `home_directory_path`, with `passphrase` or else one prompted for. A backup that
+/// fails to restore is set aside, so that the node boots as it was.
+pub async fn restore_staged(
+    home_directory_path: &Path,
+    passphrase: Option<&str>,
+) -> anyhow::Result<()> {
+    let backups_path = home_directory_path.join(BACKUPS_DIR);
+    let staged_path = backups_path.join(STAGED_RESTORE);
+    let Ok(staged) = tokio::fs::read(&staged_path).await else {
+        return Ok(());
+    };
+    let result = async {
+        let passphrase = match passphrase {
+            Some(passphrase) => passphrase.to_string(),
+            None => read_passphrase("passphrase of the backup to restore: ")?,
+        };
+        let bundle: Bundle = rmp_serde::from_slice(&decrypt(&staged, &passphrase)?)?;
+        apply(home_directory_path, &bundle).await
+    }
+    .await;
+    match result {
+        Ok(()) => tokio::fs::remove_file(&staged_path).await?,
+        Err(_) => tokio::fs::rename(&staged_path, backups_path.join(FAILED_RESTORE)).await?,
+    }
+    result
+}
+
+/// Write the files and databases of a backup into `home_directory_path`,
+/// replacing any there.
+async fn apply(home_directory_path: &Path, bundle: &Bundle) -> anyhow::Result<()> {
+    for (name, contents) in &bundle.files {
+        // only names we back up are written, whatever the bundle holds
+        if name != KEYFILE && !SETTINGS_FILES.contains(&name.as_str()) {
+            continue;
+        }
+        tokio::fs::write(home_directory_path.join(name), contents).await?;
+    }
+    for (path, contents) in &bundle.databases {
+        // only paths of the form sqlite opens databases at are written
+        let parts: Vec<&str> = path.split('/').collect();
+        let &[package_id, db, file] = parts.as_slice() else {
+            continue;
+        };
+        if [package_id, db, file]
+            .iter()
+            .any(|part| part.is_empty() || *part == "..")
+            || file != format!("{db}.db")
+        {
+            continue;
+        }
+        let db_path = home_directory_path.join("sqlite").join(package_id).join(db);
+        tokio::fs::create_dir_all(&db_path).await?;
+        // the write-ahead log of a replaced database would be applied over the backup
+        for stale in [format!("{db}.db-wal"), format!("{db}.db-shm")] {
+            let _ = tokio::fs::remove_file(db_path.join(stale)).await;
+        }
+        tokio::fs::write(db_path.join(file), contents).await?;
+    }
+    println!(
+        "restored backup from {}\r",
+        chrono::DateTime::from_timestamp(bundle.created as i64, 0)
+            .map(|created| created.to_rfc3339())
+            .unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// A runtime module that makes backups while the node runs, keeps them in the home
+/// directory, and stages them to be restored at the next boot. Every action takes
+/// the root capability, as given to the settings app.
+pub async fn backups(
+    our: String,
+    send_to_loop: MessageSender,
+    mut recv_from_loop: MessageReceiver,
+    send_to_caps_oracle: CapMessageSender,
+    home_directory_path: PathBuf,
+) -> anyhow::Result<()> {
+    let backups_path = home_directory_path.join(BACKUPS_DIR);
+    let creating = Arc::new(AtomicBool::new(false));
+
+    while let Some(km) = recv_from_loop.recv().await {
+        if km.source.node != our {
+            continue;
+        }
+        let Message::Request(ref req) = km.message else {
+            continue;
+        };
+        let target = km.rsvp.clone().unwrap_or(km.source.clone());
+        let action = if has_root_capability(&our, &km.source, &send_to_caps_oracle).await {
+            serde_json::from_slice::<BackupAction>(&req.body)
+                .map_err(|_| BackupError::MalformedRequest)
+        } else {
+            Err(BackupError::NoCap)
+        };
+        let response = match action {
+            Err(e) => Err(e),
+            Ok(BackupAction::Create {
+                passphrase,
+                include_keyfile,
+            }) => {
+                if creating.swap(true, Ordering::SeqCst) {
+                    Err(BackupError::InProgress)
+                } else {
+                    // answered once made, which can take a while on a node with large databases
+                    let our = our.clone();
+                    let send_to_loop = send_to_loop.clone();
+                    let home_directory_path = home_directory_path.clone();
+                    let creating = creating.clone();
+                    let km_id = km.id;
+                    tokio::spawn(async move {
+                        let response = match create(
+                            &our,
+                            &home_directory_path,
+                            &target,
+                            &passphrase,
+                            include_keyfile,
+                            &send_to_loop,
+                        )
+                        .await
+                        {
+                            Ok(info) => BackupResponse::Created(info),
+                            Err(e) => BackupResponse::Err(e),
+                        };
+                        creating.store(false, Ordering::SeqCst);
+                        respond(&our, km_id, target, response, &send_to_loop).await;
+                    });
+                    continue;
+                }
+            }
+            Ok(BackupAction::List) => list(&backups_path).await.map(BackupResponse::Backups),
+            Ok(BackupAction::Restore { id, passphrase }) => {
+                stage_restore(&backups_path, id, passphrase)
+                    .await
+                    .map(|()| BackupResponse::Ok)
+            }
+            Ok(BackupAction::Remove(id)) => {
+                remove(&backups_path, id).await.map(|()| BackupResponse::Ok)
+            }
+        };
+        respond(
+            &our,
+            km.id,
+            target,
+            response.unwrap_or_else(BackupResponse::Err),
+            &send_to_loop,
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Make a backup, sending its progress to `target` as it goes.
+async fn create(
+    our: &str,
+    home_directory_path: &Path,
+    target: &Address,
+    passphrase: &str,
+    include_keyfile: bool,
+    send_to_loop: &MessageSender,
+) -> Result<BackupInfo, BackupError> {
+    let id = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let progress = |stage: BackupStage| {
+        let km = KernelMessage::builder()
+            .id(rand::random())
+            .source((our, BACKUP_PROCESS_ID.clone()))
+            .target(target.clone())
+            .message(Message::Request(Request {
+                inherit: false,
+                expects_response: None,
+                body: serde_json::to_vec(&BackupProgress { id, stage }).unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            }))
+            .build()
+            .unwrap();
+        // progress is dropped if the event loop is backed up, rather than holding up the backup
+        let _ = send_to_loop.try_send(km);
+    };
+    let bundle = collect(home_directory_path, include_keyfile, &progress)
+        .await
+        .map_err(|e| BackupError::Failed(e.to_string()))?;
+    progress(BackupStage::Encrypting);
+    let keyfile = bundle.files.contains_key(KEYFILE);
+    let settings_files = bundle.files.len() - keyfile as usize;
+    let (databases, apps) = (bundle.databases.len(), bundle.apps.len());
+    let plaintext = rmp_serde::to_vec(&bundle).map_err(|e| BackupError::Failed(e.to_string()))?;
+    let passphrase = passphrase.to_string();
+    let encrypted = tokio::task::spawn_blocking(move || encrypt(&plaintext, &passphrase))
+        .await
+        .map_err(|e| BackupError::Failed(e.to_string()))?;
+    progress(BackupStage::Writing);
+    let backups_path = home_directory_path.join(BACKUPS_DIR);
+    tokio::fs::create_dir_all(&backups_path).await?;
+    let info = BackupInfo {
+        id,
+        bytes: encrypted.len() as u64,
+        keyfile,
+        settings_files,
+        databases,
+        apps,
+    };
+    tokio::fs::write(backups_path.join(format!("{id}.kbk")), encrypted).await?;
+    // written last, so that a backup cut short is not listed
+    tokio::fs::write(
+        backups_path.join(format!("{id}.json")),
+        serde_json::to_vec(&info).unwrap(),
+    )
+    .await?;
+    Ok(info)
+}
+
+async fn list(backups_path: &Path) -> Result<Vec<BackupInfo>, BackupError> {
+    let mut backups = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(backups_path).await else {
+        return Ok(backups);
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let Ok(info) = serde_json::from_slice::<BackupInfo>(&tokio::fs::read(&path).await?) else {
+            continue;
+        };
+        backups.push(info);
+    }
+    backups.sort_by_key(|backup| backup.id);
+    Ok(backups)
+}
+
+/// Check that a backup decrypts with `passphrase`, then stage it, as it is kept,
+/// for [`restore_staged`].
+async fn stage_restore(
+    backups_path: &Path,
+    id: u64,
+    passphrase: String,
+) -> Result<(), BackupError> {
+    let Ok(encrypted) = tokio::fs::read(backups_path.join(format!("{id}.kbk"))).await else {
+        return Err(BackupError::NoBackup(id));
+    };
+    // checked now, rather than failing at boot
+    let encrypted = tokio::task::spawn_blocking(move || {
+        let plaintext = decrypt(&encrypted, &passphrase)?;
+        rmp_serde::from_slice::<Bundle>(&plaintext)?;
+        anyhow::Ok(encrypted)
+    })
+    .await
+    .map_err(|e| BackupError::Failed(e.to_string()))?
+    .map_err(|_| BackupError::WrongPassphrase)?;
+    tokio::fs::write(backups_path.join(STAGED_RESTORE), encrypted).await?;
+    Ok(())
+}
+
+async fn remove(backups_path: &Path, id: u64) -> Result<(), BackupError> {
+    if tokio::fs::remove_file(backups_path.join(format!("{id}.json")))
+        .await
+        .is_err()
+    {
+        return Err(BackupError::NoBackup(id));
+    }
+    let _ = tokio::fs::remove_file(backups_path.join(format!("{id}.kbk"))).await;
+    Ok(())
+}
+
+async fn has_root_capability(
+    our: &str,
+    source: &Address,
+    send_to_caps_oracle: &CapMessageSender,
+) -> bool {
+    let (send_cap_bool, recv_cap_bool) = tokio::sync::oneshot::channel();
+    if send_to_caps_oracle
+        .send(CapMessage::Has {
+            on: source.process.clone(),
+            cap: Capability::new(
+                (our, BACKUP_PROCESS_ID.clone()),
+                "{\"root\":true}".to_string(),
+            ),
+            responder: send_cap_bool,
+        })
+        .await
+        .is_err()
+    {
+        return false;
+    }
+    recv_cap_bool.await.unwrap_or(false)
+}
+
+async fn respond(
+    our: &str,
+    km_id: u64,
+    target: Address,
+    response: BackupResponse,
+    send_to_loop: &MessageSender,
+) {
+    KernelMessage::builder()
+        .id(km_id)
+        .source((our, BACKUP_PROCESS_ID.clone()))
+        .target(target)
+        .message(Message::Response((
+            Response {
+                inherit: false,
+                body: serde_json::to_vec(&response).unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            },
+            None,
+        )))
+        .build()
+        .unwrap()
+        .send(send_to_loop)
+        .await;
+}
+
+/// Read a passphrase from the terminal without echoing it.
+pub fn read_passphrase(prompt: &str) -> anyhow::Result<String> {
+    use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+    use std::io::Write;
+
+    print!("{prompt}");
+    std::io::stdout().flush()?;
+    crossterm::terminal::enable_raw_mode()?;
+    let mut passphrase = String::new();
+    let result = loop {
+        match read() {
+            Ok(Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            })) => match code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err(anyhow::anyhow!("cancelled"));
+                }
+                KeyCode::Char(c) => passphrase.push(c),
+                KeyCode::Backspace => {
+                    passphrase.pop();
+                }
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(e) => break Err(e.into()),
+        }
+    };
+    crossterm::terminal::disable_raw_mode()?;
+    println!("\r");
+    result.map(|()| passphrase)
+}
+
+async fn load_target(target_path: &Path) -> anyhow::Result<BackupTarget> {
+    let bytes = tokio::fs::read(target_path).await.map_err(|e| {
+        anyhow::anyhow!(
+            "failed to read backup target {}: {e}",
+            target_path.display()
+        )
+    })?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+async fn collect(
+    home_directory_path: &Path,
+    include_keyfile: bool,
+    progress: &(dyn Fn(BackupStage) + Sync),
+) -> anyhow::Result<Bundle> {
+    progress(BackupStage::Settings);
+    let mut files = BTreeMap::new();
+    let keyfile = tokio::fs::read(home_directory_path.join(KEYFILE))
+        .await
+        .map_err(|_| anyhow::anyhow!("no node to back up, {KEYFILE} not found"))?;
+    if include_keyfile {
+        files.insert(KEYFILE.to_string(), keyfile);
+    }
+    for name in SETTINGS_FILES {
+        if let Ok(contents) = tokio::fs::read(home_directory_path.join(name)).await {
+            files.insert(name.to_string(), contents);
+        }
+    }
+
+    // each installed package keeps its manifest in its drive, under pkg/
+    let mut apps = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(home_directory_path.join("vfs")).await {
+        while let Some(entry) = entries.next_entry().await? {
+            if tokio::fs::try_exists(entry.path().join("pkg").join("manifest.json")).await? {
+                apps.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    apps.sort();
+
+    // each database is kept at sqlite/<package>/<db>/<db>.db
+    let mut db_paths = Vec::new();
+    let sqlite_path = home_directory_path.join("sqlite");
+    if let Ok(mut packages) = tokio::fs::read_dir(&sqlite_path).await {
+        while let Some(package) = packages.next_entry().await? {
+            let Ok(mut dbs) = tokio::fs::read_dir(package.path()).await else {
+                continue;
+            };
+            while let Some(db) = dbs.next_entry().await? {
+                let db = db.file_name().to_string_lossy().to_string();
+                let db_file_path = package.path().join(&db).join(format!("{db}.db"));
+                if tokio::fs::try_exists(&db_file_path).await? {
+                    let package = package.file_name().to_string_lossy().to_string();
+                    db_paths.push((format!("{package}/{db}"), db_file_path));
+                }
+            }
+        }
+    }
+
+    let mut databases = BTreeMap::new();
+    let total = db_paths.len();
+    for (done, (db, db_file_path)) in db_paths.into_iter().enumerate() {
+        progress(BackupStage::Database {
+            db: db.clone(),
+            done,
+            total,
+        });
+        let key = format!(
+            "{db}/{}",
+            db_file_path.file_name().unwrap().to_string_lossy()
+        );
+        // an encrypted db can only be read with its key, derived from the
+        // keyfile, which takes the node password: so it is copied as stored,
+        // and only if it has no changes left in its write-ahead log
+        if crate::sqlite::stored_encrypted(&db_file_path)? == Some(true) {
+            let wal_path = db_file_path.with_extension("db-wal");
+            let wal_len = match tokio::fs::metadata(&wal_path).await {
+                Ok(metadata) => metadata.len(),
+                Err(_) => 0,
+            };
+            if wal_len > 0 {
+                progress(BackupStage::SkippedDatabase {
+                    db,
+                    reason: "it is encrypted and has unsaved changes: restart the node, then back up again".to_string(),
+                });
+                continue;
+            }
+            databases.insert(key, tokio::fs::read(&db_file_path).await?);
+            continue;
+        }
+        let copy_path =
+            std::env::temp_dir().join(format!("kinode-backup-{}.db", rand::random::<u64>()));
+        let copied = {
+            let copy_path = copy_path.clone();
+            tokio::task::spawn_blocking(move || {
+                crate::sqlite::backup_db(&db_file_path, &copy_path, None)
+            })
+            .await?
+        };
+        let contents = match copied {
+            Ok(()) => tokio::fs::read(&copy_path)
+                .await
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        let _ = tokio::fs::remove_file(&copy_path).await;
+        databases.insert(key, contents?);
+    }
+
+    Ok(Bundle {
+        created: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs(),
+        files,
+        apps,
+        databases,
+    })
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        crate::keygen::PBKDF2_ALG,
+        NonZeroU32::new(crate::keygen::ITERATIONS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    key
+}
+
+fn encrypt(plaintext: &[u8], passphrase: &str) -> Vec<u8> {
+    let salt: [u8; SALT_LEN] = rand::random();
+    let key = derive_key(passphrase, &salt);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext).unwrap();
+    [
+        MAGIC.as_slice(),
+        &[VERSION],
+        &salt,
+        nonce.as_slice(),
+        &ciphertext,
+    ]
+    .concat()
+}
+
+fn decrypt(encrypted: &[u8], passphrase: &str) -> anyhow::Result<Vec<u8>> {
+    let header_len = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+    if encrypted.len() < header_len || &encrypted[..MAGIC.len()] != MAGIC {
+        return Err(anyhow::anyhow!("not a node backup"));
+    }
+    if encrypted[MAGIC.len()] != VERSION {
+        return Err(anyhow::anyhow!(
+            "backup format {} is not supported by this version",
+            encrypted[MAGIC.len()]
+        ));
+    }
+    let salt = &encrypted[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+    let nonce = &encrypted[MAGIC.len() + 1 + SALT_LEN..header_len];
+    let key = derive_key(passphrase, salt);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    cipher
+        .decrypt(
+            generic_array::GenericArray::from_slice(nonce),
+            &encrypted[header_len..],
+        )
+        .map_err(|_| anyhow::anyhow!("wrong passphrase, or the backup is damaged"))
+}
+
+async fn upload(target: &BackupTarget, body: Vec<u8>) -> anyhow::Result<()> {
+    let response = match target {
+        BackupTarget::S3 { .. } => s3_request(target, reqwest::Method::PUT, body)?,
+        BackupTarget::WebDav { url, token } => reqwest::Client::new()
+            .put(url)
+            .basic_auth("backup", Some(token))
+            .body(body),
+    }
+    .send()
+    .await?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("upload failed: {}", response.status()));
+    }
+    Ok(())
+}
+
+async fn download(target: &BackupTarget) -> anyhow::Result<Vec<u8>> {
+    let response = match target {
+        BackupTarget::S3 { .. } => s3_request(target, reqwest::Method::GET, vec![])?,
+        BackupTarget::WebDav { url, token } => reqwest::Client::new()
+            .get(url)
+            .basic_auth("backup", Some(token)),
+    }
+    .send()
+    .await?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("download failed: {}", response.status()));
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// A request for the backup object, signed with AWS Signature Version 4.
+fn s3_request(
+    target: &BackupTarget,
+    method: reqwest::Method,
+    body: Vec<u8>,
+) -> anyhow::Result<reqwest::RequestBuilder> {
+    let BackupTarget::S3 {
+        endpoint,
+        region,
+        bucket,
+        key,
+        access_key_id,
+        secret_access_key,
+    } = target
+    else {
+        return Err(anyhow::anyhow!("not an S3 target"));
+    };
+    let path = format!("/{}/{}", uri_encode(bucket), uri_encode(key));
+    let url = reqwest::Url::parse(&format!("{}{path}", endpoint.trim_end_matches('/')))?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(anyhow::anyhow!("no host in endpoint {endpoint}")),
+    };
+
+    let now = chrono::Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = hex::encode(Sha256::digest(&body));
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{method}\n{path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}"
+    );
+    let scope = format!("{date}/{region}/s3/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let signing_key = [region.as_str(), "s3", "aws4_request"].iter().fold(
+        hmac_sha256(
+            format!("AWS4{secret_access_key}").as_bytes(),
+            date.as_bytes(),
+        ),
+        |key, part| hmac_sha256(&key, part.as_bytes()),
+    );
+    let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    Ok(reqwest::Client::new()
+        .request(method, url)
+        .header("x-amz-date", amz_date)
+        .header("x-amz-content-sha256", payload_hash)
+        .header(
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={access_key_id}/{scope}, SignedHeaders={signed_headers}, Signature={signature}"
+            ),
+        )
+        .body(body))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// percent-encode all but unreserved characters and `/`, as S3 signing expects
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
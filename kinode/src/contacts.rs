@@ -0,0 +1,307 @@
+//! The address book of the node, kept by the `contacts:distro:sys` runtime module in
+//! `.contacts` in the home directory, so that every app that deals in other nodes
+//! shares the same contacts rather than keeping its own.
+//!
+//! Apps read and change it with capabilities issued by the module, one for each kind
+//! of action. Contacts are shared between nodes as offers: one of ours is sent to the
+//! contacts module of another node, which keeps it, if we are one of its contacts,
+//! until the user accepts or rejects it.
+use lib::types::core::{
+    Address, CapMessage, CapMessageSender, Capability, Contact, ContactOffer, ContactsAction,
+    ContactsCapability, ContactsError, ContactsResponse, KernelMessage, Message, MessageReceiver,
+    MessageSender, NodeId, Request, Response, CONTACTS_PROCESS_ID,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+const CONTACTS_FILE: &str = ".contacts";
+/// offers kept, newest first
+const MAX_OFFERS: usize = 100;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Stored {
+    contacts: BTreeMap<NodeId, Contact>,
+    /// newest first
+    offers: Vec<ContactOffer>,
+    next_offer_id: u64,
+}
+
+/// A runtime module that keeps the contacts of the node. Each action takes the
+/// capability named by [`ContactsAction::capability`], or the root capability.
+pub async fn contacts(
+    our: String,
+    send_to_loop: MessageSender,
+    mut recv_from_loop: MessageReceiver,
+    send_to_caps_oracle: CapMessageSender,
+    home_directory_path: PathBuf,
+) -> anyhow::Result<()> {
+    let path = home_directory_path.join(CONTACTS_FILE);
+    let mut stored = load(&path).await?;
+
+    while let Some(km) = recv_from_loop.recv().await {
+        let Message::Request(ref req) = km.message else {
+            continue;
+        };
+        let action = serde_json::from_slice::<ContactsAction>(&req.body);
+
+        // offers come from the contacts modules of other nodes, and are only kept
+        // from our contacts, so that strangers can't fill up our offers
+        if km.source.node != our {
+            if let Ok(ContactsAction::Offer { node, contact }) = action {
+                if km.source.process == *CONTACTS_PROCESS_ID
+                    && stored.contacts.contains_key(&km.source.node)
+                    && is_node_name(&node)
+                {
+                    stored.offer(km.source.node.clone(), node, contact);
+                    let _ = save(&path, &stored).await;
+                }
+            }
+            continue;
+        }
+
+        let response = match action {
+            Err(_) | Ok(ContactsAction::Offer { .. }) => {
+                ContactsResponse::Err(ContactsError::MalformedRequest)
+            }
+            Ok(action) => {
+                let capability = action.capability().unwrap();
+                if !has_capability(&our, &km.source, capability, &send_to_caps_oracle).await {
+                    ContactsResponse::Err(ContactsError::NoCap)
+                } else {
+                    match stored.handle(&our, action, &send_to_loop).await {
+                        Ok(response) => match save(&path, &stored).await {
+                            Ok(()) => response,
+                            Err(e) => ContactsResponse::Err(e),
+                        },
+                        Err(e) => ContactsResponse::Err(e),
+                    }
+                }
+            }
+        };
+        if req.expects_response.is_some() {
+            let target = km.rsvp.clone().unwrap_or(km.source.clone());
+            respond(&our, km.id, target, response, &send_to_loop).await;
+        }
+    }
+    Ok(())
+}
+
+impl Stored {
+    async fn handle(
+        &mut self,
+        our: &str,
+        action: ContactsAction,
+        send_to_loop: &MessageSender,
+    ) -> Result<ContactsResponse, ContactsError> {
+        match action {
+            ContactsAction::GetNames => Ok(ContactsResponse::Names(
+                self.contacts.keys().cloned().collect(),
+            )),
+            ContactsAction::GetAll => Ok(ContactsResponse::Contacts(self.contacts.clone())),
+            ContactsAction::Get(node) => {
+                Ok(ContactsResponse::Contact(self.contacts.get(&node).cloned()))
+            }
+            ContactsAction::Query { field, value } => Ok(ContactsResponse::Contacts(
+                self.contacts
+                    .iter()
+                    .filter(|(_, contact)| match (contact.get(&field), &value) {
+                        (Some(found), Some(value)) => found == value,
+                        (found, None) => found.is_some(),
+                        (None, Some(_)) => false,
+                    })
+                    .map(|(node, contact)| (node.clone(), contact.clone()))
+                    .collect(),
+            )),
+            ContactsAction::Add(node) => {
+                check_node_name(&node)?;
+                self.contacts.entry(node).or_default();
+                Ok(ContactsResponse::Ok)
+            }
+            ContactsAction::SetField { node, field, value } => {
+                check_node_name(&node)?;
+                self.contacts.entry(node).or_default().insert(field, value);
+                Ok(ContactsResponse::Ok)
+            }
+            ContactsAction::Remove(node) => {
+                self.contacts.remove(&node);
+                Ok(ContactsResponse::Ok)
+            }
+            ContactsAction::RemoveField { node, field } => {
+                if let Some(contact) = self.contacts.get_mut(&node) {
+                    contact.remove(&field);
+                }
+                Ok(ContactsResponse::Ok)
+            }
+            ContactsAction::Share { with, node, fields } => {
+                check_node_name(&with)?;
+                let Some(contact) = self.contacts.get(&node) else {
+                    return Err(ContactsError::NoContact(node));
+                };
+                let contact = match fields {
+                    Some(fields) => contact
+                        .iter()
+                        .filter(|(field, _)| fields.contains(field))
+                        .map(|(field, value)| (field.clone(), value.clone()))
+                        .collect(),
+                    None => contact.clone(),
+                };
+                KernelMessage::builder()
+                    .id(rand::random())
+                    .source((our, CONTACTS_PROCESS_ID.clone()))
+                    .target((with.as_str(), CONTACTS_PROCESS_ID.clone()))
+                    .message(Message::Request(Request {
+                        inherit: false,
+                        expects_response: None,
+                        body: serde_json::to_vec(&ContactsAction::Offer { node, contact }).unwrap(),
+                        metadata: None,
+                        capabilities: vec![],
+                    }))
+                    .build()
+                    .unwrap()
+                    .send(send_to_loop)
+                    .await;
+                Ok(ContactsResponse::Ok)
+            }
+            ContactsAction::GetOffers => Ok(ContactsResponse::Offers(self.offers.clone())),
+            ContactsAction::AcceptOffer(id) => {
+                let offer = self.take_offer(id)?;
+                self.contacts
+                    .entry(offer.node)
+                    .or_default()
+                    .extend(offer.contact);
+                Ok(ContactsResponse::Ok)
+            }
+            ContactsAction::RejectOffer(id) => {
+                self.take_offer(id)?;
+                Ok(ContactsResponse::Ok)
+            }
+            // handled in the loop
+            ContactsAction::Offer { .. } => Err(ContactsError::MalformedRequest),
+        }
+    }
+
+    /// Keep an offer, in place of any the same node made of the same contact.
+    fn offer(&mut self, from: NodeId, node: NodeId, contact: Contact) {
+        self.offers
+            .retain(|offer| offer.from != from || offer.node != node);
+        self.offers.insert(
+            0,
+            ContactOffer {
+                id: self.next_offer_id,
+                from,
+                node,
+                contact,
+            },
+        );
+        self.offers.truncate(MAX_OFFERS);
+        self.next_offer_id += 1;
+    }
+
+    fn take_offer(&mut self, id: u64) -> Result<ContactOffer, ContactsError> {
+        let Some(index) = self.offers.iter().position(|offer| offer.id == id) else {
+            return Err(ContactsError::NoOffer);
+        };
+        Ok(self.offers.remove(index))
+    }
+}
+
+/// Whether a name could be that of a node, such as `alice.os`. Whether it is
+/// registered is left to the apps that add it.
+fn is_node_name(name: &str) -> bool {
+    name.contains('.')
+        && !name.starts_with('.')
+        && !name.ends_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
+}
+
+fn check_node_name(name: &str) -> Result<(), ContactsError> {
+    if is_node_name(name) {
+        Ok(())
+    } else {
+        Err(ContactsError::InvalidNode(name.to_string()))
+    }
+}
+
+/// Whether the source holds the capability for an action, or the root capability.
+async fn has_capability(
+    our: &str,
+    source: &Address,
+    capability: ContactsCapability,
+    send_to_caps_oracle: &CapMessageSender,
+) -> bool {
+    for params in [
+        serde_json::to_string(&capability).unwrap(),
+        "{\"root\":true}".to_string(),
+    ] {
+        let (send_cap_bool, recv_cap_bool) = tokio::sync::oneshot::channel();
+        if send_to_caps_oracle
+            .send(CapMessage::Has {
+                on: source.process.clone(),
+                cap: Capability::new((our, CONTACTS_PROCESS_ID.clone()), params),
+                responder: send_cap_bool,
+            })
+            .await
+            .is_err()
+        {
+            return false;
+        }
+        if recv_cap_bool.await.unwrap_or(false) {
+            return true;
+        }
+    }
+    false
+}
+
+async fn respond(
+    our: &str,
+    km_id: u64,
+    target: Address,
+    response: ContactsResponse,
+    send_to_loop: &MessageSender,
+) {
+    KernelMessage::builder()
+        .id(km_id)
+        .source((our, CONTACTS_PROCESS_ID.clone()))
+        .target(target)
+        .message(Message::Response((
+            Response {
+                inherit: false,
+                body: serde_json::to_vec(&response).unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            },
+            None,
+        )))
+        .build()
+        .unwrap()
+        .send(send_to_loop)
+        .await;
+}
+
+/// Load the address book. One that can't be read is never taken as empty, since
+/// the next save would then write the empty book over it: the node won't start
+/// until the file is fixed or removed.
+async fn load(path: &Path) -> anyhow::Result<Stored> {
+    let contents = match tokio::fs::read(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Stored::default()),
+        Err(e) => return Err(anyhow::anyhow!("contacts: couldn't read {path:?}: {e}")),
+    };
+    serde_json::from_slice(&contents)
+        .map_err(|e| anyhow::anyhow!("contacts: couldn't parse {path:?}: {e}"))
+}
+
+/// Save the address book, written aside and renamed into place, so a crash
+/// mid-write never leaves it partial.
+async fn save(path: &Path, stored: &Stored) -> Result<(), ContactsError> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+    file.write_all(&serde_json::to_vec(stored).unwrap()).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
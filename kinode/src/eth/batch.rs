@@ -0,0 +1,223 @@
+use crate::eth::*;
+
+/// most requests a batch can hold
+const MAX_BATCH_LEN: usize = 100;
+
+/// Fulfill each request of a batch, from the cache where we can, and otherwise in
+/// one batch per provider: calls a provider fails to answer go on to the next one,
+/// in the same order as single requests. Errors returned by the RPC are answers.
+pub async fn fulfill_batch(
+    our: &str,
+    km_id: u64,
+    send_to_loop: &MessageSender,
+    chain_id: u64,
+    requests: &[BatchCall],
+    providers: &Providers,
+    remote_request_receiver: &mut ProcessMessageReceiver,
+    print_tx: &PrintSender,
+    request_cache: &cache::ResponseCache,
+    health: &health::HealthTracker,
+) -> EthResponse {
+    if requests.is_empty() || requests.len() > MAX_BATCH_LEN {
+        return EthResponse::Err(EthError::InvalidParams);
+    }
+    let mut methods = vec![];
+    for call in requests {
+        match valid_method(&call.method) {
+            Some("eth_subscribe" | "eth_unsubscribe") | None => {
+                return EthResponse::Err(EthError::InvalidMethod(call.method.clone()));
+            }
+            Some(method) => methods.push(method),
+        }
+    }
+    // keyed as single requests are, so that batches and single requests share the cache
+    let keys: Vec<Vec<u8>> = requests
+        .iter()
+        .map(|call| {
+            serde_json::to_vec(&EthAction::Request {
+                chain_id,
+                method: call.method.clone(),
+                params: call.params.clone(),
+            })
+            .unwrap()
+        })
+        .collect();
+
+    let mut results: Vec<Option<EthResponse>> = vec![None; requests.len()];
+    for (i, key) in keys.iter().enumerate() {
+        results[i] = request_cache.get(key, methods[i]).await;
+    }
+    let pending = |results: &Vec<Option<EthResponse>>| -> Vec<usize> {
+        (0..results.len())
+            .filter(|i| results[*i].is_none())
+            .collect()
+    };
+
+    let mut urls = {
+        // in code block to drop providers lock asap to avoid deadlock
+        let Some(aps) = providers.get(&chain_id) else {
+            return EthResponse::Err(EthError::NoRpcForChain);
+        };
+        aps.urls.clone()
+    };
+    health.order(chain_id, &mut urls, |url| &url.url);
+
+    for url_provider in urls.iter_mut() {
+        let todo = pending(&results);
+        if todo.is_empty() {
+            break;
+        }
+        if url_provider.pubsub.is_none() {
+            if activate_url_provider(url_provider).await.is_err() {
+                verbose_print(
+                    print_tx,
+                    &format!("eth: could not activate url provider {}", url_provider.url),
+                )
+                .await;
+                health.record_failure(chain_id, &url_provider.url);
+                continue;
+            }
+            // keep the connection for later requests
+            providers.entry(chain_id).and_modify(|aps| {
+                if let Some(old) = aps.urls.iter_mut().find(|u| u.url == url_provider.url) {
+                    if old.pubsub.is_none() {
+                        old.pubsub = url_provider.pubsub.clone();
+                    }
+                }
+            });
+        }
+        let pubsub = url_provider.pubsub.as_ref().unwrap();
+
+        let start = Instant::now();
+        let mut batch = pubsub.client().new_batch();
+        let mut waiters = vec![];
+        for i in &todo {
+            match batch.add_call::<_, serde_json::Value>(methods[*i], &requests[*i].params) {
+                Ok(waiter) => waiters.push((*i, waiter)),
+                Err(_) => results[*i] = Some(EthResponse::Err(EthError::InvalidParams)),
+            }
+        }
+        if let Err(e) = batch.send().await {
+            verbose_print(
+                print_tx,
+                &format!(
+                    "eth: got error from url provider {} on batch: {e}",
+                    url_provider.url
+                ),
+            )
+            .await;
+            health.record_failure(chain_id, &url_provider.url);
+            reset_url_provider(providers, chain_id, &url_provider.url);
+            continue;
+        }
+        let mut failed = false;
+        for (i, waiter) in waiters {
+            match waiter.await {
+                Ok(value) => {
+                    request_cache
+                        .insert(keys[i].clone(), methods[i], &requests[i].params, &value)
+                        .await;
+                    results[i] = Some(EthResponse::Response(value));
+                }
+                Err(RpcError::ErrorResp(err)) => {
+                    let err_value =
+                        serde_json::to_value(err).unwrap_or_else(|_| serde_json::Value::Null);
+                    results[i] = Some(EthResponse::Err(EthError::RpcError(err_value)));
+                }
+                // left for the next provider
+                Err(_) => failed = true,
+            }
+        }
+        if failed {
+            health.record_failure(chain_id, &url_provider.url);
+            reset_url_provider(providers, chain_id, &url_provider.url);
+        } else {
+            health.record_success(chain_id, &url_provider.url, start.elapsed());
+        }
+    }
+
+    let mut nodes = {
+        // in code block to drop providers lock asap to avoid deadlock
+        let Some(aps) = providers.get(&chain_id) else {
+            return EthResponse::Err(EthError::NoRpcForChain);
+        };
+        aps.nodes.clone()
+    };
+    health.order(chain_id, &mut nodes, |node| &node.kns_update.name);
+
+    for node_provider in &nodes {
+        let todo = pending(&results);
+        if todo.is_empty() {
+            break;
+        }
+        verbose_print(
+            print_tx,
+            &format!(
+                "eth: attempting to fulfill batch via {}",
+                node_provider.kns_update.name
+            ),
+        )
+        .await;
+        let start = Instant::now();
+        let response = forward_to_node_provider(
+            our,
+            km_id,
+            None,
+            node_provider,
+            EthAction::BatchRequest {
+                chain_id,
+                requests: todo.iter().map(|i| requests[*i].clone()).collect(),
+            },
+            send_to_loop,
+            remote_request_receiver,
+        )
+        .await;
+        match response {
+            EthResponse::Batch(responses) if responses.len() == todo.len() => {
+                health.record_success(chain_id, &node_provider.kns_update.name, start.elapsed());
+                for (i, response) in todo.into_iter().zip(responses) {
+                    if let EthResponse::Response(value) = &response {
+                        request_cache
+                            .insert(keys[i].clone(), methods[i], &requests[i].params, value)
+                            .await;
+                    }
+                    results[i] = Some(response);
+                }
+            }
+            // not tried
+            EthResponse::Err(EthError::PermissionDenied) => {}
+            // nodes running versions without batches can't parse them,
+            // so fall back to the next provider
+            EthResponse::Err(EthError::MalformedRequest) => {}
+            EthResponse::Err(EthError::RpcTimeout) => {
+                health.record_failure(chain_id, &node_provider.kns_update.name);
+            }
+            _ => {
+                health.record_failure(chain_id, &node_provider.kns_update.name);
+                set_node_unusable(
+                    providers,
+                    &chain_id,
+                    &node_provider.kns_update.name,
+                    print_tx,
+                )
+                .await;
+            }
+        }
+    }
+
+    EthResponse::Batch(
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or(EthResponse::Err(EthError::NoRpcForChain)))
+            .collect(),
+    )
+}
+
+/// drop the connection to a url provider that failed, to reconnect on next use
+fn reset_url_provider(providers: &Providers, chain_id: u64, url: &str) {
+    providers.entry(chain_id).and_modify(|aps| {
+        if let Some(url_provider) = aps.urls.iter_mut().find(|u| u.url == url) {
+            url_provider.pubsub = None;
+        }
+    });
+}
@@ -0,0 +1,150 @@
+//! Benchmarks of RPC URLs, to judge a provider before saving it, and to keep
+//! judging those saved.
+//!
+//! A benchmark opens a connection of its own to the URL, checks that it serves the
+//! expected chain, times [`LATENCY_SAMPLES`] requests for the latest block, and asks
+//! for a balance as of an early block, whose state only archive nodes keep. Saved
+//! RPC URLs are benchmarked every [`BENCHMARK_INTERVAL`], and the latest benchmark
+//! of each URL is kept in memory.
+use crate::eth::{utils::activate_url_provider, Providers, UrlProvider};
+use alloy::providers::Provider;
+use dashmap::DashMap;
+use lib::types::eth::ProviderTest;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// how often saved RPC URLs are benchmarked
+pub const BENCHMARK_INTERVAL: Duration = Duration::from_secs(600);
+/// requests for the latest block timed in each benchmark
+const LATENCY_SAMPLES: usize = 3;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// the block whose state is asked for to tell archive nodes from full nodes,
+/// which prune state older than the last few hundred blocks
+const ARCHIVE_BLOCK: &str = "0x1";
+
+/// latest benchmark of each RPC URL, by chain ID and URL
+#[derive(Debug, Default)]
+pub struct Benchmarks {
+    tests: DashMap<(u64, String), ProviderTest>,
+}
+
+impl Benchmarks {
+    pub fn record(&self, test: ProviderTest) {
+        self.tests.insert((test.chain_id, test.url.clone()), test);
+    }
+
+    /// latest benchmark of each saved RPC URL that has been benchmarked
+    pub fn report(&self, providers: &Providers) -> Vec<ProviderTest> {
+        providers
+            .iter()
+            .flat_map(|entry| {
+                let chain_id = *entry.key();
+                entry
+                    .urls
+                    .iter()
+                    .filter_map(|url| {
+                        self.tests
+                            .get(&(chain_id, url.url.clone()))
+                            .map(|test| test.clone())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+/// Benchmark every saved RPC URL, one at a time, so as not to skew each other.
+pub async fn benchmark_saved(providers: Providers, benchmarks: Arc<Benchmarks>) {
+    let urls: Vec<(u64, String)> = providers
+        .iter()
+        .flat_map(|entry| {
+            let chain_id = *entry.key();
+            entry
+                .urls
+                .iter()
+                .map(|url| (chain_id, url.url.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for (chain_id, url) in urls {
+        benchmarks.record(benchmark(chain_id, url).await);
+    }
+}
+
+pub async fn benchmark(chain_id: u64, url: String) -> ProviderTest {
+    let mut test = ProviderTest {
+        chain_id,
+        url: url.clone(),
+        tested_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        error: None,
+        latency_ms: None,
+        block_number: None,
+        archive: None,
+    };
+    let mut url_provider = UrlProvider {
+        trusted: false,
+        url,
+        pubsub: None,
+    };
+    if let Err(e) = activate_url_provider(&mut url_provider).await {
+        test.error = Some(format!("failed to connect: {e}"));
+        return test;
+    }
+    let pubsub = url_provider.pubsub.as_ref().unwrap();
+
+    match tokio::time::timeout(REQUEST_TIMEOUT, pubsub.get_chain_id()).await {
+        Ok(Ok(served)) if served == chain_id => {}
+        Ok(Ok(served)) => {
+            test.error = Some(format!("serves chain {served}, not {chain_id}"));
+            return test;
+        }
+        Ok(Err(e)) => {
+            test.error = Some(format!("failed to get chain ID: {e}"));
+            return test;
+        }
+        Err(_) => {
+            test.error = Some("timed out".to_string());
+            return test;
+        }
+    }
+
+    let mut latencies = Vec::with_capacity(LATENCY_SAMPLES);
+    for _ in 0..LATENCY_SAMPLES {
+        let start = Instant::now();
+        match tokio::time::timeout(REQUEST_TIMEOUT, pubsub.get_block_number()).await {
+            Ok(Ok(block_number)) => {
+                latencies.push(start.elapsed());
+                test.block_number = Some(block_number);
+            }
+            Ok(Err(e)) => {
+                test.error = Some(format!("failed to get latest block: {e}"));
+                return test;
+            }
+            Err(_) => {
+                test.error = Some("timed out".to_string());
+                return test;
+            }
+        }
+    }
+    latencies.sort_unstable();
+    test.latency_ms = Some(latencies[latencies.len() / 2].as_millis() as u64);
+
+    // full nodes answer with an error, such as "missing trie node"
+    test.archive = match tokio::time::timeout(
+        REQUEST_TIMEOUT,
+        pubsub.raw_request::<_, serde_json::Value>(
+            "eth_getBalance".into(),
+            serde_json::json!([alloy_primitives::Address::ZERO, ARCHIVE_BLOCK]),
+        ),
+    )
+    .await
+    {
+        Ok(Ok(_)) => Some(true),
+        Ok(Err(_)) => Some(false),
+        Err(_) => None,
+    };
+    test
+}
@@ -0,0 +1,193 @@
+//! Cache of RPC responses, so that processes repeating the same reads, such as
+//! kimap gets and log queries, don't each cost a round trip to a provider.
+//!
+//! Responses are keyed by chain ID, method and params, and kept for a TTL set by
+//! method: results that can't change, such as a transaction by its hash, are kept
+//! for an hour, while results that follow the head of the chain are kept for about
+//! a block. Methods that change state or depend on pending transactions are never
+//! cached. The TTL of any method can be overridden with [`EthConfigAction::SetCacheTtl`].
+//! When full, the least recently used response is dropped first.
+use dashmap::DashMap;
+use indexmap::IndexMap;
+use lib::types::eth::{CacheStats, EthResponse};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const MAX_ENTRIES: usize = 500;
+/// larger responses, such as wide log queries, are not cached
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// results that follow the head of the chain
+const HEAD_TTL_MS: u64 = 2_000;
+/// results that can't change once they exist
+const FIXED_TTL_MS: u64 = 3_600_000;
+
+/// TTL of each method, in milliseconds, where 0 means never cached
+const DEFAULT_TTLS_MS: &[(&str, u64)] = &[
+    ("eth_getBalance", HEAD_TTL_MS),
+    ("eth_sendRawTransaction", 0),
+    ("eth_call", HEAD_TTL_MS),
+    ("eth_chainId", FIXED_TTL_MS),
+    ("eth_getTransactionReceipt", FIXED_TTL_MS),
+    // nonces must be fresh to send transactions
+    ("eth_getTransactionCount", 0),
+    ("eth_estimateGas", HEAD_TTL_MS),
+    ("eth_blockNumber", HEAD_TTL_MS),
+    ("eth_getBlockByHash", FIXED_TTL_MS),
+    ("eth_getBlockByNumber", HEAD_TTL_MS),
+    ("eth_getTransactionByHash", FIXED_TTL_MS),
+    ("eth_getCode", HEAD_TTL_MS),
+    ("eth_getStorageAt", HEAD_TTL_MS),
+    ("eth_gasPrice", HEAD_TTL_MS),
+    ("eth_accounts", HEAD_TTL_MS),
+    ("eth_hashrate", HEAD_TTL_MS),
+    ("eth_getLogs", HEAD_TTL_MS),
+    ("eth_subscribe", 0),
+    ("eth_unsubscribe", 0),
+];
+
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    /// in order of last use, least recent first
+    entries: Mutex<IndexMap<Vec<u8>, (EthResponse, Instant)>>,
+    /// TTLs set by the user, in milliseconds, over those of DEFAULT_TTLS_MS
+    ttl_overrides: DashMap<String, u64>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(ttl_overrides: HashMap<String, u64>) -> Self {
+        Self {
+            ttl_overrides: ttl_overrides.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The cached response to the request serialized as `key`, if it hasn't expired.
+    pub async fn get(&self, key: &[u8], method: &str) -> Option<EthResponse> {
+        if self.method_ttl_ms(method) == 0 {
+            return None;
+        }
+        let mut entries = self.entries.lock().await;
+        match entries.shift_remove(key) {
+            Some((response, expires)) if Instant::now() < expires => {
+                entries.insert(key.to_vec(), (response.clone(), expires));
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(response)
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Cache `result`, the response to the request serialized as `key`, if `method` is cached.
+    pub async fn insert(
+        &self,
+        key: Vec<u8>,
+        method: &str,
+        params: &serde_json::Value,
+        result: &serde_json::Value,
+    ) {
+        let ttl_ms = self.ttl_ms(method, params, result);
+        if ttl_ms == 0 {
+            return;
+        }
+        if result.to_string().len() > MAX_RESPONSE_SIZE {
+            return;
+        }
+        let mut entries = self.entries.lock().await;
+        while entries.len() >= MAX_ENTRIES {
+            entries.shift_remove_index(0);
+        }
+        entries.insert(
+            key,
+            (
+                EthResponse::Response(result.clone()),
+                Instant::now() + Duration::from_millis(ttl_ms),
+            ),
+        );
+    }
+
+    pub async fn clear(&self) {
+        self.entries.lock().await.clear();
+    }
+
+    /// Set the TTL of `method`, or restore its default with `None`.
+    pub fn set_ttl(&self, method: &str, ttl_ms: Option<u64>) {
+        match ttl_ms {
+            Some(ttl_ms) => {
+                self.ttl_overrides.insert(method.to_string(), ttl_ms);
+            }
+            None => {
+                self.ttl_overrides.remove(method);
+            }
+        }
+    }
+
+    /// the TTLs set by the user, to persist
+    pub fn ttl_overrides(&self) -> HashMap<String, u64> {
+        self.ttl_overrides
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        let mut ttls_ms: HashMap<String, u64> = DEFAULT_TTLS_MS
+            .iter()
+            .map(|(method, ttl_ms)| (method.to_string(), *ttl_ms))
+            .collect();
+        ttls_ms.extend(self.ttl_overrides());
+        CacheStats {
+            entries: self.entries.lock().await.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            ttls_ms,
+        }
+    }
+
+    fn method_ttl_ms(&self, method: &str) -> u64 {
+        if let Some(ttl_ms) = self.ttl_overrides.get(method) {
+            return *ttl_ms;
+        }
+        DEFAULT_TTLS_MS
+            .iter()
+            .find(|(m, _)| *m == method)
+            .map(|(_, ttl_ms)| *ttl_ms)
+            .unwrap_or(0)
+    }
+
+    /// the TTL of a response: that of its method, unless the method's
+    /// default depends on what was asked for and what came back
+    fn ttl_ms(&self, method: &str, params: &serde_json::Value, result: &serde_json::Value) -> u64 {
+        let ttl_ms = self.method_ttl_ms(method);
+        if self.ttl_overrides.contains_key(method) {
+            return ttl_ms;
+        }
+        if ttl_ms == FIXED_TTL_MS && result.is_null() {
+            // not found yet, such as a receipt of a pending transaction
+            return HEAD_TTL_MS;
+        }
+        if method == "eth_getTransactionByHash" && result["blockNumber"].is_null() {
+            // a pending transaction, which may yet be mined, replaced or dropped
+            return 0;
+        }
+        if ttl_ms == HEAD_TTL_MS && pins_block_hash(params) {
+            return FIXED_TTL_MS;
+        }
+        ttl_ms
+    }
+}
+
+/// whether the request is made at a block given by its hash, as log
+/// filters and EIP-1898 block parameters can be, so its result is fixed
+fn pins_block_hash(params: &serde_json::Value) -> bool {
+    params
+        .as_array()
+        .is_some_and(|params| params.iter().any(|param| param.get("blockHash").is_some()))
+}
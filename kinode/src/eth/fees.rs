@@ -0,0 +1,140 @@
+//! Fee suggestions, so that apps presenting fee choices don't each poll fee history.
+//!
+//! Suggestions are made from `eth_feeHistory` over the last [`HISTORY_BLOCKS`]
+//! blocks: the tip of each level is the median, over blocks with transactions,
+//! of a percentile of the tips paid in each block, and the max fee leaves room
+//! for the next block's base fee to double. Chains without EIP-1559 are given
+//! their `eth_gasPrice`. A suggestion is cached per chain for [`CACHE_TTL`].
+use crate::eth::*;
+use std::time::Duration;
+use transactions::{parse_number, RpcContext};
+
+const CACHE_TTL: Duration = Duration::from_secs(6);
+const HISTORY_BLOCKS: u64 = 20;
+/// percentiles of the tips paid in a block for the slow, standard and fast levels
+const PERCENTILES: [u64; 3] = [25, 50, 90];
+
+/// the latest suggestion for each chain, by chain ID
+#[derive(Debug, Default)]
+pub struct FeeOracle {
+    suggestions: DashMap<u64, (FeeSuggestion, Instant)>,
+}
+
+impl FeeOracle {
+    pub async fn suggest(
+        &self,
+        ctx: &RpcContext,
+        chain_id: u64,
+    ) -> Result<FeeSuggestion, EthError> {
+        if let Some(cached) = self.suggestions.get(&chain_id) {
+            let (suggestion, fetched) = cached.value();
+            if fetched.elapsed() < CACHE_TTL {
+                return Ok(suggestion.clone());
+            }
+        }
+        // any trouble with fee history, such as a provider that doesn't serve
+        // it, falls back to the gas price
+        let suggestion = match from_fee_history(ctx, chain_id).await {
+            Ok(Some(suggestion)) => suggestion,
+            Ok(None) | Err(_) => from_gas_price(ctx, chain_id).await?,
+        };
+        self.suggestions
+            .insert(chain_id, (suggestion.clone(), Instant::now()));
+        Ok(suggestion)
+    }
+}
+
+/// `None` if the chain has no base fee
+async fn from_fee_history(
+    ctx: &RpcContext,
+    chain_id: u64,
+) -> Result<Option<FeeSuggestion>, EthError> {
+    let history = ctx
+        .call(
+            chain_id,
+            "eth_feeHistory",
+            serde_json::json!([format!("{HISTORY_BLOCKS:#x}"), "latest", PERCENTILES]),
+        )
+        .await?;
+    // one base fee per block, then the base fee of the next block
+    let Some(next_base_fee) = history
+        .get("baseFeePerGas")
+        .and_then(|fees| fees.as_array()?.last().and_then(parse_number))
+        .filter(|base_fee| *base_fee > 0)
+    else {
+        return Ok(None);
+    };
+    let oldest_block = history
+        .get("oldestBlock")
+        .and_then(parse_number)
+        .ok_or(EthError::RpcMalformedResponse)? as u64;
+    let rewards = history
+        .get("reward")
+        .and_then(|r| r.as_array())
+        .ok_or(EthError::RpcMalformedResponse)?;
+    let gas_used_ratios = history
+        .get("gasUsedRatio")
+        .and_then(|r| r.as_array())
+        .ok_or(EthError::RpcMalformedResponse)?;
+
+    // empty blocks pay no tips, and would drag the suggestions down
+    let mut tips: [Vec<u128>; 3] = Default::default();
+    for (reward, gas_used_ratio) in rewards.iter().zip(gas_used_ratios) {
+        if gas_used_ratio.as_f64().unwrap_or(0.0) == 0.0 {
+            continue;
+        }
+        let Some(reward) = reward.as_array() else {
+            return Err(EthError::RpcMalformedResponse);
+        };
+        for (level, tip) in reward.iter().enumerate().take(PERCENTILES.len()) {
+            tips[level].push(parse_number(tip).ok_or(EthError::RpcMalformedResponse)?);
+        }
+    }
+    let [slow, standard, fast] = tips.map(median);
+    // each level pays at least as much as the one below it, and some tip at all
+    let slow = slow.max(1);
+    let standard = standard.max(slow);
+    let fast = fast.max(standard);
+    let level = |tip: u128| FeeLevel {
+        max_fee_per_gas: next_base_fee * 2 + tip,
+        max_priority_fee_per_gas: tip,
+    };
+    Ok(Some(FeeSuggestion {
+        chain_id,
+        block_number: oldest_block + rewards.len().saturating_sub(1) as u64,
+        base_fee_per_gas: Some(next_base_fee),
+        slow: level(slow),
+        standard: level(standard),
+        fast: level(fast),
+    }))
+}
+
+async fn from_gas_price(ctx: &RpcContext, chain_id: u64) -> Result<FeeSuggestion, EthError> {
+    let gas_price = ctx
+        .call_number(chain_id, "eth_gasPrice", serde_json::json!([]))
+        .await?;
+    let block_number = ctx
+        .call_number(chain_id, "eth_blockNumber", serde_json::json!([]))
+        .await? as u64;
+    // offer a little less to wait, and a little more to hurry
+    let level = |gas_price: u128| FeeLevel {
+        max_fee_per_gas: gas_price,
+        max_priority_fee_per_gas: gas_price,
+    };
+    Ok(FeeSuggestion {
+        chain_id,
+        block_number,
+        base_fee_per_gas: None,
+        slow: level(gas_price - gas_price / 10),
+        standard: level(gas_price),
+        fast: level(gas_price + gas_price / 5),
+    })
+}
+
+fn median(mut values: Vec<u128>) -> u128 {
+    if values.is_empty() {
+        return 0;
+    }
+    values.sort_unstable();
+    values[values.len() / 2]
+}
@@ -0,0 +1,214 @@
+//! Per-provider health, used to route requests away from failing providers.
+//!
+//! Every request sent to a provider records whether it was answered, and how fast.
+//! Providers that fail several requests in a row, fail most recent requests, or fall
+//! behind the most synced provider for their chain are demoted: they are tried only
+//! once the others have failed, until their demotion runs out or they answer again.
+//! Repeated demotions last longer. Health is kept in memory, so a reboot clears it.
+use crate::eth::{Providers, UrlProvider};
+use alloy::providers::Provider;
+use dashmap::DashMap;
+use lib::types::eth::ProviderHealth;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// weight of the latest request in the error rate and latency averages
+const EWMA_WEIGHT: f64 = 0.2;
+/// failures in a row that demote a provider
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+/// recent error rate that demotes a provider, once it has served this many requests
+const MAX_ERROR_RATE: f64 = 0.5;
+const MIN_REQUESTS_FOR_ERROR_RATE: u64 = 10;
+/// blocks a provider can be behind the most synced one before it is demoted
+const MAX_BLOCKS_BEHIND: u64 = 10;
+/// how long the first demotion lasts, doubling for each demotion since the
+/// provider last answered, up to MAX_DEMOTION
+const DEMOTION: Duration = Duration::from_secs(30);
+const MAX_DEMOTION: Duration = Duration::from_secs(600);
+/// how often the block number of each active RPC URL is checked
+pub const SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const SYNC_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+struct Stats {
+    requests: u64,
+    errors: u64,
+    error_rate: f64,
+    latency_ms: Option<f64>,
+    consecutive_failures: u32,
+    block_number: Option<u64>,
+    demoted_until: Option<Instant>,
+    /// demotions since the provider last answered
+    demotions: u32,
+}
+
+impl Stats {
+    fn demote(&mut self) {
+        let demotion = DEMOTION
+            .saturating_mul(2u32.saturating_pow(self.demotions))
+            .min(MAX_DEMOTION);
+        self.demoted_until = Some(Instant::now() + demotion);
+        self.demotions += 1;
+        self.consecutive_failures = 0;
+    }
+
+    fn is_demoted(&self) -> bool {
+        self.demoted_until
+            .is_some_and(|demoted_until| Instant::now() < demoted_until)
+    }
+}
+
+/// health of each provider, by chain ID and RPC URL or node name
+#[derive(Debug, Default)]
+pub struct HealthTracker {
+    stats: DashMap<(u64, String), Stats>,
+}
+
+impl HealthTracker {
+    pub fn record_success(&self, chain_id: u64, provider: &str, latency: Duration) {
+        let mut stats = self
+            .stats
+            .entry((chain_id, provider.to_string()))
+            .or_default();
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        stats.requests += 1;
+        stats.error_rate *= 1.0 - EWMA_WEIGHT;
+        stats.latency_ms = Some(match stats.latency_ms {
+            Some(average) => average + EWMA_WEIGHT * (latency_ms - average),
+            None => latency_ms,
+        });
+        stats.consecutive_failures = 0;
+        stats.demoted_until = None;
+        stats.demotions = 0;
+    }
+
+    pub fn record_failure(&self, chain_id: u64, provider: &str) {
+        let mut stats = self
+            .stats
+            .entry((chain_id, provider.to_string()))
+            .or_default();
+        stats.requests += 1;
+        stats.errors += 1;
+        stats.error_rate += EWMA_WEIGHT * (1.0 - stats.error_rate);
+        stats.consecutive_failures += 1;
+        if !stats.is_demoted()
+            && (stats.consecutive_failures >= MAX_CONSECUTIVE_FAILURES
+                || (stats.requests >= MIN_REQUESTS_FOR_ERROR_RATE
+                    && stats.error_rate > MAX_ERROR_RATE))
+        {
+            stats.demote();
+        }
+    }
+
+    /// Record the latest block a provider reported, demoting it if it is
+    /// too far behind the most synced provider for the chain.
+    pub fn record_block_number(&self, chain_id: u64, provider: &str, block_number: u64) {
+        self.stats
+            .entry((chain_id, provider.to_string()))
+            .or_default()
+            .block_number = Some(block_number);
+        let Some(latest) = self.latest_block_number(chain_id) else {
+            return;
+        };
+        if latest.saturating_sub(block_number) > MAX_BLOCKS_BEHIND {
+            if let Some(mut stats) = self.stats.get_mut(&(chain_id, provider.to_string())) {
+                if !stats.is_demoted() {
+                    stats.demote();
+                }
+            }
+        }
+    }
+
+    /// Move demoted providers to the back, keeping the order otherwise.
+    pub fn order<T>(&self, chain_id: u64, providers: &mut [T], name: impl Fn(&T) -> &str) {
+        providers.sort_by_key(|provider| self.is_demoted(chain_id, name(provider)));
+    }
+
+    fn is_demoted(&self, chain_id: u64, provider: &str) -> bool {
+        self.stats
+            .get(&(chain_id, provider.to_string()))
+            .is_some_and(|stats| stats.is_demoted())
+    }
+
+    fn latest_block_number(&self, chain_id: u64) -> Option<u64> {
+        self.stats
+            .iter()
+            .filter(|entry| entry.key().0 == chain_id)
+            .filter_map(|entry| entry.value().block_number)
+            .max()
+    }
+
+    /// health of each provider configured, including those not yet used
+    pub fn report(&self, providers: &Providers) -> Vec<ProviderHealth> {
+        let mut report = vec![];
+        for entry in providers.iter() {
+            let chain_id = *entry.key();
+            let latest = self.latest_block_number(chain_id);
+            let names = entry
+                .urls
+                .iter()
+                .map(|url| url.url.clone())
+                .chain(entry.nodes.iter().map(|node| node.kns_update.name.clone()));
+            for provider in names {
+                let health = match self.stats.get(&(chain_id, provider.clone())) {
+                    Some(stats) => ProviderHealth {
+                        chain_id,
+                        provider,
+                        requests: stats.requests,
+                        errors: stats.errors,
+                        error_rate: stats.error_rate,
+                        latency_ms: stats.latency_ms.map(|latency| latency.round() as u64),
+                        block_number: stats.block_number,
+                        blocks_behind: stats
+                            .block_number
+                            .zip(latest)
+                            .map(|(ours, latest)| latest.saturating_sub(ours)),
+                        demoted: stats.is_demoted(),
+                    },
+                    None => ProviderHealth {
+                        chain_id,
+                        provider,
+                        requests: 0,
+                        errors: 0,
+                        error_rate: 0.0,
+                        latency_ms: None,
+                        block_number: None,
+                        blocks_behind: None,
+                        demoted: false,
+                    },
+                };
+                report.push(health);
+            }
+        }
+        report
+    }
+}
+
+/// Ask each RPC URL we hold a connection to for its latest block, to find
+/// those that have fallen behind. Unconnected providers are left alone, so
+/// that checking doesn't open connections that would otherwise go unused.
+pub async fn check_sync(providers: Providers, health: Arc<HealthTracker>) {
+    let urls: Vec<(u64, UrlProvider)> = providers
+        .iter()
+        .flat_map(|entry| {
+            let chain_id = *entry.key();
+            entry
+                .urls
+                .iter()
+                .filter(|url| url.pubsub.is_some())
+                .map(|url| (chain_id, url.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for (chain_id, url_provider) in urls {
+        let pubsub = url_provider.pubsub.as_ref().unwrap();
+        let start = Instant::now();
+        match tokio::time::timeout(SYNC_CHECK_TIMEOUT, pubsub.get_block_number()).await {
+            Ok(Ok(block_number)) => {
+                health.record_success(chain_id, &url_provider.url, start.elapsed());
+                health.record_block_number(chain_id, &url_provider.url, block_number);
+            }
+            _ => health.record_failure(chain_id, &url_provider.url),
+        }
+    }
+}
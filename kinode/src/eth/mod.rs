@@ -3,19 +3,24 @@ use alloy::pubsub::PubSubFrontend;
 use alloy::rpc::json_rpc::RpcError;
 use anyhow::Result;
 use dashmap::DashMap;
-use indexmap::IndexMap;
 use lib::types::core::*;
 use lib::types::eth::*;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use std::time::Instant;
 use tokio::task::JoinHandle;
 use utils::*;
 
+mod batch;
+mod benchmark;
+mod cache;
+mod fees;
+mod health;
+mod multiplex;
 mod subscription;
+mod transactions;
 mod utils;
 
 /// meta-type for all incoming requests we need to handle
@@ -104,7 +109,11 @@ type ResponseChannels = Arc<DashMap<u64, ProcessMessageSender>>;
 
 #[derive(Debug)]
 enum ActiveSub {
-    Local((tokio::sync::mpsc::Sender<bool>, JoinHandle<()>)),
+    /// served from our RPC URLs, through an upstream subscription shared with others
+    Multiplexed {
+        key: multiplex::UpstreamKey,
+        target: Address,
+    },
     Remote {
         provider_node: String,
         handle: JoinHandle<()>,
@@ -115,9 +124,8 @@ enum ActiveSub {
 impl ActiveSub {
     async fn close(&self, sub_id: u64, state: &ModuleState) {
         match self {
-            ActiveSub::Local((close_sender, _handle)) => {
-                close_sender.send(true).await.unwrap();
-                //handle.abort();
+            ActiveSub::Multiplexed { key, target } => {
+                state.multiplexer.leave(key, target, sub_id);
             }
             ActiveSub::Remote {
                 provider_node,
@@ -163,13 +171,20 @@ struct ModuleState {
     /// our sender for terminal prints
     print_tx: PrintSender,
     /// cache of ETH requests
-    request_cache: RequestCache,
+    request_cache: Arc<cache::ResponseCache>,
+    /// error rates, latency and sync status of our providers
+    health: Arc<health::HealthTracker>,
+    /// latest benchmark of each RPC URL
+    benchmarks: Arc<benchmark::Benchmarks>,
+    /// nonces of the accounts we send transactions for
+    nonces: Arc<transactions::NonceManager>,
+    /// upstream subscriptions to our RPC URLs, shared between subscribers
+    multiplexer: Arc<multiplex::Multiplexer>,
+    /// recent fee suggestions, by chain
+    fees: Arc<fees::FeeOracle>,
 }
 
-type RequestCache = Arc<Mutex<IndexMap<Vec<u8>, (EthResponse, Instant)>>>;
-
 const DELAY_MS: u64 = 1_000;
-const MAX_REQUEST_CACHE_LEN: usize = 500;
 
 /// TODO replace with alloy abstraction
 fn valid_method(method: &str) -> Option<&'static str> {
@@ -188,6 +203,7 @@ fn valid_method(method: &str) -> Option<&'static str> {
         "eth_getCode" => Some("eth_getCode"),
         "eth_getStorageAt" => Some("eth_getStorageAt"),
         "eth_gasPrice" => Some("eth_gasPrice"),
+        "eth_feeHistory" => Some("eth_feeHistory"),
         "eth_accounts" => Some("eth_accounts"),
         "eth_hashrate" => Some("eth_hashrate"),
         "eth_getLogs" => Some("eth_getLogs"),
@@ -237,6 +253,13 @@ pub async fn provider(
     )
     .await;
 
+    // load the cache TTLs set for RPC methods, if any
+    let cache_ttls: HashMap<String, u64> =
+        match tokio::fs::read_to_string(home_directory_path.join(".eth_cache_ttls")).await {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+            Err(_) => HashMap::new(),
+        };
+
     // initialize module state
     // fill out providers based on saved configs (possibly persisted, given to us)
     // this can be a mix of node providers and rpc providers
@@ -249,7 +272,12 @@ pub async fn provider(
         response_channels: Arc::new(DashMap::new()),
         send_to_loop,
         print_tx,
-        request_cache: Arc::new(Mutex::new(IndexMap::new())),
+        request_cache: Arc::new(cache::ResponseCache::new(cache_ttls)),
+        health: Arc::new(health::HealthTracker::default()),
+        benchmarks: Arc::new(benchmark::Benchmarks::default()),
+        nonces: Arc::new(transactions::NonceManager::default()),
+        multiplexer: Arc::new(multiplex::Multiplexer::default()),
+        fees: Arc::new(fees::FeeOracle::default()),
     };
 
     // convert saved configs into data structure that we will use to route queries
@@ -266,9 +294,26 @@ pub async fn provider(
 
     verbose_print(&state.print_tx, "eth: provider initialized").await;
 
-    // main loop: handle incoming network errors and incoming kernel messages
+    let mut sync_check = tokio::time::interval(health::SYNC_CHECK_INTERVAL);
+    let mut benchmark_check = tokio::time::interval(benchmark::BENCHMARK_INTERVAL);
+
+    // main loop: handle incoming network errors and incoming kernel messages,
+    // and periodically check that our providers are keeping up with their chains
+    // and benchmark them
     loop {
         tokio::select! {
+            _ = sync_check.tick() => {
+                tokio::spawn(health::check_sync(
+                    state.providers.clone(),
+                    state.health.clone(),
+                ));
+            }
+            _ = benchmark_check.tick() => {
+                tokio::spawn(benchmark::benchmark_saved(
+                    state.providers.clone(),
+                    state.benchmarks.clone(),
+                ));
+            }
             Some(wrapped_error) = net_error_recv.recv() => {
                 handle_network_error(
                     wrapped_error,
@@ -377,6 +422,12 @@ async fn handle_message(
                     handle_eth_action(state, km, timeout, eth_action).await
                 }
                 IncomingReq::EthConfigAction(eth_config_action) => {
+                    // actions that take a while are answered once done, from their own task
+                    let Some(response) =
+                        handle_eth_config_action(state, caps_oracle, &km, eth_config_action).await
+                    else {
+                        return Ok(());
+                    };
                     kernel_message(
                         &state.our.clone(),
                         km.id,
@@ -384,7 +435,7 @@ async fn handle_message(
                         None,
                         false,
                         None,
-                        handle_eth_config_action(state, caps_oracle, &km, eth_config_action).await,
+                        response,
                         &state.send_to_loop,
                     )
                     .await;
@@ -526,6 +577,9 @@ async fn handle_eth_action(
                 EthAction::SubscribeLogs { .. } => "subscribe",
                 EthAction::UnsubscribeLogs(_) => "unsubscribe",
                 EthAction::Request { .. } => "request",
+                EthAction::BatchRequest { .. } => "batch request",
+                EthAction::SendTransaction { .. } => "transaction",
+                EthAction::GetFeeSuggestion(_) => "fee suggestion",
             },
             km.source,
             state
@@ -615,7 +669,48 @@ async fn handle_eth_action(
                 state.active_subscriptions.remove(&km.source);
             }
         }
-        EthAction::Request { .. } => {
+        EthAction::SendTransaction {
+            chain_id,
+            tx,
+            confirmations,
+        } => {
+            // only our processes spend from our accounts
+            if km.source.node != *state.our {
+                return Err(EthError::PermissionDenied);
+            }
+            transactions::send_transaction(
+                rpc_context(state),
+                state.nonces.clone(),
+                state.fees.clone(),
+                km,
+                chain_id,
+                tx,
+                confirmations,
+            )
+            .await;
+        }
+        EthAction::GetFeeSuggestion(chain_id) => {
+            let ctx = rpc_context(state);
+            let fees = state.fees.clone();
+            tokio::spawn(async move {
+                let response = match fees.suggest(&ctx, chain_id).await {
+                    Ok(suggestion) => EthResponse::FeeSuggestion(suggestion),
+                    Err(e) => EthResponse::Err(e),
+                };
+                kernel_message(
+                    &ctx.our,
+                    km.id,
+                    km.rsvp.unwrap_or(km.source),
+                    None,
+                    false,
+                    None,
+                    response,
+                    &ctx.send_to_loop,
+                )
+                .await;
+            });
+        }
+        EthAction::Request { .. } | EthAction::BatchRequest { .. } => {
             let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
             state.response_channels.insert(km.id, sender);
             let our = state.our.to_string();
@@ -623,7 +718,8 @@ async fn handle_eth_action(
             let providers = state.providers.clone();
             let response_channels = state.response_channels.clone();
             let print_tx = state.print_tx.clone();
-            let mut request_cache = Arc::clone(&state.request_cache);
+            let request_cache = Arc::clone(&state.request_cache);
+            let health = state.health.clone();
             tokio::spawn(async move {
                 match tokio::time::timeout(
                     std::time::Duration::from_secs(timeout),
@@ -635,7 +731,8 @@ async fn handle_eth_action(
                         &providers,
                         &mut receiver,
                         &print_tx,
-                        &mut request_cache,
+                        &request_cache,
+                        &health,
                     ),
                 )
                 .await
@@ -654,7 +751,8 @@ async fn handle_eth_action(
                                     &providers,
                                     &mut receiver,
                                     &print_tx,
-                                    &mut request_cache,
+                                    &request_cache,
+                                    &health,
                                 ),
                             )
                             .await
@@ -711,6 +809,18 @@ async fn handle_eth_action(
     Ok(())
 }
 
+fn rpc_context(state: &ModuleState) -> transactions::RpcContext {
+    transactions::RpcContext {
+        our: state.our.clone(),
+        send_to_loop: state.send_to_loop.clone(),
+        providers: state.providers.clone(),
+        response_channels: state.response_channels.clone(),
+        print_tx: state.print_tx.clone(),
+        request_cache: state.request_cache.clone(),
+        health: state.health.clone(),
+    }
+}
+
 async fn fulfill_request(
     our: &str,
     km_id: u64,
@@ -719,8 +829,24 @@ async fn fulfill_request(
     providers: &Providers,
     remote_request_receiver: &mut ProcessMessageReceiver,
     print_tx: &PrintSender,
-    request_cache: &mut RequestCache,
+    request_cache: &cache::ResponseCache,
+    health: &health::HealthTracker,
 ) -> EthResponse {
+    if let EthAction::BatchRequest { chain_id, requests } = eth_action {
+        return batch::fulfill_batch(
+            our,
+            km_id,
+            send_to_loop,
+            *chain_id,
+            requests,
+            providers,
+            remote_request_receiver,
+            print_tx,
+            request_cache,
+            health,
+        )
+        .await;
+    }
     let serialized_action = serde_json::to_vec(eth_action).unwrap();
     let EthAction::Request {
         ref chain_id,
@@ -730,15 +856,8 @@ async fn fulfill_request(
     else {
         return EthResponse::Err(EthError::PermissionDenied); // will never hit
     };
-    {
-        let mut request_cache = request_cache.lock().await;
-        if let Some((cache_hit, time_of_hit)) = request_cache.shift_remove(&serialized_action) {
-            // refresh cache entry (it is most recently accessed) & return it
-            if time_of_hit.elapsed() < Duration::from_millis(DELAY_MS) {
-                request_cache.insert(serialized_action, (cache_hit.clone(), time_of_hit));
-                return cache_hit;
-            }
-        }
+    if let Some(cache_hit) = request_cache.get(&serialized_action, method).await {
+        return cache_hit;
     }
     let Some(method) = valid_method(&method) else {
         return EthResponse::Err(EthError::InvalidMethod(method.to_string()));
@@ -750,10 +869,12 @@ async fn fulfill_request(
         };
         aps.urls.clone()
     };
+    health.order(*chain_id, &mut urls, |url| &url.url);
 
     // first, try any url providers we have for this chain,
     // then if we have none or they all fail, go to node providers.
     // finally, if no provider works, return an error.
+    // demoted providers are tried after the others of their kind.
 
     // bump the successful provider to the front of the list for future requests
     for url_provider in urls.iter_mut() {
@@ -773,11 +894,21 @@ async fn fulfill_request(
                         &format!("eth: could not activate url provider {}", url_provider.url),
                     )
                     .await;
+                    health.record_failure(*chain_id, &url_provider.url);
                     continue;
                 }
             }
         };
-        match pubsub.raw_request(method.into(), params).await {
+        let start = Instant::now();
+        let result = pubsub.raw_request(method.into(), params).await;
+        match &result {
+            // an error response is still an answer
+            Ok(_) | Err(RpcError::ErrorResp(_)) => {
+                health.record_success(*chain_id, &url_provider.url, start.elapsed())
+            }
+            Err(_) => health.record_failure(*chain_id, &url_provider.url),
+        }
+        match result {
             Ok(value) => {
                 let mut is_replacement_successful = true;
                 providers.entry(chain_id.clone()).and_modify(|aps| {
@@ -801,14 +932,10 @@ async fn fulfill_request(
                     )
                     .await;
                 }
-                let response = EthResponse::Response(value);
-                let mut request_cache = request_cache.lock().await;
-                if request_cache.len() >= MAX_REQUEST_CACHE_LEN {
-                    // drop 10% oldest cache entries
-                    request_cache.drain(0..MAX_REQUEST_CACHE_LEN / 10);
-                }
-                request_cache.insert(serialized_action, (response.clone(), Instant::now()));
-                return response;
+                request_cache
+                    .insert(serialized_action, method, params, &value)
+                    .await;
+                return EthResponse::Response(value);
             }
             Err(rpc_error) => {
                 verbose_print(
@@ -850,13 +977,14 @@ async fn fulfill_request(
         }
     }
 
-    let nodes = {
+    let mut nodes = {
         // in code block to drop providers lock asap to avoid deadlock
         let Some(aps) = providers.get(&chain_id) else {
             return EthResponse::Err(EthError::NoRpcForChain);
         };
         aps.nodes.clone()
     };
+    health.order(*chain_id, &mut nodes, |node| &node.kns_update.name);
     for node_provider in &nodes {
         verbose_print(
             print_tx,
@@ -866,6 +994,7 @@ async fn fulfill_request(
             ),
         )
         .await;
+        let start = Instant::now();
         let response = forward_to_node_provider(
             our,
             km_id,
@@ -876,6 +1005,14 @@ async fn fulfill_request(
             remote_request_receiver,
         )
         .await;
+        match &response {
+            // not tried
+            EthResponse::Err(EthError::PermissionDenied) => {}
+            EthResponse::Err(EthError::RpcTimeout | EthError::RpcMalformedResponse) => {
+                health.record_failure(*chain_id, &node_provider.kns_update.name)
+            }
+            _ => health.record_success(*chain_id, &node_provider.kns_update.name, start.elapsed()),
+        }
         if let EthResponse::Err(e) = response {
             if let EthError::RpcMalformedResponse = e {
                 set_node_unusable(
@@ -887,6 +1024,11 @@ async fn fulfill_request(
                 .await;
             }
         } else {
+            if let EthResponse::Response(value) = &response {
+                request_cache
+                    .insert(serialized_action, method, params, value)
+                    .await;
+            }
             return response;
         }
     }
@@ -939,14 +1081,14 @@ async fn handle_eth_config_action(
     caps_oracle: &CapMessageSender,
     km: &KernelMessage,
     eth_config_action: EthConfigAction,
-) -> EthConfigResponse {
+) -> Option<EthConfigResponse> {
     if km.source.node != *state.our {
         verbose_print(
             &state.print_tx,
             "eth: got eth_config_action from unauthorized remote source",
         )
         .await;
-        return EthConfigResponse::PermissionDenied;
+        return Some(EthConfigResponse::PermissionDenied);
     }
 
     // check capabilities to ensure the sender is allowed to make this request
@@ -956,7 +1098,7 @@ async fn handle_eth_config_action(
             "eth: got eth_config_action from unauthorized local source",
         )
         .await;
-        return EthConfigResponse::PermissionDenied;
+        return Some(EthConfigResponse::PermissionDenied);
     }
 
     verbose_print(
@@ -967,6 +1109,7 @@ async fn handle_eth_config_action(
 
     let mut save_settings = false;
     let mut save_providers = false;
+    let mut save_cache_ttls = false;
 
     // modify our providers and access settings based on config action
     match eth_config_action {
@@ -1024,13 +1167,63 @@ async fn handle_eth_config_action(
             save_providers = true;
         }
         EthConfigAction::GetProviders => {
-            return EthConfigResponse::Providers(providers_to_saved_configs(&state.providers));
+            return Some(EthConfigResponse::Providers(providers_to_saved_configs(
+                &state.providers,
+            )));
         }
         EthConfigAction::GetAccessSettings => {
-            return EthConfigResponse::AccessSettings(state.access_settings.clone());
+            return Some(EthConfigResponse::AccessSettings(
+                state.access_settings.clone(),
+            ));
+        }
+        EthConfigAction::SetCacheTtl { method, ttl_ms } => {
+            state.request_cache.set_ttl(&method, ttl_ms);
+            save_cache_ttls = true;
+        }
+        EthConfigAction::GetCacheStats => {
+            return Some(EthConfigResponse::CacheStats(
+                state.request_cache.stats().await,
+            ));
+        }
+        EthConfigAction::ClearCache => {
+            state.request_cache.clear().await;
+        }
+        EthConfigAction::GetProviderHealth => {
+            return Some(EthConfigResponse::ProviderHealth(
+                state.health.report(&state.providers),
+            ));
+        }
+        EthConfigAction::TestProvider((chain_id, url)) => {
+            // a benchmark takes a while, so is run and answered from its own task,
+            // leaving the loop free to handle other requests meanwhile
+            let our = state.our.clone();
+            let benchmarks = state.benchmarks.clone();
+            let send_to_loop = state.send_to_loop.clone();
+            let (km_id, target) = (km.id, km.rsvp.as_ref().unwrap_or(&km.source).clone());
+            tokio::spawn(async move {
+                let test = benchmark::benchmark(chain_id, url).await;
+                benchmarks.record(test.clone());
+                kernel_message(
+                    &our,
+                    km_id,
+                    target,
+                    None,
+                    false,
+                    None,
+                    EthConfigResponse::ProviderTest(test),
+                    &send_to_loop,
+                )
+                .await;
+            });
+            return None;
+        }
+        EthConfigAction::GetProviderTests => {
+            return Some(EthConfigResponse::ProviderTests(
+                state.benchmarks.report(&state.providers),
+            ));
         }
         EthConfigAction::GetState => {
-            return EthConfigResponse::State {
+            return Some(EthConfigResponse::State {
                 active_subscriptions: state
                     .active_subscriptions
                     .iter()
@@ -1043,7 +1236,7 @@ async fn handle_eth_config_action(
                                     (
                                         *id,
                                         match sub {
-                                            ActiveSub::Local(_) => None,
+                                            ActiveSub::Multiplexed { .. } => None,
                                             ActiveSub::Remote { provider_node, .. } => {
                                                 Some(provider_node.clone())
                                             }
@@ -1055,7 +1248,7 @@ async fn handle_eth_config_action(
                     })
                     .collect(),
                 outstanding_requests: state.response_channels.iter().map(|e| *e.key()).collect(),
-            };
+            });
         }
     }
     // save providers and/or access settings, depending on necessity, to disk
@@ -1079,5 +1272,15 @@ async fn handle_eth_config_action(
             verbose_print(&state.print_tx, "eth: saved new provider settings").await;
         };
     }
-    EthConfigResponse::Ok
+    if save_cache_ttls {
+        if let Ok(()) = tokio::fs::write(
+            state.home_directory_path.join(".eth_cache_ttls"),
+            serde_json::to_string(&state.request_cache.ttl_overrides()).unwrap(),
+        )
+        .await
+        {
+            verbose_print(&state.print_tx, "eth: saved new cache TTLs").await;
+        };
+    }
+    Some(EthConfigResponse::Ok)
 }
@@ -0,0 +1,474 @@
+//! Sharing of subscriptions to our RPC URLs between subscribers, and their recovery.
+//!
+//! Subscribers, local or remote, whose filters differ only in block range share
+//! one upstream subscription, since block ranges don't apply to subscriptions.
+//! Each update is sent to every subscriber, and the latest log sent to each, and
+//! the latest block all of whose logs it has been sent, are tracked. When the
+//! upstream subscription fails, as when its provider drops the connection, it is
+//! resubscribed through any of the chain's RPC URLs, and each subscriber is sent
+//! the logs it missed, from the block after the last it had processed up to the
+//! head of the chain. Missed heads are not replayed. Only once resubscribing has failed
+//! [`MAX_RECOVERY_ATTEMPTS`] times are subscribers sent an error.
+use crate::eth::*;
+use alloy::pubsub::RawSubscription;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+const MAX_RECOVERY_ATTEMPTS: u32 = 5;
+/// wait before the first attempt to resubscribe, doubling for each attempt after
+const RECOVERY_BACKOFF: Duration = Duration::from_secs(1);
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// what subscribers share an upstream subscription by
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UpstreamKey {
+    pub chain_id: u64,
+    pub kind: SubscriptionKind,
+    /// the filter without its block range, serialized
+    params: String,
+}
+
+impl UpstreamKey {
+    pub fn new(chain_id: u64, kind: SubscriptionKind, params: &serde_json::Value) -> Self {
+        let mut params = params.clone();
+        if let Some(filter) = params.as_object_mut() {
+            filter.remove("fromBlock");
+            filter.remove("toBlock");
+            filter.remove("blockHash");
+        }
+        Self {
+            chain_id,
+            kind,
+            params: params.to_string(),
+        }
+    }
+
+    /// the filter to subscribe with
+    pub fn params(&self) -> serde_json::Value {
+        serde_json::from_str(&self.params).unwrap()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Subscriber {
+    target: Address,
+    rsvp: Option<Address>,
+    sub_id: u64,
+    /// block number and log index of the latest log sent
+    last_sent: Option<(u64, u64)>,
+    /// latest block all of whose logs have been sent, to replay from the block
+    /// after: first the latest block seen when the subscriber joined, then the
+    /// block before each log sent, or the head of the chain a replay reached
+    processed_block: Option<u64>,
+}
+
+impl Subscriber {
+    fn new(target: &Address, rsvp: &Option<Address>, sub_id: u64, latest_block: u64) -> Self {
+        Self {
+            target: target.clone(),
+            rsvp: rsvp.clone(),
+            sub_id,
+            last_sent: None,
+            processed_block: (latest_block > 0).then_some(latest_block),
+        }
+    }
+
+    /// whether a log at `position` is new to the subscriber
+    fn is_unsent(&self, position: (u64, u64)) -> bool {
+        let after_processed = match self.processed_block {
+            Some(processed_block) => position.0 > processed_block,
+            None => true,
+        };
+        let after_last_sent = match self.last_sent {
+            Some(last_sent) => position > last_sent,
+            None => true,
+        };
+        after_processed && after_last_sent
+    }
+
+    /// note that the log at `position` was sent: logs arrive in block order,
+    /// so every block before its block has been processed
+    fn sent(&mut self, position: (u64, u64)) {
+        self.last_sent = Some(position);
+        self.processed(position.0.saturating_sub(1));
+    }
+
+    fn processed(&mut self, block: u64) {
+        self.processed_block = self.processed_block.max(Some(block));
+    }
+}
+
+type Subscribers = Arc<DashMap<(Address, u64), Subscriber>>;
+
+struct Upstream {
+    /// tells an upstream apart from one that replaced it under the same key
+    id: u64,
+    subscribers: Subscribers,
+    /// latest block number seen in an update, 0 if none yet
+    latest_block: Arc<AtomicU64>,
+    close_sender: tokio::sync::mpsc::Sender<()>,
+}
+
+impl Upstream {
+    fn add(&self, target: &Address, rsvp: &Option<Address>, sub_id: u64) {
+        self.subscribers.insert(
+            (target.clone(), sub_id),
+            Subscriber::new(
+                target,
+                rsvp,
+                sub_id,
+                self.latest_block.load(Ordering::Relaxed),
+            ),
+        );
+    }
+}
+
+/// the upstream subscriptions open, by what they are shared by
+#[derive(Default)]
+pub struct Multiplexer {
+    upstreams: DashMap<UpstreamKey, Upstream>,
+}
+
+/// what an upstream needs from the module to send updates, resubscribe and replay
+#[derive(Clone)]
+pub struct MuxContext {
+    pub our: Arc<String>,
+    pub send_to_loop: MessageSender,
+    pub providers: Providers,
+    pub print_tx: PrintSender,
+    pub health: Arc<health::HealthTracker>,
+    pub active_subscriptions: ActiveSubscriptions,
+    pub multiplexer: Arc<Multiplexer>,
+}
+
+impl Multiplexer {
+    /// Add a subscriber to the upstream for `key`, if one is open.
+    pub fn join(
+        &self,
+        key: &UpstreamKey,
+        target: &Address,
+        rsvp: &Option<Address>,
+        sub_id: u64,
+    ) -> bool {
+        let Some(upstream) = self.upstreams.get(key) else {
+            return false;
+        };
+        upstream.add(target, rsvp, sub_id);
+        true
+    }
+
+    /// Share a new upstream subscription, made through `pubsub`, starting with one subscriber.
+    /// If another was opened for the same key meanwhile, the subscriber joins that one instead.
+    pub async fn open(
+        &self,
+        key: UpstreamKey,
+        rx: RawSubscription,
+        pubsub: RootProvider<PubSubFrontend>,
+        target: &Address,
+        rsvp: &Option<Address>,
+        sub_id: u64,
+        ctx: MuxContext,
+    ) {
+        let latest_block = tokio::time::timeout(Duration::from_secs(10), pubsub.get_block_number())
+            .await
+            .ok()
+            .and_then(|r| r.ok())
+            .unwrap_or(0);
+        let (id, subscribers, latest_block, close_receiver) =
+            match self.upstreams.entry(key.clone()) {
+                dashmap::mapref::entry::Entry::Occupied(upstream) => {
+                    // dropping rx ends the redundant subscription
+                    upstream.get().add(target, rsvp, sub_id);
+                    return;
+                }
+                dashmap::mapref::entry::Entry::Vacant(entry) => {
+                    let (close_sender, close_receiver) = tokio::sync::mpsc::channel(1);
+                    let upstream = Upstream {
+                        id: rand::random(),
+                        subscribers: Arc::new(DashMap::new()),
+                        latest_block: Arc::new(AtomicU64::new(latest_block)),
+                        close_sender,
+                    };
+                    upstream.add(target, rsvp, sub_id);
+                    let started = (
+                        upstream.id,
+                        upstream.subscribers.clone(),
+                        upstream.latest_block.clone(),
+                        close_receiver,
+                    );
+                    entry.insert(upstream);
+                    started
+                }
+            };
+        tokio::spawn(run_upstream(
+            key,
+            id,
+            rx,
+            pubsub,
+            subscribers,
+            latest_block,
+            close_receiver,
+            ctx,
+        ));
+    }
+
+    /// Remove a subscriber, closing its upstream if no subscribers are left.
+    pub fn leave(&self, key: &UpstreamKey, target: &Address, sub_id: u64) {
+        if let Some(upstream) = self.upstreams.get(key) {
+            upstream.subscribers.remove(&(target.clone(), sub_id));
+        }
+        if let Some((_, upstream)) = self
+            .upstreams
+            .remove_if(key, |_, upstream| upstream.subscribers.is_empty())
+        {
+            let _ = upstream.close_sender.try_send(());
+        }
+    }
+}
+
+async fn run_upstream(
+    key: UpstreamKey,
+    id: u64,
+    mut rx: RawSubscription,
+    mut pubsub: RootProvider<PubSubFrontend>,
+    subscribers: Subscribers,
+    latest_block: Arc<AtomicU64>,
+    mut close_receiver: tokio::sync::mpsc::Receiver<()>,
+    ctx: MuxContext,
+) {
+    loop {
+        let error = tokio::select! {
+            _ = close_receiver.recv() => return,
+            value = rx.recv() => match value {
+                Ok(value) => match serde_json::from_str::<serde_json::Value>(value.get()) {
+                    Ok(result) => {
+                        send_update(&ctx, &subscribers, &latest_block, &result).await;
+                        continue;
+                    }
+                    Err(e) => e.to_string(),
+                },
+                // still subscribed, but updates were dropped: send them from the chain
+                Err(RecvError::Lagged(_)) => {
+                    replay(&key, &pubsub, &ctx, &subscribers, &latest_block).await;
+                    continue;
+                }
+                Err(e) => e.to_string(),
+            },
+        };
+        verbose_print(
+            &ctx.print_tx,
+            &format!(
+                "eth: subscription on chain {} failed ({error}), resubscribing",
+                key.chain_id
+            ),
+        )
+        .await;
+
+        let mut resubscribed = None;
+        for attempt in 0..MAX_RECOVERY_ATTEMPTS {
+            tokio::select! {
+                _ = close_receiver.recv() => return,
+                _ = tokio::time::sleep(RECOVERY_BACKOFF * 2u32.pow(attempt)) => {}
+            }
+            resubscribed = super::subscription::subscribe_via_urls(
+                key.chain_id,
+                key.kind,
+                &key.params(),
+                &ctx.providers,
+                &ctx.print_tx,
+                &ctx.health,
+            )
+            .await;
+            if resubscribed.is_some() {
+                break;
+            }
+        }
+        let Some((new_rx, new_pubsub)) = resubscribed else {
+            give_up(&key, id, &ctx, &subscribers, error).await;
+            return;
+        };
+        rx = new_rx;
+        pubsub = new_pubsub;
+        replay(&key, &pubsub, &ctx, &subscribers, &latest_block).await;
+    }
+}
+
+/// Send an update to each subscriber it is new to.
+async fn send_update(
+    ctx: &MuxContext,
+    subscribers: &Subscribers,
+    latest_block: &AtomicU64,
+    result: &serde_json::Value,
+) {
+    if let Some(block) = block_number(result) {
+        latest_block.fetch_max(block, Ordering::Relaxed);
+    }
+    let position = log_position(result);
+    // copied out, so as not to hold the map while sending
+    let snapshot: Vec<Subscriber> = subscribers.iter().map(|s| s.value().clone()).collect();
+    for subscriber in snapshot {
+        if let Some(position) = position {
+            if !subscriber.is_unsent(position) {
+                // already sent in a replay
+                continue;
+            }
+        }
+        kernel_message(
+            &ctx.our,
+            rand::random(),
+            subscriber.target.clone(),
+            subscriber.rsvp.clone(),
+            true,
+            None,
+            EthSubResult::Ok(EthSub {
+                id: subscriber.sub_id,
+                result: result.clone(),
+            }),
+            &ctx.send_to_loop,
+        )
+        .await;
+        if let Some(position) = position {
+            if let Some(mut s) = subscribers.get_mut(&(subscriber.target, subscriber.sub_id)) {
+                s.sent(position);
+            }
+        }
+    }
+}
+
+/// Send each subscriber the logs it missed, from the block after the last it had
+/// processed up to the head of the chain, fetched in one range from the earliest
+/// such block. A subscriber that had processed no block is caught up from the head.
+async fn replay(
+    key: &UpstreamKey,
+    pubsub: &RootProvider<PubSubFrontend>,
+    ctx: &MuxContext,
+    subscribers: &Subscribers,
+    latest_block: &AtomicU64,
+) {
+    if key.kind != SubscriptionKind::Logs {
+        return;
+    }
+    let to_block = match tokio::time::timeout(REPLAY_TIMEOUT, pubsub.get_block_number()).await {
+        Ok(Ok(block)) => block,
+        Ok(Err(e)) => {
+            verbose_print(&ctx.print_tx, &format!("eth: could not replay logs: {e}")).await;
+            return;
+        }
+        Err(_) => {
+            verbose_print(&ctx.print_tx, "eth: could not replay logs: timed out").await;
+            return;
+        }
+    };
+    let Some(from_block) = subscribers
+        .iter()
+        .filter_map(|s| s.processed_block.map(|block| block + 1))
+        .min()
+    else {
+        mark_processed(subscribers, to_block);
+        return;
+    };
+    if from_block > to_block {
+        return;
+    }
+    let mut filter = key.params();
+    let Some(object) = filter.as_object_mut() else {
+        return;
+    };
+    object.insert("fromBlock".into(), format!("{from_block:#x}").into());
+    object.insert("toBlock".into(), format!("{to_block:#x}").into());
+    let logs = match tokio::time::timeout(
+        REPLAY_TIMEOUT,
+        pubsub.raw_request::<_, Vec<serde_json::Value>>("eth_getLogs".into(), [filter]),
+    )
+    .await
+    {
+        Ok(Ok(logs)) => logs,
+        Ok(Err(e)) => {
+            verbose_print(&ctx.print_tx, &format!("eth: could not replay logs: {e}")).await;
+            return;
+        }
+        Err(_) => {
+            verbose_print(&ctx.print_tx, "eth: could not replay logs: timed out").await;
+            return;
+        }
+    };
+    verbose_print(
+        &ctx.print_tx,
+        &format!(
+            "eth: replaying up to {} logs from block {from_block} on chain {}",
+            logs.len(),
+            key.chain_id
+        ),
+    )
+    .await;
+    for log in logs {
+        send_update(ctx, subscribers, latest_block, &log).await;
+    }
+    mark_processed(subscribers, to_block);
+}
+
+/// note that every subscriber has been sent all logs up to and including `block`
+fn mark_processed(subscribers: &Subscribers, block: u64) {
+    for mut subscriber in subscribers.iter_mut() {
+        subscriber.processed(block);
+    }
+}
+
+/// Tell each subscriber its subscription is closed, and forget them.
+async fn give_up(
+    key: &UpstreamKey,
+    id: u64,
+    ctx: &MuxContext,
+    subscribers: &Subscribers,
+    error: String,
+) {
+    ctx.multiplexer
+        .upstreams
+        .remove_if(key, |_, upstream| upstream.id == id);
+    let snapshot: Vec<Subscriber> = subscribers.iter().map(|s| s.value().clone()).collect();
+    for subscriber in snapshot {
+        kernel_message(
+            &ctx.our,
+            rand::random(),
+            subscriber.target.clone(),
+            subscriber.rsvp.clone(),
+            true,
+            None,
+            EthSubResult::Err(EthSubError {
+                id: subscriber.sub_id,
+                error: format!("subscription closed unexpectedly: {error}"),
+            }),
+            &ctx.send_to_loop,
+        )
+        .await;
+        ctx.active_subscriptions
+            .entry(subscriber.target)
+            .and_modify(|sub_map| {
+                sub_map.remove(&subscriber.sub_id);
+            });
+    }
+}
+
+/// the block of a log or head
+fn block_number(result: &serde_json::Value) -> Option<u64> {
+    result
+        .get("blockNumber")
+        .or(result.get("number"))
+        .and_then(parse_quantity)
+}
+
+/// block number and log index of a log, unless it is a log removed by a reorg
+fn log_position(result: &serde_json::Value) -> Option<(u64, u64)> {
+    if result.get("removed").and_then(|r| r.as_bool()) == Some(true) {
+        return None;
+    }
+    Some((
+        result.get("blockNumber").and_then(parse_quantity)?,
+        result.get("logIndex").and_then(parse_quantity)?,
+    ))
+}
+
+fn parse_quantity(value: &serde_json::Value) -> Option<u64> {
+    u64::from_str_radix(value.as_str()?.trim_start_matches("0x"), 16).ok()
+}
@@ -1,6 +1,7 @@
 use crate::eth::*;
 use alloy::pubsub::RawSubscription;
 use alloy::rpc::types::eth::pubsub::SubscriptionResult;
+use multiplex::{MuxContext, UpstreamKey};
 
 /// cleans itself up when the subscription is closed or fails.
 pub async fn create_new_subscription(
@@ -17,7 +18,38 @@ pub async fn create_new_subscription(
     let providers = state.providers.clone();
     let response_channels = state.response_channels.clone();
     let print_tx = state.print_tx.clone();
+    let health = state.health.clone();
+    let multiplexer = state.multiplexer.clone();
+    let EthAction::SubscribeLogs {
+        chain_id,
+        kind,
+        ref params,
+        ..
+    } = eth_action
+    else {
+        return; // will never hit
+    };
+    let key = UpstreamKey::new(chain_id, kind, params);
     tokio::spawn(async move {
+        // if another subscriber has the same filter, share its subscription
+        if multiplexer.join(&key, &target, &rsvp, sub_id) {
+            kernel_message(
+                &our,
+                km_id,
+                target.clone(),
+                rsvp.clone(),
+                false,
+                None,
+                EthResponse::Ok,
+                &send_to_loop,
+            )
+            .await;
+            active_subscriptions
+                .entry(target.clone())
+                .or_insert(HashMap::new())
+                .insert(sub_id, ActiveSub::Multiplexed { key, target });
+            return;
+        }
         match tokio::time::timeout(
             std::time::Duration::from_secs(30),
             build_subscription(
@@ -29,6 +61,7 @@ pub async fn create_new_subscription(
                 &providers,
                 &response_channels,
                 &print_tx,
+                &health,
             ),
         )
         .await
@@ -63,60 +96,35 @@ pub async fn create_new_subscription(
                     &send_to_loop,
                 )
                 .await;
-                let mut subs = active_subscriptions
-                    .entry(target.clone())
-                    .or_insert(HashMap::new());
-                let our = our.clone();
-                let send_to_loop = send_to_loop.clone();
-                let print_tx = print_tx.clone();
-                let active_subscriptions = active_subscriptions.clone();
-                let (close_sender, close_receiver) = tokio::sync::mpsc::channel(1);
                 match maybe_raw_sub {
-                    Ok((rx, _chain_id)) => {
-                        subs.insert(
-                            sub_id,
-                            // this is a local sub, as in, we connect to the rpc endpoint
-                            ActiveSub::Local((
-                                close_sender,
-                                tokio::spawn(async move {
-                                    // await the subscription error and kill it if so
-                                    let r = maintain_local_subscription(
-                                        &our,
-                                        sub_id,
-                                        rx,
-                                        &target,
-                                        &rsvp,
-                                        &send_to_loop,
-                                        &active_subscriptions,
-                                        close_receiver,
-                                    )
-                                    .await;
-                                    let Err(e) = r else {
-                                        return;
-                                    };
-                                    verbose_print(
-                                        &print_tx,
-                                        &format!(
-                                            "eth: closed local subscription due to error {e:?}"
-                                        ),
-                                    )
-                                    .await;
-                                    kernel_message(
-                                        &our,
-                                        rand::random(),
-                                        target.clone(),
-                                        rsvp,
-                                        true,
-                                        None,
-                                        EthSubResult::Err(e),
-                                        &send_to_loop,
-                                    )
-                                    .await;
-                                }),
-                            )),
-                        );
+                    Ok((rx, pubsub)) => {
+                        // this is a local sub, as in, we connect to the rpc endpoint,
+                        // shared with any subscribers with the same filter that follow
+                        let ctx = MuxContext {
+                            our: our.clone(),
+                            send_to_loop: send_to_loop.clone(),
+                            providers: providers.clone(),
+                            print_tx: print_tx.clone(),
+                            health: health.clone(),
+                            active_subscriptions: active_subscriptions.clone(),
+                            multiplexer: multiplexer.clone(),
+                        };
+                        multiplexer
+                            .open(key.clone(), rx, pubsub, &target, &rsvp, sub_id, ctx)
+                            .await;
+                        active_subscriptions
+                            .entry(target.clone())
+                            .or_insert(HashMap::new())
+                            .insert(sub_id, ActiveSub::Multiplexed { key, target });
                     }
                     Err((provider_node, remote_sub_id)) => {
+                        let mut subs = active_subscriptions
+                            .entry(target.clone())
+                            .or_insert(HashMap::new());
+                        let our = our.clone();
+                        let send_to_loop = send_to_loop.clone();
+                        let print_tx = print_tx.clone();
+                        let active_subscriptions = active_subscriptions.clone();
                         // this is a remote sub, given by a relay node
                         let (sender, rx) = tokio::sync::mpsc::channel(10);
                         let keepalive_km_id = rand::random();
@@ -179,7 +187,8 @@ async fn build_subscription(
     providers: &Providers,
     response_channels: &ResponseChannels,
     print_tx: &PrintSender,
-) -> Result<Result<(RawSubscription, u64), (String, u64)>, EthError> {
+    health: &health::HealthTracker,
+) -> Result<Result<(RawSubscription, RootProvider<PubSubFrontend>), (String, u64)>, EthError> {
     let EthAction::SubscribeLogs {
         chain_id,
         kind,
@@ -198,20 +207,117 @@ async fn build_subscription(
         .send(print_tx)
         .await;
     }
-    let mut urls = {
+    if !providers.contains_key(chain_id) {
+        return Err(EthError::NoRpcForChain);
+    }
+    let chain_id = chain_id.clone();
+
+    // first, try any url providers we have for this chain,
+    // then if we have none or they all fail, go to node providers.
+    // finally, if no provider works, return an error.
+    if let Some(sub) =
+        subscribe_via_urls(chain_id, *kind, params, providers, print_tx, health).await
+    {
+        return Ok(Ok(sub));
+    }
+
+    let (sender, mut response_receiver) = tokio::sync::mpsc::channel(1);
+    response_channels.insert(km_id, sender);
+    // we need to create our own unique sub id because in the remote provider node,
+    // all subs will be identified under our process address.
+    let remote_sub_id = rand::random();
+    let mut nodes = {
         // in code block to drop providers lock asap to avoid deadlock
         let Some(aps) = providers.get(&chain_id) else {
             return Err(EthError::NoRpcForChain);
         };
-        aps.urls.clone()
+        aps.nodes.clone()
     };
-    let chain_id = chain_id.clone();
+    health.order(chain_id, &mut nodes, |node| &node.kns_update.name);
+    for node_provider in &nodes {
+        verbose_print(
+            &print_tx,
+            &format!(
+                "eth: attempting to fulfill via {}",
+                node_provider.kns_update.name
+            ),
+        )
+        .await;
+        match forward_to_node_provider(
+            &our,
+            km_id,
+            Some(target.clone()),
+            node_provider,
+            EthAction::SubscribeLogs {
+                sub_id: remote_sub_id,
+                chain_id: chain_id.clone(),
+                kind: kind.clone(),
+                params: params.clone(),
+            },
+            &send_to_loop,
+            &mut response_receiver,
+        )
+        .await
+        {
+            EthResponse::Ok => {
+                kernel_message(
+                    &our,
+                    km_id,
+                    target.clone(),
+                    None,
+                    false,
+                    None,
+                    EthResponse::Ok,
+                    &send_to_loop,
+                )
+                .await;
+                response_channels.remove(&km_id);
+                return Ok(Err((node_provider.kns_update.name.clone(), remote_sub_id)));
+            }
+            EthResponse::Response { .. } => {
+                // the response to a SubscribeLogs request must be an 'ok'
+                set_node_unusable(
+                    &providers,
+                    &chain_id,
+                    &node_provider.kns_update.name,
+                    print_tx,
+                )
+                .await;
+            }
+            EthResponse::Err(e) => {
+                if let EthError::RpcMalformedResponse = e {
+                    set_node_unusable(
+                        &providers,
+                        &chain_id,
+                        &node_provider.kns_update.name,
+                        print_tx,
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+    response_channels.remove(&km_id);
+    return Err(EthError::NoRpcForChain);
+}
 
-    // first, try any url providers we have for this chain,
-    // then if we have none or they all fail, go to node providers.
-    // finally, if no provider works, return an error.
+/// Subscribe through the first of our RPC URLs for the chain that takes the subscription,
+/// trying the healthiest first and bumping the one that works to the front of the list.
+pub async fn subscribe_via_urls(
+    chain_id: u64,
+    kind: SubscriptionKind,
+    params: &serde_json::Value,
+    providers: &Providers,
+    print_tx: &PrintSender,
+    health: &health::HealthTracker,
+) -> Option<(RawSubscription, RootProvider<PubSubFrontend>)> {
+    let mut urls = {
+        // in code block to drop providers lock asap to avoid deadlock
+        let aps = providers.get(&chain_id)?;
+        aps.urls.clone()
+    };
+    health.order(chain_id, &mut urls, |url| &url.url);
 
-    // bump the successful provider to the front of the list for future requests
     for url_provider in urls.iter_mut() {
         let pubsub = match &url_provider.pubsub {
             Some(pubsub) => pubsub,
@@ -229,6 +335,7 @@ async fn build_subscription(
                         &format!("eth: could not activate url provider {}", url_provider.url),
                     )
                     .await;
+                    health.record_failure(chain_id, &url_provider.url);
                     continue;
                 }
             }
@@ -262,9 +369,10 @@ async fn build_subscription(
                     )
                     .await;
                 }
-                return Ok(Ok((rx, chain_id)));
+                return Some((rx, pubsub.clone()));
             }
             Err(rpc_error) => {
+                health.record_failure(chain_id, &url_provider.url);
                 verbose_print(
                     &print_tx,
                     &format!(
@@ -297,134 +405,7 @@ async fn build_subscription(
             }
         }
     }
-
-    let (sender, mut response_receiver) = tokio::sync::mpsc::channel(1);
-    response_channels.insert(km_id, sender);
-    // we need to create our own unique sub id because in the remote provider node,
-    // all subs will be identified under our process address.
-    let remote_sub_id = rand::random();
-    let nodes = {
-        // in code block to drop providers lock asap to avoid deadlock
-        let Some(aps) = providers.get(&chain_id) else {
-            return Err(EthError::NoRpcForChain);
-        };
-        aps.nodes.clone()
-    };
-    for node_provider in &nodes {
-        verbose_print(
-            &print_tx,
-            &format!(
-                "eth: attempting to fulfill via {}",
-                node_provider.kns_update.name
-            ),
-        )
-        .await;
-        match forward_to_node_provider(
-            &our,
-            km_id,
-            Some(target.clone()),
-            node_provider,
-            EthAction::SubscribeLogs {
-                sub_id: remote_sub_id,
-                chain_id: chain_id.clone(),
-                kind: kind.clone(),
-                params: params.clone(),
-            },
-            &send_to_loop,
-            &mut response_receiver,
-        )
-        .await
-        {
-            EthResponse::Ok => {
-                kernel_message(
-                    &our,
-                    km_id,
-                    target.clone(),
-                    None,
-                    false,
-                    None,
-                    EthResponse::Ok,
-                    &send_to_loop,
-                )
-                .await;
-                response_channels.remove(&km_id);
-                return Ok(Err((node_provider.kns_update.name.clone(), remote_sub_id)));
-            }
-            EthResponse::Response { .. } => {
-                // the response to a SubscribeLogs request must be an 'ok'
-                set_node_unusable(
-                    &providers,
-                    &chain_id,
-                    &node_provider.kns_update.name,
-                    print_tx,
-                )
-                .await;
-            }
-            EthResponse::Err(e) => {
-                if let EthError::RpcMalformedResponse = e {
-                    set_node_unusable(
-                        &providers,
-                        &chain_id,
-                        &node_provider.kns_update.name,
-                        print_tx,
-                    )
-                    .await;
-                }
-            }
-        }
-    }
-    response_channels.remove(&km_id);
-    return Err(EthError::NoRpcForChain);
-}
-
-async fn maintain_local_subscription(
-    our: &str,
-    sub_id: u64,
-    mut rx: RawSubscription,
-    target: &Address,
-    rsvp: &Option<Address>,
-    send_to_loop: &MessageSender,
-    active_subscriptions: &ActiveSubscriptions,
-    mut close_receiver: tokio::sync::mpsc::Receiver<bool>,
-) -> Result<(), EthSubError> {
-    let e = loop {
-        tokio::select! {
-            _ = close_receiver.recv() => {
-                return Ok(());
-            },
-            value = rx.recv() => {
-                let value = match value {
-                    Ok(v) => serde_json::from_str::<serde_json::Value>(v.get()),
-                    Err(e) => break e.to_string(),
-                };
-                let result = match value {
-                    Ok(v) => v,
-                    Err(e) => break e.to_string(),
-                };
-                kernel_message(
-                    our,
-                    rand::random(),
-                    target.clone(),
-                    rsvp.clone(),
-                    true,
-                    None,
-                    EthSubResult::Ok(EthSub { id: sub_id, result }),
-                    &send_to_loop,
-                )
-                .await;
-            },
-        }
-    };
-    active_subscriptions
-        .entry(target.clone())
-        .and_modify(|sub_map| {
-            sub_map.remove(&sub_id);
-        });
-
-    Err(EthSubError {
-        id: sub_id,
-        error: format!("subscription ({target}) closed unexpectedly {e}"),
-    })
+    None
 }
 
 /// handle the subscription updates from a remote provider,
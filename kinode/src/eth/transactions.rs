@@ -0,0 +1,504 @@
+//! Sending transactions for processes, so that apps don't each hand-roll nonces,
+//! gas, fees and watching for receipts.
+//!
+//! A transaction is sent through the same providers, in the same order, as any
+//! other request. While it is pending, it is checked on every [`POLL_INTERVAL`]:
+//! unsigned transactions are re-signed with bumped fees every [`RESEND_INTERVAL`],
+//! and signed ones are rebroadcast. Unsigned transactions are signed by the wallet,
+//! `wallet:distro:sys`, as asked for by the process that sent them, so that keys
+//! never pass through here. Nonces of the accounts the wallet signs for are handed
+//! out here, so that transactions sent in quick succession don't reuse one before
+//! the first is seen as pending.
+use crate::eth::*;
+use alloy::consensus::{Transaction, TxEnvelope};
+use alloy::network::eip2718::Decodable2718;
+use alloy::rpc::types::eth::TransactionRequest;
+use alloy_primitives::Address as EthAddress;
+use std::str::FromStr;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const RESEND_INTERVAL: Duration = Duration::from_secs(60);
+/// most times an unsigned transaction is re-signed with higher fees
+const MAX_FEE_BUMPS: u32 = 5;
+/// how long a transaction can go unmined before giving up on it
+const GIVE_UP_AFTER: Duration = Duration::from_secs(60 * 60);
+/// margin over the estimated gas limit
+const GAS_LIMIT_MARGIN_PERCENT: u64 = 20;
+/// how long the wallet may take to sign, which waits on the user's approval
+const WALLET_TIMEOUT: Duration = Duration::from_secs(6 * 60);
+
+/// what sending a transaction needs from the module, to make requests of providers
+#[derive(Clone)]
+pub struct RpcContext {
+    pub our: Arc<String>,
+    pub send_to_loop: MessageSender,
+    pub providers: Providers,
+    pub response_channels: ResponseChannels,
+    pub print_tx: PrintSender,
+    pub request_cache: Arc<cache::ResponseCache>,
+    pub health: Arc<health::HealthTracker>,
+}
+
+impl RpcContext {
+    pub async fn call(
+        &self,
+        chain_id: u64,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, EthError> {
+        let km_id = rand::random();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        self.response_channels.insert(km_id, sender);
+        let response = fulfill_request(
+            &self.our,
+            km_id,
+            &self.send_to_loop,
+            &EthAction::Request {
+                chain_id,
+                method: method.to_string(),
+                params,
+            },
+            &self.providers,
+            &mut receiver,
+            &self.print_tx,
+            &self.request_cache,
+            &self.health,
+        )
+        .await;
+        self.response_channels.remove(&km_id);
+        match response {
+            EthResponse::Response(value) => Ok(value),
+            EthResponse::Err(e) => Err(e),
+            _ => Err(EthError::RpcMalformedResponse),
+        }
+    }
+
+    pub async fn call_number(
+        &self,
+        chain_id: u64,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<u128, EthError> {
+        parse_number(&self.call(chain_id, method, params).await?)
+            .ok_or(EthError::RpcMalformedResponse)
+    }
+}
+
+/// next nonce of each account we sign for, by chain ID
+#[derive(Debug, Default)]
+pub struct NonceManager {
+    nonces: tokio::sync::Mutex<HashMap<(u64, EthAddress), u64>>,
+}
+
+/// a transaction being sent, and what we need to resend it
+enum Outgoing {
+    Signed {
+        raw: Vec<u8>,
+    },
+    Unsigned {
+        request: TransactionRequest,
+        /// the process the wallet signs for
+        requester: Address,
+        /// as last signed
+        raw: Vec<u8>,
+        bumps: u32,
+    },
+}
+
+/// Send a transaction for the process that sent `km`, then watch it in a
+/// task of its own, reporting its progress to the process.
+pub async fn send_transaction(
+    ctx: RpcContext,
+    nonces: Arc<NonceManager>,
+    fees: Arc<fees::FeeOracle>,
+    km: KernelMessage,
+    chain_id: u64,
+    tx: TransactionToSend,
+    confirmations: u64,
+) {
+    let target = km.rsvp.clone().unwrap_or(km.source.clone());
+    tokio::spawn(async move {
+        let sent = match tx {
+            TransactionToSend::Signed(raw) => send_signed(&ctx, chain_id, &raw).await,
+            TransactionToSend::Unsigned { request, key } => {
+                send_unsigned(&ctx, &nonces, &fees, chain_id, request, &key, &km.source).await
+            }
+        };
+        let (outgoing, from, nonce, hash) = match sent {
+            Ok(sent) => sent,
+            Err(e) => {
+                error_message(&ctx.our, km.id, target, e, &ctx.send_to_loop).await;
+                return;
+            }
+        };
+        let id = rand::random();
+        kernel_message(
+            &ctx.our,
+            km.id,
+            target.clone(),
+            None,
+            false,
+            None,
+            EthResponse::TransactionSent {
+                id,
+                hash: hash.clone(),
+            },
+            &ctx.send_to_loop,
+        )
+        .await;
+        let status = watch(
+            &ctx,
+            chain_id,
+            outgoing,
+            from,
+            nonce,
+            hash,
+            confirmations,
+            |status| {
+                kernel_message(
+                    &ctx.our,
+                    rand::random(),
+                    target.clone(),
+                    None,
+                    true,
+                    None,
+                    TxUpdate { id, status },
+                    &ctx.send_to_loop,
+                )
+            },
+        )
+        .await;
+        kernel_message(
+            &ctx.our,
+            rand::random(),
+            target,
+            None,
+            true,
+            None,
+            TxUpdate { id, status },
+            &ctx.send_to_loop,
+        )
+        .await;
+    });
+}
+
+async fn send_signed(
+    ctx: &RpcContext,
+    chain_id: u64,
+    raw: &str,
+) -> Result<(Outgoing, EthAddress, u64, String), EthError> {
+    let raw = hex::decode(raw.trim_start_matches("0x")).map_err(|_| EthError::InvalidParams)?;
+    let envelope =
+        TxEnvelope::decode_2718(&mut raw.as_slice()).map_err(|_| EthError::InvalidParams)?;
+    let from = envelope
+        .recover_signer()
+        .map_err(|_| EthError::InvalidParams)?;
+    let nonce = envelope.nonce();
+    let hash = format!("{:#x}", envelope.tx_hash());
+    ctx.call(
+        chain_id,
+        "eth_sendRawTransaction",
+        serde_json::json!([format!("0x{}", hex::encode(&raw))]),
+    )
+    .await?;
+    Ok((Outgoing::Signed { raw }, from, nonce, hash))
+}
+
+async fn send_unsigned(
+    ctx: &RpcContext,
+    nonces: &NonceManager,
+    fees: &fees::FeeOracle,
+    chain_id: u64,
+    request: serde_json::Value,
+    key: &str,
+    requester: &Address,
+) -> Result<(Outgoing, EthAddress, u64, String), EthError> {
+    let mut request: TransactionRequest =
+        serde_json::from_value(request).map_err(|_| EthError::InvalidParams)?;
+    let from = EthAddress::from_str(key).map_err(|_| EthError::InvalidParams)?;
+    request.from = Some(from);
+    request.chain_id = Some(chain_id);
+
+    if request.gas.is_none() {
+        let estimate = ctx
+            .call_number(
+                chain_id,
+                "eth_estimateGas",
+                serde_json::json!([serde_json::to_value(&request).unwrap()]),
+            )
+            .await? as u64;
+        request.gas = Some(estimate + estimate * GAS_LIMIT_MARGIN_PERCENT / 100);
+    }
+    if request.gas_price.is_none() && request.max_fee_per_gas.is_none() {
+        let suggestion = fees.suggest(ctx, chain_id).await?;
+        match suggestion.base_fee_per_gas {
+            Some(base_fee) => {
+                let tip = *request
+                    .max_priority_fee_per_gas
+                    .get_or_insert(suggestion.standard.max_priority_fee_per_gas);
+                // room for the base fee to double before the transaction is mined
+                request.max_fee_per_gas = Some(base_fee * 2 + tip);
+            }
+            None => request.gas_price = Some(suggestion.standard.max_fee_per_gas),
+        }
+    }
+
+    let nonce = match request.nonce {
+        Some(nonce) => nonce,
+        None => {
+            let pending = ctx
+                .call_number(
+                    chain_id,
+                    "eth_getTransactionCount",
+                    serde_json::json!([from, "pending"]),
+                )
+                .await? as u64;
+            // taken at once, so that another transaction can't take the same one
+            let mut nonces = nonces.nonces.lock().await;
+            let next = nonces.entry((chain_id, from)).or_default();
+            let nonce = pending.max(*next);
+            *next = nonce + 1;
+            nonce
+        }
+    };
+    request.nonce = Some(nonce);
+    let (hash, raw) = match sign_and_send(ctx, chain_id, &request, requester).await {
+        Ok(sent) => sent,
+        Err(e) => {
+            // the nonce is unused, so handed out again, unless a later one was since
+            let mut nonces = nonces.nonces.lock().await;
+            if let Some(next) = nonces.get_mut(&(chain_id, from)) {
+                if *next == nonce + 1 {
+                    *next = nonce;
+                }
+            }
+            return Err(e);
+        }
+    };
+    let mut nonces = nonces.nonces.lock().await;
+    let next = nonces.entry((chain_id, from)).or_default();
+    *next = (*next).max(nonce + 1);
+    Ok((
+        Outgoing::Unsigned {
+            request,
+            requester: requester.clone(),
+            raw,
+            bumps: 0,
+        },
+        from,
+        nonce,
+        hash,
+    ))
+}
+
+async fn sign_and_send(
+    ctx: &RpcContext,
+    chain_id: u64,
+    request: &TransactionRequest,
+    requester: &Address,
+) -> Result<(String, Vec<u8>), EthError> {
+    let raw = sign_with_wallet(ctx, request, requester).await?;
+    let envelope =
+        TxEnvelope::decode_2718(&mut raw.as_slice()).map_err(|_| EthError::InvalidParams)?;
+    ctx.call(
+        chain_id,
+        "eth_sendRawTransaction",
+        serde_json::json!([format!("0x{}", hex::encode(&raw))]),
+    )
+    .await?;
+    Ok((format!("{:#x}", envelope.tx_hash()), raw))
+}
+
+/// Ask the wallet to sign a transaction request as the process that sent it,
+/// whose capability to sign transactions the wallet checks. The wallet answers us
+/// once the user approves or denies it, with the signed transaction.
+async fn sign_with_wallet(
+    ctx: &RpcContext,
+    request: &TransactionRequest,
+    requester: &Address,
+) -> Result<Vec<u8>, EthError> {
+    let km_id = rand::random();
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+    ctx.response_channels.insert(km_id, sender);
+    KernelMessage::builder()
+        .id(km_id)
+        .source(requester.clone())
+        .target((ctx.our.as_str(), WALLET_PROCESS_ID.clone()))
+        .rsvp(Some((ctx.our.as_str(), ETH_PROCESS_ID.clone()).into()))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: Some(WALLET_TIMEOUT.as_secs()),
+            body: serde_json::to_vec(&WalletAction::SignTransaction(
+                serde_json::to_value(request).unwrap(),
+            ))
+            .unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(&ctx.send_to_loop)
+        .await;
+    let response = tokio::time::timeout(WALLET_TIMEOUT, receiver.recv()).await;
+    ctx.response_channels.remove(&km_id);
+    let Ok(Some(Ok(km))) = response else {
+        return Err(EthError::RpcTimeout);
+    };
+    let Message::Response((response, _)) = km.message else {
+        return Err(EthError::RpcMalformedResponse);
+    };
+    match serde_json::from_slice::<WalletResponse>(&response.body) {
+        Ok(WalletResponse::SignedTransaction(raw)) => {
+            hex::decode(raw.trim_start_matches("0x")).map_err(|_| EthError::RpcMalformedResponse)
+        }
+        Ok(WalletResponse::Err(
+            WalletError::NoCap | WalletError::Denied | WalletError::TimedOut,
+        )) => Err(EthError::PermissionDenied),
+        Ok(WalletResponse::Err(_)) => Err(EthError::InvalidParams),
+        _ => Err(EthError::RpcMalformedResponse),
+    }
+}
+
+/// Watch a sent transaction until it is confirmed or fails, resending it while it
+/// is pending, and returning its final status. Statuses on the way are passed to `report`.
+async fn watch<F, Fut>(
+    ctx: &RpcContext,
+    chain_id: u64,
+    mut outgoing: Outgoing,
+    from: EthAddress,
+    nonce: u64,
+    hash: String,
+    confirmations: u64,
+    report: F,
+) -> TxStatus
+where
+    F: Fn(TxStatus) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let started = Instant::now();
+    let mut last_sent = Instant::now();
+    // every hash sent, the latest last: any of them can be mined
+    let mut hashes = vec![hash];
+    let mut mined_in = None;
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        // the account's nonce first: if it moved past ours and none of
+        // our hashes were mined by then, another transaction took it
+        let Ok(latest_nonce) = ctx
+            .call_number(
+                chain_id,
+                "eth_getTransactionCount",
+                serde_json::json!([from, "latest"]),
+            )
+            .await
+        else {
+            continue;
+        };
+        let mut receipt = None;
+        for hash in hashes.iter().rev() {
+            if let Ok(value) = ctx
+                .call(
+                    chain_id,
+                    "eth_getTransactionReceipt",
+                    serde_json::json!([hash]),
+                )
+                .await
+            {
+                if !value.is_null() {
+                    receipt = Some((hash.clone(), value));
+                    break;
+                }
+            }
+        }
+
+        if let Some((hash, receipt)) = receipt {
+            let Some(block_number) = receipt.get("blockNumber").and_then(parse_number) else {
+                continue;
+            };
+            let block_number = block_number as u64;
+            let Ok(head) = ctx
+                .call_number(chain_id, "eth_blockNumber", serde_json::json!([]))
+                .await
+            else {
+                continue;
+            };
+            let confirmed = (head as u64 + 1).saturating_sub(block_number);
+            if confirmed >= confirmations {
+                return TxStatus::Confirmed {
+                    hash,
+                    block_number,
+                    confirmations: confirmed,
+                    success: receipt.get("status").and_then(parse_number) == Some(1),
+                };
+            }
+            // reported again if a reorg moves it to another block
+            if mined_in != Some(block_number) {
+                mined_in = Some(block_number);
+                report(TxStatus::Mined { hash, block_number }).await;
+            }
+            continue;
+        }
+        mined_in = None;
+        if latest_nonce as u64 > nonce {
+            return TxStatus::Failed(
+                "replaced by another transaction with the same nonce".to_string(),
+            );
+        }
+        if started.elapsed() > GIVE_UP_AFTER {
+            return TxStatus::Failed(format!(
+                "not mined after {} minutes",
+                GIVE_UP_AFTER.as_secs() / 60
+            ));
+        }
+        if last_sent.elapsed() < RESEND_INTERVAL {
+            continue;
+        }
+        last_sent = Instant::now();
+        match &mut outgoing {
+            Outgoing::Unsigned {
+                request,
+                requester,
+                raw,
+                bumps,
+            } if *bumps < MAX_FEE_BUMPS => {
+                let mut bumped = request.clone();
+                bump_fees(&mut bumped);
+                // the replacement can be refused, such as if the original is
+                // being mined, or denied by the user, so keep the original fees
+                // until it is taken
+                if let Ok((hash, signed)) = sign_and_send(ctx, chain_id, &bumped, requester).await {
+                    *request = bumped;
+                    *raw = signed;
+                    *bumps += 1;
+                    hashes.push(hash.clone());
+                    report(TxStatus::Resent { hash }).await;
+                }
+            }
+            // rebroadcast as last signed, rather than asking the user again
+            Outgoing::Unsigned { raw, .. } | Outgoing::Signed { raw } => {
+                let _ = ctx
+                    .call(
+                        chain_id,
+                        "eth_sendRawTransaction",
+                        serde_json::json!([format!("0x{}", hex::encode(raw))]),
+                    )
+                    .await;
+            }
+        }
+    }
+}
+
+/// raise fees by 12.5%, over the 10% that nodes require of a replacement
+fn bump_fees(request: &mut TransactionRequest) {
+    let bump = |fee: u128| fee + fee / 8 + 1;
+    request.gas_price = request.gas_price.map(bump);
+    request.max_fee_per_gas = request.max_fee_per_gas.map(bump);
+    request.max_priority_fee_per_gas = request.max_priority_fee_per_gas.map(bump);
+}
+
+/// a JSON-RPC quantity, such as "0x1a"
+pub fn parse_number(value: &serde_json::Value) -> Option<u128> {
+    u128::from_str_radix(value.as_str()?.trim_start_matches("0x"), 16).ok()
+}
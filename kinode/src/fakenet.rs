@@ -4,11 +4,18 @@ use alloy::pubsub::PubSubFrontend;
 use alloy::rpc::client::WsConnect;
 use alloy::rpc::types::eth::{TransactionInput, TransactionRequest};
 use alloy::signers::local::PrivateKeySigner;
-use alloy_primitives::{Address, Bytes, FixedBytes, U256};
+use alloy_primitives::{keccak256, Address, Bytes, FixedBytes, U256};
 use alloy_sol_types::SolCall;
 use lib::core::{Identity, NodeRouting};
-use std::net::Ipv4Addr;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::process::Stdio;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
+use warp::http::StatusCode;
+use warp::Filter;
 
 use crate::{keygen, sol::*, KIMAP_ADDRESS, MULTICALL_ADDRESS};
 
@@ -23,6 +30,12 @@ const MULTICALL: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
 
 const KIMAP: &str = "0x9CE8cCD2932DC727c70f9ae4f8C2b68E6Abed58C";
 
+/// the first of anvil's default accounts, which owns everything minted on the fakechain
+const FAKECHAIN_PRIVATE_KEY: &str =
+    "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+const FAKECHAIN_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Attempts to connect to a local anvil fakechain,
 /// registering a name with its KiMap contract.
 /// If name is already registered, resets it.
@@ -32,91 +45,205 @@ pub async fn mint_local(
     pubkey: &str,
     fakechain_port: u16,
 ) -> Result<(), anyhow::Error> {
-    let privkey_signer = PrivateKeySigner::from_str(
-        "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
-    )?;
-
-    let wallet_address = privkey_signer.address();
+    let provider = connect(fakechain_port).await?;
 
-    let wallet: EthereumWallet = privkey_signer.into();
+    let localhost = Ipv4Addr::new(127, 0, 0, 1);
+    let ip = keygen::ip_to_bytes(localhost.into());
+    let pubkey = hex::decode(pubkey)?;
+    let notes = vec![
+        ("~ip".to_string(), ip),
+        ("~ws-port".to_string(), ws_port.to_be_bytes().to_vec()),
+        ("~net-key".to_string(), pubkey),
+    ];
+    mint_or_reset(&provider, name, &notes).await?;
+    mine_block(&provider).await
+}
 
-    let multicall_address = Address::from_str(MULTICALL)?;
-    let dotos = Address::from_str(FAKE_DOTOS_TBA)?;
-    let dotdev = Address::from_str(FAKE_DOTDEV_TBA)?;
-    let kimap = Address::from_str(KIMAP)?;
+/// Launches anvil on the fakechain port, loading a state dump of a chain with
+/// KiMap deployed, unless a chain is running there already, which is then used.
+/// A launched chain is stopped when the returned child process is dropped.
+pub async fn launch_fakechain(
+    fakechain_port: u16,
+    state: &Path,
+) -> Result<Option<tokio::process::Child>, anyhow::Error> {
+    if connect(fakechain_port).await.is_ok() {
+        println!("using fakechain already running on port {fakechain_port}\r");
+        return Ok(None);
+    }
+    let child = tokio::process::Command::new("anvil")
+        .arg("--port")
+        .arg(fakechain_port.to_string())
+        .arg("--load-state")
+        .arg(state)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("couldn't launch anvil, is it installed? {e}"))?;
+    let started = Instant::now();
+    while connect(fakechain_port).await.is_err() {
+        if started.elapsed() > FAKECHAIN_STARTUP_TIMEOUT {
+            return Err(anyhow::anyhow!(
+                "fakechain did not start on port {fakechain_port}"
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+    println!("launched fakechain on port {fakechain_port}\r");
+    Ok(Some(child))
+}
 
-    let parts: Vec<&str> = name.split('.').collect();
-    let label = parts[0];
-    let minter = match parts.get(1) {
-        Some(&"os") => dotos,
-        Some(&"dev") => dotdev,
-        _ => dotdev,
-    };
+/// Names and app listings to put on the fakechain at boot, read from the JSON
+/// file given with `--fakechain-fixtures`.
+#[derive(Debug, Deserialize)]
+struct Fixtures {
+    #[serde(default)]
+    names: Vec<NameFixture>,
+    #[serde(default)]
+    apps: Vec<AppFixture>,
+}
 
-    let endpoint = format!("ws://localhost:{}", fakechain_port);
-    let ws = WsConnect::new(endpoint);
+#[derive(Debug, Deserialize)]
+struct NameFixture {
+    /// full name, e.g. `alice.dev`: its parent must be `os`, `dev`, or minted by us
+    name: String,
+    /// note labels, e.g. `~ip`, to their data: `0x`-prefixed hex, or else text
+    #[serde(default)]
+    notes: BTreeMap<String, String>,
+}
 
-    let provider: RootProvider<PubSubFrontend> = ProviderBuilder::default().on_ws(ws).await?;
+#[derive(Debug, Deserialize)]
+struct AppFixture {
+    package: String,
+    /// a name minted by us, as a name fixture or the node itself
+    publisher: String,
+    /// the listing's metadata, served from the node while it runs
+    metadata: Option<serde_json::Value>,
+    /// where the listing's metadata is, if not given as `metadata`
+    metadata_uri: Option<String>,
+    /// the hash of the metadata at `metadata_uri`
+    metadata_hash: Option<String>,
+}
 
-    // get tba to see if name is already registered
-    let namehash: [u8; 32] = keygen::namehash(name);
+/// Puts the names and app listings in a fixtures file on the fakechain,
+/// minting each entry or, if minted already, setting its notes again.
+pub async fn apply_fixtures(path: &Path, fakechain_port: u16) -> Result<(), anyhow::Error> {
+    let fixtures: Fixtures = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+    let provider = connect(fakechain_port).await?;
+
+    for fixture in &fixtures.names {
+        let notes = fixture
+            .notes
+            .iter()
+            .map(|(note, data)| Ok((note.clone(), note_data(data)?)))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        mint_or_reset(&provider, &fixture.name, &notes).await?;
+    }
 
-    let get_call = getCall {
-        node: namehash.into(),
+    let metadata: HashMap<String, Vec<u8>> = fixtures
+        .apps
+        .iter()
+        .filter_map(|app| {
+            let metadata = serde_json::to_vec(app.metadata.as_ref()?).ok()?;
+            Some((format!("{}.{}.json", app.package, app.publisher), metadata))
+        })
+        .collect();
+    let metadata_addr = if metadata.is_empty() {
+        None
+    } else {
+        Some(serve_metadata(metadata.clone()))
+    };
+    for app in &fixtures.apps {
+        let file = format!("{}.{}.json", app.package, app.publisher);
+        let (uri, hash) = match (metadata.get(&file), metadata_addr) {
+            (Some(bytes), Some(addr)) => (
+                format!("http://{addr}/{file}"),
+                keccak256(bytes).to_string(),
+            ),
+            _ => match (&app.metadata_uri, &app.metadata_hash) {
+                (Some(uri), Some(hash)) => (uri.clone(), hash.clone()),
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "app {}:{} needs metadata, or a metadata_uri and metadata_hash",
+                        app.package,
+                        app.publisher
+                    ))
+                }
+            },
+        };
+        // the app store looks up the hash when it sees the uri, so the hash goes first
+        let notes = vec![
+            ("~metadata-hash".to_string(), hash.into_bytes()),
+            ("~metadata-uri".to_string(), uri.into_bytes()),
+        ];
+        mint_or_reset(
+            &provider,
+            &format!("{}.{}", app.package, app.publisher),
+            &notes,
+        )
+        .await?;
     }
-    .abi_encode();
+    mine_block(&provider).await?;
 
-    let get_tx = TransactionRequest::default()
-        .to(kimap)
-        .input(TransactionInput::new(get_call.into()));
+    println!(
+        "applied fakechain fixtures: {} names, {} apps\r",
+        fixtures.names.len(),
+        fixtures.apps.len()
+    );
+    Ok(())
+}
 
-    let exists = provider.call(&get_tx).await?;
-    println!("exists: {:?}", exists);
+/// Serves listing metadata on an unused local port, for as long as the node runs.
+fn serve_metadata(metadata: HashMap<String, Vec<u8>>) -> SocketAddr {
+    let route = warp::path!(String).map(move |file: String| match metadata.get(&file) {
+        Some(bytes) => warp::reply::with_status(bytes.clone(), StatusCode::OK),
+        None => warp::reply::with_status(vec![], StatusCode::NOT_FOUND),
+    });
+    let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    addr
+}
 
-    let decoded = getCall::abi_decode_returns(&exists, false)?;
+fn note_data(data: &str) -> Result<Vec<u8>, anyhow::Error> {
+    match data.strip_prefix("0x") {
+        Some(hex_data) => Ok(hex::decode(hex_data)?),
+        None => Ok(data.as_bytes().to_vec()),
+    }
+}
 
-    let tba = decoded.tba;
-    let _owner = decoded.owner;
-    let bytes = decoded.data;
-    // now set ip, port and pubkey
+async fn connect(fakechain_port: u16) -> Result<RootProvider<PubSubFrontend>, anyhow::Error> {
+    let endpoint = format!("ws://localhost:{}", fakechain_port);
+    let ws = WsConnect::new(endpoint);
+    Ok(ProviderBuilder::default().on_ws(ws).await?)
+}
 
-    println!("tba, owner and bytes: {:?}, {:?}, {:?}", tba, _owner, bytes);
+/// Mints a name with the given notes, owned by the fakechain wallet.
+/// If the name is already registered, sets its notes again instead.
+async fn mint_or_reset(
+    provider: &RootProvider<PubSubFrontend>,
+    name: &str,
+    notes: &[(String, Vec<u8>)],
+) -> Result<(), anyhow::Error> {
+    let wallet_address = PrivateKeySigner::from_str(FAKECHAIN_PRIVATE_KEY)?.address();
+    let multicall_address = Address::from_str(MULTICALL)?;
+    let kimap = Address::from_str(KIMAP)?;
 
-    let localhost = Ipv4Addr::new(127, 0, 0, 1);
-    let ip = keygen::ip_to_bytes(localhost.into());
-    let pubkey = hex::decode(pubkey)?;
-    let multicalls: Vec<Call> = vec![
-        Call {
-            target: kimap,
-            callData: Bytes::from(
-                noteCall {
-                    note: "~ip".into(),
-                    data: ip.into(),
-                }
-                .abi_encode(),
-            ),
-        },
-        Call {
-            target: kimap,
-            callData: Bytes::from(
-                noteCall {
-                    note: "~ws-port".into(),
-                    data: ws_port.to_be_bytes().into(),
-                }
-                .abi_encode(),
-            ),
-        },
-        Call {
+    // get tba to see if name is already registered
+    let (tba, _owner, _data) = get(provider, name).await?;
+
+    let multicalls: Vec<Call> = notes
+        .iter()
+        .map(|(note, data)| Call {
             target: kimap,
             callData: Bytes::from(
                 noteCall {
-                    note: "~net-key".into(),
-                    data: Bytes::from(pubkey),
+                    note: note.as_bytes().to_vec().into(),
+                    data: data.clone().into(),
                 }
                 .abi_encode(),
             ),
-        },
-    ];
+        })
+        .collect();
 
     let is_reset = tba != Address::default();
 
@@ -135,62 +262,113 @@ pub async fn mint_local(
         (execute_call, tba)
     } else {
         // name is not registered, mint it with multicall in initialization param
-        (
-            mintCall {
-                who: wallet_address,
-                label: Bytes::from(label.as_bytes().to_vec()),
-                initialization: execute_call.into(),
-                erc721Data: vec![].into(),
-                implementation: Address::from_str(KINO_ACCOUNT_IMPL).unwrap(),
+        let (label, parent) = name.split_once('.').unwrap_or((name, "dev"));
+        let mint_call = mintCall {
+            who: wallet_address,
+            label: Bytes::from(label.as_bytes().to_vec()),
+            initialization: execute_call.into(),
+            erc721Data: vec![].into(),
+            implementation: Address::from_str(KINO_ACCOUNT_IMPL).unwrap(),
+        }
+        .abi_encode();
+        match parent {
+            "os" => (mint_call, Address::from_str(FAKE_DOTOS_TBA)?),
+            "dev" => (mint_call, Address::from_str(FAKE_DOTDEV_TBA)?),
+            _ => {
+                // a parent we minted mints through its tba, which we own
+                let (parent_tba, _owner, _data) = get(provider, parent).await?;
+                if parent_tba == Address::default() {
+                    return Err(anyhow::anyhow!(
+                        "can't mint {name}: {parent} is not registered"
+                    ));
+                }
+                (
+                    executeCall {
+                        to: kimap,
+                        value: U256::from(0),
+                        data: mint_call.into(),
+                        operation: 0,
+                    }
+                    .abi_encode(),
+                    parent_tba,
+                )
             }
-            .abi_encode(),
-            minter,
-        )
+        }
     };
 
-    let nonce = provider.get_transaction_count(wallet_address).await?;
-
     let tx = TransactionRequest::default()
         .to(to)
         .input(TransactionInput::new(input_bytes.into()))
-        .nonce(nonce)
-        .with_chain_id(31337)
-        .with_gas_limit(12_000_00)
-        .with_max_priority_fee_per_gas(200_000_000_000)
-        .with_max_fee_per_gas(300_000_000_000);
+        .with_gas_limit(12_000_00);
+    send(provider, tx).await
+}
 
-    // Build the transaction using the `EthereumSigner` with the provided signer.
-    let tx_envelope = tx.build(&wallet).await?;
+async fn get(
+    provider: &RootProvider<PubSubFrontend>,
+    name: &str,
+) -> Result<(Address, Address, Bytes), anyhow::Error> {
+    let namehash: [u8; 32] = keygen::namehash(name);
 
-    // Encode the transaction using EIP-2718 encoding.
-    let tx_encoded = tx_envelope.encoded_2718();
+    let get_call = getCall {
+        node: namehash.into(),
+    }
+    .abi_encode();
 
-    // Send the raw transaction and retrieve the transaction receipt.
-    let tx_hash = provider.send_raw_transaction(&tx_encoded).await?;
-    let _receipt = tx_hash.get_receipt().await?;
+    let get_tx = TransactionRequest::default()
+        .to(Address::from_str(KIMAP)?)
+        .input(TransactionInput::new(get_call.into()));
+
+    let exists = provider.call(&get_tx).await?;
+    let decoded = getCall::abi_decode_returns(&exists, false)?;
+    Ok((decoded.tba, decoded.owner, decoded.data))
+}
 
-    // send a small amount of ETH to the zero address
-    // this is a workaround to get anvil to mine a block after our registration tx
-    // instead of doing block-time 1s or similar, which leads to runaway mem-usage.
+/// send a small amount of ETH to the zero address
+/// this is a workaround to get anvil to mine a block after our registration tx
+/// instead of doing block-time 1s or similar, which leads to runaway mem-usage.
+async fn mine_block(provider: &RootProvider<PubSubFrontend>) -> Result<(), anyhow::Error> {
     let zero_address = Address::default();
     let small_amount = U256::from(10); // 10 wei (0.00000001 ETH)
 
-    let nonce = provider.get_transaction_count(wallet_address).await?;
-
     let small_tx = TransactionRequest::default()
         .to(zero_address)
         .value(small_amount)
+        .with_gas_limit(21_000);
+    send(provider, small_tx).await
+}
+
+/// Signs a transaction with the fakechain wallet, sends it, and waits for it to be mined.
+async fn send(
+    provider: &RootProvider<PubSubFrontend>,
+    tx: TransactionRequest,
+) -> Result<(), anyhow::Error> {
+    let privkey_signer = PrivateKeySigner::from_str(FAKECHAIN_PRIVATE_KEY)?;
+    let wallet_address = privkey_signer.address();
+    let wallet: EthereumWallet = privkey_signer.into();
+
+    let nonce = provider.get_transaction_count(wallet_address).await?;
+
+    let tx = tx
         .nonce(nonce)
         .with_chain_id(31337)
-        .with_gas_limit(21_000)
         .with_max_priority_fee_per_gas(200_000_000_000)
         .with_max_fee_per_gas(300_000_000_000);
 
-    let small_tx_envelope = small_tx.build(&wallet).await?;
-    let small_tx_encoded = small_tx_envelope.encoded_2718();
+    // Build the transaction using the `EthereumSigner` with the provided signer.
+    let tx_envelope = tx.build(&wallet).await?;
 
-    let small_tx_hash = provider.send_raw_transaction(&small_tx_encoded).await?;
-    let _small_receipt = small_tx_hash.get_receipt().await?;
+    // Encode the transaction using EIP-2718 encoding.
+    let tx_encoded = tx_envelope.encoded_2718();
+
+    // Send the raw transaction and retrieve the transaction receipt.
+    let tx_hash = provider.send_raw_transaction(&tx_encoded).await?;
+    let receipt = tx_hash.get_receipt().await?;
+    if !receipt.status() {
+        return Err(anyhow::anyhow!(
+            "fakechain transaction {} reverted",
+            receipt.transaction_hash
+        ));
+    }
 
     Ok(())
 }
@@ -205,10 +383,7 @@ pub async fn assign_ws_local_helper(
     let kimap = Address::from_str(KIMAP_ADDRESS)?;
     let multicall = Address::from_str(MULTICALL_ADDRESS)?;
 
-    let endpoint = format!("ws://localhost:{}", fakechain_port);
-    let ws = WsConnect::new(endpoint);
-
-    let provider: RootProvider<PubSubFrontend> = ProviderBuilder::default().on_ws(ws).await?;
+    let provider = connect(fakechain_port).await?;
 
     let netkey_hash = FixedBytes::<32>::from_slice(&keygen::namehash(&format!("~ip.{}", our.name)));
     let ws_hash =
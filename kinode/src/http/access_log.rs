@@ -0,0 +1,121 @@
+//! Structured access logs, kept when the node is booted with `--http-access-log`.
+//!
+//! Each request served is written as a line of JSON to `access.log` in the
+//! `http_access_log` directory of the home directory, outside of the VFS, since
+//! it is written by the runtime rather than an app. Once that file grows past
+//! [`MAX_FILE_SIZE`] it is rotated to `access.log.1`, shifting older files up,
+//! and only the newest [`MAX_ROTATED_FILES`] of those are kept. Settings reads them back with
+//! `HttpServerAction::QueryAccessLog`.
+use crate::http::server_types::AccessLogEntry;
+use std::path::{Path, PathBuf};
+use tokio::{fs, io::AsyncWriteExt, sync::mpsc};
+
+const DIRECTORY: &str = "http_access_log";
+const FILE_NAME: &str = "access.log";
+
+/// the size past which the current log file is rotated
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+/// how many rotated log files are kept besides the current one
+const MAX_ROTATED_FILES: usize = 4;
+/// how many entries can wait to be written before new ones are dropped, so
+/// that a slow disk never holds up responses
+const QUEUE_SIZE: usize = 1024;
+
+pub struct AccessLog {
+    directory: PathBuf,
+    sender: mpsc::Sender<AccessLogEntry>,
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLog").finish_non_exhaustive()
+    }
+}
+
+impl AccessLog {
+    /// open the log in the home directory, spawning the task that writes to it
+    pub async fn open(home_directory_path: &Path) -> anyhow::Result<Self> {
+        let directory = home_directory_path.join(DIRECTORY);
+        fs::create_dir_all(&directory).await?;
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(write_entries(directory.clone(), receiver));
+        Ok(Self { directory, sender })
+    }
+
+    /// queue an entry to be written, dropping it if the queue is full
+    pub fn record(&self, entry: AccessLogEntry) {
+        let _ = self.sender.try_send(entry);
+    }
+
+    /// The logged entries matching the query, oldest first, of which
+    /// only the latest `limit` are returned.
+    pub async fn query(
+        &self,
+        since: Option<u64>,
+        path_prefix: Option<&str>,
+        limit: Option<u32>,
+    ) -> Vec<AccessLogEntry> {
+        let mut entries = Vec::new();
+        for n in (0..=MAX_ROTATED_FILES).rev() {
+            let Ok(contents) = fs::read_to_string(file_path(&self.directory, n)).await else {
+                continue;
+            };
+            entries.extend(
+                contents
+                    .lines()
+                    .filter_map(|line| serde_json::from_str::<AccessLogEntry>(line).ok())
+                    .filter(|entry| since.map_or(true, |since| entry.timestamp >= since))
+                    .filter(|entry| {
+                        path_prefix.map_or(true, |prefix| entry.path.starts_with(prefix))
+                    }),
+            );
+        }
+        if let Some(limit) = limit {
+            let excess = entries.len().saturating_sub(limit as usize);
+            entries.drain(..excess);
+        }
+        entries
+    }
+}
+
+/// the current log file for `n == 0`, else the `n`th most recently rotated one
+fn file_path(directory: &Path, n: usize) -> PathBuf {
+    if n == 0 {
+        directory.join(FILE_NAME)
+    } else {
+        directory.join(format!("{FILE_NAME}.{n}"))
+    }
+}
+
+async fn write_entries(directory: PathBuf, mut receiver: mpsc::Receiver<AccessLogEntry>) {
+    let path = file_path(&directory, 0);
+    while let Some(entry) = receiver.recv().await {
+        let mut line = serde_json::to_vec(&entry).unwrap();
+        line.push(b'\n');
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await;
+        let result = match file {
+            Ok(mut file) => file.write_all(&line).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            println!("http-server: failed to write access log: {e}\r");
+            continue;
+        }
+        let size = fs::metadata(&path).await.map(|m| m.len()).unwrap_or(0);
+        if size > MAX_FILE_SIZE {
+            rotate(&directory).await;
+        }
+    }
+}
+
+/// shift each log file up by one, dropping the oldest
+async fn rotate(directory: &Path) {
+    let _ = fs::remove_file(file_path(directory, MAX_ROTATED_FILES)).await;
+    for n in (0..MAX_ROTATED_FILES).rev() {
+        let _ = fs::rename(file_path(directory, n), file_path(directory, n + 1)).await;
+    }
+}
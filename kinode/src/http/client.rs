@@ -1,13 +1,17 @@
 use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as base64_standard, Engine};
 use dashmap::DashMap;
 use futures::stream::{SplitSink, SplitStream};
 use futures::SinkExt;
 use futures::StreamExt;
 use http::header::{HeaderMap, HeaderName, HeaderValue};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message as TungsteniteMessage};
-use tokio_tungstenite::{connect_async, tungstenite};
+use tokio_tungstenite::{client_async_tls, connect_async, tungstenite};
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 
 use lib::types::{core::*, http_client::*, http_server::*};
@@ -17,6 +21,143 @@ use lib::types::{core::*, http_client::*, http_server::*};
 // m our@http-client:distro:sys '{"method": "POST", "url": "https://jsonplaceholder.typicode.com/posts", "headers": {"Content-Type": "application/json"}}'
 // m our@http-client:distro:sys '{"method": "PUT", "url": "https://jsonplaceholder.typicode.com/posts", "headers": {"Content-Type": "application/json"}}'
 
+/// how long an idle connection is kept open for reuse by later requests
+const POOL_IDLE_TIMEOUT: u64 = 90;
+/// how many idle connections are kept open to each host
+const POOL_MAX_IDLE_PER_HOST: usize = 16;
+const TCP_KEEPALIVE: u64 = 60;
+/// the size of each part that a streamed response body is sent in
+const STREAM_CHUNK_SIZE: usize = 1024 * 1024;
+
+const PROXIES_FILE: &str = ".http_client_proxies";
+/// the longest response head taken from an HTTP proxy to a CONNECT, in bytes
+const MAX_PROXY_RESPONSE_HEAD: usize = 8 * 1024;
+/// the port of a SOCKS5 proxy whose URL gives none
+const SOCKS5_DEFAULT_PORT: u16 = 1080;
+
+/// The outbound proxies set through settings, each with a client that uses it.
+/// Clients are shared by requests, so that connections are kept alive and reused.
+struct Proxies {
+    file: PathBuf,
+    /// keyed by the process whose requests use the proxy, or None for the default
+    proxied: DashMap<Option<ProcessId>, (String, reqwest::Client)>,
+    direct: reqwest::Client,
+}
+
+impl Proxies {
+    /// load the proxies persisted in the home directory, if any
+    async fn load(home_directory_path: &Path) -> Result<Self> {
+        let file = home_directory_path.join(PROXIES_FILE);
+        let proxies: Vec<ProxyInfo> = tokio::fs::read(&file)
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        let proxied = DashMap::new();
+        for ProxyInfo { process, url } in proxies {
+            match build_client(Some(&url)) {
+                Ok(client) => {
+                    proxied.insert(process, (url, client));
+                }
+                Err(e) => println!("http-client: failed to use proxy {url}: {e}\r"),
+            }
+        }
+        Ok(Self {
+            file,
+            proxied,
+            direct: build_client(None)?,
+        })
+    }
+
+    /// the client for the requests of `process`
+    fn client(&self, process: &ProcessId) -> reqwest::Client {
+        self.proxied
+            .get(&Some(process.clone()))
+            .or_else(|| self.proxied.get(&None))
+            .map(|entry| entry.1.clone())
+            .unwrap_or_else(|| self.direct.clone())
+    }
+
+    /// the URL of the proxy for the requests of `process`, if any
+    fn url(&self, process: &ProcessId) -> Option<String> {
+        self.proxied
+            .get(&Some(process.clone()))
+            .or_else(|| self.proxied.get(&None))
+            .map(|entry| entry.0.clone())
+    }
+
+    async fn set(
+        &self,
+        process: Option<ProcessId>,
+        url: Option<String>,
+        print_tx: &PrintSender,
+    ) -> Result<HttpClientResponse, HttpClientError> {
+        match url {
+            Some(url) => {
+                let client = build_client(Some(&url))
+                    .map_err(|_| HttpClientError::BadProxy { url: url.clone() })?;
+                let _ = print_tx
+                    .send(Printout::new(
+                        1,
+                        HTTP_CLIENT_PROCESS_ID.clone(),
+                        match &process {
+                            Some(process) => format!("http-client: set proxy for {process}"),
+                            None => "http-client: set default proxy".to_string(),
+                        },
+                    ))
+                    .await;
+                self.proxied.insert(process, (url, client));
+            }
+            None => {
+                self.proxied.remove(&process);
+            }
+        }
+        self.persist().await;
+        Ok(HttpClientResponse::Proxies(self.list()))
+    }
+
+    fn list(&self) -> Vec<ProxyInfo> {
+        let mut proxies: Vec<ProxyInfo> = self
+            .proxied
+            .iter()
+            .map(|entry| ProxyInfo {
+                process: entry.key().clone(),
+                url: entry.value().0.clone(),
+            })
+            .collect();
+        proxies.sort_by_key(|proxy| proxy.process.as_ref().map(|p| p.to_string()));
+        proxies
+    }
+
+    async fn persist(&self) {
+        let proxies = serde_json::to_vec(&self.list()).unwrap();
+        if let Err(e) = tokio::fs::write(&self.file, proxies).await {
+            println!("http-client: failed to persist {PROXIES_FILE}: {e}\r");
+        }
+    }
+}
+
+/// A client for the requests a runtime module makes itself, rather than through
+/// http-client, which uses the proxy set in settings for `process` or by default, if any.
+pub async fn proxied_client(home_directory_path: &Path, process: &ProcessId) -> reqwest::Client {
+    match Proxies::load(home_directory_path).await {
+        Ok(proxies) => proxies.client(process),
+        Err(_) => reqwest::Client::new(),
+    }
+}
+
+/// A client that sends its requests through the proxy at `proxy`, if any
+fn build_client(proxy: Option<&str>) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .pool_idle_timeout(Duration::from_secs(POOL_IDLE_TIMEOUT))
+        .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+        .tcp_keepalive(Duration::from_secs(TCP_KEEPALIVE));
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    builder.build()
+}
+
 /// WebSocket client connections are mapped by a tuple of ProcessId and
 /// a process-supplied channel_id (u32)
 type WebSocketId = (ProcessId, u32);
@@ -27,17 +168,23 @@ type WebSocketMap = DashMap<
 /// The WebSocket streams are split into sink and stream
 /// so that both incoming and outgoing pushes can be routed appropriately
 type WebSocketStreams = Arc<WebSocketMap>;
+/// Connections that dropped and are waiting to be reconnected. Removing a
+/// connection from here cancels its reconnection.
+type WebSocketReconnects = Arc<dashmap::DashSet<WebSocketId>>;
+type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
 
 pub async fn http_client(
     our_name: String,
     send_to_loop: MessageSender,
     mut recv_in_client: MessageReceiver,
     print_tx: PrintSender,
+    home_directory_path: PathBuf,
 ) -> Result<()> {
-    let client = reqwest::Client::new();
+    let proxies = Proxies::load(&home_directory_path).await?;
     let our_name = Arc::new(our_name);
 
     let ws_streams: WebSocketStreams = Arc::new(DashMap::new());
+    let ws_reconnects: WebSocketReconnects = Arc::new(dashmap::DashSet::new());
 
     while let Some(KernelMessage {
         id,
@@ -72,12 +219,15 @@ pub async fn http_client(
         };
 
         let our = our_name.clone();
+        let from_settings = source.process == ProcessId::new(Some("settings"), "settings", "sys");
+        let client = proxies.client(&source.process);
+        let proxy = proxies.url(&source.process);
         // target is the source or specified rsvp Address to which
         // responses or incoming WS messages will be routed
         let target = rsvp.unwrap_or(source);
 
-        // Handle the request, returning if the request was a WS request
-        let (is_ws, result) = match request {
+        // Handle the request, returning whether it is to be responded to now
+        let (respond_now, result) = match request {
             HttpClientAction::Http(req) => {
                 tokio::spawn(handle_http_request(
                     our,
@@ -86,7 +236,7 @@ pub async fn http_client(
                     expects_response,
                     req,
                     blob,
-                    client.clone(),
+                    client,
                     send_to_loop.clone(),
                     print_tx.clone(),
                 ));
@@ -102,6 +252,7 @@ pub async fn http_client(
                 url,
                 headers,
                 channel_id,
+                reconnect,
             } => (
                 true,
                 connect_websocket(
@@ -111,7 +262,10 @@ pub async fn http_client(
                     &url,
                     headers,
                     channel_id,
+                    reconnect,
+                    proxy,
                     ws_streams.clone(),
+                    ws_reconnects.clone(),
                     send_to_loop.clone(),
                     print_tx.clone(),
                 )
@@ -137,15 +291,25 @@ pub async fn http_client(
                     target.clone(),
                     channel_id,
                     ws_streams.clone(),
+                    ws_reconnects.clone(),
                     print_tx.clone(),
                 )
                 .await,
             ),
+            HttpClientAction::SetProxy { .. } | HttpClientAction::ListProxies if !from_settings => {
+                (true, Err(HttpClientError::ProxyForbidden))
+            }
+            HttpClientAction::SetProxy { process, url } => {
+                (true, proxies.set(process, url, &print_tx).await)
+            }
+            HttpClientAction::ListProxies => {
+                (true, Ok(HttpClientResponse::Proxies(proxies.list())))
+            }
         };
 
-        // If the incoming request was a WS request, send a response
+        // If the incoming request was not an HTTP request, send a response
         // HTTP responses are handled in the handle_http_request function
-        if is_ws {
+        if respond_now {
             let Ok(body) =
                 serde_json::to_vec::<Result<HttpClientResponse, HttpClientError>>(&result)
             else {
@@ -181,12 +345,13 @@ async fn connect_websocket(
     url: &str,
     headers: HashMap<String, String>,
     channel_id: u32,
+    reconnect: Option<ReconnectPolicy>,
+    proxy: Option<String>,
     ws_streams: WebSocketStreams,
+    ws_reconnects: WebSocketReconnects,
     send_to_loop: MessageSender,
     print_tx: PrintSender,
 ) -> Result<HttpClientResponse, HttpClientError> {
-    let print_tx_clone = print_tx.clone();
-
     // First check the URL
     let Ok(url) = url::Url::parse(url) else {
         return Err(HttpClientError::BadUrl {
@@ -194,6 +359,51 @@ async fn connect_websocket(
         });
     };
 
+    // Connect the WebSocket
+    let ws_stream = open_websocket(&url, &headers, proxy.as_deref(), &print_tx).await?;
+
+    // Split the WebSocket connection
+    let (sink, stream) = ws_stream.split();
+
+    // Close any existing sink with the same ProcessId and channel_id,
+    // or cancel its reconnection
+    let ws_id = (target.process.clone(), channel_id);
+    if let Some(mut sink) = ws_streams.get_mut(&ws_id) {
+        let _ = sink.close().await;
+    }
+    ws_reconnects.remove(&ws_id);
+
+    // Insert the sink (send or push part of the WebSocket connection)
+    ws_streams.insert(ws_id, sink);
+
+    // Spawn a new tokio process to listen to incoming WS events on the stream
+    tokio::spawn(listen_to_stream(
+        our.clone(),
+        id,
+        target.clone(),
+        channel_id,
+        stream,
+        url,
+        headers,
+        reconnect,
+        proxy,
+        ws_streams,
+        ws_reconnects,
+        send_to_loop.clone(),
+        print_tx,
+    ));
+
+    Ok(HttpClientResponse::WebSocketAck)
+}
+
+/// Open a WebSocket connection to `url`, with the given headers, through the
+/// proxy at `proxy`, if any, as HTTP requests are
+async fn open_websocket(
+    url: &url::Url,
+    headers: &HashMap<String, String>,
+    proxy: Option<&str>,
+    print_tx: &PrintSender,
+) -> Result<WsStream, HttpClientError> {
     let Ok(mut req) = url.clone().into_client_request() else {
         return Err(HttpClientError::WsOpenFailed {
             url: url.to_string(),
@@ -202,17 +412,25 @@ async fn connect_websocket(
 
     // Add headers to the request
     let req_headers = req.headers_mut();
-    for (key, value) in headers.clone() {
+    for (key, value) in headers {
         if let Ok(key_name) = HeaderName::from_bytes(key.as_bytes()) {
-            if let Ok(value_header) = HeaderValue::from_str(&value) {
+            if let Ok(value_header) = HeaderValue::from_str(value) {
                 req_headers.insert(key_name, value_header);
             }
         }
     }
 
-    // Connect the WebSocket
-    let ws_stream = match connect_async(req).await {
-        Ok((ws_stream, _)) => ws_stream,
+    let connected = match proxy {
+        None => connect_async(req).await.map_err(anyhow::Error::from),
+        Some(proxy) => match connect_through_proxy(proxy, url).await {
+            Ok(stream) => client_async_tls(req, stream)
+                .await
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(e),
+        },
+    };
+    match connected {
+        Ok((ws_stream, _)) => Ok(ws_stream),
         Err(e) => {
             let _ = print_tx
                 .send(Printout::new(
@@ -221,54 +439,268 @@ async fn connect_websocket(
                     format!("http-client: underlying lib connection error {e:?}"),
                 ))
                 .await;
-
-            return Err(HttpClientError::WsOpenFailed {
+            Err(HttpClientError::WsOpenFailed {
                 url: url.to_string(),
-            });
+            })
         }
-    };
+    }
+}
 
-    // Split the WebSocket connection
-    let (sink, stream) = ws_stream.split();
+/// Forward the messages of a connection to the process that opened it, and,
+/// if it has a reconnect policy, reconnect it whenever it drops without the
+/// process closing it.
+async fn listen_to_stream(
+    our: Arc<String>,
+    id: u64,
+    target: Address,
+    channel_id: u32,
+    mut stream: SplitStream<WsStream>,
+    url: url::Url,
+    headers: HashMap<String, String>,
+    reconnect: Option<ReconnectPolicy>,
+    proxy: Option<String>,
+    ws_streams: WebSocketStreams,
+    ws_reconnects: WebSocketReconnects,
+    send_to_loop: MessageSender,
+    print_tx: PrintSender,
+) {
+    let ws_id = (target.process.clone(), channel_id);
+    loop {
+        let dropped = forward_stream(
+            our.clone(),
+            id,
+            target.clone(),
+            channel_id,
+            stream,
+            ws_streams.clone(),
+            send_to_loop.clone(),
+        )
+        .await;
+        let Some(policy) = reconnect.as_ref().filter(|_| dropped) else {
+            break;
+        };
 
-    // Close any existing sink with the same ProcessId and channel_id
-    if let Some(mut sink) = ws_streams.get_mut(&(target.process.clone(), channel_id)) {
-        let _ = sink.close().await;
-    }
+        // Tell the process, so that it can hold its pushes until reconnected
+        ws_reconnects.insert(ws_id.clone());
+        send_client_request(
+            our.clone(),
+            id,
+            target.clone(),
+            HttpClientRequest::WebSocketDisconnected { channel_id },
+            None,
+            send_to_loop.clone(),
+        )
+        .await;
 
-    // Insert the sink (send or push part of the WebSocket connection)
-    ws_streams.insert((target.process.clone(), channel_id), sink);
+        let Some(ws_stream) = reconnect_websocket(
+            &ws_id,
+            &url,
+            &headers,
+            proxy.as_deref(),
+            policy,
+            &ws_reconnects,
+            &print_tx,
+        )
+        .await
+        else {
+            // Given up, or closed by the process while waiting
+            if ws_reconnects.remove(&ws_id).is_some() {
+                break;
+            }
+            return;
+        };
+        let (mut sink, new_stream) = ws_stream.split();
+        if ws_reconnects.remove(&ws_id).is_none() {
+            // closed or reopened by the process while connecting
+            let _ = sink.close().await;
+            return;
+        }
+        ws_streams.insert(ws_id.clone(), sink);
+        stream = new_stream;
+        send_client_request(
+            our.clone(),
+            id,
+            target.clone(),
+            HttpClientRequest::WebSocketReconnected { channel_id },
+            None,
+            send_to_loop.clone(),
+        )
+        .await;
+    }
 
-    // Spawn a new tokio process to listen to incoming WS events on the stream
-    tokio::spawn(listen_to_stream(
-        our.clone(),
+    // Notify the originating process that the connection was closed
+    send_client_request(
+        our,
         id,
-        target.clone(),
-        channel_id,
-        stream,
-        ws_streams,
-        send_to_loop.clone(),
-        print_tx_clone,
-    ));
+        target,
+        HttpClientRequest::WebSocketClose { channel_id },
+        None,
+        send_to_loop,
+    )
+    .await;
+}
 
-    Ok(HttpClientResponse::WebSocketAck)
+/// Try to reconnect a dropped connection, backing off between attempts as the
+/// policy describes. Returns None if every attempt fails, or if the
+/// reconnection is cancelled.
+async fn reconnect_websocket(
+    ws_id: &WebSocketId,
+    url: &url::Url,
+    headers: &HashMap<String, String>,
+    proxy: Option<&str>,
+    policy: &ReconnectPolicy,
+    ws_reconnects: &WebSocketReconnects,
+    print_tx: &PrintSender,
+) -> Option<WsStream> {
+    let mut backoff = policy.initial_backoff_ms.min(policy.max_backoff_ms);
+    let mut attempt: u32 = 0;
+    while policy.max_attempts.map_or(true, |max| attempt < max) {
+        attempt += 1;
+        tokio::time::sleep(Duration::from_millis(backoff)).await;
+        if !ws_reconnects.contains(ws_id) {
+            return None;
+        }
+        let _ = print_tx
+            .send(Printout::new(
+                2,
+                HTTP_CLIENT_PROCESS_ID.clone(),
+                format!("http-client: reconnecting to {url} (attempt {attempt})"),
+            ))
+            .await;
+        if let Ok(ws_stream) = open_websocket(url, headers, proxy, print_tx).await {
+            return Some(ws_stream);
+        }
+        backoff = backoff.saturating_mul(2).min(policy.max_backoff_ms);
+    }
+    None
 }
 
-async fn listen_to_stream(
+/// Open a TCP connection to the host of `url` through the proxy at `proxy`,
+/// with a CONNECT to an `http://` proxy, or the SOCKS5 handshake with a
+/// `socks5://` or `socks5h://` one, which resolves the host itself.
+async fn connect_through_proxy(
+    proxy: &str,
+    url: &url::Url,
+) -> anyhow::Result<tokio::net::TcpStream> {
+    let proxy = url::Url::parse(proxy)?;
+    let proxy_host = proxy
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("proxy {proxy} has no host"))?;
+    let proxy_port = proxy.port_or_known_default().unwrap_or(SOCKS5_DEFAULT_PORT);
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("{url} has no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow::anyhow!("{url} has no port"))?;
+    let mut stream = tokio::net::TcpStream::connect((proxy_host, proxy_port)).await?;
+    match proxy.scheme() {
+        "http" => {
+            let mut connect = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+            if !proxy.username().is_empty() {
+                let credentials = base64_standard.encode(format!(
+                    "{}:{}",
+                    proxy.username(),
+                    proxy.password().unwrap_or("")
+                ));
+                connect.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+            }
+            connect.push_str("\r\n");
+            stream.write_all(connect.as_bytes()).await?;
+            // read the response head byte by byte, so as not to read past it
+            let mut head = vec![];
+            while !head.ends_with(b"\r\n\r\n") {
+                if head.len() > MAX_PROXY_RESPONSE_HEAD {
+                    return Err(anyhow::anyhow!("proxy response too long"));
+                }
+                head.push(stream.read_u8().await?);
+            }
+            let status = String::from_utf8_lossy(&head);
+            let status = status.lines().next().unwrap_or_default();
+            if status.split_whitespace().nth(1) != Some("200") {
+                return Err(anyhow::anyhow!("proxy refused CONNECT: {status}"));
+            }
+        }
+        "socks5" | "socks5h" => {
+            let (username, password) = (proxy.username(), proxy.password().unwrap_or(""));
+            let with_auth = !username.is_empty();
+            // offer no authentication, or username and password if given
+            stream
+                .write_all(&[5, 1, if with_auth { 2 } else { 0 }])
+                .await?;
+            let mut reply = [0u8; 2];
+            stream.read_exact(&mut reply).await?;
+            match reply {
+                [5, 0] if !with_auth => {}
+                [5, 2] if with_auth => {
+                    if username.len() > 255 || password.len() > 255 {
+                        return Err(anyhow::anyhow!("proxy credentials too long"));
+                    }
+                    let mut auth = vec![1, username.len() as u8];
+                    auth.extend_from_slice(username.as_bytes());
+                    auth.push(password.len() as u8);
+                    auth.extend_from_slice(password.as_bytes());
+                    stream.write_all(&auth).await?;
+                    stream.read_exact(&mut reply).await?;
+                    if reply[1] != 0 {
+                        return Err(anyhow::anyhow!("proxy refused credentials"));
+                    }
+                }
+                _ => return Err(anyhow::anyhow!("proxy refused authentication method")),
+            }
+            // connect by domain name, leaving the proxy to resolve it
+            if host.len() > 255 {
+                return Err(anyhow::anyhow!("host {host} too long"));
+            }
+            let mut connect = vec![5, 1, 0, 3, host.len() as u8];
+            connect.extend_from_slice(host.as_bytes());
+            connect.extend_from_slice(&port.to_be_bytes());
+            stream.write_all(&connect).await?;
+            let mut head = [0u8; 4];
+            stream.read_exact(&mut head).await?;
+            if head[1] != 0 {
+                return Err(anyhow::anyhow!(
+                    "proxy refused to connect, code {}",
+                    head[1]
+                ));
+            }
+            // skip the address the proxy bound, and its port
+            let address_length = match head[3] {
+                1 => 4,
+                4 => 16,
+                3 => stream.read_u8().await? as usize,
+                _ => return Err(anyhow::anyhow!("proxy gave a malformed reply")),
+            };
+            let mut bound = vec![0u8; address_length + 2];
+            stream.read_exact(&mut bound).await?;
+        }
+        scheme => {
+            return Err(anyhow::anyhow!(
+                "WebSockets can't go through a proxy of scheme {scheme}"
+            ))
+        }
+    }
+    Ok(stream)
+}
+
+/// Forward the messages of a connection to the process that opened it until
+/// the connection ends. Returns true if it was ended by the remote end or an
+/// error while the process still held it open.
+async fn forward_stream(
     our: Arc<String>,
     id: u64,
     target: Address,
     channel_id: u32,
-    mut stream: SplitStream<WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>>,
+    mut stream: SplitStream<WsStream>,
     ws_streams: WebSocketStreams,
     send_to_loop: MessageSender,
-    _print_tx: PrintSender,
-) {
+) -> bool {
+    let ws_id = (target.process.clone(), channel_id);
     while let Some(message) = stream.next().await {
         match message {
             Ok(msg) => {
                 // Handle different types of incoming WebSocket messages
-                let (body, blob, should_exit) = match msg {
+                let (body, blob) = match msg {
                     TungsteniteMessage::Text(text) => (
                         HttpClientRequest::WebSocketPush {
                             channel_id,
@@ -278,7 +710,6 @@ async fn listen_to_stream(
                             mime: Some("text/plain".into()),
                             bytes: text.into_bytes(),
                         }),
-                        false,
                     ),
                     TungsteniteMessage::Binary(bytes) => (
                         HttpClientRequest::WebSocketPush {
@@ -289,13 +720,10 @@ async fn listen_to_stream(
                             mime: Some("application/octet-stream".into()),
                             bytes,
                         }),
-                        false,
                     ),
                     TungsteniteMessage::Close(_) => {
                         // remove the websocket from the map
-                        ws_streams.remove(&(target.process.clone(), channel_id));
-
-                        (HttpClientRequest::WebSocketClose { channel_id }, None, true)
+                        return ws_streams.remove(&ws_id).is_some();
                     }
                     TungsteniteMessage::Ping(_) => (
                         HttpClientRequest::WebSocketPush {
@@ -303,7 +731,6 @@ async fn listen_to_stream(
                             message_type: WsMessageType::Ping,
                         },
                         None,
-                        false,
                     ),
                     TungsteniteMessage::Pong(_) => (
                         HttpClientRequest::WebSocketPush {
@@ -311,7 +738,6 @@ async fn listen_to_stream(
                             message_type: WsMessageType::Pong,
                         },
                         None,
-                        false,
                     ),
                     _ => {
                         // should never get a TungsteniteMessage::Frame, ignore if we do
@@ -319,8 +745,8 @@ async fn listen_to_stream(
                     }
                 };
 
-                if ws_streams.contains_key(&(target.process.clone(), channel_id)) || should_exit {
-                    handle_ws_message(
+                if ws_streams.contains_key(&ws_id) {
+                    send_client_request(
                         our.clone(),
                         id,
                         target.clone(),
@@ -330,38 +756,21 @@ async fn listen_to_stream(
                     )
                     .await;
                 }
-
-                if should_exit {
-                    break;
-                }
             }
             Err(e) => {
                 println!("WebSocket Client Error ({}): {:?}", channel_id, e);
 
                 // The connection was closed/reset by the remote server, so we'll remove and close it
-                if let Some(mut ws_sink) = ws_streams.get_mut(&(target.process.clone(), channel_id))
-                {
-                    // Close the stream. The stream is closed even on error.
-                    let _ = ws_sink.close().await;
-                }
-                // Remove the stream from the map
-                ws_streams.remove(&(target.process.clone(), channel_id));
-
-                // Notify the originating process that the connection was closed
-                handle_ws_message(
-                    our.clone(),
-                    id,
-                    target.clone(),
-                    HttpClientRequest::WebSocketClose { channel_id },
-                    None,
-                    send_to_loop.clone(),
-                )
-                .await;
-
-                break;
+                let Some((_, mut ws_sink)) = ws_streams.remove(&ws_id) else {
+                    return false;
+                };
+                // Close the stream. The stream is closed even on error.
+                let _ = ws_sink.close().await;
+                return true;
             }
         }
     }
+    ws_streams.remove(&ws_id).is_some()
 }
 
 async fn handle_http_request(
@@ -436,14 +845,34 @@ async fn handle_http_request(
     }
 
     // Add the body as appropriate
-    if let Some(blob) = body {
-        request_builder = request_builder.body(blob.bytes);
+    let mut headers = deserialize_headers(req.headers);
+    let body = match req.form {
+        None => body.map(|blob| blob.bytes),
+        Some(form) => match encode_form(form, body) {
+            Ok((bytes, content_type)) => {
+                headers.insert(http::header::CONTENT_TYPE, content_type);
+                Some(bytes)
+            }
+            Err(e) => {
+                http_error_message(
+                    our,
+                    id,
+                    target,
+                    expects_response,
+                    HttpClientError::BadForm(e),
+                    send_to_loop,
+                )
+                .await;
+                return;
+            }
+        },
+    };
+    if let Some(body) = body {
+        request_builder = request_builder.body(body);
     }
 
     // Add the headers
-    let build = request_builder
-        .headers(deserialize_headers(req.headers))
-        .build();
+    let build = request_builder.headers(headers).build();
     if let Err(e) = build {
         http_error_message(
             our,
@@ -457,43 +886,16 @@ async fn handle_http_request(
         return;
     };
 
-    // Send the HTTP request
-    match client.execute(build.unwrap()).await {
-        Ok(response) => {
-            // Handle the response and forward to the target process
-            let Ok(body) = serde_json::to_vec::<Result<HttpClientResponse, HttpClientError>>(&Ok(
-                HttpClientResponse::Http(HttpResponse {
-                    status: response.status().as_u16(),
-                    headers: serialize_headers(response.headers()),
-                }),
-            )) else {
-                return;
-            };
-            let _ = send_to_loop
-                .send(KernelMessage {
-                    id,
-                    source: Address {
-                        node: our.to_string(),
-                        process: ProcessId::new(Some("http-client"), "distro", "sys"),
-                    },
-                    target,
-                    rsvp: None,
-                    message: Message::Response((
-                        Response {
-                            inherit: false,
-                            body,
-                            metadata: None,
-                            capabilities: vec![],
-                        },
-                        None,
-                    )),
-                    lazy_load_blob: Some(LazyLoadBlob {
-                        mime: None,
-                        bytes: response.bytes().await.unwrap_or_default().to_vec(),
-                    }),
-                })
-                .await;
-        }
+    // Send the HTTP request, giving up on retries once the requester's timeout is up
+    let attempts = execute_with_retries(&client, build.unwrap(), req.retry.as_ref(), &print_tx);
+    let response = match expects_response {
+        Some(timeout) => tokio::time::timeout(Duration::from_secs(timeout), attempts)
+            .await
+            .unwrap_or_else(|_| Err(format!("timed out after {timeout}s"))),
+        None => attempts.await,
+    };
+    let mut response = match response {
+        Ok(response) => response,
         Err(e) => {
             let _ = print_tx
                 .send(Printout::new(
@@ -508,18 +910,256 @@ async fn handle_http_request(
                 id,
                 target,
                 expects_response,
-                HttpClientError::ExecuteRequestFailed(e.to_string()),
+                HttpClientError::ExecuteRequestFailed(e),
                 send_to_loop,
             )
             .await;
+            return;
+        }
+    };
+    let http_response = HttpResponse {
+        status: response.status().as_u16(),
+        headers: serialize_headers(response.headers()),
+    };
+
+    let Some(stream_threshold) = req.stream_threshold else {
+        // Handle the response and forward to the target process
+        let bytes = response.bytes().await.unwrap_or_default().to_vec();
+        send_http_response(
+            our,
+            id,
+            target,
+            HttpClientResponse::Http(http_response),
+            Some(LazyLoadBlob { mime: None, bytes }),
+            send_to_loop,
+        )
+        .await;
+        return;
+    };
+
+    // Read the body up to the threshold: if it ends there, respond as usual
+    let mut body = Vec::new();
+    while body.len() as u64 <= stream_threshold {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) => {
+                send_http_response(
+                    our,
+                    id,
+                    target,
+                    HttpClientResponse::Http(http_response),
+                    Some(LazyLoadBlob {
+                        mime: None,
+                        bytes: body,
+                    }),
+                    send_to_loop,
+                )
+                .await;
+                return;
+            }
+            Err(e) => {
+                http_error_message(
+                    our,
+                    id,
+                    target,
+                    expects_response,
+                    HttpClientError::ExecuteRequestFailed(e.to_string()),
+                    send_to_loop,
+                )
+                .await;
+                return;
+            }
+        }
+    }
+
+    // Otherwise, stream it to the target process, identified by the request ID
+    let _ = print_tx
+        .send(Printout::new(
+            2,
+            HTTP_CLIENT_PROCESS_ID.clone(),
+            format!("http-client: streaming response body of request {id}"),
+        ))
+        .await;
+    send_http_response(
+        our.clone(),
+        id,
+        target.clone(),
+        HttpClientResponse::HttpStream {
+            response: http_response,
+            stream_id: id,
+        },
+        None,
+        send_to_loop.clone(),
+    )
+    .await;
+    loop {
+        let (chunk, last) = match response.chunk().await {
+            Ok(Some(chunk)) => {
+                body.extend_from_slice(&chunk);
+                if body.len() < STREAM_CHUNK_SIZE {
+                    continue;
+                }
+                (std::mem::take(&mut body), false)
+            }
+            Ok(None) => (std::mem::take(&mut body), true),
+            Err(_) => {
+                send_client_request(
+                    our,
+                    id,
+                    target,
+                    HttpClientRequest::HttpBodyAbort { stream_id: id },
+                    None,
+                    send_to_loop,
+                )
+                .await;
+                return;
+            }
+        };
+        send_client_request(
+            our.clone(),
+            id,
+            target.clone(),
+            HttpClientRequest::HttpBodyChunk {
+                stream_id: id,
+                last,
+            },
+            Some(LazyLoadBlob {
+                mime: None,
+                bytes: chunk,
+            }),
+            send_to_loop.clone(),
+        )
+        .await;
+        if last {
+            return;
         }
     }
 }
 
+/// Execute a request, retrying it as the policy describes, if any
+async fn execute_with_retries(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    retry: Option<&RetryPolicy>,
+    print_tx: &PrintSender,
+) -> Result<reqwest::Response, String> {
+    let Some(retry) = retry else {
+        return client.execute(request).await.map_err(|e| e.to_string());
+    };
+    let mut backoff = retry.initial_backoff_ms.min(retry.max_backoff_ms);
+    for attempt in 1..=retry.max_retries {
+        // a request with a streaming body cannot be cloned, so is only attempted once
+        let Some(attempt_request) = request.try_clone() else {
+            break;
+        };
+        let wait = match client.execute(attempt_request).await {
+            Ok(response) if !retry.retry_on_status.contains(&response.status().as_u16()) => {
+                return Ok(response);
+            }
+            Ok(response) => retry_after(&response).unwrap_or(backoff),
+            Err(_) => backoff,
+        }
+        .min(retry.max_backoff_ms);
+        let _ = print_tx
+            .send(Printout::new(
+                2,
+                HTTP_CLIENT_PROCESS_ID.clone(),
+                format!(
+                    "http-client: retrying request to {} in {wait}ms (retry {attempt} of {})",
+                    request.url(),
+                    retry.max_retries,
+                ),
+            ))
+            .await;
+        tokio::time::sleep(Duration::from_millis(wait)).await;
+        backoff = backoff.saturating_mul(2).min(retry.max_backoff_ms);
+    }
+    client.execute(request).await.map_err(|e| e.to_string())
+}
+
+/// The delay in milliseconds asked for by a `Retry-After` header, if it gives one in seconds
+fn retry_after(response: &reqwest::Response) -> Option<u64> {
+    let seconds: u64 = response
+        .headers()
+        .get(http::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(seconds.saturating_mul(1000))
+}
+
 //
 //  helpers
 //
 
+/// Encode a form as a request body, returning it with its `Content-Type`
+fn encode_form(
+    form: RequestForm,
+    blob: Option<LazyLoadBlob>,
+) -> Result<(Vec<u8>, HeaderValue), String> {
+    match form {
+        RequestForm::UrlEncoded(fields) => {
+            let body = url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(fields)
+                .finish();
+            Ok((
+                body.into_bytes(),
+                HeaderValue::from_static("application/x-www-form-urlencoded"),
+            ))
+        }
+        RequestForm::Multipart(parts) => {
+            let contents = blob.map(|blob| blob.bytes).unwrap_or_default();
+            let boundary = format!("kinode-form-boundary-{:032x}", rand::random::<u128>());
+            let mut body = Vec::with_capacity(contents.len());
+            let mut offset = 0usize;
+            for part in parts {
+                let end = usize::try_from(part.length)
+                    .ok()
+                    .and_then(|length| offset.checked_add(length))
+                    .filter(|end| *end <= contents.len())
+                    .ok_or_else(|| format!("blob too short for part {}", part.name))?;
+                body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+                let mut disposition =
+                    format!("form-data; name=\"{}\"", escape_form_name(&part.name));
+                if let Some(filename) = &part.filename {
+                    disposition.push_str(&format!("; filename=\"{}\"", escape_form_name(filename)));
+                }
+                body.extend_from_slice(
+                    format!("Content-Disposition: {disposition}\r\n").as_bytes(),
+                );
+                if let Some(content_type) = &part.content_type {
+                    if content_type.contains(['\r', '\n']) {
+                        return Err(format!("bad content type for part {}", part.name));
+                    }
+                    body.extend_from_slice(format!("Content-Type: {content_type}\r\n").as_bytes());
+                }
+                body.extend_from_slice(b"\r\n");
+                body.extend_from_slice(&contents[offset..end]);
+                body.extend_from_slice(b"\r\n");
+                offset = end;
+            }
+            if offset != contents.len() {
+                return Err("blob longer than the parts".to_string());
+            }
+            body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+            let content_type =
+                HeaderValue::from_str(&format!("multipart/form-data; boundary={boundary}"))
+                    .map_err(|e| e.to_string())?;
+            Ok((body, content_type))
+        }
+    }
+}
+
+/// Escape a field name or filename for a `Content-Disposition` header,
+/// as browsers do
+fn escape_form_name(name: &str) -> String {
+    name.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
 /// Convert a &str to Pascal-Case (for HTTP headers)
 fn to_pascal_case(s: &str) -> String {
     s.split('-')
@@ -559,6 +1199,42 @@ fn deserialize_headers(hashmap: HashMap<String, String>) -> HeaderMap {
     header_map
 }
 
+/// Send the response to an HTTP request to a target
+async fn send_http_response(
+    our: Arc<String>,
+    id: u64,
+    target: Address,
+    response: HttpClientResponse,
+    blob: Option<LazyLoadBlob>,
+    send_to_loop: MessageSender,
+) {
+    let Ok(body) = serde_json::to_vec::<Result<HttpClientResponse, HttpClientError>>(&Ok(response))
+    else {
+        return;
+    };
+    let _ = send_to_loop
+        .send(KernelMessage {
+            id,
+            source: Address {
+                node: our.to_string(),
+                process: ProcessId::new(Some("http-client"), "distro", "sys"),
+            },
+            target,
+            rsvp: None,
+            message: Message::Response((
+                Response {
+                    inherit: false,
+                    body,
+                    metadata: None,
+                    capabilities: vec![],
+                },
+                None,
+            )),
+            lazy_load_blob: blob,
+        })
+        .await;
+}
+
 /// Send an HTTP error to a target
 async fn http_error_message(
     our: Arc<String>,
@@ -630,7 +1306,13 @@ async fn send_ws_push(
         }
         WsMessageType::Ping => ws_stream.send(TungsteniteMessage::Ping(vec![])).await,
         WsMessageType::Pong => ws_stream.send(TungsteniteMessage::Pong(vec![])).await,
-        WsMessageType::Close => ws_stream.send(TungsteniteMessage::Close(None)).await,
+        WsMessageType::Close => {
+            // closed by the process, so not to be reconnected
+            let result = ws_stream.send(TungsteniteMessage::Close(None)).await;
+            drop(ws_stream);
+            ws_streams.remove(&(target.process.clone(), channel_id));
+            result
+        }
     };
 
     Ok(HttpClientResponse::WebSocketAck)
@@ -641,10 +1323,16 @@ async fn close_ws_connection(
     target: Address,
     channel_id: u32,
     ws_streams: WebSocketStreams,
+    ws_reconnects: WebSocketReconnects,
     _print_tx: PrintSender,
 ) -> Result<HttpClientResponse, HttpClientError> {
-    let Some((_, mut ws_sink)) = ws_streams.remove(&(target.process.clone(), channel_id)) else {
-        return Err(HttpClientError::WsCloseFailed { channel_id });
+    let ws_id = (target.process.clone(), channel_id);
+    let Some((_, mut ws_sink)) = ws_streams.remove(&ws_id) else {
+        // a dropped connection is closed by cancelling its reconnection
+        return match ws_reconnects.remove(&ws_id) {
+            Some(_) => Ok(HttpClientResponse::WebSocketAck),
+            None => Err(HttpClientError::WsCloseFailed { channel_id }),
+        };
     };
 
     // Close the stream. The stream is closed even on error.
@@ -653,8 +1341,9 @@ async fn close_ws_connection(
     Ok(HttpClientResponse::WebSocketAck)
 }
 
-/// Forward an incoming WS request from an external source to the corresponding process
-async fn handle_ws_message(
+/// Send a request to the process that opened a WS connection or an HTTP stream:
+/// an incoming WS message, or part of a streamed response body
+async fn send_client_request(
     our: Arc<String>,
     id: u64,
     target: Address,
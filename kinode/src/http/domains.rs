@@ -0,0 +1,233 @@
+//! Custom hostnames for apps, configured through settings.
+//!
+//! A request whose `Host` is set with `HttpServerAction::SetDomain` is routed to
+//! the paths of the host's process, so that an app's UI can be served at, say,
+//! `chess.mynode.example/` rather than `mynode.example/chess:chess:sys/`.
+//! Hosts configured with a certificate can also be served over TLS, with the
+//! certificate for each connection chosen by the host it asks for.
+use crate::http::server_types::{DomainInfo, HttpServerError, TlsCertificate};
+use crate::http::utils;
+use dashmap::DashMap;
+use lib::types::core::{PrintSender, Printout, ProcessId, HTTP_SERVER_PROCESS_ID};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio_rustls::rustls::{
+    self,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+
+const DOMAINS_FILE: &str = ".http_domains";
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Domain {
+    process: ProcessId,
+    certificate: Option<TlsCertificate>,
+}
+
+pub struct Domains {
+    file: PathBuf,
+    domains: DashMap<String, Domain>,
+    /// the parsed certificates of the hosts that have one
+    certified_keys: DashMap<String, Arc<CertifiedKey>>,
+    /// held while the domains are written out, so that an older snapshot is
+    /// never written over a newer one
+    persisting: tokio::sync::Mutex<()>,
+    print_tx: PrintSender,
+}
+
+impl std::fmt::Debug for Domains {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Domains").finish_non_exhaustive()
+    }
+}
+
+impl Domains {
+    /// load the domains persisted in the home directory, if any
+    pub async fn load(home_directory_path: &Path, print_tx: PrintSender) -> Self {
+        let file = home_directory_path.join(DOMAINS_FILE);
+        let domains: HashMap<String, Domain> = tokio::fs::read(&file)
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        let certified_keys = DashMap::new();
+        for (host, domain) in &domains {
+            let Some(certificate) = &domain.certificate else {
+                continue;
+            };
+            match certified_key(certificate) {
+                Some(key) => {
+                    certified_keys.insert(host.clone(), Arc::new(key));
+                }
+                None => {
+                    Printout::new(
+                        0,
+                        HTTP_SERVER_PROCESS_ID.clone(),
+                        format!("http-server: failed to parse certificate for {host}, not serving it over TLS"),
+                    )
+                    .send(&print_tx)
+                    .await;
+                }
+            }
+        }
+        Self {
+            file,
+            domains: domains.into_iter().collect(),
+            certified_keys,
+            persisting: tokio::sync::Mutex::new(()),
+            print_tx,
+        }
+    }
+
+    pub async fn set(
+        &self,
+        host: &str,
+        process: ProcessId,
+        certificate: Option<TlsCertificate>,
+    ) -> Result<(), HttpServerError> {
+        let host = normalize_host(host).ok_or(HttpServerError::InvalidDomain)?;
+        match &certificate {
+            Some(certificate) => {
+                let key = certified_key(certificate).ok_or(HttpServerError::InvalidCertificate)?;
+                self.certified_keys.insert(host.clone(), Arc::new(key));
+            }
+            None => {
+                self.certified_keys.remove(&host);
+            }
+        }
+        self.domains.insert(
+            host,
+            Domain {
+                process,
+                certificate,
+            },
+        );
+        self.persist().await;
+        Ok(())
+    }
+
+    pub async fn remove(&self, host: &str) -> Result<(), HttpServerError> {
+        let host = normalize_host(host).ok_or(HttpServerError::InvalidDomain)?;
+        if self.domains.remove(&host).is_none() {
+            return Err(HttpServerError::DomainNotFound);
+        }
+        self.certified_keys.remove(&host);
+        self.persist().await;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<DomainInfo> {
+        let mut domains: Vec<DomainInfo> = self
+            .domains
+            .iter()
+            .map(|entry| DomainInfo {
+                host: entry.key().clone(),
+                process: entry.value().process.clone(),
+                tls: entry.value().certificate.is_some(),
+            })
+            .collect();
+        domains.sort_by(|a, b| a.host.cmp(&b.host));
+        domains
+    }
+
+    /// The path a request for `path` at `host` is handled as, if the host is set:
+    /// the path under the host's process, unless it is already.
+    pub fn route(&self, host: Option<&str>, path: &str) -> Option<String> {
+        let host = self.matching_host(host?)?;
+        let process = self.domains.get(&host)?.process.to_string();
+        // the homepage binds its paths without a process prefix
+        if process == "homepage:homepage:sys" {
+            return None;
+        }
+        let prefix = format!("/{process}");
+        if path == prefix || path.starts_with(&format!("{prefix}/")) {
+            return None;
+        }
+        Some(format!("{prefix}{}", utils::normalize_path(path)))
+    }
+
+    /// The key under which `host` is set: the host itself, or else
+    /// the wildcard covering it, one label deep
+    fn matching_host(&self, host: &str) -> Option<String> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if self.domains.contains_key(&host) {
+            return Some(host);
+        }
+        let (_, parent) = host.split_once('.')?;
+        let wildcard = format!("*.{parent}");
+        self.domains.contains_key(&wildcard).then_some(wildcard)
+    }
+
+    async fn persist(&self) {
+        let _persisting = self.persisting.lock().await;
+        let domains: HashMap<String, Domain> = self
+            .domains
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        if let Err(e) =
+            utils::write_atomic(&self.file, &serde_json::to_vec(&domains).unwrap()).await
+        {
+            Printout::new(
+                0,
+                HTTP_SERVER_PROCESS_ID.clone(),
+                format!("http-server: failed to persist {DOMAINS_FILE}: {e}"),
+            )
+            .send(&self.print_tx)
+            .await;
+        }
+    }
+}
+
+impl ResolvesServerCert for Domains {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let host = self.matching_host(client_hello.server_name()?)?;
+        self.certified_keys.get(&host).map(|key| key.clone())
+    }
+}
+
+/// A TLS configuration that picks the certificate of the host each client asks for.
+pub fn tls_acceptor(domains: Arc<Domains>) -> anyhow::Result<tokio_rustls::TlsAcceptor> {
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_cert_resolver(domains);
+    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+}
+
+fn certified_key(certificate: &TlsCertificate) -> Option<CertifiedKey> {
+    let cert_chain = rustls_pemfile::certs(&mut certificate.cert_chain.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    if cert_chain.is_empty() {
+        return None;
+    }
+    let private_key =
+        rustls_pemfile::private_key(&mut certificate.private_key.as_bytes()).ok()??;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&private_key).ok()?;
+    Some(CertifiedKey::new(cert_chain, signing_key))
+}
+
+/// A lowercase hostname, optionally with a leading `*.` wildcard label, or None
+/// if `host` is not one
+fn normalize_host(host: &str) -> Option<String> {
+    let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+    let name = host.strip_prefix("*.").unwrap_or(&host);
+    let valid = !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    valid.then_some(host)
+}
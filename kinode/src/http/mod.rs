@@ -1,6 +1,10 @@
 #![allow(unused)]
+pub mod access_log;
 pub mod client;
+pub mod domains;
+pub mod proxy;
 pub mod server;
+pub mod totp;
 pub mod utils;
 
 pub use lib::types::http_client as client_types;
@@ -0,0 +1,264 @@
+//! Reverse-proxy bindings: paths whose requests http-server forwards to an
+//! upstream HTTP service, rather than to the process that bound them.
+use crate::http::server_types::ProxyConfig;
+use futures::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use tokio_tungstenite::tungstenite::{
+    self, client::IntoClientRequest, Message as TungsteniteMessage,
+};
+use warp::{http::StatusCode, Reply};
+
+/// how long to wait for the upstream to respond
+const PROXY_TIMEOUT: u64 = 60;
+
+/// Headers that describe a single connection rather than the request,
+/// so are never forwarded in either direction.
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+];
+
+/// The upstream URL for a request, given the part of its path
+/// under the bound path, if any.
+pub fn upstream_url(proxy: &ProxyConfig, rest: Option<&str>, query: &str) -> String {
+    let mut url = proxy.upstream.trim_end_matches('/').to_string();
+    if let Some(rest) = rest {
+        url.push('/');
+        url.push_str(rest.trim_start_matches('/'));
+    }
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(query);
+    }
+    url
+}
+
+/// The headers to forward to the upstream: the client's, without hop-by-hop
+/// headers or the node's own credentials, rewritten as the binding specifies.
+/// Credentials the upstream needs are set by the binding.
+fn forwarded_headers(
+    proxy: &ProxyConfig,
+    our: &str,
+    headers: &warp::http::HeaderMap,
+    socket_addr: Option<SocketAddr>,
+    host: &str,
+) -> Vec<(String, String)> {
+    let mut forwarded: Vec<(String, String)> = headers
+        .iter()
+        .filter(|(name, _)| {
+            !HOP_BY_HOP_HEADERS.contains(&name.as_str())
+                && name != "authorization"
+                && !name.as_str().starts_with("sec-websocket-")
+                && !proxy
+                    .remove_request_headers
+                    .iter()
+                    .any(|removed| removed.eq_ignore_ascii_case(name.as_str()))
+        })
+        .filter_map(|(name, value)| {
+            let value = value.to_str().ok()?;
+            if name == "cookie" {
+                let cookies = value
+                    .split("; ")
+                    .filter(|cookie| {
+                        !cookie.starts_with(&format!("kinode-auth_{our}"))
+                            && !cookie.starts_with(&format!("kinode-csrf_{our}"))
+                    })
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                return (!cookies.is_empty()).then(|| (name.to_string(), cookies));
+            }
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect();
+    if let Some(addr) = socket_addr {
+        forwarded.push(("x-forwarded-for".to_string(), addr.ip().to_string()));
+    }
+    forwarded.push(("x-forwarded-host".to_string(), host.to_string()));
+    forwarded.retain(|(name, _)| {
+        !proxy
+            .set_request_headers
+            .keys()
+            .any(|set| set.eq_ignore_ascii_case(name))
+    });
+    forwarded.extend(
+        proxy
+            .set_request_headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.clone())),
+    );
+    forwarded
+}
+
+/// A `Set-Cookie` of the upstream, scoped to the path bound to it on the node's
+/// host, or None if it would set one of the node's own cookies.
+fn rewritten_set_cookie(value: &str, our: &str, bound_path: &str) -> Option<String> {
+    let mut parts = value.split(';').map(str::trim);
+    let cookie = parts.next()?;
+    if cookie.starts_with(&format!("kinode-auth_{our}"))
+        || cookie.starts_with(&format!("kinode-csrf_{our}"))
+    {
+        return None;
+    }
+    let mut rewritten = vec![cookie.to_string()];
+    for attribute in parts {
+        let name = attribute.split('=').next().unwrap_or_default().trim();
+        if !name.eq_ignore_ascii_case("path") && !name.eq_ignore_ascii_case("domain") {
+            rewritten.push(attribute.to_string());
+        }
+    }
+    rewritten.push(format!("Path={bound_path}"));
+    Some(rewritten.join("; "))
+}
+
+/// Forward an HTTP request to the upstream and relay its response.
+pub async fn forward_http(
+    client: &reqwest::Client,
+    proxy: &ProxyConfig,
+    our: &str,
+    bound_path: &str,
+    url: String,
+    method: &warp::http::Method,
+    headers: &warp::http::HeaderMap,
+    body: warp::hyper::body::Bytes,
+    socket_addr: Option<SocketAddr>,
+    host: &str,
+) -> warp::reply::Response {
+    let Ok(method) = http::Method::from_bytes(method.as_str().as_bytes()) else {
+        return warp::reply::with_status(vec![], StatusCode::METHOD_NOT_ALLOWED).into_response();
+    };
+    let mut request = client
+        .request(method, &url)
+        .timeout(std::time::Duration::from_secs(PROXY_TIMEOUT))
+        .body(body.to_vec());
+    for (name, value) in forwarded_headers(proxy, our, headers, socket_addr, host) {
+        request = request.header(name, value);
+    }
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            let status = if e.is_timeout() {
+                StatusCode::GATEWAY_TIMEOUT
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
+            return warp::reply::with_status(vec![], status).into_response();
+        }
+    };
+
+    let mut builder = warp::http::Response::builder().status(response.status().as_u16());
+    for (name, value) in response.headers() {
+        if HOP_BY_HOP_HEADERS.contains(&name.as_str())
+            || proxy
+                .set_response_headers
+                .keys()
+                .any(|set| set.eq_ignore_ascii_case(name.as_str()))
+        {
+            continue;
+        }
+        if name == "set-cookie" {
+            if let Some(cookie) = value
+                .to_str()
+                .ok()
+                .and_then(|value| rewritten_set_cookie(value, our, bound_path))
+            {
+                builder = builder.header(name.as_str(), cookie);
+            }
+            continue;
+        }
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    for (name, value) in &proxy.set_response_headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    let Ok(bytes) = response.bytes().await else {
+        return warp::reply::with_status(vec![], StatusCode::BAD_GATEWAY).into_response();
+    };
+    match builder.body(bytes.to_vec()) {
+        Ok(response) => response.into_response(),
+        Err(_) => warp::reply::with_status(vec![], StatusCode::BAD_GATEWAY).into_response(),
+    }
+}
+
+/// Open a WebSocket to the upstream and pass messages through in both
+/// directions until either side closes.
+pub async fn forward_websocket(
+    mut client_ws: warp::ws::WebSocket,
+    proxy: &ProxyConfig,
+    our: &str,
+    url: String,
+    headers: &warp::http::HeaderMap,
+    socket_addr: Option<SocketAddr>,
+    host: &str,
+) {
+    let url = match url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{rest}"),
+        Some(("http", rest)) => format!("ws://{rest}"),
+        _ => url,
+    };
+    let Ok(mut request) = url.into_client_request() else {
+        let _ = client_ws.close().await;
+        return;
+    };
+    for (name, value) in forwarded_headers(proxy, our, headers, socket_addr, host) {
+        if let (Ok(name), Ok(value)) = (
+            tungstenite::http::HeaderName::from_bytes(name.as_bytes()),
+            tungstenite::http::HeaderValue::from_str(&value),
+        ) {
+            request.headers_mut().insert(name, value);
+        }
+    }
+    let Ok((upstream_ws, _)) = tokio_tungstenite::connect_async(request).await else {
+        let _ = client_ws.close().await;
+        return;
+    };
+
+    let (mut client_tx, mut client_rx) = client_ws.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream_ws.split();
+    let client_to_upstream = async {
+        while let Some(Ok(message)) = client_rx.next().await {
+            let message = if message.is_text() {
+                TungsteniteMessage::Text(message.to_str().unwrap_or_default().to_string())
+            } else if message.is_binary() {
+                TungsteniteMessage::Binary(message.into_bytes())
+            } else if message.is_ping() {
+                TungsteniteMessage::Ping(message.into_bytes())
+            } else if message.is_pong() {
+                TungsteniteMessage::Pong(message.into_bytes())
+            } else {
+                TungsteniteMessage::Close(None)
+            };
+            if upstream_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+        let _ = upstream_tx.close().await;
+    };
+    let upstream_to_client = async {
+        while let Some(Ok(message)) = upstream_rx.next().await {
+            let message = match message {
+                TungsteniteMessage::Text(text) => warp::ws::Message::text(text),
+                TungsteniteMessage::Binary(bytes) => warp::ws::Message::binary(bytes),
+                TungsteniteMessage::Ping(bytes) => warp::ws::Message::ping(bytes),
+                TungsteniteMessage::Pong(bytes) => warp::ws::Message::pong(bytes),
+                TungsteniteMessage::Close(_) => warp::ws::Message::close(),
+                // raw frames are never yielded when reading
+                TungsteniteMessage::Frame(_) => continue,
+            };
+            if client_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+        let _ = client_tx.close().await;
+    };
+    tokio::select! {
+        _ = client_to_upstream => {}
+        _ = upstream_to_client => {}
+    }
+}
@@ -1,21 +1,25 @@
 use crate::http::server_types::{
-    HttpResponse, HttpServerAction, HttpServerError, HttpServerRequest, IncomingHttpRequest,
-    MessageType, RpcResponseBody, WsMessageType,
+    AccessLogEntry, CorsPolicy, HttpResponse, HttpServerAction, HttpServerError, HttpServerRequest,
+    IncomingHttpRequest, MessageType, MiddlewareResponse, PairingPayload, PairingRequest,
+    PairingResponse, PasswordChange, ProxyConfig, RateLimit, RpcResponseBody, WsAuthorization,
+    WsMessageType,
 };
-use crate::http::utils;
+use crate::http::{access_log, domains, proxy, totp, utils};
+use crate::kernel::DormantProcesses;
 use crate::keygen;
 use base64::{engine::general_purpose::STANDARD as base64_standard, Engine};
 use dashmap::DashMap;
 use futures::{SinkExt, StreamExt};
 use http::uri::Authority;
 use lib::types::core::{
-    check_process_id_kimap_safe, Address, KernelCommand, KernelMessage, LazyLoadBlob, LoginInfo,
-    Message, MessageReceiver, MessageSender, PrintSender, Printout, ProcessId, Request, Response,
-    HTTP_SERVER_PROCESS_ID,
+    check_process_id_kimap_safe, Address, KernelCommand, KernelMessage, KernelResponse,
+    LazyLoadBlob, LoginInfo, Message, MessageReceiver, MessageSender, NotifyAction, NotifyEvent,
+    PrintSender, Printout, ProcessId, Request, Response, HTTP_SERVER_PROCESS_ID, KERNEL_PROCESS_ID,
+    NOTIFY_PROCESS_ID,
 };
 use route_recognizer::Router;
 use sha2::{Digest, Sha256};
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc};
 use tokio::sync::RwLock;
 use warp::{
     http::{
@@ -32,23 +36,76 @@ const HTTP_SELF_IMPOSED_TIMEOUT: u64 = 15;
 const HTTP_SELF_IMPOSED_TIMEOUT: u64 = 600;
 
 const WS_SELF_IMPOSED_MAX_CONNECTIONS: u32 = 128;
+const EVENT_STREAM_SELF_IMPOSED_MAX_CONNECTIONS: u32 = 128;
+
+/// how often to send a comment down an idle event stream, so that proxies
+/// and browsers do not time it out, and so that closed streams are noticed
+const EVENT_STREAM_KEEPALIVE_INTERVAL: u64 = 15;
+
+/// how long to wait for a process that starts on demand to start, and then
+/// to bind the path of the request that started it
+const WAKE_TIMEOUT: u64 = 10;
+/// the path under which the response of the kernel to a request to start a
+/// process on demand is awaited in `HttpResponseSenders`
+const WAKE_PATH: &str = "/kernel:distro:sys/wake";
+
+/// how long a pairing token issued by `GET /pair` can be redeemed for
+const PAIRING_TOKEN_TTL: u64 = 300;
+
+/// the limit on attempts to log in or redeem a pairing token from each client,
+/// much stricter than the node-wide limit, to make brute-forcing impractical
+const LOGIN_RATE_LIMIT: RateLimit = RateLimit {
+    per_minute: 10,
+    burst: 5,
+};
 
 const LOGIN_HTML: &str = include_str!("login.html");
 
+/// how long a client has to complete a TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: u64 = 10;
+/// warp does not see the peer address of connections served over TLS, so it is
+/// passed to the filters in this header, replacing any sent by the client
+const PEER_ADDR_HEADER: &str = "x-kinode-peer-addr";
+
+/// returned from a login with the right password but a missing or wrong TOTP code,
+/// so that the login page can ask for one
+const TOTP_REQUIRED: &str = "TOTP code required";
+
 /// mapping from a given HTTP request (assigned an ID) to the oneshot
 /// channel that will get a response from the app that handles the request,
 /// and a string which contains the path that the request was made to.
 type HttpResponseSenders = Arc<DashMap<u64, (String, HttpSender)>>;
 type HttpSender = tokio::sync::oneshot::Sender<(HttpResponse, Vec<u8>)>;
 
+/// mapping from a request passed to a middleware process (assigned an ID)
+/// to that process, and the oneshot channel that will get its response.
+type MiddlewareSenders = Arc<DashMap<u64, (ProcessId, MiddlewareSender)>>;
+type MiddlewareSender = tokio::sync::oneshot::Sender<(MiddlewareResponse, Vec<u8>)>;
+
+/// mapping from a path to the process that set middleware on it, and the
+/// middleware process that requests to it and the paths under it pass through.
+type Middlewares = Arc<DashMap<String, (ProcessId, ProcessId)>>;
+
 /// mapping from an open websocket connection to a channel that will ingest
 /// WebSocketPush messages from the app that handles the connection, and
 /// send them to the connection.
 type WebSocketSenders = Arc<DashMap<u32, (ProcessId, WebSocketSender)>>;
 type WebSocketSender = tokio::sync::mpsc::Sender<warp::ws::Message>;
 
+/// mapping from an open event stream to a channel that will ingest
+/// EventStreamPush messages from the app that handles the stream, and
+/// send them to the client.
+type EventStreamSenders = Arc<DashMap<u32, (ProcessId, EventStreamSender)>>;
+type EventStreamSender = tokio::sync::mpsc::Sender<warp::sse::Event>;
+
+/// one-time pairing tokens issued to logged-in users, mapped to their
+/// expiration timestamp and the subdomain they were issued for.
+/// a token is removed as soon as it is redeemed.
+type PairingTokens = Arc<DashMap<String, (u64, Option<String>)>>;
+
 type PathBindings = Arc<RwLock<Router<BoundPath>>>;
 type WsPathBindings = Arc<RwLock<Router<BoundWsPath>>>;
+type EventStreamPathBindings = Arc<RwLock<Router<BoundEventStreamPath>>>;
 
 struct BoundPath {
     pub app: Option<ProcessId>, // if None, path has been unbound
@@ -56,7 +113,11 @@ struct BoundPath {
     pub secure_subdomain: Option<String>,
     pub authenticated: bool,
     pub local_only: bool,
-    pub static_content: Option<LazyLoadBlob>, // TODO store in filesystem and cache
+    pub static_content: Option<Arc<utils::StaticContent>>, // TODO store in filesystem
+    pub compress: bool,
+    pub cors: Option<CorsPolicy>,
+    pub rate_limit: Option<RateLimit>,
+    pub proxy: Option<ProxyConfig>,
 }
 
 struct BoundWsPath {
@@ -64,6 +125,15 @@ struct BoundWsPath {
     pub secure_subdomain: Option<String>,
     pub authenticated: bool,
     pub extension: bool,
+    /// only set for proxy bindings, which share their HTTP binding's setting
+    pub local_only: bool,
+    pub proxy: Option<ProxyConfig>,
+    pub authorization: Option<WsAuthorization>,
+}
+
+struct BoundEventStreamPath {
+    pub app: Option<ProcessId>, // if None, path has been unbound
+    pub authenticated: bool,
 }
 
 async fn send_push(
@@ -182,9 +252,43 @@ pub async fn http_server(
     mut recv_in_server: MessageReceiver,
     send_to_loop: MessageSender,
     print_tx: PrintSender,
+    rate_limit_per_minute: u32,
+    auth_token_lifetime: u64,
+    https_port: Option<u16>,
+    access_log: bool,
+    home_directory_path: PathBuf,
+    dormant_processes: DormantProcesses,
 ) -> anyhow::Result<()> {
     let http_response_senders: HttpResponseSenders = Arc::new(DashMap::new());
+    let middleware_senders: MiddlewareSenders = Arc::new(DashMap::new());
+    let middlewares: Middlewares = Arc::new(DashMap::new());
     let ws_senders: WebSocketSenders = Arc::new(DashMap::new());
+    let event_stream_senders: EventStreamSenders = Arc::new(DashMap::new());
+    let api_tokens = Arc::new(utils::ApiTokens::load(&home_directory_path, print_tx.clone()).await);
+    let domains = Arc::new(domains::Domains::load(&home_directory_path, print_tx.clone()).await);
+    let access_log = if access_log {
+        match access_log::AccessLog::open(&home_directory_path).await {
+            Ok(access_log) => Some(Arc::new(access_log)),
+            Err(e) => {
+                Printout::new(
+                    0,
+                    HTTP_SERVER_PROCESS_ID.clone(),
+                    format!("http-server: failed to open access log, not logging access: {e}"),
+                )
+                .send(&print_tx)
+                .await;
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let totp = Arc::new(
+        totp::Totp::load(&home_directory_path, &jwt_secret_bytes, print_tx.clone()).await?,
+    );
+    let jwt_secret = Arc::new(
+        utils::JwtSecret::load(jwt_secret_bytes, &home_directory_path, auth_token_lifetime).await,
+    );
 
     let mut bindings_map: Router<BoundPath> = Router::new();
 
@@ -198,32 +302,59 @@ pub async fn http_server(
             authenticated: false,
             local_only: true,
             static_content: None,
+            compress: false,
+            cors: None,
+            rate_limit: None,
+            proxy: None,
         },
     );
 
     let path_bindings: PathBindings = Arc::new(RwLock::new(bindings_map));
     let ws_path_bindings: WsPathBindings = Arc::new(RwLock::new(Router::new()));
+    let event_stream_path_bindings: EventStreamPathBindings = Arc::new(RwLock::new(Router::new()));
 
     tokio::spawn(serve(
         Arc::new(our_name),
         our_port,
         http_response_senders.clone(),
+        middleware_senders.clone(),
+        middlewares.clone(),
         path_bindings.clone(),
         ws_path_bindings.clone(),
         ws_senders.clone(),
-        Arc::new(encoded_keyfile),
-        Arc::new(jwt_secret_bytes),
+        event_stream_path_bindings.clone(),
+        event_stream_senders.clone(),
+        Arc::new(utils::EncodedKeyfile::new(
+            encoded_keyfile,
+            &home_directory_path,
+        )),
+        jwt_secret.clone(),
         send_to_loop.clone(),
         print_tx.clone(),
+        rate_limit_per_minute,
+        api_tokens.clone(),
+        totp,
+        domains.clone(),
+        https_port,
+        access_log.clone(),
+        dormant_processes,
     ));
 
     while let Some(km) = recv_in_server.recv().await {
         handle_app_message(
             km,
             http_response_senders.clone(),
+            middleware_senders.clone(),
+            middlewares.clone(),
             path_bindings.clone(),
             ws_path_bindings.clone(),
             ws_senders.clone(),
+            event_stream_path_bindings.clone(),
+            event_stream_senders.clone(),
+            api_tokens.clone(),
+            domains.clone(),
+            access_log.clone(),
+            jwt_secret.clone(),
             send_to_loop.clone(),
             print_tx.clone(),
         )
@@ -238,32 +369,112 @@ async fn serve(
     our: Arc<String>,
     our_port: u16,
     http_response_senders: HttpResponseSenders,
+    middleware_senders: MiddlewareSenders,
+    middlewares: Middlewares,
     path_bindings: PathBindings,
     ws_path_bindings: WsPathBindings,
     ws_senders: WebSocketSenders,
-    encoded_keyfile: Arc<Vec<u8>>,
-    jwt_secret_bytes: Arc<Vec<u8>>,
+    event_stream_path_bindings: EventStreamPathBindings,
+    event_stream_senders: EventStreamSenders,
+    encoded_keyfile: Arc<utils::EncodedKeyfile>,
+    jwt_secret: Arc<utils::JwtSecret>,
     send_to_loop: MessageSender,
     print_tx: PrintSender,
+    rate_limit_per_minute: u32,
+    api_tokens: Arc<utils::ApiTokens>,
+    totp: Arc<totp::Totp>,
+    domains: Arc<domains::Domains>,
+    https_port: Option<u16>,
+    access_log: Option<Arc<access_log::AccessLog>>,
+    dormant_processes: DormantProcesses,
 ) {
+    let tls_print_tx = print_tx.clone();
+    let log_our = our.clone();
+    let log_jwt_secret = jwt_secret.clone();
+    let log_api_tokens = api_tokens.clone();
+    let tls_domains = domains.clone();
+    let rate_limiter = Arc::new(utils::RateLimiter::default());
+    let cloned_rate_limiter = rate_limiter.clone();
+    // shared by proxy bindings, so that connections to upstreams are reused
+    let http_client = reqwest::Client::new();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            cloned_rate_limiter.prune();
+        }
+    });
+
+    // filter to limit the rate of all requests from each client
+    let node_rate_limit = RateLimit {
+        per_minute: rate_limit_per_minute,
+        burst: rate_limit_per_minute,
+    };
+    let cloned_rate_limiter = rate_limiter.clone();
+    let rate_limited = remote_addr()
+        .and(warp::filters::header::headers_cloned())
+        .and_then(
+            move |socket_addr: Option<SocketAddr>, headers: warp::http::HeaderMap| {
+                let rate_limiter = cloned_rate_limiter.clone();
+                async move {
+                    let headers = utils::serialize_headers(&headers);
+                    match utils::client_ip(socket_addr, &headers) {
+                        Some(ip) => rate_limiter
+                            .check(ip, "node", node_rate_limit)
+                            .map_err(|retry_after| warp::reject::custom(RateLimited(retry_after))),
+                        None => Ok(()),
+                    }
+                }
+            },
+        )
+        .untuple_one();
+
     // filter to receive websockets
     let cloned_our = our.clone();
-    let cloned_jwt_secret_bytes = jwt_secret_bytes.clone();
+    let cloned_jwt_secret = jwt_secret.clone();
     let cloned_msg_tx = send_to_loop.clone();
     let cloned_print_tx = print_tx.clone();
+    let cloned_http_client = http_client.clone();
+    let cloned_api_tokens = api_tokens.clone();
+    let cloned_domains = domains.clone();
     let ws_route = warp::ws()
-        .and(warp::addr::remote())
+        .and(remote_addr())
         .and(warp::path::full())
+        .and(raw_query())
         .and(warp::filters::host::optional())
         .and(warp::filters::header::headers_cloned())
         .and(warp::any().map(move || cloned_our.clone()))
-        .and(warp::any().map(move || cloned_jwt_secret_bytes.clone()))
+        .and(warp::any().map(move || cloned_jwt_secret.clone()))
         .and(warp::any().map(move || ws_senders.clone()))
         .and(warp::any().map(move || ws_path_bindings.clone()))
         .and(warp::any().map(move || cloned_msg_tx.clone()))
         .and(warp::any().map(move || cloned_print_tx.clone()))
+        .and(warp::any().map(move || cloned_http_client.clone()))
+        .and(warp::any().map(move || cloned_api_tokens.clone()))
+        .and(warp::any().map(move || cloned_domains.clone()))
         .and_then(ws_handler);
 
+    // filter to open event streams
+    let cloned_our = our.clone();
+    let cloned_jwt_secret = jwt_secret.clone();
+    let cloned_api_tokens = api_tokens.clone();
+    let cloned_msg_tx = send_to_loop.clone();
+    let cloned_print_tx = print_tx.clone();
+    let cloned_domains = domains.clone();
+    let event_stream_route = warp::get()
+        .and(warp::path::full())
+        .and(warp::filters::host::optional())
+        .and(warp::filters::header::headers_cloned())
+        .and(warp::any().map(move || cloned_our.clone()))
+        .and(warp::any().map(move || cloned_jwt_secret.clone()))
+        .and(warp::any().map(move || cloned_api_tokens.clone()))
+        .and(warp::any().map(move || event_stream_senders.clone()))
+        .and(warp::any().map(move || event_stream_path_bindings.clone()))
+        .and(warp::any().map(move || cloned_msg_tx.clone()))
+        .and(warp::any().map(move || cloned_print_tx.clone()))
+        .and(warp::any().map(move || cloned_domains.clone()))
+        .and_then(event_stream_handler);
+
     #[cfg(feature = "simulation-mode")]
     let fake_node = "true";
     #[cfg(not(feature = "simulation-mode"))]
@@ -276,6 +487,11 @@ async fn serve(
             .replace("${fake}", fake_node),
     );
     let cloned_our = our.clone();
+    let cloned_jwt_secret = jwt_secret.clone();
+    let cloned_totp = totp.clone();
+    let cloned_rate_limiter = rate_limiter.clone();
+    let cloned_encoded_keyfile = encoded_keyfile.clone();
+    let cloned_msg_tx = send_to_loop.clone();
     let cloned_login_html: &'static str = login_html.to_string().leak();
     let login = warp::path("login").and(warp::path::end()).and(
         warp::get()
@@ -283,50 +499,320 @@ async fn serve(
                 warp::reply::with_status(warp::reply::html(cloned_login_html), StatusCode::OK)
             })
             .or(warp::post()
+                .and(remote_addr())
+                .and(warp::filters::header::headers_cloned())
+                .and(warp::any().map(move || cloned_rate_limiter.clone()))
                 .and(warp::filters::host::optional())
                 .and(warp::query::<HashMap<String, String>>())
                 .and(warp::body::content_length_limit(1024 * 16))
                 .and(warp::body::bytes())
                 .and(warp::any().map(move || cloned_our.clone()))
-                .and(warp::any().map(move || encoded_keyfile.clone()))
+                .and(warp::any().map(move || cloned_encoded_keyfile.clone()))
+                .and(warp::any().map(move || cloned_jwt_secret.clone()))
+                .and(warp::any().map(move || cloned_totp.clone()))
+                .and(warp::any().map(move || cloned_msg_tx.clone()))
                 .and_then(login_handler)),
     );
 
+    // filter to change the node password, re-encrypting the keyfile
+    let cloned_our = our.clone();
+    let cloned_jwt_secret = jwt_secret.clone();
+    let cloned_totp = totp.clone();
+    let cloned_rate_limiter = rate_limiter.clone();
+    let cloned_print_tx = print_tx.clone();
+    let password = warp::path!("login" / "password")
+        .and(warp::post())
+        .and(remote_addr())
+        .and(warp::filters::host::optional())
+        .and(warp::filters::header::headers_cloned())
+        .and(warp::any().map(move || cloned_rate_limiter.clone()))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::bytes())
+        .and(warp::any().map(move || cloned_our.clone()))
+        .and(warp::any().map(move || encoded_keyfile.clone()))
+        .and(warp::any().map(move || cloned_jwt_secret.clone()))
+        .and(warp::any().map(move || cloned_totp.clone()))
+        .and(warp::any().map(move || cloned_print_tx.clone()))
+        .and_then(password_handler);
+
+    // filter to enroll in, confirm, or disable a TOTP second factor for login
+    let cloned_our = our.clone();
+    let cloned_jwt_secret = jwt_secret.clone();
+    let cloned_print_tx = print_tx.clone();
+    let totp_route = warp::path!("login" / "totp")
+        .and(warp::filters::method::method())
+        .and(warp::filters::host::optional())
+        .and(warp::filters::header::headers_cloned())
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::bytes())
+        .and(warp::any().map(move || cloned_our.clone()))
+        .and(warp::any().map(move || cloned_jwt_secret.clone()))
+        .and(warp::any().map(move || totp.clone()))
+        .and(warp::any().map(move || cloned_print_tx.clone()))
+        .and_then(totp_handler);
+
+    // filter to let the web UI silently refresh its auth token before it expires
+    let cloned_our = our.clone();
+    let cloned_jwt_secret = jwt_secret.clone();
+    let refresh = warp::path!("login" / "refresh")
+        .and(warp::post())
+        .and(warp::filters::host::optional())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::filters::header::headers_cloned())
+        .and(warp::any().map(move || cloned_our.clone()))
+        .and(warp::any().map(move || cloned_jwt_secret.clone()))
+        .and_then(refresh_handler);
+
+    // filter to issue and redeem pairing tokens for companion clients
+    let pairing_tokens: PairingTokens = Arc::new(DashMap::new());
+    let cloned_our = our.clone();
+    let cloned_jwt_secret = jwt_secret.clone();
+    let cloned_pairing_tokens = pairing_tokens.clone();
+    let cloned_our_2 = our.clone();
+    let cloned_jwt_secret_2 = jwt_secret.clone();
+    let cloned_print_tx = print_tx.clone();
+    let cloned_rate_limiter = rate_limiter.clone();
+    let pair = warp::path("pair").and(warp::path::end()).and(
+        warp::get()
+            .and(warp::filters::host::optional())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(warp::filters::header::headers_cloned())
+            .and(warp::any().map(move || cloned_our.clone()))
+            .and(warp::any().map(move || cloned_jwt_secret.clone()))
+            .and(warp::any().map(move || cloned_pairing_tokens.clone()))
+            .and_then(pair_start_handler)
+            .or(warp::post()
+                .and(remote_addr())
+                .and(warp::filters::header::headers_cloned())
+                .and(warp::any().map(move || cloned_rate_limiter.clone()))
+                .and(warp::body::content_length_limit(1024 * 16))
+                .and(warp::body::bytes())
+                .and(warp::any().map(move || cloned_our_2.clone()))
+                .and(warp::any().map(move || cloned_jwt_secret_2.clone()))
+                .and(warp::any().map(move || pairing_tokens.clone()))
+                .and(warp::any().map(move || cloned_print_tx.clone()))
+                .and_then(pair_complete_handler)),
+    );
+
     // filter to receive all other HTTP requests
     let filter = warp::filters::method::method()
-        .and(warp::addr::remote())
+        .and(remote_addr())
         .and(warp::filters::host::optional())
         .and(warp::path::full())
         .and(warp::query::<HashMap<String, String>>())
+        .and(raw_query())
         .and(warp::filters::header::headers_cloned())
         .and(warp::filters::body::bytes())
         .and(warp::any().map(move || our.clone()))
         .and(warp::any().map(move || http_response_senders.clone()))
         .and(warp::any().map(move || path_bindings.clone()))
-        .and(warp::any().map(move || jwt_secret_bytes.clone()))
+        .and(warp::any().map(move || jwt_secret.clone()))
         .and(warp::any().map(move || send_to_loop.clone()))
         .and(warp::any().map(move || print_tx.clone()))
         .and(warp::any().map(move || login_html.clone()))
+        .and(warp::any().map(move || rate_limiter.clone()))
+        .and(warp::any().map(move || api_tokens.clone()))
+        .and(warp::any().map(move || http_client.clone()))
+        .and(warp::any().map(move || middleware_senders.clone()))
+        .and(warp::any().map(move || middlewares.clone()))
+        .and(warp::any().map(move || domains.clone()))
+        .and(warp::any().map(move || dormant_processes.clone()))
         .and_then(http_handler);
 
-    let filter_with_ws = ws_route.or(login).or(filter);
+    let filter_with_ws = rate_limited
+        .and(
+            ws_route
+                .or(login)
+                .or(refresh)
+                .or(password)
+                .or(totp_route)
+                .or(pair)
+                .or(event_stream_route)
+                .or(filter),
+        )
+        .recover(handle_rejection)
+        .with(warp::log::custom(move |info| {
+            if let Some(access_log) = &access_log {
+                access_log.record(access_log_entry(
+                    &info,
+                    &log_our,
+                    &log_jwt_secret,
+                    &log_api_tokens,
+                ));
+            }
+        }));
+    if let Some(https_port) = https_port {
+        tokio::spawn(serve_tls(
+            https_port,
+            tls_domains,
+            warp::service(filter_with_ws.clone()),
+            tls_print_tx,
+        ));
+    }
     warp::serve(filter_with_ws)
         .run(([0, 0, 0, 0], our_port))
         .await;
 }
 
+/// Serve over TLS, for the domains set with a certificate, choosing the
+/// certificate of each connection by the host that the client asks for.
+async fn serve_tls<S>(
+    https_port: u16,
+    domains: Arc<domains::Domains>,
+    service: S,
+    print_tx: PrintSender,
+) where
+    S: warp::hyper::service::Service<
+            warp::hyper::Request<warp::hyper::Body>,
+            Response = warp::reply::Response,
+            Error = std::convert::Infallible,
+        > + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let (acceptor, listener) = match domains::tls_acceptor(domains) {
+        Ok(acceptor) => match tokio::net::TcpListener::bind(("0.0.0.0", https_port)).await {
+            Ok(listener) => (acceptor, listener),
+            Err(e) => {
+                Printout::new(
+                    0,
+                    HTTP_SERVER_PROCESS_ID.clone(),
+                    format!("http-server: failed to bind HTTPS port {https_port}: {e}"),
+                )
+                .send(&print_tx)
+                .await;
+                return;
+            }
+        },
+        Err(e) => {
+            Printout::new(
+                0,
+                HTTP_SERVER_PROCESS_ID.clone(),
+                format!("http-server: failed to configure TLS: {e}"),
+            )
+            .send(&print_tx)
+            .await;
+            return;
+        }
+    };
+    loop {
+        let Ok((stream, peer_addr)) = listener.accept().await else {
+            continue;
+        };
+        let acceptor = acceptor.clone();
+        let service = service.clone();
+        // handshakes are done apart from the accept loop, so a slow client cannot hold it up
+        tokio::spawn(async move {
+            let Ok(Ok(stream)) = tokio::time::timeout(
+                std::time::Duration::from_secs(TLS_HANDSHAKE_TIMEOUT),
+                acceptor.accept(stream),
+            )
+            .await
+            else {
+                return;
+            };
+            let peer_addr = HeaderValue::from_str(&peer_addr.to_string()).unwrap();
+            let service = warp::hyper::service::service_fn(
+                move |mut request: warp::hyper::Request<warp::hyper::Body>| {
+                    request
+                        .headers_mut()
+                        .insert(PEER_ADDR_HEADER, peer_addr.clone());
+                    let mut service = service.clone();
+                    warp::hyper::service::Service::call(&mut service, request)
+                },
+            );
+            let _ = warp::hyper::server::conn::Http::new()
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await;
+        });
+    }
+}
+
+/// The address of the client: the peer address of the connection,
+/// which for TLS connections is passed in [`PEER_ADDR_HEADER`].
+fn remote_addr(
+) -> impl Filter<Extract = (Option<SocketAddr>,), Error = std::convert::Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::filters::header::headers_cloned())
+        .map(
+            |remote: Option<SocketAddr>, headers: warp::http::HeaderMap| {
+                remote.or_else(|| headers.get(PEER_ADDR_HEADER)?.to_str().ok()?.parse().ok())
+            },
+        )
+}
+
+/// Describe a served request for the access log. The user is the node if the
+/// request has a valid auth token, or else the API token it presents, if any.
+fn access_log_entry(
+    info: &warp::log::Info,
+    our: &str,
+    jwt_secret: &utils::JwtSecret,
+    api_tokens: &utils::ApiTokens,
+) -> AccessLogEntry {
+    let headers = utils::serialize_headers(info.request_headers());
+    let socket_addr = info.remote_addr().or_else(|| {
+        info.request_headers()
+            .get(PEER_ADDR_HEADER)?
+            .to_str()
+            .ok()?
+            .parse()
+            .ok()
+    });
+    let source_ip = utils::client_ip(socket_addr, &headers).or(socket_addr.map(|addr| addr.ip()));
+    let user = if utils::authenticate(our, None, &headers, &jwt_secret.get()).is_some() {
+        Some(our.to_string())
+    } else {
+        api_tokens
+            .name(&headers)
+            .map(|name| format!("api-token:{name}"))
+    };
+    AccessLogEntry {
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64,
+        method: info.method().to_string(),
+        host: info.host().map(str::to_string),
+        path: info.path().to_string(),
+        status: info.status().as_u16(),
+        latency_ms: info.elapsed().as_millis() as u64,
+        source_ip: source_ip.map(|ip| ip.to_string()),
+        user,
+    }
+}
+
+/// the raw query string of a request, which is empty if there is none
+fn raw_query() -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
+    warp::query::raw().or(warp::any().map(String::new)).unify()
+}
+
 /// handle non-GET requests on /login. if POST, validate password
 /// and return auth token, which will be stored in a cookie.
 ///
 /// if redirect is provided in URL, such as ?redirect=/chess:chess:sys/,
 /// the browser will be redirected to that path after successful login.
 async fn login_handler(
+    socket_addr: Option<SocketAddr>,
+    headers: warp::http::HeaderMap,
+    rate_limiter: Arc<utils::RateLimiter>,
     host: Option<warp::host::Authority>,
     query_params: HashMap<String, String>,
     body: warp::hyper::body::Bytes,
     our: Arc<String>,
-    encoded_keyfile: Arc<Vec<u8>>,
+    encoded_keyfile: Arc<utils::EncodedKeyfile>,
+    jwt_secret: Arc<utils::JwtSecret>,
+    totp: Arc<totp::Totp>,
+    send_to_loop: MessageSender,
 ) -> Result<impl warp::Reply, warp::Rejection> {
+    let client_ip = utils::client_ip(socket_addr, &utils::serialize_headers(&headers));
+    if let Some(ip) = client_ip {
+        if let Err(retry_after) = rate_limiter.check(ip, "login", LOGIN_RATE_LIMIT) {
+            return Ok(too_many_requests(retry_after));
+        }
+    }
+
     let Ok(info) = serde_json::from_slice::<LoginInfo>(&body) else {
         return Ok(warp::reply::with_status(
             warp::reply::json(&"Failed to parse login info"),
@@ -339,23 +825,27 @@ async fn login_handler(
     let info = LoginInfo {
         password_hash: "secret".to_string(),
         subdomain: info.subdomain,
+        totp: info.totp,
     };
 
+    let encoded_keyfile = encoded_keyfile.get().await;
     match keygen::decode_keyfile(&encoded_keyfile, &info.password_hash) {
-        Ok(keyfile) => {
-            let token = match keygen::generate_jwt(
-                &keyfile.jwt_secret_bytes,
-                our.as_ref(),
-                &info.subdomain,
-            ) {
-                Some(token) => token,
-                None => {
-                    return Ok(warp::reply::with_status(
-                        warp::reply::json(&"Failed to generate JWT"),
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                    )
-                    .into_response())
-                }
+        Ok(_keyfile) => {
+            // the second factor is only checked once the password is known to be right
+            if totp.enabled() && !totp.verify(info.totp.as_deref().unwrap_or_default()).await {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&TOTP_REQUIRED),
+                    StatusCode::UNAUTHORIZED,
+                )
+                .into_response());
+            }
+
+            let Some(token) = jwt_secret.issue(our.as_ref(), &info.subdomain) else {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&"Failed to generate JWT"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                )
+                .into_response());
             };
 
             let mut response = if let Some(redirect) = query_params.get("redirect") {
@@ -368,61 +858,60 @@ async fn login_handler(
                 .into_response()
             };
 
-            let cookie = match info.subdomain.unwrap_or_default().as_str() {
-                "" => format!("kinode-auth_{our}={token};"),
-                subdomain => {
-                    // enforce that subdomain string only contains a-z, 0-9, ., :, and -
-                    let subdomain = subdomain
-                        .chars()
-                        .filter(|c| {
-                            c.is_ascii_alphanumeric() || c == &'-' || c == &':' || c == &'.'
-                        })
-                        .collect::<String>();
-                    format!("kinode-auth_{our}@{subdomain}={token};")
-                }
-            };
-
-            match HeaderValue::from_str(&cookie) {
-                Ok(v) => {
-                    response.headers_mut().append(SET_COOKIE, v);
-                    response
-                        .headers_mut()
-                        .append("HttpOnly", HeaderValue::from_static("true"));
-                    response
-                        .headers_mut()
-                        .append("Secure", HeaderValue::from_static("true"));
-                    response
-                        .headers_mut()
-                        .append("SameSite", HeaderValue::from_static("Strict"));
+            if let Err(e) = set_auth_cookies(
+                &mut response,
+                &our,
+                &info.subdomain.unwrap_or_default(),
+                &token,
+                &jwt_secret,
+            ) {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&e),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                )
+                .into_response());
+            }
 
-                    if let Some(redirect) = query_params.get("redirect") {
-                        // get http/https from request headers
-                        let proto = match response.headers().get("X-Forwarded-Proto") {
-                            Some(proto) => proto.to_str().unwrap_or("http").to_string(),
-                            None => "http".to_string(),
-                        };
+            if let Some(redirect) = query_params.get("redirect") {
+                // get http/https from request headers
+                let proto = match response.headers().get("X-Forwarded-Proto") {
+                    Some(proto) => proto.to_str().unwrap_or("http").to_string(),
+                    None => "http".to_string(),
+                };
 
-                        response.headers_mut().append(
-                            "Location",
-                            HeaderValue::from_str(&format!(
-                                "{proto}://{}{redirect}",
-                                host.unwrap()
-                            ))
-                            .unwrap(),
-                        );
-                        response
-                            .headers_mut()
-                            .append("Content-Length", HeaderValue::from_str("0").unwrap());
-                    }
+                response.headers_mut().append(
+                    "Location",
+                    HeaderValue::from_str(&format!("{proto}://{}{redirect}", host.unwrap()))
+                        .unwrap(),
+                );
+                response
+                    .headers_mut()
+                    .append("Content-Length", HeaderValue::from_str("0").unwrap());
+            }
 
-                    Ok(response)
-                }
-                Err(e) => Ok(warp::reply::with_status(
-                    warp::reply::json(&format!("Failed to generate Auth JWT: {e}")),
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                )
-                .into_response()),
+            // notify checks whether we have logged in from this IP before
+            if let Some(ip) = client_ip {
+                KernelMessage::builder()
+                    .id(rand::random())
+                    .source((our.as_str(), HTTP_SERVER_PROCESS_ID.clone()))
+                    .target((our.as_str(), NOTIFY_PROCESS_ID.clone()))
+                    .message(Message::Request(Request {
+                        inherit: false,
+                        expects_response: None,
+                        body: serde_json::to_vec(&NotifyAction::Event(NotifyEvent::Login {
+                            ip: ip.to_string(),
+                        }))
+                        .unwrap(),
+                        metadata: None,
+                        capabilities: vec![],
+                    }))
+                    .build()
+                    .unwrap()
+                    .send(&send_to_loop)
+                    .await;
             }
+
+            Ok(response)
         }
         Err(e) => Ok(warp::reply::with_status(
             warp::reply::json(&format!("Failed to decode keyfile: {e}")),
@@ -432,20 +921,527 @@ async fn login_handler(
     }
 }
 
+/// Set the auth cookie, along with the CSRF cookie derived from it, for a
+/// newly-issued auth token. The auth cookie expires along with the token.
+fn set_auth_cookies(
+    response: &mut warp::reply::Response,
+    our: &str,
+    subdomain: &str,
+    token: &str,
+    jwt_secret: &utils::JwtSecret,
+) -> Result<(), String> {
+    let Some(csrf_token) = utils::generate_csrf_token(token, &jwt_secret.get()) else {
+        return Err("Failed to generate CSRF token".to_string());
+    };
+    let max_age = jwt_secret.lifetime();
+
+    let (cookie, csrf_cookie) = match subdomain {
+        "" => (
+            format!("kinode-auth_{our}={token}; Max-Age={max_age};"),
+            format!("kinode-csrf_{our}={csrf_token}; Path=/; SameSite=Strict"),
+        ),
+        subdomain => {
+            // enforce that subdomain string only contains a-z, 0-9, ., :, and -
+            let subdomain = subdomain
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || c == &'-' || c == &':' || c == &'.')
+                .collect::<String>();
+            (
+                format!("kinode-auth_{our}@{subdomain}={token}; Max-Age={max_age};"),
+                format!("kinode-csrf_{our}@{subdomain}={csrf_token}; Path=/; SameSite=Strict"),
+            )
+        }
+    };
+
+    match (
+        HeaderValue::from_str(&cookie),
+        HeaderValue::from_str(&csrf_cookie),
+    ) {
+        (Ok(v), Ok(csrf_v)) => {
+            response.headers_mut().append(SET_COOKIE, v);
+            // not HttpOnly: frontends read this to set the X-CSRF-Token header
+            response.headers_mut().append(SET_COOKIE, csrf_v);
+            response
+                .headers_mut()
+                .append("HttpOnly", HeaderValue::from_static("true"));
+            response
+                .headers_mut()
+                .append("Secure", HeaderValue::from_static("true"));
+            response
+                .headers_mut()
+                .append("SameSite", HeaderValue::from_static("Strict"));
+            Ok(())
+        }
+        (Err(e), _) | (_, Err(e)) => Err(format!("Failed to generate Auth JWT: {e}")),
+    }
+}
+
+/// handle POST requests on /login/refresh. a logged-in web UI calls this
+/// periodically to swap its auth token for a fresh one before it expires,
+/// so that the user is only asked to log in again after being away for
+/// longer than the token lifetime.
+///
+/// if subdomain is provided in URL, such as ?subdomain=chess:chess:sys,
+/// the token for that secure subdomain is refreshed.
+async fn refresh_handler(
+    host: Option<warp::host::Authority>,
+    query_params: HashMap<String, String>,
+    headers: warp::http::HeaderMap,
+    our: Arc<String>,
+    jwt_secret: Arc<utils::JwtSecret>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let serialized_headers = utils::serialize_headers(&headers);
+    let subdomain = match query_params.get("subdomain") {
+        None => None,
+        Some(subdomain) => match subdomain.parse::<ProcessId>() {
+            Ok(process) => Some(process),
+            Err(_) => {
+                return Ok(warp::reply::with_status(vec![], StatusCode::BAD_REQUEST).into_response())
+            }
+        },
+    };
+    let jwt_secret_bytes = jwt_secret.get();
+    // only cookies are refreshed: clients presenting a token in a header manage it themselves
+    let host = host.unwrap_or(warp::host::Authority::from_static("localhost"));
+    if utils::authenticate(
+        &our,
+        subdomain.as_ref(),
+        &serialized_headers,
+        &jwt_secret_bytes,
+    ) != Some(utils::AuthSource::Cookie)
+        || !utils::csrf_check_passes(
+            &our,
+            subdomain.as_ref(),
+            host.as_str(),
+            &serialized_headers,
+            &jwt_secret_bytes,
+        )
+    {
+        return Ok(warp::reply::with_status(vec![], StatusCode::UNAUTHORIZED).into_response());
+    }
+
+    let subdomain = subdomain.map(|process| process.to_string());
+    let Some(token) = jwt_secret.issue(our.as_ref(), &subdomain) else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Failed to generate JWT"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response());
+    };
+    let mut response = warp::reply::with_status(
+        warp::reply::json(&(chrono::Utc::now().timestamp() as u64 + jwt_secret.lifetime())),
+        StatusCode::OK,
+    )
+    .into_response();
+    if let Err(e) = set_auth_cookies(
+        &mut response,
+        &our,
+        &subdomain.unwrap_or_default(),
+        &token,
+        &jwt_secret,
+    ) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&e),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response());
+    }
+    Ok(response)
+}
+
+/// handle requests on /login/totp from a logged-in user:
+/// - GET generates a new TOTP secret to enroll, as a [`totp::TotpSetup`].
+/// - POST confirms the enrollment with `{"code": ...}` from the authenticator
+///   app, enabling the second factor and returning the recovery codes.
+///   replacing an existing enrollment also takes `{"current_code": ...}`.
+/// - DELETE disables the second factor, given `{"code": ...}`.
+async fn totp_handler(
+    method: warp::http::Method,
+    host: Option<warp::host::Authority>,
+    headers: warp::http::HeaderMap,
+    body: warp::hyper::body::Bytes,
+    our: Arc<String>,
+    jwt_secret: Arc<utils::JwtSecret>,
+    totp: Arc<totp::Totp>,
+    print_tx: PrintSender,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let serialized_headers = utils::serialize_headers(&headers);
+    let jwt_secret_bytes = jwt_secret.get();
+    let host = host.unwrap_or(warp::host::Authority::from_static("localhost"));
+    let Some(auth_source) = utils::authenticate(&our, None, &serialized_headers, &jwt_secret_bytes)
+    else {
+        return Ok(warp::reply::with_status(vec![], StatusCode::UNAUTHORIZED).into_response());
+    };
+    if utils::is_state_changing(&method)
+        && !auth_source.csrf_check_passes(
+            &our,
+            None,
+            host.as_str(),
+            &serialized_headers,
+            &jwt_secret_bytes,
+        )
+    {
+        return Ok(warp::reply::with_status(vec![], StatusCode::FORBIDDEN).into_response());
+    }
+
+    if method == warp::http::Method::GET {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&totp.begin_enrollment(&our)),
+            StatusCode::OK,
+        )
+        .into_response());
+    }
+    let Ok(code) = serde_json::from_slice::<totp::TotpCode>(&body) else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Failed to parse TOTP code"),
+            StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    };
+    if method == warp::http::Method::POST {
+        let Some(recovery_codes) = totp
+            .complete_enrollment(&code.code, code.current_code.as_deref())
+            .await
+        else {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"Invalid or expired TOTP code"),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response());
+        };
+        Printout::new(
+            0,
+            HTTP_SERVER_PROCESS_ID.clone(),
+            "http-server: enabled TOTP second factor for login",
+        )
+        .send(&print_tx)
+        .await;
+        Ok(
+            warp::reply::with_status(warp::reply::json(&recovery_codes), StatusCode::OK)
+                .into_response(),
+        )
+    } else if method == warp::http::Method::DELETE {
+        if !totp.disable(&code.code).await {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"Invalid TOTP code"),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response());
+        }
+        Printout::new(
+            0,
+            HTTP_SERVER_PROCESS_ID.clone(),
+            "http-server: disabled TOTP second factor for login",
+        )
+        .send(&print_tx)
+        .await;
+        Ok(warp::reply::with_status(vec![], StatusCode::NO_CONTENT).into_response())
+    } else {
+        Ok(warp::reply::with_status(vec![], StatusCode::METHOD_NOT_ALLOWED).into_response())
+    }
+}
+
+/// handle POST requests on /login/password from a logged-in user, changing the
+/// node password given a [`PasswordChange`]. the old password, and a TOTP code
+/// if enrolled, confirm the change, which re-encrypts the keyfile on disk.
+async fn password_handler(
+    socket_addr: Option<SocketAddr>,
+    host: Option<warp::host::Authority>,
+    headers: warp::http::HeaderMap,
+    rate_limiter: Arc<utils::RateLimiter>,
+    body: warp::hyper::body::Bytes,
+    our: Arc<String>,
+    encoded_keyfile: Arc<utils::EncodedKeyfile>,
+    jwt_secret: Arc<utils::JwtSecret>,
+    totp: Arc<totp::Totp>,
+    print_tx: PrintSender,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let serialized_headers = utils::serialize_headers(&headers);
+    // the old password can be guessed here as at a login, so is guarded as strictly
+    if let Some(ip) = utils::client_ip(socket_addr, &serialized_headers) {
+        if let Err(retry_after) = rate_limiter.check(ip, "login", LOGIN_RATE_LIMIT) {
+            return Ok(too_many_requests(retry_after));
+        }
+    }
+    let jwt_secret_bytes = jwt_secret.get();
+    let host = host.unwrap_or(warp::host::Authority::from_static("localhost"));
+    let Some(auth_source) = utils::authenticate(&our, None, &serialized_headers, &jwt_secret_bytes)
+    else {
+        return Ok(warp::reply::with_status(vec![], StatusCode::UNAUTHORIZED).into_response());
+    };
+    if !auth_source.csrf_check_passes(
+        &our,
+        None,
+        host.as_str(),
+        &serialized_headers,
+        &jwt_secret_bytes,
+    ) {
+        return Ok(warp::reply::with_status(vec![], StatusCode::FORBIDDEN).into_response());
+    }
+
+    // logins to a simulated node always use the same password
+    if cfg!(feature = "simulation-mode") {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Can't change the password of a simulated node"),
+            StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    }
+
+    let Ok(change) = serde_json::from_slice::<PasswordChange>(&body) else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Failed to parse password change"),
+            StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    };
+    if change.new_password_hash.is_empty() || change.new_password_hash == change.old_password_hash {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"New password must differ from the old one"),
+            StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    }
+    if totp.enabled()
+        && !totp
+            .verify(change.totp.as_deref().unwrap_or_default())
+            .await
+    {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&TOTP_REQUIRED),
+            StatusCode::UNAUTHORIZED,
+        )
+        .into_response());
+    }
+
+    match encoded_keyfile
+        .change_password(change.old_password_hash, change.new_password_hash)
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&"Incorrect password"),
+                StatusCode::FORBIDDEN,
+            )
+            .into_response());
+        }
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&format!("Failed to save keyfile: {e}")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response());
+        }
+    }
+    Printout::new(
+        0,
+        HTTP_SERVER_PROCESS_ID.clone(),
+        "http-server: changed node password",
+    )
+    .send(&print_tx)
+    .await;
+
+    let mut response = warp::reply::with_status(vec![], StatusCode::NO_CONTENT).into_response();
+    if !change.log_out_everywhere {
+        return Ok(response);
+    }
+    if let Err(e) = jwt_secret.rotate().await {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&format!("Changed password, but failed to log out: {e}")),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response());
+    }
+    // keep the browser that made the change logged in
+    if auth_source == utils::AuthSource::Cookie {
+        let token = jwt_secret.issue(our.as_ref(), &None);
+        if let Some(Err(e)) =
+            token.map(|token| set_auth_cookies(&mut response, &our, "", &token, &jwt_secret))
+        {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&e),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response());
+        }
+    }
+    Ok(response)
+}
+
+/// handle GET requests on /pair. a logged-in user receives a single-use
+/// pairing token, which is meant to be displayed as a QR code and scanned
+/// by a companion client that then redeems it with a POST to /pair.
+/// `?subdomain={process}` scopes the pairing to that process, and `?qr=svg`
+/// returns the payload rendered as a QR code rather than as JSON.
+async fn pair_start_handler(
+    host: Option<warp::host::Authority>,
+    query_params: HashMap<String, String>,
+    headers: warp::http::HeaderMap,
+    our: Arc<String>,
+    jwt_secret: Arc<utils::JwtSecret>,
+    pairing_tokens: PairingTokens,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let serialized_headers = utils::serialize_headers(&headers);
+    if utils::authenticate(&our, None, &serialized_headers, &jwt_secret.get()).is_none() {
+        return Ok(warp::reply::with_status(vec![], StatusCode::UNAUTHORIZED).into_response());
+    }
+
+    let subdomain = match query_params.get("subdomain").map(String::as_str) {
+        None | Some("") => None,
+        Some(subdomain) => match subdomain.parse::<ProcessId>() {
+            Ok(process) => Some(process.to_string()),
+            Err(_) => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&"Pairing subdomain must be a valid process ID"),
+                    StatusCode::BAD_REQUEST,
+                )
+                .into_response())
+            }
+        },
+    };
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    // prune any tokens that were never redeemed
+    pairing_tokens.retain(|_, (expiration, _)| *expiration > now);
+
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    let expiration = now + PAIRING_TOKEN_TTL;
+    pairing_tokens.insert(token.clone(), (expiration, subdomain.clone()));
+
+    let proto = match headers.get("X-Forwarded-Proto") {
+        Some(proto) => proto.to_str().unwrap_or("http"),
+        None => "http",
+    };
+    let host = host.unwrap_or(warp::host::Authority::from_static("localhost"));
+
+    let payload = PairingPayload {
+        node: our.to_string(),
+        url: format!("{proto}://{host}"),
+        token,
+        expiration,
+        subdomain,
+    };
+
+    if query_params.get("qr").map(String::as_str) == Some("svg") {
+        let qr = match qrcode::QrCode::new(serde_json::to_vec(&payload).unwrap()) {
+            Ok(qr) => qr,
+            Err(_) => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&"Failed to generate pairing QR code"),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                )
+                .into_response())
+            }
+        };
+        let svg = qr
+            .render::<qrcode::render::svg::Color>()
+            .min_dimensions(256, 256)
+            .build();
+        return Ok(warp::reply::with_header(
+            warp::reply::with_header(svg, "Content-Type", "image/svg+xml"),
+            "Cache-Control",
+            "no-store",
+        )
+        .into_response());
+    }
+
+    Ok(warp::reply::with_status(warp::reply::json(&payload), StatusCode::OK).into_response())
+}
+
+/// handle POST requests on /pair. if the pairing token is valid and
+/// unexpired, consume it and return an auth token for the companion client,
+/// scoped to whatever subdomain the token was issued for.
+async fn pair_complete_handler(
+    socket_addr: Option<SocketAddr>,
+    headers: warp::http::HeaderMap,
+    rate_limiter: Arc<utils::RateLimiter>,
+    body: warp::hyper::body::Bytes,
+    our: Arc<String>,
+    jwt_secret: Arc<utils::JwtSecret>,
+    pairing_tokens: PairingTokens,
+    print_tx: PrintSender,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // pairing tokens are as good as a password, so are guarded as strictly
+    if let Some(ip) = utils::client_ip(socket_addr, &utils::serialize_headers(&headers)) {
+        if let Err(retry_after) = rate_limiter.check(ip, "login", LOGIN_RATE_LIMIT) {
+            return Ok(too_many_requests(retry_after));
+        }
+    }
+
+    let Ok(pairing) = serde_json::from_slice::<PairingRequest>(&body) else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Failed to parse pairing request"),
+            StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    };
+
+    let Some((_token, (expiration, subdomain))) = pairing_tokens.remove(&pairing.token) else {
+        return Ok(warp::reply::with_status(vec![], StatusCode::UNAUTHORIZED).into_response());
+    };
+    if expiration <= chrono::Utc::now().timestamp() as u64 {
+        return Ok(warp::reply::with_status(vec![], StatusCode::UNAUTHORIZED).into_response());
+    }
+
+    let Some(auth_token) = jwt_secret.issue(our.as_ref(), &subdomain) else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Failed to generate JWT"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response());
+    };
+
+    Printout::new(
+        0,
+        HTTP_SERVER_PROCESS_ID.clone(),
+        format!(
+            "http-server: paired new client \"{}\"{}",
+            pairing.client_name,
+            match subdomain {
+                Some(ref subdomain) => format!(" scoped to {subdomain}"),
+                None => String::new(),
+            }
+        ),
+    )
+    .send(&print_tx)
+    .await;
+
+    let cookie_name = match subdomain {
+        Some(subdomain) => format!("kinode-auth_{our}@{subdomain}"),
+        None => format!("kinode-auth_{our}"),
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&PairingResponse {
+            node: our.to_string(),
+            cookie_name,
+            auth_token,
+        }),
+        StatusCode::OK,
+    )
+    .into_response())
+}
+
 async fn ws_handler(
     ws_connection: Ws,
     socket_addr: Option<SocketAddr>,
     path: warp::path::FullPath,
+    raw_query: String,
     host: Option<warp::host::Authority>,
     headers: warp::http::HeaderMap,
     our: Arc<String>,
-    jwt_secret_bytes: Arc<Vec<u8>>,
+    jwt_secret: Arc<utils::JwtSecret>,
     ws_senders: WebSocketSenders,
     ws_path_bindings: WsPathBindings,
     send_to_loop: MessageSender,
     print_tx: PrintSender,
+    http_client: reqwest::Client,
+    api_tokens: Arc<utils::ApiTokens>,
+    domains: Arc<domains::Domains>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let original_path = utils::normalize_path(path.as_str());
+    let routed_path = domains.route(host.as_ref().map(|host| host.host()), path.as_str());
+    let original_path = utils::normalize_path(routed_path.as_deref().unwrap_or(path.as_str()));
     Printout::new(
         2,
         HTTP_SERVER_PROCESS_ID.clone(),
@@ -469,6 +1465,7 @@ async fn ws_handler(
     }
 
     let serialized_headers = utils::serialize_headers(&headers);
+    let jwt_secret_bytes = jwt_secret.get();
 
     let ws_path_bindings = ws_path_bindings.read().await;
     let Ok(route) = ws_path_bindings.recognize(original_path) else {
@@ -480,7 +1477,15 @@ async fn ws_handler(
         return Err(warp::reject::not_found());
     };
 
-    if bound_path.authenticated {
+    let require_api_token = bound_path
+        .authorization
+        .as_ref()
+        .is_some_and(|authorization| authorization.require_api_token);
+    if require_api_token {
+        if !api_tokens.authorize(&serialized_headers, original_path, &warp::http::Method::GET) {
+            return Err(warp::reject::not_found());
+        }
+    } else if bound_path.authenticated {
         let Some(auth_token) = serialized_headers.get("cookie") else {
             return Err(warp::reject::not_found());
         };
@@ -493,7 +1498,13 @@ async fn ws_handler(
             };
             // parse out subdomain from host (there can only be one)
             let request_subdomain = host.host().split('.').next().unwrap_or("");
+            // browsers send the auth cookie along with handshakes from pages on
+            // other subdomains too, so also require the page to be this app's own
+            let origin_matches = serialized_headers.get("origin").map_or(true, |origin| {
+                origin.split("://").nth(1) == Some(host.as_str())
+            });
             if request_subdomain != subdomain
+                || !origin_matches
                 || !utils::auth_token_valid(&our, Some(&app), auth_token, &jwt_secret_bytes)
             {
                 return Err(warp::reject::not_found());
@@ -505,6 +1516,22 @@ async fn ws_handler(
         }
     }
 
+    if let Some(authorization) = &bound_path.authorization {
+        if !authorization.channel_tokens.is_empty() {
+            let channel_token = url::form_urlencoded::parse(raw_query.as_bytes())
+                .find(|(key, _)| key == "channel_token")
+                .map(|(_, value)| value);
+            if !channel_token.is_some_and(|token| {
+                authorization
+                    .channel_tokens
+                    .iter()
+                    .any(|allowed| allowed.as_str() == token)
+            }) {
+                return Err(warp::reject::not_found());
+            }
+        }
+    }
+
     let is_local = socket_addr
         .map(|addr| addr.ip().is_loopback())
         .unwrap_or(false);
@@ -515,6 +1542,21 @@ async fn ws_handler(
 
     let extension = bound_path.extension;
 
+    if bound_path.local_only && !is_local {
+        return Err(warp::reject::not_found());
+    }
+
+    if let Some(proxy) = bound_path.proxy.clone() {
+        let url = proxy::upstream_url(&proxy, route.params().find("rest"), &raw_query);
+        drop(ws_path_bindings);
+        let host = host.map(|host| host.to_string()).unwrap_or_default();
+        return Ok(ws_connection
+            .on_upgrade(move |ws: WebSocket| async move {
+                proxy::forward_websocket(ws, &proxy, &our, url, &headers, socket_addr, &host).await;
+            })
+            .into_response());
+    }
+
     drop(ws_path_bindings);
 
     // stripping ProcessId from path
@@ -527,19 +1569,21 @@ async fn ws_handler(
             .trim_start_matches('/')
     );
 
-    Ok(ws_connection.on_upgrade(move |ws: WebSocket| async move {
-        maintain_websocket(
-            ws,
-            our.clone(),
-            app,
-            formatted_path,
-            ws_senders.clone(),
-            send_to_loop.clone(),
-            print_tx.clone(),
-            extension,
-        )
-        .await;
-    }))
+    Ok(ws_connection
+        .on_upgrade(move |ws: WebSocket| async move {
+            maintain_websocket(
+                ws,
+                our.clone(),
+                app,
+                formatted_path,
+                ws_senders.clone(),
+                send_to_loop.clone(),
+                print_tx.clone(),
+                extension,
+            )
+            .await;
+        })
+        .into_response())
 }
 
 async fn http_handler(
@@ -548,17 +1592,27 @@ async fn http_handler(
     host: Option<warp::host::Authority>,
     path: warp::path::FullPath,
     query_params: HashMap<String, String>,
+    raw_query: String,
     headers: warp::http::HeaderMap,
     body: warp::hyper::body::Bytes,
     our: Arc<String>,
     http_response_senders: HttpResponseSenders,
     path_bindings: PathBindings,
-    jwt_secret_bytes: Arc<Vec<u8>>,
+    jwt_secret: Arc<utils::JwtSecret>,
     send_to_loop: MessageSender,
     print_tx: PrintSender,
     login_html: Arc<String>,
+    rate_limiter: Arc<utils::RateLimiter>,
+    api_tokens: Arc<utils::ApiTokens>,
+    http_client: reqwest::Client,
+    middleware_senders: MiddlewareSenders,
+    middlewares: Middlewares,
+    domains: Arc<domains::Domains>,
+    dormant_processes: DormantProcesses,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let original_path = utils::normalize_path(path.as_str());
+    // requests to an app's own domain are for its paths
+    let routed_path = domains.route(host.as_ref().map(|host| host.host()), path.as_str());
+    let original_path = utils::normalize_path(routed_path.as_deref().unwrap_or(path.as_str()));
     let base_path = original_path.split('/').skip(1).next().unwrap_or("");
     Printout::new(
         2,
@@ -570,6 +1624,28 @@ async fn http_handler(
 
     let id: u64 = rand::random();
     let serialized_headers = utils::serialize_headers(&headers);
+    let jwt_secret_bytes = jwt_secret.get();
+
+    // a process that starts on demand binds its paths once started, so the
+    // first request for one of them starts it; the kernel is only asked to
+    // start processes that are waiting to be
+    if let Ok(process_id) = base_path.parse::<ProcessId>() {
+        if dormant_processes.contains(&process_id)
+            && !is_bound(&path_bindings, &original_path, base_path).await
+            && !wake_process(
+                &our,
+                process_id,
+                &original_path,
+                base_path,
+                &path_bindings,
+                &http_response_senders,
+                &send_to_loop,
+            )
+            .await
+        {
+            return Ok(warp::reply::with_status(vec![], StatusCode::NOT_FOUND).into_response());
+        }
+    }
 
     let path_bindings = path_bindings.read().await;
     let route = if let Ok(route) = path_bindings.recognize(&original_path) {
@@ -595,6 +1671,40 @@ async fn http_handler(
         return Ok(warp::reply::with_status(vec![], StatusCode::NOT_FOUND).into_response());
     };
 
+    if let (Some(rate_limit), Some(ip)) = (
+        bound_path.rate_limit,
+        utils::client_ip(socket_addr, &serialized_headers),
+    ) {
+        if let Err(retry_after) = rate_limiter.check(ip, &bound_path.path, rate_limit) {
+            return Ok(too_many_requests(retry_after));
+        }
+    }
+
+    // the CORS headers to add to the response, if the request comes from
+    // another origin that the path's policy allows
+    let cors_headers = match (&bound_path.cors, serialized_headers.get("origin")) {
+        (Some(policy), Some(origin)) if policy.allows_origin(origin) => {
+            // answer preflights before authenticating: browsers send them without credentials
+            if method == warp::http::Method::OPTIONS
+                && serialized_headers.contains_key("access-control-request-method")
+            {
+                let Some(preflight) =
+                    utils::cors_preflight_headers(policy, origin, &serialized_headers)
+                else {
+                    return Ok(
+                        warp::reply::with_status(vec![], StatusCode::FORBIDDEN).into_response()
+                    );
+                };
+                return Ok(with_headers(
+                    warp::reply::with_status(vec![], StatusCode::NO_CONTENT).into_response(),
+                    preflight,
+                ));
+            }
+            utils::cors_headers(policy, origin)
+        }
+        _ => vec![],
+    };
+
     let host = host.unwrap_or(warp::host::Authority::from_static("localhost"));
 
     if bound_path.authenticated {
@@ -638,31 +1748,59 @@ async fn http_handler(
                     .body(vec![])
                     .into_response());
             }
-            if !utils::auth_token_valid(
-                &our,
-                Some(&app),
-                serialized_headers.get("cookie").unwrap_or(&"".to_string()),
-                &jwt_secret_bytes,
-            ) {
-                // redirect to login page so they can get an auth token
-                return Ok(warp::http::Response::builder()
-                    .status(StatusCode::OK)
-                    .body(login_html.to_string())
-                    .into_response());
+        }
+        let subdomain = bound_path.secure_subdomain.as_ref().map(|_| app);
+        // failing that, the request may present an API token scoped to this path
+        let Some(auth_source) =
+            utils::authenticate(&our, subdomain, &serialized_headers, &jwt_secret_bytes).or_else(
+                || {
+                    api_tokens
+                        .authorize(&serialized_headers, &original_path, &method)
+                        .then_some(utils::AuthSource::Bearer)
+                },
+            )
+        else {
+            // clients that are not browsers, such as WebDAV mounts, cannot use the login page.
+            // WebDAV mounts are challenged to present an auth token as Basic credentials;
+            // no other path asks for them, so browsers never prompt for or cache them
+            if serialized_headers.contains_key("authorization")
+                || (method != warp::http::Method::GET && method != warp::http::Method::POST)
+            {
+                let mut response = warp::http::Response::builder().status(StatusCode::UNAUTHORIZED);
+                if *app == ProcessId::new(Some("webdav"), "settings", "sys") {
+                    response =
+                        response.header("WWW-Authenticate", format!("Basic realm=\"{our}\""));
+                }
+                return Ok(with_headers(
+                    response.body(vec![]).into_response(),
+                    cors_headers,
+                ));
             }
-        } else {
-            if !utils::auth_token_valid(
+            // redirect to login page so they can get an auth token
+            return Ok(warp::http::Response::builder()
+                .status(StatusCode::OK)
+                .body(login_html.to_string())
+                .into_response());
+        };
+        // cookies and Basic credentials are attached to cross-site requests by the
+        // browser, so state-changing requests authenticated by them must pass a CSRF check
+        if utils::is_state_changing(&method)
+            && !auth_source.csrf_check_passes(
                 &our,
-                None,
-                serialized_headers.get("cookie").unwrap_or(&"".to_string()),
+                subdomain,
+                host.as_str(),
+                &serialized_headers,
                 &jwt_secret_bytes,
-            ) {
-                // redirect to login page so they can get an auth token
-                return Ok(warp::http::Response::builder()
-                    .status(StatusCode::OK)
-                    .body(login_html.to_string())
-                    .into_response());
-            }
+            )
+        {
+            Printout::new(
+                1,
+                HTTP_SERVER_PROCESS_ID.clone(),
+                format!("http-server: rejecting {method} {original_path}: failed CSRF check"),
+            )
+            .send(&print_tx)
+            .await;
+            return Ok(warp::reply::with_status(vec![], StatusCode::FORBIDDEN).into_response());
         }
     }
 
@@ -674,27 +1812,132 @@ async fn http_handler(
         return Ok(warp::reply::with_status(vec![], StatusCode::FORBIDDEN).into_response());
     }
 
-    // if path has static content and this is a GET request, serve it
-    if method == warp::http::Method::GET {
-        if let Some(static_content) = &bound_path.static_content {
-            return Ok(warp::http::Response::builder()
-                .status(StatusCode::OK)
-                .header(
-                    "Content-Type",
-                    static_content
-                        .mime
-                        .as_ref()
-                        .unwrap_or(&"text/plain".to_string()),
-                )
-                .body(static_content.bytes.clone())
-                .into_response());
+    let app = app.clone();
+    // static content is only served for GET and HEAD requests
+    let static_content =
+        match method == warp::http::Method::GET || method == warp::http::Method::HEAD {
+            true => bound_path.static_content.clone(),
+            false => None,
+        };
+    let proxy = bound_path
+        .proxy
+        .clone()
+        .map(|proxy| (proxy, bound_path.path.clone()));
+    let compress = bound_path.compress;
+    let mut request = IncomingHttpRequest {
+        source_socket_addr: socket_addr.map(|addr| addr.to_string()),
+        method: method.to_string(),
+        url: format!(
+            "http://{}{}", // note that protocol is being lost here
+            host.host(),
+            original_path
+        ),
+        bound_path: bound_path.path.clone(),
+        headers: serialized_headers,
+        url_params: route
+            .params()
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        query_params,
+    };
+
+    // unlock to avoid deadlock with .write()s
+    drop(path_bindings);
+
+    let mut headers = headers;
+    let mut middleware_headers = HashMap::new();
+    if let Some(middleware) = find_middleware(&middlewares, &app, original_path) {
+        match run_middleware(
+            &our,
+            middleware,
+            request.clone(),
+            &body,
+            &middleware_senders,
+            &send_to_loop,
+        )
+        .await
+        {
+            Ok((
+                MiddlewareResponse::Continue {
+                    set_request_headers,
+                    set_response_headers,
+                },
+                _,
+            )) => {
+                for (name, value) in set_request_headers {
+                    let (Ok(header_name), Ok(header_value)) = (
+                        warp::http::header::HeaderName::from_bytes(name.as_bytes()),
+                        HeaderValue::from_str(&value),
+                    ) else {
+                        continue;
+                    };
+                    request.headers.insert(header_name.to_string(), value);
+                    headers.insert(header_name, header_value);
+                }
+                middleware_headers = set_response_headers;
+            }
+            Ok((MiddlewareResponse::Respond(http_response), body)) => {
+                return Ok(with_headers(
+                    build_response(http_response, body),
+                    cors_headers,
+                ));
+            }
+            Err(status) => {
+                return Ok(warp::reply::with_status(vec![], status).into_response());
+            }
         }
     }
+    let finish = move |response: warp::reply::Response| {
+        with_headers(
+            with_replaced_headers(response, &middleware_headers),
+            cors_headers,
+        )
+    };
+
+    // if path has static content and this is a GET or HEAD request, serve it
+    if let Some(static_content) = static_content {
+        return Ok(finish(serve_static_content(
+            &static_content,
+            &request.headers,
+            method == warp::http::Method::HEAD,
+            compress,
+        )));
+    }
+
+    if let Some((proxy, proxy_path)) = proxy {
+        let url = proxy::upstream_url(
+            &proxy,
+            request.url_params.get("rest").map(String::as_str),
+            &raw_query,
+        );
+        return Ok(finish(
+            proxy::forward_http(
+                &http_client,
+                &proxy,
+                &our,
+                &proxy_path,
+                url,
+                &method,
+                &headers,
+                body,
+                socket_addr,
+                host.as_str(),
+            )
+            .await,
+        ));
+    }
+
+    // negotiated now, since the request headers are passed on to the app
+    let encoding = match compress {
+        true => utils::negotiate_encoding(&request.headers),
+        false => None,
+    };
 
     // RPC functionality: if path is /rpc:distro:sys/message,
     // we extract message from base64 encoded bytes in data
     // and send it to the correct app.
-    let (message, is_fire_and_forget) = if app == &"rpc:distro:sys" {
+    let (message, is_fire_and_forget) = if app == "rpc:distro:sys" {
         match handle_rpc_message(our, id, body, print_tx).await {
             Ok((message, is_fire_and_forget)) => (message, is_fire_and_forget),
             Err(e) => {
@@ -703,11 +1946,6 @@ async fn http_handler(
         }
     } else {
         // otherwise, make a message to the correct app
-        let url_params: HashMap<String, String> = route
-            .params()
-            .into_iter()
-            .map(|(key, value)| (key.to_string(), value.to_string()))
-            .collect();
         (
             KernelMessage {
                 id,
@@ -717,26 +1955,13 @@ async fn http_handler(
                 },
                 target: Address {
                     node: our.to_string(),
-                    process: app.clone(),
+                    process: app,
                 },
                 rsvp: None,
                 message: Message::Request(Request {
                     inherit: false,
                     expects_response: Some(HTTP_SELF_IMPOSED_TIMEOUT),
-                    body: serde_json::to_vec(&HttpServerRequest::Http(IncomingHttpRequest {
-                        source_socket_addr: socket_addr.map(|addr| addr.to_string()),
-                        method: method.to_string(),
-                        url: format!(
-                            "http://{}{}", // note that protocol is being lost here
-                            host.host(),
-                            original_path
-                        ),
-                        bound_path: bound_path.path.clone(),
-                        headers: serialized_headers,
-                        url_params,
-                        query_params,
-                    }))
-                    .unwrap(),
+                    body: serde_json::to_vec(&HttpServerRequest::Http(request)).unwrap(),
                     metadata: None,
                     capabilities: vec![],
                 }),
@@ -749,9 +1974,6 @@ async fn http_handler(
         )
     };
 
-    // unlock to avoid deadlock with .write()s
-    drop(path_bindings);
-
     if is_fire_and_forget {
         message.send(&send_to_loop).await;
         return Ok(warp::reply::with_status(vec![], StatusCode::OK).into_response());
@@ -779,6 +2001,30 @@ async fn http_handler(
         }
     };
 
+    let (body, encoding) = match encoding {
+        Some(encoding) if utils::is_compressible_response(&http_response.headers, &body) => {
+            match encoding.compress(&body) {
+                Ok(compressed) => (compressed, Some(encoding)),
+                Err(_) => (body, None),
+            }
+        }
+        _ => (body, None),
+    };
+
+    let mut response = build_response(http_response, body);
+    if let Some(encoding) = encoding {
+        let headers = response.headers_mut();
+        headers.insert(
+            "Content-Encoding",
+            HeaderValue::from_static(encoding.name()),
+        );
+        headers.append("Vary", HeaderValue::from_static("Accept-Encoding"));
+    }
+    Ok(finish(response))
+}
+
+/// Turn a response from a process into a reply to the client
+fn build_response(http_response: HttpResponse, body: Vec<u8>) -> warp::reply::Response {
     let reply = warp::reply::with_status(
         body,
         StatusCode::from_u16(http_response.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
@@ -803,7 +2049,246 @@ async fn http_handler(
         }
         existing_headers.insert(header_name.to_owned(), header_value.to_owned());
     }
-    Ok(response)
+    response
+}
+
+/// The middleware process covering a path of `app`, if any: that set on the
+/// longest prefix of the path
+fn find_middleware(middlewares: &Middlewares, app: &ProcessId, path: &str) -> Option<ProcessId> {
+    middlewares
+        .iter()
+        .filter(|entry| {
+            let prefix = entry.key().trim_end_matches('/');
+            &entry.value().0 == app && (path == prefix || path.starts_with(&format!("{prefix}/")))
+        })
+        .max_by_key(|entry| entry.key().len())
+        .map(|entry| entry.value().1.clone())
+}
+
+async fn is_bound(path_bindings: &PathBindings, path: &str, base_path: &str) -> bool {
+    let path_bindings = path_bindings.read().await;
+    path_bindings.recognize(path).is_ok() || path_bindings.recognize(base_path).is_ok()
+}
+
+/// Ask the kernel to start a process that starts on demand and has not yet been
+/// started, and if it does, wait a while for the process to bind a request's path.
+/// Returns whether the path ended up bound.
+async fn wake_process(
+    our: &str,
+    process_id: ProcessId,
+    path: &str,
+    base_path: &str,
+    path_bindings: &PathBindings,
+    http_response_senders: &HttpResponseSenders,
+    send_to_loop: &MessageSender,
+) -> bool {
+    let id: u64 = rand::random();
+    let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
+    http_response_senders.insert(id, (WAKE_PATH.to_string(), response_sender));
+
+    KernelMessage::builder()
+        .id(id)
+        .source((our, HTTP_SERVER_PROCESS_ID.clone()))
+        .target((our, KERNEL_PROCESS_ID.clone()))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: Some(WAKE_TIMEOUT),
+            body: serde_json::to_vec(&KernelCommand::WakeProcess(process_id)).unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(send_to_loop)
+        .await;
+
+    let timeout_duration = tokio::time::Duration::from_secs(WAKE_TIMEOUT);
+    let result = tokio::time::timeout(timeout_duration, response_receiver).await;
+    http_response_senders.remove(&id);
+    // only a process the kernel actually started can go on to bind the path
+    let Ok(Ok((HttpResponse { status: 200, .. }, _))) = result else {
+        return false;
+    };
+    let deadline = tokio::time::Instant::now() + timeout_duration;
+    while tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        if is_bound(path_bindings, path, base_path).await {
+            return true;
+        }
+    }
+    false
+}
+
+/// Pass a request to a middleware process, and wait for its response
+async fn run_middleware(
+    our: &str,
+    middleware: ProcessId,
+    request: IncomingHttpRequest,
+    body: &[u8],
+    middleware_senders: &MiddlewareSenders,
+    send_to_loop: &MessageSender,
+) -> Result<(MiddlewareResponse, Vec<u8>), StatusCode> {
+    let id: u64 = rand::random();
+    let (response_sender, response_receiver) = tokio::sync::oneshot::channel();
+    middleware_senders.insert(id, (middleware.clone(), response_sender));
+
+    KernelMessage::builder()
+        .id(id)
+        .source((our, HTTP_SERVER_PROCESS_ID.clone()))
+        .target((our, &middleware))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: Some(HTTP_SELF_IMPOSED_TIMEOUT),
+            body: serde_json::to_vec(&HttpServerRequest::Middleware(request)).unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .lazy_load_blob(Some(LazyLoadBlob {
+            mime: None,
+            bytes: body.to_vec(),
+        }))
+        .build()
+        .unwrap()
+        .send(send_to_loop)
+        .await;
+
+    let timeout_duration = tokio::time::Duration::from_secs(HTTP_SELF_IMPOSED_TIMEOUT);
+    let result = tokio::time::timeout(timeout_duration, response_receiver).await;
+    middleware_senders.remove(&id);
+    match result {
+        Ok(Ok(response)) => Ok(response),
+        // the request is not let through if the middleware fails to vet it
+        Ok(Err(_)) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        // the middleware, not the client, was too slow
+        Err(_) => Err(StatusCode::GATEWAY_TIMEOUT),
+    }
+}
+
+/// A request rejected because its client exceeded the node-wide rate limit,
+/// with the number of seconds until it may try again
+#[derive(Debug)]
+struct RateLimited(u64);
+
+impl warp::reject::Reject for RateLimited {}
+
+async fn handle_rejection(
+    rejection: warp::Rejection,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match rejection.find::<RateLimited>() {
+        Some(RateLimited(retry_after)) => Ok(too_many_requests(*retry_after)),
+        None => Err(rejection),
+    }
+}
+
+fn too_many_requests(retry_after: u64) -> warp::reply::Response {
+    warp::http::Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header("Retry-After", retry_after.to_string())
+        .body(vec![])
+        .into_response()
+}
+
+/// Add headers to a response, such as those required by a path's CORS policy
+fn with_headers(
+    mut response: warp::reply::Response,
+    headers: Vec<(&'static str, String)>,
+) -> warp::reply::Response {
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response
+}
+
+/// Set headers on a response, replacing any it already has
+fn with_replaced_headers(
+    mut response: warp::reply::Response,
+    headers: &HashMap<String, String>,
+) -> warp::reply::Response {
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            warp::http::header::HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
+
+/// Serve content bound with `cache: true`, answering conditional requests with
+/// `304 Not Modified` and `Range` requests with the requested part, so that large
+/// assets such as videos can be streamed and unchanged ones are not downloaded again.
+/// Whole responses are compressed if the path allows it and the client accepts it.
+fn serve_static_content(
+    static_content: &utils::StaticContent,
+    headers: &HashMap<String, String>,
+    head: bool,
+    compress: bool,
+) -> warp::reply::Response {
+    // ranges are of the uncompressed content, so are always served uncompressed
+    let encoding = match compress && !headers.contains_key("range") {
+        true => utils::negotiate_encoding(headers)
+            .filter(|encoding| static_content.compressed(*encoding).is_some()),
+        false => None,
+    };
+    let mut response = warp::http::Response::builder()
+        .header(
+            "ETag",
+            match encoding {
+                Some(encoding) => static_content.encoded_etag(encoding),
+                None => static_content.etag.clone(),
+            },
+        )
+        .header("Last-Modified", &static_content.last_modified)
+        .header("Accept-Ranges", "bytes");
+    if compress {
+        response = response.header("Vary", "Accept-Encoding");
+    }
+
+    if static_content.not_modified(headers) {
+        return response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(vec![])
+            .into_response();
+    }
+
+    let bytes = &static_content.blob.bytes;
+    let response = response.header(
+        "Content-Type",
+        static_content.blob.mime.as_deref().unwrap_or("text/plain"),
+    );
+    let (response, body) = match (static_content.range(headers), encoding) {
+        (Ok(None), Some(encoding)) => (
+            response
+                .status(StatusCode::OK)
+                .header("Content-Encoding", encoding.name()),
+            static_content.compressed(encoding).unwrap_or(&bytes[..]),
+        ),
+        (Ok(None), None) => (response.status(StatusCode::OK), &bytes[..]),
+        (Ok(Some((start, end))), _) => (
+            response.status(StatusCode::PARTIAL_CONTENT).header(
+                "Content-Range",
+                format!("bytes {start}-{end}/{}", bytes.len()),
+            ),
+            &bytes[start as usize..=end as usize],
+        ),
+        (Err(()), _) => {
+            return response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("Content-Range", format!("bytes */{}", bytes.len()))
+                .body(vec![])
+                .into_response();
+        }
+    };
+    if head {
+        return response
+            .header("Content-Length", body.len())
+            .body(vec![])
+            .into_response();
+    }
+    response.body(body.to_vec()).into_response()
 }
 
 async fn handle_rpc_message(
@@ -1088,27 +2573,207 @@ async fn websocket_close(
     ws_senders.remove(&channel_id);
     KernelMessage::builder()
         .id(rand::random())
-        .source(("our", HTTP_SERVER_PROCESS_ID.clone()))
-        .target(("our", &process))
+        .source(("our", HTTP_SERVER_PROCESS_ID.clone()))
+        .target(("our", &process))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: None,
+            body: serde_json::to_vec(&HttpServerRequest::WebSocketClose(channel_id)).unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(send_to_loop)
+        .await;
+}
+
+async fn event_stream_handler(
+    path: warp::path::FullPath,
+    host: Option<warp::host::Authority>,
+    headers: warp::http::HeaderMap,
+    our: Arc<String>,
+    jwt_secret: Arc<utils::JwtSecret>,
+    api_tokens: Arc<utils::ApiTokens>,
+    event_stream_senders: EventStreamSenders,
+    event_stream_path_bindings: EventStreamPathBindings,
+    send_to_loop: MessageSender,
+    print_tx: PrintSender,
+    domains: Arc<domains::Domains>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let serialized_headers = utils::serialize_headers(&headers);
+    // any other GET to the path is for its HTTP binding, if it has one
+    if !serialized_headers
+        .get("accept")
+        .is_some_and(|accept| accept.contains("text/event-stream"))
+    {
+        return Err(warp::reject::not_found());
+    }
+    let routed_path = domains.route(host.as_ref().map(|host| host.host()), path.as_str());
+    let original_path = utils::normalize_path(routed_path.as_deref().unwrap_or(path.as_str()));
+
+    let event_stream_path_bindings = event_stream_path_bindings.read().await;
+    let Ok(route) = event_stream_path_bindings.recognize(original_path) else {
+        return Err(warp::reject::not_found());
+    };
+    let bound_path = route.handler();
+    let Some(app) = bound_path.app.clone() else {
+        return Err(warp::reject::not_found());
+    };
+    if bound_path.authenticated
+        && utils::authenticate(&our, None, &serialized_headers, &jwt_secret.get()).is_none()
+        && !api_tokens.authorize(&serialized_headers, original_path, &warp::http::Method::GET)
+    {
+        return Ok(warp::reply::with_status(vec![], StatusCode::UNAUTHORIZED).into_response());
+    }
+    drop(event_stream_path_bindings);
+
+    if event_stream_senders.len() >= EVENT_STREAM_SELF_IMPOSED_MAX_CONNECTIONS as usize {
+        Printout::new(
+            0,
+            HTTP_SERVER_PROCESS_ID.clone(),
+            format!(
+                "http-server: too many open event streams ({})! rejecting incoming",
+                event_stream_senders.len()
+            ),
+        )
+        .send(&print_tx)
+        .await;
+        return Ok(
+            warp::reply::with_status(vec![], StatusCode::SERVICE_UNAVAILABLE).into_response(),
+        );
+    }
+
+    let channel_id: u32 = rand::random();
+    let (sender, receiver) = tokio::sync::mpsc::channel(100);
+    event_stream_senders.insert(channel_id, (app.clone(), sender));
+
+    Printout::new(
+        2,
+        HTTP_SERVER_PROCESS_ID.clone(),
+        format!("http-server: new event stream to {app} with id {channel_id}"),
+    )
+    .send(&print_tx)
+    .await;
+
+    // stripping ProcessId from path
+    let formatted_path = format!(
+        "/{}",
+        original_path
+            .trim_start_matches('/')
+            .strip_prefix(&app.to_string())
+            .unwrap_or("")
+            .trim_start_matches('/')
+    );
+    KernelMessage::builder()
+        .id(rand::random())
+        .source((&*our, HTTP_SERVER_PROCESS_ID.clone()))
+        .target((&*our, &app))
         .message(Message::Request(Request {
             inherit: false,
             expects_response: None,
-            body: serde_json::to_vec(&HttpServerRequest::WebSocketClose(channel_id)).unwrap(),
+            body: serde_json::to_vec(&HttpServerRequest::EventStreamOpen {
+                path: formatted_path,
+                channel_id,
+                last_event_id: serialized_headers.get("last-event-id").cloned(),
+            })
+            .unwrap(),
             metadata: None,
             capabilities: vec![],
         }))
         .build()
         .unwrap()
-        .send(send_to_loop)
+        .send(&send_to_loop)
         .await;
+
+    let guard = EventStreamGuard {
+        our,
+        channel_id,
+        app,
+        event_stream_senders,
+        send_to_loop,
+        print_tx,
+    };
+    // the stream ends when the app closes it, dropping the sender,
+    // and is dropped when the client goes away
+    let events = futures::stream::unfold((receiver, guard), |(mut receiver, guard)| async move {
+        let event = receiver.recv().await?;
+        Some((Ok::<_, std::convert::Infallible>(event), (receiver, guard)))
+    });
+    let keep_alive = warp::sse::keep_alive().interval(std::time::Duration::from_secs(
+        EVENT_STREAM_KEEPALIVE_INTERVAL,
+    ));
+    Ok(with_headers(
+        warp::sse::reply(keep_alive.stream(events)).into_response(),
+        // stop reverse proxies such as nginx from buffering the stream
+        vec![("X-Accel-Buffering", "no".to_string())],
+    ))
+}
+
+/// Held by an open event stream. When the stream is dropped, tells
+/// the app that bound it, unless the app was the one to close it.
+struct EventStreamGuard {
+    our: Arc<String>,
+    channel_id: u32,
+    app: ProcessId,
+    event_stream_senders: EventStreamSenders,
+    send_to_loop: MessageSender,
+    print_tx: PrintSender,
+}
+
+impl Drop for EventStreamGuard {
+    fn drop(&mut self) {
+        let closed_by_client = self.event_stream_senders.remove(&self.channel_id).is_some();
+        let our = self.our.clone();
+        let channel_id = self.channel_id;
+        let app = self.app.clone();
+        let send_to_loop = self.send_to_loop.clone();
+        let print_tx = self.print_tx.clone();
+        tokio::spawn(async move {
+            Printout::new(
+                2,
+                HTTP_SERVER_PROCESS_ID.clone(),
+                format!("http-server: event stream {channel_id} closed"),
+            )
+            .send(&print_tx)
+            .await;
+            if !closed_by_client {
+                return;
+            }
+            KernelMessage::builder()
+                .id(rand::random())
+                .source((&*our, HTTP_SERVER_PROCESS_ID.clone()))
+                .target((&*our, &app))
+                .message(Message::Request(Request {
+                    inherit: false,
+                    expects_response: None,
+                    body: serde_json::to_vec(&HttpServerRequest::EventStreamClose(channel_id))
+                        .unwrap(),
+                    metadata: None,
+                    capabilities: vec![],
+                }))
+                .build()
+                .unwrap()
+                .send(&send_to_loop)
+                .await;
+        });
+    }
 }
 
 async fn handle_app_message(
     km: KernelMessage,
     http_response_senders: HttpResponseSenders,
+    middleware_senders: MiddlewareSenders,
+    middlewares: Middlewares,
     path_bindings: PathBindings,
     ws_path_bindings: WsPathBindings,
     ws_senders: WebSocketSenders,
+    event_stream_path_bindings: EventStreamPathBindings,
+    event_stream_senders: EventStreamSenders,
+    api_tokens: Arc<utils::ApiTokens>,
+    domains: Arc<domains::Domains>,
+    access_log: Option<Arc<access_log::AccessLog>>,
+    jwt_secret: Arc<utils::JwtSecret>,
     send_to_loop: MessageSender,
     print_tx: PrintSender,
 ) {
@@ -1117,9 +2782,44 @@ async fn handle_app_message(
     // when we get a Request, parse it into an HttpServerAction and perform it.
     match km.message {
         Message::Response((response, _context)) => {
+            if let Some((_id, (_middleware, sender))) = middleware_senders
+                .remove_if(&km.id, |_, (middleware, _)| {
+                    middleware == &km.source.process
+                })
+            {
+                let Ok(response) = serde_json::from_slice::<MiddlewareResponse>(&response.body)
+                else {
+                    // the request will be refused when sender is dropped.
+                    return;
+                };
+                let _ = sender.send((
+                    response,
+                    match km.lazy_load_blob {
+                        None => vec![],
+                        Some(p) => p.bytes,
+                    },
+                ));
+                return;
+            }
             let Some((_id, (path, sender))) = http_response_senders.remove(&km.id) else {
                 return;
             };
+            // the kernel's answer to `wake_process`, passed on as a status
+            if path == WAKE_PATH {
+                let woke = km.source.process == *KERNEL_PROCESS_ID
+                    && matches!(
+                        serde_json::from_slice::<KernelResponse>(&response.body),
+                        Ok(KernelResponse::WokeProcess(_))
+                    );
+                let _ = sender.send((
+                    HttpResponse {
+                        status: if woke { 200 } else { 404 },
+                        headers: HashMap::new(),
+                    },
+                    vec![],
+                ));
+                return;
+            }
             // if path is /rpc/message, return accordingly with base64 encoded blob
             if path == "/rpc:distro:sys/message" {
                 let blob = km.lazy_load_blob.map(|p| LazyLoadBlob {
@@ -1183,6 +2883,9 @@ async fn handle_app_message(
                     authenticated,
                     local_only,
                     cache,
+                    compress,
+                    cors,
+                    rate_limit,
                 } => {
                     if check_process_id_kimap_safe(&km.source.process).is_err() {
                         let source = km.source.clone();
@@ -1195,6 +2898,16 @@ async fn handle_app_message(
                         .await;
                         return;
                     }
+                    if cors.as_ref().is_some_and(|cors| !cors.is_valid()) {
+                        send_action_response(
+                            km.id,
+                            km.source,
+                            &send_to_loop,
+                            Err(HttpServerError::InvalidCorsPolicy),
+                        )
+                        .await;
+                        return;
+                    }
                     let path = utils::format_path_with_process(&km.source.process, &path);
                     let mut path_bindings = path_bindings.write().await;
                     Printout::new(
@@ -1223,6 +2936,10 @@ async fn handle_app_message(
                                 authenticated,
                                 local_only,
                                 static_content: None,
+                                compress,
+                                cors: cors.clone(),
+                                rate_limit,
+                                proxy: None,
                             },
                         );
                     } else {
@@ -1244,12 +2961,22 @@ async fn handle_app_message(
                                 secure_subdomain: None,
                                 authenticated,
                                 local_only,
-                                static_content: Some(blob),
+                                static_content: Some(Arc::new(utils::StaticContent::new(blob))),
+                                compress,
+                                cors: cors.clone(),
+                                rate_limit,
+                                proxy: None,
                             },
                         );
                     }
                 }
-                HttpServerAction::SecureBind { path, cache } => {
+                HttpServerAction::SecureBind {
+                    path,
+                    cache,
+                    compress,
+                    cors,
+                    rate_limit,
+                } => {
                     if check_process_id_kimap_safe(&km.source.process).is_err() {
                         let source = km.source.clone();
                         send_action_response(
@@ -1261,6 +2988,16 @@ async fn handle_app_message(
                         .await;
                         return;
                     }
+                    if cors.as_ref().is_some_and(|cors| !cors.is_valid()) {
+                        send_action_response(
+                            km.id,
+                            km.source,
+                            &send_to_loop,
+                            Err(HttpServerError::InvalidCorsPolicy),
+                        )
+                        .await;
+                        return;
+                    }
                     let path = utils::format_path_with_process(&km.source.process, &path);
                     let subdomain = utils::generate_secure_subdomain(&km.source.process);
                     let mut path_bindings = path_bindings.write().await;
@@ -1284,6 +3021,10 @@ async fn handle_app_message(
                                 authenticated: true,
                                 local_only: false,
                                 static_content: None,
+                                compress,
+                                cors: cors.clone(),
+                                rate_limit,
+                                proxy: None,
                             },
                         );
                     } else {
@@ -1305,7 +3046,81 @@ async fn handle_app_message(
                                 secure_subdomain: Some(subdomain),
                                 authenticated: true,
                                 local_only: false,
-                                static_content: Some(blob),
+                                static_content: Some(Arc::new(utils::StaticContent::new(blob))),
+                                compress,
+                                cors: cors.clone(),
+                                rate_limit,
+                                proxy: None,
+                            },
+                        );
+                    }
+                }
+                HttpServerAction::BindProxy {
+                    path,
+                    authenticated,
+                    local_only,
+                    proxy,
+                } => {
+                    if check_process_id_kimap_safe(&km.source.process).is_err() {
+                        send_action_response(
+                            km.id,
+                            km.source,
+                            &send_to_loop,
+                            Err(HttpServerError::InvalidSourceProcess),
+                        )
+                        .await;
+                        return;
+                    }
+                    let path = utils::format_path_with_process(&km.source.process, &path);
+                    Printout::new(
+                        2,
+                        HTTP_SERVER_PROCESS_ID.clone(),
+                        format!(
+                            "http: binding {path} as proxy to {}, {}, {}",
+                            proxy.upstream,
+                            if authenticated {
+                                "authenticated"
+                            } else {
+                                "unauthenticated"
+                            },
+                            if local_only { "local only" } else { "open" },
+                        ),
+                    )
+                    .send(&print_tx)
+                    .await;
+                    // the path itself, and everything under it
+                    let routes = [path.clone(), format!("{path}/*rest")];
+                    let mut path_bindings = path_bindings.write().await;
+                    for route in &routes {
+                        path_bindings.add(
+                            route,
+                            BoundPath {
+                                app: Some(km.source.process.clone()),
+                                path: path.clone(),
+                                secure_subdomain: None,
+                                authenticated,
+                                local_only,
+                                static_content: None,
+                                compress: false,
+                                cors: None,
+                                rate_limit: None,
+                                proxy: Some(proxy.clone()),
+                            },
+                        );
+                    }
+                    drop(path_bindings);
+                    let mut ws_path_bindings = ws_path_bindings.write().await;
+                    for route in &routes {
+                        ws_path_bindings.add(
+                            route,
+                            BoundWsPath {
+                                app: Some(km.source.process.clone()),
+                                secure_subdomain: None,
+                                authenticated,
+                                extension: false,
+                                local_only,
+                                proxy: Some(proxy.clone()),
+                                authorization: None,
                             },
                         );
                     }
@@ -1313,22 +3128,73 @@ async fn handle_app_message(
                 HttpServerAction::Unbind { path } => {
                     let path = utils::format_path_with_process(&km.source.process, &path);
                     let mut path_bindings = path_bindings.write().await;
-                    path_bindings.add(
-                        &path,
-                        BoundPath {
-                            app: None,
-                            path: path.clone(),
-                            secure_subdomain: None,
-                            authenticated: false,
-                            local_only: false,
-                            static_content: None,
-                        },
-                    );
+                    // a proxy binding also covers the paths under it, and websockets
+                    let was_proxy = path_bindings
+                        .recognize(&path)
+                        .is_ok_and(|route| route.handler().proxy.is_some());
+                    let mut routes = vec![path.clone()];
+                    if was_proxy {
+                        routes.push(format!("{path}/*rest"));
+                    }
+                    for route in &routes {
+                        path_bindings.add(
+                            route,
+                            BoundPath {
+                                app: None,
+                                path: path.clone(),
+                                secure_subdomain: None,
+                                authenticated: false,
+                                local_only: false,
+                                static_content: None,
+                                compress: false,
+                                cors: None,
+                                rate_limit: None,
+                                proxy: None,
+                            },
+                        );
+                    }
+                    drop(path_bindings);
+                    if was_proxy {
+                        let mut ws_path_bindings = ws_path_bindings.write().await;
+                        for route in &routes {
+                            ws_path_bindings.add(
+                                route,
+                                BoundWsPath {
+                                    app: None,
+                                    secure_subdomain: None,
+                                    authenticated: false,
+                                    extension: false,
+                                    local_only: false,
+                                    proxy: None,
+                                    authorization: None,
+                                },
+                            );
+                        }
+                    }
+                }
+                HttpServerAction::SetMiddleware { path, middleware } => {
+                    let path = utils::format_path_with_process(&km.source.process, &path);
+                    match middleware {
+                        Some(middleware) => {
+                            Printout::new(
+                                2,
+                                HTTP_SERVER_PROCESS_ID.clone(),
+                                format!("http: passing requests to {path} through {middleware}"),
+                            )
+                            .send(&print_tx)
+                            .await;
+                            middlewares.insert(path, (km.source.process.clone(), middleware));
+                        }
+                        None => {
+                            middlewares.remove(&path);
+                        }
+                    }
                 }
                 HttpServerAction::WebSocketBind {
                     path,
                     authenticated,
                     extension,
+                    authorization,
                 } => {
                     if check_process_id_kimap_safe(&km.source.process).is_err() {
                         let source = km.source.clone();
@@ -1350,10 +3216,17 @@ async fn handle_app_message(
                             secure_subdomain: None,
                             authenticated,
                             extension,
+                            local_only: false,
+                            proxy: None,
+                            authorization,
                         },
                     );
                 }
-                HttpServerAction::WebSocketSecureBind { path, extension } => {
+                HttpServerAction::WebSocketSecureBind {
+                    path,
+                    extension,
+                    authorization,
+                } => {
                     if check_process_id_kimap_safe(&km.source.process).is_err() {
                         let source = km.source.clone();
                         send_action_response(
@@ -1375,6 +3248,9 @@ async fn handle_app_message(
                             secure_subdomain: Some(subdomain),
                             authenticated: true,
                             extension,
+                            local_only: false,
+                            proxy: None,
+                            authorization,
                         },
                     );
                 }
@@ -1388,6 +3264,9 @@ async fn handle_app_message(
                             secure_subdomain: None,
                             authenticated: false,
                             extension: false,
+                            local_only: false,
+                            proxy: None,
+                            authorization: None,
                         },
                     );
                 }
@@ -1464,6 +3343,239 @@ async fn handle_app_message(
                         ws_senders.remove(&channel_id);
                     }
                 }
+                HttpServerAction::EventStreamBind {
+                    path,
+                    authenticated,
+                } => {
+                    if check_process_id_kimap_safe(&km.source.process).is_err() {
+                        send_action_response(
+                            km.id,
+                            km.source,
+                            &send_to_loop,
+                            Err(HttpServerError::InvalidSourceProcess),
+                        )
+                        .await;
+                        return;
+                    }
+                    let path = utils::format_path_with_process(&km.source.process, &path);
+                    let mut event_stream_path_bindings = event_stream_path_bindings.write().await;
+                    event_stream_path_bindings.add(
+                        &path,
+                        BoundEventStreamPath {
+                            app: Some(km.source.process.clone()),
+                            authenticated,
+                        },
+                    );
+                }
+                HttpServerAction::EventStreamUnbind { path } => {
+                    let path = utils::format_path_with_process(&km.source.process, &path);
+                    let mut event_stream_path_bindings = event_stream_path_bindings.write().await;
+                    event_stream_path_bindings.add(
+                        &path,
+                        BoundEventStreamPath {
+                            app: None,
+                            authenticated: false,
+                        },
+                    );
+                }
+                HttpServerAction::EventStreamPush {
+                    channel_id,
+                    event,
+                    id,
+                } => {
+                    let Some(blob) = km.lazy_load_blob else {
+                        send_action_response(
+                            km.id,
+                            km.source,
+                            &send_to_loop,
+                            Err(HttpServerError::NoBlob),
+                        )
+                        .await;
+                        return;
+                    };
+                    // a line break would end the field early, and start another
+                    let has_line_break = |field: &Option<String>| {
+                        field.as_ref().is_some_and(|f| f.contains(['\r', '\n']))
+                    };
+                    if has_line_break(&event) || has_line_break(&id) {
+                        send_action_response(
+                            km.id,
+                            km.source,
+                            &send_to_loop,
+                            Err(HttpServerError::MalformedRequest),
+                        )
+                        .await;
+                        return;
+                    }
+                    // each line of data is sent as its own field, split on '\n' only
+                    let data = String::from_utf8_lossy(&blob.bytes)
+                        .replace("\r\n", "\n")
+                        .replace('\r', "\n");
+                    let mut sse_event = warp::sse::Event::default().data(data);
+                    if let Some(event) = event {
+                        sse_event = sse_event.event(event);
+                    }
+                    if let Some(id) = id {
+                        sse_event = sse_event.id(id);
+                    }
+                    let sender = event_stream_senders
+                        .get(&channel_id)
+                        .filter(|got| got.value().0 == km.source.process)
+                        .map(|got| got.value().1.clone());
+                    let sent = match sender {
+                        Some(sender) => sender.send(sse_event).await.is_ok(),
+                        None => false,
+                    };
+                    if !sent {
+                        send_action_response(
+                            km.id,
+                            km.source,
+                            &send_to_loop,
+                            Err(HttpServerError::EventStreamChannelNotFound),
+                        )
+                        .await;
+                        return;
+                    }
+                }
+                HttpServerAction::EventStreamClose(channel_id) => {
+                    // dropping the sender ends the stream
+                    if event_stream_senders
+                        .remove_if(&channel_id, |_, (app, _)| app == &km.source.process)
+                        .is_none()
+                    {
+                        send_action_response(
+                            km.id,
+                            km.source,
+                            &send_to_loop,
+                            Err(HttpServerError::EventStreamChannelNotFound),
+                        )
+                        .await;
+                        return;
+                    }
+                }
+                HttpServerAction::CreateApiToken { .. }
+                | HttpServerAction::RevokeApiToken { .. }
+                | HttpServerAction::ListApiTokens
+                | HttpServerAction::RotateJwtSecret
+                | HttpServerAction::SetDomain { .. }
+                | HttpServerAction::RemoveDomain { .. }
+                | HttpServerAction::ListDomains
+                | HttpServerAction::QueryAccessLog { .. } => {
+                    // credentials are managed by the user through settings, exclusively
+                    if km.source.process != ProcessId::new(Some("settings"), "settings", "sys") {
+                        send_action_response(
+                            km.id,
+                            km.source,
+                            &send_to_loop,
+                            Err(HttpServerError::InvalidSourceProcess),
+                        )
+                        .await;
+                        return;
+                    }
+                    let (result, blob) = match message {
+                        HttpServerAction::CreateApiToken {
+                            name,
+                            scopes,
+                            read_only,
+                        } => {
+                            Printout::new(
+                                1,
+                                HTTP_SERVER_PROCESS_ID.clone(),
+                                format!("http: minting API token {name} for {scopes:?}"),
+                            )
+                            .send(&print_tx)
+                            .await;
+                            match api_tokens.create(name, scopes, read_only).await {
+                                Ok(token) => (
+                                    Ok(()),
+                                    Some(LazyLoadBlob {
+                                        mime: Some("text/plain".to_string()),
+                                        bytes: token.into_bytes(),
+                                    }),
+                                ),
+                                Err(e) => (Err(e), None),
+                            }
+                        }
+                        HttpServerAction::RevokeApiToken { name } => {
+                            (api_tokens.revoke(&name).await, None)
+                        }
+                        HttpServerAction::ListApiTokens => (
+                            Ok(()),
+                            Some(LazyLoadBlob {
+                                mime: Some("application/json".to_string()),
+                                bytes: serde_json::to_vec(&api_tokens.list()).unwrap(),
+                            }),
+                        ),
+                        HttpServerAction::SetDomain {
+                            host,
+                            process,
+                            certificate,
+                        } => {
+                            Printout::new(
+                                1,
+                                HTTP_SERVER_PROCESS_ID.clone(),
+                                format!("http: serving {process} at {host}"),
+                            )
+                            .send(&print_tx)
+                            .await;
+                            (domains.set(&host, process, certificate).await, None)
+                        }
+                        HttpServerAction::RemoveDomain { host } => {
+                            (domains.remove(&host).await, None)
+                        }
+                        HttpServerAction::ListDomains => (
+                            Ok(()),
+                            Some(LazyLoadBlob {
+                                mime: Some("application/json".to_string()),
+                                bytes: serde_json::to_vec(&domains.list()).unwrap(),
+                            }),
+                        ),
+                        HttpServerAction::QueryAccessLog {
+                            since,
+                            path_prefix,
+                            limit,
+                        } => match &access_log {
+                            Some(access_log) => {
+                                let entries =
+                                    access_log.query(since, path_prefix.as_deref(), limit).await;
+                                (
+                                    Ok(()),
+                                    Some(LazyLoadBlob {
+                                        mime: Some("application/json".to_string()),
+                                        bytes: serde_json::to_vec(&entries).unwrap(),
+                                    }),
+                                )
+                            }
+                            None => (Err(HttpServerError::AccessLogDisabled), None),
+                        },
+                        _ => match jwt_secret.rotate().await {
+                            Ok(()) => {
+                                Printout::new(
+                                    0,
+                                    HTTP_SERVER_PROCESS_ID.clone(),
+                                    "http-server: rotated JWT secret, all auth tokens revoked",
+                                )
+                                .send(&print_tx)
+                                .await;
+                                (Ok(()), None)
+                            }
+                            Err(e) => {
+                                Printout::new(
+                                    0,
+                                    HTTP_SERVER_PROCESS_ID.clone(),
+                                    format!("http-server: failed to rotate JWT secret: {e}"),
+                                )
+                                .send(&print_tx)
+                                .await;
+                                (Err(HttpServerError::JwtSecretNotRotated), None)
+                            }
+                        },
+                    };
+                    let target = km.rsvp.unwrap_or(km.source);
+                    send_action_response_with_blob(km.id, target, &send_to_loop, result, blob)
+                        .await;
+                    return;
+                }
             }
             if km.rsvp.is_some() || expects_response.is_some() {
                 let target = km.rsvp.unwrap_or(km.source);
@@ -1478,6 +3590,16 @@ pub async fn send_action_response(
     target: Address,
     send_to_loop: &MessageSender,
     result: Result<(), HttpServerError>,
+) {
+    send_action_response_with_blob(id, target, send_to_loop, result, None).await;
+}
+
+async fn send_action_response_with_blob(
+    id: u64,
+    target: Address,
+    send_to_loop: &MessageSender,
+    result: Result<(), HttpServerError>,
+    blob: Option<LazyLoadBlob>,
 ) {
     KernelMessage::builder()
         .id(id)
@@ -1492,6 +3614,7 @@ pub async fn send_action_response(
             },
             None,
         )))
+        .lazy_load_blob(blob)
         .build()
         .unwrap()
         .send(send_to_loop)
@@ -0,0 +1,278 @@
+//! Optional TOTP (RFC 6238) second factor for logging in to the node.
+//!
+//! Once enrolled, `POST /login` must carry a current code from the user's
+//! authenticator app, or else one of the single-use recovery codes issued
+//! at enrollment. The TOTP secret and recovery codes are kept in the home
+//! directory, encrypted with a key derived from the keyfile's JWT secret.
+use crate::http::utils;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key,
+};
+use hmac::{Hmac, Mac};
+use lib::types::core::{PrintSender, Printout, HTTP_SERVER_PROCESS_ID};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// seconds each code is valid for
+const TIME_STEP: u64 = 30;
+/// codes from this many steps before or after the current one are accepted,
+/// to allow for clock drift between the node and the authenticator
+const ALLOWED_DRIFT: u64 = 1;
+const DIGITS: u32 = 6;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// how long a secret generated for enrollment can be confirmed for
+const ENROLLMENT_TTL: u64 = 600;
+
+const TOTP_FILE: &str = ".totp";
+
+#[derive(Serialize, Deserialize)]
+struct Enrollment {
+    secret: Vec<u8>,
+    recovery_codes: Vec<String>,
+}
+
+/// Returned from `GET /login/totp`. The `uri` is meant to be displayed as a
+/// QR code for an authenticator app to scan; `secret` can be typed in instead.
+#[derive(Serialize)]
+pub struct TotpSetup {
+    pub secret: String,
+    pub uri: String,
+}
+
+/// Body of `POST` and `DELETE` requests on `/login/totp`.
+#[derive(Deserialize)]
+pub struct TotpCode {
+    pub code: String,
+    /// when re-enrolling, a code for the secret being replaced
+    #[serde(default)]
+    pub current_code: Option<String>,
+}
+
+pub struct Totp {
+    file: PathBuf,
+    print_tx: PrintSender,
+    key: Vec<u8>,
+    enrollment: Mutex<Option<Enrollment>>,
+    /// secret awaiting confirmation with a code, and when it expires
+    pending: Mutex<Option<(Vec<u8>, u64)>>,
+    /// the last time step a code was accepted for: a code cannot be used twice
+    last_step: Mutex<u64>,
+}
+
+impl Totp {
+    pub async fn load(
+        home_directory_path: &Path,
+        jwt_secret_bytes: &[u8],
+        print_tx: PrintSender,
+    ) -> anyhow::Result<Self> {
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(jwt_secret_bytes).unwrap();
+        mac.update(b"kinode-totp");
+        let key = mac.finalize().into_bytes().to_vec();
+        let file = home_directory_path.join(TOTP_FILE);
+        let enrollment = match tokio::fs::read(&file).await {
+            // never fail open: a second factor that cannot be read
+            // must be removed by hand to log in without it
+            Ok(encrypted) => Some(decrypt(&key, &encrypted).ok_or_else(|| {
+                anyhow::anyhow!("failed to decrypt {TOTP_FILE}; delete it to disable TOTP")
+            })?),
+            Err(_) => None,
+        };
+        Ok(Self {
+            file,
+            print_tx,
+            key,
+            enrollment: Mutex::new(enrollment),
+            pending: Mutex::new(None),
+            last_step: Mutex::new(0),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enrollment.lock().unwrap().is_some()
+    }
+
+    /// Generate a new secret to enroll, which takes effect once confirmed
+    /// with a code from the authenticator app.
+    pub fn begin_enrollment(&self, our: &str) -> TotpSetup {
+        let secret = rand::random::<[u8; 20]>().to_vec();
+        let encoded = base32_encode(&secret);
+        *self.pending.lock().unwrap() = Some((secret, now() + ENROLLMENT_TTL));
+        TotpSetup {
+            uri: format!(
+                "otpauth://totp/Kinode:{our}?secret={encoded}&issuer=Kinode\
+                 &digits={DIGITS}&period={TIME_STEP}"
+            ),
+            secret: encoded,
+        }
+    }
+
+    /// Confirm the pending enrollment with `code` from the authenticator app.
+    /// An existing enrollment is only replaced given `current_code`, a valid
+    /// code (or recovery code) for it, so that a hijacked session cannot
+    /// swap out the second factor. Returns the recovery codes, which are
+    /// shown to the user only once.
+    pub async fn complete_enrollment(
+        &self,
+        code: &str,
+        current_code: Option<&str>,
+    ) -> Option<Vec<String>> {
+        let (secret, expiration) = {
+            let mut pending = self.pending.lock().unwrap();
+            match pending.as_ref() {
+                Some((secret, expiration)) if *expiration > now() => {
+                    self.matching_step(secret, code)?;
+                    pending.take().unwrap()
+                }
+                _ => return None,
+            }
+        };
+        if self.enabled() && !self.verify(current_code.unwrap_or_default()).await {
+            // leave the new secret pending so the user can retry
+            *self.pending.lock().unwrap() = Some((secret, expiration));
+            return None;
+        }
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| hex::encode(rand::random::<[u8; 5]>()))
+            .collect();
+        *self.enrollment.lock().unwrap() = Some(Enrollment {
+            secret,
+            recovery_codes: recovery_codes.clone(),
+        });
+        self.persist().await;
+        Some(recovery_codes)
+    }
+
+    /// Disable the second factor, which requires a valid code.
+    pub async fn disable(&self, code: &str) -> bool {
+        if !self.verify(code).await {
+            return false;
+        }
+        *self.enrollment.lock().unwrap() = None;
+        if let Err(e) = tokio::fs::remove_file(&self.file).await {
+            self.print(format!("http-server: failed to remove {TOTP_FILE}: {e}"))
+                .await;
+        }
+        true
+    }
+
+    /// Check a code from the authenticator app, or consume a recovery code.
+    pub async fn verify(&self, code: &str) -> bool {
+        let code = code.trim();
+        let used_recovery_code = {
+            let mut enrollment = self.enrollment.lock().unwrap();
+            let Some(enrollment) = enrollment.as_mut() else {
+                return false;
+            };
+            if let Some(step) = self.matching_step(&enrollment.secret, code) {
+                let mut last_step = self.last_step.lock().unwrap();
+                if step <= *last_step {
+                    return false;
+                }
+                *last_step = step;
+                return true;
+            }
+            let count = enrollment.recovery_codes.len();
+            enrollment
+                .recovery_codes
+                .retain(|recovery_code| recovery_code != &code.to_lowercase());
+            enrollment.recovery_codes.len() < count
+        };
+        if used_recovery_code {
+            self.persist().await;
+        }
+        used_recovery_code
+    }
+
+    /// the time step within the allowed drift that `code` is valid for, if any
+    fn matching_step(&self, secret: &[u8], code: &str) -> Option<u64> {
+        let current = now() / TIME_STEP;
+        (current.saturating_sub(ALLOWED_DRIFT)..=current + ALLOWED_DRIFT)
+            .find(|step| hotp(secret, *step) == code)
+    }
+
+    async fn persist(&self) {
+        let encrypted = {
+            let enrollment = self.enrollment.lock().unwrap();
+            let Some(enrollment) = enrollment.as_ref() else {
+                return;
+            };
+            encrypt(&self.key, enrollment)
+        };
+        // written atomically: a partial file would fail to decrypt, and so
+        // keep http-server from starting until it is removed
+        if let Err(e) = utils::write_atomic(&self.file, &encrypted).await {
+            self.print(format!("http-server: failed to persist {TOTP_FILE}: {e}"))
+                .await;
+        }
+    }
+
+    async fn print(&self, content: String) {
+        Printout::new(0, HTTP_SERVER_PROCESS_ID.clone(), content)
+            .send(&self.print_tx)
+            .await;
+    }
+}
+
+/// RFC 4226 HOTP code for the given counter
+fn hotp(secret: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<sha1::Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// RFC 4648 base32, without padding, as expected by authenticator apps
+fn base32_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn encrypt(key: &[u8], enrollment: &Enrollment) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, serde_json::to_vec(enrollment).unwrap().as_ref())
+        .unwrap();
+    [nonce.to_vec(), ciphertext].concat()
+}
+
+fn decrypt(key: &[u8], encrypted: &[u8]) -> Option<Enrollment> {
+    if encrypted.len() < 12 {
+        return None;
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = generic_array::GenericArray::from_slice(&encrypted[..12]);
+    let plaintext = cipher.decrypt(nonce, &encrypted[12..]).ok()?;
+    serde_json::from_slice(&plaintext).ok()
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
@@ -1,9 +1,18 @@
+use base64::{engine::general_purpose::STANDARD as base64_standard, Engine};
+use chrono::SubsecRound;
 use hmac::{Hmac, Mac};
 use jwt::VerifyWithKey;
-use lib::{core::ProcessId, types::http_server};
+use lib::{
+    core::{LazyLoadBlob, PrintSender, Printout, ProcessId, HTTP_SERVER_PROCESS_ID},
+    types::http_server::{self, ApiTokenInfo, CorsPolicy, HttpServerError, RateLimit},
+};
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
 use tokio::net::TcpListener;
 use warp::http::{header::HeaderName, header::HeaderValue, HeaderMap};
 
@@ -20,33 +29,248 @@ pub struct RpcMessage {
     pub data: Option<String>,
 }
 
-pub fn auth_token_valid(
+/// The key that auth tokens are signed with, and how long the tokens it
+/// issues are valid for. The key is derived from the keyfile's JWT secret
+/// and a salt kept in the home directory: rotating the salt invalidates
+/// every auth token issued so far.
+pub struct JwtSecret {
+    keyfile_secret: Vec<u8>,
+    salt_file: PathBuf,
+    current: std::sync::RwLock<std::sync::Arc<Vec<u8>>>,
+    lifetime: u64,
+}
+
+impl JwtSecret {
+    pub async fn load(keyfile_secret: Vec<u8>, home_directory_path: &Path, lifetime: u64) -> Self {
+        let salt_file = home_directory_path.join(JWT_SALT_FILE);
+        let salt = tokio::fs::read(&salt_file).await.unwrap_or_default();
+        let current = crate::keygen::derive_jwt_secret(&keyfile_secret, &salt);
+        Self {
+            keyfile_secret,
+            salt_file,
+            current: std::sync::RwLock::new(std::sync::Arc::new(current)),
+            lifetime,
+        }
+    }
+
+    pub fn get(&self) -> std::sync::Arc<Vec<u8>> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// how long, in seconds, the auth tokens issued are valid for
+    pub fn lifetime(&self) -> u64 {
+        self.lifetime
+    }
+
+    pub fn issue(&self, our_node: &str, subdomain: &Option<String>) -> Option<String> {
+        crate::keygen::generate_jwt(&self.get(), our_node, subdomain, self.lifetime)
+    }
+
+    /// Replace the salt, and with it the key, persisting the new salt so the
+    /// rotation survives a restart.
+    pub async fn rotate(&self) -> std::io::Result<()> {
+        let salt = rand::random::<[u8; 32]>();
+        tokio::fs::write(&self.salt_file, salt).await?;
+        *self.current.write().unwrap() = std::sync::Arc::new(crate::keygen::derive_jwt_secret(
+            &self.keyfile_secret,
+            &salt,
+        ));
+        Ok(())
+    }
+}
+
+/// file in the home directory holding the salt the JWT signing key is derived with
+pub const JWT_SALT_FILE: &str = ".jwt_salt";
+
+/// file in the home directory holding the keyfile, encrypted with the node's password
+pub const KEYFILE: &str = ".keys";
+
+/// The keyfile that logins are checked against, replaced when the password changes.
+pub struct EncodedKeyfile {
+    file: PathBuf,
+    current: tokio::sync::RwLock<std::sync::Arc<Vec<u8>>>,
+}
+
+impl EncodedKeyfile {
+    pub fn new(encoded_keyfile: Vec<u8>, home_directory_path: &Path) -> Self {
+        Self {
+            file: home_directory_path.join(KEYFILE),
+            current: tokio::sync::RwLock::new(std::sync::Arc::new(encoded_keyfile)),
+        }
+    }
+
+    pub async fn get(&self) -> std::sync::Arc<Vec<u8>> {
+        self.current.read().await.clone()
+    }
+
+    /// Re-encrypt the keyfile with a new password and replace it on disk. The new
+    /// keyfile is written beside the old one and renamed over it, so a crash leaves
+    /// one or the other whole. Returns false, changing nothing, if the old password
+    /// is wrong.
+    pub async fn change_password(
+        &self,
+        old_password_hash: String,
+        new_password_hash: String,
+    ) -> std::io::Result<bool> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut current = self.current.write().await;
+        let encoded_keyfile = current.clone();
+        // deriving each key takes a while, so keep it off the runtime's threads
+        let reencrypted = tokio::task::spawn_blocking(move || {
+            crate::keygen::reencrypt_keyfile(
+                &encoded_keyfile,
+                &old_password_hash,
+                new_password_hash,
+            )
+        })
+        .await
+        .expect("http-server: keyfile re-encryption panicked");
+        let Ok(reencrypted) = reencrypted else {
+            return Ok(false);
+        };
+
+        let temp_file = self.file.with_extension("new");
+        let mut file = tokio::fs::File::create(&temp_file).await?;
+        file.write_all(&reencrypted).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_file, &self.file).await?;
+
+        *current = std::sync::Arc::new(reencrypted);
+        Ok(true)
+    }
+}
+
+/// How the auth token of an authenticated request was presented.
+/// Requests authenticated with a cookie are subject to CSRF checks,
+/// since browsers attach cookies to cross-site requests automatically.
+/// So are those with `Basic` credentials, which browsers cache and attach
+/// the same way. Requests authenticated with a `Bearer` token are not.
+#[derive(Debug, PartialEq)]
+pub enum AuthSource {
+    Cookie,
+    Basic,
+    Bearer,
+}
+
+impl AuthSource {
+    /// Whether a state-changing request authenticated this way passes the CSRF check.
+    /// Clients that send `Basic` credentials themselves, such as WebDAV mounts, set
+    /// no `Origin`, so those requests need only come from the host if they set one.
+    pub fn csrf_check_passes(
+        &self,
+        our_node: &str,
+        subdomain: Option<&ProcessId>,
+        host: &str,
+        headers: &HashMap<String, String>,
+        jwt_secret: &[u8],
+    ) -> bool {
+        match self {
+            AuthSource::Cookie => csrf_check_passes(our_node, subdomain, host, headers, jwt_secret),
+            AuthSource::Basic => match headers.get("origin") {
+                Some(origin) => origin_matches(origin, host),
+                None => true,
+            },
+            AuthSource::Bearer => true,
+        }
+    }
+}
+
+/// Authenticate a request using an `Authorization` header or the auth cookie.
+/// The header, if present, takes precedence. It may carry the auth token as a
+/// `Bearer` token, or as the password of `Basic` credentials, which is all
+/// that clients such as WebDAV mounts can send.
+pub fn authenticate(
     our_node: &str,
     subdomain: Option<&ProcessId>,
-    auth_token: &str,
+    headers: &HashMap<String, String>,
     jwt_secret: &[u8],
-) -> bool {
-    let token: Vec<&str> = auth_token.split("; ").collect();
+) -> Option<AuthSource> {
+    if let Some(authorization) = headers.get("authorization") {
+        return match authorization_token(headers) {
+            Some(token) if jwt_valid(our_node, subdomain, &token, jwt_secret) => {
+                match authorization.starts_with("Basic ") {
+                    true => Some(AuthSource::Basic),
+                    false => Some(AuthSource::Bearer),
+                }
+            }
+            _ => None,
+        };
+    }
+    if auth_token_valid(
+        our_node,
+        subdomain,
+        headers.get("cookie").unwrap_or(&"".to_string()),
+        jwt_secret,
+    ) {
+        Some(AuthSource::Cookie)
+    } else {
+        None
+    }
+}
+
+/// extract the token from an `Authorization` header, presented either
+/// as a `Bearer` token or as the password of `Basic` credentials
+fn authorization_token(headers: &HashMap<String, String>) -> Option<String> {
+    let authorization = headers.get("authorization")?;
+    if let Some(bearer) = authorization.strip_prefix("Bearer ") {
+        Some(bearer.to_string())
+    } else if let Some(basic) = authorization.strip_prefix("Basic ") {
+        basic_auth_password(basic)
+    } else {
+        None
+    }
+}
+
+/// extract the password from base64-encoded `user:password` Basic credentials
+fn basic_auth_password(credentials: &str) -> Option<String> {
+    let credentials = base64_standard.decode(credentials.trim()).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (_user, password) = credentials.split_once(':')?;
+    Some(password.to_string())
+}
 
-    let token_label = match subdomain {
+pub fn auth_cookie_name(our_node: &str, subdomain: Option<&ProcessId>) -> String {
+    match subdomain {
         None => format!("kinode-auth_{our_node}"),
         Some(subdomain) => format!("kinode-auth_{our_node}@{subdomain}"),
-    };
+    }
+}
 
-    let mut auth_token = None;
-    for entry in token {
+/// extract the value of the auth cookie from a `cookie` header string
+pub fn get_auth_token(
+    our_node: &str,
+    subdomain: Option<&ProcessId>,
+    cookie: &str,
+) -> Option<String> {
+    let token_label = auth_cookie_name(our_node, subdomain);
+    for entry in cookie.split("; ") {
         let token_parts: Vec<&str> = entry.split('=').collect();
-        if token_parts.len() == 2 && token_parts[0] == token_label {
-            auth_token = Some(token_parts[1].to_string());
-            break;
+        if token_parts.len() == 2 && token_parts[0] == token_label && !token_parts[1].is_empty() {
+            return Some(token_parts[1].to_string());
         }
     }
+    None
+}
 
-    let auth_token = match auth_token {
-        Some(token) if !token.is_empty() => token,
-        _ => return false,
-    };
+pub fn auth_token_valid(
+    our_node: &str,
+    subdomain: Option<&ProcessId>,
+    auth_token: &str,
+    jwt_secret: &[u8],
+) -> bool {
+    match get_auth_token(our_node, subdomain, auth_token) {
+        Some(token) => jwt_valid(our_node, subdomain, &token, jwt_secret),
+        None => false,
+    }
+}
 
+fn jwt_valid(
+    our_node: &str,
+    subdomain: Option<&ProcessId>,
+    auth_token: &str,
+    jwt_secret: &[u8],
+) -> bool {
     let Ok(secret) = Hmac::<Sha256>::new_from_slice(jwt_secret) else {
         return false;
     };
@@ -54,7 +278,7 @@ pub fn auth_token_valid(
     // Verify JWT structure (header.payload.signature) before attempting to decode
     let jwt_format =
         regex::Regex::new(r"^[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+$").unwrap();
-    if !jwt_format.is_match(&auth_token) {
+    if !jwt_format.is_match(auth_token) {
         return false;
     }
 
@@ -70,6 +294,439 @@ pub fn auth_token_valid(
     }
 }
 
+/// The CSRF token for a session is derived from its auth token, so it
+/// is bound to that session and needs no server-side storage.
+pub fn generate_csrf_token(auth_token: &str, jwt_secret: &[u8]) -> Option<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(jwt_secret).ok()?;
+    mac.update(b"kinode-csrf:");
+    mac.update(auth_token.as_bytes());
+    Some(hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether an HTTP method can change state and so must be CSRF-protected
+pub fn is_state_changing(method: &warp::http::Method) -> bool {
+    method == warp::http::Method::POST
+        || method == warp::http::Method::PUT
+        || method == warp::http::Method::PATCH
+        || method == warp::http::Method::DELETE
+}
+
+/// A cookie-authenticated, state-changing request passes the CSRF check if either:
+/// 1. its `Origin` header matches the host it was sent to (browsers always set
+///    `Origin` on cross-site requests, and scripts cannot forge it), or
+/// 2. it carries an `X-CSRF-Token` header matching the token for its session,
+///    which frontends read from the CSRF cookie set at login.
+pub fn csrf_check_passes(
+    our_node: &str,
+    subdomain: Option<&ProcessId>,
+    host: &str,
+    headers: &HashMap<String, String>,
+    jwt_secret: &[u8],
+) -> bool {
+    if headers
+        .get("origin")
+        .is_some_and(|origin| origin_matches(origin, host))
+    {
+        return true;
+    }
+    let Some(csrf_header) = headers.get("x-csrf-token") else {
+        return false;
+    };
+    let Some(auth_token) = get_auth_token(
+        our_node,
+        subdomain,
+        headers.get("cookie").unwrap_or(&"".to_string()),
+    ) else {
+        return false;
+    };
+    match generate_csrf_token(&auth_token, jwt_secret) {
+        Some(expected) => &expected == csrf_header,
+        None => false,
+    }
+}
+
+/// Whether an `Origin` header names the host a request was sent to.
+fn origin_matches(origin: &str, host: &str) -> bool {
+    let Ok(origin) = url::Url::parse(origin) else {
+        return false;
+    };
+    let origin_authority = match (origin.host_str(), origin.port()) {
+        (Some(origin_host), Some(port)) => format!("{origin_host}:{port}"),
+        (Some(origin_host), None) => origin_host.to_string(),
+        (None, _) => String::new(),
+    };
+    !origin_authority.is_empty() && origin_authority == host
+}
+
+/// Content bound to a path with `cache: true`, served by the http-server itself.
+/// Its validators are computed once, when it is bound, so that browsers can
+/// revalidate their cached copy without downloading it again.
+pub struct StaticContent {
+    pub blob: LazyLoadBlob,
+    pub etag: String,
+    pub last_modified: String,
+    last_modified_at: chrono::DateTime<chrono::Utc>,
+    /// compressed copies, made the first time each encoding is requested
+    brotli: std::sync::OnceLock<Option<Vec<u8>>>,
+    gzip: std::sync::OnceLock<Option<Vec<u8>>>,
+}
+
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+impl StaticContent {
+    pub fn new(blob: LazyLoadBlob) -> Self {
+        let hash = Sha256::digest(&blob.bytes);
+        // HTTP dates have a resolution of seconds
+        let now = chrono::Utc::now().trunc_subsecs(0);
+        Self {
+            etag: format!("\"{}\"", hex::encode(&hash[..16])),
+            last_modified: now.format(HTTP_DATE_FORMAT).to_string(),
+            last_modified_at: now,
+            blob,
+            brotli: std::sync::OnceLock::new(),
+            gzip: std::sync::OnceLock::new(),
+        }
+    }
+
+    /// The content compressed with an encoding, or `None` if compressing it
+    /// would not help, because it is small or of a type that doesn't compress.
+    pub fn compressed(&self, encoding: Encoding) -> Option<&[u8]> {
+        let cell = match encoding {
+            Encoding::Brotli => &self.brotli,
+            Encoding::Gzip => &self.gzip,
+        };
+        cell.get_or_init(|| {
+            let compressible = self.blob.bytes.len() >= COMPRESSION_THRESHOLD
+                && is_compressible(self.blob.mime.as_deref().unwrap_or("text/plain"));
+            match compressible {
+                true => encoding.compress(&self.blob.bytes).ok(),
+                false => None,
+            }
+        })
+        .as_deref()
+    }
+
+    /// The ETag of the content in an encoding: each encoding is a different
+    /// representation, so must have a different strong validator.
+    pub fn encoded_etag(&self, encoding: Encoding) -> String {
+        format!("{}-{}\"", self.etag.trim_end_matches('"'), encoding.name())
+    }
+
+    /// Whether a conditional GET can be answered with `304 Not Modified`.
+    /// `If-None-Match`, if present, takes precedence over `If-Modified-Since`.
+    pub fn not_modified(&self, headers: &HashMap<String, String>) -> bool {
+        if let Some(if_none_match) = headers.get("if-none-match") {
+            return if_none_match.split(',').any(|tag| {
+                let tag = tag.trim();
+                let tag = tag.strip_prefix("W/").unwrap_or(tag);
+                tag == "*"
+                    || tag == self.etag
+                    || [Encoding::Brotli, Encoding::Gzip]
+                        .iter()
+                        .any(|encoding| tag == self.encoded_etag(*encoding))
+            });
+        }
+        match headers
+            .get("if-modified-since")
+            .and_then(|date| chrono::NaiveDateTime::parse_from_str(date, HTTP_DATE_FORMAT).ok())
+        {
+            Some(date) => self.last_modified_at.naive_utc() <= date,
+            None => false,
+        }
+    }
+
+    /// The byte range requested by a `Range` header, as an inclusive `(start, end)`.
+    /// `Ok(None)` means the whole content should be sent, either because no
+    /// single range was requested or because an `If-Range` validator no longer
+    /// matches. `Err(())` means the range cannot be satisfied.
+    pub fn range(&self, headers: &HashMap<String, String>) -> Result<Option<(u64, u64)>, ()> {
+        let Some(ranges) = headers
+            .get("range")
+            .and_then(|range| range.trim().strip_prefix("bytes="))
+        else {
+            return Ok(None);
+        };
+        if let Some(if_range) = headers.get("if-range") {
+            if if_range.trim() != self.etag && if_range.trim() != self.last_modified {
+                return Ok(None);
+            }
+        }
+        // multiple ranges would need a multipart response: send everything instead
+        if ranges.contains(',') {
+            return Ok(None);
+        }
+        let len = self.blob.bytes.len() as u64;
+        let Some((start, end)) = ranges.trim().split_once('-') else {
+            return Ok(None);
+        };
+        let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+            // `bytes=start-end`
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+            // `bytes=start-`
+            (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+            // `bytes=-suffix`: the last `suffix` bytes
+            (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+                (len.saturating_sub(suffix), len.saturating_sub(1))
+            }
+            _ => return Ok(None),
+        };
+        if start >= len {
+            return Err(());
+        }
+        Ok(Some((start, end)))
+    }
+}
+
+/// Responses smaller than this are sent uncompressed, as compressing them saves
+/// too little to be worth the time.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// A content coding for compressed responses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    pub fn compress(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        use std::io::Write;
+        match self {
+            Encoding::Brotli => {
+                // quality 5 and a 4MiB window trade a little size for much faster compression
+                let mut writer = brotli::CompressorWriter::new(vec![], 4096, 5, 22);
+                writer.write_all(bytes)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// The encoding to compress a response with, given the request's `Accept-Encoding`
+/// header, preferring brotli. `None` if the client accepts neither.
+pub fn negotiate_encoding(headers: &HashMap<String, String>) -> Option<Encoding> {
+    let accepted = headers
+        .get("accept-encoding")?
+        .split(',')
+        .filter_map(|coding| {
+            let mut parts = coding.split(';');
+            let name = parts.next()?.trim().to_ascii_lowercase();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (quality > 0.0).then_some(name)
+        })
+        .collect::<Vec<_>>();
+    if accepted.iter().any(|name| name == "br") {
+        Some(Encoding::Brotli)
+    } else if accepted.iter().any(|name| name == "gzip") {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    }
+}
+
+/// Whether content of a MIME type shrinks when compressed. Media types such as
+/// images and video are already compressed, so are sent as they are.
+pub fn is_compressible(mime: &str) -> bool {
+    let mime = mime.split(';').next().unwrap_or("").trim();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
+
+/// Whether an app's response is worth compressing: large enough, of a type that
+/// compresses, not already encoded by the app itself, and not carrying secrets.
+pub fn is_compressible_response(headers: &HashMap<String, String>, body: &[u8]) -> bool {
+    body.len() >= COMPRESSION_THRESHOLD
+        && !carries_secrets(headers)
+        && !headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case("content-encoding"))
+        && headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .is_some_and(|(_, mime)| is_compressible(mime))
+}
+
+/// Whether a response sets cookies or is marked `Cache-Control: no-store`, as
+/// responses carrying tokens or other secrets are. Compressing these next to
+/// attacker-influenced content would let the secret be guessed from the
+/// compressed length (BREACH), so they are always sent uncompressed.
+fn carries_secrets(headers: &HashMap<String, String>) -> bool {
+    headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("set-cookie")
+            || (name.eq_ignore_ascii_case("cache-control")
+                && value
+                    .split(',')
+                    .any(|directive| directive.trim().eq_ignore_ascii_case("no-store")))
+    })
+}
+
+/// The headers that let a frontend from an allowed origin read a response.
+pub fn cors_headers(policy: &CorsPolicy, origin: &str) -> Vec<(&'static str, String)> {
+    // a wildcard can't be used with credentials, so the origin is always echoed
+    let mut headers = vec![
+        ("Access-Control-Allow-Origin", origin.to_string()),
+        ("Vary", "Origin".to_string()),
+    ];
+    if policy.allow_credentials {
+        headers.push(("Access-Control-Allow-Credentials", "true".to_string()));
+    }
+    if !policy.exposed_headers.is_empty() {
+        headers.push((
+            "Access-Control-Expose-Headers",
+            policy.exposed_headers.join(", "),
+        ));
+    }
+    headers
+}
+
+/// The headers answering a preflight request from an allowed origin, or `None`
+/// if the method or headers it asks to use are not allowed.
+pub fn cors_preflight_headers(
+    policy: &CorsPolicy,
+    origin: &str,
+    headers: &HashMap<String, String>,
+) -> Option<Vec<(&'static str, String)>> {
+    let method = headers.get("access-control-request-method")?;
+    if !policy.allows_method(method) {
+        return None;
+    }
+    let requested_headers = headers
+        .get("access-control-request-headers")
+        .map(|requested| {
+            requested
+                .split(',')
+                .map(|name| name.trim().to_ascii_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let any_header = policy.allowed_headers.iter().any(|name| name == "*");
+    if !any_header
+        && !requested_headers.iter().all(|requested| {
+            policy
+                .allowed_headers
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(requested))
+        })
+    {
+        return None;
+    }
+
+    let mut preflight = vec![
+        ("Access-Control-Allow-Origin", origin.to_string()),
+        ("Vary", "Origin".to_string()),
+        ("Access-Control-Allow-Methods", method.to_string()),
+    ];
+    if !requested_headers.is_empty() {
+        preflight.push(("Access-Control-Allow-Headers", requested_headers.join(", ")));
+    }
+    if policy.allow_credentials {
+        preflight.push(("Access-Control-Allow-Credentials", "true".to_string()));
+    }
+    if let Some(max_age) = policy.max_age {
+        preflight.push(("Access-Control-Max-Age", max_age.to_string()));
+    }
+    Some(preflight)
+}
+
+/// How long a client's rate limit bucket is kept after its last request.
+/// Any bucket idle this long has refilled, so forgetting it changes nothing.
+const RATE_LIMIT_IDLE: u64 = 600;
+
+/// Token buckets for rate limiting, one for each client IP address in each
+/// scope, such as the node as a whole, the login page, or a bound path.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: dashmap::DashMap<(IpAddr, String), TokenBucket>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: std::time::Instant,
+}
+
+impl RateLimiter {
+    /// Take a token from a client's bucket in a scope. If the bucket is empty,
+    /// return how many seconds the client should wait before trying again.
+    pub fn check(&self, ip: IpAddr, scope: &str, limit: RateLimit) -> Result<(), u64> {
+        if limit.per_minute == 0 {
+            return Ok(());
+        }
+        let now = std::time::Instant::now();
+        let rate = limit.per_minute as f64 / 60.0;
+        let capacity = limit.burst.max(1) as f64;
+        let mut bucket = self
+            .buckets
+            .entry((ip, scope.to_string()))
+            .or_insert(TokenBucket {
+                tokens: capacity,
+                updated: now,
+            });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / rate).ceil() as u64)
+        }
+    }
+
+    /// Forget the buckets of clients that have been idle long enough to refill
+    pub fn prune(&self) {
+        let idle = std::time::Duration::from_secs(RATE_LIMIT_IDLE);
+        self.buckets
+            .retain(|_, bucket| bucket.updated.elapsed() < idle);
+    }
+}
+
+/// The IP address of the client that made a request, for rate limiting. Behind a
+/// reverse proxy on this machine, that is the last `X-Forwarded-For` address, the
+/// one the proxy appended: any before it were sent by the client, so can be forged.
+/// Local clients that are not proxied are not rate limited, so are `None`.
+pub fn client_ip(
+    socket_addr: Option<SocketAddr>,
+    headers: &HashMap<String, String>,
+) -> Option<IpAddr> {
+    let ip = socket_addr?.ip();
+    if !ip.is_loopback() {
+        return Some(ip);
+    }
+    headers
+        .get("x-forwarded-for")?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
 pub fn normalize_path(path: &str) -> &str {
     match path.strip_suffix('/') {
         Some(new) => new,
@@ -139,6 +796,151 @@ pub async fn is_port_available(bind_addr: &str) -> Option<TcpListener> {
     TcpListener::bind(bind_addr).await.ok()
 }
 
+/// Write a file aside and rename it over `path`, so that a crash mid-write
+/// leaves either the old contents or the new, never a partial file.
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let tmp_path = path.with_extension("tmp");
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
 pub fn _binary_encoded_string_to_bytes(s: &str) -> Vec<u8> {
     s.chars().map(|c| c as u8).collect()
 }
+
+/// Revocable API tokens minted through settings. Tokens are keyed by their
+/// SHA-256 hash, so the tokens themselves are never written to disk.
+pub struct ApiTokens {
+    file: PathBuf,
+    tokens: dashmap::DashMap<String, ApiTokenInfo>,
+    /// held while the tokens are written out, so that an older snapshot, which
+    /// may still hold a revoked token, is never written over a newer one
+    persisting: tokio::sync::Mutex<()>,
+    print_tx: PrintSender,
+}
+
+impl ApiTokens {
+    /// load the tokens persisted in the home directory, if any
+    pub async fn load(home_directory_path: &Path, print_tx: PrintSender) -> Self {
+        let file = home_directory_path.join(".http_api_tokens");
+        let tokens: HashMap<String, ApiTokenInfo> = tokio::fs::read(&file)
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Self {
+            file,
+            tokens: tokens.into_iter().collect(),
+            persisting: tokio::sync::Mutex::new(()),
+            print_tx,
+        }
+    }
+
+    /// mint a new token, returning it
+    pub async fn create(
+        &self,
+        name: String,
+        scopes: Vec<String>,
+        read_only: bool,
+    ) -> Result<String, HttpServerError> {
+        if self.tokens.iter().any(|entry| entry.value().name == name) {
+            return Err(HttpServerError::ApiTokenExists);
+        }
+        let token = format!("kinode-api_{}", hex::encode(rand::random::<[u8; 32]>()));
+        self.tokens.insert(
+            hash_api_token(&token),
+            ApiTokenInfo {
+                name,
+                scopes: scopes.iter().map(|scope| normalize_scope(scope)).collect(),
+                read_only,
+                created: chrono::Utc::now().timestamp() as u64,
+            },
+        );
+        self.persist().await;
+        Ok(token)
+    }
+
+    pub async fn revoke(&self, name: &str) -> Result<(), HttpServerError> {
+        let count = self.tokens.len();
+        self.tokens.retain(|_, info| info.name != name);
+        if self.tokens.len() == count {
+            return Err(HttpServerError::ApiTokenNotFound);
+        }
+        self.persist().await;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<ApiTokenInfo> {
+        let mut tokens: Vec<ApiTokenInfo> = self
+            .tokens
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        tokens.sort_by(|a, b| a.name.cmp(&b.name));
+        tokens
+    }
+
+    /// Whether the request presents, in its `Authorization` header, an API token
+    /// that has a scope covering `path` and that permits `method`.
+    pub fn authorize(
+        &self,
+        headers: &HashMap<String, String>,
+        path: &str,
+        method: &warp::http::Method,
+    ) -> bool {
+        let Some(token) = authorization_token(headers) else {
+            return false;
+        };
+        let Some(info) = self.tokens.get(&hash_api_token(&token)) else {
+            return false;
+        };
+        if info.read_only && method != warp::http::Method::GET && method != warp::http::Method::HEAD
+        {
+            return false;
+        }
+        info.scopes.iter().any(|scope| {
+            path == scope
+                || path
+                    .strip_prefix(scope.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+
+    /// The name of the API token the request presents, if it is one, whatever its scopes
+    pub fn name(&self, headers: &HashMap<String, String>) -> Option<String> {
+        let token = authorization_token(headers)?;
+        let info = self.tokens.get(&hash_api_token(&token))?;
+        Some(info.name.clone())
+    }
+
+    async fn persist(&self) {
+        let _persisting = self.persisting.lock().await;
+        let tokens: HashMap<String, ApiTokenInfo> = self
+            .tokens
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        if let Err(e) = write_atomic(&self.file, &serde_json::to_vec(&tokens).unwrap()).await {
+            Printout::new(
+                0,
+                HTTP_SERVER_PROCESS_ID.clone(),
+                format!("http-server: failed to persist API tokens: {e}"),
+            )
+            .send(&self.print_tx)
+            .await;
+        }
+    }
+}
+
+fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// a scope given as a process ID covers every path bound by that process
+fn normalize_scope(scope: &str) -> String {
+    normalize_path(&format!("/{}", scope.trim_start_matches('/'))).to_string()
+}
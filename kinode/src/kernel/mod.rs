@@ -1,9 +1,13 @@
 use lib::types::core::{self as t, KERNEL_PROCESS_ID, STATE_PROCESS_ID, VFS_PROCESS_ID};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     path::PathBuf,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
 };
 use tokio::{
     sync::{mpsc, Mutex},
@@ -19,9 +23,16 @@ mod standard_host;
 mod standard_host_v0;
 /// Implement the functions served to processes by `wit-v1.0.0/kinode.wit`.
 mod standard_host_v1;
+/// Cache the compiled form of process Wasm across reboots.
+mod wasm_cache;
 
 pub const LATEST_WIT_VERSION: u32 = 0;
 const PROCESS_CHANNEL_CAPACITY: usize = 100;
+/// how often messages waiting in the kernel for room in a process's channel are retried
+const BACKLOG_FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+/// the most messages a package may ask to have wait for one of its processes,
+/// kept well under what a channel or semaphore can be sized to
+const MAX_QUEUE_LIMIT: usize = 10_000;
 
 #[derive(Serialize, Deserialize)]
 struct StartProcessMetadata {
@@ -35,6 +46,44 @@ struct StartProcessMetadata {
 type Senders = HashMap<t::ProcessId, ProcessSender>;
 //  handles are for managing liveness, map is for persistence and metadata.
 type ProcessHandles = HashMap<t::ProcessId, JoinHandle<anyhow::Result<()>>>;
+//  live metrics for each process, reported by `KernelPrint::Metrics`
+type ProcessMetrics = HashMap<t::ProcessId, Metrics>;
+//  processes that start on demand and have not yet been started, shared with
+//  http-server so that it only wakes processes that are waiting to be
+pub type DormantProcesses = Arc<dashmap::DashSet<t::ProcessId>>;
+//  processes with messages in their backlog that wait for room in their channel
+type BackloggedProcesses = HashSet<t::ProcessId>;
+
+#[derive(Default)]
+struct Metrics {
+    messages_received: u64,
+    messages_sent: u64,
+    last_activity: Option<SystemTime>,
+    /// updated by the process's store as it grows its linear memory; none for runtime processes
+    memory_bytes: Option<Arc<AtomicU64>>,
+    /// messages the process has taken from its channel but not yet handled, while
+    /// it awaits a response, or before it runs; none for runtime processes
+    taken_messages: Option<Arc<AtomicUsize>>,
+    /// messages routed to the process that wait for room in its channel, or, if it
+    /// is suspended, for it to be resumed
+    backlog: VecDeque<t::KernelMessage>,
+    suspended: bool,
+    queue_policy: t::QueuePolicy,
+    /// requests dropped or rejected because the queue of the process was full
+    dropped_messages: u64,
+}
+
+impl Metrics {
+    /// messages routed to a userspace process that it has not yet handled
+    fn queue_depth(&self, sender: &t::ProcessMessageSender) -> usize {
+        sender.max_capacity() - sender.capacity()
+            + self.backlog.len()
+            + self
+                .taken_messages
+                .as_ref()
+                .map_or(0, |taken| taken.load(Ordering::Relaxed))
+    }
+}
 
 enum ProcessSender {
     Runtime {
@@ -44,6 +93,16 @@ enum ProcessSender {
     Userspace(t::ProcessMessageSender),
 }
 
+impl ProcessSender {
+    /// messages sent to the process that it has not yet received
+    fn queue_depth(&self) -> usize {
+        match self {
+            ProcessSender::Runtime { sender, .. } => sender.max_capacity() - sender.capacity(),
+            ProcessSender::Userspace(sender) => sender.max_capacity() - sender.capacity(),
+        }
+    }
+}
+
 pub type ProcessRestartBackoffs = HashMap<t::ProcessId, Arc<Mutex<Option<RestartBackoff>>>>;
 
 pub struct RestartBackoff {
@@ -96,7 +155,10 @@ async fn handle_kernel_request(
     send_to_terminal: &t::PrintSender,
     senders: &mut Senders,
     process_handles: &mut ProcessHandles,
+    process_metrics: &mut ProcessMetrics,
     process_map: &mut t::ProcessMap,
+    dormant_processes: &DormantProcesses,
+    backlogged_processes: &mut BackloggedProcesses,
     caps_oracle: &t::CapMessageSender,
     engine: &Engine,
     home_directory_path: &PathBuf,
@@ -291,6 +353,7 @@ async fn handle_kernel_request(
                 send_to_terminal,
                 senders,
                 process_handles,
+                process_metrics,
                 engine,
                 caps_oracle,
                 &start_process_metadata,
@@ -301,6 +364,7 @@ async fn handle_kernel_request(
             {
                 Ok(()) => {
                     let on_exit_none = start_process_metadata.persisted.on_exit.is_none();
+                    dormant_processes.remove(&start_process_metadata.process_id);
                     process_map.insert(
                         start_process_metadata.process_id,
                         start_process_metadata.persisted,
@@ -440,21 +504,23 @@ async fn handle_kernel_request(
         // skip the capabilities-cleanup RevokeAll, pass "no-revoke" in the metadata
         //
         t::KernelCommand::KillProcess(process_id) => {
-            let process_handle = match process_handles.remove(&process_id) {
-                Some(ph) => ph,
-                None => {
-                    t::Printout::new(
-                        2,
-                        KERNEL_PROCESS_ID.clone(),
-                        format!("kernel: no such process {process_id} to kill"),
-                    )
-                    .send(send_to_terminal)
-                    .await;
-                    return None;
-                }
-            };
+            // a process waiting to start on demand has no task to abort
+            let process_handle = process_handles.remove(&process_id);
+            if process_handle.is_none() && dormant_processes.remove(&process_id).is_none() {
+                t::Printout::new(
+                    2,
+                    KERNEL_PROCESS_ID.clone(),
+                    format!("kernel: no such process {process_id} to kill"),
+                )
+                .send(send_to_terminal)
+                .await;
+                return None;
+            }
             senders.remove(&process_id);
-            process_handle.abort();
+            process_metrics.remove(&process_id);
+            if let Some(process_handle) = process_handle {
+                process_handle.abort();
+            }
             process_map.remove(&process_id);
             if request.metadata != Some("no-revoke".to_string()) {
                 caps_oracle
@@ -502,6 +568,171 @@ async fn handle_kernel_request(
                 .await;
             None
         }
+        t::KernelCommand::SuspendProcess(process_id) => {
+            let response = match (
+                senders.get(&process_id),
+                process_metrics.get_mut(&process_id),
+            ) {
+                (Some(ProcessSender::Userspace(_)), Some(metrics)) => {
+                    metrics.suspended = true;
+                    t::KernelResponse::SuspendedProcess(process_id)
+                }
+                _ => t::KernelResponse::SuspendProcessError,
+            };
+            t::KernelMessage::builder()
+                .id(km.id)
+                .source(("our", KERNEL_PROCESS_ID.clone()))
+                .target(km.rsvp.unwrap_or(km.source))
+                .message(t::Message::Response((
+                    t::Response {
+                        inherit: false,
+                        body: serde_json::to_vec(&response).unwrap(),
+                        metadata: None,
+                        capabilities: vec![],
+                    },
+                    None,
+                )))
+                .build()
+                .unwrap()
+                .send(send_to_loop)
+                .await;
+            None
+        }
+        t::KernelCommand::ResumeProcess(process_id) => {
+            let response = match (
+                senders.get(&process_id),
+                process_metrics.get_mut(&process_id),
+            ) {
+                (Some(ProcessSender::Userspace(sender)), Some(metrics)) => {
+                    metrics.suspended = false;
+                    flush_backlog(sender, metrics).await;
+                    if !metrics.backlog.is_empty() {
+                        backlogged_processes.insert(process_id.clone());
+                    }
+                    t::KernelResponse::ResumedProcess(process_id)
+                }
+                _ => t::KernelResponse::SuspendProcessError,
+            };
+            t::KernelMessage::builder()
+                .id(km.id)
+                .source(("our", KERNEL_PROCESS_ID.clone()))
+                .target(km.rsvp.unwrap_or(km.source))
+                .message(t::Message::Response((
+                    t::Response {
+                        inherit: false,
+                        body: serde_json::to_vec(&response).unwrap(),
+                        metadata: None,
+                        capabilities: vec![],
+                    },
+                    None,
+                )))
+                .build()
+                .unwrap()
+                .send(send_to_loop)
+                .await;
+            None
+        }
+        t::KernelCommand::RestartProcess(process_id) => {
+            let response = match restart_process(
+                our_name,
+                keypair.clone(),
+                &process_id,
+                send_to_loop,
+                send_to_terminal,
+                senders,
+                process_handles,
+                process_metrics,
+                process_map,
+                caps_oracle,
+                engine,
+                home_directory_path,
+                process_restart_backoffs,
+            )
+            .await
+            {
+                Ok(()) => {
+                    t::Printout::new(
+                        0,
+                        KERNEL_PROCESS_ID.clone(),
+                        format!("kernel: restarted process {process_id}"),
+                    )
+                    .send(send_to_terminal)
+                    .await;
+                    t::KernelResponse::RestartedProcess(process_id)
+                }
+                Err(e) => {
+                    t::Printout::new(
+                        0,
+                        KERNEL_PROCESS_ID.clone(),
+                        format!("kernel: couldn't restart process {process_id}: {e}"),
+                    )
+                    .send(send_to_terminal)
+                    .await;
+                    t::KernelResponse::RestartProcessError
+                }
+            };
+            t::KernelMessage::builder()
+                .id(km.id)
+                .source(("our", KERNEL_PROCESS_ID.clone()))
+                .target(km.rsvp.unwrap_or(km.source))
+                .message(t::Message::Response((
+                    t::Response {
+                        inherit: false,
+                        body: serde_json::to_vec(&response).unwrap(),
+                        metadata: None,
+                        capabilities: vec![],
+                    },
+                    None,
+                )))
+                .build()
+                .unwrap()
+                .send(send_to_loop)
+                .await;
+            None
+        }
+        t::KernelCommand::WakeProcess(process_id) => {
+            let response = match wake_process(
+                our_name,
+                keypair.clone(),
+                &process_id,
+                send_to_loop,
+                send_to_terminal,
+                senders,
+                process_handles,
+                process_metrics,
+                process_map,
+                dormant_processes,
+                caps_oracle,
+                engine,
+                home_directory_path,
+                process_restart_backoffs,
+            )
+            .await
+            {
+                Ok(()) => t::KernelResponse::WokeProcess(process_id),
+                Err(_) => t::KernelResponse::WakeProcessError,
+            };
+            if request.expects_response.is_some() {
+                t::KernelMessage::builder()
+                    .id(km.id)
+                    .source(("our", KERNEL_PROCESS_ID.clone()))
+                    .target(km.rsvp.unwrap_or(km.source))
+                    .message(t::Message::Response((
+                        t::Response {
+                            inherit: false,
+                            body: serde_json::to_vec(&response).unwrap(),
+                            metadata: None,
+                            capabilities: vec![],
+                        },
+                        None,
+                    )))
+                    .build()
+                    .unwrap()
+                    .send(send_to_loop)
+                    .await;
+            }
+            None
+        }
         t::KernelCommand::Debug(kind) => {
             let response = match kind {
                 t::KernelPrint::ProcessMap => t::KernelPrintResponse::ProcessMap(
@@ -519,6 +750,45 @@ async fn handle_kernel_request(
                         .get(&on)
                         .map(|p| p.capabilities.contains_key(&cap)),
                 ),
+                t::KernelPrint::Metrics => t::KernelPrintResponse::Metrics(
+                    senders
+                        .iter()
+                        .map(|(process_id, sender)| {
+                            let metrics = process_metrics.get(process_id);
+                            let userspace = match sender {
+                                ProcessSender::Userspace(sender) => metrics.map(|m| (sender, m)),
+                                ProcessSender::Runtime { .. } => None,
+                            };
+                            (
+                                process_id.clone(),
+                                t::ProcessMetrics {
+                                    queue_depth: match userspace {
+                                        Some((sender, m)) => m.queue_depth(sender),
+                                        None => sender.queue_depth(),
+                                    },
+                                    messages_received: metrics
+                                        .map(|m| m.messages_received)
+                                        .unwrap_or_default(),
+                                    messages_sent: metrics
+                                        .map(|m| m.messages_sent)
+                                        .unwrap_or_default(),
+                                    last_activity: metrics
+                                        .and_then(|m| m.last_activity)
+                                        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                                        .map(|d| d.as_secs()),
+                                    memory_bytes: metrics
+                                        .and_then(|m| m.memory_bytes.as_ref())
+                                        .map(|b| b.load(Ordering::Relaxed)),
+                                    suspended: metrics.map_or(false, |m| m.suspended),
+                                    queue_limit: userspace.map(|(_, m)| m.queue_policy.limit),
+                                    dropped_messages: metrics
+                                        .map(|m| m.dropped_messages)
+                                        .unwrap_or_default(),
+                                },
+                            )
+                        })
+                        .collect(),
+                ),
             };
             t::KernelMessage::builder()
                 .id(km.id)
@@ -542,6 +812,220 @@ async fn handle_kernel_request(
     }
 }
 
+/// where the Wasm of a process is kept, by its `wasm_bytes_handle`
+fn wasm_bytes_path(vfs_path: &PathBuf, wasm_bytes_handle: &str) -> PathBuf {
+    let wasm_bytes_handle = wasm_bytes_handle
+        .strip_prefix("/")
+        .unwrap_or(wasm_bytes_handle);
+    #[cfg(unix)]
+    let path = vfs_path.join(wasm_bytes_handle);
+    #[cfg(target_os = "windows")]
+    let path = vfs_path.join(wasm_bytes_handle.replace(":", "_"));
+    path
+}
+
+/// The processes that the manifests of the packages in the process map ask to
+/// start on demand. A package whose manifest can't be read starts on boot.
+async fn on_demand_processes(
+    vfs_path: &PathBuf,
+    process_map: &t::ProcessMap,
+) -> HashSet<t::ProcessId> {
+    let packages: HashSet<(&str, &str)> = process_map
+        .iter()
+        .filter(|(_, persisted)| !persisted.wasm_bytes_handle.is_empty())
+        .map(|(process_id, _)| (process_id.package(), process_id.publisher()))
+        .collect();
+    let mut on_demand = HashSet::new();
+    for (package, publisher) in packages {
+        let path = wasm_bytes_path(
+            vfs_path,
+            &format!("{package}:{publisher}/pkg/manifest.json"),
+        );
+        let Ok(manifest) = tokio::fs::read(&path).await else {
+            continue;
+        };
+        let Ok(manifest) = serde_json::from_slice::<Vec<t::PackageManifestEntry>>(&manifest) else {
+            continue;
+        };
+        on_demand.extend(
+            manifest
+                .into_iter()
+                .filter(|entry| entry.start == t::StartMode::OnDemand)
+                .map(|entry| t::ProcessId::new(Some(&entry.process_name), package, publisher)),
+        );
+    }
+    on_demand
+}
+
+/// Abort a running userspace process and start it again from its Wasm, keeping its
+/// place in the process map, and so its capabilities, as if the node had rebooted.
+async fn restart_process(
+    our_name: &str,
+    keypair: Arc<ring::signature::Ed25519KeyPair>,
+    process_id: &t::ProcessId,
+    send_to_loop: &t::MessageSender,
+    send_to_terminal: &t::PrintSender,
+    senders: &mut Senders,
+    process_handles: &mut ProcessHandles,
+    process_metrics: &mut ProcessMetrics,
+    process_map: &t::ProcessMap,
+    caps_oracle: &t::CapMessageSender,
+    engine: &Engine,
+    home_directory_path: &PathBuf,
+    process_restart_backoffs: &mut ProcessRestartBackoffs,
+) -> anyhow::Result<()> {
+    let Some(persisted) = process_map.get(process_id) else {
+        return Err(anyhow::anyhow!("no such process"));
+    };
+    let Some(ProcessSender::Userspace(_)) = senders.get(process_id) else {
+        return Err(anyhow::anyhow!("not a running userspace process"));
+    };
+    let wasm_bytes = tokio::fs::read(wasm_bytes_path(
+        &home_directory_path.join("vfs"),
+        &persisted.wasm_bytes_handle,
+    ))
+    .await?;
+    if let Some(handle) = process_handles.remove(process_id) {
+        handle.abort();
+    }
+    senders.remove(process_id);
+    process_metrics.remove(process_id);
+    start_and_run_process(
+        our_name,
+        keypair,
+        wasm_bytes,
+        process_id,
+        persisted,
+        send_to_loop,
+        send_to_terminal,
+        senders,
+        process_handles,
+        process_metrics,
+        caps_oracle,
+        engine,
+        home_directory_path,
+        process_restart_backoffs,
+    )
+    .await
+}
+
+/// Start a process that was left unstarted on boot because it starts on demand,
+/// as it would have been started on boot.
+async fn wake_process(
+    our_name: &str,
+    keypair: Arc<ring::signature::Ed25519KeyPair>,
+    process_id: &t::ProcessId,
+    send_to_loop: &t::MessageSender,
+    send_to_terminal: &t::PrintSender,
+    senders: &mut Senders,
+    process_handles: &mut ProcessHandles,
+    process_metrics: &mut ProcessMetrics,
+    process_map: &t::ProcessMap,
+    dormant_processes: &DormantProcesses,
+    caps_oracle: &t::CapMessageSender,
+    engine: &Engine,
+    home_directory_path: &PathBuf,
+    process_restart_backoffs: &mut ProcessRestartBackoffs,
+) -> anyhow::Result<()> {
+    if dormant_processes.remove(process_id).is_none() {
+        return Err(anyhow::anyhow!("not waiting to start on demand"));
+    }
+    let Some(persisted) = process_map.get(process_id) else {
+        return Err(anyhow::anyhow!("no such process"));
+    };
+    let wasm_bytes = tokio::fs::read(wasm_bytes_path(
+        &home_directory_path.join("vfs"),
+        &persisted.wasm_bytes_handle,
+    ))
+    .await?;
+    t::Printout::new(
+        2,
+        KERNEL_PROCESS_ID.clone(),
+        format!("kernel: starting {process_id} on demand"),
+    )
+    .send(send_to_terminal)
+    .await;
+    start_and_run_process(
+        our_name,
+        keypair,
+        wasm_bytes,
+        process_id,
+        persisted,
+        send_to_loop,
+        send_to_terminal,
+        senders,
+        process_handles,
+        process_metrics,
+        caps_oracle,
+        engine,
+        home_directory_path,
+        process_restart_backoffs,
+    )
+    .await
+}
+
+/// Start a persisted process from its Wasm and send it the "run" message, as on boot.
+async fn start_and_run_process(
+    our_name: &str,
+    keypair: Arc<ring::signature::Ed25519KeyPair>,
+    wasm_bytes: Vec<u8>,
+    process_id: &t::ProcessId,
+    persisted: &t::PersistedProcess,
+    send_to_loop: &t::MessageSender,
+    send_to_terminal: &t::PrintSender,
+    senders: &mut Senders,
+    process_handles: &mut ProcessHandles,
+    process_metrics: &mut ProcessMetrics,
+    caps_oracle: &t::CapMessageSender,
+    engine: &Engine,
+    home_directory_path: &PathBuf,
+    process_restart_backoffs: &mut ProcessRestartBackoffs,
+) -> anyhow::Result<()> {
+    start_process(
+        our_name,
+        keypair,
+        wasm_bytes,
+        send_to_loop,
+        send_to_terminal,
+        senders,
+        process_handles,
+        process_metrics,
+        engine,
+        caps_oracle,
+        &StartProcessMetadata {
+            source: t::Address {
+                node: our_name.to_string(),
+                process: KERNEL_PROCESS_ID.clone(),
+            },
+            process_id: process_id.clone(),
+            persisted: persisted.clone(),
+            reboot: true,
+        },
+        home_directory_path,
+        process_restart_backoffs,
+    )
+    .await?;
+    let Some(ProcessSender::Userspace(sender)) = senders.get(process_id) else {
+        return Err(anyhow::anyhow!("process did not start"));
+    };
+    sender
+        .send(Ok(t::KernelMessage::builder()
+            .id(rand::random())
+            .source((our_name, KERNEL_PROCESS_ID.clone()))
+            .target((our_name, process_id))
+            .message(t::Message::Request(t::Request {
+                inherit: false,
+                expects_response: None,
+                body: b"run".to_vec(),
+                metadata: None,
+                capabilities: vec![],
+            }))
+            .build()
+            .unwrap()))
+        .await?;
+    Ok(())
+}
+
 /// spawn a process loop and insert the process in the relevant kernel state maps
 async fn start_process(
     our_name: &str,
@@ -551,22 +1035,42 @@ async fn start_process(
     send_to_terminal: &t::PrintSender,
     senders: &mut Senders,
     process_handles: &mut ProcessHandles,
+    process_metrics: &mut ProcessMetrics,
     engine: &Engine,
     caps_oracle: &t::CapMessageSender,
     process_metadata: &StartProcessMetadata,
     home_directory_path: &PathBuf,
     process_restart_backoffs: &mut ProcessRestartBackoffs,
 ) -> anyhow::Result<()> {
-    let (send_to_process, recv_in_process) =
-        mpsc::channel::<Result<t::KernelMessage, t::WrappedSendError>>(PROCESS_CHANNEL_CAPACITY);
     let id = &process_metadata.process_id;
     if senders.contains_key(id) {
         return Err(anyhow::anyhow!("process with ID {id} already exists"));
     }
+    let mut queue_policy = queue_policy(&home_directory_path.join("vfs"), id).await;
+    queue_policy.limit = queue_policy.limit.min(MAX_QUEUE_LIMIT);
+    // a process that blocks its senders is sent messages straight into its channel,
+    // so the channel holds as many as may wait; others wait in the kernel's backlog
+    let channel_capacity = match queue_policy.on_full {
+        t::OnFullQueue::Block => queue_policy.limit,
+        _ => queue_policy.limit.min(PROCESS_CHANNEL_CAPACITY),
+    };
+    let (send_to_process, recv_in_process) =
+        mpsc::channel::<Result<t::KernelMessage, t::WrappedSendError>>(channel_capacity.max(1));
     senders.insert(
         id.clone(),
         ProcessSender::Userspace(send_to_process.clone()),
     );
+    let memory_bytes = Arc::new(AtomicU64::new(0));
+    let taken_messages = Arc::new(AtomicUsize::new(0));
+    process_metrics.insert(
+        id.clone(),
+        Metrics {
+            memory_bytes: Some(memory_bytes.clone()),
+            taken_messages: Some(taken_messages.clone()),
+            queue_policy,
+            ..Default::default()
+        },
+    );
     let metadata = t::ProcessMetadata {
         our: t::Address {
             node: our_name.to_string(),
@@ -600,6 +1104,8 @@ async fn start_process(
             engine.clone(),
             home_directory_path.clone(),
             maybe_restart_backoff,
+            memory_bytes,
+            taken_messages,
         )),
     );
     Ok(())
@@ -628,13 +1134,14 @@ pub async fn kernel(
         bool,
     )>,
     default_pki_entries: Vec<t::KnsUpdate>,
+    dormant_processes: DormantProcesses,
 ) -> anyhow::Result<()> {
     let mut config = Config::new();
-    config.cache_config_load_default().unwrap();
     config.wasm_backtrace_details(WasmBacktraceDetails::Enable);
     config.wasm_component_model(true);
     config.async_support(true);
     let engine = Engine::new(&config).unwrap();
+    wasm_cache::prune(&home_directory_path, &engine).await;
 
     let vfs_path = home_directory_path.join("vfs");
     tokio::fs::create_dir_all(&vfs_path)
@@ -661,6 +1168,7 @@ pub async fn kernel(
 
     // each running process is stored in this map
     let mut process_handles: ProcessHandles = HashMap::with_capacity(process_map.len());
+    let mut process_metrics: ProcessMetrics = HashMap::with_capacity(process_map.len());
 
     let mut in_stepthrough_mode: bool = false;
     // this flag starts as true, and terminal will alert us if we can
@@ -675,23 +1183,28 @@ pub async fn kernel(
 
     let mut process_restart_backoffs: ProcessRestartBackoffs = HashMap::new();
 
+    // processes whose packages ask for them to start on demand are kept in the
+    // process map, but only started once something messages them
+    let on_demand_processes = on_demand_processes(&vfs_path, &process_map).await;
+    let mut backlogged_processes: BackloggedProcesses = HashSet::new();
+
     for (process_id, persisted) in &process_map {
         // runtime extensions will have a bytes_handle of "", because they have no
         // Wasm code saved in filesystem.
         if persisted.wasm_bytes_handle.is_empty() {
             continue;
         }
-        let wasm_bytes_handle = persisted
-            .wasm_bytes_handle
-            .strip_prefix("/")
-            .unwrap_or_else(|| &persisted.wasm_bytes_handle);
-        #[cfg(unix)]
-        let path = vfs_path.join(wasm_bytes_handle);
-        #[cfg(target_os = "windows")]
-        let path = vfs_path.join(wasm_bytes_handle.replace(":", "_"));
+        let path = wasm_bytes_path(&vfs_path, &persisted.wasm_bytes_handle);
+        let on_demand = on_demand_processes.contains(process_id);
 
-        // read wasm bytes directly from vfs
-        let wasm_bytes = match tokio::fs::read(&path).await {
+        // read wasm bytes directly from vfs, or only check they are there
+        // for a process that starts on demand
+        let wasm_bytes = if on_demand {
+            tokio::fs::metadata(&path).await.map(|_| vec![])
+        } else {
+            tokio::fs::read(&path).await
+        };
+        let wasm_bytes = match wasm_bytes {
             Ok(bytes) => bytes,
             Err(e) => {
                 t::Printout::new(
@@ -731,6 +1244,11 @@ pub async fn kernel(
             }
         }
 
+        if on_demand {
+            dormant_processes.insert(process_id.clone());
+            continue;
+        }
+
         let start_process_metadata = StartProcessMetadata {
             source: t::Address {
                 node: our.name.clone(),
@@ -749,6 +1267,7 @@ pub async fn kernel(
             &send_to_terminal,
             &mut senders,
             &mut process_handles,
+            &mut process_metrics,
             &engine,
             &caps_oracle_sender,
             &start_process_metadata,
@@ -811,9 +1330,30 @@ pub async fn kernel(
         .send(&send_to_loop)
         .await;
 
+    let mut backlog_flush = tokio::time::interval(BACKLOG_FLUSH_INTERVAL);
+    backlog_flush.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
     // main event loop
     loop {
         tokio::select! {
+            // messages waiting in the kernel for room in the channels of processes
+            // that were slow to take them are retried
+            _ = backlog_flush.tick(), if !backlogged_processes.is_empty() => {
+                let mut still_backlogged = HashSet::new();
+                for process_id in backlogged_processes.drain() {
+                    let (Some(ProcessSender::Userspace(sender)), Some(metrics)) =
+                        (senders.get(&process_id), process_metrics.get_mut(&process_id))
+                    else {
+                        continue;
+                    };
+                    flush_backlog(sender, metrics).await;
+                    // a suspended process is flushed again when it is resumed
+                    if !metrics.suspended && !metrics.backlog.is_empty() {
+                        still_backlogged.insert(process_id);
+                    }
+                }
+                backlogged_processes = still_backlogged;
+            },
             // debug mode toggle: when on, this loop becomes a manual step-through
             Some(debug_command) = recv_debug_in_loop.recv() => {
                 match debug_command {
@@ -867,6 +1407,7 @@ pub async fn kernel(
                                 match wrapped_network_error.error.kind {
                                     t::SendErrorKind::Timeout => "due to timeout",
                                     t::SendErrorKind::Offline => "because the receiver is offline",
+                                    t::SendErrorKind::QueueFull => "because the receiver's queue is full",
                                 },
                             )
                         ).send(&send_to_terminal).await;
@@ -1019,6 +1560,12 @@ pub async fn kernel(
                     t::Printout::new(3, kernel_message.target.process.clone(), format!("{kernel_message}")).send(&send_to_terminal).await;
                 }
 
+                if kernel_message.source.node == our.name && senders.contains_key(&kernel_message.source.process) {
+                    let metrics = process_metrics.entry(kernel_message.source.process.clone()).or_default();
+                    metrics.messages_sent += 1;
+                    metrics.last_activity = Some(SystemTime::now());
+                }
+
                 if our.name != kernel_message.target.node {
                     // handle messages sent over network
                     send_to_net.send(kernel_message).await.expect("fatal: net module died");
@@ -1032,7 +1579,10 @@ pub async fn kernel(
                         &send_to_terminal,
                         &mut senders,
                         &mut process_handles,
+                        &mut process_metrics,
                         &mut process_map,
+                        &dormant_processes,
+                        &mut backlogged_processes,
                         &caps_oracle_sender,
                         &engine,
                         &home_directory_path,
@@ -1046,12 +1596,45 @@ pub async fn kernel(
                         return Ok(());
                     }
                 } else {
+                    // a process that starts on demand is started by the first message for it
+                    if dormant_processes.contains(&kernel_message.target.process) {
+                        if let Err(e) = wake_process(
+                            &our.name,
+                            keypair.clone(),
+                            &kernel_message.target.process,
+                            &send_to_loop,
+                            &send_to_terminal,
+                            &mut senders,
+                            &mut process_handles,
+                            &mut process_metrics,
+                            &process_map,
+                            &dormant_processes,
+                            &caps_oracle_sender,
+                            &engine,
+                            &home_directory_path,
+                            &mut process_restart_backoffs,
+                        ).await {
+                            t::Printout::new(
+                                0,
+                                KERNEL_PROCESS_ID.clone(),
+                                format!("kernel: couldn't start {} on demand: {e}", kernel_message.target.process),
+                            ).send(&send_to_terminal).await;
+                        }
+                    }
                     // pass message to appropriate runtime module or process
                     match senders.get(&kernel_message.target.process) {
                         Some(ProcessSender::Userspace(sender)) => {
-                            sender.send(Ok(kernel_message)).await.ok();
+                            let process_id = kernel_message.target.process.clone();
+                            let metrics = process_metrics.entry(process_id.clone()).or_default();
+                            deliver(&our.name, sender, metrics, &senders, kernel_message).await;
+                            if !metrics.suspended && !metrics.backlog.is_empty() {
+                                backlogged_processes.insert(process_id);
+                            }
                         }
                         Some(ProcessSender::Runtime { sender, .. }) => {
+                            let metrics = process_metrics.entry(kernel_message.target.process.clone()).or_default();
+                            metrics.messages_received += 1;
+                            metrics.last_activity = Some(SystemTime::now());
                             sender.send(kernel_message).await.expect("event loop: fatal: runtime module died");
                         }
                         None => {
@@ -1203,10 +1786,117 @@ pub async fn kernel(
     }
 }
 
+/// The queue policy that the manifest of a process's package gives it, or the default.
+async fn queue_policy(vfs_path: &PathBuf, process_id: &t::ProcessId) -> t::QueuePolicy {
+    let path = wasm_bytes_path(
+        vfs_path,
+        &format!(
+            "{}:{}/pkg/manifest.json",
+            process_id.package(),
+            process_id.publisher()
+        ),
+    );
+    let Ok(manifest) = tokio::fs::read(&path).await else {
+        return t::QueuePolicy::default();
+    };
+    serde_json::from_slice::<Vec<t::PackageManifestEntry>>(&manifest)
+        .ok()
+        .and_then(|manifest| {
+            manifest
+                .into_iter()
+                .find(|entry| entry.process_name == process_id.process())
+        })
+        .map(|entry| entry.queue)
+        .unwrap_or_default()
+}
+
+/// Route a message to a userspace process, behind any that wait for room in its
+/// channel, applying its queue policy to a request that arrives when its queue is
+/// full. A suspended process can't make room, so one that blocks its senders has
+/// new requests rejected until it is resumed. The sender of a dropped request, if
+/// local and awaiting a response, gets a queue-full error in place of one.
+async fn deliver(
+    our_name: &str,
+    sender: &t::ProcessMessageSender,
+    metrics: &mut Metrics,
+    senders: &Senders,
+    km: t::KernelMessage,
+) {
+    let is_request = matches!(km.message, t::Message::Request(_));
+    if is_request
+        && (metrics.queue_policy.on_full != t::OnFullQueue::Block || metrics.suspended)
+        && metrics.queue_depth(sender) >= metrics.queue_policy.limit
+    {
+        metrics.dropped_messages += 1;
+        let oldest_request = match metrics.queue_policy.on_full {
+            t::OnFullQueue::DropOldest => metrics
+                .backlog
+                .iter()
+                .position(|km| matches!(km.message, t::Message::Request(_))),
+            _ => None,
+        };
+        let Some(oldest_request) = oldest_request else {
+            drop_message(our_name, senders, km).await;
+            return;
+        };
+        let dropped = metrics.backlog.remove(oldest_request).unwrap();
+        drop_message(our_name, senders, dropped).await;
+    }
+    metrics.messages_received += 1;
+    metrics.last_activity = Some(SystemTime::now());
+    metrics.backlog.push_back(km);
+    flush_backlog(sender, metrics).await;
+}
+
+async fn drop_message(our_name: &str, senders: &Senders, km: t::KernelMessage) {
+    if km.source.node == our_name {
+        throw_send_error(our_name, senders, km, t::SendErrorKind::QueueFull).await;
+    }
+}
+
+/// Hand a process the messages in its backlog, unless it is suspended, for as long
+/// as its channel has room, or, if it blocks its senders, waiting for room.
+async fn flush_backlog(sender: &t::ProcessMessageSender, metrics: &mut Metrics) {
+    if metrics.suspended {
+        return;
+    }
+    let block = metrics.queue_policy.on_full == t::OnFullQueue::Block;
+    while let Some(km) = metrics.backlog.pop_front() {
+        if block {
+            if sender.send(Ok(km)).await.is_err() {
+                // the process has exited
+                metrics.backlog.clear();
+                return;
+            }
+            continue;
+        }
+        match sender.try_send(Ok(km)) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(Ok(km))) => {
+                metrics.backlog.push_front(km);
+                return;
+            }
+            Err(_) => {
+                metrics.backlog.clear();
+                return;
+            }
+        }
+    }
+}
+
 async fn throw_timeout(
     our_name: &str,
     senders: &HashMap<t::ProcessId, ProcessSender>,
     km: t::KernelMessage,
+) {
+    throw_send_error(our_name, senders, km, t::SendErrorKind::Timeout).await;
+}
+
+async fn throw_send_error(
+    our_name: &str,
+    senders: &HashMap<t::ProcessId, ProcessSender>,
+    km: t::KernelMessage,
+    kind: t::SendErrorKind,
 ) {
     if let t::Message::Request(req) = &km.message {
         if req.expects_response.is_some() {
@@ -1219,7 +1909,7 @@ async fn throw_timeout(
                             process: KERNEL_PROCESS_ID.clone(),
                         },
                         error: t::SendError {
-                            kind: t::SendErrorKind::Timeout,
+                            kind,
                             target: km.target,
                             lazy_load_blob: km.lazy_load_blob,
                             message: km.message,
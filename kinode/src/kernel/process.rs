@@ -3,12 +3,15 @@ use lib::{types::core as t, v0::ProcessV0, v1::ProcessV1, Process};
 use std::{
     collections::{HashMap, VecDeque},
     path::PathBuf,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
 };
 use tokio::{fs, sync::Mutex, task::JoinHandle};
 use wasmtime::{
     component::{Component, Linker, ResourceTable as Table},
-    Engine, Store,
+    Engine, ResourceLimiter, Store,
 };
 use wasmtime_wasi::{
     pipe::MemoryOutputPipe, DirPerms, FilePerms, WasiCtx, WasiCtxBuilder, WasiView,
@@ -51,9 +54,82 @@ pub struct ProcessState {
     pub contexts: HashMap<u64, (ProcessContext, JoinHandle<()>)>,
     /// store the messages that we've gotten from event loop but haven't processed yet
     /// TODO make this an ordered map for O(1) retrieval by ID
-    pub message_queue: VecDeque<Result<t::KernelMessage, t::WrappedSendError>>,
+    pub message_queue: MessageQueue,
     /// pipe for getting info about capabilities
     pub caps_oracle: t::CapMessageSender,
+    /// reports the size of our linear memory to the kernel
+    pub memory_tracker: MemoryTracker,
+}
+
+/// The messages a process has taken from the event loop but not yet handled,
+/// counted for the kernel, which holds them against the limit of its queue.
+pub struct MessageQueue {
+    messages: VecDeque<Result<t::KernelMessage, t::WrappedSendError>>,
+    len: Arc<AtomicUsize>,
+}
+
+impl MessageQueue {
+    pub fn new(len: Arc<AtomicUsize>) -> Self {
+        len.store(0, Ordering::Relaxed);
+        MessageQueue {
+            messages: VecDeque::new(),
+            len,
+        }
+    }
+
+    pub fn push_back(&mut self, message: Result<t::KernelMessage, t::WrappedSendError>) {
+        self.messages.push_back(message);
+        self.len.store(self.messages.len(), Ordering::Relaxed);
+    }
+
+    pub fn pop_front(&mut self) -> Option<Result<t::KernelMessage, t::WrappedSendError>> {
+        let message = self.messages.pop_front();
+        self.len.store(self.messages.len(), Ordering::Relaxed);
+        message
+    }
+
+    pub fn remove(
+        &mut self,
+        index: usize,
+    ) -> Option<Result<t::KernelMessage, t::WrappedSendError>> {
+        let message = self.messages.remove(index);
+        self.len.store(self.messages.len(), Ordering::Relaxed);
+        message
+    }
+
+    pub fn iter(
+        &self,
+    ) -> std::collections::vec_deque::Iter<'_, Result<t::KernelMessage, t::WrappedSendError>> {
+        self.messages.iter()
+    }
+}
+
+/// Record the bytes of linear memory a process has, as the store grows it,
+/// for the kernel's process metrics. Growth itself is never limited.
+pub struct MemoryTracker {
+    pub memory_bytes: Arc<AtomicU64>,
+}
+
+impl ResourceLimiter for MemoryTracker {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        self.memory_bytes
+            .fetch_add((desired - current) as u64, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        Ok(true)
+    }
 }
 
 pub struct ProcessWasi {
@@ -155,8 +231,9 @@ async fn make_component(
     home_directory_path: PathBuf,
     process_state: ProcessState,
 ) -> anyhow::Result<(Process, Store<ProcessWasi>, MemoryOutputPipe)> {
-    let component =
-        Component::new(&engine, wasm_bytes.to_vec()).expect("make_component: couldn't read file");
+    let component = super::wasm_cache::load_component(&engine, wasm_bytes, &home_directory_path)
+        .await
+        .expect("make_component: couldn't read file");
 
     let mut linker = Linker::new(&engine);
     Process::add_to_linker(&mut linker, |state: &mut ProcessWasi| state).unwrap();
@@ -174,6 +251,7 @@ async fn make_component(
             wasi,
         },
     );
+    store.limiter(|state| &mut state.process.memory_tracker);
 
     let bindings = match Process::instantiate_async(&mut store, &component, &linker).await {
         Ok(b) => b,
@@ -199,8 +277,9 @@ async fn make_component_v0(
     home_directory_path: PathBuf,
     process_state: ProcessState,
 ) -> anyhow::Result<(ProcessV0, Store<ProcessWasiV0>, MemoryOutputPipe)> {
-    let component =
-        Component::new(&engine, wasm_bytes.to_vec()).expect("make_component: couldn't read file");
+    let component = super::wasm_cache::load_component(&engine, wasm_bytes, &home_directory_path)
+        .await
+        .expect("make_component: couldn't read file");
 
     let mut linker = Linker::new(&engine);
     ProcessV0::add_to_linker(&mut linker, |state: &mut ProcessWasiV0| state).unwrap();
@@ -218,6 +297,7 @@ async fn make_component_v0(
             wasi,
         },
     );
+    store.limiter(|state| &mut state.process.memory_tracker);
 
     let bindings = match ProcessV0::instantiate_async(&mut store, &component, &linker).await {
         Ok(b) => b,
@@ -242,8 +322,9 @@ async fn make_component_v1(
     home_directory_path: PathBuf,
     process_state: ProcessState,
 ) -> anyhow::Result<(ProcessV1, Store<ProcessWasiV1>, MemoryOutputPipe)> {
-    let component =
-        Component::new(&engine, wasm_bytes.to_vec()).expect("make_component: couldn't read file");
+    let component = super::wasm_cache::load_component(&engine, wasm_bytes, &home_directory_path)
+        .await
+        .expect("make_component: couldn't read file");
 
     let mut linker = Linker::new(&engine);
     ProcessV1::add_to_linker(&mut linker, |state: &mut ProcessWasiV1| state).unwrap();
@@ -261,6 +342,7 @@ async fn make_component_v1(
             wasi,
         },
     );
+    store.limiter(|state| &mut state.process.memory_tracker);
 
     let bindings = match ProcessV1::instantiate_async(&mut store, &component, &linker).await {
         Ok(b) => b,
@@ -292,15 +374,16 @@ pub async fn make_process_loop(
     engine: Engine,
     home_directory_path: PathBuf,
     maybe_restart_backoff: Option<Arc<Mutex<Option<RestartBackoff>>>>,
+    memory_bytes: Arc<AtomicU64>,
+    queued_messages: Arc<AtomicUsize>,
 ) -> anyhow::Result<()> {
-    // before process can be instantiated, need to await 'run' message from kernel
-    let mut pre_boot_queue = Vec::<Result<t::KernelMessage, t::WrappedSendError>>::new();
+    // before process can be instantiated, need to await 'run' message from kernel.
+    // requests that come first are queued for it; responses and errors can't be
+    // for requests it made, and would be dropped on receipt
+    let mut message_queue = MessageQueue::new(queued_messages);
     while let Some(message) = recv_in_process.recv().await {
         match message {
-            Err(_) => {
-                pre_boot_queue.push(message);
-                continue;
-            }
+            Err(_) => continue,
             Ok(message) => {
                 if (message.source
                     == t::Address {
@@ -318,14 +401,12 @@ pub async fn make_process_loop(
                 {
                     break;
                 }
-                pre_boot_queue.push(Ok(message));
+                if let t::Message::Request(_) = message.message {
+                    message_queue.push_back(Ok(message));
+                }
             }
         }
     }
-    // now that we've received the run message, we can send the pre-boot queue
-    for message in pre_boot_queue {
-        send_to_process.send(message).await?;
-    }
 
     let our = metadata.our.clone();
     let wit_version = metadata.wit_version.clone();
@@ -341,8 +422,9 @@ pub async fn make_process_loop(
         last_message_blobbed: false,
         last_blob: None,
         contexts: HashMap::new(),
-        message_queue: VecDeque::new(),
+        message_queue,
         caps_oracle: caps_oracle.clone(),
+        memory_tracker: MemoryTracker { memory_bytes },
     };
 
     let metadata = match wit_version {
@@ -0,0 +1,114 @@
+//! The compiled form of process Wasm is kept in `.wasm_cache` in the home directory,
+//! so that processes start without recompiling their Wasm after a reboot or reinstall.
+//!
+//! Entries are named by the SHA-256 hash of the Wasm they were compiled from, in a
+//! directory named for the runtime version and the compatibility hash of the engine,
+//! so that an upgraded runtime, or one built with a different compiler, starts afresh.
+//! The directories of other versions are removed on boot, as are the least recently
+//! used entries past [`MAX_CACHE_SIZE`], which are left behind by apps that have
+//! since been updated or uninstalled.
+use sha2::{Digest, Sha256};
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use wasmtime::{component::Component, Engine};
+
+const WASM_CACHE_DIR: &str = ".wasm_cache";
+/// the most compiled Wasm kept; an entry is marked used each time it is loaded
+const MAX_CACHE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// The directory of the cache for this runtime and engine.
+fn cache_dir(home_directory_path: &Path, engine: &Engine) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    home_directory_path.join(WASM_CACHE_DIR).join(format!(
+        "{}-{:016x}",
+        env!("CARGO_PKG_VERSION"),
+        hasher.finish()
+    ))
+}
+
+/// Remove the caches of other runtime versions, and create ours. Of ours, remove
+/// any partly written entries, and the least recently used entries past
+/// [`MAX_CACHE_SIZE`].
+pub async fn prune(home_directory_path: &Path, engine: &Engine) {
+    let ours = cache_dir(home_directory_path, engine);
+    if let Ok(mut entries) = tokio::fs::read_dir(home_directory_path.join(WASM_CACHE_DIR)).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.path() != ours {
+                let _ = tokio::fs::remove_dir_all(entry.path()).await;
+            }
+        }
+    }
+    let _ = tokio::fs::create_dir_all(&ours).await;
+
+    let mut cached: Vec<(PathBuf, SystemTime, u64)> = vec![];
+    if let Ok(mut entries) = tokio::fs::read_dir(&ours).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            // partly written entries are named `<hash>.cwasm.<random>`
+            if path.extension() != Some(std::ffi::OsStr::new("cwasm")) {
+                let _ = tokio::fs::remove_file(&path).await;
+                continue;
+            }
+            if let Ok(metadata) = entry.metadata().await {
+                let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                cached.push((path, used, metadata.len()));
+            }
+        }
+    }
+    // most recently used first
+    cached.sort_by(|a, b| b.1.cmp(&a.1));
+    let mut size = 0;
+    for (path, _, len) in cached {
+        size += len;
+        if size > MAX_CACHE_SIZE {
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+    }
+}
+
+/// Load the component of some Wasm, from the cache if it was compiled before, else
+/// compiling it and caching the result. The cache is only an optimization: if it
+/// can't be read or written, the Wasm is compiled as if it weren't there.
+pub async fn load_component(
+    engine: &Engine,
+    wasm_bytes: &[u8],
+    home_directory_path: &Path,
+) -> anyhow::Result<Component> {
+    let path = cache_dir(home_directory_path, engine)
+        .join(format!("{}.cwasm", hex::encode(Sha256::digest(wasm_bytes))));
+
+    if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        // SAFETY: the cache is written only by `precompile_component` below, into
+        // the home directory, which is trusted like the rest of the node's state,
+        // and for an engine of the same version and configuration.
+        match unsafe { Component::deserialize_file(engine, &path) } {
+            Ok(component) => {
+                // mark it used, so that `prune` keeps it over those that aren't
+                let _ = std::fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(SystemTime::now()));
+                return Ok(component);
+            }
+            Err(_) => {
+                let _ = tokio::fs::remove_file(&path).await;
+            }
+        }
+    }
+
+    let compiled = engine.precompile_component(wasm_bytes)?;
+    // write to a temporary file first, so that a crash mid-write can't leave a
+    // partial entry behind
+    let tmp_path = path.with_extension(format!("cwasm.{}", rand::random::<u64>()));
+    if tokio::fs::write(&tmp_path, &compiled).await.is_ok() {
+        if tokio::fs::rename(&tmp_path, &path).await.is_err() {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+        }
+    }
+    // SAFETY: the bytes were just produced by `precompile_component` on this engine.
+    unsafe { Component::deserialize(engine, &compiled) }
+}
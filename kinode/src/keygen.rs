@@ -66,6 +66,44 @@ pub fn encode_keyfile(
 }
 
 pub fn decode_keyfile(keyfile: &[u8], password_hash: &str) -> Result<Keyfile, &'static str> {
+    let (username, routers, serialized_networking_keypair, jwt_secret_bytes, file_key) =
+        decrypt_keyfile(keyfile, password_hash)?;
+
+    let networking_keypair = signature::Ed25519KeyPair::from_pkcs8(&serialized_networking_keypair)
+        .map_err(|_| "failed to parse networking keys")?;
+
+    Ok(Keyfile {
+        username,
+        routers,
+        networking_keypair,
+        jwt_secret_bytes,
+        file_key,
+    })
+}
+
+/// Re-encrypt a keyfile under a new password, with a fresh salt and nonces.
+/// Fails if the old password doesn't decrypt it.
+pub fn reencrypt_keyfile(
+    keyfile: &[u8],
+    old_password_hash: &str,
+    new_password_hash: String,
+) -> Result<Vec<u8>, &'static str> {
+    let (username, routers, networking_key, jwt_secret_bytes, file_key) =
+        decrypt_keyfile(keyfile, old_password_hash)?;
+    Ok(encode_keyfile(
+        new_password_hash,
+        username,
+        routers,
+        &networking_key,
+        &jwt_secret_bytes,
+        &file_key,
+    ))
+}
+
+/// username, routers, serialized networking keypair, jwt secret, file key
+type DecryptedKeyfile = (String, Vec<String>, Vec<u8>, Vec<u8>, Vec<u8>);
+
+fn decrypt_keyfile(keyfile: &[u8], password_hash: &str) -> Result<DecryptedKeyfile, &'static str> {
     use generic_array::GenericArray;
 
     let (username, routers, salt, key_enc, jwt_enc, file_enc) =
@@ -100,9 +138,6 @@ pub fn decode_keyfile(keyfile: &[u8], password_hash: &str) -> Result<Keyfile, &'
         .decrypt(net_nonce, &key_enc[12..])
         .map_err(|_| "failed to decrypt networking keys")?;
 
-    let networking_keypair = signature::Ed25519KeyPair::from_pkcs8(&serialized_networking_keypair)
-        .map_err(|_| "failed to parse networking keys")?;
-
     let jwt_secret_bytes: Vec<u8> = cipher
         .decrypt(jwt_nonce, &jwt_enc[12..])
         .map_err(|_| "failed to decrypt jwt secret")?;
@@ -111,19 +146,37 @@ pub fn decode_keyfile(keyfile: &[u8], password_hash: &str) -> Result<Keyfile, &'
         .decrypt(file_nonce, &file_enc[12..])
         .map_err(|_| "failed to decrypt file key")?;
 
-    Ok(Keyfile {
+    Ok((
         username,
         routers,
-        networking_keypair,
+        serialized_networking_keypair,
         jwt_secret_bytes,
         file_key,
-    })
+    ))
 }
 
+/// Derive the key that auth tokens are signed with from the keyfile's JWT secret
+/// and a salt. Replacing the salt rotates the key without having to re-encrypt
+/// the keyfile. Without a salt, the keyfile's JWT secret is used as-is.
+pub fn derive_jwt_secret(jwt_secret_bytes: &[u8], salt: &[u8]) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    if salt.is_empty() {
+        return jwt_secret_bytes.to_vec();
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(jwt_secret_bytes).unwrap();
+    mac.update(b"kinode-jwt:");
+    mac.update(salt);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Generate an auth token that expires `lifetime` seconds from now.
 pub fn generate_jwt(
     jwt_secret_bytes: &[u8],
     username: &str,
     subdomain: &Option<String>,
+    lifetime: u64,
 ) -> Option<String> {
     use hmac::Hmac;
     use jwt::SignWithKey;
@@ -135,9 +188,8 @@ pub fn generate_jwt(
         subdomain => Some(subdomain.to_string()),
     };
 
-    // set JWT to expire in 6 months
     let expiration = chrono::Utc::now()
-        .add(chrono::Duration::weeks(26))
+        .add(chrono::Duration::seconds(lifetime as i64))
         .timestamp() as u64;
 
     let claims = crate::http::server_types::JwtClaims {
@@ -149,6 +201,23 @@ pub fn generate_jwt(
     claims.sign_with_key(&jwt_secret).ok()
 }
 
+/// Replace the routers of a keyfile. They are stored unencrypted, so this
+/// doesn't need the password.
+pub fn set_routers(keyfile: &[u8], routers: Vec<String>) -> Result<Vec<u8>, &'static str> {
+    let (username, _routers, salt, key_enc, jwt_enc, file_enc) =
+        serde_json::from_slice::<(String, Vec<String>, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>)>(
+            keyfile,
+        )
+        .or_else(|_| {
+            bincode::deserialize::<(String, Vec<String>, Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>)>(
+                keyfile,
+            )
+        })
+        .map_err(|_| "failed to deserialize keyfile")?;
+
+    Ok(serde_json::to_vec(&(username, routers, salt, key_enc, jwt_enc, file_enc)).unwrap())
+}
+
 #[cfg(not(feature = "simulation-mode"))]
 pub fn get_username_and_routers(keyfile: &[u8]) -> Result<(String, Vec<String>), &'static str> {
     let (username, routers, _salt, _key_enc, _jwt_enc, _file_enc) =
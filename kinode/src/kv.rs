@@ -1,19 +1,52 @@
+use crate::replication::{self, Replications};
 use crate::vfs::UniqueQueue;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key,
+};
 use dashmap::DashMap;
 use lib::types::core::{
     Address, CapMessage, CapMessageSender, Capability, FdManagerRequest, KernelMessage, KvAction,
-    KvCapabilityKind, KvCapabilityParams, KvError, KvRequest, KvResponse, LazyLoadBlob, Message,
-    MessageReceiver, MessageSender, PackageId, PrintSender, Printout, ProcessId, Request, Response,
+    KvCapabilityKind, KvCapabilityParams, KvEntry, KvError, KvReplicatedBatch, KvRequest,
+    KvResponse, KvWrite, LazyLoadBlob, Message, MessageReceiver, MessageSender, PackageId,
+    PrintSender, Printout, ProcessId, ReplicationRole, ReplicationStatus, Request, Response,
     FD_MANAGER_PROCESS_ID, KV_PROCESS_ID,
 };
-use rocksdb::OptimisticTransactionDB;
+use rocksdb::{
+    ColumnFamily, Direction, ErrorKind, IteratorMode, OptimisticTransactionDB, Options, Transaction,
+};
 use std::{
     collections::{HashMap, VecDeque},
     path::PathBuf,
     sync::Arc,
+    time::Duration,
 };
 use tokio::{fs, sync::Mutex};
 
+/// column family of the time each key with a time to live expires at, as big-endian unix ms
+const EXPIRIES: &str = "expiries";
+/// column family holding, in an encrypted db, [`CANARY`] encrypted, to check the key with
+const ENCRYPTION: &str = "encryption";
+const CANARY: &[u8] = b"kinode-kv";
+/// column family holding, for replication, the sequence number of the last batch of
+/// writes logged or applied, the last the follower applied, and the log itself
+const REPLICATION: &str = "replication";
+const SEQ_KEY: &[u8] = b"seq";
+const ACKED_KEY: &[u8] = b"acked";
+/// prefix of each batch of writes logged, followed by its big-endian sequence number
+const LOG_PREFIX: &[u8] = b"log/";
+const NONCE_LEN: usize = 12;
+/// how often expired keys are deleted; until then, reads treat them as gone
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// the most expiries a sweep looks at in one pass over a db, which keeps the db
+/// from being opened or closed until the pass is over
+const SWEEP_BATCH: usize = 1000;
+const MAX_SCAN_LIMIT: u64 = 1000;
+/// how many times a write is retried when another write races it
+const CAS_ATTEMPTS: u32 = 5;
+
+type DbKey = (PackageId, String);
+
 #[derive(Clone)]
 struct KvState {
     our: Arc<Address>,
@@ -24,6 +57,10 @@ struct KvState {
     /// access order of dbs, used to cull if we hit the fds limit
     access_order: Arc<Mutex<UniqueQueue<(PackageId, String)>>>,
     txs: Arc<DashMap<u64, Vec<(KvAction, Option<Vec<u8>>)>>>,
+    /// the cipher of each open db that is encrypted
+    ciphers: Arc<DashMap<(PackageId, String), Aes256Gcm>>,
+    file_key: Arc<Vec<u8>>,
+    replications: Replications,
     fds_limit: u64,
 }
 
@@ -33,6 +70,8 @@ impl KvState {
         send_to_terminal: PrintSender,
         send_to_loop: MessageSender,
         home_directory_path: PathBuf,
+        file_key: Vec<u8>,
+        replications: Replications,
     ) -> Self {
         Self {
             our: Arc::new(our),
@@ -42,11 +81,20 @@ impl KvState {
             open_kvs: Arc::new(DashMap::new()),
             access_order: Arc::new(Mutex::new(UniqueQueue::new())),
             txs: Arc::new(DashMap::new()),
+            ciphers: Arc::new(DashMap::new()),
+            file_key: Arc::new(file_key),
+            replications,
             fds_limit: 10,
         }
     }
 
-    pub async fn open_db(&mut self, key: &(PackageId, String)) -> Result<(), KvError> {
+    /// Open a db, encrypting it if it is empty and `encrypt` is set.
+    /// A db that has keys is opened encrypted or not as it is stored.
+    pub async fn open_db(
+        &mut self,
+        key: &(PackageId, String),
+        encrypt: bool,
+    ) -> Result<(), KvError> {
         if self.open_kvs.contains_key(key) {
             let mut access_order = self.access_order.lock().await;
             access_order.remove(key);
@@ -70,10 +118,32 @@ impl KvState {
 
         fs::create_dir_all(&db_path).await?;
 
-        self.open_kvs.insert(
-            key.clone(),
-            OptimisticTransactionDB::open_default(&db_path).map_err(rocks_to_kv_err)?,
-        );
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let db = OptimisticTransactionDB::open_cf(
+            &options,
+            &db_path,
+            [EXPIRIES, ENCRYPTION, REPLICATION],
+        )
+        .map_err(rocks_to_kv_err)?;
+        let encryption = column_family(&db, ENCRYPTION)?;
+        let cipher = db_cipher(&self.file_key, &key.0);
+        match db.get_cf(encryption, CANARY).map_err(rocks_to_kv_err)? {
+            Some(canary) => {
+                if decrypt_value(Some(&cipher), &canary)? != CANARY {
+                    return Err(KvError::DecryptionFailed);
+                }
+                self.ciphers.insert(key.clone(), cipher);
+            }
+            None if encrypt && db.iterator(IteratorMode::Start).next().is_none() => {
+                db.put_cf(encryption, CANARY, encrypt_value(Some(&cipher), CANARY))
+                    .map_err(rocks_to_kv_err)?;
+                self.ciphers.insert(key.clone(), cipher);
+            }
+            None => {}
+        }
+        self.open_kvs.insert(key.clone(), db);
         let mut access_order = self.access_order.lock().await;
         access_order.push_back(key.clone());
         Ok(())
@@ -81,6 +151,7 @@ impl KvState {
 
     pub async fn remove_db(&mut self, key: &(PackageId, String)) {
         self.open_kvs.remove(key);
+        self.ciphers.remove(key);
         let mut access_order = self.access_order.lock().await;
         access_order.remove(key);
     }
@@ -102,21 +173,37 @@ pub async fn kv(
     mut recv_from_loop: MessageReceiver,
     send_to_caps_oracle: CapMessageSender,
     home_directory_path: PathBuf,
+    file_key: Vec<u8>,
 ) -> anyhow::Result<()> {
     let our = Address::new(our_node.as_str(), KV_PROCESS_ID.clone());
 
     crate::fd_manager::send_fd_manager_request_fds_limit(&our, &send_to_loop).await;
 
-    let mut state = KvState::new(our, send_to_terminal, send_to_loop, home_directory_path);
+    let replications = Replications::load(home_directory_path.join("kv/.replication")).await?;
+    let mut state = KvState::new(
+        our,
+        send_to_terminal,
+        send_to_loop,
+        home_directory_path,
+        file_key,
+        replications,
+    );
 
     if let Err(e) = fs::create_dir_all(&*state.kv_path).await {
         panic!("failed creating kv dir! {e:?}");
     }
 
+    tokio::spawn(sweep_expired(
+        state.open_kvs.clone(),
+        state.send_to_terminal.clone(),
+    ));
+    tokio::spawn(retry_pushes(state.clone()));
+
     let process_queues: HashMap<ProcessId, Arc<Mutex<VecDeque<KernelMessage>>>> = HashMap::new();
 
     while let Some(km) = recv_from_loop.recv().await {
-        if state.our.node != km.source.node {
+        // other nodes' kv only, which stream writes to dbs following them, and answer pushes
+        if state.our.node != km.source.node && km.source.process != *KV_PROCESS_ID {
             Printout::new(
                 1,
                 KV_PROCESS_ID.clone(),
@@ -203,15 +290,18 @@ async fn handle_request(
         lazy_load_blob: blob,
         ..
     } = km;
-    let Message::Request(Request {
-        body,
-        expects_response,
-        metadata,
-        ..
-    }) = message
-    else {
-        // we got a response -- safe to ignore
-        return Ok(());
+    let (body, expects_response, metadata) = match message {
+        Message::Request(Request {
+            body,
+            expects_response,
+            metadata,
+            ..
+        }) => (body, expects_response, metadata),
+        Message::Response((Response { body, .. }, _)) => {
+            // the answer of a follower to writes pushed to it; any other is safe to ignore
+            handle_push_answer(id, &source, &body, state).await;
+            return Ok(());
+        }
     };
 
     let request: KvRequest = match serde_json::from_slice(&body) {
@@ -222,6 +312,11 @@ async fn handle_request(
         }
     };
 
+    // other nodes can only stream writes to dbs that follow them
+    if source.node != state.our.node && !matches!(request.action, KvAction::Replicate(_)) {
+        return Err(KvError::UnexpectedReplication);
+    }
+
     let db_key = (request.package_id, request.db);
 
     check_caps(
@@ -234,10 +329,34 @@ async fn handle_request(
     .await?;
 
     // always open to ensure db exists
-    state.open_db(&db_key).await?;
+    state.open_db(&db_key, false).await?;
+
+    let cipher = state.ciphers.get(&db_key).map(|cipher| cipher.clone());
+    if cipher.is_some()
+        && !matches!(request.action, KvAction::Replicate(_))
+        && PackageId::new(source.process.package(), source.process.publisher()) != db_key.0
+    {
+        return Err(KvError::EncryptedDb);
+    }
+    let cipher = cipher.as_ref();
+
+    let is_write = matches!(
+        request.action,
+        KvAction::Set { .. }
+            | KvAction::Delete { .. }
+            | KvAction::Commit { .. }
+            | KvAction::CompareAndSwap { .. }
+    );
+    if is_write && state.replications.is_follower(&db_key) {
+        return Err(KvError::Follower);
+    }
+    let leader = matches!(
+        state.replications.role(&db_key),
+        Some(ReplicationRole::Leader { .. })
+    );
 
     let (body, bytes) = match request.action {
-        KvAction::Open => {
+        KvAction::Open | KvAction::OpenEncrypted => {
             // handled in check_caps.
             (serde_json::to_vec(&KvResponse::Ok).unwrap(), None)
         }
@@ -254,9 +373,12 @@ async fn handle_request(
             };
 
             match db.get(&key) {
+                Ok(Some(_)) if is_expired(&db, &key)? => {
+                    return Err(KvError::KeyNotFound);
+                }
                 Ok(Some(value)) => (
                     serde_json::to_vec(&KvResponse::Get(key)).unwrap(),
-                    Some(value),
+                    Some(decrypt_value(cipher, &value)?),
                 ),
                 Ok(None) => {
                     return Err(KvError::KeyNotFound);
@@ -274,7 +396,11 @@ async fn handle_request(
                 None,
             )
         }
-        KvAction::Set { ref key, tx_id } => {
+        KvAction::Set {
+            ref key,
+            tx_id,
+            ttl_ms,
+        } => {
             let db = match state.open_kvs.get(&db_key) {
                 None => {
                     return Err(KvError::NoDb(db_key.0, db_key.1));
@@ -287,7 +413,12 @@ async fn handle_request(
 
             match tx_id {
                 None => {
-                    db.put(key, blob.bytes).map_err(rocks_to_kv_err)?;
+                    let writes = [KvWrite::Set {
+                        key: key.clone(),
+                        value: blob.bytes,
+                        expires_at: ttl_ms.map(|ttl_ms| now_ms().saturating_add(ttl_ms)),
+                    }];
+                    commit_writes(&db, cipher, &writes, leader)?;
                 }
                 Some(tx_id) => {
                     let mut tx = match state.txs.get_mut(&tx_id) {
@@ -311,7 +442,8 @@ async fn handle_request(
             };
             match tx_id {
                 None => {
-                    db.delete(key).map_err(rocks_to_kv_err)?;
+                    let writes = [KvWrite::Delete { key: key.clone() }];
+                    commit_writes(&db, cipher, &writes, leader)?;
                 }
                 Some(tx_id) => {
                     let mut tx = match state.txs.get_mut(&tx_id) {
@@ -339,31 +471,214 @@ async fn handle_request(
                 }
                 Some(tx) => tx,
             };
-            let tx = db.transaction();
+            let writes: Vec<KvWrite> = txs
+                .into_iter()
+                .filter_map(|(action, blob)| match action {
+                    KvAction::Set { key, ttl_ms, .. } => Some(KvWrite::Set {
+                        key,
+                        value: blob?,
+                        expires_at: ttl_ms.map(|ttl_ms| now_ms().saturating_add(ttl_ms)),
+                    }),
+                    KvAction::Delete { key, .. } => Some(KvWrite::Delete { key }),
+                    _ => None,
+                })
+                .collect();
+            commit_writes(&db, cipher, &writes, leader)?;
+            (serde_json::to_vec(&KvResponse::Ok).unwrap(), None)
+        }
+        KvAction::Scan {
+            prefix,
+            cursor,
+            limit,
+        } => {
+            let db = match state.open_kvs.get(&db_key) {
+                None => {
+                    return Err(KvError::NoDb(db_key.0, db_key.1));
+                }
+                Some(db) => db,
+            };
+            let limit = limit.clamp(1, MAX_SCAN_LIMIT) as usize;
+            let start = match &cursor {
+                Some(cursor) if *cursor > prefix => cursor.clone(),
+                _ => prefix.clone(),
+            };
 
-            for (action, blob) in txs {
-                match action {
-                    KvAction::Set { key, .. } => {
-                        if let Some(blob) = blob {
-                            tx.put(&key, &blob).map_err(rocks_to_kv_err)?;
-                        }
+            let now = now_ms();
+            let mut entries: Vec<KvEntry> = vec![];
+            let mut next_cursor = None;
+            for item in db.iterator(IteratorMode::From(&start, Direction::Forward)) {
+                let (key, value) = item.map_err(rocks_to_kv_err)?;
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+                // the cursor is the last key of the previous page
+                if cursor.as_deref() == Some(&*key) {
+                    continue;
+                }
+                let expires_at = expires_at(&db, &key)?;
+                if expires_at.is_some_and(|expires_at| expires_at <= now) {
+                    continue;
+                }
+                if entries.len() == limit {
+                    next_cursor = entries.last().map(|entry| entry.key.clone());
+                    break;
+                }
+                entries.push(KvEntry {
+                    key: key.into_vec(),
+                    value: decrypt_value(cipher, &value)?,
+                    expires_at,
+                });
+            }
+            (
+                serde_json::to_vec(&KvResponse::Scan { next_cursor }).unwrap(),
+                Some(serde_json::to_vec(&entries).unwrap()),
+            )
+        }
+        KvAction::CompareAndSwap {
+            key,
+            expected,
+            ttl_ms,
+        } => {
+            let db = match state.open_kvs.get(&db_key) {
+                None => {
+                    return Err(KvError::NoDb(db_key.0, db_key.1));
+                }
+                Some(db) => db,
+            };
+            let expiries = column_family(&db, EXPIRIES)?;
+
+            let mut attempts = 1;
+            loop {
+                // reading for update makes the commit fail if the key is written meanwhile
+                let tx = db.transaction();
+                let mut current = tx
+                    .get_for_update(&key, true)
+                    .map_err(rocks_to_kv_err)?
+                    .map(|current| decrypt_value(cipher, &current))
+                    .transpose()?;
+                let expires_at = tx
+                    .get_for_update_cf(expiries, &key, true)
+                    .map_err(rocks_to_kv_err)?;
+                if expires_at
+                    .and_then(|expires_at| decode_u64(&expires_at))
+                    .is_some_and(|expires_at| expires_at <= now_ms())
+                {
+                    current = None;
+                }
+                if current != expected {
+                    break (
+                        serde_json::to_vec(&KvResponse::CompareAndSwap { swapped: false }).unwrap(),
+                        current,
+                    );
+                }
+                let write = match &blob {
+                    Some(blob) => KvWrite::Set {
+                        key: key.clone(),
+                        value: blob.bytes.clone(),
+                        expires_at: ttl_ms.map(|ttl_ms| now_ms().saturating_add(ttl_ms)),
+                    },
+                    None => KvWrite::Delete { key: key.clone() },
+                };
+                apply_writes(&db, &tx, cipher, &[write], leader)?;
+                match tx.commit() {
+                    Ok(()) => {
+                        break (
+                            serde_json::to_vec(&KvResponse::CompareAndSwap { swapped: true })
+                                .unwrap(),
+                            None,
+                        );
+                    }
+                    // another write to the key raced this one: compare again
+                    Err(e) if is_conflict(&e) && attempts < CAS_ATTEMPTS => {
+                        attempts += 1;
                     }
-                    KvAction::Delete { key, .. } => {
-                        tx.delete(&key).map_err(rocks_to_kv_err)?;
+                    Err(e) => {
+                        return Err(rocks_to_kv_err(e));
                     }
-                    _ => {}
                 }
             }
+        }
+        KvAction::SetReplication(role) => {
+            let previous = state.replications.role(&db_key);
+            // set first, so that writes made while the snapshot is logged are logged after it
+            state.replications.set(&db_key, role.clone()).await?;
+            let reset = match state.open_kvs.get(&db_key) {
+                None => Err(KvError::NoDb(db_key.0.clone(), db_key.1.clone())),
+                Some(db) => reset_replication(&db, cipher, role.as_ref()),
+            };
+            if let Err(e) = reset {
+                state.replications.set(&db_key, previous).await?;
+                return Err(e);
+            }
+            if let Some(ReplicationRole::Leader { .. }) = role {
+                spawn_push(state, &db_key);
+            }
+            (serde_json::to_vec(&KvResponse::Ok).unwrap(), None)
+        }
+        KvAction::GetReplication => {
+            let db = match state.open_kvs.get(&db_key) {
+                None => {
+                    return Err(KvError::NoDb(db_key.0, db_key.1));
+                }
+                Some(db) => db,
+            };
+            let replication = column_family(&db, REPLICATION)?;
+            let status = ReplicationStatus {
+                role: state.replications.role(&db_key),
+                last_seq: read_u64(&db, replication, SEQ_KEY)?,
+                acked_seq: read_u64(&db, replication, ACKED_KEY)?,
+            };
+            (
+                serde_json::to_vec(&KvResponse::Replication(status)).unwrap(),
+                None,
+            )
+        }
+        KvAction::Replicate(batches) => {
+            let db = match state.open_kvs.get(&db_key) {
+                None => {
+                    return Err(KvError::NoDb(db_key.0, db_key.1));
+                }
+                Some(db) => db,
+            };
+            let replication = column_family(&db, REPLICATION)?;
 
-            match tx.commit() {
-                Ok(_) => (serde_json::to_vec(&KvResponse::Ok).unwrap(), None),
-                Err(e) => {
-                    return Err(rocks_to_kv_err(e));
+            let mut applied_seq = read_u64(&db, replication, SEQ_KEY)?;
+            for batch in batches {
+                let tx = db.transaction();
+                applied_seq = tx
+                    .get_for_update_cf(replication, SEQ_KEY, true)
+                    .map_err(rocks_to_kv_err)?
+                    .and_then(|seq| decode_u64(&seq))
+                    .unwrap_or(0);
+                // applied before, and pushed again as its answer was lost
+                if batch.seq <= applied_seq {
+                    continue;
+                }
+                // one is missing: the leader pushes again from the last applied
+                if batch.seq != applied_seq + 1 {
+                    break;
+                }
+                apply_writes(&db, &tx, cipher, &batch.writes, false)?;
+                tx.put_cf(replication, SEQ_KEY, batch.seq.to_be_bytes())
+                    .map_err(rocks_to_kv_err)?;
+                match tx.commit() {
+                    Ok(()) => applied_seq = batch.seq,
+                    // applied meanwhile from another push
+                    Err(e) if is_conflict(&e) => break,
+                    Err(e) => return Err(rocks_to_kv_err(e)),
                 }
             }
+            (
+                serde_json::to_vec(&KvResponse::Replicated { applied_seq }).unwrap(),
+                None,
+            )
         }
     };
 
+    if is_write && leader {
+        spawn_push(state, &db_key);
+    }
+
     if let Some(target) = km.rsvp.or_else(|| expects_response.map(|_| source)) {
         KernelMessage::builder()
             .id(id)
@@ -405,7 +720,8 @@ async fn check_caps(
         KvAction::Delete { .. }
         | KvAction::Set { .. }
         | KvAction::BeginTx
-        | KvAction::Commit { .. } => {
+        | KvAction::Commit { .. }
+        | KvAction::CompareAndSwap { .. } => {
             let Ok(()) = send_to_caps_oracle
                 .send(CapMessage::Has {
                     on: source.process.clone(),
@@ -428,7 +744,7 @@ async fn check_caps(
             };
             Ok(())
         }
-        KvAction::Get { .. } => {
+        KvAction::Get { .. } | KvAction::Scan { .. } | KvAction::GetReplication => {
             let Ok(()) = send_to_caps_oracle
                 .send(CapMessage::Has {
                     on: source.process.clone(),
@@ -451,10 +767,11 @@ async fn check_caps(
             };
             Ok(())
         }
-        KvAction::Open { .. } => {
+        KvAction::Open | KvAction::OpenEncrypted => {
             if src_package_id != db_key.0 {
                 return Err(KvError::MismatchingPackageId);
             }
+            let encrypt = matches!(action, KvAction::OpenEncrypted);
 
             add_capability(
                 KvCapabilityKind::Read,
@@ -473,11 +790,12 @@ async fn check_caps(
             )
             .await?;
 
-            if state.open_kvs.contains_key(&db_key) {
-                return Ok(());
+            if !state.open_kvs.contains_key(db_key) {
+                state.open_db(db_key, encrypt).await?;
+            }
+            if encrypt && !state.ciphers.contains_key(db_key) {
+                return Err(KvError::NotEncrypted);
             }
-
-            state.open_db(&db_key).await?;
             Ok(())
         }
         KvAction::RemoveDb { .. } => {
@@ -486,6 +804,9 @@ async fn check_caps(
             }
 
             state.remove_db(&db_key).await;
+            if state.replications.role(db_key).is_some() {
+                state.replications.set(db_key, None).await?;
+            }
 
             #[cfg(unix)]
             let db_path = state.kv_path.join(format!("{}", db_key.0)).join(&db_key.1);
@@ -499,6 +820,20 @@ async fn check_caps(
 
             Ok(())
         }
+        KvAction::SetReplication(_) => {
+            if src_package_id != db_key.0 {
+                return Err(KvError::MismatchingPackageId);
+            }
+            Ok(())
+        }
+        KvAction::Replicate(_) => match state.replications.role(db_key) {
+            Some(ReplicationRole::Follower { leader })
+                if source.node == leader && source.process == *KV_PROCESS_ID =>
+            {
+                Ok(())
+            }
+            _ => Err(KvError::UnexpectedReplication),
+        },
     }
 }
 
@@ -560,6 +895,484 @@ async fn add_capability(
     Ok(())
 }
 
+/// Apply writes in one transaction, logged as a batch for the follower if the db
+/// is a leader, retrying if another write races them.
+fn commit_writes(
+    db: &OptimisticTransactionDB,
+    cipher: Option<&Aes256Gcm>,
+    writes: &[KvWrite],
+    leader: bool,
+) -> Result<(), KvError> {
+    let mut attempts = 1;
+    loop {
+        let tx = db.transaction();
+        apply_writes(db, &tx, cipher, writes, leader)?;
+        match tx.commit() {
+            Ok(()) => return Ok(()),
+            Err(e) if is_conflict(&e) && attempts < CAS_ATTEMPTS => attempts += 1,
+            Err(e) => return Err(rocks_to_kv_err(e)),
+        }
+    }
+}
+
+fn apply_writes(
+    db: &OptimisticTransactionDB,
+    tx: &Transaction<OptimisticTransactionDB>,
+    cipher: Option<&Aes256Gcm>,
+    writes: &[KvWrite],
+    leader: bool,
+) -> Result<(), KvError> {
+    let expiries = column_family(db, EXPIRIES)?;
+    for write in writes {
+        match write {
+            KvWrite::Set {
+                key,
+                value,
+                expires_at,
+            } => set(
+                tx,
+                expiries,
+                key,
+                &encrypt_value(cipher, value),
+                *expires_at,
+            ),
+            KvWrite::Delete { key } => delete(tx, expiries, key),
+        }
+        .map_err(rocks_to_kv_err)?;
+    }
+    if leader {
+        log_batch(db, tx, cipher, writes)?;
+    }
+    Ok(())
+}
+
+/// Log a batch of writes under the next sequence number. Reading the last for
+/// update makes batches logged at once conflict, so that no two get the same one.
+fn log_batch(
+    db: &OptimisticTransactionDB,
+    tx: &Transaction<OptimisticTransactionDB>,
+    cipher: Option<&Aes256Gcm>,
+    writes: &[KvWrite],
+) -> Result<(), KvError> {
+    let replication = column_family(db, REPLICATION)?;
+    let seq = tx
+        .get_for_update_cf(replication, SEQ_KEY, true)
+        .map_err(rocks_to_kv_err)?
+        .and_then(|seq| decode_u64(&seq))
+        .unwrap_or(0)
+        + 1;
+    let batch = encrypt_value(cipher, &rmp_serde::to_vec(writes).unwrap());
+    tx.put_cf(replication, log_key(seq), batch)
+        .map_err(rocks_to_kv_err)?;
+    tx.put_cf(replication, SEQ_KEY, seq.to_be_bytes())
+        .map_err(rocks_to_kv_err)
+}
+
+/// Forget what was logged or applied for replication, and as a new leader, log
+/// all the db holds, in batches, so that the follower starts with a copy of it.
+fn reset_replication(
+    db: &OptimisticTransactionDB,
+    cipher: Option<&Aes256Gcm>,
+    role: Option<&ReplicationRole>,
+) -> Result<(), KvError> {
+    if let Some(ReplicationRole::Follower { .. }) = role {
+        if db.iterator(IteratorMode::Start).next().is_some() {
+            return Err(KvError::NotEmpty);
+        }
+    }
+    let replication = column_family(db, REPLICATION)?;
+    let tx = db.transaction();
+    // read first, so that writes logged while the snapshot is taken make it fail
+    tx.get_for_update_cf(replication, SEQ_KEY, true)
+        .map_err(rocks_to_kv_err)?;
+    for item in db.iterator_cf(replication, IteratorMode::Start) {
+        let (key, _) = item.map_err(rocks_to_kv_err)?;
+        tx.delete_cf(replication, key).map_err(rocks_to_kv_err)?;
+    }
+    if let Some(ReplicationRole::Leader { .. }) = role {
+        let now = now_ms();
+        let mut writes = vec![];
+        for item in db.iterator(IteratorMode::Start) {
+            let (key, value) = item.map_err(rocks_to_kv_err)?;
+            let expires_at = expires_at(db, &key)?;
+            if expires_at.is_some_and(|expires_at| expires_at <= now) {
+                continue;
+            }
+            writes.push(KvWrite::Set {
+                key: key.into_vec(),
+                value: decrypt_value(cipher, &value)?,
+                expires_at,
+            });
+            if writes.len() == replication::SNAPSHOT_BATCH_LEN {
+                log_batch(db, &tx, cipher, &std::mem::take(&mut writes))?;
+            }
+        }
+        if !writes.is_empty() {
+            log_batch(db, &tx, cipher, &writes)?;
+        }
+    }
+    tx.commit().map_err(rocks_to_kv_err)
+}
+
+/// Push the batches of a leader db its follower has not applied yet, unless a push
+/// is awaiting its answer already.
+async fn push(mut state: KvState, db_key: DbKey) -> Result<(), KvError> {
+    let Some(ReplicationRole::Leader { follower }) = state.replications.role(&db_key) else {
+        return Ok(());
+    };
+    let Some(id) = state.replications.start_push(&db_key) else {
+        return Ok(());
+    };
+    let batches = match unapplied_batches(&mut state, &db_key).await {
+        Ok(batches) if !batches.is_empty() => batches,
+        result => {
+            state.replications.finish_push(id);
+            return result.map(|_| ());
+        }
+    };
+    KernelMessage::builder()
+        .id(id)
+        .source(state.our.as_ref().clone())
+        .target(Address::new(follower.as_str(), KV_PROCESS_ID.clone()))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: Some(replication::ACK_TIMEOUT_SECS),
+            body: serde_json::to_vec(&KvRequest {
+                package_id: db_key.0,
+                db: db_key.1,
+                action: KvAction::Replicate(batches),
+            })
+            .unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(&state.send_to_loop)
+        .await;
+    Ok(())
+}
+
+async fn unapplied_batches(
+    state: &mut KvState,
+    db_key: &DbKey,
+) -> Result<Vec<KvReplicatedBatch>, KvError> {
+    state.open_db(db_key, false).await?;
+    let Some(db) = state.open_kvs.get(db_key) else {
+        return Err(KvError::NoDb(db_key.0.clone(), db_key.1.clone()));
+    };
+    let cipher = state.ciphers.get(db_key).map(|cipher| cipher.clone());
+    let replication = column_family(&db, REPLICATION)?;
+    let acked_seq = read_u64(&db, replication, ACKED_KEY)?;
+
+    let mut batches = vec![];
+    let start = log_key(acked_seq + 1);
+    for item in db.iterator_cf(replication, IteratorMode::From(&start, Direction::Forward)) {
+        let (key, batch) = item.map_err(rocks_to_kv_err)?;
+        if !key.starts_with(LOG_PREFIX) || batches.len() == replication::MAX_BATCHES {
+            break;
+        }
+        let seq = decode_u64(&key[LOG_PREFIX.len()..]).ok_or(KvError::MalformedRequest)?;
+        let writes = rmp_serde::from_slice(&decrypt_value(cipher.as_ref(), &batch)?)
+            .map_err(|e| KvError::RocksDBError(e.to_string()))?;
+        batches.push(KvReplicatedBatch { seq, writes });
+    }
+    Ok(batches)
+}
+
+/// Take in the answer of a follower to a push: drop the batches it applied from
+/// the log, and push any more.
+async fn handle_push_answer(id: u64, source: &Address, body: &[u8], state: &mut KvState) {
+    let Some(db_key) = state.replications.finish_push(id) else {
+        return;
+    };
+    let Some(ReplicationRole::Leader { follower }) = state.replications.role(&db_key) else {
+        return;
+    };
+    if source.node != follower {
+        return;
+    }
+    let result = match serde_json::from_slice::<KvResponse>(body) {
+        Ok(KvResponse::Replicated { applied_seq }) => {
+            record_applied(state, &db_key, applied_seq).await
+        }
+        Ok(KvResponse::Err(e)) => Err(e),
+        _ => Err(KvError::MalformedRequest),
+    };
+    match result {
+        Ok(true) => spawn_push(state, &db_key),
+        Ok(false) => {}
+        Err(e) => {
+            Printout::new(
+                1,
+                KV_PROCESS_ID.clone(),
+                format!(
+                    "kv: follower {follower} of [{}, {}] did not apply writes: {e}",
+                    db_key.0, db_key.1
+                ),
+            )
+            .send(&state.send_to_terminal)
+            .await;
+        }
+    }
+}
+
+/// Record the last batch a follower applied, returning whether it has more to apply.
+async fn record_applied(
+    state: &mut KvState,
+    db_key: &DbKey,
+    applied_seq: u64,
+) -> Result<bool, KvError> {
+    state.open_db(db_key, false).await?;
+    let Some(db) = state.open_kvs.get(db_key) else {
+        return Err(KvError::NoDb(db_key.0.clone(), db_key.1.clone()));
+    };
+    let replication = column_family(&db, REPLICATION)?;
+    let acked_seq = read_u64(&db, replication, ACKED_KEY)?;
+    let seq = read_u64(&db, replication, SEQ_KEY)?;
+    if applied_seq < acked_seq || applied_seq > seq {
+        // it lost batches gone from the log, or applied those of another leader
+        // or of an earlier run: it must be set to follow anew
+        return Err(KvError::UnexpectedReplication);
+    }
+    let tx = db.transaction();
+    for seq in acked_seq + 1..=applied_seq {
+        tx.delete_cf(replication, log_key(seq))
+            .map_err(rocks_to_kv_err)?;
+    }
+    tx.put_cf(replication, ACKED_KEY, applied_seq.to_be_bytes())
+        .map_err(rocks_to_kv_err)?;
+    tx.commit().map_err(rocks_to_kv_err)?;
+    Ok(applied_seq < seq)
+}
+
+fn spawn_push(state: &KvState, db_key: &DbKey) {
+    let (state, db_key) = (state.clone(), db_key.clone());
+    tokio::spawn(async move {
+        if let Err(e) = push(state.clone(), db_key.clone()).await {
+            Printout::new(
+                1,
+                KV_PROCESS_ID.clone(),
+                format!(
+                    "kv: failed to push writes of [{}, {}] to its follower: {e}",
+                    db_key.0, db_key.1
+                ),
+            )
+            .send(&state.send_to_terminal)
+            .await;
+        }
+    });
+}
+
+/// Push the batches followers have not applied, every [`replication::RETRY_INTERVAL`],
+/// as those pushed before may have been lost, or written while a follower was offline.
+async fn retry_pushes(state: KvState) {
+    let mut interval = tokio::time::interval(replication::RETRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        for db_key in state.replications.leaders() {
+            spawn_push(&state, &db_key);
+        }
+    }
+}
+
+fn log_key(seq: u64) -> Vec<u8> {
+    [LOG_PREFIX, &seq.to_be_bytes()].concat()
+}
+
+fn read_u64(
+    db: &OptimisticTransactionDB,
+    column_family: &ColumnFamily,
+    key: &[u8],
+) -> Result<u64, KvError> {
+    Ok(db
+        .get_cf(column_family, key)
+        .map_err(rocks_to_kv_err)?
+        .and_then(|value| decode_u64(&value))
+        .unwrap_or(0))
+}
+
+fn is_conflict(error: &rocksdb::Error) -> bool {
+    matches!(error.kind(), ErrorKind::Busy | ErrorKind::TryAgain)
+}
+
+/// Delete the expired keys of the open dbs, every [`SWEEP_INTERVAL`]. Each db
+/// is swept in passes of [`SWEEP_BATCH`] expiries, off the runtime's threads.
+async fn sweep_expired(
+    open_kvs: Arc<DashMap<(PackageId, String), OptimisticTransactionDB>>,
+    send_to_terminal: PrintSender,
+) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let db_keys: Vec<(PackageId, String)> =
+            open_kvs.iter().map(|entry| entry.key().clone()).collect();
+        for db_key in db_keys {
+            let mut from: Option<Vec<u8>> = None;
+            loop {
+                let open_kvs = open_kvs.clone();
+                let pass_key = db_key.clone();
+                let pass = tokio::task::spawn_blocking(move || {
+                    let db = open_kvs.get(&pass_key)?;
+                    Some(remove_expired(&db, from.as_deref()))
+                })
+                .await;
+                match pass {
+                    Ok(Some(Ok(Some(next)))) => from = Some(next),
+                    Ok(Some(Err(e))) => {
+                        Printout::new(
+                            1,
+                            KV_PROCESS_ID.clone(),
+                            format!(
+                                "kv: failed to delete expired keys of [{}, {}]: {e}",
+                                db_key.0, db_key.1
+                            ),
+                        )
+                        .send(&send_to_terminal)
+                        .await;
+                        break;
+                    }
+                    // swept, closed meanwhile, or the pass panicked
+                    _ => break,
+                }
+            }
+        }
+    }
+}
+
+/// Delete the expired keys among up to [`SWEEP_BATCH`] expiries, starting at `from`,
+/// returning the key the next pass should start at, if any are left.
+fn remove_expired(
+    db: &OptimisticTransactionDB,
+    from: Option<&[u8]>,
+) -> Result<Option<Vec<u8>>, KvError> {
+    let expiries = column_family(db, EXPIRIES)?;
+    let now = now_ms();
+    let mode = match from {
+        Some(from) => IteratorMode::From(from, Direction::Forward),
+        None => IteratorMode::Start,
+    };
+    for (looked_at, item) in db.iterator_cf(expiries, mode).enumerate() {
+        let (key, expires_at) = item.map_err(rocks_to_kv_err)?;
+        if looked_at == SWEEP_BATCH {
+            return Ok(Some(key.to_vec()));
+        }
+        if decode_u64(&expires_at).is_some_and(|expires_at| expires_at > now) {
+            continue;
+        }
+        let tx = db.transaction();
+        // unless the key was set again since
+        if tx
+            .get_for_update_cf(expiries, &key, true)
+            .map_err(rocks_to_kv_err)?
+            .as_deref()
+            != Some(&*expires_at)
+        {
+            continue;
+        }
+        delete(&tx, expiries, &key).map_err(rocks_to_kv_err)?;
+        // failing only if the key was written meanwhile, to be swept next time if need be
+        let _ = tx.commit();
+    }
+    Ok(None)
+}
+
+fn set(
+    tx: &Transaction<OptimisticTransactionDB>,
+    expiries: &ColumnFamily,
+    key: &[u8],
+    value: &[u8],
+    expires_at: Option<u64>,
+) -> Result<(), rocksdb::Error> {
+    tx.put(key, value)?;
+    match expires_at {
+        Some(expires_at) => tx.put_cf(expiries, key, expires_at.to_be_bytes()),
+        None => tx.delete_cf(expiries, key),
+    }
+}
+
+fn delete(
+    tx: &Transaction<OptimisticTransactionDB>,
+    expiries: &ColumnFamily,
+    key: &[u8],
+) -> Result<(), rocksdb::Error> {
+    tx.delete(key)?;
+    tx.delete_cf(expiries, key)
+}
+
+fn column_family<'a>(
+    db: &'a OptimisticTransactionDB,
+    name: &str,
+) -> Result<&'a ColumnFamily, KvError> {
+    db.cf_handle(name)
+        .ok_or_else(|| KvError::RocksDBError(format!("missing column family {name}")))
+}
+
+fn expires_at(db: &OptimisticTransactionDB, key: &[u8]) -> Result<Option<u64>, KvError> {
+    Ok(db
+        .get_cf(column_family(db, EXPIRIES)?, key)
+        .map_err(rocks_to_kv_err)?
+        .and_then(|expires_at| decode_u64(&expires_at)))
+}
+
+fn is_expired(db: &OptimisticTransactionDB, key: &[u8]) -> Result<bool, KvError> {
+    Ok(expires_at(db, key)?.is_some_and(|expires_at| expires_at <= now_ms()))
+}
+
+fn decode_u64(bytes: &[u8]) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// The cipher of a package's encrypted dbs, keyed from the keyfile's file key.
+/// It encrypts values only: keys are stored in plaintext, to keep them in order.
+fn db_cipher(file_key: &[u8], package_id: &PackageId) -> Aes256Gcm {
+    let salt = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, b"kinode-kv");
+    let mut key = [0u8; 32];
+    salt.extract(file_key)
+        .expand(
+            &[
+                b"encrypted-dbs".as_slice(),
+                package_id.to_string().as_bytes(),
+            ],
+            ring::hkdf::HKDF_SHA256,
+        )
+        .unwrap()
+        .fill(&mut key)
+        .unwrap();
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+/// Encrypt a value as it is stored in an encrypted db: nonce, then ciphertext.
+fn encrypt_value(cipher: Option<&Aes256Gcm>, value: &[u8]) -> Vec<u8> {
+    let Some(cipher) = cipher else {
+        return value.to_vec();
+    };
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let mut encrypted = nonce.to_vec();
+    encrypted.extend(cipher.encrypt(&nonce, value).unwrap());
+    encrypted
+}
+
+fn decrypt_value(cipher: Option<&Aes256Gcm>, value: &[u8]) -> Result<Vec<u8>, KvError> {
+    let Some(cipher) = cipher else {
+        return Ok(value.to_vec());
+    };
+    if value.len() < NONCE_LEN {
+        return Err(KvError::DecryptionFailed);
+    }
+    let (nonce, ciphertext) = value.split_at(NONCE_LEN);
+    cipher
+        .decrypt(nonce.into(), ciphertext)
+        .map_err(|_| KvError::DecryptionFailed)
+}
+
 fn rocks_to_kv_err(error: rocksdb::Error) -> KvError {
     KvError::RocksDBError(error.to_string())
 }
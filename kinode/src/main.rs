@@ -14,6 +14,8 @@ use std::path::Path;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 
+mod backup;
+mod contacts;
 mod eth;
 #[cfg(feature = "simulation-mode")]
 mod fakenet;
@@ -23,14 +25,18 @@ mod kernel;
 mod keygen;
 mod kv;
 mod net;
+mod notify;
+mod profiles;
 #[cfg(not(feature = "simulation-mode"))]
 mod register;
+mod replication;
 mod sol;
 mod sqlite;
 mod state;
 mod terminal;
 mod timer;
 mod vfs;
+mod wallet;
 
 const EVENT_LOOP_CHANNEL_CAPACITY: usize = 100_000;
 const EVENT_LOOP_DEBUG_CHANNEL_CAPACITY: usize = 50;
@@ -44,12 +50,21 @@ const CAP_CHANNEL_CAPACITY: usize = 1_000;
 const KV_CHANNEL_CAPACITY: usize = 1_000;
 const SQLITE_CHANNEL_CAPACITY: usize = 1_000;
 const FD_MANAGER_CHANNEL_CAPACITY: usize = 1_000;
+const WALLET_CHANNEL_CAPACITY: usize = 32;
+const BACKUP_CHANNEL_CAPACITY: usize = 32;
+const NOTIFY_CHANNEL_CAPACITY: usize = 32;
+const CONTACTS_CHANNEL_CAPACITY: usize = 32;
 const WS_MIN_PORT: u16 = 9_000;
 const TCP_MIN_PORT: u16 = 10_000;
 const MAX_PORT: u16 = 65_535;
 
 const DEFAULT_MAX_PEERS: u64 = 32;
 const DEFAULT_MAX_PASSTHROUGHS: u64 = 0;
+const DEFAULT_KEEPALIVE_INTERVAL_SECS: u64 = 30;
+const DEFAULT_KEEPALIVE_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_COMPRESSION_THRESHOLD: u64 = 16_384;
+const DEFAULT_HTTP_RATE_LIMIT: u32 = 1200;
+const DEFAULT_AUTH_TOKEN_LIFETIME_DAYS: u64 = 30;
 
 /// default routers as a eth-provider fallback
 const DEFAULT_ETH_PROVIDERS: &str = include_str!("eth/default_providers_mainnet.json");
@@ -68,19 +83,74 @@ async fn main() {
     let app = build_command();
 
     let matches = app.get_matches();
-    let home_directory_path = matches
-        .get_one::<String>("home")
-        .expect("home directory required");
+    if *matches.get_one::<bool>("list-profiles").unwrap() {
+        match profiles::Profiles::load().await {
+            Ok(profiles) => print!("{profiles}"),
+            Err(e) => println!("failed to load profiles: {e}"),
+        }
+        return;
+    }
+    if let Some(name) = matches.get_one::<String>("remove-profile") {
+        let mut profiles = profiles::Profiles::load()
+            .await
+            .expect("failed to load profiles");
+        match profiles.remove(name) {
+            Some(profile) => {
+                profiles.save().await.expect("failed to save profiles");
+                println!(
+                    "removed profile {name}, its home {} is left as it was",
+                    profile.home.display()
+                );
+            }
+            None => println!("no profile named {name}"),
+        }
+        return;
+    }
+    let profile = match profiles::select(&matches).await {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        }
+    };
+    let home_directory_path = &profile.home;
     if let Err(e) = tokio::fs::create_dir_all(home_directory_path).await {
         panic!("failed to create home directory: {e:?}");
     }
-    let home_directory_path = std::fs::canonicalize(&home_directory_path).expect(&format!(
-        "specified home directory {home_directory_path} not found"
+    let home_directory_path = std::fs::canonicalize(home_directory_path).expect(&format!(
+        "specified home directory {} not found",
+        home_directory_path.display()
     ));
-    let http_server_port = set_http_server_port(matches.get_one::<u16>("port")).await;
-    let ws_networking_port = matches.get_one::<u16>("ws-port");
+    if let Some(target) = matches.get_one::<String>("backup") {
+        let passphrase = backup_passphrase(&matches, true);
+        match backup::backup(&home_directory_path, Path::new(target), &passphrase).await {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("backup failed: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(target) = matches.get_one::<String>("restore-backup") {
+        let passphrase = backup_passphrase(&matches, false);
+        if let Err(e) = backup::restore(&home_directory_path, Path::new(target), &passphrase).await
+        {
+            eprintln!("restore failed: {e}");
+            std::process::exit(1);
+        }
+    }
+    // a backup restored from settings is applied before the node logs in.
+    // one that fails is set aside, and the node boots as it was
+    let backup_passphrase = matches.get_one::<String>("backup-passphrase");
+    if let Err(e) =
+        backup::restore_staged(&home_directory_path, backup_passphrase.map(String::as_str)).await
+    {
+        eprintln!("restore failed, booting without it: {e}");
+    }
+    let http_server_port = set_http_server_port(profile.port.as_ref()).await;
+    let ws_networking_port = profile.ws_port.as_ref();
     #[cfg(not(feature = "simulation-mode"))]
-    let tcp_networking_port = matches.get_one::<u16>("tcp-port");
+    let tcp_networking_port = profile.tcp_port.as_ref();
     let verbose_mode = *matches
         .get_one::<u8>("verbosity")
         .expect("verbosity required");
@@ -94,6 +164,10 @@ async fn main() {
 
     // detached determines whether terminal is interactive
     let detached = *matches.get_one::<bool>("detached").unwrap();
+    let auth_token_lifetime = matches
+        .get_one::<u64>("auth-token-lifetime")
+        .unwrap_or(&DEFAULT_AUTH_TOKEN_LIFETIME_DAYS)
+        * 86_400;
 
     let process_verbosity = matches.get_one::<String>("process-verbosity").unwrap();
     let process_verbosity: ProcessVerbosity = if process_verbosity.is_empty() {
@@ -198,12 +272,36 @@ async fn main() {
     // terminal receives prints via this channel, all other modules send prints
     let (print_sender, print_receiver): (PrintSender, PrintReceiver) =
         mpsc::channel(TERMINAL_CHANNEL_CAPACITY);
+    // terminal receives responses to its own requests, such as tab completions, via this channel
+    let (terminal_sender, terminal_receiver): (MessageSender, MessageReceiver) =
+        mpsc::channel(TERMINAL_CHANNEL_CAPACITY);
+    // wallet signs for processes, with the user's consent
+    let (wallet_sender, wallet_receiver): (MessageSender, MessageReceiver) =
+        mpsc::channel(WALLET_CHANNEL_CAPACITY);
+    // backup makes backups of the node while it runs, for settings
+    let (backup_sender, backup_receiver): (MessageSender, MessageReceiver) =
+        mpsc::channel(BACKUP_CHANNEL_CAPACITY);
+    // notify turns events on the node into notifications, delivered as set in settings
+    let (notify_sender, notify_receiver): (MessageSender, MessageReceiver) =
+        mpsc::channel(NOTIFY_CHANNEL_CAPACITY);
+    // contacts keeps the address book of the node, shared by every app that has access
+    let (contacts_sender, contacts_receiver): (MessageSender, MessageReceiver) =
+        mpsc::channel(CONTACTS_CHANNEL_CAPACITY);
 
     let our_ip = find_public_ip().await;
     let (ws_tcp_handle, ws_flag_used) = setup_networking("ws", ws_networking_port).await;
     #[cfg(not(feature = "simulation-mode"))]
     let (tcp_tcp_handle, tcp_flag_used) = setup_networking("tcp", tcp_networking_port).await;
 
+    // a fakechain launched here runs until the node exits
+    #[cfg(feature = "simulation-mode")]
+    let _fakechain = match matches.get_one::<String>("fakechain-state") {
+        Some(state) => fakenet::launch_fakechain(fakechain_port.unwrap_or(8545), Path::new(state))
+            .await
+            .expect("failed to launch fakechain"),
+        None => None,
+    };
+
     #[cfg(feature = "simulation-mode")]
     let (our, encoded_keyfile, decoded_keyfile) = simulate_node(
         fake_node_name.cloned(),
@@ -218,6 +316,13 @@ async fn main() {
     )
     .await;
 
+    #[cfg(feature = "simulation-mode")]
+    if let Some(fixtures) = matches.get_one::<String>("fakechain-fixtures") {
+        fakenet::apply_fixtures(Path::new(fixtures), fakechain_port.unwrap_or(8545))
+            .await
+            .expect("failed to apply fakechain fixtures");
+    }
+
     #[cfg(not(feature = "simulation-mode"))]
     println!(
         "Welcome to Kinode.\nThe time is {}.\nLogin or register at http://localhost:{}\r",
@@ -235,6 +340,7 @@ async fn main() {
                 http_server_port,
                 rpc.cloned(),
                 detached,
+                auth_token_lifetime,
             )
             .await
         }
@@ -309,6 +415,36 @@ async fn main() {
             None,
             false,
         ),
+        (
+            ProcessId::new(Some("terminal"), "distro", "sys"),
+            terminal_sender,
+            None,
+            false,
+        ),
+        (
+            ProcessId::new(Some("wallet"), "distro", "sys"),
+            wallet_sender,
+            None,
+            false,
+        ),
+        (
+            ProcessId::new(Some("backup"), "distro", "sys"),
+            backup_sender,
+            None,
+            false,
+        ),
+        (
+            ProcessId::new(Some("notify"), "distro", "sys"),
+            notify_sender,
+            None,
+            false,
+        ),
+        (
+            ProcessId::new(Some("contacts"), "distro", "sys"),
+            contacts_sender,
+            None,
+            false,
+        ),
     ];
 
     /*
@@ -320,9 +456,7 @@ async fn main() {
     let networking_keypair_arc = Arc::new(decoded_keyfile.networking_keypair);
     let our_name_arc = Arc::new(our.name.clone());
 
-    let home_directory_string = matches
-        .get_one::<String>("home")
-        .expect("home directory required");
+    let home_directory_string = home_directory_path.to_string_lossy().to_string();
     let (kernel_process_map, db, reverse_cap_index) = state::load_state(
         our.name.clone(),
         networking_keypair_arc.clone(),
@@ -333,6 +467,9 @@ async fn main() {
     .expect("state load failed!");
 
     let mut tasks = tokio::task::JoinSet::<Result<()>>::new();
+    // processes that start on demand and have not yet been started, filled by
+    // the kernel and read by http-server, which starts them on their first request
+    let dormant_processes = kernel::DormantProcesses::default();
     tasks.spawn(kernel::kernel(
         our.clone(),
         networking_keypair_arc.clone(),
@@ -362,6 +499,7 @@ async fn main() {
                 }
             })
             .collect(),
+        dormant_processes.clone(),
     ));
     tasks.spawn(net::networking(
         our.clone(),
@@ -378,6 +516,17 @@ async fn main() {
         *matches
             .get_one::<u64>("max-passthroughs")
             .unwrap_or(&DEFAULT_MAX_PASSTHROUGHS),
+        *matches
+            .get_one::<u64>("keepalive-interval")
+            .unwrap_or(&DEFAULT_KEEPALIVE_INTERVAL_SECS),
+        *matches
+            .get_one::<u64>("keepalive-timeout")
+            .unwrap_or(&DEFAULT_KEEPALIVE_TIMEOUT_SECS),
+        *matches
+            .get_one::<u64>("compression-threshold")
+            .unwrap_or(&DEFAULT_COMPRESSION_THRESHOLD),
+        *matches.get_one::<bool>("lan-discovery").unwrap(),
+        home_directory_path.clone(),
     ));
     tasks.spawn(state::state_sender(
         our_name_arc.clone(),
@@ -401,6 +550,7 @@ async fn main() {
         kv_receiver,
         caps_oracle_sender.clone(),
         home_directory_path.clone(),
+        decoded_keyfile.file_key.clone(),
     ));
     tasks.spawn(sqlite::sqlite(
         our_name_arc.clone(),
@@ -409,6 +559,7 @@ async fn main() {
         sqlite_receiver,
         caps_oracle_sender.clone(),
         home_directory_path.clone(),
+        decoded_keyfile.file_key.clone(),
     ));
     tasks.spawn(http::server::http_server(
         our.name.clone(),
@@ -418,12 +569,21 @@ async fn main() {
         http_server_receiver,
         kernel_message_sender.clone(),
         print_sender.clone(),
+        *matches
+            .get_one::<u32>("http-rate-limit")
+            .unwrap_or(&DEFAULT_HTTP_RATE_LIMIT),
+        auth_token_lifetime,
+        matches.get_one::<u16>("https-port").copied(),
+        *matches.get_one::<bool>("http-access-log").unwrap(),
+        home_directory_path.clone(),
+        dormant_processes,
     ));
     tasks.spawn(http::client::http_client(
         our.name.clone(),
         kernel_message_sender.clone(),
         http_client_receiver,
         print_sender.clone(),
+        home_directory_path.clone(),
     ));
     tasks.spawn(timer::timer_service(
         our.name.clone(),
@@ -448,6 +608,39 @@ async fn main() {
         vfs_message_receiver,
         caps_oracle_sender.clone(),
         home_directory_path.clone(),
+        decoded_keyfile.file_key.clone(),
+    ));
+    tasks.spawn(wallet::wallet(
+        our.name.clone(),
+        kernel_message_sender.clone(),
+        print_sender.clone(),
+        wallet_receiver,
+        caps_oracle_sender.clone(),
+        home_directory_path.clone(),
+        decoded_keyfile.file_key.clone(),
+    ));
+    tasks.spawn(backup::backups(
+        our.name.clone(),
+        kernel_message_sender.clone(),
+        backup_receiver,
+        caps_oracle_sender.clone(),
+        home_directory_path.clone(),
+    ));
+    tasks.spawn(notify::notify(
+        our.name.clone(),
+        kernel_message_sender.clone(),
+        print_sender.clone(),
+        notify_receiver,
+        caps_oracle_sender.clone(),
+        home_directory_path.clone(),
+        decoded_keyfile.file_key.clone(),
+    ));
+    tasks.spawn(contacts::contacts(
+        our.name.clone(),
+        kernel_message_sender.clone(),
+        contacts_receiver,
+        caps_oracle_sender.clone(),
+        home_directory_path.clone(),
     ));
 
     // if a runtime task exits, try to recover it,
@@ -469,6 +662,7 @@ async fn main() {
             kernel_debug_message_sender,
             print_sender.clone(),
             print_receiver,
+            terminal_receiver,
             detached,
             verbose_mode,
             is_logging,
@@ -664,8 +858,36 @@ pub async fn simulate_node(
     }
 }
 
+/// the passphrase given with --backup-passphrase, or else prompted for,
+/// twice if `confirm` so that a typo doesn't lock away a new backup
+fn backup_passphrase(matches: &clap::ArgMatches, confirm: bool) -> String {
+    if let Some(passphrase) = matches.get_one::<String>("backup-passphrase") {
+        return passphrase.clone();
+    }
+    loop {
+        let passphrase = backup::read_passphrase("backup passphrase: ").unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            std::process::exit(1);
+        });
+        if passphrase.is_empty() {
+            println!("the passphrase can't be empty\r");
+            continue;
+        }
+        if !confirm {
+            return passphrase;
+        }
+        match backup::read_passphrase("confirm passphrase: ") {
+            Ok(confirmed) if confirmed == passphrase => return passphrase,
+            Ok(_) => println!("passphrases don't match\r"),
+            Err(e) => {
+                eprintln!("error: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
 /// build the command line interface for kinode
-///
 fn build_command() -> Command {
     // embed values in binary for inspection without running & print on boot
     //  e.g., to inspect without running, use
@@ -684,11 +906,34 @@ fn build_command() -> Command {
         .version(version)
         .author("Sybil Technologies AG")
         .about("A General Purpose Sovereign Cloud Computing Platform")
-        .arg(arg!([home] "Path to home directory").required(true))
+        .arg(arg!([home] "Path to home directory [default: that of the last profile booted]"))
+        .arg(
+            arg!(--profile <NAME> "Boot the home saved as this profile, with its ports")
+                .conflicts_with("home"),
+        )
+        .arg(
+            arg!(--"save-profile" <NAME> "Save the home and ports given as this profile")
+                .requires("home"),
+        )
+        .arg(
+            arg!(--"list-profiles" "List the saved profiles and exit")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(arg!(--"remove-profile" <NAME> "Forget this profile, leaving its home, and exit"))
+        .arg(
+            arg!(--backup <TARGET> "Back up the node's keyfile and settings, encrypted, to the target in this JSON file, and exit")
+                .conflicts_with("restore-backup"),
+        )
+        .arg(arg!(--"restore-backup" <TARGET> "Restore a backup from the target in this JSON file into an empty home, then boot"))
+        .arg(arg!(--"backup-passphrase" <PASSPHRASE> "Passphrase of the backup [default: prompt for it]"))
         .arg(
             arg!(-p --port <PORT> "Port to bind [default: first unbound at or above 8080]")
                 .value_parser(value_parser!(u16)),
         )
+        .arg(
+            arg!(--"https-port" <PORT> "Port to serve HTTPS on, for the custom domains given a TLS certificate in settings [default: off]")
+                .value_parser(value_parser!(u16)),
+        )
         .arg(
             arg!(--"ws-port" <PORT> "Kinode internal WebSockets protocol port [default: first unbound at or above 9000]")
                 .alias("--ws-port")
@@ -735,6 +980,34 @@ fn build_command() -> Command {
             arg!(--"max-passthroughs" <MAX_PASSTHROUGHS> "Maximum number of passthroughs serve as a router (default 0)")
                 .value_parser(value_parser!(u64)),
         )
+        .arg(
+            arg!(--"keepalive-interval" <SECS> "Seconds a peer connection can be quiet before the peer is pinged (default 30)")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(--"keepalive-timeout" <SECS> "Seconds a pinged peer has to answer before its connection is dropped and reopened (default 10)")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(--"compression-threshold" <BYTES> "Size in bytes over which messages to other nodes are compressed; setting to 0 -> never compress (default 16384)")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"lan-discovery" "Advertise this node on the local network with mDNS, and connect directly to other nodes found there")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"http-rate-limit" <REQUESTS_PER_MINUTE> "Maximum HTTP requests per minute from each client IP address; setting to 0 -> no limit (default 1200)")
+                .value_parser(value_parser!(u32)),
+        )
+        .arg(
+            arg!(--"auth-token-lifetime" <DAYS> "Days until a login expires, unless refreshed by an open web UI (default 30)")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"http-access-log" "Log each HTTP request served to the distro:sys VFS drive, for auditing through settings")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             arg!(--"soft-ulimit" <SOFT_ULIMIT> "Enforce a static maximum number of file descriptors (default fetched from system)")
                 .value_parser(value_parser!(u64)),
@@ -750,7 +1023,9 @@ fn build_command() -> Command {
         .arg(
             arg!(--"fakechain-port" <FAKECHAIN_PORT> "Port to bind to for fakechain")
                 .value_parser(value_parser!(u16)),
-        );
+        )
+        .arg(arg!(--"fakechain-state" <PATH> "Launch anvil on the fakechain port with this state dump, unless a chain is running there already"))
+        .arg(arg!(--"fakechain-fixtures" <PATH> "JSON file of names and app listings to put on the fakechain at boot"));
     app
 }
 
@@ -795,12 +1070,17 @@ async fn serve_register_fe(
     http_server_port: u16,
     maybe_rpc: Option<String>,
     detached: bool,
+    auth_token_lifetime: u64,
 ) -> (Identity, Vec<u8>, Keyfile) {
     let (kill_tx, kill_rx) = tokio::sync::oneshot::channel::<bool>();
 
     let disk_keyfile: Option<Vec<u8>> = tokio::fs::read(home_directory_path.join(".keys"))
         .await
         .ok();
+    // auth tokens must be signed with the key http-server will verify them with
+    let jwt_salt = tokio::fs::read(home_directory_path.join(http::utils::JWT_SALT_FILE))
+        .await
+        .unwrap_or_default();
 
     let (tx, mut rx) = mpsc::channel::<(Identity, Keyfile, Vec<u8>)>(1);
     let (our, decoded_keyfile, encoded_keyfile) = tokio::select! {
@@ -813,7 +1093,11 @@ async fn serve_register_fe(
                 http_server_port,
                 disk_keyfile,
                 maybe_rpc,
-                detached) => {
+                detached,
+                register::AuthTokenConfig {
+                    jwt_salt,
+                    lifetime: auth_token_lifetime,
+                }) => {
             panic!("registration failed")
         }
         Some((our, decoded_keyfile, encoded_keyfile)) = rx.recv() => {
@@ -0,0 +1,107 @@
+use lib::types::core::{Identity, NodeRouting, PeerAccess};
+use {
+    dashmap::DashSet,
+    std::path::{Path, PathBuf},
+    std::sync::atomic::{AtomicBool, Ordering},
+    tokio::io::AsyncWriteExt,
+};
+
+const ACCESS_FILE: &str = ".net_peer_access";
+
+/// Which peers we connect and exchange messages with, set by the operator
+/// with [`lib::core::NetAction::BlockPeer`] and friends. Persisted in the
+/// home directory, unlike the rest of networking state.
+pub struct PeerAccessList {
+    file: PathBuf,
+    blocked: DashSet<String>,
+    allowed: DashSet<String>,
+    allowlist_only: AtomicBool,
+}
+
+impl PeerAccessList {
+    pub async fn load(home_directory_path: &Path) -> Self {
+        let file = home_directory_path.join(ACCESS_FILE);
+        let access: PeerAccess = tokio::fs::read(&file)
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Self {
+            file,
+            blocked: access.blocked.into_iter().collect(),
+            allowed: access.allowed.into_iter().collect(),
+            allowlist_only: AtomicBool::new(access.allowlist_only),
+        }
+    }
+
+    /// whether we may connect and exchange messages with `name`. blocked peers never
+    /// may. in allowlist-only mode, only allowed peers and our own routers may.
+    pub fn permits(&self, our: &Identity, name: &str) -> bool {
+        if self.blocked.contains(name) {
+            return false;
+        }
+        if !self.allowlist_only.load(Ordering::Relaxed) || self.allowed.contains(name) {
+            return true;
+        }
+        match &our.routing {
+            NodeRouting::Routers(routers) | NodeRouting::Both { routers, .. } => {
+                routers.iter().any(|router| router == name)
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn block(&self, name: String) {
+        self.blocked.insert(name);
+        self.persist().await;
+    }
+
+    pub async fn unblock(&self, name: &str) {
+        self.blocked.remove(name);
+        self.persist().await;
+    }
+
+    pub async fn allow(&self, name: String) {
+        self.allowed.insert(name);
+        self.persist().await;
+    }
+
+    pub async fn disallow(&self, name: &str) {
+        self.allowed.remove(name);
+        self.persist().await;
+    }
+
+    pub async fn set_allowlist_only(&self, allowlist_only: bool) {
+        self.allowlist_only.store(allowlist_only, Ordering::Relaxed);
+        self.persist().await;
+    }
+
+    pub fn get(&self) -> PeerAccess {
+        let mut blocked: Vec<String> = self.blocked.iter().map(|name| name.clone()).collect();
+        let mut allowed: Vec<String> = self.allowed.iter().map(|name| name.clone()).collect();
+        blocked.sort();
+        allowed.sort();
+        PeerAccess {
+            blocked,
+            allowed,
+            allowlist_only: self.allowlist_only.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Written aside and renamed into place: a partial file would be read as no
+    /// access list at all, lifting every block.
+    async fn persist(&self) {
+        let result: std::io::Result<()> = async {
+            let access = serde_json::to_vec(&self.get()).unwrap();
+            let tmp_path = self.file.with_extension("tmp");
+            let mut file = tokio::fs::File::create(&tmp_path).await?;
+            file.write_all(&access).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&tmp_path, &self.file).await
+        }
+        .await;
+        if let Err(e) = result {
+            println!("net: failed to persist {ACCESS_FILE}: {e}\r");
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Compression of large messages between nodes.
+//!
+//! Each end of a new connection sends a [`NetResponse::CompressionOffer`]. Once we
+//! have the peer's offer, messages whose serialized size is over both our threshold
+//! and theirs are sent as a [`NetResponse::Compressed`], if that makes them smaller.
+//! Blobs are serialized with their message, so are compressed along with it. Peers
+//! that never make an offer, such as those running versions without compression,
+//! are always sent messages as they are.
+use crate::net::utils::{net_response, MESSAGE_MAX_SIZE};
+use lib::types::core::{CompressionAlgorithm, KernelMessage, Message, NetResponse, NET_PROCESS_ID};
+use std::sync::OnceLock;
+
+/// the algorithms we take, in order of preference
+const ALGORITHMS: [CompressionAlgorithm; 2] =
+    [CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4];
+const ZSTD_LEVEL: i32 = 3;
+
+/// compression over one connection
+pub struct Compression {
+    /// our threshold, or None if we don't compress
+    threshold: Option<u64>,
+    /// the algorithm and threshold to send with, once the peer has made an offer
+    negotiated: OnceLock<(CompressionAlgorithm, u64)>,
+}
+
+impl Compression {
+    pub fn new(threshold: Option<u64>) -> Self {
+        Self {
+            threshold,
+            negotiated: OnceLock::new(),
+        }
+    }
+
+    /// our offer, to send over the connection before anything else, if we compress
+    pub fn offer(&self, our: &str, peer: &str) -> Option<KernelMessage> {
+        let threshold = self.threshold?;
+        Some(net_response(
+            our,
+            peer,
+            NetResponse::CompressionOffer {
+                algorithms: ALGORITHMS.to_vec(),
+                threshold,
+            },
+        ))
+    }
+
+    /// `km`, compressed if the peer takes compressed messages and it is worth it
+    pub fn compress(&self, km: KernelMessage) -> KernelMessage {
+        let Some((algorithm, threshold)) = self.negotiated.get() else {
+            return km;
+        };
+        let Ok(serialized) = rmp_serde::to_vec(&km) else {
+            return km;
+        };
+        let len = serialized.len() as u64;
+        // too-large messages are left to fail as they would uncompressed
+        if len < *threshold || len > MESSAGE_MAX_SIZE as u64 {
+            return km;
+        }
+        let data = match algorithm {
+            CompressionAlgorithm::Zstd => match zstd::bulk::compress(&serialized, ZSTD_LEVEL) {
+                Ok(data) => data,
+                Err(_) => return km,
+            },
+            CompressionAlgorithm::Lz4 => lz4_flex::compress(&serialized),
+        };
+        if data.len() >= serialized.len() {
+            return km;
+        }
+        net_response(
+            &km.source.node,
+            &km.target.node,
+            NetResponse::Compressed {
+                algorithm: *algorithm,
+                len,
+                data,
+            },
+        )
+    }
+
+    /// Take in a message from the peer: unpack it if it is compressed, and keep
+    /// their offer if it is one. Returns the message to pass on, if any.
+    pub fn receive(&self, km: KernelMessage) -> anyhow::Result<Option<KernelMessage>> {
+        if km.source.process != *NET_PROCESS_ID || km.target.process != *NET_PROCESS_ID {
+            return Ok(Some(km));
+        }
+        let Message::Response((response, _)) = &km.message else {
+            return Ok(Some(km));
+        };
+        match rmp_serde::from_slice(&response.body) {
+            Ok(NetResponse::CompressionOffer {
+                algorithms,
+                threshold,
+            }) => {
+                if let Some(ours) = self.threshold {
+                    if let Some(algorithm) = ALGORITHMS.iter().find(|a| algorithms.contains(a)) {
+                        let _ = self.negotiated.set((*algorithm, ours.max(threshold)));
+                    }
+                }
+                Ok(None)
+            }
+            Ok(NetResponse::Compressed {
+                algorithm,
+                len,
+                data,
+            }) => {
+                if len > MESSAGE_MAX_SIZE as u64 {
+                    return Err(anyhow::anyhow!("compressed message too large"));
+                }
+                let serialized = match algorithm {
+                    CompressionAlgorithm::Zstd => zstd::bulk::decompress(&data, len as usize)?,
+                    CompressionAlgorithm::Lz4 => lz4_flex::decompress(&data, len as usize)?,
+                };
+                if serialized.len() as u64 != len {
+                    return Err(anyhow::anyhow!("compressed message has wrong length"));
+                }
+                Ok(Some(rmp_serde::from_slice(&serialized)?))
+            }
+            _ => Ok(Some(km)),
+        }
+    }
+}
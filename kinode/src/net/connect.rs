@@ -1,12 +1,21 @@
-use crate::net::types::{IdentityExt, NetData, Peer};
-use crate::net::{tcp, utils, ws};
+use crate::net::types::{IdentityExt, NetData, Peer, PeerReceiver};
+use crate::net::{punch, simulate, tcp, utils, ws};
 use lib::types::core::{Identity, KernelMessage, NodeRouting};
 use rand::prelude::SliceRandom;
-use tokio::sync::mpsc;
+
+/// send a message to another node, under any network conditions we simulate
+pub async fn send_to_peer(ext: &IdentityExt, data: &NetData, km: KernelMessage) {
+    if let Some(km) = simulate::apply(ext, data, km).await {
+        deliver(ext, data, km).await;
+    }
+}
 
 /// if target is a peer, queue to be routed
 /// otherwise, create peer and initiate routing
-pub async fn send_to_peer(ext: &IdentityExt, data: &NetData, mut km: KernelMessage) {
+pub async fn deliver(ext: &IdentityExt, data: &NetData, mut km: KernelMessage) {
+    if !data.access.permits(&ext.our, &km.target.node) {
+        return utils::error_offline(km, &ext.network_error_tx).await;
+    }
     if let Some(mut peer) = data.peers.get_mut(&km.target.node) {
         match peer.send(km) {
             Ok(()) => {
@@ -43,8 +52,9 @@ pub async fn send_to_peer(ext: &IdentityExt, data: &NetData, mut km: KernelMessa
     ));
 }
 
-/// based on peer's identity, either use one of their
-/// protocols to connect directly, or loop through their
+/// if we found the peer on the local network, connect to it there.
+/// otherwise, or if that fails, based on peer's identity, either use
+/// one of their protocols to connect directly, or loop through their
 /// routers to open a passthroughconnection for us
 ///
 /// if we fail to connect, remove the peer from the map
@@ -53,8 +63,23 @@ async fn connect_to_peer(
     ext: IdentityExt,
     data: NetData,
     peer_id: Identity,
-    peer_rx: mpsc::UnboundedReceiver<KernelMessage>,
+    peer_rx: PeerReceiver,
 ) {
+    let lan_addr = data.lan_peers.get(&peer_id.name).map(|addr| *addr);
+    let peer_rx = match lan_addr {
+        Some(addr) => match tcp::init_lan(&ext, &data, &peer_id, addr, peer_rx).await {
+            Ok(()) => {
+                utils::print_debug(
+                    &ext.print_tx,
+                    &format!("net: connected to {} on the local network", peer_id.name),
+                )
+                .await;
+                return;
+            }
+            Err(peer_rx) => peer_rx,
+        },
+        None => peer_rx,
+    };
     if peer_id.is_direct() {
         utils::print_debug(
             &ext.print_tx,
@@ -101,14 +126,16 @@ async fn connect_via_router(
     ext: &IdentityExt,
     data: &NetData,
     peer_id: &Identity,
-    mut peer_rx: mpsc::UnboundedReceiver<KernelMessage>,
+    mut peer_rx: PeerReceiver,
 ) {
     let routers_shuffled = {
         let mut routers = match peer_id.routing {
             NodeRouting::Routers(ref routers) => routers.clone(),
             _ => vec![],
         };
+        // shuffled so that equally healthy routers share the load
         routers.shuffle(&mut rand::thread_rng());
+        data.router_scores.sort(&mut routers);
         routers
     };
     for router_name in &routers_shuffled {
@@ -128,9 +155,12 @@ async fn connect_via_router(
                         &format!("net: connected to {}", router_id.name),
                     )
                     .await;
+                    data.router_scores.record_success(&router_id.name, None);
+                    tokio::spawn(punch::start(ext.clone(), data.clone(), peer_id.clone()));
                     return;
                 }
                 Err(e) => {
+                    data.router_scores.record_failure(&router_id.name);
                     peer_rx = e;
                     continue;
                 }
@@ -144,9 +174,12 @@ async fn connect_via_router(
                         &format!("net: connected to {}", router_id.name),
                     )
                     .await;
+                    data.router_scores.record_success(&router_id.name, None);
+                    tokio::spawn(punch::start(ext.clone(), data.clone(), peer_id.clone()));
                     return;
                 }
                 Err(e) => {
+                    data.router_scores.record_failure(&router_id.name);
                     peer_rx = e;
                     continue;
                 }
@@ -160,16 +193,19 @@ pub async fn handle_failed_connection(
     ext: &IdentityExt,
     data: &NetData,
     peer_id: &Identity,
-    mut peer_rx: mpsc::UnboundedReceiver<KernelMessage>,
+    mut peer_rx: PeerReceiver,
 ) {
     utils::print_debug(
         &ext.print_tx,
         &format!("net: failed to connect to {}", peer_id.name),
     )
     .await;
+    data.peers.record_connection_failure(&peer_id.name);
     data.peers.remove(&peer_id.name).await;
     peer_rx.close();
     while let Some(km) = peer_rx.recv().await {
-        utils::error_offline(km, &ext.network_error_tx).await;
+        if let Err(km) = data.offline_queues.hold(km) {
+            utils::error_offline(km, &ext.network_error_tx).await;
+        }
     }
 }
@@ -0,0 +1,277 @@
+//! A pass/fail report on whether other nodes can reach us, for
+//! [`NetAction::RunDiagnostics`](lib::types::core::NetAction::RunDiagnostics).
+//!
+//! Direct nodes check that each of their ports accepts connections at their
+//! public IP. Indirect nodes check that they are connected to their routers.
+//! Both check that the KNS record other nodes find them by matches how they
+//! are running: its networking key, and its IP and ports or its routers.
+use crate::net::{
+    types::{IdentityExt, NetData},
+    utils::TIMEOUT,
+};
+use lib::types::core::{
+    DiagnosticCheck, Identity, KernelMessage, Message, NetResponse, NodeRouting, Response,
+};
+use tokio::{net::TcpStream, time};
+
+/// Run the checks and answer the request for them. Reaching our own ports
+/// may take a while, so this is spawned rather than awaited.
+pub async fn respond(ext: IdentityExt, data: NetData, km: KernelMessage) {
+    let report = NetResponse::DiagnosticsReport(run(&ext, &data).await);
+    KernelMessage::builder()
+        .id(km.id)
+        .source((ext.our.name.as_str(), "net", "distro", "sys"))
+        .target(km.rsvp.unwrap_or(km.source))
+        .message(Message::Response((
+            Response {
+                inherit: false,
+                body: rmp_serde::to_vec(&report).expect("net: failed to serialize response"),
+                metadata: None,
+                capabilities: vec![],
+            },
+            None,
+        )))
+        .build()
+        .unwrap()
+        .send(&ext.kernel_message_tx)
+        .await;
+}
+
+async fn run(ext: &IdentityExt, data: &NetData) -> Vec<DiagnosticCheck> {
+    let mut checks = vec![];
+    let record: Option<Identity> = data.pki.get(&ext.our.name).map(|r| r.clone());
+    match &record {
+        Some(record) => {
+            checks.push(check(
+                "KNS record",
+                true,
+                format!("found the record of {}", ext.our.name),
+            ));
+            checks.push(if record.networking_key == ext.our.networking_key {
+                check("networking key", true, "matches our KNS record".to_string())
+            } else {
+                check(
+                    "networking key",
+                    false,
+                    format!(
+                        "our KNS record has {}, but we run with {}: other nodes will fail \
+                         to connect to us until the record is updated",
+                        record.networking_key, ext.our.networking_key
+                    ),
+                )
+            });
+        }
+        None => checks.push(check(
+            "KNS record",
+            false,
+            format!(
+                "no record of {} yet: the indexer may still be syncing, try again soon",
+                ext.our.name
+            ),
+        )),
+    }
+
+    match &ext.our.routing {
+        NodeRouting::Direct { ports, .. } => {
+            if let Some(record) = &record {
+                check_direct_record(ext, record, ports, &mut checks);
+            }
+            for (protocol, port) in ports {
+                checks.push(check_port(&ext.our_ip, protocol, *port).await);
+            }
+        }
+        NodeRouting::Routers(routers) | NodeRouting::Both { routers, .. } => {
+            if let Some(record) = &record {
+                check_indirect_record(record, routers, &mut checks);
+            }
+            check_routers(data, routers, &mut checks);
+        }
+    }
+    checks
+}
+
+fn check(name: &str, passed: bool, detail: String) -> DiagnosticCheck {
+    DiagnosticCheck {
+        name: name.to_string(),
+        passed,
+        detail,
+    }
+}
+
+fn check_direct_record(
+    ext: &IdentityExt,
+    record: &Identity,
+    ports: &std::collections::BTreeMap<String, u16>,
+    checks: &mut Vec<DiagnosticCheck>,
+) {
+    let NodeRouting::Direct {
+        ip: record_ip,
+        ports: record_ports,
+    } = &record.routing
+    else {
+        checks.push(check(
+            "KNS routing",
+            false,
+            "our KNS record lists routers, but we run as a direct node: other nodes \
+             will try to reach us through them until the record is updated"
+                .to_string(),
+        ));
+        return;
+    };
+    checks.push(if *record_ip == *ext.our_ip {
+        check(
+            "KNS IP",
+            true,
+            format!("our KNS record has our public IP, {record_ip}"),
+        )
+    } else {
+        check(
+            "KNS IP",
+            false,
+            format!(
+                "our KNS record has {record_ip}, but our public IP is {}: update the record",
+                ext.our_ip
+            ),
+        )
+    });
+    checks.push(if record_ports == ports {
+        check(
+            "KNS ports",
+            true,
+            format!(
+                "our KNS record has the ports we listen on, {}",
+                ports_string(ports)
+            ),
+        )
+    } else {
+        check(
+            "KNS ports",
+            false,
+            format!(
+                "our KNS record has {}, but we listen on {}: update the record",
+                ports_string(record_ports),
+                ports_string(ports)
+            ),
+        )
+    });
+}
+
+fn check_indirect_record(record: &Identity, routers: &[String], checks: &mut Vec<DiagnosticCheck>) {
+    let NodeRouting::Routers(record_routers) = &record.routing else {
+        checks.push(check(
+            "KNS routing",
+            false,
+            "our KNS record lists an IP and ports, but we run as an indirect node: \
+             other nodes will fail to connect to us until the record is updated"
+                .to_string(),
+        ));
+        return;
+    };
+    let mut record_routers = record_routers.clone();
+    let mut routers = routers.to_vec();
+    record_routers.sort();
+    routers.sort();
+    checks.push(if record_routers == routers {
+        check(
+            "KNS routers",
+            true,
+            format!(
+                "our KNS record has the routers we use, {}",
+                routers.join(", ")
+            ),
+        )
+    } else {
+        check(
+            "KNS routers",
+            false,
+            format!(
+                "our KNS record has {}, but we use {}: update the record",
+                record_routers.join(", "),
+                routers.join(", ")
+            ),
+        )
+    });
+}
+
+/// Connect to one of our ports at our public IP, as another node would.
+async fn check_port(ip: &str, protocol: &str, port: u16) -> DiagnosticCheck {
+    let name = format!("{protocol} port {port} reachable");
+    match time::timeout(TIMEOUT, TcpStream::connect((ip, port))).await {
+        Ok(Ok(_)) => check(&name, true, format!("accepted a connection at {ip}:{port}")),
+        Ok(Err(e)) => check(
+            &name,
+            false,
+            format!(
+                "failed to connect to {ip}:{port}: {e}. Forward the port to this machine and \
+                 open it in its firewall. Some home routers refuse connections to their own \
+                 public IP from inside, so check from another network before changing anything."
+            ),
+        ),
+        Err(_) => check(
+            &name,
+            false,
+            format!(
+                "timed out connecting to {ip}:{port}. Forward the port to this machine and \
+                 open it in its firewall."
+            ),
+        ),
+    }
+}
+
+fn check_routers(data: &NetData, routers: &[String], checks: &mut Vec<DiagnosticCheck>) {
+    let scores = data.router_scores.get();
+    let mut connected = 0;
+    for router in routers {
+        let name = format!("router {router}");
+        let score = scores.iter().find(|s| s.name == *router);
+        if data.peers.contains_key(router) {
+            connected += 1;
+            checks.push(check(
+                &name,
+                true,
+                match score.and_then(|s| s.latency_ms) {
+                    Some(latency_ms) => format!("connected, {latency_ms}ms away"),
+                    None => "connected".to_string(),
+                },
+            ));
+        } else {
+            checks.push(check(
+                &name,
+                false,
+                match score {
+                    Some(s) => format!(
+                        "not connected, after {} failures in a row; we keep trying",
+                        s.consecutive_failures
+                    ),
+                    None => "not connected; we keep trying".to_string(),
+                },
+            ));
+        }
+    }
+    checks.push(if connected > 0 {
+        check(
+            "routed",
+            true,
+            format!(
+                "other nodes can reach us through {connected} of {} routers",
+                routers.len()
+            ),
+        )
+    } else {
+        check(
+            "routed",
+            false,
+            "other nodes can't reach us: we are connected to none of our routers. \
+             If this lasts, pick other routers and update our KNS record."
+                .to_string(),
+        )
+    });
+}
+
+fn ports_string(ports: &std::collections::BTreeMap<String, u16>) -> String {
+    ports
+        .iter()
+        .map(|(protocol, port)| format!("{protocol} {port}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -8,11 +8,26 @@ pub async fn maintain_routers(ext: IdentityExt, data: NetData) -> anyhow::Result
         return Err(anyhow::anyhow!("net: no routers to maintain"));
     };
     loop {
-        for router_name in routers {
+        let mut routers = routers.clone();
+        data.router_scores.sort(&mut routers);
+        // failing routers are left for probes to revive, unless all of them are
+        // failing, in which case we keep trying them rather than go unreachable
+        let all_failing = routers
+            .iter()
+            .all(|router_name| data.router_scores.is_failing(router_name));
+        for router_name in &routers {
             if data.peers.contains_key(router_name.as_str()) {
                 // already connected to this router
                 continue;
             }
+            if !data.access.permits(&ext.our, router_name) {
+                // blocked by the operator
+                continue;
+            }
+            if !all_failing && data.router_scores.is_failing(router_name) {
+                // wait for a probe to reach it again
+                continue;
+            }
             let Some(router_id) = data.pki.get(router_name.as_str()) else {
                 // router does not exist in PKI that we know of
                 continue;
@@ -39,9 +54,11 @@ pub async fn connect_to_router(router_id: &Identity, ext: &IdentityExt, data: &N
                     &format!("net: connected to router {} via tcp", router_id.name),
                 )
                 .await;
+                data.router_scores.record_success(&router_id.name, None);
                 return;
             }
             Err(peer_rx) => {
+                data.router_scores.record_failure(&router_id.name);
                 return connect::handle_failed_connection(ext, data, router_id, peer_rx).await;
             }
         }
@@ -54,9 +71,11 @@ pub async fn connect_to_router(router_id: &Identity, ext: &IdentityExt, data: &N
                     &format!("net: connected to router {} via ws", router_id.name),
                 )
                 .await;
+                data.router_scores.record_success(&router_id.name, None);
                 return;
             }
             Err(peer_rx) => {
+                data.router_scores.record_failure(&router_id.name);
                 return connect::handle_failed_connection(ext, data, router_id, peer_rx).await;
             }
         }
@@ -0,0 +1,115 @@
+//! Keepalive pings, to find connections that died without closing.
+//!
+//! A connection that hears nothing from its peer for [`KeepaliveConfig::interval`]
+//! sends a [`NetResponse::KeepalivePing`], which the peer answers with a
+//! [`NetResponse::KeepalivePong`] straight from its end of the connection. Pings
+//! keep NAT mappings from expiring, and their round trip is kept in the peer's
+//! stats. If a peer that has answered pings before hears one and says nothing for
+//! [`KeepaliveConfig::timeout`], the connection is taken as dead: it is closed,
+//! and the messages still waiting on it are sent again over a new one.
+//!
+//! Pings are sent as responses, which nodes without keepalives ignore.
+use crate::net::{types::Peers, utils::net_response};
+use lib::types::core::{KernelMessage, Message, NetResponse, NET_PROCESS_ID};
+use {
+    std::sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    std::time::Duration,
+    tokio::sync::mpsc::UnboundedSender,
+    tokio::time,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeepaliveConfig {
+    /// how long a connection can be quiet before we ping the peer
+    pub interval: Duration,
+    /// how long the peer has to answer a ping
+    pub timeout: Duration,
+}
+
+/// what we have heard from the other end of a connection
+pub struct Liveness {
+    /// UNIX time in milliseconds of the last message received
+    last_heard: AtomicU64,
+    answers_pings: AtomicBool,
+}
+
+impl Liveness {
+    pub fn new() -> Self {
+        Self {
+            last_heard: AtomicU64::new(now_ms()),
+            answers_pings: AtomicBool::new(false),
+        }
+    }
+
+    pub fn heard(&self) {
+        self.last_heard.store(now_ms(), Ordering::Relaxed);
+    }
+}
+
+/// If `km` is a keepalive, handle it: answer a ping, or record the round trip
+/// of a pong. Returns whether it was one, in which case it goes no further.
+pub fn handle(
+    km: &KernelMessage,
+    peers: &Peers,
+    liveness: &Liveness,
+    control_tx: &UnboundedSender<KernelMessage>,
+) -> bool {
+    if km.source.process != *NET_PROCESS_ID || km.target.process != *NET_PROCESS_ID {
+        return false;
+    }
+    let Message::Response((response, _)) = &km.message else {
+        return false;
+    };
+    match rmp_serde::from_slice(&response.body) {
+        Ok(NetResponse::KeepalivePing(sent)) => {
+            let _ = control_tx.send(net_response(
+                &km.target.node,
+                &km.source.node,
+                NetResponse::KeepalivePong(sent),
+            ));
+            true
+        }
+        Ok(NetResponse::KeepalivePong(sent)) => {
+            liveness.answers_pings.store(true, Ordering::Relaxed);
+            peers.record_rtt(&km.source.node, now_ms().saturating_sub(sent));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Ping `peer_name` whenever the connection goes quiet, returning once it has
+/// stopped answering.
+pub async fn watch(
+    peers: &Peers,
+    peer_name: &str,
+    liveness: &Liveness,
+    control_tx: &UnboundedSender<KernelMessage>,
+) {
+    let config = peers.keepalive();
+    loop {
+        time::sleep(config.interval).await;
+        let pinged = now_ms();
+        let quiet_for = pinged.saturating_sub(liveness.last_heard.load(Ordering::Relaxed));
+        if quiet_for < config.interval.as_millis() as u64 {
+            continue;
+        }
+        let ping = net_response(peers.our(), peer_name, NetResponse::KeepalivePing(pinged));
+        if control_tx.send(ping).is_err() {
+            return;
+        }
+        time::sleep(config.timeout).await;
+        if liveness.last_heard.load(Ordering::Relaxed) < pinged
+            && liveness.answers_pings.load(Ordering::Relaxed)
+        {
+            return;
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
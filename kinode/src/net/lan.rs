@@ -0,0 +1,162 @@
+//! Discovery of nodes on the local network, when booted with `--lan-discovery`.
+//!
+//! We listen for TCP connections on a port of our own, and advertise it over
+//! mDNS along with our node name. Nodes found advertising the same service are
+//! connected to at their local address before any of their public routes, so
+//! nodes on one network can talk without the public routing infrastructure.
+//! Connections are authenticated with the same handshake as any other, so a
+//! node found on the network must still be in our PKI.
+use crate::net::{
+    tcp,
+    types::{IdentityExt, NetData},
+    utils::{print_debug, print_loud, TIMEOUT},
+};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use {
+    std::collections::HashMap,
+    std::net::{IpAddr, SocketAddr},
+    std::time::Duration,
+    tokio::net::TcpListener,
+    tokio::time,
+};
+
+const SERVICE_TYPE: &str = "_kinode._tcp.local.";
+/// the TXT record holding the advertised node's name
+const NAME_PROPERTY: &str = "name";
+/// how long to wait before advertising again after discovery fails
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Advertise ourselves and track the nodes advertising themselves, forever.
+/// Discovery failing, say for want of a network interface, is retried rather
+/// than returned, since the end of any networking task takes down the rest.
+pub async fn discover(ext: IdentityExt, data: NetData) -> anyhow::Result<()> {
+    loop {
+        let result = match ServiceDaemon::new() {
+            Ok(daemon) => {
+                let result = advertise_and_browse(&ext, &data, &daemon).await;
+                let _ = daemon.shutdown();
+                result
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            print_loud(
+                &ext.print_tx,
+                &format!(
+                    "net: local network discovery failed, retrying in {}s: {e}",
+                    RETRY_INTERVAL.as_secs()
+                ),
+            )
+            .await;
+        }
+        // nodes found by a failed discovery may no longer be there
+        data.lan_peers.clear();
+        time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+async fn advertise_and_browse(
+    ext: &IdentityExt,
+    data: &NetData,
+    daemon: &ServiceDaemon,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind("0.0.0.0:0").await?;
+    let port = listener.local_addr()?.port();
+
+    // node names contain dots, which don't belong in an mDNS instance name
+    let instance = ext.our.name.replace('.', "-");
+    let service = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance,
+        &format!("{instance}.local."),
+        "",
+        port,
+        &[(NAME_PROPERTY, ext.our.name.as_str())][..],
+    )?
+    .enable_addr_auto();
+    daemon.register(service)?;
+    let browser = daemon.browse(SERVICE_TYPE)?;
+
+    print_debug(
+        &ext.print_tx,
+        &format!("net: advertising on the local network, listening on port {port}"),
+    )
+    .await;
+
+    // mDNS fullname -> node name, to forget nodes when they leave
+    let mut found: HashMap<String, String> = HashMap::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, socket_addr)) = accepted else {
+                    continue;
+                };
+                print_debug(
+                    &ext.print_tx,
+                    &format!("net: got local network connection from {socket_addr}"),
+                )
+                .await;
+                let ext = ext.clone();
+                let data = data.clone();
+                tokio::spawn(async move {
+                    match time::timeout(TIMEOUT, tcp::recv_connection(ext.clone(), data, stream)).await {
+                        Ok(Ok(())) => return,
+                        Ok(Err(e)) => {
+                            print_debug(
+                                &ext.print_tx,
+                                &format!("net: error receiving local network connection: {e}"),
+                            )
+                            .await
+                        }
+                        Err(_e) => {
+                            print_debug(
+                                &ext.print_tx,
+                                &format!("net: local network connection from {socket_addr} timed out"),
+                            )
+                            .await
+                        }
+                    }
+                });
+            }
+            event = browser.recv_async() => {
+                match event? {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let Some(name) = info.get_property_val_str(NAME_PROPERTY) else {
+                            continue;
+                        };
+                        if name == ext.our.name || !data.pki.contains_key(name) {
+                            continue;
+                        }
+                        let Some(addr) = local_address(info.get_addresses().iter()) else {
+                            continue;
+                        };
+                        let addr = SocketAddr::new(addr, info.get_port());
+                        print_debug(
+                            &ext.print_tx,
+                            &format!("net: found {name} on the local network at {addr}"),
+                        )
+                        .await;
+                        found.insert(info.get_fullname().to_string(), name.to_string());
+                        data.lan_peers.insert(name.to_string(), addr);
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        if let Some(name) = found.remove(&fullname) {
+                            data.lan_peers.remove(&name);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// the address to reach an advertised node at, preferring IPv4
+fn local_address<'a>(addrs: impl Iterator<Item = &'a IpAddr>) -> Option<IpAddr> {
+    let addrs: Vec<&IpAddr> = addrs.filter(|addr| !addr.is_unspecified()).collect();
+    addrs
+        .iter()
+        .find(|addr| addr.is_ipv4())
+        .or(addrs.first())
+        .map(|addr| **addr)
+}
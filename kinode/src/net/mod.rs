@@ -9,10 +9,22 @@ use types::{
     ActivePassthroughs, IdentityExt, NetData, OnchainPKI, Peers, PendingPassthroughs, TCP_PROTOCOL,
     WS_PROTOCOL,
 };
-use {dashmap::DashMap, ring::signature::Ed25519KeyPair, std::sync::Arc, tokio::task::JoinSet};
+use {
+    dashmap::DashMap, ring::signature::Ed25519KeyPair, std::path::PathBuf, std::sync::Arc,
+    tokio::task::JoinSet,
+};
 
+mod access;
+mod compression;
 mod connect;
+mod diagnose;
 mod indirect;
+mod keepalive;
+mod lan;
+mod offline;
+mod punch;
+mod router_scores;
+mod simulate;
 mod tcp;
 mod types;
 mod utils;
@@ -40,6 +52,14 @@ pub async fn networking(
     max_peers: u64,
     // only used by routers
     max_passthroughs: u64,
+    // seconds a connection can be quiet before we ping the peer,
+    // and then how long they have to answer
+    keepalive_interval: u64,
+    keepalive_timeout: u64,
+    // bytes over which messages are compressed, 0 to never compress
+    compression_threshold: u64,
+    lan_discovery: bool,
+    home_directory_path: PathBuf,
 ) -> anyhow::Result<()> {
     crate::fd_manager::send_fd_manager_request_fds_limit(
         &Address::new(&our.name, NET_PROCESS_ID.clone()),
@@ -55,11 +75,21 @@ pub async fn networking(
         network_error_tx,
         print_tx,
         _reveal_ip,
+        keyfile_path: Arc::new(home_directory_path.join(".keys")),
     };
     // start by initializing the structs where we'll store PKI in memory
     // and store a mapping of peers we have an active route for
     let pki: OnchainPKI = Arc::new(DashMap::new());
-    let peers: Peers = Peers::new(max_peers, ext.kernel_message_tx.clone());
+    let peers: Peers = Peers::new(
+        ext.our.name.clone(),
+        max_peers,
+        keepalive::KeepaliveConfig {
+            interval: std::time::Duration::from_secs(keepalive_interval),
+            timeout: std::time::Duration::from_secs(keepalive_timeout),
+        },
+        (compression_threshold > 0).then_some(compression_threshold),
+        ext.kernel_message_tx.clone(),
+    );
     // only used by routers
     let pending_passthroughs: PendingPassthroughs = Arc::new(DashMap::new());
     let active_passthroughs: ActivePassthroughs = Arc::new(DashMap::new());
@@ -71,6 +101,12 @@ pub async fn networking(
         active_passthroughs,
         max_passthroughs,
         fds_limit: 10, // small hardcoded limit that gets replaced by fd-manager soon after boot
+        access: Arc::new(access::PeerAccessList::load(&home_directory_path).await),
+        hole_punches: Arc::new(DashMap::new()),
+        router_scores: Arc::new(router_scores::RouterScores::new()),
+        offline_queues: Arc::new(offline::OfflineQueues::load(&home_directory_path).await),
+        lan_peers: Arc::new(DashMap::new()),
+        simulated: Arc::new(simulate::SimulatedConditions::default()),
     };
 
     let mut tasks = JoinSet::<anyhow::Result<()>>::new();
@@ -79,6 +115,11 @@ pub async fn networking(
     // and depending on the ports in our identity, the tasks
     // for ws and/or tcp, or indirect routing.
     tasks.spawn(local_recv(ext.clone(), kernel_message_rx, net_data.clone()));
+    tasks.spawn(router_scores::probe_routers(ext.clone(), net_data.clone()));
+    tasks.spawn(offline::retry_held(ext.clone(), net_data.clone()));
+    if lan_discovery {
+        tasks.spawn(lan::discover(ext.clone(), net_data.clone()));
+    }
 
     match &ext.our.routing {
         NodeRouting::Direct { ip, ports } => {
@@ -142,7 +183,7 @@ async fn handle_message(ext: &IdentityExt, km: KernelMessage, data: &mut NetData
     match &km.message {
         lib::core::Message::Request(request) => handle_request(ext, &km, &request.body, data).await,
         lib::core::Message::Response((response, _context)) => {
-            handle_response(&km, &response.body, data).await
+            handle_response(ext, &km, &response.body, data).await
         }
     }
 }
@@ -185,7 +226,48 @@ async fn handle_local_request(
                 utils::ingest_log(log, &data.pki);
             }
         }
+        Ok(NetAction::RunDiagnostics) => {
+            tokio::spawn(diagnose::respond(ext.clone(), data.clone(), km.clone()));
+        }
         Ok(gets) => {
+            match &gets {
+                NetAction::BlockPeer(name) => data.access.block(name.clone()).await,
+                NetAction::UnblockPeer(name) => data.access.unblock(name).await,
+                NetAction::AllowPeer(name) => data.access.allow(name.clone()).await,
+                NetAction::DisallowPeer(name) => data.access.disallow(name).await,
+                NetAction::SetAllowlistOnly(allowlist_only) => {
+                    data.access.set_allowlist_only(*allowlist_only).await
+                }
+                NetAction::SetOfflineQueue(config) => {
+                    for km in data.offline_queues.set_config(config.clone()).await {
+                        utils::error_offline(km, &ext.network_error_tx).await;
+                    }
+                }
+                #[cfg(feature = "simulation-mode")]
+                NetAction::SetNetworkConditions(name, conditions) => {
+                    data.simulated.set(name.clone(), conditions.clone());
+                    if data.simulated.is_disconnected(name) {
+                        data.peers.remove(name).await;
+                    }
+                }
+                #[cfg(not(feature = "simulation-mode"))]
+                NetAction::SetNetworkConditions(..) => {
+                    utils::print_loud(
+                        &ext.print_tx,
+                        "net: network conditions can only be simulated in simulation mode",
+                    )
+                    .await;
+                }
+                _ => {}
+            }
+            if matches!(
+                gets,
+                NetAction::BlockPeer(_)
+                    | NetAction::DisallowPeer(_)
+                    | NetAction::SetAllowlistOnly(true)
+            ) {
+                disconnect_unpermitted_peers(ext, data).await;
+            }
             let (response_body, response_blob) = match gets {
                 NetAction::GetPeers => (
                     NetResponse::Peers(
@@ -231,6 +313,88 @@ async fn handle_local_request(
                         ));
                     }
 
+                    let stats = data.peers.stats();
+                    if !stats.is_empty() {
+                        printout.push_str(&format!(
+                            "traffic with {} peers since boot (in / out):\r\n",
+                            stats.len()
+                        ));
+                        for s in stats.iter() {
+                            printout.push_str(&format!(
+                                "    {}: {} / {} messages, {} / {} bytes, {} failed connections, {} errors{}\r\n",
+                                s.name,
+                                s.messages_received,
+                                s.messages_sent,
+                                s.bytes_received,
+                                s.bytes_sent,
+                                s.connection_failures,
+                                s.connection_errors,
+                                match s.rtt_ms {
+                                    Some(rtt_ms) => format!(", {rtt_ms}ms round trip"),
+                                    None => String::new(),
+                                },
+                            ));
+                        }
+                    }
+
+                    let router_scores = data.router_scores.get();
+                    if !router_scores.is_empty() {
+                        printout.push_str(&format!(
+                            "scores of {} routers, healthiest first:\r\n",
+                            router_scores.len()
+                        ));
+                        for r in router_scores.iter() {
+                            printout.push_str(&format!(
+                                "    {}: {:.2}, {}, {} / {} probes and connections worked\r\n",
+                                r.name,
+                                r.score,
+                                match r.latency_ms {
+                                    Some(latency_ms) => format!("{latency_ms}ms"),
+                                    None => "unreachable".to_string(),
+                                },
+                                r.successes,
+                                r.successes + r.failures,
+                            ));
+                        }
+                    }
+
+                    if !data.lan_peers.is_empty() {
+                        printout.push_str(&format!(
+                            "we found {} peers on the local network:\r\n",
+                            data.lan_peers.len()
+                        ));
+                        for p in data.lan_peers.iter() {
+                            printout.push_str(&format!("    {} at {}\r\n", p.key(), p.value()));
+                        }
+                    }
+
+                    let held = data.offline_queues.count();
+                    if held > 0 {
+                        printout.push_str(&format!(
+                            "holding {} requests for {} unreachable peers\r\n",
+                            held,
+                            data.offline_queues.peers().len()
+                        ));
+                    }
+
+                    let simulated = data.simulated.get();
+                    if !simulated.is_empty() {
+                        printout.push_str(&format!(
+                            "simulating network conditions for {} peers:\r\n",
+                            simulated.len()
+                        ));
+                        for (name, c) in simulated.iter() {
+                            printout.push_str(&format!(
+                                "    {}: {}ms latency, {}ms jitter, {:.0}% loss{}\r\n",
+                                name,
+                                c.latency_ms,
+                                c.jitter_ms,
+                                c.loss * 100.0,
+                                if c.disconnected { ", disconnected" } else { "" },
+                            ));
+                        }
+                    }
+
                     if data.max_passthroughs > 0 {
                         printout.push_str(&format!(
                             "we allow {} max passthroughs\r\n",
@@ -307,6 +471,34 @@ async fn handle_local_request(
                         None,
                     )
                 }
+                NetAction::BlockPeer(_)
+                | NetAction::UnblockPeer(_)
+                | NetAction::AllowPeer(_)
+                | NetAction::DisallowPeer(_)
+                | NetAction::SetAllowlistOnly(_)
+                | NetAction::GetPeerAccess => (NetResponse::PeerAccess(data.access.get()), None),
+                NetAction::GetPeerStats => (NetResponse::PeerStats(data.peers.stats()), None),
+                NetAction::SetOfflineQueue(_) | NetAction::GetOfflineQueue => {
+                    (NetResponse::OfflineQueue(data.offline_queues.get()), None)
+                }
+                NetAction::GetRouterScores => {
+                    (NetResponse::RouterScores(data.router_scores.get()), None)
+                }
+                NetAction::SetNetworkConditions(..) | NetAction::GetNetworkConditions => {
+                    (NetResponse::NetworkConditions(data.simulated.get()), None)
+                }
+                NetAction::SetRouters(routers) => (
+                    NetResponse::SetRouters(
+                        if km.source.process
+                            == lib::core::ProcessId::new(Some("settings"), "settings", "sys")
+                        {
+                            utils::save_routers(&ext.keyfile_path, routers).await
+                        } else {
+                            Err("routers can only be set by settings:settings:sys".to_string())
+                        },
+                    ),
+                    None,
+                ),
                 _ => {
                     // already matched these outcomes
                     return;
@@ -335,6 +527,30 @@ async fn handle_local_request(
     }
 }
 
+/// close our connections with any peers that are no longer permitted,
+/// and any passthroughs we hold open for them
+async fn disconnect_unpermitted_peers(ext: &IdentityExt, data: &NetData) {
+    let unpermitted: Vec<String> = data
+        .peers
+        .peers()
+        .iter()
+        .filter(|peer| !data.access.permits(&ext.our, peer.key()))
+        .map(|peer| peer.key().clone())
+        .collect();
+    for name in unpermitted {
+        utils::print_debug(&ext.print_tx, &format!("net: disconnecting from {name}")).await;
+        if let Some((_, mut peer)) = data.peers.remove(&name).await {
+            peer.kill();
+        }
+    }
+    data.pending_passthroughs.retain(|(from, target), _| {
+        data.access.permits(&ext.our, from) && data.access.permits(&ext.our, target)
+    });
+    data.active_passthroughs.retain(|(from, target), _| {
+        data.access.permits(&ext.our, from) && data.access.permits(&ext.our, target)
+    });
+}
+
 async fn handle_fdman(km: &KernelMessage, request_body: &[u8], data: &mut NetData) {
     if km.source.process != *lib::core::FD_MANAGER_PROCESS_ID {
         return;
@@ -377,6 +593,9 @@ async fn handle_remote_request(
             ));
         }
         Ok(NetAction::ConnectionRequest(from)) => {
+            if !data.access.permits(&ext.our, &from) {
+                return Err(anyhow::anyhow!("net: {from} is not permitted to connect"));
+            }
             // someone wants to open a passthrough with us through a router.
             // if we are an indirect node, and source is one of our routers,
             // respond by attempting to init a matching passthrough.
@@ -410,6 +629,31 @@ async fn handle_remote_request(
                 },
             ));
         }
+        Ok(NetAction::HolePunch(addr)) => {
+            // an indirect node we have a routed connection with wants to
+            // replace it with a direct one
+            if !data.access.permits(&ext.our, &km.source.node) {
+                return Err(anyhow::anyhow!(
+                    "net: {} is not permitted to connect",
+                    km.source.node
+                ));
+            }
+            if !data.peers.contains_key(&km.source.node) {
+                return Err(anyhow::anyhow!(
+                    "net: hole punch from a node we aren't connected to"
+                ));
+            }
+            let Some(peer_id) = data.pki.get(&km.source.node) else {
+                return Err(anyhow::anyhow!("net: peer not in PKI"));
+            };
+            tokio::spawn(punch::respond(
+                ext.clone(),
+                data.clone(),
+                km.id,
+                peer_id.clone(),
+                addr,
+            ));
+        }
         _ => {
             // if we can't parse this to a NetAction, treat it as a hello and print it,
             // and respond with a simple "ack" response
@@ -427,8 +671,14 @@ async fn handle_remote_request(
 }
 
 // Responses are received as a router, when we send ConnectionRequests
-// to a node we do routing for.
-async fn handle_response(km: &KernelMessage, response_body: &[u8], data: &NetData) {
+// to a node we do routing for, and as an indirect node, when we send
+// HolePunches to a node we have a routed connection with.
+async fn handle_response(
+    ext: &IdentityExt,
+    km: &KernelMessage,
+    response_body: &[u8],
+    data: &NetData,
+) {
     match rmp_serde::from_slice::<lib::core::NetResponse>(response_body) {
         Ok(lib::core::NetResponse::Rejected(to)) => {
             // drop from our pending map
@@ -436,6 +686,17 @@ async fn handle_response(km: &KernelMessage, response_body: &[u8], data: &NetDat
             data.pending_passthroughs
                 .remove(&(to, km.source.node.to_owned()));
         }
+        Ok(lib::core::NetResponse::HolePunch(addr)) => {
+            let Some(peer_id) = data.pki.get(&km.source.node) else {
+                return;
+            };
+            tokio::spawn(punch::finish(
+                ext.clone(),
+                data.clone(),
+                peer_id.clone(),
+                addr,
+            ));
+        }
         _ => {
             // ignore any other response, for now
         }
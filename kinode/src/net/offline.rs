@@ -0,0 +1,160 @@
+use crate::net::{
+    connect,
+    types::{IdentityExt, NetData},
+    utils::{error_offline, get_now},
+};
+use lib::types::core::{KernelMessage, Message, OfflineQueue, OfflineQueueConfig};
+use {
+    dashmap::DashMap,
+    std::collections::VecDeque,
+    std::path::{Path, PathBuf},
+    std::sync::RwLock,
+    tokio::time,
+};
+
+const CONFIG_FILE: &str = ".net_offline_queue";
+
+/// how often held requests are retried
+const RETRY_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+/// Requests for nodes we can't reach, held and retried once the operator turns this
+/// on with [`lib::core::NetAction::SetOfflineQueue`]. Only requests that expect no
+/// response are held, since the kernel times the rest out on its own schedule.
+/// The settings are persisted in the home directory, but held requests are not.
+pub struct OfflineQueues {
+    file: PathBuf,
+    config: RwLock<Option<OfflineQueueConfig>>,
+    /// node -> (expiry, request), oldest first
+    queues: DashMap<String, VecDeque<(u64, KernelMessage)>>,
+    /// message id -> expiry, so that a request that fails again
+    /// when retried keeps the expiry it was first held with
+    expiries: DashMap<u64, u64>,
+}
+
+impl OfflineQueues {
+    pub async fn load(home_directory_path: &Path) -> Self {
+        let file = home_directory_path.join(CONFIG_FILE);
+        let config: Option<OfflineQueueConfig> = tokio::fs::read(&file)
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Self {
+            file,
+            config: RwLock::new(config),
+            queues: DashMap::new(),
+            expiries: DashMap::new(),
+        }
+    }
+
+    /// Hold `km` to be retried, or give it back if it can't be held: if we aren't
+    /// holding requests, it expects a response, it has expired, or we hold too many.
+    pub fn hold(&self, km: KernelMessage) -> Result<(), KernelMessage> {
+        let Some(config) = self.config.read().unwrap().clone() else {
+            return Err(km);
+        };
+        let Message::Request(request) = &km.message else {
+            return Err(km);
+        };
+        if request.expects_response.is_some() {
+            return Err(km);
+        }
+        let now = get_now();
+        let expires = *self.expiries.entry(km.id).or_insert(now + config.ttl);
+        if expires <= now || self.count() >= config.max_total as usize {
+            return Err(km);
+        }
+        let mut queue = self.queues.entry(km.target.node.clone()).or_default();
+        if queue.len() >= config.max_per_peer as usize {
+            return Err(km);
+        }
+        queue.push_back((expires, km));
+        Ok(())
+    }
+
+    /// take every request held for `name`, oldest first
+    pub fn take(&self, name: &str) -> Vec<KernelMessage> {
+        self.queues
+            .remove(name)
+            .map(|(_, queue)| queue.into_iter().map(|(_, km)| km).collect())
+            .unwrap_or_default()
+    }
+
+    /// take every request that has expired
+    pub fn expire(&self) -> Vec<KernelMessage> {
+        let now = get_now();
+        let mut expired = Vec::new();
+        self.queues.retain(|_, queue| {
+            let (held, gone): (VecDeque<_>, VecDeque<_>) =
+                queue.drain(..).partition(|(expires, _)| *expires > now);
+            expired.extend(gone.into_iter().map(|(_, km)| km));
+            *queue = held;
+            !queue.is_empty()
+        });
+        self.expiries.retain(|_, expires| *expires > now);
+        expired
+    }
+
+    /// the nodes we hold requests for
+    pub fn peers(&self) -> Vec<String> {
+        self.queues
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Change the settings, returning every held request if turned off.
+    pub async fn set_config(&self, config: Option<OfflineQueueConfig>) -> Vec<KernelMessage> {
+        let disabled = config.is_none();
+        *self.config.write().unwrap() = config;
+        self.persist().await;
+        if !disabled {
+            return Vec::new();
+        }
+        self.expiries.clear();
+        self.peers()
+            .iter()
+            .flat_map(|name| self.take(name))
+            .collect()
+    }
+
+    pub fn get(&self) -> OfflineQueue {
+        OfflineQueue {
+            config: self.config.read().unwrap().clone(),
+            queued: self
+                .queues
+                .iter()
+                .map(|entry| (entry.key().clone(), entry.value().len() as u64))
+                .collect(),
+        }
+    }
+
+    /// how many requests we hold in all
+    pub fn count(&self) -> usize {
+        self.queues.iter().map(|entry| entry.value().len()).sum()
+    }
+
+    async fn persist(&self) {
+        let config = self.config.read().unwrap().clone();
+        if let Err(e) = tokio::fs::write(&self.file, serde_json::to_vec(&config).unwrap()).await {
+            println!("net: failed to persist {CONFIG_FILE}: {e}\r");
+        }
+    }
+}
+
+/// Retry held requests, and fail expired ones as offline, forever.
+pub async fn retry_held(ext: IdentityExt, data: NetData) -> anyhow::Result<()> {
+    loop {
+        time::sleep(RETRY_INTERVAL).await;
+        for km in data.offline_queues.expire() {
+            error_offline(km, &ext.network_error_tx).await;
+        }
+        for name in data.offline_queues.peers() {
+            // requests that still can't be delivered are held again
+            // by connect::handle_failed_connection
+            for km in data.offline_queues.take(&name) {
+                connect::send_to_peer(&ext, &data, km).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! NAT traversal between indirect nodes.
+//!
+//! Two indirect nodes normally talk through a passthrough held open by one of
+//! their routers. Once such a routed connection is up, the node that opened it
+//! tries to replace it with a direct one:
+//!
+//! 1. each node learns the public address its NAT maps a fresh local port to,
+//!    by connecting from that port to one of its routers, which replies with
+//!    the address it sees the connection come from, signed (see [`OBSERVE_ADDRESS`]);
+//! 2. the nodes swap those addresses with [`NetAction::HolePunch`] and
+//!    [`NetResponse::HolePunch`] over the routed connection;
+//! 3. both connect to the other's address from their mapped port at once,
+//!    which most NATs let through as a TCP simultaneous open.
+//!
+//! An address is only connected to if a router that the PKI lists for the other
+//! node signed it, so a node can't have us connect to anywhere but itself.
+//!
+//! If that works, the direct connection replaces the routed one. If not, the
+//! nodes keep using the router, and don't try again for [`RETRY_AFTER`].
+//! Both networking protocols run over TCP, so there is no UDP punching.
+use crate::net::{
+    connect, tcp,
+    types::{IdentityExt, NetData, Peer, TCP_PROTOCOL},
+    utils::{get_now, make_conn_url, print_debug, validate_signature, TIMEOUT},
+};
+use lib::types::core::{
+    Identity, KernelMessage, Message, NetAction, NetResponse, NodeRouting, ObservedAddress,
+    Request, Response,
+};
+use {
+    dashmap::mapref::entry::Entry,
+    std::net::{Ipv4Addr, SocketAddr},
+    tokio::net::{TcpSocket, TcpStream},
+    tokio::time,
+};
+
+/// Sent by a node in place of a routing request or handshake, asking the node
+/// it connected to for the public address of its socket.
+pub const OBSERVE_ADDRESS: &[u8] = b"observe-address";
+
+/// how long we try to connect to a peer's public address before giving up
+const PUNCH_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+/// how long each attempt to connect waits for the peer's end to open
+const ATTEMPT_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+const ATTEMPT_INTERVAL: time::Duration = time::Duration::from_millis(200);
+/// seconds after trying to punch through to a peer, whether it worked or not,
+/// before we try again
+const RETRY_AFTER: u64 = 600;
+
+/// stands in for the local address of a punch while we are not waiting to make it
+const UNSPECIFIED: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+/// Try to replace our routed connection with `peer_id` with a direct one.
+/// Should be spawned once the routed connection is up.
+pub async fn start(ext: IdentityExt, data: NetData, peer_id: Identity) {
+    if peer_id.is_direct() || !claim(&ext, &data, &peer_id.name) {
+        return;
+    }
+    let (local, public) = match observe_address(&ext, &data).await {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            print_debug(&ext.print_tx, &format!("net: can't punch through NAT: {e}")).await;
+            return;
+        }
+    };
+    data.hole_punches
+        .insert(peer_id.name.clone(), (local, get_now()));
+    connect::send_to_peer(
+        &ext,
+        &data,
+        KernelMessage::builder()
+            .id(rand::random())
+            .source((ext.our.name.as_str(), "net", "distro", "sys"))
+            .target((peer_id.name.as_str(), "net", "distro", "sys"))
+            .message(Message::Request(Request {
+                inherit: false,
+                expects_response: Some(5),
+                body: rmp_serde::to_vec(&NetAction::HolePunch(public)).unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            }))
+            .build()
+            .unwrap(),
+    )
+    .await;
+}
+
+/// Handle a [`NetAction::HolePunch`] from `peer_id`: tell them our public address,
+/// then connect to theirs.
+pub async fn respond(
+    ext: IdentityExt,
+    data: NetData,
+    request_id: u64,
+    peer_id: Identity,
+    their_addr: ObservedAddress,
+) {
+    let Some(their_addr) = vouched_address(&data, &peer_id, &their_addr) else {
+        print_debug(
+            &ext.print_tx,
+            &format!(
+                "net: {} asked to punch through to an address none of its routers observed",
+                peer_id.name
+            ),
+        )
+        .await;
+        return;
+    };
+    if !claim(&ext, &data, &peer_id.name) {
+        return;
+    }
+    let (local, public) = match observe_address(&ext, &data).await {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            print_debug(&ext.print_tx, &format!("net: can't punch through NAT: {e}")).await;
+            return;
+        }
+    };
+    connect::send_to_peer(
+        &ext,
+        &data,
+        KernelMessage::builder()
+            .id(request_id)
+            .source((ext.our.name.as_str(), "net", "distro", "sys"))
+            .target((peer_id.name.as_str(), "net", "distro", "sys"))
+            .message(Message::Response((
+                Response {
+                    inherit: false,
+                    body: rmp_serde::to_vec(&NetResponse::HolePunch(public)).unwrap(),
+                    metadata: None,
+                    capabilities: vec![],
+                },
+                None,
+            )))
+            .build()
+            .unwrap(),
+    )
+    .await;
+    punch(&ext, &data, &peer_id, local, their_addr).await;
+}
+
+/// Handle a [`NetResponse::HolePunch`] from `peer_id`, if we asked for one:
+/// connect to the address they gave.
+pub async fn finish(
+    ext: IdentityExt,
+    data: NetData,
+    peer_id: Identity,
+    their_addr: ObservedAddress,
+) {
+    let Some(their_addr) = vouched_address(&data, &peer_id, &their_addr) else {
+        return;
+    };
+    let local = match data.hole_punches.get_mut(&peer_id.name) {
+        Some(mut punch) => std::mem::replace(&mut punch.0, UNSPECIFIED),
+        None => return,
+    };
+    if local == UNSPECIFIED {
+        return;
+    }
+    punch(&ext, &data, &peer_id, local, their_addr).await;
+}
+
+/// mark that we are punching through to `name`, unless we can't or did so recently
+fn claim(ext: &IdentityExt, data: &NetData, name: &str) -> bool {
+    if !matches!(ext.our.routing, NodeRouting::Routers(_)) {
+        return false;
+    }
+    let now = get_now();
+    match data.hole_punches.entry(name.to_string()) {
+        Entry::Occupied(punch) if now.saturating_sub(punch.get().1) < RETRY_AFTER => false,
+        entry => {
+            entry.insert((UNSPECIFIED, now));
+            true
+        }
+    }
+}
+
+/// The address `observed` gives, if it was signed by one of the routers that the
+/// PKI lists for `peer_id`.
+fn vouched_address(
+    data: &NetData,
+    peer_id: &Identity,
+    observed: &ObservedAddress,
+) -> Option<SocketAddr> {
+    let NodeRouting::Routers(ref routers) = peer_id.routing else {
+        return None;
+    };
+    if !routers.contains(&observed.observer)
+        || !validate_signature(
+            &observed.observer,
+            &observed.signature,
+            &[OBSERVE_ADDRESS, observed.address.as_bytes()].concat(),
+            &data.pki,
+        )
+    {
+        return None;
+    }
+    observed.address.parse().ok()
+}
+
+/// A fresh local address, and the public address one of our routers sees it as.
+async fn observe_address(
+    ext: &IdentityExt,
+    data: &NetData,
+) -> anyhow::Result<(SocketAddr, ObservedAddress)> {
+    let NodeRouting::Routers(ref routers) = ext.our.routing else {
+        return Err(anyhow::anyhow!("not an indirect node"));
+    };
+    for router_name in routers {
+        let Some((ip, port)) = data.pki.get(router_name).and_then(|router_id| {
+            router_id
+                .tcp_routing()
+                .map(|(ip, port)| (ip.to_string(), *port))
+        }) else {
+            continue;
+        };
+        match time::timeout(TIMEOUT, observe_address_via(ext, router_name, &ip, port)).await {
+            Ok(Ok(addrs)) => return Ok(addrs),
+            Ok(Err(e)) => {
+                print_debug(
+                    &ext.print_tx,
+                    &format!("net: router {router_name} didn't observe our address: {e}"),
+                )
+                .await;
+            }
+            Err(_) => {
+                print_debug(
+                    &ext.print_tx,
+                    &format!("net: router {router_name} timed out observing our address"),
+                )
+                .await;
+            }
+        }
+    }
+    Err(anyhow::anyhow!("no router observed our address"))
+}
+
+async fn observe_address_via(
+    ext: &IdentityExt,
+    router_name: &str,
+    ip: &str,
+    port: u16,
+) -> anyhow::Result<(SocketAddr, ObservedAddress)> {
+    let tcp_url = make_conn_url(&ext.our_ip, ip, &port, TCP_PROTOCOL)?;
+    let router_addr = tokio::net::lookup_host(&tcp_url)
+        .await?
+        .next()
+        .ok_or(anyhow::anyhow!("failed to resolve {tcp_url}"))?;
+    let unspecified = match router_addr {
+        SocketAddr::V4(_) => UNSPECIFIED,
+        SocketAddr::V6(_) => SocketAddr::new(std::net::Ipv6Addr::UNSPECIFIED.into(), 0),
+    };
+    let mut stream = connect_from(unspecified, router_addr).await?;
+    let local = stream.local_addr()?;
+    tcp::utils::send_raw(&mut stream, OBSERVE_ADDRESS).await?;
+    let (_len, observed) = tcp::utils::recv_raw(&mut stream).await?;
+    let observed: ObservedAddress = rmp_serde::from_slice(&observed)?;
+    if observed.observer != router_name {
+        return Err(anyhow::anyhow!("observed by {} instead", observed.observer));
+    }
+    Ok((local, observed))
+}
+
+/// connect to `remote` from `local`, which other sockets of ours may share
+async fn connect_from(local: SocketAddr, remote: SocketAddr) -> std::io::Result<TcpStream> {
+    let socket = match remote {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(true)?;
+    socket.bind(local)?;
+    socket.connect(remote).await
+}
+
+/// Connect to `remote` from `local` while the peer does the reverse, and
+/// replace our connection with them if that works.
+async fn punch(
+    ext: &IdentityExt,
+    data: &NetData,
+    peer_id: &Identity,
+    local: SocketAddr,
+    remote: SocketAddr,
+) {
+    let deadline = time::Instant::now() + PUNCH_TIMEOUT;
+    let stream = loop {
+        if time::Instant::now() >= deadline {
+            print_debug(
+                &ext.print_tx,
+                &format!(
+                    "net: failed to punch through to {}, staying routed",
+                    peer_id.name
+                ),
+            )
+            .await;
+            return;
+        }
+        match time::timeout(ATTEMPT_TIMEOUT, connect_from(local, remote)).await {
+            Ok(Ok(stream)) => break stream,
+            _ => time::sleep(ATTEMPT_INTERVAL).await,
+        }
+    };
+    // both ends opened the connection at once, so take turns by name
+    let connection = if ext.our.name < peer_id.name {
+        time::timeout(
+            TIMEOUT,
+            tcp::handshake_as_initiator(ext, peer_id, stream, false),
+        )
+        .await
+    } else {
+        time::timeout(TIMEOUT, tcp::handshake_as_responder(ext, peer_id, stream)).await
+    };
+    let connection = match connection {
+        Ok(Ok(connection)) => connection,
+        Ok(Err(e)) => {
+            print_debug(
+                &ext.print_tx,
+                &format!("net: error in handshake after punching through: {e}"),
+            )
+            .await;
+            return;
+        }
+        Err(_) => {
+            print_debug(
+                &ext.print_tx,
+                "net: handshake after punching through timed out",
+            )
+            .await;
+            return;
+        }
+    };
+    print_debug(
+        &ext.print_tx,
+        &format!("net: punched through to {}", peer_id.name),
+    )
+    .await;
+    // replacing the routed peer drops its sender, so its connection
+    // closes once the messages queued on it are sent
+    let (mut peer, peer_rx) = Peer::new(peer_id.clone(), false);
+    peer.handle = Some(tokio::spawn(tcp::utils::maintain_connection(
+        peer_id.name.clone(),
+        data.peers.clone(),
+        connection,
+        peer_rx,
+        ext.kernel_message_tx.clone(),
+        ext.print_tx.clone(),
+    )));
+    data.peers.insert(peer_id.name.clone(), peer).await;
+}
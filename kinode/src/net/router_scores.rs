@@ -0,0 +1,175 @@
+//! Router health, so that we route through the routers that work best.
+//!
+//! Our own routers, and those of any indirect node we connect to, are probed
+//! every [`PROBE_INTERVAL`] and scored by how quickly they answer and how often
+//! probes and connections through them work. Routers are then tried healthiest
+//! first, and our own are left alone after repeated failures until a probe
+//! reaches them again, unless all of them are failing.
+use crate::net::{
+    types::{IdentityExt, NetData, TCP_PROTOCOL},
+    utils::{get_now, make_conn_url, print_debug, TIMEOUT},
+};
+use lib::types::core::{Identity, NodeRouting, RouterScore};
+use {dashmap::DashMap, tokio::time};
+
+/// how often each router is probed
+const PROBE_INTERVAL: time::Duration = time::Duration::from_secs(60);
+/// the latency scored for a router we have never heard back from
+const DEFAULT_LATENCY_MS: u64 = 250;
+/// how many routers we keep scores for
+const MAX_SCORED_ROUTERS: usize = 256;
+/// failures in a row after which we stop connecting to one of our routers
+/// until a probe reaches it
+const MAX_CONSECUTIVE_FAILURES: u64 = 3;
+
+pub struct RouterScores {
+    scores: DashMap<String, RouterScore>,
+}
+
+impl RouterScores {
+    pub fn new() -> Self {
+        Self {
+            scores: DashMap::new(),
+        }
+    }
+
+    /// the scores of every router, healthiest first
+    pub fn get(&self) -> Vec<RouterScore> {
+        let mut scores: Vec<RouterScore> = self
+            .scores
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        scores.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.name.cmp(&b.name)));
+        scores
+    }
+
+    /// the score of `name`, or that of a router we know nothing about
+    pub fn score(&self, name: &str) -> f64 {
+        self.scores
+            .get(name)
+            .map(|score| score.score)
+            .unwrap_or_else(|| compute_score(&RouterScore::default()))
+    }
+
+    /// whether `name` has failed too many times in a row to keep trying
+    pub fn is_failing(&self, name: &str) -> bool {
+        self.scores
+            .get(name)
+            .is_some_and(|score| score.consecutive_failures >= MAX_CONSECUTIVE_FAILURES)
+    }
+
+    /// sort `routers` healthiest first, keeping the order of equally healthy ones
+    pub fn sort(&self, routers: &mut [String]) {
+        routers.sort_by(|a, b| self.score(b).total_cmp(&self.score(a)));
+    }
+
+    pub fn record_success(&self, name: &str, latency_ms: Option<u64>) {
+        self.update(name, |score| {
+            score.successes += 1;
+            score.consecutive_failures = 0;
+            if let Some(sample) = latency_ms {
+                // exponentially weighted, so one slow probe doesn't sink a router
+                score.latency_ms = Some(match score.latency_ms {
+                    Some(latency) => (latency * 3 + sample) / 4,
+                    None => sample,
+                });
+            }
+        });
+    }
+
+    pub fn record_failure(&self, name: &str) {
+        self.update(name, |score| {
+            score.failures += 1;
+            score.consecutive_failures += 1;
+        });
+    }
+
+    fn update(&self, name: &str, update: impl FnOnce(&mut RouterScore)) {
+        if let Some(mut score) = self.scores.get_mut(name) {
+            update(&mut score);
+            score.score = compute_score(&score);
+            return;
+        }
+        if self.scores.len() >= MAX_SCORED_ROUTERS {
+            return;
+        }
+        let mut score = RouterScore {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        update(&mut score);
+        score.score = compute_score(&score);
+        self.scores.insert(name.to_string(), score);
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.scores
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+}
+
+/// reliability, counting each router as having one success and one failure
+/// to start with, scaled down by latency
+fn compute_score(score: &RouterScore) -> f64 {
+    let reliability =
+        (score.successes as f64 + 1.0) / ((score.successes + score.failures) as f64 + 2.0);
+    let latency_ms = score.latency_ms.unwrap_or(DEFAULT_LATENCY_MS) as f64;
+    reliability * 100.0 / (100.0 + latency_ms)
+}
+
+/// Probe our own routers, and any other router we have scored, forever.
+pub async fn probe_routers(ext: IdentityExt, data: NetData) -> anyhow::Result<()> {
+    loop {
+        let mut routers = data.router_scores.names();
+        if let NodeRouting::Routers(ours) | NodeRouting::Both { routers: ours, .. } =
+            &ext.our.routing
+        {
+            for router in ours {
+                if !routers.contains(router) {
+                    routers.push(router.clone());
+                }
+            }
+        }
+        for router_name in routers {
+            if router_name == ext.our.name {
+                continue;
+            }
+            let Some(router_id) = data.pki.get(&router_name).map(|id| id.clone()) else {
+                // not indexed yet
+                continue;
+            };
+            match probe(&ext, &router_id).await {
+                Ok(latency_ms) => data
+                    .router_scores
+                    .record_success(&router_name, Some(latency_ms)),
+                Err(e) => {
+                    print_debug(
+                        &ext.print_tx,
+                        &format!("net: probe of router {router_name} failed: {e}"),
+                    )
+                    .await;
+                    data.router_scores.record_failure(&router_name);
+                }
+            }
+            if let Some(mut score) = data.router_scores.scores.get_mut(&router_name) {
+                score.last_probed = get_now();
+            }
+        }
+        time::sleep(PROBE_INTERVAL).await;
+    }
+}
+
+/// The milliseconds it takes to open a TCP connection to the router's port,
+/// which is closed again without a word, whichever protocol the port takes.
+async fn probe(ext: &IdentityExt, router_id: &Identity) -> anyhow::Result<u64> {
+    let Some((ip, port)) = router_id.tcp_routing().or(router_id.ws_routing()) else {
+        return Err(anyhow::anyhow!("no routing ports"));
+    };
+    let tcp_url = make_conn_url(&ext.our_ip, ip, port, TCP_PROTOCOL)?;
+    let start = time::Instant::now();
+    time::timeout(TIMEOUT, tokio::net::TcpStream::connect(&tcp_url)).await??;
+    Ok(start.elapsed().as_millis() as u64)
+}
@@ -0,0 +1,80 @@
+//! Simulated network conditions, for testing how processes cope with a bad network.
+//!
+//! In simulation mode, [`NetAction::SetNetworkConditions`] sets the latency, jitter,
+//! loss and disconnection applied to the messages we send to a node. Only our side
+//! of the link is affected: to degrade both directions, set conditions on both
+//! nodes. Conditions are kept in memory, so a reboot clears them.
+use crate::net::{
+    connect,
+    types::{IdentityExt, NetData},
+    utils::error_offline,
+};
+use lib::types::core::{KernelMessage, NetworkConditions, NodeId};
+use {dashmap::DashMap, rand::Rng, std::collections::BTreeMap, tokio::time};
+
+#[derive(Default)]
+pub struct SimulatedConditions {
+    conditions: DashMap<NodeId, NetworkConditions>,
+}
+
+impl SimulatedConditions {
+    #[cfg(feature = "simulation-mode")]
+    pub fn set(&self, name: NodeId, conditions: Option<NetworkConditions>) {
+        match conditions {
+            Some(conditions) => {
+                self.conditions.insert(name, conditions);
+            }
+            None => {
+                self.conditions.remove(&name);
+            }
+        }
+    }
+
+    pub fn get(&self) -> BTreeMap<NodeId, NetworkConditions> {
+        self.conditions
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    /// whether we act as if `name` were offline
+    pub fn is_disconnected(&self, name: &str) -> bool {
+        self.conditions
+            .get(name)
+            .is_some_and(|conditions| conditions.disconnected)
+    }
+}
+
+/// Apply the conditions simulated for the target of `km`, returning it
+/// if it should be sent right away. Otherwise it is dropped, failed as
+/// offline, or sent once its latency is up.
+pub async fn apply(ext: &IdentityExt, data: &NetData, km: KernelMessage) -> Option<KernelMessage> {
+    let Some(conditions) = data
+        .simulated
+        .conditions
+        .get(&km.target.node)
+        .map(|conditions| conditions.clone())
+    else {
+        return Some(km);
+    };
+    if conditions.disconnected {
+        error_offline(km, &ext.network_error_tx).await;
+        return None;
+    }
+    let mut rng = rand::thread_rng();
+    if rng.gen::<f64>() < conditions.loss {
+        return None;
+    }
+    let jitter = rng.gen_range(0..=conditions.jitter_ms * 2);
+    let delay_ms = (conditions.latency_ms + jitter).saturating_sub(conditions.jitter_ms);
+    if delay_ms == 0 {
+        return Some(km);
+    }
+    let ext = ext.clone();
+    let data = data.clone();
+    tokio::spawn(async move {
+        time::sleep(time::Duration::from_millis(delay_ms)).await;
+        connect::deliver(&ext, &data, km).await;
+    });
+    None
+}
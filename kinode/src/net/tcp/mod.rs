@@ -1,15 +1,19 @@
 use crate::net::{
-    types::{IdentityExt, NetData, Peer, PendingStream, RoutingRequest, TCP_PROTOCOL},
+    punch,
+    types::{
+        IdentityExt, NetData, Peer, PeerReceiver, PendingStream, RoutingRequest, TCP_PROTOCOL,
+    },
     utils::{
         build_initiator, build_responder, create_passthrough, make_conn_url, print_debug,
         validate_handshake, validate_routing_request, TIMEOUT,
     },
 };
-use lib::types::core::{Identity, KernelMessage};
+use lib::types::core::{Identity, ObservedAddress};
 use {
     anyhow::anyhow,
+    std::net::SocketAddr,
     tokio::net::{TcpListener, TcpStream},
-    tokio::{sync::mpsc, time},
+    tokio::time,
 };
 
 pub mod utils;
@@ -83,8 +87,8 @@ pub async fn init_direct(
     peer_id: &Identity,
     port: u16,
     proxy_request: bool,
-    peer_rx: mpsc::UnboundedReceiver<KernelMessage>,
-) -> Result<(), mpsc::UnboundedReceiver<KernelMessage>> {
+    peer_rx: PeerReceiver,
+) -> Result<(), PeerReceiver> {
     match time::timeout(
         TIMEOUT,
         connect_with_handshake(ext, peer_id, port, None, proxy_request),
@@ -118,14 +122,49 @@ pub async fn init_direct(
     }
 }
 
+/// Connect to a peer at the address it advertised on the local network.
+pub async fn init_lan(
+    ext: &IdentityExt,
+    data: &NetData,
+    peer_id: &Identity,
+    addr: SocketAddr,
+    peer_rx: PeerReceiver,
+) -> Result<(), PeerReceiver> {
+    let connect = async {
+        let stream = TcpStream::connect(addr).await?;
+        handshake_as_initiator(ext, peer_id, stream, false).await
+    };
+    match time::timeout(TIMEOUT, connect).await {
+        Ok(Ok(connection)) => {
+            tokio::spawn(utils::maintain_connection(
+                peer_id.name.clone(),
+                data.peers.clone(),
+                connection,
+                peer_rx,
+                ext.kernel_message_tx.clone(),
+                ext.print_tx.clone(),
+            ));
+            Ok(())
+        }
+        Ok(Err(e)) => {
+            print_debug(&ext.print_tx, &format!("net: error in tcp::init_lan: {e}")).await;
+            Err(peer_rx)
+        }
+        Err(_) => {
+            print_debug(&ext.print_tx, "net: tcp::init_lan timed out").await;
+            Err(peer_rx)
+        }
+    }
+}
+
 pub async fn init_routed(
     ext: &IdentityExt,
     data: &NetData,
     peer_id: &Identity,
     router_id: &Identity,
     router_port: u16,
-    peer_rx: mpsc::UnboundedReceiver<KernelMessage>,
-) -> Result<(), mpsc::UnboundedReceiver<KernelMessage>> {
+    peer_rx: PeerReceiver,
+) -> Result<(), PeerReceiver> {
     match time::timeout(
         TIMEOUT,
         connect_with_handshake(ext, peer_id, router_port, Some(router_id), false),
@@ -155,7 +194,7 @@ pub async fn init_routed(
     }
 }
 
-async fn recv_connection(
+pub async fn recv_connection(
     ext: IdentityExt,
     data: NetData,
     mut stream: TcpStream,
@@ -163,6 +202,23 @@ async fn recv_connection(
     // before we begin XX handshake pattern, check first message over socket
     let (len, first_message) = utils::recv_raw(&mut stream).await?;
 
+    // a node behind NAT asking for the public address of its socket,
+    // so it can try punching through to a peer
+    if first_message == punch::OBSERVE_ADDRESS {
+        let address = stream.peer_addr()?.to_string();
+        let signature = ext
+            .keypair
+            .sign(&[punch::OBSERVE_ADDRESS, address.as_bytes()].concat())
+            .as_ref()
+            .to_vec();
+        let observed = ObservedAddress {
+            address,
+            observer: ext.our.name.clone(),
+            signature,
+        };
+        return utils::send_raw(&mut stream, &rmp_serde::to_vec(&observed)?).await;
+    }
+
     // if the first message contains a "routing request",
     // we see if the target is someone we are actively routing for,
     // and create a Passthrough connection if so.
@@ -206,6 +262,14 @@ async fn recv_connection(
             .ok_or(anyhow!("noise error: missing remote pubkey"))?,
         &their_id,
     )?;
+    if !data.access.permits(&ext.our, &their_handshake.name)
+        || data.simulated.is_disconnected(&their_handshake.name)
+    {
+        return Err(anyhow!(
+            "{} is not permitted to connect",
+            their_handshake.name
+        ));
+    }
 
     // if we already have a connection to this peer, kill it so we
     // don't build a duplicate connection
@@ -273,6 +337,17 @@ async fn connect_with_handshake(
         .await?;
     }
 
+    handshake_as_initiator(ext, peer_id, stream, proxy_request).await
+}
+
+/// Run the XX handshake over `stream` as its initiator, expecting to find `peer_id`
+/// on the other end.
+pub async fn handshake_as_initiator(
+    ext: &IdentityExt,
+    peer_id: &Identity,
+    mut stream: TcpStream,
+    proxy_request: bool,
+) -> anyhow::Result<PeerConnection> {
     let mut buf = [0u8; 65535];
     let (mut noise, our_static_key) = build_initiator();
 
@@ -371,6 +446,16 @@ async fn connect_with_handshake_via_router(
     )
     .await?;
 
+    handshake_as_responder(ext, peer_id, stream).await
+}
+
+/// Run the XX handshake over `stream` as its responder, expecting to find `peer_id`
+/// on the other end.
+pub async fn handshake_as_responder(
+    ext: &IdentityExt,
+    peer_id: &Identity,
+    mut stream: TcpStream,
+) -> anyhow::Result<PeerConnection> {
     let mut buf = [0u8; 65535];
     let (mut noise, our_static_key) = build_responder();
 
@@ -1,6 +1,8 @@
 use crate::net::{
+    compression::Compression,
+    keepalive::{self, Liveness},
     tcp::PeerConnection,
-    types::{HandshakePayload, IdentityExt, Peers},
+    types::{HandshakePayload, IdentityExt, PeerReceiver, Peers},
     utils::{print_debug, print_loud, IDLE_TIMEOUT, MESSAGE_MAX_SIZE},
 };
 use lib::types::core::{
@@ -9,7 +11,6 @@ use lib::types::core::{
 use {
     tokio::io::{AsyncReadExt, AsyncWriteExt},
     tokio::net::{tcp::OwnedReadHalf, tcp::OwnedWriteHalf, TcpStream},
-    tokio::sync::mpsc::UnboundedReceiver,
 };
 
 /// should always be spawned on its own task
@@ -17,7 +18,7 @@ pub async fn maintain_connection(
     peer_name: NodeId,
     peers: Peers,
     mut conn: PeerConnection,
-    mut peer_rx: UnboundedReceiver<KernelMessage>,
+    mut peer_rx: PeerReceiver,
     kernel_message_tx: MessageSender,
     print_tx: PrintSender,
 ) {
@@ -40,24 +41,71 @@ pub async fn maintain_connection(
         (c2, c1)
     };
 
+    // keepalives jump the queue, so that a busy connection isn't taken for a dead one
+    let (control_tx, mut control_rx) = tokio::sync::mpsc::unbounded_channel();
+    let liveness = Liveness::new();
+    let compression = Compression::new(peers.compression_threshold());
+    if let Some(offer) = compression.offer(peers.our(), &peer_name) {
+        let _ = control_tx.send(offer);
+    }
+
     let write_buf = &mut [0; 65536];
+    let write_peers = peers.clone();
+    let write_peer_name = peer_name.clone();
+    let write_rx = &mut peer_rx;
+    let write_compression = &compression;
     let write = async move {
-        while let Some(km) = peer_rx.recv().await {
-            let Ok(()) =
+        loop {
+            let km = tokio::select! {
+                biased;
+                Some(km) = control_rx.recv() => km,
+                km = write_rx.recv() => match km {
+                    Some(km) => write_compression.compress(km),
+                    None => break,
+                },
+            };
+            let Ok(bytes) =
                 send_protocol_message(&km, &mut our_cipher, write_buf, &mut write_stream).await
             else {
+                write_peers.record_connection_error(&write_peer_name);
                 break;
             };
+            write_peers.record_sent(&write_peer_name, bytes);
         }
     };
 
     let read_buf = &mut conn.buf;
     let read_peer_name = peer_name.clone();
     let read_print_tx = print_tx.clone();
+    let read_peers = peers.clone();
+    let read_liveness = &liveness;
+    let read_control_tx = &control_tx;
+    let read_compression = &compression;
     let read = async move {
         loop {
-            match recv_protocol_message(&mut their_cipher, read_buf, &mut read_stream).await {
-                Ok(km) => {
+            match recv_protocol_message(
+                &mut their_cipher,
+                read_buf,
+                &mut read_stream,
+                read_liveness,
+            )
+            .await
+            {
+                Ok((km, bytes)) => {
+                    read_peers.record_received(&read_peer_name, bytes);
+                    let km = match read_compression.receive(km) {
+                        Ok(Some(km)) => km,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            read_peers.record_connection_error(&read_peer_name);
+                            print_debug(
+                                &read_print_tx,
+                                &format!("net: error unpacking compressed message: {e}"),
+                            )
+                            .await;
+                            break;
+                        }
+                    };
                     if km.source.node != read_peer_name {
                         print_loud(
                             &read_print_tx,
@@ -77,12 +125,16 @@ pub async fn maintain_connection(
                         .await;
                         break;
                     }
+                    if keepalive::handle(&km, &read_peers, read_liveness, read_control_tx) {
+                        continue;
+                    }
                     kernel_message_tx
                         .send(km)
                         .await
                         .expect("net: fatal: kernel receiver died");
                 }
                 Err(e) => {
+                    read_peers.record_connection_error(&read_peer_name);
                     print_debug(
                         &read_print_tx,
                         &format!("net: error receiving message: {e}"),
@@ -95,6 +147,7 @@ pub async fn maintain_connection(
     };
 
     let timeout = tokio::time::sleep(IDLE_TIMEOUT);
+    let mut dead = false;
 
     tokio::select! {
         _ = write => (),
@@ -102,10 +155,18 @@ pub async fn maintain_connection(
         _ = timeout => {
             print_debug(&print_tx, &format!("net: closing idle connection with {peer_name}")).await;
         }
+        _ = keepalive::watch(&peers, &peer_name, &liveness, &control_tx) => {
+            print_debug(&print_tx, &format!("net: {peer_name} stopped answering pings")).await;
+            peers.record_connection_error(&peer_name);
+            dead = true;
+        }
     }
 
     print_debug(&print_tx, &format!("net: connection lost with {peer_name}")).await;
     peers.remove(&peer_name).await;
+    if dead {
+        peers.resend(&mut peer_rx).await;
+    }
 }
 
 async fn send_protocol_message(
@@ -113,7 +174,7 @@ async fn send_protocol_message(
     cipher: &mut snow::CipherState,
     buf: &mut [u8],
     stream: &mut OwnedWriteHalf,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<usize> {
     let serialized = rmp_serde::to_vec(km)?;
     if serialized.len() > MESSAGE_MAX_SIZE as usize {
         return Err(anyhow::anyhow!("message too large"));
@@ -128,16 +189,22 @@ async fn send_protocol_message(
         stream.write_all(&len.to_be_bytes()).await?;
         stream.write_all(&buf[..len as usize]).await?;
     }
-    Ok(stream.flush().await?)
+    stream.flush().await?;
+    Ok(serialized.len())
 }
 
 /// any error in receiving a message will result in the connection being closed.
+/// returns the message along with its serialized size. every frame read counts
+/// as hearing from the peer, so that a large message slowly arriving isn't taken
+/// for a dead connection.
 async fn recv_protocol_message(
     cipher: &mut snow::CipherState,
     buf: &mut [u8],
     stream: &mut OwnedReadHalf,
-) -> anyhow::Result<KernelMessage> {
+    liveness: &Liveness,
+) -> anyhow::Result<(KernelMessage, usize)> {
     stream.read_exact(&mut buf[..4]).await?;
+    liveness.heard();
     let outer_len = u32::from_be_bytes(buf[..4].try_into().unwrap()) as usize;
 
     let mut msg = vec![0; outer_len];
@@ -148,10 +215,11 @@ async fn recv_protocol_message(
         let inner_len = u16::from_be_bytes(inner_len);
 
         stream.read_exact(&mut buf[..inner_len as usize]).await?;
+        liveness.heard();
         let read_len = cipher.decrypt(&buf[..inner_len as usize], &mut msg[ptr..])?;
         ptr += read_len;
     }
-    Ok(rmp_serde::from_slice(&msg)?)
+    Ok((rmp_serde::from_slice(&msg)?, outer_len))
 }
 
 pub async fn send_protocol_handshake(
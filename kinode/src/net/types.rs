@@ -1,11 +1,17 @@
+use crate::net::{
+    access::PeerAccessList, keepalive::KeepaliveConfig, offline::OfflineQueues,
+    router_scores::RouterScores, simulate::SimulatedConditions, utils::get_now,
+};
 use lib::types::core::{
-    Address, Identity, KernelMessage, MessageSender, NetworkErrorSender, NodeId, PrintSender,
-    NET_PROCESS_ID,
+    Address, Identity, KernelMessage, Message, MessageSender, NetworkErrorSender, NodeId,
+    PeerStats, PrintSender, NET_PROCESS_ID,
 };
 use {
     dashmap::DashMap,
     ring::signature::Ed25519KeyPair,
     serde::{Deserialize, Serialize},
+    std::net::SocketAddr,
+    std::path::PathBuf,
     std::sync::atomic::AtomicU64,
     std::sync::Arc,
     tokio::net::TcpStream,
@@ -16,6 +22,10 @@ use {
 pub const WS_PROTOCOL: &str = "ws";
 pub const TCP_PROTOCOL: &str = "tcp";
 
+/// how many peers to keep [`PeerStats`] for. past this, the stats of the
+/// least recently active unconnected peer are dropped to make room.
+const MAX_PEER_STATS: usize = 4096;
+
 /// Sent to a node when you want to connect directly to them.
 /// Sent in the 'e, ee, s, es' and 's, se' phases of XX noise protocol pattern.
 ///
@@ -58,24 +68,124 @@ pub struct RoutingRequest {
 
 #[derive(Clone)]
 pub struct Peers {
+    our: Arc<NodeId>,
     max_peers: Arc<AtomicU64>,
+    keepalive: KeepaliveConfig,
+    /// serialized size in bytes over which we offer to compress messages,
+    /// or None if we don't
+    compression_threshold: Option<u64>,
     send_to_loop: MessageSender,
     peers: Arc<DashMap<String, Peer>>,
+    /// kept across connections, so not stored in [`Peer`]
+    stats: Arc<DashMap<String, PeerStats>>,
 }
 
 impl Peers {
-    pub fn new(max_peers: u64, send_to_loop: MessageSender) -> Self {
+    pub fn new(
+        our: NodeId,
+        max_peers: u64,
+        keepalive: KeepaliveConfig,
+        compression_threshold: Option<u64>,
+        send_to_loop: MessageSender,
+    ) -> Self {
         Self {
+            our: Arc::new(our),
             max_peers: Arc::new(max_peers.into()),
+            keepalive,
+            compression_threshold,
             send_to_loop,
             peers: Arc::new(DashMap::new()),
+            stats: Arc::new(DashMap::new()),
         }
     }
 
+    /// the stats of every peer, sorted by name
+    pub fn stats(&self) -> Vec<PeerStats> {
+        let mut stats: Vec<PeerStats> = self
+            .stats
+            .iter()
+            .map(|entry| PeerStats {
+                connected: self.peers.contains_key(entry.key()),
+                ..entry.value().clone()
+            })
+            .collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+
+    pub fn record_sent(&self, name: &str, bytes: usize) {
+        self.update_stats(name, |stats| {
+            stats.bytes_sent += bytes as u64;
+            stats.messages_sent += 1;
+            stats.last_activity = get_now();
+        });
+    }
+
+    pub fn record_received(&self, name: &str, bytes: usize) {
+        self.update_stats(name, |stats| {
+            stats.bytes_received += bytes as u64;
+            stats.messages_received += 1;
+            stats.last_activity = get_now();
+        });
+    }
+
+    pub fn record_connection_failure(&self, name: &str) {
+        self.update_stats(name, |stats| stats.connection_failures += 1);
+    }
+
+    pub fn record_connection_error(&self, name: &str) {
+        self.update_stats(name, |stats| stats.connection_errors += 1);
+    }
+
+    pub fn record_rtt(&self, name: &str, rtt_ms: u64) {
+        self.update_stats(name, |stats| {
+            // exponentially weighted, so one slow ping doesn't hide a good link
+            stats.rtt_ms = Some(match stats.rtt_ms {
+                Some(rtt) => (rtt * 3 + rtt_ms) / 4,
+                None => rtt_ms,
+            });
+        });
+    }
+
+    fn update_stats(&self, name: &str, update: impl FnOnce(&mut PeerStats)) {
+        if let Some(mut stats) = self.stats.get_mut(name) {
+            return update(&mut stats);
+        }
+        if self.stats.len() >= MAX_PEER_STATS {
+            let stalest = self
+                .stats
+                .iter()
+                .filter(|entry| !self.peers.contains_key(entry.key()))
+                .min_by_key(|entry| entry.last_activity)
+                .map(|entry| entry.key().clone());
+            if let Some(stalest) = stalest {
+                self.stats.remove(&stalest);
+            }
+        }
+        let mut stats = PeerStats {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        update(&mut stats);
+        self.stats.insert(name.to_string(), stats);
+    }
+
     pub fn peers(&self) -> &DashMap<String, Peer> {
         &self.peers
     }
 
+    pub fn our(&self) -> &str {
+        &self.our
+    }
+
+    pub fn keepalive(&self) -> KeepaliveConfig {
+        self.keepalive
+    }
+
+    pub fn compression_threshold(&self) -> Option<u64> {
+        self.compression_threshold
+    }
+
     pub fn max_peers(&self) -> u64 {
         self.max_peers.load(std::sync::atomic::Ordering::Relaxed)
     }
@@ -125,6 +235,16 @@ impl Peers {
         self.peers.remove(name)
     }
 
+    /// Send the messages left waiting on a dead connection back through the
+    /// kernel, which hands them back to us to send over a new connection.
+    /// The peer must already be removed, so that they aren't queued on it again.
+    pub async fn resend(&self, peer_rx: &mut PeerReceiver) {
+        peer_rx.close();
+        while let Some(km) = peer_rx.recv().await {
+            km.send(&self.send_to_loop).await;
+        }
+    }
+
     /// close the n oldest connections
     pub async fn cull(&self, n: usize) {
         let mut to_remove = Vec::with_capacity(n);
@@ -169,12 +289,65 @@ impl PendingStream {
 
 type KillSender = tokio::sync::mpsc::Sender<()>;
 
+/// peer -> (local address we punch through to them from, when we started)
+///
+/// only used by indirect nodes
+pub type HolePunches = Arc<DashMap<NodeId, (SocketAddr, u64)>>;
+
+/// Messages whose body and blob together are larger than this are sent as
+/// bulk traffic, behind any interactive messages waiting for the same peer.
+/// Well under the chunks ft-worker sends files in, so that transfers never
+/// hold up small control messages.
+const BULK_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// For each source and target pair of addresses, how many of its messages wait
+/// in the bulk queue of a [`Peer`]. While any do, the pair's small messages wait
+/// behind them, so that a pair's messages are always written in the order sent.
+type BulkPending = Arc<DashMap<(Address, Address), usize>>;
+
+/// The receiving end of a [`Peer`]'s queues, held by the task that writes to
+/// its connection. Interactive messages go out before bulk ones, except that
+/// messages between the same two processes keep their order.
+pub struct PeerReceiver {
+    interactive: UnboundedReceiver<KernelMessage>,
+    bulk: UnboundedReceiver<KernelMessage>,
+    bulk_pending: BulkPending,
+}
+
+impl PeerReceiver {
+    /// Receive the next message to write, preferring interactive traffic.
+    /// Returns None once both queues are closed and empty.
+    pub async fn recv(&mut self) -> Option<KernelMessage> {
+        tokio::select! {
+            biased;
+            Some(km) = self.interactive.recv() => Some(km),
+            Some(km) = self.bulk.recv() => {
+                let pair = (km.source.clone(), km.target.clone());
+                self.bulk_pending.remove_if_mut(&pair, |_, pending| {
+                    *pending -= 1;
+                    *pending == 0
+                });
+                Some(km)
+            },
+            else => None,
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.interactive.close();
+        self.bulk.close();
+    }
+}
+
 pub struct Peer {
     pub identity: Identity,
     /// If true, we are routing for them and have a RoutingClientConnection
     /// associated with them. We can send them prompts to establish Passthroughs.
     pub routing_for: bool,
+    /// queue for interactive traffic, which is written before bulk traffic
     pub sender: UnboundedSender<KernelMessage>,
+    pub bulk_sender: UnboundedSender<KernelMessage>,
+    bulk_pending: BulkPending,
     pub handle: Option<tokio::task::JoinHandle<()>>,
     /// unix timestamp of last message sent *or* received
     pub last_message: u64,
@@ -183,29 +356,51 @@ pub struct Peer {
 impl Peer {
     /// Create a new Peer.
     /// If `routing_for` is true, we are routing for them.
-    pub fn new(identity: Identity, routing_for: bool) -> (Self, UnboundedReceiver<KernelMessage>) {
+    pub fn new(identity: Identity, routing_for: bool) -> (Self, PeerReceiver) {
         let (peer_tx, peer_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (bulk_tx, bulk_rx) = tokio::sync::mpsc::unbounded_channel();
+        let bulk_pending: BulkPending = Arc::new(DashMap::new());
         (
             Self {
                 identity,
                 routing_for,
                 sender: peer_tx,
+                bulk_sender: bulk_tx,
+                bulk_pending: bulk_pending.clone(),
                 handle: None,
                 last_message: std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
                     .as_secs(),
             },
-            peer_rx,
+            PeerReceiver {
+                interactive: peer_rx,
+                bulk: bulk_rx,
+                bulk_pending,
+            },
         )
     }
 
-    /// Send a message to the peer.
+    /// Send a message to the peer, queued by its traffic class, or behind the
+    /// bulk messages of the same pair of processes that are still queued.
     pub fn send(
         &mut self,
         km: KernelMessage,
     ) -> Result<(), tokio::sync::mpsc::error::SendError<KernelMessage>> {
-        self.sender.send(km)?;
+        let pair = (km.source.clone(), km.target.clone());
+        if is_bulk(&km) || self.bulk_pending.contains_key(&pair) {
+            // counted before it is queued, so the writer can't see it first
+            *self.bulk_pending.entry(pair.clone()).or_insert(0) += 1;
+            if let Err(e) = self.bulk_sender.send(km) {
+                self.bulk_pending.remove_if_mut(&pair, |_, pending| {
+                    *pending -= 1;
+                    *pending == 0
+                });
+                return Err(e);
+            }
+        } else {
+            self.sender.send(km)?;
+        }
         self.set_last_message();
         Ok(())
     }
@@ -224,6 +419,18 @@ impl Peer {
         }
     }
 }
+
+fn is_bulk(km: &KernelMessage) -> bool {
+    let body = match &km.message {
+        Message::Request(request) => request.body.len(),
+        Message::Response((response, _)) => response.body.len(),
+    };
+    let blob = km
+        .lazy_load_blob
+        .as_ref()
+        .map_or(0, |blob| blob.bytes.len());
+    body + blob > BULK_MESSAGE_SIZE
+}
 /// [`Identity`], with additional fields for networking.
 #[derive(Clone)]
 pub struct IdentityExt {
@@ -234,6 +441,8 @@ pub struct IdentityExt {
     pub network_error_tx: NetworkErrorSender,
     pub print_tx: PrintSender,
     pub _reveal_ip: bool, // TODO use
+    /// where our keyfile is, to save the routers we go online with
+    pub keyfile_path: Arc<PathBuf>,
 }
 
 #[derive(Clone)]
@@ -246,4 +455,13 @@ pub struct NetData {
     pub active_passthroughs: ActivePassthroughs,
     pub max_passthroughs: u64,
     pub fds_limit: u64,
+    pub access: Arc<PeerAccessList>,
+    /// only used by indirect nodes
+    pub hole_punches: HolePunches,
+    pub router_scores: Arc<RouterScores>,
+    pub offline_queues: Arc<OfflineQueues>,
+    /// peers found on the local network -> their address there
+    pub lan_peers: Arc<DashMap<NodeId, SocketAddr>>,
+    /// only set in simulation mode
+    pub simulated: Arc<SimulatedConditions>,
 }
@@ -3,14 +3,15 @@ use crate::net::types::{
     RoutingRequest, TCP_PROTOCOL, WS_PROTOCOL,
 };
 use lib::types::core::{
-    Identity, KernelMessage, KnsUpdate, Message, MessageSender, NetAction, NetworkErrorSender,
-    NodeId, NodeRouting, PrintSender, Printout, Request, Response, SendError, SendErrorKind,
-    WrappedSendError, NET_PROCESS_ID,
+    Identity, KernelMessage, KnsUpdate, Message, MessageSender, NetAction, NetResponse,
+    NetworkErrorSender, NodeId, NodeRouting, PrintSender, Printout, Request, Response, SendError,
+    SendErrorKind, WrappedSendError, NET_PROCESS_ID,
 };
 use {
     futures::{SinkExt, StreamExt},
     ring::signature::{self},
     snow::params::NoiseParams,
+    std::path::Path,
     tokio::time,
     tokio_tungstenite::connect_async,
 };
@@ -37,6 +38,15 @@ pub async fn create_passthrough(
     data: &NetData,
     socket_1: PendingStream,
 ) -> anyhow::Result<()> {
+    if !data.access.permits(&ext.our, &from_id.name)
+        || !data.access.permits(&ext.our, &target_id.name)
+    {
+        return Err(anyhow::anyhow!(
+            "passthrough denied: {} -> {} is not permitted",
+            from_id.name,
+            target_id.name
+        ));
+    }
     // if we already are at the max number of passthroughs, reject
     if data.max_passthroughs == 0 {
         return Err(anyhow::anyhow!(
@@ -388,6 +398,25 @@ pub async fn error_offline(km: KernelMessage, network_error_tx: &NetworkErrorSen
         .expect("net: network_error_tx was dropped");
 }
 
+/// a response from net on one node to net on another, handled by net itself
+pub fn net_response(from: &str, to: &str, body: NetResponse) -> KernelMessage {
+    KernelMessage::builder()
+        .id(rand::random())
+        .source((from, "net", "distro", "sys"))
+        .target((to, "net", "distro", "sys"))
+        .message(Message::Response((
+            Response {
+                inherit: false,
+                body: rmp_serde::to_vec(&body).unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            },
+            None,
+        )))
+        .build()
+        .unwrap()
+}
+
 pub fn net_key_string_to_hex(s: &str) -> Vec<u8> {
     hex::decode(s.strip_prefix("0x").unwrap_or(s)).unwrap_or_default()
 }
@@ -441,6 +470,23 @@ pub async fn print_debug(print_tx: &PrintSender, content: &str) {
         .await;
 }
 
+/// Save the routers we go online with into our keyfile, for when we next boot.
+/// The keyfile is written aside and renamed over the old one, so that a crash
+/// mid-write can't leave a node without its keys.
+pub async fn save_routers(keyfile_path: &Path, routers: Vec<NodeId>) -> Result<(), String> {
+    let keyfile = tokio::fs::read(keyfile_path)
+        .await
+        .map_err(|e| format!("failed to read keyfile: {e}"))?;
+    let keyfile = crate::keygen::set_routers(&keyfile, routers)?;
+    let tmp_path = keyfile_path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, keyfile)
+        .await
+        .map_err(|e| format!("failed to write keyfile: {e}"))?;
+    tokio::fs::rename(&tmp_path, keyfile_path)
+        .await
+        .map_err(|e| format!("failed to replace keyfile: {e}"))
+}
+
 pub fn get_now() -> u64 {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -1,16 +1,16 @@
 use crate::net::{
-    types::{IdentityExt, NetData, Peer, PendingStream, RoutingRequest, WS_PROTOCOL},
+    types::{IdentityExt, NetData, Peer, PeerReceiver, PendingStream, RoutingRequest, WS_PROTOCOL},
     utils::{
         build_initiator, build_responder, create_passthrough, make_conn_url, print_debug,
         validate_handshake, validate_routing_request, TIMEOUT,
     },
 };
-use lib::types::core::{Identity, KernelMessage};
+use lib::types::core::Identity;
 use {
     anyhow::{anyhow, Result},
     futures::SinkExt,
     tokio::net::{TcpListener, TcpStream},
-    tokio::{sync::mpsc, time},
+    tokio::time,
     tokio_tungstenite::{
         accept_async, connect_async, tungstenite, MaybeTlsStream, WebSocketStream,
     },
@@ -96,8 +96,8 @@ pub async fn init_direct(
     peer_id: &Identity,
     port: u16,
     proxy_request: bool,
-    peer_rx: mpsc::UnboundedReceiver<KernelMessage>,
-) -> Result<(), mpsc::UnboundedReceiver<KernelMessage>> {
+    peer_rx: PeerReceiver,
+) -> Result<(), PeerReceiver> {
     match time::timeout(
         TIMEOUT,
         connect_with_handshake(ext, peer_id, port, None, proxy_request),
@@ -137,8 +137,8 @@ pub async fn init_routed(
     peer_id: &Identity,
     router_id: &Identity,
     router_port: u16,
-    peer_rx: mpsc::UnboundedReceiver<KernelMessage>,
-) -> Result<(), mpsc::UnboundedReceiver<KernelMessage>> {
+    peer_rx: PeerReceiver,
+) -> Result<(), PeerReceiver> {
     match time::timeout(
         TIMEOUT,
         connect_with_handshake(ext, peer_id, router_port, Some(router_id), false),
@@ -262,6 +262,14 @@ async fn recv_connection(
             .ok_or(anyhow!("noise error: missing remote pubkey"))?,
         &their_id,
     )?;
+    if !data.access.permits(&ext.our, &their_handshake.name)
+        || data.simulated.is_disconnected(&their_handshake.name)
+    {
+        return Err(anyhow!(
+            "{} is not permitted to connect",
+            their_handshake.name
+        ));
+    }
 
     // if we already have a connection to this peer, kill it so we
     // don't build a duplicate connection
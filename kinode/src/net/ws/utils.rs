@@ -1,12 +1,13 @@
 use crate::net::{
-    types::{HandshakePayload, IdentityExt, Peers},
+    compression::Compression,
+    keepalive::{self, Liveness},
+    types::{HandshakePayload, IdentityExt, PeerReceiver, Peers},
     utils::{print_debug, print_loud, IDLE_TIMEOUT, MESSAGE_MAX_SIZE},
     ws::{PeerConnection, WebSocket},
 };
 use lib::core::{check_process_id_kimap_safe, KernelMessage, MessageSender, NodeId, PrintSender};
 use {
     futures::{SinkExt, StreamExt},
-    tokio::sync::mpsc::UnboundedReceiver,
     tokio_tungstenite::tungstenite,
 };
 
@@ -18,7 +19,7 @@ pub async fn maintain_connection(
     peer_name: NodeId,
     peers: Peers,
     mut conn: PeerConnection,
-    mut peer_rx: UnboundedReceiver<KernelMessage>,
+    mut peer_rx: PeerReceiver,
     kernel_message_tx: MessageSender,
     print_tx: PrintSender,
 ) {
@@ -33,37 +34,54 @@ pub async fn maintain_connection(
         (c2, c1)
     };
 
+    // keepalives jump the queue, so that a busy connection isn't taken for a dead one
+    let (control_tx, mut control_rx) = tokio::sync::mpsc::unbounded_channel();
+    let liveness = Liveness::new();
+    let compression = Compression::new(peers.compression_threshold());
+    if let Some(offer) = compression.offer(peers.our(), &peer_name) {
+        let _ = control_tx.send(offer);
+    }
+
     let write_buf = &mut [0; 65536];
     let write_print_tx = print_tx.clone();
+    let write_peers = peers.clone();
+    let write_peer_name = peer_name.clone();
+    let write_rx = &mut peer_rx;
+    let write_compression = &compression;
     let write = async move {
         loop {
             tokio::select! {
-                Some(km) = peer_rx.recv() => {
-                    if let Err(e) =
-                        send_protocol_message(&km, &mut our_cipher, write_buf, &mut write_stream).await
-                    {
-                        if e.to_string() == "message too large" {
-                            // this will result in a Timeout if the message
-                            // requested a response, otherwise nothing. so,
-                            // we should always print something to terminal
-                            print_loud(
-                                &write_print_tx,
-                                &format!(
-                                    "net: tried to send too-large message, limit is {:.2}mb",
-                                    MESSAGE_MAX_SIZE as f64 / 1_048_576.0
-                                ),
-                            )
-                            .await;
-                        }
+                biased;
+                Some(km) = control_rx.recv() => {
+                    if send_protocol_message(&km, &mut our_cipher, write_buf, &mut write_stream).await.is_err() {
+                        write_peers.record_connection_error(&write_peer_name);
                         break;
                     }
                 }
-                // keepalive ping -- note that we don't look for pongs
-                // just to close if the connection is truly dead
-                _ = tokio::time::sleep(std::time::Duration::from_secs(30)) => {
-                    match write_stream.send(tungstenite::Message::Ping(vec![])).await {
-                        Ok(()) => continue,
-                        Err(_) => break,
+                km = write_rx.recv() => {
+                    let Some(km) = km else {
+                        break;
+                    };
+                    let km = write_compression.compress(km);
+                    match send_protocol_message(&km, &mut our_cipher, write_buf, &mut write_stream).await {
+                        Ok(bytes) => write_peers.record_sent(&write_peer_name, bytes),
+                        Err(e) => {
+                            write_peers.record_connection_error(&write_peer_name);
+                            if e.to_string() == "message too large" {
+                                // this will result in a Timeout if the message
+                                // requested a response, otherwise nothing. so,
+                                // we should always print something to terminal
+                                print_loud(
+                                    &write_print_tx,
+                                    &format!(
+                                        "net: tried to send too-large message, limit is {:.2}mb",
+                                        MESSAGE_MAX_SIZE as f64 / 1_048_576.0
+                                    ),
+                                )
+                                .await;
+                            }
+                            break;
+                        }
                     }
                 }
             }
@@ -73,10 +91,35 @@ pub async fn maintain_connection(
     let read_buf = &mut conn.buf;
     let read_peer_name = peer_name.clone();
     let read_print_tx = print_tx.clone();
+    let read_peers = peers.clone();
+    let read_liveness = &liveness;
+    let read_control_tx = &control_tx;
+    let read_compression = &compression;
     let read = async move {
         loop {
-            match recv_protocol_message(&mut their_cipher, read_buf, &mut read_stream).await {
-                Ok(km) => {
+            match recv_protocol_message(
+                &mut their_cipher,
+                read_buf,
+                &mut read_stream,
+                read_liveness,
+            )
+            .await
+            {
+                Ok((km, bytes)) => {
+                    read_peers.record_received(&read_peer_name, bytes);
+                    let km = match read_compression.receive(km) {
+                        Ok(Some(km)) => km,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            read_peers.record_connection_error(&read_peer_name);
+                            print_debug(
+                                &read_print_tx,
+                                &format!("net: error unpacking compressed message: {e}"),
+                            )
+                            .await;
+                            break;
+                        }
+                    };
                     if km.source.node != read_peer_name {
                         print_loud(
                             &read_print_tx,
@@ -96,12 +139,16 @@ pub async fn maintain_connection(
                         .await;
                         break;
                     }
+                    if keepalive::handle(&km, &read_peers, read_liveness, read_control_tx) {
+                        continue;
+                    }
                     kernel_message_tx
                         .send(km)
                         .await
                         .expect("net: fatal: kernel receiver died");
                 }
                 Err(e) => {
+                    read_peers.record_connection_error(&read_peer_name);
                     print_debug(
                         &read_print_tx,
                         &format!("net: error receiving message: {e}"),
@@ -114,6 +161,7 @@ pub async fn maintain_connection(
     };
 
     let timeout = tokio::time::sleep(IDLE_TIMEOUT);
+    let mut dead = false;
 
     tokio::select! {
         _ = write => (),
@@ -121,10 +169,18 @@ pub async fn maintain_connection(
         _ = timeout => {
             print_debug(&print_tx, &format!("net: closing idle connection with {peer_name}")).await;
         }
+        _ = keepalive::watch(&peers, &peer_name, &liveness, &control_tx) => {
+            print_debug(&print_tx, &format!("net: {peer_name} stopped answering pings")).await;
+            peers.record_connection_error(&peer_name);
+            dead = true;
+        }
     }
 
     print_debug(&print_tx, &format!("net: connection lost with {peer_name}")).await;
     peers.remove(&peer_name).await;
+    if dead {
+        peers.resend(&mut peer_rx).await;
+    }
 }
 
 async fn send_protocol_message(
@@ -132,13 +188,14 @@ async fn send_protocol_message(
     cipher: &mut snow::CipherState,
     buf: &mut [u8],
     stream: &mut WsWriteHalf,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<usize> {
     let serialized = rmp_serde::to_vec(km)?;
     if serialized.len() > MESSAGE_MAX_SIZE as usize {
         return Err(anyhow::anyhow!("message too large"));
     }
 
-    let len = (serialized.len() as u32).to_be_bytes();
+    let serialized_len = serialized.len();
+    let len = (serialized_len as u32).to_be_bytes();
     let with_length_prefix = [len.to_vec(), serialized].concat();
 
     // 65519 = 65535 - 16 (TAGLEN)
@@ -149,16 +206,20 @@ async fn send_protocol_message(
             .await?;
     }
     stream.flush().await?;
-    Ok(())
+    Ok(serialized_len)
 }
 
 /// any error in receiving a message will result in the connection being closed.
+/// returns the message along with its serialized size. every frame read counts
+/// as hearing from the peer, so that a large message slowly arriving isn't taken
+/// for a dead connection.
 async fn recv_protocol_message(
     cipher: &mut snow::CipherState,
     buf: &mut [u8],
     stream: &mut WsReadHalf,
-) -> anyhow::Result<KernelMessage> {
-    let outer_len = cipher.decrypt(&recv_read_only(stream).await?, buf)?;
+    liveness: &Liveness,
+) -> anyhow::Result<(KernelMessage, usize)> {
+    let outer_len = cipher.decrypt(&recv_read_only(stream, liveness).await?, buf)?;
 
     if outer_len < 4 {
         return Err(anyhow::anyhow!("protocol message too small!"));
@@ -174,11 +235,11 @@ async fn recv_protocol_message(
     msg.extend_from_slice(&buf[4..outer_len]);
 
     while msg.len() < msg_len as usize {
-        let len = cipher.decrypt(&recv_read_only(stream).await?, buf)?;
+        let len = cipher.decrypt(&recv_read_only(stream, liveness).await?, buf)?;
         msg.extend_from_slice(&buf[..len]);
     }
 
-    Ok(rmp_serde::from_slice(&msg)?)
+    Ok((rmp_serde::from_slice(&msg)?, msg_len as usize))
 }
 
 pub async fn send_protocol_handshake(
@@ -231,11 +292,18 @@ pub async fn recv(socket: &mut WebSocket) -> anyhow::Result<Vec<u8>> {
     }
 }
 
-/// Receive a byte array from a read stream. If this returns an error,
-/// we should close the connection.
-pub async fn recv_read_only(socket: &mut WsReadHalf) -> anyhow::Result<Vec<u8>> {
+/// Receive a byte array from a read stream, noting every frame, pings included,
+/// as heard from the peer. If this returns an error, we should close the connection.
+pub async fn recv_read_only(
+    socket: &mut WsReadHalf,
+    liveness: &Liveness,
+) -> anyhow::Result<Vec<u8>> {
     loop {
-        match socket.next().await {
+        let frame = socket.next().await;
+        if let Some(Ok(_)) = frame {
+            liveness.heard();
+        }
+        match frame {
             Some(Ok(tungstenite::Message::Ping(_))) => continue,
             Some(Ok(tungstenite::Message::Pong(_))) => continue,
             Some(Ok(tungstenite::Message::Binary(bin))) => return Ok(bin),
@@ -0,0 +1,489 @@
+//! Notifications of events on the node, delivered through the channels picked in
+//! the settings app.
+//!
+//! Events are reported to the `notify:distro:sys` runtime module by the App Store,
+//! for app updates, and by http-server, for logins, while the module itself checks
+//! the free space on the disk of the home directory. Those of the kinds enabled
+//! become [`Notification`]s, which are posted to the homepage's inbox,
+//! `notifications:homepage:sys`, where they are listed and counted under the
+//! settings app, then emailed and POSTed to a webhook, if set. The settings are kept
+//! in `.notify` in the home directory, with the SMTP password encrypted with the
+//! keyfile's file key.
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key,
+};
+use lib::types::core::{
+    Address, CapMessage, CapMessageSender, Capability, ChannelTest, EmailSettings, KernelMessage,
+    Message, MessageReceiver, MessageSender, Notification, NotifyAction, NotifyError, NotifyEvent,
+    NotifyResponse, NotifySettings, PrintSender, Printout, ProcessId, Request, Response,
+    HTTP_SERVER_PROCESS_ID, NOTIFY_PROCESS_ID,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const NOTIFY_FILE: &str = ".notify";
+/// how often the free space on the disk is checked
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// IPs logged in from that are remembered, forgetting the least recently used first
+const MAX_LOGIN_IPS: usize = 1000;
+/// how long an email or webhook may take to send
+const SEND_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The settings as kept in `.notify`, with the SMTP password
+/// as a hex-encoded nonce followed by the encrypted password.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Stored {
+    settings: NotifySettings,
+    /// IPs logged in from, so that logins from others are notified, least recent first
+    login_ips: VecDeque<String>,
+}
+
+struct Notifier {
+    our: String,
+    send_to_loop: MessageSender,
+    print_tx: PrintSender,
+    home_directory_path: PathBuf,
+    path: PathBuf,
+    cipher: Aes256Gcm,
+    stored: Stored,
+}
+
+/// A runtime module that notifies events on the node. Events are reported by the
+/// processes that witness them, so each kind is taken only from its reporters, or
+/// from a process with the root capability, as given to the settings app, which
+/// every other action takes.
+pub async fn notify(
+    our: String,
+    send_to_loop: MessageSender,
+    print_tx: PrintSender,
+    mut recv_from_loop: MessageReceiver,
+    send_to_caps_oracle: CapMessageSender,
+    home_directory_path: PathBuf,
+    file_key: Vec<u8>,
+) -> anyhow::Result<()> {
+    let cipher = password_cipher(&file_key);
+    let path = home_directory_path.join(NOTIFY_FILE);
+    let stored = load(&path, &cipher).await;
+    let mut notifier = Notifier {
+        our,
+        send_to_loop,
+        print_tx,
+        home_directory_path: home_directory_path.clone(),
+        path,
+        cipher,
+        stored,
+    };
+    // low disk space is notified once, until space is freed
+    let mut low_disk = false;
+    let mut disk_check = tokio::time::interval(DISK_CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            Some(km) = recv_from_loop.recv() => {
+                if km.source.node != notifier.our {
+                    continue;
+                }
+                let Message::Request(ref req) = km.message else {
+                    continue;
+                };
+                let target = km.rsvp.clone().unwrap_or(km.source.clone());
+                let action = match serde_json::from_slice::<NotifyAction>(&req.body) {
+                    Ok(NotifyAction::Event(event)) => {
+                        if reports(&km.source.process, &event)
+                            || has_root_capability(&notifier.our, &km.source, &send_to_caps_oracle).await
+                        {
+                            notifier.event(event).await;
+                        }
+                        continue;
+                    }
+                    Ok(action) => {
+                        if has_root_capability(&notifier.our, &km.source, &send_to_caps_oracle).await {
+                            Ok(action)
+                        } else {
+                            Err(NotifyError::NoCap)
+                        }
+                    }
+                    Err(_) => Err(NotifyError::MalformedRequest),
+                };
+                let response = match action {
+                    Err(e) => NotifyResponse::Err(e),
+                    Ok(NotifyAction::Test) => {
+                        // answered once sent, which can take a while with a slow SMTP server
+                        let our = notifier.our.clone();
+                        let settings = notifier.stored.settings.clone();
+                        let send_to_loop = notifier.send_to_loop.clone();
+                        let client = crate::http::client::proxied_client(
+                            &notifier.home_directory_path,
+                            &NOTIFY_PROCESS_ID,
+                        )
+                        .await;
+                        let km_id = km.id;
+                        tokio::spawn(async move {
+                            let tests = test(&our, &settings, &client).await;
+                            respond(&our, km_id, target, NotifyResponse::Tested(tests), &send_to_loop).await;
+                        });
+                        continue;
+                    }
+                    Ok(action) => notifier.handle_root_action(action).await,
+                };
+                respond(&notifier.our, km.id, target, response, &notifier.send_to_loop).await;
+            }
+            _ = disk_check.tick() => {
+                let Some((available_bytes, total_bytes)) = disk_space(&home_directory_path) else {
+                    continue;
+                };
+                if available_bytes >= notifier.stored.settings.low_disk_bytes {
+                    low_disk = false;
+                } else if !low_disk {
+                    low_disk = true;
+                    notifier.event(NotifyEvent::LowDisk { available_bytes, total_bytes }).await;
+                }
+            }
+        }
+    }
+}
+
+impl Notifier {
+    /// Notify an event, if its kind is enabled.
+    async fn event(&mut self, event: NotifyEvent) {
+        if let NotifyEvent::Login { ip } = &event {
+            let first = self.stored.login_ips.is_empty();
+            let known = match self.stored.login_ips.iter().position(|known| known == ip) {
+                Some(index) => {
+                    self.stored.login_ips.remove(index);
+                    true
+                }
+                None => false,
+            };
+            self.stored.login_ips.push_back(ip.clone());
+            if self.stored.login_ips.len() > MAX_LOGIN_IPS {
+                self.stored.login_ips.pop_front();
+            }
+            // the first login is the user's own, and only learned from
+            if known || first {
+                let _ = self.save().await;
+                return;
+            }
+        }
+        if !self.stored.settings.events.contains(&event.kind()) {
+            let _ = self.save().await;
+            return;
+        }
+        let _ = self.save().await;
+        let notification = Notification {
+            id: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            message: event.to_string(),
+            event,
+        };
+
+        if self.stored.settings.badge {
+            // the inbox lists it under the settings app, where notifications are set up
+            let push = serde_json::json!({
+                "Push": {
+                    "title": notification.event.title(),
+                    "body": notification.message,
+                    "path": "/",
+                }
+            });
+            KernelMessage::builder()
+                .id(rand::random())
+                .source((self.our.as_str(), NOTIFY_PROCESS_ID.clone()))
+                .target((
+                    self.our.as_str(),
+                    ProcessId::new(Some("notifications"), "homepage", "sys"),
+                ))
+                .message(Message::Request(Request {
+                    inherit: false,
+                    expects_response: None,
+                    body: serde_json::to_vec(&push).unwrap(),
+                    metadata: None,
+                    capabilities: vec![],
+                }))
+                .build()
+                .unwrap()
+                .send(&self.send_to_loop)
+                .await;
+        }
+
+        // sent in the background, so that a slow SMTP server doesn't hold up the loop
+        let our = self.our.clone();
+        let settings = self.stored.settings.clone();
+        let print_tx = self.print_tx.clone();
+        let client = match settings.webhook {
+            Some(_) => Some(
+                crate::http::client::proxied_client(&self.home_directory_path, &NOTIFY_PROCESS_ID)
+                    .await,
+            ),
+            None => None,
+        };
+        tokio::spawn(async move {
+            if let Some(email) = &settings.email {
+                if let Err(e) = send_email(&our, email, &notification.message).await {
+                    Printout::new(
+                        0,
+                        NOTIFY_PROCESS_ID.clone(),
+                        format!("notify: failed to email notification: {e}"),
+                    )
+                    .send(&print_tx)
+                    .await;
+                }
+            }
+            if let (Some(webhook), Some(client)) = (&settings.webhook, &client) {
+                if let Err(e) = post_webhook(&our, client, webhook, Some(&notification)).await {
+                    Printout::new(
+                        0,
+                        NOTIFY_PROCESS_ID.clone(),
+                        format!("notify: failed to send notification to webhook: {e}"),
+                    )
+                    .send(&print_tx)
+                    .await;
+                }
+            }
+        });
+    }
+
+    async fn handle_root_action(&mut self, action: NotifyAction) -> NotifyResponse {
+        match action {
+            NotifyAction::GetSettings => {
+                let mut settings = self.stored.settings.clone();
+                if let Some(email) = settings.email.as_mut() {
+                    email.password.clear();
+                }
+                NotifyResponse::Settings(settings)
+            }
+            NotifyAction::SetSettings(mut settings) => {
+                if let (Some(email), Some(old)) =
+                    (settings.email.as_mut(), self.stored.settings.email.as_ref())
+                {
+                    if email.password.is_empty() {
+                        email.password = old.password.clone();
+                    }
+                }
+                self.stored.settings = settings;
+                match self.save().await {
+                    Ok(()) => NotifyResponse::Ok,
+                    Err(e) => NotifyResponse::Err(e),
+                }
+            }
+            // handled in the loop
+            NotifyAction::Event(_) | NotifyAction::Test => {
+                NotifyResponse::Err(NotifyError::MalformedRequest)
+            }
+        }
+    }
+
+    async fn save(&self) -> Result<(), NotifyError> {
+        let mut stored = self.stored.clone();
+        if let Some(email) = stored.settings.email.as_mut() {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let ciphertext = self
+                .cipher
+                .encrypt(&nonce, email.password.as_bytes())
+                .map_err(|e| NotifyError::IOError(e.to_string()))?;
+            email.password = hex::encode([nonce.as_slice(), &ciphertext].concat());
+        }
+        // written beside and renamed into place, so a crash never leaves it partial
+        let tmp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec(&stored).unwrap()).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+/// Whether a process is one that witnesses, so reports, events of this kind.
+/// Low disk space is only ever noticed by this module itself.
+fn reports(process: &ProcessId, event: &NotifyEvent) -> bool {
+    match event {
+        NotifyEvent::Login { .. } => process == &*HTTP_SERVER_PROCESS_ID,
+        NotifyEvent::UpdateAvailable { .. } | NotifyEvent::AutoUpdateFailed { .. } => {
+            process.package() == "app-store" && process.publisher() == "sys"
+        }
+        NotifyEvent::LowDisk { .. } => false,
+    }
+}
+
+/// Send a test notification through each channel set.
+async fn test(our: &str, settings: &NotifySettings, client: &reqwest::Client) -> Vec<ChannelTest> {
+    let mut tests = vec![];
+    if let Some(email) = &settings.email {
+        tests.push(ChannelTest {
+            channel: "email".to_string(),
+            error: send_email(our, email, "a test notification")
+                .await
+                .err()
+                .map(|e| e.to_string()),
+        });
+    }
+    if let Some(webhook) = &settings.webhook {
+        tests.push(ChannelTest {
+            channel: "webhook".to_string(),
+            error: post_webhook(our, client, webhook, None)
+                .await
+                .err()
+                .map(|e| e.to_string()),
+        });
+    }
+    tests
+}
+
+async fn send_email(our: &str, email: &EmailSettings, message: &str) -> anyhow::Result<()> {
+    use lettre::{
+        transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+        Tokio1Executor,
+    };
+    let mail = lettre::Message::builder()
+        .from(email.from.parse()?)
+        .to(email.to.parse()?)
+        .subject(format!("{our}: {message}"))
+        .body(format!(
+            "{message}\n\nChoose what {our} notifies you of in its Settings app.\n"
+        ))?;
+    let relay = if email.smtp_port == 465 {
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&email.smtp_host)?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&email.smtp_host)?
+    };
+    relay
+        .port(email.smtp_port)
+        .credentials(Credentials::new(
+            email.username.clone(),
+            email.password.clone(),
+        ))
+        .timeout(Some(SEND_TIMEOUT))
+        .build()
+        .send(mail)
+        .await?;
+    Ok(())
+}
+
+/// POST a notification to a webhook, or a test notification if none.
+async fn post_webhook(
+    our: &str,
+    client: &reqwest::Client,
+    url: &str,
+    notification: Option<&Notification>,
+) -> anyhow::Result<()> {
+    let body = serde_json::json!({
+        "node": our,
+        "message": notification.map_or("a test notification", |n| n.message.as_str()),
+        "notification": notification,
+    });
+    client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_vec(&body).unwrap())
+        .timeout(SEND_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// The free and total space, in bytes, on the disk holding `path`.
+#[cfg(unix)]
+fn disk_space(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block_size = stat.f_frsize as u64;
+    Some((
+        stat.f_bavail as u64 * block_size,
+        stat.f_blocks as u64 * block_size,
+    ))
+}
+
+#[cfg(not(unix))]
+fn disk_space(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+async fn has_root_capability(
+    our: &str,
+    source: &Address,
+    send_to_caps_oracle: &CapMessageSender,
+) -> bool {
+    let (send_cap_bool, recv_cap_bool) = tokio::sync::oneshot::channel();
+    if send_to_caps_oracle
+        .send(CapMessage::Has {
+            on: source.process.clone(),
+            cap: Capability::new(
+                (our, NOTIFY_PROCESS_ID.clone()),
+                "{\"root\":true}".to_string(),
+            ),
+            responder: send_cap_bool,
+        })
+        .await
+        .is_err()
+    {
+        return false;
+    }
+    recv_cap_bool.await.unwrap_or(false)
+}
+
+async fn respond(
+    our: &str,
+    km_id: u64,
+    target: Address,
+    response: NotifyResponse,
+    send_to_loop: &MessageSender,
+) {
+    KernelMessage::builder()
+        .id(km_id)
+        .source((our, NOTIFY_PROCESS_ID.clone()))
+        .target(target)
+        .message(Message::Response((
+            Response {
+                inherit: false,
+                body: serde_json::to_vec(&response).unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            },
+            None,
+        )))
+        .build()
+        .unwrap()
+        .send(send_to_loop)
+        .await;
+}
+
+/// The cipher of the SMTP password, keyed from the keyfile's file key.
+fn password_cipher(file_key: &[u8]) -> Aes256Gcm {
+    let salt = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, b"kinode-notify");
+    let mut key = [0u8; 32];
+    salt.extract(file_key)
+        .expand(&[b"smtp-password".as_slice()], ring::hkdf::HKDF_SHA256)
+        .unwrap()
+        .fill(&mut key)
+        .unwrap();
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+async fn load(path: &Path, cipher: &Aes256Gcm) -> Stored {
+    let Ok(contents) = tokio::fs::read(path).await else {
+        return Stored::default();
+    };
+    let Ok(mut stored) = serde_json::from_slice::<Stored>(&contents) else {
+        return Stored::default();
+    };
+    if let Some(email) = stored.settings.email.as_mut() {
+        email.password = hex::decode(&email.password)
+            .ok()
+            .filter(|encrypted| encrypted.len() >= 12)
+            .and_then(|encrypted| {
+                let (nonce, ciphertext) = encrypted.split_at(12);
+                cipher.decrypt(nonce.into(), ciphertext).ok()
+            })
+            .and_then(|password| String::from_utf8(password).ok())
+            .unwrap_or_default();
+    }
+    stored
+}
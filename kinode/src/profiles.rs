@@ -0,0 +1,164 @@
+//! Named node homes, so that one install can run several nodes, such as a personal
+//! node and test nodes, without remembering the home and ports of each.
+//!
+//! A profile is saved by booting a home with `--save-profile <NAME>`, and booted
+//! again with `--profile <NAME>`. The last profile booted is booted when no home
+//! is given. Profiles that keep different ports can run side by side.
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const PROFILES_DIR: &str = ".kinode";
+const PROFILES_FILE: &str = "profiles.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Profile {
+    pub home: PathBuf,
+    /// ports left as None are picked at boot, as without a profile
+    pub port: Option<u16>,
+    pub ws_port: Option<u16>,
+    pub tcp_port: Option<u16>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Profiles {
+    /// the profile last booted, booted again when no home is given
+    pub current: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    pub async fn load() -> anyhow::Result<Self> {
+        match tokio::fs::read(profiles_path()?).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn save(&self) -> anyhow::Result<()> {
+        let path = profiles_path()?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+
+    /// Add or replace the profile `name`, unless another profile has the same
+    /// home, which would boot the same node twice, or one of the same ports.
+    pub fn add(&mut self, name: &str, profile: Profile) -> anyhow::Result<()> {
+        for (other_name, other) in self.profiles.iter().filter(|(n, _)| *n != name) {
+            if other.home == profile.home {
+                return Err(anyhow::anyhow!(
+                    "profile {other_name} already has home {}",
+                    profile.home.display()
+                ));
+            }
+            let ours = [profile.port, profile.ws_port, profile.tcp_port];
+            let theirs = [other.port, other.ws_port, other.tcp_port];
+            if let Some(port) = ours.iter().flatten().find(|p| theirs.contains(&Some(**p))) {
+                return Err(anyhow::anyhow!(
+                    "profile {other_name} already uses port {port}"
+                ));
+            }
+        }
+        self.profiles.insert(name.to_string(), profile);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Profile> {
+        if self.current.as_deref() == Some(name) {
+            self.current = None;
+        }
+        self.profiles.remove(name)
+    }
+}
+
+impl std::fmt::Display for Profiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.profiles.is_empty() {
+            return write!(f, "no profiles, save one with --save-profile <NAME>");
+        }
+        for (name, profile) in &self.profiles {
+            let ports: Vec<String> = [
+                ("http", profile.port),
+                ("ws", profile.ws_port),
+                ("tcp", profile.tcp_port),
+            ]
+            .iter()
+            .filter_map(|(kind, port)| port.map(|port| format!("{kind} {port}")))
+            .collect();
+            writeln!(
+                f,
+                "{}{name}: {}{}",
+                if self.current.as_deref() == Some(name.as_str()) {
+                    "* "
+                } else {
+                    "  "
+                },
+                profile.home.display(),
+                if ports.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", ports.join(", "))
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The home and ports to boot with: those given on the command line, saving them
+/// as a profile if asked to, or those of the profile given, or of the last one booted.
+/// Ports given on the command line are used over those of a profile, for this boot only.
+pub async fn select(matches: &ArgMatches) -> anyhow::Result<Profile> {
+    let port = matches.get_one::<u16>("port").cloned();
+    let ws_port = matches.get_one::<u16>("ws-port").cloned();
+    let tcp_port = matches.get_one::<u16>("tcp-port").cloned();
+
+    if let Some(home) = matches.get_one::<String>("home") {
+        let profile = Profile {
+            home: std::path::absolute(home)?,
+            port,
+            ws_port,
+            tcp_port,
+        };
+        if let Some(name) = matches.get_one::<String>("save-profile") {
+            let mut profiles = Profiles::load().await?;
+            profiles.add(name, profile.clone())?;
+            profiles.current = Some(name.clone());
+            profiles.save().await?;
+        }
+        return Ok(profile);
+    }
+
+    let mut profiles = Profiles::load().await?;
+    let name = match matches.get_one::<String>("profile") {
+        Some(name) => name.clone(),
+        None => profiles.current.clone().ok_or(anyhow::anyhow!(
+            "a home directory or --profile <NAME> is required"
+        ))?,
+    };
+    let Some(profile) = profiles.profiles.get(&name).cloned() else {
+        return Err(anyhow::anyhow!(
+            "no profile named {name}, see --list-profiles"
+        ));
+    };
+    if profiles.current.as_ref() != Some(&name) {
+        profiles.current = Some(name);
+        profiles.save().await?;
+    }
+    Ok(Profile {
+        home: profile.home,
+        port: port.or(profile.port),
+        ws_port: ws_port.or(profile.ws_port),
+        tcp_port: tcp_port.or(profile.tcp_port),
+    })
+}
+
+fn profiles_path() -> anyhow::Result<PathBuf> {
+    let home = std::env::var_os("HOME").ok_or(anyhow::anyhow!("HOME is not set"))?;
+    Ok(PathBuf::from(home).join(PROFILES_DIR).join(PROFILES_FILE))
+}
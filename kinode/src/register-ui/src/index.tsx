@@ -8,8 +8,10 @@ import './kinode.css';
 
 import {
   getDefaultConfig,
+  getDefaultWallets,
   RainbowKitProvider,
 } from '@rainbow-me/rainbowkit';
+import { ledgerWallet } from '@rainbow-me/rainbowkit/wallets';
 import { WagmiProvider, http } from 'wagmi';
 import {
   optimism,
@@ -24,9 +26,20 @@ import {
 import { Buffer } from 'buffer';
 window.Buffer = Buffer;
 
+// hardware wallets keep the owner key of a node off any computer it runs on.
+// Trezor has no connector of its own, but signs through MetaMask or Rabby.
+const { wallets } = getDefaultWallets();
+
 const config = getDefaultConfig({
   appName: 'Kinode Register UI',
   projectId: 'c6da298e8ee4e4b00ea32cd4c20c40af',
+  wallets: [
+    ...wallets,
+    {
+      groupName: 'Hardware',
+      wallets: [ledgerWallet],
+    },
+  ],
   chains: [optimism],
   ssr: false,
   transports: {
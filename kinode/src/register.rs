@@ -31,6 +31,14 @@ use {alloy_sol_macro::sol, alloy_sol_types::SolValue};
 type RegistrationSender = mpsc::Sender<(Identity, Keyfile, Vec<u8>)>;
 
 /// Serve the registration page and receive POSTs and PUTs from it
+/// How to sign the auth token issued once registration or login succeeds,
+/// so that http-server accepts it.
+pub struct AuthTokenConfig {
+    pub jwt_salt: Vec<u8>,
+    /// in seconds
+    pub lifetime: u64,
+}
+
 pub async fn register(
     tx: RegistrationSender,
     kill_rx: oneshot::Receiver<bool>,
@@ -41,6 +49,7 @@ pub async fn register(
     keyfile: Option<Vec<u8>>,
     maybe_rpc: Option<String>,
     detached: bool,
+    auth_token_config: AuthTokenConfig,
 ) {
     // Networking info is generated and passed to the UI, but not used until confirmed
     let (public_key, serialized_networking_keypair) = keygen::generate_networking_key();
@@ -127,6 +136,10 @@ pub async fn register(
     let boot_provider = provider.clone();
     let login_provider = provider.clone();
     let import_provider = provider.clone();
+    let auth_token_config = Arc::new(auth_token_config);
+    let boot_auth_token_config = auth_token_config.clone();
+    let login_auth_token_config = auth_token_config.clone();
+    let import_auth_token_config = auth_token_config;
 
     let api = warp::path("info")
         .and(
@@ -178,7 +191,15 @@ pub async fn register(
                 .and(net_keypair.clone())
                 .and_then(move |boot_info, tx, our_temp_id, net_keypair| {
                     let boot_provider = boot_provider.clone();
-                    handle_boot(boot_info, tx, our_temp_id, net_keypair, boot_provider)
+                    let auth_token_config = boot_auth_token_config.clone();
+                    handle_boot(
+                        boot_info,
+                        tx,
+                        our_temp_id,
+                        net_keypair,
+                        boot_provider,
+                        auth_token_config,
+                    )
                 }),
         ))
         .or(warp::path("import-keyfile").and(
@@ -191,7 +212,16 @@ pub async fn register(
                 .and(tx.clone())
                 .and_then(move |boot_info, ip, ws_port, tcp_port, tx| {
                     let import_provider = import_provider.clone();
-                    handle_import_keyfile(boot_info, ip, ws_port, tcp_port, tx, import_provider)
+                    let auth_token_config = import_auth_token_config.clone();
+                    handle_import_keyfile(
+                        boot_info,
+                        ip,
+                        ws_port,
+                        tcp_port,
+                        tx,
+                        import_provider,
+                        auth_token_config,
+                    )
                 }),
         ))
         .or(warp::path("login").and(
@@ -205,6 +235,7 @@ pub async fn register(
                 .and(keyfile.clone())
                 .and_then(move |boot_info, ip, ws_port, tcp_port, tx, keyfile| {
                     let login_provider = login_provider.clone();
+                    let auth_token_config = login_auth_token_config.clone();
                     handle_login(
                         boot_info,
                         ip,
@@ -213,6 +244,7 @@ pub async fn register(
                         tx,
                         keyfile,
                         login_provider,
+                        auth_token_config,
                     )
                 }),
         ));
@@ -303,6 +335,7 @@ async fn handle_boot(
     our: Arc<Identity>,
     networking_keypair: Arc<Vec<u8>>,
     provider: Arc<RootProvider<PubSubFrontend>>,
+    auth_token_config: Arc<AuthTokenConfig>,
 ) -> Result<impl Reply, Rejection> {
     let kimap = EthAddress::from_str(KIMAP_ADDRESS).unwrap();
     let mut our = our.as_ref().clone();
@@ -414,7 +447,14 @@ async fn handle_boot(
                     &decoded_keyfile.file_key,
                 );
 
-                return success_response(sender, our, decoded_keyfile, encoded_keyfile).await;
+                return success_response(
+                    sender,
+                    our,
+                    decoded_keyfile,
+                    encoded_keyfile,
+                    &auth_token_config,
+                )
+                .await;
             }
             Err(_) => {
                 attempts += 1;
@@ -437,6 +477,7 @@ async fn handle_import_keyfile(
     tcp_networking_port: (u16, bool),
     sender: Arc<RegistrationSender>,
     provider: Arc<RootProvider<PubSubFrontend>>,
+    auth_token_config: Arc<AuthTokenConfig>,
 ) -> Result<impl Reply, Rejection> {
     println!("received base64 keyfile: {}\r", info.keyfile);
     // if keyfile was not present in node and is present from user upload
@@ -494,7 +535,14 @@ async fn handle_import_keyfile(
         )
         .into_response());
     }
-    success_response(sender, our, decoded_keyfile, encoded_keyfile).await
+    success_response(
+        sender,
+        our,
+        decoded_keyfile,
+        encoded_keyfile,
+        &auth_token_config,
+    )
+    .await
 }
 
 async fn handle_login(
@@ -505,6 +553,7 @@ async fn handle_login(
     sender: Arc<RegistrationSender>,
     encoded_keyfile: Option<Vec<u8>>,
     provider: Arc<RootProvider<PubSubFrontend>>,
+    auth_token_config: Arc<AuthTokenConfig>,
 ) -> Result<impl Reply, Rejection> {
     if encoded_keyfile.is_none() {
         return Ok(warp::reply::with_status(
@@ -554,7 +603,14 @@ async fn handle_login(
         )
         .into_response());
     }
-    success_response(sender, our, decoded_keyfile, encoded_keyfile).await
+    success_response(
+        sender,
+        our,
+        decoded_keyfile,
+        encoded_keyfile,
+        &auth_token_config,
+    )
+    .await
 }
 
 pub async fn assign_routing(
@@ -665,18 +721,24 @@ async fn success_response(
     our: Identity,
     decoded_keyfile: Keyfile,
     encoded_keyfile: Vec<u8>,
+    auth_token_config: &AuthTokenConfig,
 ) -> Result<warp::reply::Response, Rejection> {
     let encoded_keyfile_str = base64_standard.encode(&encoded_keyfile);
-    let token = match keygen::generate_jwt(&decoded_keyfile.jwt_secret_bytes, &our.name, &None) {
-        Some(token) => token,
-        None => {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&"Failed to generate JWT"),
-                StatusCode::SERVICE_UNAVAILABLE,
-            )
-            .into_response())
-        }
-    };
+    let jwt_secret = keygen::derive_jwt_secret(
+        &decoded_keyfile.jwt_secret_bytes,
+        &auth_token_config.jwt_salt,
+    );
+    let token =
+        match keygen::generate_jwt(&jwt_secret, &our.name, &None, auth_token_config.lifetime) {
+            Some(token) => token,
+            None => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&"Failed to generate JWT"),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                )
+                .into_response())
+            }
+        };
 
     sender
         .send((our.clone(), decoded_keyfile, encoded_keyfile))
@@ -687,7 +749,10 @@ async fn success_response(
         warp::reply::with_status(warp::reply::json(&encoded_keyfile_str), StatusCode::FOUND)
             .into_response();
 
-    match HeaderValue::from_str(&format!("kinode-auth_{}={token};", our.name)) {
+    match HeaderValue::from_str(&format!(
+        "kinode-auth_{}={token}; Max-Age={};",
+        our.name, auth_token_config.lifetime
+    )) {
         Ok(v) => {
             response.headers_mut().append(SET_COOKIE, v);
             response
@@ -0,0 +1,122 @@
+//! Replication of kv and sqlite databases to a follower node, for warm standbys.
+//!
+//! A leader logs each batch of writes to a database, in the same transaction as
+//! the writes, under the next sequence number, and streams the log to the follower
+//! in order. The follower applies each batch in a transaction that also records its
+//! sequence number, and answers with the last it applied, after which the leader
+//! drops the log up to there. Batches already applied are skipped and batches out
+//! of order are not applied, so after either node restarts, or the two lose touch,
+//! streaming resumes from the last batch the follower applied, with nothing applied
+//! twice or missed.
+//!
+//! The roles of the dbs of a module are kept here, and persisted to disk; the log
+//! and sequence numbers are kept by each module in the db itself.
+use dashmap::DashMap;
+use lib::types::core::{PackageId, ReplicationRole};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex, time::Instant};
+
+/// how often leaders stream batches the follower has not applied yet
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// how long a push of batches may go unanswered before they are pushed again
+pub const ACK_TIMEOUT_SECS: u64 = 30;
+/// the most batches pushed at once
+pub const MAX_BATCHES: usize = 100;
+/// the most writes in a batch of the snapshot a leader logs when it starts
+pub const SNAPSHOT_BATCH_LEN: usize = 1_000;
+
+type DbKey = (PackageId, String);
+
+#[derive(Clone)]
+pub struct Replications {
+    path: Arc<PathBuf>,
+    roles: Arc<DashMap<DbKey, ReplicationRole>>,
+    /// held while the roles are written out, so an older snapshot is never
+    /// written over a newer one
+    persisting: Arc<Mutex<()>>,
+    /// the db of each push awaiting an answer, by message ID, and when it was sent
+    pushes: Arc<DashMap<u64, (DbKey, Instant)>>,
+}
+
+impl Replications {
+    /// Load the roles persisted at `path`. Roles that can't be read are never
+    /// taken as none, since a follower would then take writes of its own: the
+    /// module won't start until the file is fixed or removed.
+    pub async fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let roles: Vec<(DbKey, ReplicationRole)> = match fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| anyhow::anyhow!("couldn't parse replication roles {path:?}: {e}"))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "couldn't read replication roles {path:?}: {e}"
+                ))
+            }
+        };
+        Ok(Self {
+            path: Arc::new(path),
+            roles: Arc::new(roles.into_iter().collect()),
+            persisting: Arc::new(Mutex::new(())),
+            pushes: Arc::new(DashMap::new()),
+        })
+    }
+
+    pub fn role(&self, db_key: &DbKey) -> Option<ReplicationRole> {
+        self.roles.get(db_key).map(|role| role.clone())
+    }
+
+    pub fn is_follower(&self, db_key: &DbKey) -> bool {
+        matches!(self.role(db_key), Some(ReplicationRole::Follower { .. }))
+    }
+
+    pub fn leaders(&self) -> Vec<DbKey> {
+        self.roles
+            .iter()
+            .filter(|entry| matches!(entry.value(), ReplicationRole::Leader { .. }))
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    pub async fn set(
+        &self,
+        db_key: &DbKey,
+        role: Option<ReplicationRole>,
+    ) -> Result<(), std::io::Error> {
+        let _persisting = self.persisting.lock().await;
+        match role {
+            Some(role) => self.roles.insert(db_key.clone(), role),
+            None => self.roles.remove(db_key).map(|(_, role)| role),
+        };
+        let roles: Vec<(DbKey, ReplicationRole)> = self
+            .roles
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        // written aside and renamed into place, so a crash never leaves it partial
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path).await?;
+        file.write_all(&serde_json::to_vec(&roles).unwrap()).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp_path, &*self.path).await
+    }
+
+    /// Start a push of a db's batches, returning the ID of its message, unless
+    /// another is still awaiting an answer.
+    pub fn start_push(&self, db_key: &DbKey) -> Option<u64> {
+        let timeout = Duration::from_secs(ACK_TIMEOUT_SECS);
+        self.pushes
+            .retain(|_, (pushed, sent)| pushed != db_key || sent.elapsed() < timeout);
+        if self.pushes.iter().any(|push| push.value().0 == *db_key) {
+            return None;
+        }
+        let id = rand::random::<u64>();
+        self.pushes.insert(id, (db_key.clone(), Instant::now()));
+        Some(id)
+    }
+
+    /// Finish a push, as its answer comes in or it turns out to have nothing to
+    /// send, returning its db.
+    pub fn finish_push(&self, id: u64) -> Option<DbKey> {
+        self.pushes.remove(&id).map(|(_, (db_key, _))| db_key)
+    }
+}
@@ -1,21 +1,48 @@
+use crate::replication::{self, Replications};
 use crate::vfs::UniqueQueue;
 use base64::{engine::general_purpose::STANDARD as base64_standard, Engine};
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use lib::types::core::{
     Address, CapMessage, CapMessageSender, Capability, FdManagerRequest, KernelMessage,
     LazyLoadBlob, Message, MessageReceiver, MessageSender, PackageId, PrintSender, Printout,
-    ProcessId, Request, Response, SqlValue, SqliteAction, SqliteCapabilityKind,
-    SqliteCapabilityParams, SqliteError, SqliteRequest, SqliteResponse, FD_MANAGER_PROCESS_ID,
-    SQLITE_PROCESS_ID,
+    ProcessId, ReplicationRole, ReplicationStatus, Request, Response, SqlValue, SqliteAction,
+    SqliteCapabilityKind, SqliteCapabilityParams, SqliteError, SqliteReplicatedBatch,
+    SqliteRequest, SqliteResponse, SqliteWrite, VfsAction, VfsRequest, VfsResponse,
+    FD_MANAGER_PROCESS_ID, SQLITE_PROCESS_ID, VFS_PROCESS_ID,
+};
+use rusqlite::{
+    backup::{Backup, StepResult},
+    Connection, OpenFlags, OptionalExtension,
 };
-use rusqlite::Connection;
 use std::{
     collections::{HashMap, HashSet, VecDeque},
-    path::PathBuf,
+    io::Read,
+    path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 use tokio::{fs, sync::Mutex};
 
+/// the first bytes of every database file SQLite stores unencrypted
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+/// prefix of the tables a replicated db keeps its log in, which are not replicated
+const REPLICATION_PREFIX: &str = "_kinode_replication";
+/// table of each batch of writes logged, by sequence number
+const LOG_TABLE: &str = "_kinode_replication_log";
+/// table of the sequence number of the last batch logged or applied, and of the
+/// last the follower applied
+const COUNTERS_TABLE: &str = "_kinode_replication";
+const SEQ_COUNTER: &str = "seq";
+const ACKED_COUNTER: &str = "acked";
+/// how long the VFS may take to write or read a backup
+const VFS_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// how many times copying a db waits on a lock before giving up
+const MAX_BUSY_RETRIES: u32 = 200;
+/// how long copying a db waits on a lock each time
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+type DbKey = (PackageId, String);
+
 lazy_static::lazy_static! {
     static ref READ_KEYWORDS: HashSet<&'static str> =
         HashSet::from(["ANALYZE", "ATTACH", "BEGIN", "EXPLAIN", "PRAGMA", "SELECT", "VALUES", "WITH"]);
@@ -33,7 +60,13 @@ struct SqliteState {
     open_dbs: Arc<DashMap<(PackageId, String), Mutex<Connection>>>,
     access_order: Arc<Mutex<UniqueQueue<(PackageId, String)>>>,
     txs: Arc<DashMap<u64, Vec<(String, Vec<SqlValue>)>>>,
+    /// open dbs that are encrypted
+    encrypted_dbs: Arc<DashSet<(PackageId, String)>>,
+    file_key: Arc<Vec<u8>>,
+    replications: Replications,
     fds_limit: u64,
+    /// requests of the VFS for backups, by ID, awaiting their responses
+    vfs_responses: Arc<DashMap<u64, tokio::sync::oneshot::Sender<KernelMessage>>>,
 }
 
 impl SqliteState {
@@ -42,6 +75,8 @@ impl SqliteState {
         send_to_terminal: PrintSender,
         send_to_loop: MessageSender,
         home_directory_path: PathBuf,
+        file_key: Vec<u8>,
+        replications: Replications,
     ) -> Self {
         Self {
             our: Arc::new(our),
@@ -51,11 +86,21 @@ impl SqliteState {
             open_dbs: Arc::new(DashMap::new()),
             access_order: Arc::new(Mutex::new(UniqueQueue::new())),
             txs: Arc::new(DashMap::new()),
+            encrypted_dbs: Arc::new(DashSet::new()),
+            file_key: Arc::new(file_key),
+            replications,
             fds_limit: 10,
+            vfs_responses: Arc::new(DashMap::new()),
         }
     }
 
-    pub async fn open_db(&mut self, key: &(PackageId, String)) -> Result<(), SqliteError> {
+    /// Open a db, creating it encrypted if it doesn't exist and `encrypt` is set.
+    /// A db that exists is opened encrypted or not as it is stored.
+    pub async fn open_db(
+        &mut self,
+        key: &(PackageId, String),
+        encrypt: bool,
+    ) -> Result<(), SqliteError> {
         if self.open_dbs.contains_key(key) {
             let mut access_order = self.access_order.lock().await;
             access_order.remove(key);
@@ -69,19 +114,18 @@ impl SqliteState {
             self.remove_db(&to_close).await;
         }
 
-        #[cfg(unix)]
-        let db_path = self.sqlite_path.join(format!("{}", key.0)).join(&key.1);
-        #[cfg(target_os = "windows")]
-        let db_path = self
-            .sqlite_path
-            .join(format!("{}_{}", key.0._package(), key.0._publisher()))
-            .join(&key.1);
+        let db_path = self.db_path(key);
 
         fs::create_dir_all(&db_path).await?;
 
         let db_file_path = db_path.join(format!("{}.db", key.1));
 
+        let encrypted = stored_encrypted(&db_file_path)?.unwrap_or(encrypt);
         let db_conn = Connection::open(db_file_path)?;
+        if encrypted {
+            apply_key(&db_conn, &db_cipher_key(&self.file_key, &key.0))?;
+            self.encrypted_dbs.insert(key.clone());
+        }
         let _: String = db_conn.query_row("PRAGMA journal_mode=WAL", [], |row| row.get(0))?;
 
         self.open_dbs.insert(key.clone(), Mutex::new(db_conn));
@@ -91,8 +135,94 @@ impl SqliteState {
         Ok(())
     }
 
+    fn db_path(&self, key: &(PackageId, String)) -> PathBuf {
+        #[cfg(unix)]
+        let db_path = self.sqlite_path.join(format!("{}", key.0)).join(&key.1);
+        #[cfg(target_os = "windows")]
+        let db_path = self
+            .sqlite_path
+            .join(format!("{}_{}", key.0._package(), key.0._publisher()))
+            .join(&key.1);
+        db_path
+    }
+
+    /// Check that a VFS path is of a file in a drive of `source`'s package.
+    fn check_backup_path(&self, source: &Address, path: &str) -> Result<(), SqliteError> {
+        let mut parts = path.trim_start_matches('/').split('/');
+        let package_id = parts
+            .next()
+            .and_then(|package_id| package_id.parse::<PackageId>().ok())
+            .ok_or(SqliteError::InvalidBackupPath)?;
+        if package_id != PackageId::new(source.process.package(), source.process.publisher()) {
+            return Err(SqliteError::InvalidBackupPath);
+        }
+        let rest: Vec<&str> = parts.collect();
+        // a drive, then at least a file name
+        if rest.len() < 2 || rest.iter().any(|part| part.is_empty() || *part == "..") {
+            return Err(SqliteError::InvalidBackupPath);
+        }
+        Ok(())
+    }
+
+    /// Act on a file in the VFS as `source`, so that the VFS checks its access
+    /// and applies its drive's quota, encryption and compression.
+    async fn vfs_request(
+        &self,
+        source: &Address,
+        path: &str,
+        action: VfsAction,
+        bytes: Option<Vec<u8>>,
+    ) -> Result<Option<LazyLoadBlob>, SqliteError> {
+        let id = rand::random();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.vfs_responses.insert(id, sender);
+        KernelMessage::builder()
+            .id(id)
+            .source(source.clone())
+            .target((self.our.node.as_str(), VFS_PROCESS_ID.clone()))
+            .rsvp(Some(self.our.as_ref().clone()))
+            .message(Message::Request(Request {
+                inherit: false,
+                expects_response: Some(VFS_TIMEOUT.as_secs()),
+                body: serde_json::to_vec(&VfsRequest {
+                    path: path.to_string(),
+                    action,
+                })
+                .unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            }))
+            .lazy_load_blob(bytes.map(|bytes| LazyLoadBlob { mime: None, bytes }))
+            .build()
+            .unwrap()
+            .send(&self.send_to_loop)
+            .await;
+        let response = tokio::time::timeout(VFS_TIMEOUT, receiver).await;
+        self.vfs_responses.remove(&id);
+        let Ok(Ok(km)) = response else {
+            return Err(SqliteError::IOError("vfs timed out".to_string()));
+        };
+        let Message::Response((response, _)) = km.message else {
+            return Err(SqliteError::IOError("vfs sent a request".to_string()));
+        };
+        match serde_json::from_slice::<VfsResponse>(&response.body) {
+            Ok(VfsResponse::Err(e)) => Err(SqliteError::IOError(format!("vfs: {e}"))),
+            Ok(_) => Ok(km.lazy_load_blob),
+            Err(_) => Err(SqliteError::IOError(
+                "vfs sent a malformed response".to_string(),
+            )),
+        }
+    }
+
+    /// A file of our own to copy a db through, unique to this copy.
+    fn scratch_path(&self) -> PathBuf {
+        self.sqlite_path
+            .join(format!(".scratch-{:016x}", rand::random::<u64>()))
+    }
+
     pub async fn remove_db(&mut self, key: &(PackageId, String)) {
         self.open_dbs.remove(key);
+        self.encrypted_dbs.remove(key);
         let mut access_order = self.access_order.lock().await;
         access_order.remove(key);
     }
@@ -114,21 +244,42 @@ pub async fn sqlite(
     mut recv_from_loop: MessageReceiver,
     send_to_caps_oracle: CapMessageSender,
     home_directory_path: PathBuf,
+    file_key: Vec<u8>,
 ) -> anyhow::Result<()> {
     let our = Address::new(our_node.as_str(), SQLITE_PROCESS_ID.clone());
 
     crate::fd_manager::send_fd_manager_request_fds_limit(&our, &send_to_loop).await;
 
-    let mut state = SqliteState::new(our, send_to_terminal, send_to_loop, home_directory_path);
+    let replications = Replications::load(home_directory_path.join("sqlite/.replication")).await?;
+    let mut state = SqliteState::new(
+        our,
+        send_to_terminal,
+        send_to_loop,
+        home_directory_path,
+        file_key,
+        replications,
+    );
 
     if let Err(e) = fs::create_dir_all(&*state.sqlite_path).await {
         panic!("failed creating sqlite dir! {e:?}");
     }
 
+    tokio::spawn(retry_pushes(state.clone()));
+
     let process_queues: HashMap<ProcessId, Arc<Mutex<VecDeque<KernelMessage>>>> = HashMap::new();
 
     while let Some(km) = recv_from_loop.recv().await {
-        if state.our.node != km.source.node {
+        if km.source.process == *VFS_PROCESS_ID {
+            if let Message::Response(_) = km.message {
+                if let Some((_, sender)) = state.vfs_responses.remove(&km.id) {
+                    let _ = sender.send(km);
+                }
+                continue;
+            }
+        }
+
+        // other nodes' sqlite only, which stream writes to dbs following them, and answer pushes
+        if state.our.node != km.source.node && km.source.process != *SQLITE_PROCESS_ID {
             Printout::new(
                 1,
                 SQLITE_PROCESS_ID.clone(),
@@ -214,15 +365,18 @@ async fn handle_request(
         lazy_load_blob: blob,
         ..
     } = km;
-    let Message::Request(Request {
-        body,
-        expects_response,
-        metadata,
-        ..
-    }) = message
-    else {
-        // we got a response -- safe to ignore
-        return Ok(());
+    let (body, expects_response, metadata) = match message {
+        Message::Request(Request {
+            body,
+            expects_response,
+            metadata,
+            ..
+        }) => (body, expects_response, metadata),
+        Message::Response((Response { body, .. }, _)) => {
+            // the answer of a follower to writes pushed to it; any other is safe to ignore
+            handle_push_answer(id, &source, &body, state).await;
+            return Ok(());
+        }
     };
 
     let request: SqliteRequest = match serde_json::from_slice(&body) {
@@ -233,6 +387,11 @@ async fn handle_request(
         }
     };
 
+    // other nodes can only stream writes to dbs that follow them
+    if source.node != state.our.node && !matches!(request.action, SqliteAction::Replicate(_)) {
+        return Err(SqliteError::UnexpectedReplication);
+    }
+
     let db_key = (request.package_id, request.db);
 
     check_caps(
@@ -245,10 +404,40 @@ async fn handle_request(
     .await?;
 
     // always open to ensure db exists
-    state.open_db(&db_key).await?;
+    state.open_db(&db_key, false).await?;
+
+    if state.encrypted_dbs.contains(&db_key)
+        && !matches!(request.action, SqliteAction::Replicate(_))
+        && PackageId::new(source.process.package(), source.process.publisher()) != db_key.0
+    {
+        return Err(SqliteError::EncryptedDb);
+    }
+
+    let is_write = matches!(
+        request.action,
+        SqliteAction::Write { .. }
+            | SqliteAction::Commit { .. }
+            | SqliteAction::WriteBatch { .. }
+            | SqliteAction::Restore { .. }
+            | SqliteAction::Migrate { .. }
+            | SqliteAction::CreateSearchIndex { .. }
+    );
+    let follower = state.replications.is_follower(&db_key);
+    // a follower is migrated by its leader, but migrating it checks it is up to date
+    if is_write && follower && !matches!(request.action, SqliteAction::Migrate { .. }) {
+        return Err(SqliteError::Follower);
+    }
+    let leader = matches!(
+        state.replications.role(&db_key),
+        Some(ReplicationRole::Leader { .. })
+    );
+    // a restore would replace the log, and what the follower has applied of it
+    if leader && matches!(request.action, SqliteAction::Restore { .. }) {
+        return Err(SqliteError::Replicated);
+    }
 
     let (body, bytes) = match request.action {
-        SqliteAction::Open => {
+        SqliteAction::Open | SqliteAction::OpenEncrypted => {
             // handled in check_caps
             (serde_json::to_vec(&SqliteResponse::Ok).unwrap(), None)
         }
@@ -275,37 +464,7 @@ async fn handle_request(
 
             let parameters = get_json_params(blob)?;
 
-            let mut statement = db.prepare(&query)?;
-            let column_names: Vec<String> = statement
-                .column_names()
-                .iter()
-                .map(|c| c.to_string())
-                .collect();
-
-            let results: Vec<HashMap<String, serde_json::Value>> = statement
-                .query_map(rusqlite::params_from_iter(parameters.iter()), |row| {
-                    let mut map = HashMap::new();
-                    for (i, column_name) in column_names.iter().enumerate() {
-                        let value: Option<SqlValue> = row.get(i)?;
-                        let value_json = match value {
-                            Some(SqlValue::Integer(int)) => serde_json::Value::Number(int.into()),
-                            Some(SqlValue::Real(real)) => serde_json::Value::Number(
-                                serde_json::Number::from_f64(real).unwrap(),
-                            ),
-                            Some(SqlValue::Text(text)) => serde_json::Value::String(text),
-                            Some(SqlValue::Blob(blob)) => {
-                                serde_json::Value::String(base64_standard.encode(blob))
-                            } // or another representation if you prefer
-                            _ => serde_json::Value::Null,
-                        };
-                        map.insert(column_name.clone(), value_json);
-                    }
-                    Ok(map)
-                })?
-                .collect::<Result<Vec<_>, _>>()?;
-
-            let results = serde_json::json!(results).to_string();
-            let results_bytes = results.as_bytes().to_vec();
+            let results_bytes = read_rows(&db, &query, &parameters)?;
 
             (
                 serde_json::to_vec(&SqliteResponse::Read).unwrap(),
@@ -319,17 +478,9 @@ async fn handle_request(
                     return Err(SqliteError::NoDb(db_key.0, db_key.1));
                 }
             };
-            let db = db.lock().await;
-
-            let first_word = statement
-                .split_whitespace()
-                .next()
-                .map(|word| word.to_uppercase())
-                .unwrap_or("".to_string());
+            let mut db = db.lock().await;
 
-            if !WRITE_KEYWORDS.contains(first_word.as_str()) {
-                return Err(SqliteError::NotAWriteKeyword);
-            }
+            check_write_keyword(&statement)?;
 
             let parameters = get_json_params(blob)?;
 
@@ -341,10 +492,7 @@ async fn handle_request(
                         .or_default()
                         .push((statement.clone(), parameters));
                 }
-                None => {
-                    let mut stmt = db.prepare(&statement)?;
-                    stmt.execute(rusqlite::params_from_iter(parameters.iter()))?;
-                }
+                None => execute_in_transaction(&mut db, [(statement, parameters)], leader)?,
             };
             (serde_json::to_vec(&SqliteResponse::Ok).unwrap(), None)
         }
@@ -373,16 +521,292 @@ async fn handle_request(
                 Some(tx) => tx,
             };
 
+            execute_in_transaction(&mut db, txs, leader)?;
+            (serde_json::to_vec(&SqliteResponse::Ok).unwrap(), None)
+        }
+        SqliteAction::Rollback { tx_id } => {
+            if state.txs.remove(&tx_id).is_none() {
+                return Err(SqliteError::NoTx(tx_id));
+            }
+            (serde_json::to_vec(&SqliteResponse::Ok).unwrap(), None)
+        }
+        SqliteAction::WriteBatch { statements, tx_id } => {
+            let db = match state.open_dbs.get(&db_key) {
+                Some(db) => db,
+                None => {
+                    return Err(SqliteError::NoDb(db_key.0, db_key.1));
+                }
+            };
+            let mut db = db.lock().await;
+
+            for statement in &statements {
+                check_write_keyword(statement)?;
+            }
+            let parameters = match blob {
+                None => vec![vec![]; statements.len()],
+                Some(blob) => get_batch_params(blob)?,
+            };
+            if parameters.len() != statements.len() {
+                return Err(SqliteError::InvalidParameters);
+            }
+            let batch = statements.into_iter().zip(parameters);
+
+            match tx_id {
+                Some(tx_id) => {
+                    state.txs.entry(tx_id).or_default().extend(batch);
+                }
+                None => execute_in_transaction(&mut db, batch, leader)?,
+            }
+            (serde_json::to_vec(&SqliteResponse::Ok).unwrap(), None)
+        }
+        SqliteAction::Backup { dest_path } => {
+            state.check_backup_path(&source, &dest_path)?;
+            let src = state.db_path(&db_key).join(format!("{}.db", db_key.1));
+            let key = state
+                .encrypted_dbs
+                .contains(&db_key)
+                .then(|| db_cipher_key(&state.file_key, &db_key.0));
+            // copied through a connection of its own, so writes to the db carry on
+            // meanwhile, to a file of ours, then written to the VFS
+            let scratch = state.scratch_path();
+            let copy = scratch.clone();
+            let copied =
+                tokio::task::spawn_blocking(move || backup_db(&src, &copy, key.as_deref()))
+                    .await
+                    .map_err(|e| SqliteError::IOError(e.to_string()))
+                    .and_then(|copied| copied);
+            let bytes = match copied {
+                Ok(()) => fs::read(&scratch).await.map_err(SqliteError::from),
+                Err(e) => Err(e),
+            };
+            let _ = fs::remove_file(&scratch).await;
+            let bytes = bytes?;
+            let parent = dest_path.rsplit_once('/').map_or("", |(parent, _)| parent);
+            state
+                .vfs_request(&source, parent, VfsAction::CreateDirAll, None)
+                .await?;
+            state
+                .vfs_request(&source, &dest_path, VfsAction::WriteAtomic, Some(bytes))
+                .await?;
+            (serde_json::to_vec(&SqliteResponse::Ok).unwrap(), None)
+        }
+        SqliteAction::Restore { src_path } => {
+            state.check_backup_path(&source, &src_path)?;
+            let Some(backup) = state
+                .vfs_request(&source, &src_path, VfsAction::Read, None)
+                .await?
+            else {
+                return Err(SqliteError::IOError("vfs sent no backup".to_string()));
+            };
+            let db = match state.open_dbs.get(&db_key) {
+                Some(db) => db,
+                None => {
+                    return Err(SqliteError::NoDb(db_key.0, db_key.1));
+                }
+            };
+            // held so that nothing else is done with the db until it is restored
+            let _db = db.lock().await;
+            let scratch = state.scratch_path();
+            fs::write(&scratch, backup.bytes).await?;
+            let dest = state.db_path(&db_key).join(format!("{}.db", db_key.1));
+            let key = state
+                .encrypted_dbs
+                .contains(&db_key)
+                .then(|| db_cipher_key(&state.file_key, &db_key.0));
+            let src = scratch.clone();
+            let restored =
+                tokio::task::spawn_blocking(move || restore_db(&src, &dest, key.as_deref()))
+                    .await
+                    .map_err(|e| SqliteError::IOError(e.to_string()))
+                    .and_then(|restored| restored);
+            let _ = fs::remove_file(&scratch).await;
+            restored?;
+            (serde_json::to_vec(&SqliteResponse::Ok).unwrap(), None)
+        }
+        SqliteAction::Migrate { migrations } => {
+            let db = match state.open_dbs.get(&db_key) {
+                Some(db) => db,
+                None => {
+                    return Err(SqliteError::NoDb(db_key.0, db_key.1));
+                }
+            };
+            let mut db = db.lock().await;
+
+            let from_version =
+                db.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))? as u64;
+            let to_version = migrations.len() as u64;
+            if from_version > to_version {
+                return Err(SqliteError::NewerThanMigrations(from_version));
+            }
+            if follower && from_version < to_version {
+                return Err(SqliteError::Follower);
+            }
+
+            // the version is set in the same transaction, so it always matches the schema
             let tx = db.transaction()?;
-            for (query, params) in txs {
-                tx.execute(&query, rusqlite::params_from_iter(params.iter()))?;
+            let mut writes = vec![];
+            for (version, migration) in (1..).zip(&migrations).skip(from_version as usize) {
+                tx.execute_batch(migration)
+                    .map_err(|e| SqliteError::MigrationFailed {
+                        version,
+                        error: e.to_string(),
+                    })?;
+                writes.push(SqliteWrite::Script(migration.clone()));
+            }
+            tx.pragma_update(None, "user_version", to_version as i64)?;
+            if leader && !writes.is_empty() {
+                writes.push(SqliteWrite::Script(format!(
+                    "PRAGMA user_version = {to_version};"
+                )));
+                log_batch(&tx, &writes)?;
             }
+            tx.commit()?;
 
+            (
+                serde_json::to_vec(&SqliteResponse::Migrated {
+                    from_version,
+                    to_version,
+                })
+                .unwrap(),
+                None,
+            )
+        }
+        SqliteAction::CreateSearchIndex {
+            index,
+            columns,
+            content,
+            tokenizer,
+        } => {
+            let db = match state.open_dbs.get(&db_key) {
+                Some(db) => db,
+                None => {
+                    return Err(SqliteError::NoDb(db_key.0, db_key.1));
+                }
+            };
+            let mut db = db.lock().await;
+
+            let script = search_index_script(
+                &index,
+                &columns,
+                content.as_deref(),
+                tokenizer.as_deref().unwrap_or("unicode61"),
+            )?;
+            let tx = db.transaction()?;
+            tx.execute_batch(&script)?;
+            if leader {
+                log_batch(&tx, &[SqliteWrite::Script(script)])?;
+            }
             tx.commit()?;
             (serde_json::to_vec(&SqliteResponse::Ok).unwrap(), None)
         }
+        SqliteAction::Search {
+            index,
+            query,
+            limit,
+            offset,
+        } => {
+            let db = match state.open_dbs.get(&db_key) {
+                Some(db) => db,
+                None => {
+                    return Err(SqliteError::NoDb(db_key.0, db_key.1));
+                }
+            };
+            let db = db.lock().await;
+
+            let index = quote_identifier(&index)?;
+            // `rank` is the bm25 score of the row, lower for better matches
+            let statement = format!(
+                "SELECT rowid, *, rank FROM {index} WHERE {index} MATCH ?1 ORDER BY rank LIMIT ?2 OFFSET ?3"
+            );
+            let parameters = [
+                SqlValue::Text(query),
+                SqlValue::Integer(limit.map(|limit| limit as i64).unwrap_or(-1)),
+                SqlValue::Integer(offset.unwrap_or(0) as i64),
+            ];
+            let results_bytes = read_rows(&db, &statement, &parameters).map_err(|e| {
+                match e.to_string() {
+                    // errors in the query itself, rather than in the index
+                    error if error.starts_with("fts5:") => SqliteError::InvalidSearchQuery(error),
+                    error => SqliteError::RusqliteError(error),
+                }
+            })?;
+
+            (
+                serde_json::to_vec(&SqliteResponse::Read).unwrap(),
+                Some(results_bytes),
+            )
+        }
+        SqliteAction::SetReplication(role) => {
+            let previous = state.replications.role(&db_key);
+            // set first, so that writes made while the snapshot is logged are logged after it
+            state.replications.set(&db_key, role.clone()).await?;
+            let reset = match state.open_dbs.get(&db_key) {
+                None => Err(SqliteError::NoDb(db_key.0.clone(), db_key.1.clone())),
+                Some(db) => reset_replication(&mut *db.lock().await, role.as_ref()),
+            };
+            if let Err(e) = reset {
+                state.replications.set(&db_key, previous).await?;
+                return Err(e);
+            }
+            if let Some(ReplicationRole::Leader { .. }) = role {
+                spawn_push(state, &db_key);
+            }
+            (serde_json::to_vec(&SqliteResponse::Ok).unwrap(), None)
+        }
+        SqliteAction::GetReplication => {
+            let db = match state.open_dbs.get(&db_key) {
+                Some(db) => db,
+                None => {
+                    return Err(SqliteError::NoDb(db_key.0, db_key.1));
+                }
+            };
+            let db = db.lock().await;
+            let status = ReplicationStatus {
+                role: state.replications.role(&db_key),
+                last_seq: read_counter(&db, SEQ_COUNTER)?,
+                acked_seq: read_counter(&db, ACKED_COUNTER)?,
+            };
+            (
+                serde_json::to_vec(&SqliteResponse::Replication(status)).unwrap(),
+                None,
+            )
+        }
+        SqliteAction::Replicate(batches) => {
+            let db = match state.open_dbs.get(&db_key) {
+                Some(db) => db,
+                None => {
+                    return Err(SqliteError::NoDb(db_key.0, db_key.1));
+                }
+            };
+            let mut db = db.lock().await;
+
+            let mut applied_seq = read_counter(&db, SEQ_COUNTER)?;
+            for batch in batches {
+                // applied before, and pushed again as its answer was lost
+                if batch.seq <= applied_seq {
+                    continue;
+                }
+                // one is missing: the leader pushes again from the last applied
+                if batch.seq != applied_seq + 1 {
+                    break;
+                }
+                let tx = db.transaction()?;
+                apply_writes(&tx, &batch.writes)?;
+                write_counter(&tx, SEQ_COUNTER, batch.seq)?;
+                tx.commit()?;
+                applied_seq = batch.seq;
+            }
+            (
+                serde_json::to_vec(&SqliteResponse::Replicated { applied_seq }).unwrap(),
+                None,
+            )
+        }
     };
 
+    if is_write && leader {
+        spawn_push(state, &db_key);
+    }
+
     if let Some(target) = km.rsvp.or_else(|| expects_response.map(|_| source)) {
         KernelMessage::builder()
             .id(id)
@@ -421,7 +845,14 @@ async fn check_caps(
     let src_package_id = PackageId::new(source.process.package(), source.process.publisher());
 
     match action {
-        SqliteAction::Write { .. } | SqliteAction::BeginTx | SqliteAction::Commit { .. } => {
+        SqliteAction::Write { .. }
+        | SqliteAction::BeginTx
+        | SqliteAction::Commit { .. }
+        | SqliteAction::Restore { .. }
+        | SqliteAction::Migrate { .. }
+        | SqliteAction::CreateSearchIndex { .. }
+        | SqliteAction::Rollback { .. }
+        | SqliteAction::WriteBatch { .. } => {
             let Ok(()) = send_to_caps_oracle
                 .send(CapMessage::Has {
                     on: source.process.clone(),
@@ -444,7 +875,10 @@ async fn check_caps(
             };
             Ok(())
         }
-        SqliteAction::Query { .. } => {
+        SqliteAction::Query { .. }
+        | SqliteAction::Backup { .. }
+        | SqliteAction::Search { .. }
+        | SqliteAction::GetReplication => {
             let Ok(()) = send_to_caps_oracle
                 .send(CapMessage::Has {
                     on: source.process.clone(),
@@ -467,10 +901,11 @@ async fn check_caps(
             };
             Ok(())
         }
-        SqliteAction::Open => {
+        SqliteAction::Open | SqliteAction::OpenEncrypted => {
             if src_package_id != db_key.0 {
                 return Err(SqliteError::MismatchingPackageId);
             }
+            let encrypt = matches!(action, SqliteAction::OpenEncrypted);
 
             add_capability(
                 SqliteCapabilityKind::Read,
@@ -489,11 +924,12 @@ async fn check_caps(
             )
             .await?;
 
-            if state.open_dbs.contains_key(db_key) {
-                return Ok(());
+            if !state.open_dbs.contains_key(db_key) {
+                state.open_db(db_key, encrypt).await?;
+            }
+            if encrypt && !state.encrypted_dbs.contains(db_key) {
+                return Err(SqliteError::NotEncrypted);
             }
-
-            state.open_db(db_key).await?;
             Ok(())
         }
         SqliteAction::RemoveDb => {
@@ -502,25 +938,509 @@ async fn check_caps(
             }
 
             state.remove_db(db_key).await;
+            if state.replications.role(db_key).is_some() {
+                state.replications.set(db_key, None).await?;
+            }
 
-            #[cfg(unix)]
-            let db_path = state
-                .sqlite_path
-                .join(format!("{}", db_key.0))
-                .join(&db_key.1);
-            #[cfg(target_os = "windows")]
-            let db_path = state
-                .sqlite_path
-                .join(format!("{}_{}", db_key.0._package(), db_key.0._publisher()))
-                .join(&db_key.1);
-
-            fs::remove_dir_all(&db_path).await?;
+            fs::remove_dir_all(&state.db_path(db_key)).await?;
 
             Ok(())
         }
+        SqliteAction::SetReplication(_) => {
+            if src_package_id != db_key.0 {
+                return Err(SqliteError::MismatchingPackageId);
+            }
+            Ok(())
+        }
+        SqliteAction::Replicate(_) => match state.replications.role(db_key) {
+            Some(ReplicationRole::Follower { leader })
+                if source.node == leader && source.process == *SQLITE_PROCESS_ID =>
+            {
+                Ok(())
+            }
+            _ => Err(SqliteError::UnexpectedReplication),
+        },
     }
 }
 
+fn check_write_keyword(statement: &str) -> Result<(), SqliteError> {
+    let first_word = statement
+        .split_whitespace()
+        .next()
+        .map(|word| word.to_uppercase())
+        .unwrap_or("".to_string());
+
+    if !WRITE_KEYWORDS.contains(first_word.as_str()) {
+        return Err(SqliteError::NotAWriteKeyword);
+    }
+    Ok(())
+}
+
+/// Execute statements in one transaction, preparing each distinct statement once,
+/// and logging them as a batch for the follower if the db is a leader.
+fn execute_in_transaction(
+    db: &mut Connection,
+    statements: impl IntoIterator<Item = (String, Vec<SqlValue>)>,
+    leader: bool,
+) -> Result<(), SqliteError> {
+    let tx = db.transaction()?;
+    let mut writes = vec![];
+    for (statement, params) in statements {
+        tx.prepare_cached(&statement)?
+            .execute(rusqlite::params_from_iter(params.iter()))?;
+        if leader {
+            writes.push(SqliteWrite::Statement { statement, params });
+        }
+    }
+    if leader {
+        log_batch(&tx, &writes)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn apply_writes(db: &Connection, writes: &[SqliteWrite]) -> Result<(), SqliteError> {
+    for write in writes {
+        match write {
+            SqliteWrite::Statement { statement, params } => {
+                db.prepare_cached(statement)?
+                    .execute(rusqlite::params_from_iter(params.iter()))?;
+            }
+            SqliteWrite::Script(script) => db.execute_batch(script)?,
+        }
+    }
+    Ok(())
+}
+
+/// Log a batch of writes under the next sequence number, in the transaction that made them.
+fn log_batch(db: &Connection, writes: &[SqliteWrite]) -> Result<(), SqliteError> {
+    let seq = read_counter(db, SEQ_COUNTER)? + 1;
+    db.execute(
+        &format!("INSERT INTO {LOG_TABLE} (seq, batch) VALUES (?1, ?2)"),
+        rusqlite::params![seq as i64, rmp_serde::to_vec(writes).unwrap()],
+    )?;
+    write_counter(db, SEQ_COUNTER, seq)
+}
+
+/// Forget what was logged or applied for replication, and as a new leader, log
+/// all the db holds, so that the follower starts with a copy of it.
+fn reset_replication(
+    db: &mut Connection,
+    role: Option<&ReplicationRole>,
+) -> Result<(), SqliteError> {
+    if let Some(ReplicationRole::Follower { .. }) = role {
+        let tables: i64 = db.query_row(
+            "SELECT count(*) FROM sqlite_master WHERE substr(name, 1, length(?1)) != ?1",
+            [REPLICATION_PREFIX],
+            |row| row.get(0),
+        )?;
+        if tables > 0 {
+            return Err(SqliteError::NotEmpty);
+        }
+    }
+    let tx = db.transaction()?;
+    tx.execute_batch(&format!(
+        "DROP TABLE IF EXISTS {LOG_TABLE}; DROP TABLE IF EXISTS {COUNTERS_TABLE};"
+    ))?;
+    if role.is_some() {
+        tx.execute_batch(&format!(
+            "CREATE TABLE {LOG_TABLE} (seq INTEGER PRIMARY KEY, batch BLOB NOT NULL);
+CREATE TABLE {COUNTERS_TABLE} (name TEXT PRIMARY KEY, value INTEGER NOT NULL);"
+        ))?;
+    }
+    if let Some(ReplicationRole::Leader { .. }) = role {
+        log_snapshot(&tx)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Log the writes that make an empty db a copy of this one: its tables, then their
+/// rows, then its indexes, triggers and views, so that no trigger fires as rows are
+/// copied. The shadow tables of virtual tables, such as search indexes, are copied
+/// as they are, rather than the virtual tables filled anew.
+fn log_snapshot(db: &Connection) -> Result<(), SqliteError> {
+    // type and whether it is WITHOUT ROWID, by table
+    let tables: Vec<(String, String, bool)> = db
+        .prepare("SELECT name, type, wr FROM pragma_table_list WHERE schema = 'main'")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    let is_copied =
+        |name: &str| !name.starts_with("sqlite_") && !name.starts_with(REPLICATION_PREFIX);
+
+    let mut created = vec![];
+    let mut created_after = vec![];
+    let mut schema = db.prepare(
+        "SELECT type, name, sql FROM sqlite_master WHERE sql IS NOT NULL ORDER BY rowid",
+    )?;
+    let mut rows = schema.query([])?;
+    while let Some(row) = rows.next()? {
+        let (kind, name, sql): (String, String, String) = (row.get(0)?, row.get(1)?, row.get(2)?);
+        if !is_copied(&name) {
+            continue;
+        }
+        if kind != "table" {
+            created_after.push(sql);
+        } else if !tables
+            .iter()
+            .any(|(table, kind, _)| *table == name && kind == "shadow")
+        {
+            created.push(sql);
+        }
+    }
+    log_batch(db, &[SqliteWrite::Script(created.join(";\n"))])?;
+
+    let mut writes = vec![];
+    for (table, kind, without_rowid) in &tables {
+        if !is_copied(table) || (kind != "table" && kind != "shadow") {
+            continue;
+        }
+        let columns: Vec<(String, String, i64)> = db
+            .prepare("SELECT name, type, pk FROM pragma_table_info(?1)")?
+            .query_map([table], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+        // the rowid, unless it is aliased by an INTEGER PRIMARY KEY column
+        let primary_key: Vec<&String> = columns
+            .iter()
+            .filter(|(_, _, pk)| *pk > 0)
+            .map(|(_, kind, _)| kind)
+            .collect();
+        let aliased = matches!(&primary_key[..], [kind] if kind.eq_ignore_ascii_case("INTEGER"));
+        let mut names: Vec<String> = columns.iter().map(|(name, ..)| quote_name(name)).collect();
+        if !without_rowid && !aliased {
+            names.insert(0, "rowid".to_string());
+        }
+        let placeholders: Vec<String> = (1..=names.len()).map(|i| format!("?{i}")).collect();
+        let (names, table) = (names.join(", "), quote_name(table));
+        let insert = format!(
+            "INSERT OR REPLACE INTO {table} ({names}) VALUES ({})",
+            placeholders.join(", ")
+        );
+
+        let mut select = db.prepare(&format!("SELECT {names} FROM {table}"))?;
+        let mut rows = select.query([])?;
+        while let Some(row) = rows.next()? {
+            let params = (0..placeholders.len())
+                .map(|i| Ok(row.get::<_, Option<SqlValue>>(i)?.unwrap_or(SqlValue::Null)))
+                .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+            writes.push(SqliteWrite::Statement {
+                statement: insert.clone(),
+                params,
+            });
+            if writes.len() == replication::SNAPSHOT_BATCH_LEN {
+                log_batch(db, &std::mem::take(&mut writes))?;
+            }
+        }
+    }
+    if !writes.is_empty() {
+        log_batch(db, &writes)?;
+    }
+
+    let version: i64 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    created_after.push(format!("PRAGMA user_version = {version}"));
+    log_batch(db, &[SqliteWrite::Script(created_after.join(";\n"))])
+}
+
+/// Push the batches of a leader db its follower has not applied yet, unless a push
+/// is awaiting its answer already.
+async fn push(mut state: SqliteState, db_key: DbKey) -> Result<(), SqliteError> {
+    let Some(ReplicationRole::Leader { follower }) = state.replications.role(&db_key) else {
+        return Ok(());
+    };
+    let Some(id) = state.replications.start_push(&db_key) else {
+        return Ok(());
+    };
+    let batches = match unapplied_batches(&mut state, &db_key).await {
+        Ok(batches) if !batches.is_empty() => batches,
+        result => {
+            state.replications.finish_push(id);
+            return result.map(|_| ());
+        }
+    };
+    KernelMessage::builder()
+        .id(id)
+        .source(state.our.as_ref().clone())
+        .target(Address::new(follower.as_str(), SQLITE_PROCESS_ID.clone()))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: Some(replication::ACK_TIMEOUT_SECS),
+            body: serde_json::to_vec(&SqliteRequest {
+                package_id: db_key.0,
+                db: db_key.1,
+                action: SqliteAction::Replicate(batches),
+            })
+            .unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(&state.send_to_loop)
+        .await;
+    Ok(())
+}
+
+async fn unapplied_batches(
+    state: &mut SqliteState,
+    db_key: &DbKey,
+) -> Result<Vec<SqliteReplicatedBatch>, SqliteError> {
+    state.open_db(db_key, false).await?;
+    let Some(db) = state.open_dbs.get(db_key) else {
+        return Err(SqliteError::NoDb(db_key.0.clone(), db_key.1.clone()));
+    };
+    let db = db.lock().await;
+    let acked_seq = read_counter(&db, ACKED_COUNTER)?;
+
+    let mut statement = db.prepare(&format!(
+        "SELECT seq, batch FROM {LOG_TABLE} WHERE seq > ?1 ORDER BY seq LIMIT ?2"
+    ))?;
+    let batches = statement
+        .query_map(
+            rusqlite::params![acked_seq as i64, replication::MAX_BATCHES as i64],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)),
+        )?
+        .map(|row| {
+            let (seq, batch) = row?;
+            Ok(SqliteReplicatedBatch {
+                seq: seq as u64,
+                writes: rmp_serde::from_slice(&batch)
+                    .map_err(|e| SqliteError::RusqliteError(e.to_string()))?,
+            })
+        })
+        .collect::<Result<Vec<_>, SqliteError>>()?;
+    Ok(batches)
+}
+
+/// Take in the answer of a follower to a push: drop the batches it applied from
+/// the log, and push any more.
+async fn handle_push_answer(id: u64, source: &Address, body: &[u8], state: &mut SqliteState) {
+    let Some(db_key) = state.replications.finish_push(id) else {
+        return;
+    };
+    let Some(ReplicationRole::Leader { follower }) = state.replications.role(&db_key) else {
+        return;
+    };
+    if source.node != follower {
+        return;
+    }
+    let result = match serde_json::from_slice::<SqliteResponse>(body) {
+        Ok(SqliteResponse::Replicated { applied_seq }) => {
+            record_applied(state, &db_key, applied_seq).await
+        }
+        Ok(SqliteResponse::Err(e)) => Err(e),
+        _ => Err(SqliteError::MalformedRequest),
+    };
+    match result {
+        Ok(true) => spawn_push(state, &db_key),
+        Ok(false) => {}
+        Err(e) => {
+            Printout::new(
+                1,
+                SQLITE_PROCESS_ID.clone(),
+                format!(
+                    "sqlite: follower {follower} of [{}, {}] did not apply writes: {e}",
+                    db_key.0, db_key.1
+                ),
+            )
+            .send(&state.send_to_terminal)
+            .await;
+        }
+    }
+}
+
+/// Record the last batch a follower applied, returning whether it has more to apply.
+async fn record_applied(
+    state: &mut SqliteState,
+    db_key: &DbKey,
+    applied_seq: u64,
+) -> Result<bool, SqliteError> {
+    state.open_db(db_key, false).await?;
+    let Some(db) = state.open_dbs.get(db_key) else {
+        return Err(SqliteError::NoDb(db_key.0.clone(), db_key.1.clone()));
+    };
+    let mut db = db.lock().await;
+    let acked_seq = read_counter(&db, ACKED_COUNTER)?;
+    let seq = read_counter(&db, SEQ_COUNTER)?;
+    if applied_seq < acked_seq || applied_seq > seq {
+        // it lost batches gone from the log, or applied those of another leader
+        // or of an earlier run: it must be set to follow anew
+        return Err(SqliteError::UnexpectedReplication);
+    }
+    let tx = db.transaction()?;
+    tx.execute(
+        &format!("DELETE FROM {LOG_TABLE} WHERE seq <= ?1"),
+        [applied_seq as i64],
+    )?;
+    write_counter(&tx, ACKED_COUNTER, applied_seq)?;
+    tx.commit()?;
+    Ok(applied_seq < seq)
+}
+
+fn spawn_push(state: &SqliteState, db_key: &DbKey) {
+    let (state, db_key) = (state.clone(), db_key.clone());
+    tokio::spawn(async move {
+        if let Err(e) = push(state.clone(), db_key.clone()).await {
+            Printout::new(
+                1,
+                SQLITE_PROCESS_ID.clone(),
+                format!(
+                    "sqlite: failed to push writes of [{}, {}] to its follower: {e}",
+                    db_key.0, db_key.1
+                ),
+            )
+            .send(&state.send_to_terminal)
+            .await;
+        }
+    });
+}
+
+/// Push the batches followers have not applied, every [`replication::RETRY_INTERVAL`],
+/// as those pushed before may have been lost, or written while a follower was offline.
+async fn retry_pushes(state: SqliteState) {
+    let mut interval = tokio::time::interval(replication::RETRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        for db_key in state.replications.leaders() {
+            spawn_push(&state, &db_key);
+        }
+    }
+}
+
+/// A counter of the replication of a db, 0 if it is not replicated.
+fn read_counter(db: &Connection, name: &str) -> Result<u64, SqliteError> {
+    let replicated: bool = db.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        [COUNTERS_TABLE],
+        |row| row.get(0),
+    )?;
+    if !replicated {
+        return Ok(0);
+    }
+    let value: Option<i64> = db
+        .query_row(
+            &format!("SELECT value FROM {COUNTERS_TABLE} WHERE name = ?1"),
+            [name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(value.unwrap_or(0) as u64)
+}
+
+fn write_counter(db: &Connection, name: &str, value: u64) -> Result<(), SqliteError> {
+    db.execute(
+        &format!("INSERT OR REPLACE INTO {COUNTERS_TABLE} (name, value) VALUES (?1, ?2)"),
+        rusqlite::params![name, value as i64],
+    )?;
+    Ok(())
+}
+
+/// Run a read query, returning its rows as a JSON array of objects by column name.
+fn read_rows(
+    db: &Connection,
+    query: &str,
+    parameters: &[SqlValue],
+) -> Result<Vec<u8>, rusqlite::Error> {
+    let mut statement = db.prepare(query)?;
+    let column_names: Vec<String> = statement
+        .column_names()
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+
+    let results: Vec<HashMap<String, serde_json::Value>> = statement
+        .query_map(rusqlite::params_from_iter(parameters.iter()), |row| {
+            let mut map = HashMap::new();
+            for (i, column_name) in column_names.iter().enumerate() {
+                let value: Option<SqlValue> = row.get(i)?;
+                let value_json = match value {
+                    Some(SqlValue::Integer(int)) => serde_json::Value::Number(int.into()),
+                    Some(SqlValue::Real(real)) => {
+                        serde_json::Value::Number(serde_json::Number::from_f64(real).unwrap())
+                    }
+                    Some(SqlValue::Text(text)) => serde_json::Value::String(text),
+                    Some(SqlValue::Blob(blob)) => {
+                        serde_json::Value::String(base64_standard.encode(blob))
+                    } // or another representation if you prefer
+                    _ => serde_json::Value::Null,
+                };
+                map.insert(column_name.clone(), value_json);
+            }
+            Ok(map)
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(serde_json::json!(results).to_string().into_bytes())
+}
+
+/// The statements creating an FTS5 index and, if it indexes a `content` table,
+/// the triggers keeping it in sync with that table, as the FTS5 docs describe.
+fn search_index_script(
+    index: &str,
+    columns: &[String],
+    content: Option<&str>,
+    tokenizer: &str,
+) -> Result<String, SqliteError> {
+    if columns.is_empty() {
+        return Err(SqliteError::InvalidIdentifier(String::new()));
+    }
+    let name = |suffix: &str| quote_identifier(&format!("{index}{suffix}"));
+    let index_name = quote_identifier(index)?;
+    let columns = columns
+        .iter()
+        .map(|column| quote_identifier(column))
+        .collect::<Result<Vec<_>, _>>()?;
+    let column_list = columns.join(", ");
+    let tokenizer = tokenizer.replace('\'', "''");
+
+    let Some(content) = content else {
+        return Ok(format!(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS {index_name} USING fts5({column_list}, tokenize = '{tokenizer}');"
+        ));
+    };
+    let content_name = quote_identifier(content)?;
+    let values = |row: &str| {
+        columns
+            .iter()
+            .map(|column| format!("{row}.{column}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let (new_values, old_values) = (values("new"), values("old"));
+    let (insert_trigger, delete_trigger, update_trigger) =
+        (name("_ai")?, name("_ad")?, name("_au")?);
+    Ok(format!(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS {index_name} USING fts5({column_list}, content = '{content}', tokenize = '{tokenizer}');
+CREATE TRIGGER IF NOT EXISTS {insert_trigger} AFTER INSERT ON {content_name} BEGIN
+    INSERT INTO {index_name}(rowid, {column_list}) VALUES (new.rowid, {new_values});
+END;
+CREATE TRIGGER IF NOT EXISTS {delete_trigger} AFTER DELETE ON {content_name} BEGIN
+    INSERT INTO {index_name}({index_name}, rowid, {column_list}) VALUES ('delete', old.rowid, {old_values});
+END;
+CREATE TRIGGER IF NOT EXISTS {update_trigger} AFTER UPDATE ON {content_name} BEGIN
+    INSERT INTO {index_name}({index_name}, rowid, {column_list}) VALUES ('delete', old.rowid, {old_values});
+    INSERT INTO {index_name}(rowid, {column_list}) VALUES (new.rowid, {new_values});
+END;
+INSERT INTO {index_name}({index_name}) VALUES ('rebuild');"
+    ))
+}
+
+/// Quote the name of a table or column for use in a statement. Names are limited
+/// to letters, digits and underscores, as they cannot be passed as parameters.
+fn quote_identifier(name: &str) -> Result<String, SqliteError> {
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(SqliteError::InvalidIdentifier(name.to_string()));
+    }
+    Ok(format!("\"{name}\""))
+}
+
+/// Quote the name of any table or column, as found in the schema.
+fn quote_name(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
 async fn handle_fd_request(km: KernelMessage, state: &mut SqliteState) -> anyhow::Result<()> {
     let Message::Request(Request { body, .. }) = km.message else {
         return Err(anyhow::anyhow!("not a request"));
@@ -577,6 +1497,97 @@ async fn add_capability(
     Ok(())
 }
 
+/// Copy the database file at `src` to `dest`, consistently even while it is being
+/// written to. The copy is made next to `dest`, and moved into place once complete.
+pub fn backup_db(src: &Path, dest: &Path, key: Option<&str>) -> Result<(), SqliteError> {
+    let src = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    let partial = dest.with_extension("partial");
+    let mut partial_db = Connection::open(&partial)?;
+    // an encrypted db is only copied to one encrypted with the same key
+    if let Some(key) = key {
+        apply_key(&src, key)?;
+        apply_key(&partial_db, key)?;
+    }
+    copy_db(&src, &mut partial_db)?;
+    drop(partial_db);
+    std::fs::rename(&partial, dest)?;
+    Ok(())
+}
+
+/// Replace the contents of the database file at `dest` with those of the backup
+/// at `src`, through a connection of its own.
+fn restore_db(src: &Path, dest: &Path, key: Option<&str>) -> Result<(), SqliteError> {
+    let src = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut dest_db = Connection::open_with_flags(dest, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    if let Some(key) = key {
+        apply_key(&src, key)?;
+        apply_key(&dest_db, key)?;
+    }
+    copy_db(&src, &mut dest_db)
+}
+
+/// Copy all of `src` into `dest` in one step of the online backup API, which
+/// reads `src` in a single transaction, retrying for a while if either is locked.
+fn copy_db(src: &Connection, dest: &mut Connection) -> Result<(), SqliteError> {
+    let backup = Backup::new(src, dest)?;
+    for _ in 0..MAX_BUSY_RETRIES {
+        match backup.step(-1)? {
+            StepResult::Done => return Ok(()),
+            _ => std::thread::sleep(BUSY_RETRY_DELAY),
+        }
+    }
+    Err(SqliteError::Busy)
+}
+
+/// Whether a db file is encrypted, or `None` if it is empty or doesn't exist.
+pub fn stored_encrypted(path: &Path) -> Result<Option<bool>, SqliteError> {
+    let mut header = Vec::with_capacity(SQLITE_HEADER.len());
+    match std::fs::File::open(path) {
+        Ok(file) => {
+            file.take(SQLITE_HEADER.len() as u64)
+                .read_to_end(&mut header)?;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    if header.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(header != SQLITE_HEADER))
+}
+
+/// The SQLCipher key of a package's encrypted dbs, keyed from the keyfile's file key,
+/// as the hex that `PRAGMA key` takes for a raw key.
+fn db_cipher_key(file_key: &[u8], package_id: &PackageId) -> String {
+    let salt = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, b"kinode-sqlite");
+    let mut key = [0u8; 32];
+    salt.extract(file_key)
+        .expand(
+            &[
+                b"encrypted-dbs".as_slice(),
+                package_id.to_string().as_bytes(),
+            ],
+            ring::hkdf::HKDF_SHA256,
+        )
+        .unwrap()
+        .fill(&mut key)
+        .unwrap();
+    hex::encode(key)
+}
+
+/// Key a connection to an encrypted db, before anything else is done with it.
+#[cfg(feature = "sqlcipher")]
+fn apply_key(db: &Connection, key: &str) -> Result<(), SqliteError> {
+    db.execute_batch(&format!("PRAGMA key = \"x'{key}'\";"))?;
+    Ok(())
+}
+
+/// Without SQLCipher, `PRAGMA key` does nothing, so a db would be left unencrypted.
+#[cfg(not(feature = "sqlcipher"))]
+fn apply_key(_db: &Connection, _key: &str) -> Result<(), SqliteError> {
+    Err(SqliteError::EncryptionUnsupported)
+}
+
 fn json_to_sqlite(value: &serde_json::Value) -> Result<SqlValue, SqliteError> {
     match value {
         serde_json::Value::Number(n) => {
@@ -606,6 +1617,17 @@ fn json_to_sqlite(value: &serde_json::Value) -> Result<SqlValue, SqliteError> {
     }
 }
 
+/// The parameters of a batch of statements: a JSON array of arrays, one per statement.
+fn get_batch_params(blob: LazyLoadBlob) -> Result<Vec<Vec<SqlValue>>, SqliteError> {
+    match serde_json::from_slice::<Vec<Vec<serde_json::Value>>>(&blob.bytes) {
+        Ok(batch) => batch
+            .iter()
+            .map(|params| params.iter().map(json_to_sqlite).collect())
+            .collect(),
+        Err(_) => Err(SqliteError::InvalidParameters),
+    }
+}
+
 fn get_json_params(blob: Option<LazyLoadBlob>) -> Result<Vec<SqlValue>, SqliteError> {
     match blob {
         None => Ok(vec![]),
@@ -8,13 +8,14 @@ use crossterm::{
 };
 use futures::{future::FutureExt, StreamExt};
 use lib::types::core::{
-    DebugCommand, DebugSender, Identity, KernelMessage, Message, MessageSender, PrintReceiver,
-    PrintSender, Printout, ProcessId, ProcessVerbosity, ProcessVerbosityVal, Request,
-    TERMINAL_PROCESS_ID,
+    Address, DebugCommand, DebugSender, Identity, KernelCommand, KernelMessage, KernelPrint,
+    KernelPrintResponse, KernelResponse, Message, MessageReceiver, MessageSender, PrintReceiver,
+    PrintSender, Printout, ProcessId, ProcessVerbosity, ProcessVerbosityVal, Request, Response,
+    KERNEL_PROCESS_ID, TERMINAL_PROCESS_ID, TERMINAL_RUNTIME_PROCESS_ID,
 };
 use std::{
-    collections::{HashMap, VecDeque},
-    fs::{read_to_string, OpenOptions},
+    collections::{HashMap, HashSet, VecDeque},
+    fs::OpenOptions,
     io::BufWriter,
     path::PathBuf,
 };
@@ -27,6 +28,18 @@ pub mod utils;
 // TODO: add a flag & `terminal::terminal()` arg so can be set at run time
 const MAX_PRINTOUT_QUEUE_LEN_DEFAULT: usize = 256;
 
+/// number of printed lines kept in memory for the pager
+const MAX_SCROLLBACK_LEN: usize = 10_000;
+
+/// number of printouts of each process kept in memory for the settings app
+const MAX_PROCESS_PRINTOUTS_LEN: usize = 200;
+
+/// seconds the terminal process has to gather tab-completion candidates
+const COMPLETION_TIMEOUT: u64 = 5;
+
+/// seconds between refreshes of the process monitor
+const MONITOR_REFRESH_INTERVAL: u64 = 1;
+
 struct State {
     pub stdout: std::io::Stdout,
     /// handle and settings for on-disk log (disabled by default, triggered by CTRL+L)
@@ -59,23 +72,38 @@ struct State {
     pub printout_queue: VecDeque<Printout>,
     pub max_printout_queue_len: usize,
     pub printout_queue_number_dropped_printouts: u64,
+    /// id and line (up to the cursor) of the most recent tab-completion query (activated by TAB)
+    pub pending_completion: Option<(u64, String)>,
+    /// the most recently printed lines, viewable in the pager
+    pub scrollback: VecDeque<String>,
+    /// the most recent printouts of each process, of any verbosity
+    pub process_printouts: HashMap<ProcessId, VecDeque<utils::ProcessPrintout>>,
+    /// if Some, the full-screen pager is open (activated by the `less` script, exited by q)
+    pub pager: Option<utils::Pager>,
+    /// if Some, the full-screen process monitor is open (activated by `top --live`, exited by q)
+    pub monitor: Option<utils::Monitor>,
+    /// local processes that receive a copy of every verbosity-0 printout, e.g. the web terminal
+    pub printout_subscribers: HashSet<Address>,
+    pub send_to_loop: MessageSender,
 }
 
 impl State {
     fn display_current_input_line(&mut self, show_end: bool) -> Result<(), std::io::Error> {
+        let (line, cursor_col) = utils::truncate_in_place(
+            &self.current_line.line,
+            self.win_cols - self.current_line.prompt_len as u16,
+            self.current_line.line_col,
+            self.current_line.cursor_col,
+            show_end,
+        );
+        self.current_line.cursor_col = cursor_col;
         execute!(
             self.stdout,
             cursor::MoveTo(0, self.win_rows),
             terminal::Clear(ClearType::CurrentLine),
             style::SetForegroundColor(style::Color::Reset),
             Print(self.current_line.prompt),
-            Print(utils::truncate_in_place(
-                &self.current_line.line,
-                self.win_cols - self.current_line.prompt_len as u16,
-                self.current_line.line_col,
-                self.current_line.cursor_col,
-                show_end
-            )),
+            Print(line),
             cursor::MoveTo(
                 self.current_line.prompt_len as u16 + self.current_line.cursor_col,
                 self.win_rows
@@ -86,46 +114,43 @@ impl State {
     fn search(&mut self, our_name: &str) -> Result<(), std::io::Error> {
         let search_prompt = format!("{} *", our_name);
         let search_query = &self.current_line.line;
-        if let Some(result) = self.command_history.search(search_query, self.search_depth) {
-            let (result_underlined, search_cursor_col) = utils::underline(result, search_query);
-            execute!(
-                self.stdout,
-                cursor::MoveTo(0, self.win_rows),
-                terminal::Clear(terminal::ClearType::CurrentLine),
-                style::SetForegroundColor(style::Color::Reset),
-                style::Print(&search_prompt),
-                style::Print(utils::truncate_in_place(
-                    &result_underlined,
-                    self.win_cols - self.current_line.prompt_len as u16,
-                    self.current_line.line_col,
-                    search_cursor_col,
-                    false,
-                )),
-                cursor::MoveTo(
-                    self.current_line.prompt_len as u16 + search_cursor_col,
-                    self.win_rows
-                ),
-            )
-        } else {
-            execute!(
-                self.stdout,
-                cursor::MoveTo(0, self.win_rows),
-                terminal::Clear(terminal::ClearType::CurrentLine),
-                style::SetForegroundColor(style::Color::Reset),
-                style::Print(&search_prompt),
-                style::Print(utils::truncate_in_place(
-                    &format!("{}: no results", &self.current_line.line),
-                    self.win_cols - self.current_line.prompt_len as u16,
-                    self.current_line.line_col,
-                    self.current_line.cursor_col,
+        let term_width = self.win_cols - self.current_line.prompt_len as u16;
+        let (line, cursor_col) = match self.command_history.search(search_query, self.search_depth)
+        {
+            Some(result) => {
+                // cursor goes at the end of the match; truncate before underlining
+                // so that the escape codes are never cut in half
+                let match_end =
+                    result.find(search_query.as_str()).unwrap_or_default() + search_query.len();
+                let (visible, cursor_col) = utils::truncate_in_place(
+                    result,
+                    term_width,
+                    result[..match_end].graphemes(true).count(),
+                    utils::display_width(&result[..match_end]) as u16,
                     false,
-                )),
-                cursor::MoveTo(
-                    self.current_line.prompt_len as u16 + self.current_line.cursor_col,
-                    self.win_rows
-                ),
-            )
-        }
+                );
+                (utils::underline(&visible, search_query), cursor_col)
+            }
+            None => utils::truncate_in_place(
+                &format!("{}: no results", search_query),
+                term_width,
+                self.current_line.line_col,
+                self.current_line.cursor_col,
+                false,
+            ),
+        };
+        execute!(
+            self.stdout,
+            cursor::MoveTo(0, self.win_rows),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            style::SetForegroundColor(style::Color::Reset),
+            style::Print(&search_prompt),
+            style::Print(line),
+            cursor::MoveTo(
+                self.current_line.prompt_len as u16 + cursor_col,
+                self.win_rows
+            ),
+        )
     }
 
     fn enter_process_verbosity_mode(&mut self) -> Result<(), std::io::Error> {
@@ -144,7 +169,11 @@ impl State {
         // Leave alternate screen and restore cursor
         execute!(self.stdout, cursor::Show, terminal::LeaveAlternateScreen)?;
 
-        // Print queued messages to main screen
+        self.print_queued_printouts()
+    }
+
+    /// print the messages queued while on the alternate screen to the main screen
+    fn print_queued_printouts(&mut self) -> anyhow::Result<()> {
         if self.printout_queue_number_dropped_printouts != 0 {
             let number_dropped_printout = Printout::new(
                 0,
@@ -164,6 +193,186 @@ impl State {
         Ok(())
     }
 
+    fn enter_pager(&mut self, pager: utils::Pager) -> Result<(), std::io::Error> {
+        if self.pager.is_none() {
+            execute!(self.stdout, terminal::EnterAlternateScreen)?;
+        }
+        self.pager = Some(pager);
+        self.display_pager()
+    }
+
+    /// if a following pager is open, add the lines of the printout it shows
+    fn follow_printout(&mut self, printout: &Printout) -> Result<(), std::io::Error> {
+        let height = self.win_rows.saturating_sub(1) as usize;
+        let Some(ref mut pager) = self.pager else {
+            return Ok(());
+        };
+        let Some(ref follow) = pager.follow else {
+            return Ok(());
+        };
+        let now = Local::now();
+        let lines: Vec<String> = follow
+            .matching_lines(&printout.source, printout.verbosity, &printout.content)
+            .map(|line| {
+                format!(
+                    "{:02}:{:02}:{:02} {line}",
+                    now.hour(),
+                    now.minute(),
+                    now.second()
+                )
+            })
+            .collect();
+        if lines.is_empty() {
+            return Ok(());
+        }
+        for line in lines {
+            pager.push_line(line, height, MAX_SCROLLBACK_LEN);
+        }
+        self.display_pager()
+    }
+
+    fn exit_pager(&mut self) -> anyhow::Result<()> {
+        self.pager = None;
+        execute!(self.stdout, terminal::LeaveAlternateScreen)?;
+        self.print_queued_printouts()?;
+        self.display_current_input_line(false)?;
+        Ok(())
+    }
+
+    /// draw a screenful of the pager's lines, with a status line (or the
+    /// search being typed) on the bottom row
+    fn display_pager(&mut self) -> Result<(), std::io::Error> {
+        let Some(ref pager) = self.pager else {
+            return Ok(());
+        };
+        let height = self.win_rows.saturating_sub(1) as usize;
+        execute!(
+            self.stdout,
+            cursor::MoveTo(0, 0),
+            terminal::Clear(ClearType::All),
+        )?;
+        for (row, line) in pager.lines.iter().skip(pager.top).take(height).enumerate() {
+            let line = utils::truncate_to_width(line, self.win_cols);
+            let line = match pager.search {
+                Some(ref search) => utils::highlight(line, search),
+                None => line.to_string(),
+            };
+            execute!(
+                self.stdout,
+                cursor::MoveTo(0, row as u16),
+                Print(line),
+                style::ResetColor,
+            )?;
+        }
+        let status = match pager.search_input {
+            Some(ref input) => format!("/{input}"),
+            None => format!(
+                "{}lines {}-{} of {} (q: quit, /: search, n/N: next/previous match)",
+                match pager.follow {
+                    Some(ref follow) => format!("following {}: ", follow.process),
+                    None => String::new(),
+                },
+                (pager.top + 1).min(pager.lines.len()),
+                (pager.top + height).min(pager.lines.len()),
+                pager.lines.len(),
+            ),
+        };
+        execute!(
+            self.stdout,
+            cursor::MoveTo(0, self.win_rows.saturating_sub(1)),
+            terminal::Clear(ClearType::CurrentLine),
+            Print(utils::truncate_to_width(&status, self.win_cols)),
+        )
+    }
+
+    fn enter_monitor(&mut self) -> Result<(), std::io::Error> {
+        if self.monitor.is_none() {
+            execute!(self.stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+            self.monitor = Some(utils::Monitor::default());
+        }
+        self.display_monitor()
+    }
+
+    fn exit_monitor(&mut self) -> anyhow::Result<()> {
+        self.monitor = None;
+        execute!(self.stdout, cursor::Show, terminal::LeaveAlternateScreen)?;
+        self.print_queued_printouts()?;
+        self.display_current_input_line(false)?;
+        Ok(())
+    }
+
+    /// draw a header, a screenful of processes with the selected one in
+    /// reverse video, and a status line on the bottom row
+    fn display_monitor(&mut self) -> Result<(), std::io::Error> {
+        let Some(ref monitor) = self.monitor else {
+            return Ok(());
+        };
+        let height = self.win_rows.saturating_sub(2) as usize;
+        execute!(
+            self.stdout,
+            cursor::MoveTo(0, 0),
+            terminal::Clear(ClearType::All),
+            style::SetAttribute(style::Attribute::Bold),
+            Print(utils::truncate_to_width(
+                &format!(
+                    "{:>6} {:>7} {:>9} {:>9} {:>6} {:>10} {:<9} PROCESS",
+                    "QUEUE", "DROPPED", "RECEIVED", "SENT", "LAST", "MEMORY", "STATE",
+                ),
+                self.win_cols,
+            )),
+            style::SetAttribute(style::Attribute::Reset),
+        )?;
+        for (row, (process_id, metrics)) in monitor
+            .rows
+            .iter()
+            .skip(monitor.top)
+            .take(height)
+            .enumerate()
+        {
+            let line = format!(
+                "{:>6} {:>7} {:>9} {:>9} {:>6} {:>10} {:<9} {process_id}",
+                metrics.queue_depth,
+                metrics.dropped_messages,
+                metrics.messages_received,
+                metrics.messages_sent,
+                metrics
+                    .last_activity
+                    .map(utils::format_elapsed)
+                    .unwrap_or("-".to_string()),
+                metrics
+                    .memory_bytes
+                    .map(utils::format_bytes)
+                    .unwrap_or("-".to_string()),
+                if metrics.suspended {
+                    "suspended"
+                } else {
+                    "running"
+                },
+            );
+            let line = utils::truncate_to_width(&line, self.win_cols);
+            let line = if monitor.selected.as_ref() == Some(process_id) {
+                format!("\x1b[7m{line}\x1b[27m")
+            } else {
+                line.to_string()
+            };
+            execute!(self.stdout, cursor::MoveTo(0, row as u16 + 1), Print(line))?;
+        }
+        let status = match (&monitor.confirm_kill, &monitor.status) {
+            (Some(process_id), _) => format!("kill {process_id}? [y/N]"),
+            (None, Some(status)) => status.clone(),
+            (None, None) => format!(
+                "{} processes (q: quit, j/k: select, K: kill, s: suspend/resume)",
+                monitor.rows.len(),
+            ),
+        };
+        execute!(
+            self.stdout,
+            cursor::MoveTo(0, self.win_rows.saturating_sub(1)),
+            terminal::Clear(ClearType::CurrentLine),
+            Print(utils::truncate_to_width(&status, self.win_cols)),
+        )
+    }
+
     fn display_process_verbosity(&mut self) -> Result<(), std::io::Error> {
         // Clear the entire screen from the input line up
         execute!(
@@ -251,7 +460,9 @@ struct CurrentLine {
     pub prompt_len: usize,
     /// the grapheme index of the cursor in the current line
     pub line_col: usize,
-    /// the column index of the cursor in the terminal window (not including prompt)
+    /// the column index of the cursor in the terminal window (not including prompt).
+    /// this is where we'd like the cursor to be: when the line is redrawn, it is
+    /// corrected to sit on a grapheme boundary, since wide graphemes take two columns
     pub cursor_col: u16,
     /// the line itself, which does not include the prompt
     pub line: String,
@@ -278,27 +489,73 @@ impl CurrentLine {
         self.line.graphemes(true).nth(self.line_col)
     }
 
-    fn insert_char(&mut self, c: char) {
+    /// insert text at the cursor and move the cursor past it. the text may join
+    /// the graphemes around it (e.g. a combining mark, an emoji modifier, or the
+    /// pieces of a CJK character committed by an IME), so the cursor's grapheme
+    /// index is recomputed rather than incremented
+    fn insert_str(&mut self, s: &str, max_cursor_col: u16) {
         let byte_index = self.byte_index();
-        self.line.insert(byte_index, c);
+        self.line.insert_str(byte_index, s);
+        let end = byte_index + s.len();
+        self.line_col = self
+            .line
+            .grapheme_indices(true)
+            .take_while(|(i, _)| *i < end)
+            .count();
+        let width = utils::display_width(&self.line[..self.byte_index()])
+            .saturating_sub(utils::display_width(&self.line[..byte_index]));
+        self.cursor_col = std::cmp::min(self.cursor_col + width as u16, max_cursor_col);
     }
 
-    fn insert_str(&mut self, s: &str) {
-        let byte_index = self.byte_index();
-        self.line.insert_str(byte_index, s);
+    /// delete the grapheme left of the cursor; returns false if there is none
+    fn delete_left(&mut self) -> bool {
+        if !self.move_left() {
+            return false;
+        }
+        self.delete_right()
     }
 
-    /// returns the deleted character
-    fn delete_char(&mut self) -> String {
+    /// delete the grapheme right of the cursor; returns false if there is none
+    fn delete_right(&mut self) -> bool {
         let byte_index = self.byte_index();
-        let next_grapheme = self.line[byte_index..]
-            .graphemes(true)
-            .next()
-            .map(|g| g.len())
-            .unwrap_or(0);
-        self.line
-            .drain(byte_index..byte_index + next_grapheme)
-            .collect()
+        let Some(grapheme_len) = self.current_char_right().map(|g| g.len()) else {
+            return false;
+        };
+        self.line.drain(byte_index..byte_index + grapheme_len);
+        true
+    }
+
+    /// move the cursor one grapheme left; returns false if at the start of the line
+    fn move_left(&mut self) -> bool {
+        let Some(width) = self.current_char_left().map(utils::display_width) else {
+            return false;
+        };
+        self.line_col -= 1;
+        self.cursor_col = self.cursor_col.saturating_sub(width as u16);
+        true
+    }
+
+    /// move the cursor one grapheme right; returns false if at the end of the line
+    fn move_right(&mut self, max_cursor_col: u16) -> bool {
+        let Some(width) = self.current_char_right().map(utils::display_width) else {
+            return false;
+        };
+        self.line_col += 1;
+        self.cursor_col = std::cmp::min(self.cursor_col + width as u16, max_cursor_col);
+        true
+    }
+
+    /// replace the line, putting the cursor at its end
+    fn set_line(&mut self, line: String, max_cursor_col: u16) {
+        self.line_col = line.graphemes(true).count();
+        self.cursor_col = std::cmp::min(utils::display_width(&line) as u16, max_cursor_col);
+        self.line = line;
+    }
+
+    fn clear(&mut self) {
+        self.line.clear();
+        self.line_col = 0;
+        self.cursor_col = 0;
     }
 }
 
@@ -312,6 +569,7 @@ pub async fn terminal(
     mut debug_event_loop: DebugSender,
     mut print_tx: PrintSender,
     mut print_rx: PrintReceiver,
+    mut terminal_rx: MessageReceiver,
     is_detached: bool,
     verbose_mode: u8,
     is_logging: bool,
@@ -337,9 +595,9 @@ pub async fn terminal(
     let logging_mode = is_logging;
 
     // the terminal stores the most recent 1000 lines entered by user
-    // in history. TODO should make history size adjustable.
+    // in history, both in memory and on disk. TODO should make history size adjustable.
     let history_path = home_directory_path.join(".terminal_history");
-    let history = read_to_string(&history_path).unwrap_or_default();
+    let history = utils::load_history(&history_path, 1000);
     let history_handle = OpenOptions::new()
         .append(true)
         .create(true)
@@ -385,6 +643,13 @@ pub async fn terminal(
         printout_queue,
         max_printout_queue_len,
         printout_queue_number_dropped_printouts,
+        pending_completion: None,
+        scrollback: VecDeque::new(),
+        process_printouts: HashMap::new(),
+        pager: None,
+        monitor: None,
+        printout_subscribers: HashSet::new(),
+        send_to_loop: event_loop.clone(),
     };
 
     // use to trigger cleanup if receive signal to kill process
@@ -435,17 +700,26 @@ pub async fn terminal(
     // only create event stream if not in detached mode
     if !is_detached {
         let mut reader = EventStream::new();
+        let mut monitor_refresh =
+            tokio::time::interval(std::time::Duration::from_secs(MONITOR_REFRESH_INTERVAL));
+        monitor_refresh.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
         loop {
             #[cfg(unix)]
             tokio::select! {
                 Some(printout) = print_rx.recv() => {
                     handle_printout(printout, &mut state)?;
                 }
+                Some(km) = terminal_rx.recv() => {
+                    handle_runtime_message(&our, km, &mut state, &mut event_loop, &mut print_tx).await?;
+                }
                 Some(Ok(event)) = reader.next().fuse() => {
                     if handle_event(&our, event, &mut state, &mut event_loop, &mut debug_event_loop, &mut print_tx).await? {
                         break;
                     }
                 }
+                _ = monitor_refresh.tick(), if state.monitor.is_some() => {
+                    send_kernel_command(&our, KernelCommand::Debug(KernelPrint::Metrics), &mut event_loop).await;
+                }
                 _ = sigalrm.recv() => return Err(anyhow::anyhow!("exiting due to SIGALRM")),
                 _ = sighup.recv() =>  return Err(anyhow::anyhow!("exiting due to SIGHUP")),
                 _ = sigint.recv() =>  return Err(anyhow::anyhow!("exiting due to SIGINT")),
@@ -460,11 +734,17 @@ pub async fn terminal(
                 Some(printout) = print_rx.recv() => {
                     handle_printout(printout, &mut state)?;
                 }
+                Some(km) = terminal_rx.recv() => {
+                    handle_runtime_message(&our, km, &mut state, &mut event_loop, &mut print_tx).await?;
+                }
                 Some(Ok(event)) = reader.next().fuse() => {
                     if handle_event(&our, event, &mut state, &mut event_loop, &mut debug_event_loop, &mut print_tx).await? {
                         break;
                     }
                 }
+                _ = monitor_refresh.tick(), if state.monitor.is_some() => {
+                    send_kernel_command(&our, KernelCommand::Debug(KernelPrint::Metrics), &mut event_loop).await;
+                }
             }
         }
     } else {
@@ -493,7 +773,20 @@ pub async fn terminal(
 }
 
 fn handle_printout(printout: Printout, state: &mut State) -> anyhow::Result<()> {
-    if state.process_verbosity_mode {
+    state.follow_printout(&printout)?;
+    let printouts = state
+        .process_printouts
+        .entry(printout.source.clone())
+        .or_default();
+    if printouts.len() >= MAX_PROCESS_PRINTOUTS_LEN {
+        printouts.pop_front();
+    }
+    printouts.push_back(utils::ProcessPrintout {
+        timestamp: Local::now().timestamp() as u64,
+        verbosity: printout.verbosity,
+        content: printout.content.clone(),
+    });
+    if state.process_verbosity_mode || state.pager.is_some() || state.monitor.is_some() {
         if state.printout_queue.len() >= state.max_printout_queue_len {
             // remove oldest if queue is overflowing
             state.printout_queue.pop_front();
@@ -521,6 +814,15 @@ fn handle_printout(printout: Printout, state: &mut State) -> anyhow::Result<()>
     if &printout.verbosity > current_verbosity {
         return Ok(());
     }
+    // nothing to print, e.g. the result of a script run for its side effects
+    if printout.content.is_empty() {
+        return Ok(());
+    }
+    // only forward command output: noisier printouts may describe
+    // the forwarding messages themselves, which would never end
+    if printout.verbosity == 0 {
+        forward_printout(&printout, state);
+    }
     let now = Local::now();
     execute!(
         stdout,
@@ -542,12 +844,282 @@ fn handle_printout(printout: Printout, state: &mut State) -> anyhow::Result<()>
     )?;
     for line in printout.content.lines() {
         execute!(stdout, Print(format!("{line}\r\n")))?;
+        if state.scrollback.len() >= MAX_SCROLLBACK_LEN {
+            state.scrollback.pop_front();
+        }
+        state.scrollback.push_back(line.to_string());
     }
     // re-display the current input line
     state.display_current_input_line(false)?;
     Ok(())
 }
 
+/// send a copy of a printout to each subscriber, without waiting on the event loop
+fn forward_printout(printout: &Printout, state: &State) {
+    for subscriber in &state.printout_subscribers {
+        let km = KernelMessage::builder()
+            .id(rand::random())
+            .source((
+                subscriber.node.as_str(),
+                TERMINAL_RUNTIME_PROCESS_ID.clone(),
+            ))
+            .target(subscriber.clone())
+            .message(Message::Request(Request {
+                inherit: false,
+                expects_response: None,
+                body: serde_json::to_vec(&utils::RuntimePrintout {
+                    verbosity: printout.verbosity,
+                    content: printout.content.clone(),
+                })
+                .unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            }))
+            .build()
+            .unwrap();
+        // dropped if the event loop is backed up: printing must never block
+        let _ = state.send_to_loop.try_send(km);
+    }
+}
+
+/// send a command to the kernel from the runtime terminal; the response, if any,
+/// is handled by `handle_kernel_response`
+async fn send_kernel_command(our: &Identity, command: KernelCommand, event_loop: &MessageSender) {
+    KernelMessage::builder()
+        .id(rand::random())
+        .source((our.name.as_str(), TERMINAL_RUNTIME_PROCESS_ID.clone()))
+        .target((our.name.as_str(), KERNEL_PROCESS_ID.clone()))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: Some(5),
+            body: serde_json::to_vec(&command).unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(event_loop)
+        .await;
+}
+
+/// handle a message sent to the runtime terminal: either a response to one of
+/// our tab-completion queries or kernel commands, or a request from a local process
+/// for the command history or a process's printouts, to open the pager or monitor,
+/// to (un)subscribe to printouts, or to rotate the log
+async fn handle_runtime_message(
+    our: &Identity,
+    km: KernelMessage,
+    state: &mut State,
+    event_loop: &mut MessageSender,
+    print_tx: &mut PrintSender,
+) -> anyhow::Result<()> {
+    if km.source.process == *KERNEL_PROCESS_ID {
+        return handle_kernel_response(km, state);
+    }
+    let Message::Request(ref request) = km.message else {
+        return handle_completion(km, state, print_tx).await;
+    };
+    if km.source.node != our.name {
+        return Ok(());
+    }
+    let response = match serde_json::from_slice::<utils::RuntimeRequest>(&request.body) {
+        Ok(utils::RuntimeRequest::History(filter)) => utils::RuntimeResponse::History(
+            state
+                .command_history
+                .filter(filter.as_deref().unwrap_or_default()),
+        ),
+        Ok(utils::RuntimeRequest::Page(text)) => {
+            // process verbosity mode or the monitor already occupies the alternate screen
+            if !state.process_verbosity_mode && state.monitor.is_none() {
+                let lines = match text {
+                    Some(text) => text.lines().map(|line| line.to_string()).collect(),
+                    None => state.scrollback.iter().cloned().collect(),
+                };
+                state.enter_pager(utils::Pager::new(lines))?;
+            }
+            return Ok(());
+        }
+        Ok(utils::RuntimeRequest::Tail(tail)) => {
+            // process verbosity mode or the monitor already occupies the alternate screen
+            if state.process_verbosity_mode || state.monitor.is_some() {
+                return Ok(());
+            }
+            let process = match tail.process.parse::<ProcessId>() {
+                Ok(process) => process,
+                Err(e) => {
+                    Printout::new(0, TERMINAL_PROCESS_ID.clone(), format!("tail: {e}"))
+                        .send(print_tx)
+                        .await;
+                    return Ok(());
+                }
+            };
+            let filter = match tail.filter.as_deref().map(regex::Regex::new).transpose() {
+                Ok(filter) => filter,
+                Err(e) => {
+                    Printout::new(
+                        0,
+                        TERMINAL_PROCESS_ID.clone(),
+                        format!("tail: invalid filter: {e}"),
+                    )
+                    .send(print_tx)
+                    .await;
+                    return Ok(());
+                }
+            };
+            state.enter_pager(utils::Pager::follow(utils::Follow {
+                process,
+                filter,
+                level: tail.level,
+            }))?;
+            return Ok(());
+        }
+        Ok(utils::RuntimeRequest::Monitor) => {
+            // process verbosity mode or the pager already occupies the alternate screen
+            if !state.process_verbosity_mode && state.pager.is_none() {
+                state.enter_monitor()?;
+                send_kernel_command(our, KernelCommand::Debug(KernelPrint::Metrics), event_loop)
+                    .await;
+            }
+            return Ok(());
+        }
+        Ok(utils::RuntimeRequest::SubscribePrintouts) => {
+            state.printout_subscribers.insert(km.source);
+            return Ok(());
+        }
+        Ok(utils::RuntimeRequest::UnsubscribePrintouts) => {
+            state.printout_subscribers.remove(&km.source);
+            return Ok(());
+        }
+        Ok(utils::RuntimeRequest::Printouts(process)) => utils::RuntimeResponse::Printouts(
+            process
+                .parse::<ProcessId>()
+                .ok()
+                .and_then(|process| state.process_printouts.get(&process))
+                .map(|printouts| printouts.iter().cloned().collect())
+                .unwrap_or_default(),
+        ),
+        Ok(utils::RuntimeRequest::RotateLog) => {
+            // each rotation removes the oldest log beyond those kept, so only
+            // the terminal and the settings app, which schedules it, may rotate
+            let from_settings =
+                km.source.process == ProcessId::new(Some("settings"), "settings", "sys");
+            if from_settings || km.source.process == *TERMINAL_PROCESS_ID {
+                state.logger.rotate()?;
+            }
+            return Ok(());
+        }
+        Err(_) => return Ok(()),
+    };
+    if request.expects_response.is_none() {
+        return Ok(());
+    }
+    KernelMessage::builder()
+        .id(km.id)
+        .source((our.name.as_str(), TERMINAL_RUNTIME_PROCESS_ID.clone()))
+        .target(km.rsvp.unwrap_or(km.source))
+        .message(Message::Response((
+            Response {
+                inherit: false,
+                body: serde_json::to_vec(&response).unwrap(),
+                metadata: request.metadata.clone(),
+                capabilities: vec![],
+            },
+            None,
+        )))
+        .build()
+        .unwrap()
+        .send(event_loop)
+        .await;
+    Ok(())
+}
+
+/// update the monitor with the kernel's response to one of its commands
+fn handle_kernel_response(km: KernelMessage, state: &mut State) -> anyhow::Result<()> {
+    let Message::Response((response, _context)) = km.message else {
+        return Ok(());
+    };
+    let Some(ref mut monitor) = state.monitor else {
+        return Ok(());
+    };
+    match serde_json::from_slice::<KernelResponse>(&response.body) {
+        Ok(KernelResponse::Debug(KernelPrintResponse::Metrics(metrics))) => {
+            monitor.update(metrics);
+            let height = state.win_rows.saturating_sub(2) as usize;
+            monitor.select(0, height);
+        }
+        Ok(KernelResponse::KilledProcess(process_id)) => {
+            monitor.status = Some(format!("killed {process_id}"));
+        }
+        Ok(KernelResponse::SuspendedProcess(process_id)) => {
+            monitor.status = Some(format!("suspended {process_id}"));
+        }
+        Ok(KernelResponse::ResumedProcess(process_id)) => {
+            monitor.status = Some(format!("resumed {process_id}"));
+        }
+        Ok(KernelResponse::SuspendProcessError) => {
+            monitor.status = Some("runtime processes cannot be suspended".to_string());
+        }
+        _ => return Ok(()),
+    }
+    state.display_monitor()?;
+    Ok(())
+}
+
+/// apply the candidates returned by the terminal process for a tab-completion query:
+/// a lone candidate, or a prefix shared by all candidates, is inserted at the cursor;
+/// otherwise the candidates are printed above the input line
+async fn handle_completion(
+    km: KernelMessage,
+    state: &mut State,
+    print_tx: &mut PrintSender,
+) -> anyhow::Result<()> {
+    let Message::Response((response, _context)) = km.message else {
+        return Ok(());
+    };
+    // drop responses to stale queries, e.g. if the line was edited since TAB was pressed
+    let Some((id, line)) = state.pending_completion.take() else {
+        return Ok(());
+    };
+    if km.id != id || state.current_line.line[..state.current_line.byte_index()] != line {
+        return Ok(());
+    }
+    let Ok(utils::CompletionResponse::Complete(candidates)) =
+        serde_json::from_slice::<utils::CompletionResponse>(&response.body)
+    else {
+        return Ok(());
+    };
+    let word = utils::completion_word(&line);
+    let completion = match candidates.as_slice() {
+        [] => {
+            // the "no-no" ding
+            print!("\x07");
+            return Ok(());
+        }
+        [candidate] => match candidate.strip_prefix(word) {
+            // a finished word, i.e. anything but a directory, is followed by a space
+            Some(rest) if !candidate.ends_with('/') => format!("{rest} "),
+            Some(rest) => rest.to_string(),
+            None => return Ok(()),
+        },
+        _ => {
+            let rest = utils::common_prefix(&candidates)
+                .strip_prefix(word)
+                .unwrap_or_default();
+            if rest.is_empty() {
+                Printout::new(0, TERMINAL_PROCESS_ID.clone(), candidates.join("  "))
+                    .send(&print_tx)
+                    .await;
+                return Ok(());
+            }
+            rest.to_string()
+        }
+    };
+    let max_cursor_col = state.win_cols - state.current_line.prompt_len as u16;
+    state.current_line.insert_str(&completion, max_cursor_col);
+    state.display_current_input_line(false)?;
+    Ok(())
+}
+
 /// returns true if runtime should exit due to CTRL+C or CTRL+D
 async fn handle_event(
     our: &Identity,
@@ -584,11 +1156,11 @@ async fn handle_event(
             )?;
             *win_cols = width - 1;
             *win_rows = height;
-            if current_line.cursor_col + current_line.prompt_len as u16 > *win_cols {
-                current_line.cursor_col = *win_cols - current_line.prompt_len as u16;
-                // can't do this because of wide graphemes :/
-                // current_line.line_col = current_line.cursor_col as usize;
-            }
+            // the line is redrawn below, which puts the cursor back on a grapheme boundary
+            current_line.cursor_col = std::cmp::min(
+                current_line.cursor_col,
+                *win_cols - current_line.prompt_len as u16,
+            );
         }
         //
         // PASTE: handle pasting of text from outside
@@ -599,12 +1171,7 @@ async fn handle_event(
                 .chars()
                 .filter(|c| !c.is_control() && !c.is_ascii_control())
                 .collect::<String>();
-            current_line.insert_str(&pasted);
-            current_line.line_col = current_line.line_col + pasted.graphemes(true).count();
-            current_line.cursor_col = std::cmp::min(
-                current_line.cursor_col + utils::display_width(&pasted) as u16,
-                *win_cols - current_line.prompt_len as u16,
-            );
+            current_line.insert_str(&pasted, *win_cols - current_line.prompt_len as u16);
         }
         Event::Key(key_event) => {
             if let Some(should_exit) = handle_key_event(
@@ -625,7 +1192,11 @@ async fn handle_event(
             // some terminal event we don't care about, yet
         }
     }
-    if state.search_mode {
+    if state.pager.is_some() {
+        state.display_pager()?;
+    } else if state.monitor.is_some() {
+        state.display_monitor()?;
+    } else if state.search_mode {
         state.search(&our.name)?;
     } else if state.process_verbosity_mode {
         state.display_process_verbosity()?;
@@ -650,6 +1221,32 @@ async fn handle_key_event(
     if key_event.kind == KeyEventKind::Release {
         return Ok(Some(false));
     }
+    if state.pager.is_some()
+        && !matches!(
+            key_event,
+            KeyEvent {
+                code: KeyCode::Char('c' | 'd'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            }
+        )
+    {
+        handle_pager_key_event(key_event, state)?;
+        return Ok(Some(false));
+    }
+    if state.monitor.is_some()
+        && !matches!(
+            key_event,
+            KeyEvent {
+                code: KeyCode::Char('c' | 'd'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            }
+        )
+    {
+        handle_monitor_key_event(our, key_event, state, event_loop).await?;
+        return Ok(Some(false));
+    }
     let State {
         command_history,
         win_cols,
@@ -813,11 +1410,7 @@ async fn handle_key_event(
             // go up one command in history
             match command_history.get_prev(&current_line.line) {
                 Some(line) => {
-                    let width = utils::display_width(&line);
-                    current_line.line_col = line.graphemes(true).count();
-                    current_line.line = line;
-                    current_line.cursor_col =
-                        std::cmp::min(width as u16, *win_cols - current_line.prompt_len as u16);
+                    current_line.set_line(line, *win_cols - current_line.prompt_len as u16);
                 }
                 None => {
                     // the "no-no" ding
@@ -845,11 +1438,7 @@ async fn handle_key_event(
             // go down one command in history
             match command_history.get_next() {
                 Some(line) => {
-                    let width = utils::display_width(&line);
-                    current_line.line_col = line.graphemes(true).count();
-                    current_line.line = line;
-                    current_line.cursor_col =
-                        std::cmp::min(width as u16, *win_cols - current_line.prompt_len as u16);
+                    current_line.set_line(line, *win_cols - current_line.prompt_len as u16);
                 }
                 None => {
                     // the "no-no" ding
@@ -885,10 +1474,7 @@ async fn handle_key_event(
                 return Ok(Some(false));
             }
             current_line.line_col = current_line.line.graphemes(true).count();
-            current_line.cursor_col = std::cmp::min(
-                utils::display_width(&current_line.line) as u16,
-                *win_cols - current_line.prompt_len as u16,
-            );
+            current_line.cursor_col = *win_cols - current_line.prompt_len as u16;
         }
         //
         //  CTRL+R: enter search mode
@@ -936,12 +1522,7 @@ async fn handle_key_event(
 
                 // Restore previous line if it exists
                 if let Some(saved_line) = state.saved_line.take() {
-                    current_line.line = saved_line;
-                    current_line.line_col = current_line.line.graphemes(true).count();
-                    current_line.cursor_col = std::cmp::min(
-                        utils::display_width(&current_line.line) as u16,
-                        *win_cols - current_line.prompt_len as u16,
-                    );
+                    current_line.set_line(saved_line, *win_cols - current_line.prompt_len as u16);
                 }
 
                 state.exit_process_verbosity_mode()?;
@@ -951,15 +1532,47 @@ async fn handle_key_event(
 
                 // Save current line
                 state.saved_line = Some(current_line.line.clone());
-                current_line.line.clear();
-                current_line.line_col = 0;
-                current_line.cursor_col = 0;
+                current_line.clear();
 
                 state.enter_process_verbosity_mode()?;
             }
             return Ok(Some(false));
         }
         //
+        //  TAB: ask the terminal process to complete the word at the cursor
+        //
+        KeyEvent {
+            code: KeyCode::Tab, ..
+        } => {
+            if state.search_mode || state.process_verbosity_mode {
+                return Ok(Some(false));
+            }
+            let line = current_line.line[..current_line.byte_index()].to_string();
+            let id = rand::random();
+            KernelMessage::builder()
+                .id(id)
+                .source((our.name.as_str(), TERMINAL_PROCESS_ID.clone()))
+                .target((our.name.as_str(), TERMINAL_PROCESS_ID.clone()))
+                .rsvp(Some(Address::new(
+                    our.name.as_str(),
+                    TERMINAL_RUNTIME_PROCESS_ID.clone(),
+                )))
+                .message(Message::Request(Request {
+                    inherit: false,
+                    expects_response: Some(COMPLETION_TIMEOUT),
+                    body: serde_json::to_vec(&utils::CompletionRequest::Complete(line.clone()))
+                        .unwrap(),
+                    metadata: None,
+                    capabilities: vec![],
+                }))
+                .build()
+                .unwrap()
+                .send(&event_loop)
+                .await;
+            state.pending_completion = Some((id, line));
+            return Ok(Some(false));
+        }
+        //
         //  KEY: handle keypress events
         //
         k => {
@@ -968,81 +1581,43 @@ async fn handle_key_event(
                 //  CHAR: write a single character
                 //
                 KeyCode::Char(c) => {
-                    current_line.insert_char(c);
-                    if (current_line.cursor_col + current_line.prompt_len as u16) < *win_cols {
-                        current_line.cursor_col += utils::display_width(&c.to_string()) as u16;
-                    }
-                    current_line.line_col += 1;
+                    current_line.insert_str(
+                        c.encode_utf8(&mut [0; 4]),
+                        *win_cols - current_line.prompt_len as u16,
+                    );
                 }
                 //
-                //  BACKSPACE: delete a single character at cursor
+                //  BACKSPACE: delete a single grapheme at cursor
                 //
                 KeyCode::Backspace => {
-                    if current_line.line_col == 0 {
+                    if !current_line.delete_left() {
                         return Ok(Some(false));
-                    } else {
-                        current_line.line_col -= 1;
-                        let c = current_line.delete_char();
-                        current_line.cursor_col -= utils::display_width(&c) as u16;
                     }
                 }
                 //
-                //  DELETE: delete a single character at right of cursor
+                //  DELETE: delete a single grapheme at right of cursor
                 //
                 KeyCode::Delete => {
-                    if current_line.line_col == current_line.line.graphemes(true).count() {
+                    if !current_line.delete_right() {
                         return Ok(Some(false));
                     }
-                    current_line.delete_char();
                 }
                 //
-                //  LEFT: move cursor one spot left
+                //  LEFT: move cursor one grapheme left, scrolling the line if at the left edge
                 //
                 KeyCode::Left => {
-                    if current_line.cursor_col as usize == 0 {
-                        if current_line.line_col == 0 {
-                            // at the very beginning of the current typed line
-                            return Ok(Some(false));
-                        } else {
-                            // virtual scroll leftward through line
-                            current_line.line_col -= 1;
-                        }
-                    } else {
-                        // simply move cursor and line position left
-                        let width = current_line
-                            .current_char_left()
-                            .map_or_else(|| 1, |c| utils::display_width(&c))
-                            as u16;
-                        execute!(stdout, cursor::MoveLeft(width))?;
-                        current_line.cursor_col -= width;
-                        if current_line.line_col != 0 {
-                            current_line.line_col -= 1;
-                        }
+                    if !current_line.move_left() {
+                        // at the very beginning of the current typed line
                         return Ok(Some(false));
                     }
                 }
                 //
-                //  RIGHT: move cursor one spot right
+                //  RIGHT: move cursor one grapheme right, scrolling the line if at the right edge
                 //
                 KeyCode::Right => {
-                    if current_line.line_col == current_line.line.graphemes(true).count() {
+                    if !current_line.move_right(*win_cols - current_line.prompt_len as u16) {
                         // at the very end of the current typed line
                         return Ok(Some(false));
-                    };
-                    if (current_line.cursor_col + current_line.prompt_len as u16) < (*win_cols - 1)
-                    {
-                        // simply move cursor and line position right
-                        let width = current_line
-                            .current_char_right()
-                            .map_or_else(|| 1, |c| utils::display_width(&c))
-                            as u16;
-                        execute!(stdout, cursor::MoveRight(width))?;
-                        current_line.cursor_col += width;
-                        current_line.line_col += 1;
-                        return Ok(Some(false));
-                    } else {
-                        // virtual scroll rightward through line
-                        current_line.line_col += 1;
                     }
                 }
                 //
@@ -1074,9 +1649,7 @@ async fn handle_key_event(
                                     .await
                                     .expect("failed to toggle process-level full event loop on");
                             }
-                            current_line.line.clear();
-                            current_line.line_col = 0;
-                            current_line.cursor_col = 0;
+                            current_line.clear();
                             state.display_process_verbosity()?;
                         } else if let Ok(process_id) = &current_line.line.parse() {
                             // remove ProcessId
@@ -1097,9 +1670,7 @@ async fn handle_key_event(
                                         );
                                 }
                             }
-                            current_line.line.clear();
-                            current_line.line_col = 0;
-                            current_line.cursor_col = 0;
+                            current_line.clear();
                             state.display_process_verbosity()?;
                         }
                         return Ok(Some(false));
@@ -1123,8 +1694,7 @@ async fn handle_key_event(
                     )?;
                     state.search_mode = false;
                     *search_depth = 0;
-                    current_line.cursor_col = 0;
-                    current_line.line_col = 0;
+                    current_line.clear();
                     command_history.add(command.to_string());
                     KernelMessage::builder()
                         .id(rand::random())
@@ -1141,7 +1711,6 @@ async fn handle_key_event(
                         .unwrap()
                         .send(&event_loop)
                         .await;
-                    current_line.line = "".to_string();
                 }
                 _ => {
                     // some keycode we don't care about, yet
@@ -1151,3 +1720,108 @@ async fn handle_key_event(
     }
     Ok(None)
 }
+
+/// q or ESC: quit the pager
+/// arrows, j/k, SPACE/b, PAGE UP/PAGE DOWN, g/G: scroll
+/// /: search, n/N: jump to the next/previous match
+fn handle_pager_key_event(key_event: KeyEvent, state: &mut State) -> anyhow::Result<()> {
+    let height = state.win_rows.saturating_sub(1) as usize;
+    let Some(ref mut pager) = state.pager else {
+        return Ok(());
+    };
+    if let Some(ref mut input) = pager.search_input {
+        match key_event.code {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Esc => pager.search_input = None,
+            KeyCode::Enter => {
+                let search = pager.search_input.take().unwrap_or_default();
+                if !search.is_empty() {
+                    pager.search = Some(search);
+                    if !pager.find_next(pager.top) {
+                        print!("\x07");
+                    }
+                }
+            }
+            _ => {}
+        }
+        state.display_pager()?;
+        return Ok(());
+    }
+    match key_event.code {
+        KeyCode::Char('q') | KeyCode::Esc => return state.exit_pager(),
+        KeyCode::Down | KeyCode::Enter | KeyCode::Char('j') => pager.scroll(1, height),
+        KeyCode::Up | KeyCode::Char('k') => pager.scroll(-1, height),
+        KeyCode::PageDown | KeyCode::Char(' ') | KeyCode::Char('f') => {
+            pager.scroll(height as isize, height)
+        }
+        KeyCode::PageUp | KeyCode::Char('b') => pager.scroll(-(height as isize), height),
+        KeyCode::Home | KeyCode::Char('g') => pager.top = 0,
+        KeyCode::End | KeyCode::Char('G') => pager.scroll_to_end(height),
+        KeyCode::Char('/') => pager.search_input = Some(String::new()),
+        KeyCode::Char('n') => {
+            if !pager.find_next(pager.top + 1) {
+                print!("\x07");
+            }
+        }
+        KeyCode::Char('N') => {
+            if !pager.find_prev(pager.top) {
+                print!("\x07");
+            }
+        }
+        _ => {}
+    }
+    state.display_pager()?;
+    Ok(())
+}
+
+/// q or ESC: quit the monitor
+/// arrows, j/k, PAGE UP/PAGE DOWN: select a process
+/// K: kill the selected process (after y to confirm)
+/// s: suspend or resume the selected process
+async fn handle_monitor_key_event(
+    our: &Identity,
+    key_event: KeyEvent,
+    state: &mut State,
+    event_loop: &MessageSender,
+) -> anyhow::Result<()> {
+    let height = state.win_rows.saturating_sub(2) as usize;
+    let Some(ref mut monitor) = state.monitor else {
+        return Ok(());
+    };
+    if let Some(process_id) = monitor.confirm_kill.take() {
+        if key_event.code == KeyCode::Char('y') {
+            monitor.status = Some(format!("killing {process_id}..."));
+            send_kernel_command(our, KernelCommand::KillProcess(process_id), event_loop).await;
+        } else {
+            monitor.status = None;
+        }
+        state.display_monitor()?;
+        return Ok(());
+    }
+    monitor.status = None;
+    match key_event.code {
+        KeyCode::Char('q') | KeyCode::Esc => return state.exit_monitor(),
+        KeyCode::Down | KeyCode::Char('j') => monitor.select(1, height),
+        KeyCode::Up | KeyCode::Char('k') => monitor.select(-1, height),
+        KeyCode::PageDown => monitor.select(height as isize, height),
+        KeyCode::PageUp => monitor.select(-(height as isize), height),
+        KeyCode::Char('K') => monitor.confirm_kill = monitor.selected.clone(),
+        KeyCode::Char('s') => {
+            if let Some(index) = monitor.selected_index() {
+                let (process_id, metrics) = &monitor.rows[index];
+                let command = if metrics.suspended {
+                    KernelCommand::ResumeProcess(process_id.clone())
+                } else {
+                    KernelCommand::SuspendProcess(process_id.clone())
+                };
+                send_kernel_command(our, command, event_loop).await;
+            }
+        }
+        _ => {}
+    }
+    state.display_monitor()?;
+    Ok(())
+}
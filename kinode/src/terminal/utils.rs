@@ -1,7 +1,9 @@
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
-use lib::types::core::Identity;
+use lib::types::core::{Identity, ProcessId, ProcessMetrics};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     fs::{File, OpenOptions},
     io::{BufWriter, Stdout, Write},
     path::{Path, PathBuf},
@@ -135,6 +137,306 @@ pub fn display_width(s: &str) -> usize {
     UnicodeWidthStr::width(s)
 }
 
+/// the `complete` request of the terminal:sys API, sent to the terminal process on TAB
+#[derive(Serialize)]
+pub enum CompletionRequest {
+    Complete(String),
+}
+
+/// the `complete` response of the terminal:sys API: candidates for the word being completed
+#[derive(Deserialize)]
+pub enum CompletionResponse {
+    Complete(Vec<String>),
+}
+
+/// the `runtime-request` of the terminal:sys API, sent by processes to the runtime terminal
+#[derive(Deserialize)]
+pub enum RuntimeRequest {
+    History(Option<String>),
+    Page(Option<String>),
+    SubscribePrintouts,
+    UnsubscribePrintouts,
+    Monitor,
+    Tail(TailRequest),
+    Printouts(String),
+    RotateLog,
+}
+
+/// the `tail-request` of the terminal:sys API
+#[derive(Deserialize)]
+pub struct TailRequest {
+    pub process: String,
+    pub filter: Option<String>,
+    pub level: u8,
+}
+
+/// the `runtime-response` of the terminal:sys API
+#[derive(Serialize)]
+pub enum RuntimeResponse {
+    History(Vec<String>),
+    Printouts(Vec<ProcessPrintout>),
+}
+
+/// the `process-printout` of the terminal:sys API
+#[derive(Clone, Serialize)]
+pub struct ProcessPrintout {
+    /// unix time, in seconds, at which it was printed
+    pub timestamp: u64,
+    pub verbosity: u8,
+    pub content: String,
+}
+
+/// the `runtime-printout` of the terminal:sys API, sent to printout subscribers
+#[derive(Serialize)]
+pub struct RuntimePrintout {
+    pub verbosity: u8,
+    pub content: String,
+}
+
+/// the word being completed: the last word of the line, where words
+/// are separated by whitespace, pipes, and redirects
+pub fn completion_word(line: &str) -> &str {
+    line.rsplit(|c: char| c.is_whitespace() || c == '|' || c == '>')
+        .next()
+        .unwrap_or(line)
+}
+
+/// the longest prefix shared by all given strings
+pub fn common_prefix(strings: &[String]) -> &str {
+    let Some(first) = strings.first() else {
+        return "";
+    };
+    strings.iter().skip(1).fold(first.as_str(), |common, s| {
+        let len = common
+            .chars()
+            .zip(s.chars())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a.len_utf8())
+            .sum();
+        &common[..len]
+    })
+}
+
+/// a full-screen, `less`-style view of some lines of text
+pub struct Pager {
+    pub lines: Vec<String>,
+    /// index of the line shown at the top of the screen
+    pub top: usize,
+    /// the most recent search, whose matches are highlighted
+    pub search: Option<String>,
+    /// the search being typed (activated by `/`)
+    pub search_input: Option<String>,
+    /// if Some, printouts from a process are appended as they arrive (activated by `tail`)
+    pub follow: Option<Follow>,
+}
+
+/// which printouts a following pager shows
+pub struct Follow {
+    pub process: ProcessId,
+    pub filter: Option<Regex>,
+    /// highest verbosity shown
+    pub level: u8,
+}
+
+impl Follow {
+    /// the lines of the printout that should be shown, if any
+    pub fn matching_lines<'a>(
+        &'a self,
+        source: &ProcessId,
+        verbosity: u8,
+        content: &'a str,
+    ) -> impl Iterator<Item = &'a str> {
+        let shown = source == &self.process && verbosity <= self.level;
+        content.lines().filter(move |line| match self.filter {
+            Some(ref filter) => shown && filter.is_match(line),
+            None => shown,
+        })
+    }
+}
+
+impl Pager {
+    pub fn new(lines: Vec<String>) -> Self {
+        Self {
+            lines,
+            top: 0,
+            search: None,
+            search_input: None,
+            follow: None,
+        }
+    }
+
+    pub fn follow(follow: Follow) -> Self {
+        Self {
+            follow: Some(follow),
+            ..Self::new(vec![])
+        }
+    }
+
+    /// add a line to the end, keeping at most `max_lines`; if the last line
+    /// was on screen, scroll so that the new one is too
+    pub fn push_line(&mut self, line: String, height: usize, max_lines: usize) {
+        let at_end = self.top >= self.lines.len().saturating_sub(height);
+        if self.lines.len() >= max_lines {
+            self.lines.remove(0);
+            self.top = self.top.saturating_sub(1);
+        }
+        self.lines.push(line);
+        if at_end {
+            self.scroll_to_end(height);
+        }
+    }
+
+    /// move the view by `delta` lines, stopping once the last line is on screen
+    pub fn scroll(&mut self, delta: isize, height: usize) {
+        let max_top = self.lines.len().saturating_sub(height);
+        self.top = self.top.saturating_add_signed(delta).min(max_top);
+    }
+
+    pub fn scroll_to_end(&mut self, height: usize) {
+        self.top = self.lines.len().saturating_sub(height);
+    }
+
+    /// move the view to the first line at or after `from` that matches the search;
+    /// returns false if there is none
+    pub fn find_next(&mut self, from: usize) -> bool {
+        let Some(ref search) = self.search else {
+            return false;
+        };
+        match (from..self.lines.len()).find(|i| self.lines[*i].contains(search.as_str())) {
+            Some(i) => {
+                self.top = i;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// move the view to the last line before `before` that matches the search;
+    /// returns false if there is none
+    pub fn find_prev(&mut self, before: usize) -> bool {
+        let Some(ref search) = self.search else {
+            return false;
+        };
+        match (0..before.min(self.lines.len()))
+            .rev()
+            .find(|i| self.lines[*i].contains(search.as_str()))
+        {
+            Some(i) => {
+                self.top = i;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// a full-screen, `top`-style view of the running processes, refreshed
+/// from the kernel's process metrics
+#[derive(Default)]
+pub struct Monitor {
+    /// processes, most memory first (runtime processes, which report none, last)
+    pub rows: Vec<(ProcessId, ProcessMetrics)>,
+    /// the highlighted process, which kill and suspend act on
+    pub selected: Option<ProcessId>,
+    /// index of the row shown at the top of the table
+    pub top: usize,
+    /// if Some, waiting for y/N to kill this process
+    pub confirm_kill: Option<ProcessId>,
+    /// outcome of the most recent action, shown in the status line
+    pub status: Option<String>,
+}
+
+impl Monitor {
+    /// replace the rows, keeping the same process selected if it is still running
+    pub fn update(&mut self, metrics: HashMap<ProcessId, ProcessMetrics>) {
+        let mut rows: Vec<(ProcessId, ProcessMetrics)> = metrics.into_iter().collect();
+        rows.sort_by(|(a_id, a), (b_id, b)| {
+            b.memory_bytes
+                .cmp(&a.memory_bytes)
+                .then_with(|| a_id.to_string().cmp(&b_id.to_string()))
+        });
+        self.rows = rows;
+        if !self
+            .selected
+            .as_ref()
+            .is_some_and(|selected| self.rows.iter().any(|(id, _)| id == selected))
+        {
+            self.selected = self.rows.first().map(|(id, _)| id.clone());
+        }
+    }
+
+    pub fn selected_index(&self) -> Option<usize> {
+        let selected = self.selected.as_ref()?;
+        self.rows.iter().position(|(id, _)| id == selected)
+    }
+
+    /// move the selection by `delta` rows, scrolling to keep it within `height` rows
+    pub fn select(&mut self, delta: isize, height: usize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let index = self
+            .selected_index()
+            .unwrap_or_default()
+            .saturating_add_signed(delta)
+            .min(self.rows.len() - 1);
+        self.selected = Some(self.rows[index].0.clone());
+        if index < self.top {
+            self.top = index;
+        } else if height > 0 && index >= self.top + height {
+            self.top = index + 1 - height;
+        }
+    }
+}
+
+/// human-readable byte count, e.g. `1.5 MiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// time since `unix_secs`, e.g. `5s`, `3m`, `2h`
+pub fn format_elapsed(unix_secs: u64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let elapsed = now.saturating_sub(unix_secs);
+    match elapsed {
+        0..=59 => format!("{elapsed}s"),
+        60..=3599 => format!("{}m", elapsed / 60),
+        3600..=86399 => format!("{}h", elapsed / 3600),
+        _ => format!("{}d", elapsed / 86400),
+    }
+}
+
+/// the longest prefix of `s` that fits in `width` columns
+pub fn truncate_to_width(s: &str, width: u16) -> &str {
+    let mut cols = 0;
+    for (i, grapheme) in s.grapheme_indices(true) {
+        cols += display_width(grapheme);
+        if cols > width as usize {
+            return &s[..i];
+        }
+    }
+    s
+}
+
+/// show every occurrence of `to_highlight` in `s` in reverse video
+pub fn highlight(s: &str, to_highlight: &str) -> String {
+    s.replace(to_highlight, &format!("\x1b[7m{to_highlight}\x1b[27m"))
+}
+
 /// produce command line prompt and its length
 pub fn make_prompt(our_name: &str) -> (&'static str, usize) {
     let prompt = Box::leak(format!("{} > ", our_name).into_boxed_str());
@@ -218,6 +520,16 @@ impl CommandHistory {
         Some(self.lines[self.index - 1].clone())
     }
 
+    /// all commands in history that contain the provided string, oldest first
+    pub fn filter(&self, find: &str) -> Vec<String> {
+        self.lines
+            .iter()
+            .rev()
+            .filter(|line| line.contains(find))
+            .cloned()
+            .collect()
+    }
+
     /// if depth = 0, find most recent command in history that contains the
     /// provided string. otherwise, skip the first <depth> matches.
     pub fn search(&mut self, find: &str, depth: usize) -> Option<&str> {
@@ -241,101 +553,82 @@ impl CommandHistory {
     }
 }
 
-pub fn underline(s: &str, to_underline: &str) -> (String, u16) {
+/// read the history file, first truncating it to its most recent `max_size`
+/// lines so that it does not grow without bound across restarts
+pub fn load_history(history_path: &Path, max_size: usize) -> String {
+    let history = std::fs::read_to_string(history_path).unwrap_or_default();
+    let lines: Vec<&str> = history.lines().collect();
+    if lines.len() <= max_size {
+        return history;
+    }
+    let mut truncated = lines[lines.len() - max_size..].join("\n");
+    truncated.push('\n');
+    let _ = std::fs::write(history_path, &truncated);
+    truncated
+}
+
+pub fn underline(s: &str, to_underline: &str) -> String {
     // format result string to have query portion underlined
-    let mut result = s.to_string();
-    let u_start = s.find(to_underline).unwrap();
-    let mut u_end = u_start + to_underline.len();
-    result.insert_str(u_end, "\x1b[24m");
-    result.insert_str(u_start, "\x1b[4m");
-    // check if u_end is at a character boundary
-    loop {
-        if result.is_char_boundary(u_end) {
-            break;
+    match s.find(to_underline) {
+        Some(u_start) if !to_underline.is_empty() => {
+            let u_end = u_start + to_underline.len();
+            format!(
+                "{}\x1b[4m{}\x1b[24m{}",
+                &s[..u_start],
+                &s[u_start..u_end],
+                &s[u_end..],
+            )
         }
-        u_end += 1;
+        _ => s.to_string(),
     }
-    let cursor_end = display_width(&result[..u_end]);
-    (result, cursor_end as u16)
 }
 
 /// if line is wider than the terminal, truncate it intelligently,
-/// keeping the cursor in the same relative position.
+/// keeping the cursor as close as possible to `cursor_col`.
+/// works on whole graphemes, so a wide grapheme is never cut in half.
+/// returns the visible part of the line and the column the cursor lands on.
 pub fn truncate_in_place(
     s: &str,
     term_width: u16,
     line_col: usize,
     cursor_col: u16,
     show_end: bool,
-) -> String {
-    let width = display_width(s);
-    if width <= term_width as usize {
-        // no adjustment to be made
-        return s.to_string();
-    }
-
-    let graphemes_with_width = s.graphemes(true).map(|g| (g, display_width(g)));
-
-    let adjusted_cursor_col = graphemes_with_width
-        .clone()
-        .take(cursor_col as usize)
-        .map(|(_, w)| w)
-        .sum::<usize>();
-
-    // input line is wider than terminal, clip start/end/both while keeping cursor
-    // in same relative position.
-    if show_end || cursor_col >= term_width {
-        // show end of line, truncate everything before
-        let mut width = 0;
-        graphemes_with_width
-            .rev()
-            .take_while(|(_, w)| {
-                width += w;
-                width <= term_width as usize
-            })
-            .map(|(g, _)| g)
-            .collect::<String>()
-            .chars()
-            .rev()
-            .collect::<String>()
-    } else if adjusted_cursor_col as usize == line_col {
-        // beginning of line is placed at left end, truncate everything past term_width
-        let mut width = 0;
-        graphemes_with_width
-            .take_while(|(_, w)| {
-                width += w;
-                width <= term_width as usize
-            })
-            .map(|(g, _)| g)
-            .collect::<String>()
-    } else if adjusted_cursor_col < line_col {
-        // some amount of the line is to the left of the terminal, clip from the right
-        // skip the difference between line_col and cursor_col *after adjusting for
-        // wide characters
-        let mut width = 0;
-        graphemes_with_width
-            .skip(line_col - adjusted_cursor_col)
-            .take_while(|(_, w)| {
-                width += w;
-                width <= term_width as usize
-            })
-            .map(|(g, _)| g)
-            .collect::<String>()
+) -> (String, u16) {
+    let graphemes = s
+        .graphemes(true)
+        .map(|g| (g, display_width(g)))
+        .collect::<Vec<_>>();
+    let term_width = term_width as usize;
+    let line_col = line_col.min(graphemes.len());
+    let target_col = if show_end {
+        term_width
     } else {
-        // show end of line, truncate everything before
-        let mut width = 0;
-        graphemes_with_width
-            .rev()
-            .take_while(|(_, w)| {
-                width += w;
-                width <= term_width as usize
-            })
-            .map(|(g, _)| g)
-            .collect::<String>()
-            .chars()
-            .rev()
-            .collect::<String>()
+        (cursor_col as usize).min(term_width)
+    };
+    // from the cursor, take as many graphemes leftward as fit before target_col
+    let mut start = line_col;
+    let mut cursor_width = 0;
+    while start > 0 && cursor_width + graphemes[start - 1].1 <= target_col {
+        start -= 1;
+        cursor_width += graphemes[start].1;
+    }
+    // then fill out the rest of the terminal rightward
+    let mut end = line_col;
+    let mut width = cursor_width;
+    while end < graphemes.len() && width + graphemes[end].1 <= term_width {
+        width += graphemes[end].1;
+        end += 1;
+    }
+    // if the line ends before the right edge, show more of its start instead
+    while start > 0 && width + graphemes[start - 1].1 <= term_width {
+        start -= 1;
+        width += graphemes[start].1;
+        cursor_width += graphemes[start].1;
     }
+    (
+        graphemes[start..end].iter().map(|(g, _)| *g).collect(),
+        cursor_width as u16,
+    )
 }
 
 pub struct Logger {
@@ -394,11 +687,7 @@ impl Logger {
                 let line_bytes = line.len();
                 let file_bytes = self.log_writer.get_ref().metadata()?.len() as usize;
                 if line_bytes + file_bytes >= (max_log_dir_bytes / number_log_files) as usize {
-                    // rotate
-                    self.log_writer = make_log_writer(&self.log_dir_path)?;
-
-                    // clean up oldest if necessary
-                    remove_oldest_if_exceeds(&self.log_dir_path, number_log_files as usize)?;
+                    self.rotate()?;
                 }
             }
         }
@@ -407,6 +696,21 @@ impl Logger {
 
         Ok(())
     }
+
+    /// Start a new log file, removing the oldest beyond the number kept.
+    pub fn rotate(&mut self) -> anyhow::Result<()> {
+        self.log_writer.flush()?;
+        self.log_writer = make_log_writer(&self.log_dir_path)?;
+
+        // clean up oldest if necessary
+        if let LoggerStrategy::Rotating {
+            number_log_files, ..
+        } = self.strategy
+        {
+            remove_oldest_if_exceeds(&self.log_dir_path, number_log_files as usize)?;
+        }
+        Ok(())
+    }
 }
 
 fn make_log_writer(log_dir_path: &Path) -> anyhow::Result<BufWriter<std::fs::File>> {
@@ -1,17 +1,23 @@
-use dashmap::DashMap;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key,
+};
+use dashmap::{DashMap, DashSet};
+use generic_array::GenericArray;
 use lib::types::core::{
-    Address, CapMessage, CapMessageSender, Capability, DirEntry, FdManagerRequest, FileMetadata,
-    FileType, KernelMessage, LazyLoadBlob, Message, MessageReceiver, MessageSender, PackageId,
-    PrintSender, Printout, ProcessId, Request, Response, VfsAction, VfsError, VfsRequest,
-    VfsResponse, FD_MANAGER_PROCESS_ID, KERNEL_PROCESS_ID, VFS_PROCESS_ID,
+    Address, CapMessage, CapMessageSender, Capability, DbUsage, DirEntry, DriveUsage,
+    FdManagerRequest, FileMetadata, FileType, KernelMessage, LazyLoadBlob, Message,
+    MessageReceiver, MessageSender, PackageId, PackageStorage, PrintSender, Printout, ProcessId,
+    Request, Response, VfsAction, VfsChange, VfsChangeKind, VfsError, VfsProgress, VfsRequest,
+    VfsResponse, VfsSnapshot, FD_MANAGER_PROCESS_ID, KERNEL_PROCESS_ID, VFS_PROCESS_ID,
 };
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     hash::Hash,
     io::Read,
     path::{Component, Path, PathBuf},
     sync::Arc,
-    time::Instant,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
     fs,
@@ -19,6 +25,35 @@ use tokio::{
     sync::Mutex,
 };
 
+/// how many files `CopyDir` copies between progress updates
+const PROGRESS_INTERVAL: u64 = 100;
+
+/// how many snapshots of a drive are kept: taking another removes the oldest
+const MAX_SNAPSHOTS: usize = 3;
+
+/// Compressed files start with a zstd skippable frame, so that they remain valid zstd
+/// streams, whose content is this tag followed by the uncompressed length as a u64.
+const SKIPPABLE_FRAME_MAGIC: [u8; 4] = [0x50, 0x2A, 0x4D, 0x18];
+const COMPRESSED_TAG: &[u8; 4] = b"knvf";
+const COMPRESSED_HEADER_LEN: usize = 20;
+const COMPRESSION_LEVEL: i32 = 3;
+/// The header is written by whatever writes the file, so the length it claims is only
+/// trusted within what zstd could have produced from the file, and never above a cap.
+/// zstd inflates a block of at most 128KiB from no fewer than 4 bytes.
+const MAX_COMPRESSION_RATIO: u64 = 1 << 15;
+const MAX_DECOMPRESSED_LEN: u64 = 1 << 32;
+
+/// text files larger than this are indexed for search by name only
+const MAX_INDEXED_FILE_SIZE: usize = 1024 * 1024;
+const MAX_SEARCH_RESULTS: usize = 1000;
+
+/// Encrypted files start with this magic, the length of their plain contents as a u64,
+/// and the nonce their AES-256-GCM ciphertext was sealed with.
+const ENCRYPTED_MAGIC: &[u8; 8] = b"\0knvenc1";
+const ENCRYPTED_HEADER_LEN: usize = 28;
+/// files on encrypted drives are held whole in memory, so can grow no larger than this
+const MAX_ENCRYPTED_LEN: u64 = 1 << 32;
+
 /// The main VFS service function.
 ///
 /// This function sets up the VFS, handles incoming requests, and manages file operations.
@@ -31,6 +66,7 @@ use tokio::{
 /// * `recv_from_loop` - Receiver for incoming messages
 /// * `send_to_caps_oracle` - Sender for capability messages
 /// * `home_directory_path` - Path to the home directory
+/// * `file_key` - Key from the keyfile, from which encrypted drives' key is derived
 ///
 /// # Returns
 /// * `anyhow::Result<()>` - Should never return Ok, but will return fatal errors.
@@ -41,6 +77,7 @@ pub async fn vfs(
     mut recv_from_loop: MessageReceiver,
     send_to_caps_oracle: CapMessageSender,
     home_directory_path: PathBuf,
+    file_key: Vec<u8>,
 ) -> anyhow::Result<()> {
     let vfs_path = home_directory_path.join("vfs");
 
@@ -49,9 +86,17 @@ pub async fn vfs(
         .map_err(|e| anyhow::anyhow!("failed creating vfs dir! {e:?}"))?;
     let vfs_path = Arc::new(fs::canonicalize(&vfs_path).await?);
 
+    let quotas = Quotas::load(home_directory_path.join(".vfs_quotas")).await;
+    let compressed_drives = DriveSet::load(home_directory_path.join(".vfs_compression")).await;
+    let encrypted_drives = DriveSet::load(home_directory_path.join(".vfs_encryption")).await;
+
     let mut files = Files::new(
         Address::new(our_node.as_str(), VFS_PROCESS_ID.clone()),
         send_to_loop,
+        quotas,
+        compressed_drives,
+        encrypted_drives,
+        file_cipher(&file_key),
     );
 
     let process_queues: HashMap<ProcessId, Arc<Mutex<VecDeque<KernelMessage>>>> =
@@ -145,6 +190,13 @@ struct Files {
     cursor_positions: Arc<DashMap<PathBuf, u64>>,
     /// access order of files
     access_order: Arc<Mutex<UniqueQueue<PathBuf>>>,
+    /// processes to notify of changes to each watched path, or anything under it
+    watchers: Arc<DashMap<PathBuf, HashSet<Address>>>,
+    quotas: Quotas,
+    compressed_drives: DriveSet,
+    encrypted_drives: DriveSet,
+    cipher: Aes256Gcm,
+    search_indices: Arc<DashMap<String, Arc<Mutex<SearchIndex>>>>,
     pub our: Address,
     pub send_to_loop: MessageSender,
     pub fds_limit: u64,
@@ -156,11 +208,24 @@ struct FileEntry {
 }
 
 impl Files {
-    pub fn new(our: Address, send_to_loop: MessageSender) -> Self {
+    pub fn new(
+        our: Address,
+        send_to_loop: MessageSender,
+        quotas: Quotas,
+        compressed_drives: DriveSet,
+        encrypted_drives: DriveSet,
+        cipher: Aes256Gcm,
+    ) -> Self {
         Self {
             open_files: Arc::new(DashMap::new()),
             cursor_positions: Arc::new(DashMap::new()),
             access_order: Arc::new(Mutex::new(UniqueQueue::new())),
+            watchers: Arc::new(DashMap::new()),
+            quotas,
+            compressed_drives,
+            encrypted_drives,
+            cipher,
+            search_indices: Arc::new(DashMap::new()),
             our,
             send_to_loop,
             fds_limit: 10, // small hardcoded limit that gets replaced by fd-manager soon after boot
@@ -243,6 +308,392 @@ impl Files {
         Ok(())
     }
 
+    /// send each change to the processes watching the changed path or one of its parents
+    async fn notify_watchers(&self, changes: Vec<(PathBuf, VfsChange)>) {
+        for (changed_path, change) in changes {
+            let watchers: HashSet<Address> = self
+                .watchers
+                .iter()
+                .filter(|entry| changed_path.starts_with(entry.key()))
+                .flat_map(|entry| entry.value().clone())
+                .collect();
+            for watcher in watchers {
+                KernelMessage::builder()
+                    .id(rand::random())
+                    .source(self.our.clone())
+                    .target(watcher)
+                    .message(Message::Request(Request {
+                        inherit: false,
+                        expects_response: None,
+                        body: serde_json::to_vec(&change).unwrap(),
+                        metadata: None,
+                        capabilities: vec![],
+                    }))
+                    .build()
+                    .unwrap()
+                    .send(&self.send_to_loop)
+                    .await;
+            }
+        }
+    }
+
+    /// Copy the tree at `from` to `to`, building it at a hidden path beside `to` and
+    /// renaming it into place once complete. If `progress` is given, send the requester
+    /// a `VfsProgress` every `PROGRESS_INTERVAL` files.
+    async fn copy_dir(
+        &self,
+        from: &Path,
+        to: &Path,
+        progress: Option<(Address, String)>,
+    ) -> Result<(), VfsError> {
+        if fs::try_exists(to).await? {
+            return Err(VfsError::IOError("destination already exists".to_string()));
+        }
+        // gather the whole tree first, so progress can be reported against a total
+        let mut dirs = vec![PathBuf::new()];
+        let mut file_paths = vec![];
+        let mut i = 0;
+        while i < dirs.len() {
+            let mut entries = fs::read_dir(from.join(&dirs[i])).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let relative_path = dirs[i].join(entry.file_name());
+                if entry.metadata().await?.is_dir() {
+                    dirs.push(relative_path);
+                } else {
+                    file_paths.push(relative_path);
+                }
+            }
+            i += 1;
+        }
+
+        let staging = staging_path(to, "copying");
+        let total = file_paths.len() as u64;
+        let result: Result<(), std::io::Error> = async {
+            for dir in &dirs {
+                fs::create_dir_all(staging.join(dir)).await?;
+            }
+            for (done, file_path) in file_paths.iter().enumerate() {
+                fs::copy(from.join(file_path), staging.join(file_path)).await?;
+                let done = done as u64 + 1;
+                if let Some((ref target, ref path)) = progress {
+                    if done % PROGRESS_INTERVAL == 0 || done == total {
+                        self.send_progress(target, path, done, total).await;
+                    }
+                }
+            }
+            fs::rename(&staging, to).await
+        }
+        .await;
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&staging).await;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Store a compressed file uncompressed, so that it can be accessed at positions within it.
+    async fn inflate(&self, path: &Path) -> Result<(), VfsError> {
+        if read_compressed_len(path).await.is_none() {
+            return Ok(());
+        }
+        let contents = decompress(fs::read(path).await?)?;
+        // any open handle was positioned in the compressed contents
+        self.remove_file(path).await?;
+        fs::write(path, contents).await?;
+        Ok(())
+    }
+
+    /// Store a file the way a drive with these settings stores files: encrypted or not,
+    /// and inflated if the drive is not compressed.
+    async fn conform(
+        &self,
+        path: &Path,
+        compressed: bool,
+        encrypted: bool,
+    ) -> Result<(), VfsError> {
+        let contents = fs::read(path).await?;
+        let was_encrypted = encrypted_len(&contents).is_some();
+        let contents = self.decrypt(contents)?;
+        if was_encrypted == encrypted && (compressed || compressed_len(&contents).is_none()) {
+            return Ok(());
+        }
+        let contents = if compressed {
+            contents
+        } else {
+            decompress(contents)?
+        };
+        let contents = if encrypted {
+            self.encrypt(&contents)
+        } else {
+            contents
+        };
+        // any open handle was positioned in the old contents
+        self.remove_file(path).await?;
+        fs::write(path, contents).await?;
+        Ok(())
+    }
+
+    /// Conform every file in a directory and all its subdirectories.
+    async fn conform_dir(
+        &self,
+        path: &Path,
+        compressed: bool,
+        encrypted: bool,
+    ) -> Result<(), VfsError> {
+        let mut dirs = vec![path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.metadata().await?.is_dir() {
+                    dirs.push(entry.path());
+                } else {
+                    self.conform(&entry.path(), compressed, encrypted).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn encrypt(&self, contents: &[u8]) -> Vec<u8> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let len = compressed_len(contents).unwrap_or(contents.len() as u64);
+        let mut encrypted = Vec::with_capacity(ENCRYPTED_HEADER_LEN + contents.len() + 16);
+        encrypted.extend_from_slice(ENCRYPTED_MAGIC);
+        encrypted.extend_from_slice(&len.to_le_bytes());
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend(self.cipher.encrypt(&nonce, contents).unwrap());
+        encrypted
+    }
+
+    /// Decrypt the contents of a file, if they are encrypted.
+    fn decrypt(&self, contents: Vec<u8>) -> Result<Vec<u8>, VfsError> {
+        if encrypted_len(&contents).is_none() {
+            return Ok(contents);
+        }
+        self.cipher
+            .decrypt(
+                GenericArray::from_slice(&contents[16..ENCRYPTED_HEADER_LEN]),
+                &contents[ENCRYPTED_HEADER_LEN..],
+            )
+            .map_err(|_| VfsError::DecryptionFailed)
+    }
+
+    /// The plain contents of a file: decrypted and decompressed.
+    async fn read_plain(&self, path: &Path) -> Result<Vec<u8>, VfsError> {
+        decompress(self.decrypt(fs::read(path).await?)?)
+    }
+
+    /// Contents as they are stored on a drive with these settings.
+    fn encode(
+        &self,
+        contents: &[u8],
+        compressed: bool,
+        encrypted: bool,
+    ) -> Result<Vec<u8>, VfsError> {
+        let contents = if compressed {
+            compress(contents)?
+        } else {
+            contents.to_vec()
+        };
+        Ok(if encrypted {
+            self.encrypt(&contents)
+        } else {
+            contents
+        })
+    }
+
+    async fn write_encoded(
+        &self,
+        path: &Path,
+        contents: &[u8],
+        compressed: bool,
+        encrypted: bool,
+    ) -> Result<(), VfsError> {
+        fs::write(path, self.encode(contents, compressed, encrypted)?).await?;
+        Ok(())
+    }
+
+    /// Perform an action on a file of an encrypted drive in memory, as if it were plain.
+    async fn encrypted_action(
+        &self,
+        action: VfsAction,
+        path: &Path,
+        compressed: bool,
+        blob: Option<LazyLoadBlob>,
+    ) -> Result<(VfsResponse, Option<Vec<u8>>), VfsError> {
+        match action {
+            VfsAction::Write => {
+                let Some(blob) = blob else {
+                    return Err(VfsError::NoBlob);
+                };
+                self.write_encoded(path, &blob.bytes, compressed, true)
+                    .await?;
+                Ok((VfsResponse::Ok, None))
+            }
+            VfsAction::Append | VfsAction::WriteAt { .. } => {
+                let Some(blob) = blob else {
+                    return Err(VfsError::NoBlob);
+                };
+                let mut contents = self.read_plain(path).await?;
+                let offset = match action {
+                    VfsAction::WriteAt { offset } => offset,
+                    _ => contents.len() as u64,
+                };
+                let end = encrypted_file_len(offset.checked_add(blob.bytes.len() as u64))?;
+                let (offset, end) = (offset as usize, end as usize);
+                if contents.len() < end {
+                    contents.resize(end, 0);
+                }
+                contents[offset..end].copy_from_slice(&blob.bytes);
+                self.write_encoded(path, &contents, compressed, true)
+                    .await?;
+                Ok((VfsResponse::Ok, None))
+            }
+            VfsAction::SetLen(len) => {
+                let len = encrypted_file_len(Some(len))?;
+                let mut contents = self.read_plain(path).await?;
+                contents.resize(len as usize, 0);
+                self.write_encoded(path, &contents, compressed, true)
+                    .await?;
+                Ok((VfsResponse::Ok, None))
+            }
+            VfsAction::Read | VfsAction::ReadToEnd => {
+                Ok((VfsResponse::Read, Some(self.read_plain(path).await?)))
+            }
+            VfsAction::ReadAt { offset, length } => {
+                let contents = self.read_plain(path).await?;
+                let start = (offset as usize).min(contents.len());
+                let end = start.saturating_add(length as usize).min(contents.len());
+                Ok((VfsResponse::Read, Some(contents[start..end].to_vec())))
+            }
+            VfsAction::ReadToString => {
+                let contents = String::from_utf8(self.read_plain(path).await?)
+                    .map_err(|e| VfsError::IOError(e.to_string()))?;
+                Ok((VfsResponse::ReadToString(contents), None))
+            }
+            VfsAction::Hash => {
+                use sha2::{Digest, Sha256};
+                let hash: [u8; 32] = Sha256::digest(self.read_plain(path).await?).into();
+                Ok((VfsResponse::Hash(hash), None))
+            }
+            VfsAction::Len => Ok((VfsResponse::Len(read_plain_len(path).await?), None)),
+            VfsAction::Metadata => {
+                let metadata = fs::metadata(path).await?;
+                let len = match metadata.is_file() {
+                    true => read_plain_len(path).await?,
+                    false => metadata.len(),
+                };
+                Ok((
+                    VfsResponse::Metadata(FileMetadata {
+                        len,
+                        file_type: get_file_type(&metadata),
+                    }),
+                    None,
+                ))
+            }
+            _ => Err(VfsError::EncryptedDrive),
+        }
+    }
+
+    /// Search a drive, indexing it first if it has not been searched before.
+    async fn search(
+        &self,
+        drive: &str,
+        base_drive: &Path,
+        query: &str,
+        full_text: bool,
+    ) -> Result<Vec<DirEntry>, VfsError> {
+        let index = match self.search_indices.get(drive) {
+            Some(index) => index.clone(),
+            None => {
+                let index = Arc::new(Mutex::new(self.index_drive(drive, base_drive).await?));
+                self.search_indices.insert(drive.to_string(), index.clone());
+                index
+            }
+        };
+        let index = index.lock().await;
+        Ok(index.search(query, full_text))
+    }
+
+    async fn index_drive(&self, drive: &str, base_drive: &Path) -> Result<SearchIndex, VfsError> {
+        let mut index = SearchIndex::default();
+        let mut dirs = vec![base_drive.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                if is_staging(&entry.file_name().to_string_lossy()) {
+                    continue;
+                }
+                let entry_path = entry.path();
+                let Ok(relative_path) = entry_path.strip_prefix(base_drive) else {
+                    continue;
+                };
+                let path = format!("{drive}/{}", relative_path.display());
+                if entry.metadata().await?.is_dir() {
+                    index.insert(path, true, None);
+                    dirs.push(entry_path);
+                } else {
+                    let words = self.file_words(&entry_path).await;
+                    index.insert(path, false, words);
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    /// The words of a file, for full-text search, if it is a text file small enough to index.
+    async fn file_words(&self, path: &Path) -> Option<HashSet<String>> {
+        let contents = self.read_plain(path).await.ok()?;
+        if contents.len() > MAX_INDEXED_FILE_SIZE {
+            return None;
+        }
+        Some(words(&String::from_utf8(contents).ok()?))
+    }
+
+    /// Bring the search index of a drive, if it has one, up to date with a file
+    /// that was written or removed.
+    async fn reindex_file(&self, drive: &str, indexed_path: &str, path: &Path) {
+        let Some(index) = self.search_indices.get(drive).map(|index| index.clone()) else {
+            return;
+        };
+        let words = match fs::metadata(path).await {
+            Ok(metadata) if metadata.is_file() => Some(self.file_words(path).await),
+            _ => None,
+        };
+        let mut index = index.lock().await;
+        index.remove(indexed_path);
+        if let Some(words) = words {
+            index.insert(indexed_path.to_string(), false, words);
+        }
+    }
+
+    async fn send_progress(&self, target: &Address, path: &str, done: u64, total: u64) {
+        KernelMessage::builder()
+            .id(rand::random())
+            .source(self.our.clone())
+            .target(target.clone())
+            .message(Message::Request(Request {
+                inherit: false,
+                expects_response: None,
+                body: serde_json::to_vec(&VfsProgress {
+                    path: path.to_string(),
+                    done,
+                    total,
+                })
+                .unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            }))
+            .build()
+            .unwrap()
+            .send(&self.send_to_loop)
+            .await;
+    }
+
     async fn try_open_file(
         &self,
         path: &Path,
@@ -259,6 +710,256 @@ impl Files {
     }
 }
 
+/// Storage quotas of drives, persisted to disk, and the bytes used by each drive
+/// with a quota: counted when first needed, then kept up to date as writes succeed.
+#[derive(Clone)]
+struct Quotas {
+    path: Arc<PathBuf>,
+    limits: Arc<DashMap<String, u64>>,
+    usage: Arc<DashMap<String, u64>>,
+}
+
+impl Quotas {
+    async fn load(path: PathBuf) -> Self {
+        let limits: HashMap<String, u64> = match fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path: Arc::new(path),
+            limits: Arc::new(limits.into_iter().collect()),
+            usage: Arc::new(DashMap::new()),
+        }
+    }
+
+    async fn set(&self, drive: &str, quota: Option<u64>) -> Result<(), VfsError> {
+        match quota {
+            Some(quota) => {
+                self.limits.insert(drive.to_string(), quota);
+            }
+            None => {
+                self.limits.remove(drive);
+                self.usage.remove(drive);
+            }
+        }
+        let limits: HashMap<String, u64> = self
+            .limits
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect();
+        fs::write(&*self.path, serde_json::to_string(&limits).unwrap()).await?;
+        Ok(())
+    }
+
+    /// bytes used by a drive, counting them if not known
+    async fn usage(&self, drive: &str, vfs_path: &PathBuf) -> Result<u64, VfsError> {
+        if let Some(usage) = self.usage.get(drive) {
+            return Ok(*usage);
+        }
+        let drive_path = join_paths_safely(vfs_path, drive);
+        #[cfg(target_os = "windows")]
+        let drive_path = internal_path_to_external(&drive_path);
+
+        // the drive's snapshots are kept for it, so count toward its quota too
+        let usage =
+            dir_size(&drive_path).await? + dir_size(&snapshots_path(vfs_path, drive)).await?;
+        if self.limits.contains_key(drive) {
+            self.usage.insert(drive.to_string(), usage);
+        }
+        Ok(usage)
+    }
+
+    /// Check that growing a drive by `growth` bytes keeps it within its quota, if any, and
+    /// if so record the growth as used in the same step, so concurrent actions can't both
+    /// pass the check. The reservation is returned to the drive if dropped uncommitted.
+    async fn reserve(
+        &self,
+        drive: &str,
+        growth: i64,
+        vfs_path: &PathBuf,
+    ) -> Result<Option<QuotaReservation>, VfsError> {
+        let Some(quota) = self.limits.get(drive).map(|quota| *quota) else {
+            return Ok(None);
+        };
+        if growth <= 0 {
+            return Ok(None);
+        }
+        let counted = self.usage(drive, vfs_path).await?;
+        // the entry holds the drive's usage locked between the check and the record
+        let mut usage = self.usage.entry(drive.to_string()).or_insert(counted);
+        if usage.saturating_add(growth as u64) > quota {
+            return Err(VfsError::QuotaExceeded {
+                drive: drive.to_string(),
+                quota,
+            });
+        }
+        *usage += growth as u64;
+        Ok(Some(QuotaReservation {
+            usage: self.usage.clone(),
+            drive: drive.to_string(),
+            bytes: growth as u64,
+            committed: false,
+        }))
+    }
+
+    fn record(&self, drive: &str, growth: i64) {
+        if let Some(mut usage) = self.usage.get_mut(drive) {
+            *usage = usage.saturating_add_signed(growth);
+        }
+    }
+
+    /// forget the usage of a drive, e.g. after a removal, so it is counted again when next needed
+    fn invalidate(&self, drive: &str) {
+        self.usage.remove(drive);
+    }
+}
+
+/// Bytes reserved from a drive's quota for an action, given back if the action fails.
+struct QuotaReservation {
+    usage: Arc<DashMap<String, u64>>,
+    drive: String,
+    bytes: u64,
+    committed: bool,
+}
+
+impl QuotaReservation {
+    fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        if let Some(mut usage) = self.usage.get_mut(&self.drive) {
+            *usage = usage.saturating_sub(self.bytes);
+        }
+    }
+}
+
+/// The paths of a drive's files and directories, and the words of its text files.
+#[derive(Default)]
+struct SearchIndex {
+    /// whether each path is a directory
+    paths: BTreeMap<String, bool>,
+    /// the paths of the text files containing each word
+    words: HashMap<String, HashSet<String>>,
+    /// the words of each text file, to remove it from `words`
+    file_words: HashMap<String, HashSet<String>>,
+}
+
+impl SearchIndex {
+    fn insert(&mut self, path: String, is_dir: bool, words: Option<HashSet<String>>) {
+        if let Some(words) = words {
+            for word in &words {
+                self.words
+                    .entry(word.clone())
+                    .or_default()
+                    .insert(path.clone());
+            }
+            self.file_words.insert(path.clone(), words);
+        }
+        self.paths.insert(path, is_dir);
+    }
+
+    fn remove(&mut self, path: &str) {
+        self.paths.remove(path);
+        for word in self.file_words.remove(path).unwrap_or_default() {
+            if let Some(paths) = self.words.get_mut(&word) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    fn search(&self, query: &str, full_text: bool) -> Vec<DirEntry> {
+        let query = query.to_lowercase();
+        let mut matches: HashSet<&String> = self
+            .paths
+            .keys()
+            .filter(|path| {
+                let name = path.rsplit('/').next().unwrap_or(path);
+                name.to_lowercase().contains(&query)
+            })
+            .collect();
+        if full_text {
+            let mut text_matches: Option<HashSet<&String>> = None;
+            for word in words(&query) {
+                let paths: HashSet<&String> = self
+                    .words
+                    .get(&word)
+                    .map(|paths| paths.iter().collect())
+                    .unwrap_or_default();
+                text_matches = Some(match text_matches {
+                    Some(text_matches) => text_matches.intersection(&paths).copied().collect(),
+                    None => paths,
+                });
+            }
+            matches.extend(text_matches.unwrap_or_default());
+        }
+        let mut matches: Vec<&String> = matches.into_iter().collect();
+        matches.sort();
+        matches
+            .into_iter()
+            .take(MAX_SEARCH_RESULTS)
+            .map(|path| DirEntry {
+                path: path.clone(),
+                file_type: match self.paths.get(path) {
+                    Some(true) => FileType::Directory,
+                    _ => FileType::File,
+                },
+            })
+            .collect()
+    }
+}
+
+/// The distinct words of some text, lowercased, for full-text search.
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| (2..=64).contains(&word.len()))
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// A set of drives, persisted to disk: those whose files are stored compressed, or encrypted.
+#[derive(Clone)]
+struct DriveSet {
+    path: Arc<PathBuf>,
+    drives: Arc<DashSet<String>>,
+}
+
+impl DriveSet {
+    async fn load(path: PathBuf) -> Self {
+        let drives: HashSet<String> = match fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+            Err(_) => HashSet::new(),
+        };
+        Self {
+            path: Arc::new(path),
+            drives: Arc::new(drives.into_iter().collect()),
+        }
+    }
+
+    fn contains(&self, drive: &str) -> bool {
+        self.drives.contains(drive)
+    }
+
+    async fn set(&self, drive: &str, contained: bool) -> Result<(), VfsError> {
+        if contained {
+            self.drives.insert(drive.to_string());
+        } else {
+            self.drives.remove(drive);
+        }
+        let drives: HashSet<String> = self.drives.iter().map(|drive| drive.clone()).collect();
+        fs::write(&*self.path, serde_json::to_string(&drives).unwrap()).await?;
+        Ok(())
+    }
+}
+
 /// Handles individual VFS requests.
 ///
 /// This function processes various VFS actions such as file operations, directory listings, etc.
@@ -338,6 +1039,60 @@ async fn handle_request(
         }
     }
 
+    // special case for root getting the usage of every drive.
+    if request.action == VfsAction::DriveUsage && request.path == "/" {
+        if !read_capability("", "", true, our_node, &km.source, send_to_caps_oracle).await {
+            return Err(VfsError::NoReadCap);
+        }
+        let usage = all_drive_usage(files, vfs_path).await?;
+
+        KernelMessage::builder()
+            .id(km.id)
+            .source((our_node, VFS_PROCESS_ID.clone()))
+            .target(km.source)
+            .message(Message::Response((
+                Response {
+                    inherit: false,
+                    body: serde_json::to_vec(&VfsResponse::DriveUsage(usage)).unwrap(),
+                    metadata,
+                    capabilities: vec![],
+                },
+                None,
+            )))
+            .build()
+            .unwrap()
+            .send(&files.send_to_loop)
+            .await;
+        return Ok(());
+    }
+
+    // special case for root getting the storage used by every package.
+    if request.action == VfsAction::StorageReport && request.path == "/" {
+        if !read_capability("", "", true, our_node, &km.source, send_to_caps_oracle).await {
+            return Err(VfsError::NoReadCap);
+        }
+        let report = storage_report(files, vfs_path).await?;
+
+        KernelMessage::builder()
+            .id(km.id)
+            .source((our_node, VFS_PROCESS_ID.clone()))
+            .target(km.source)
+            .message(Message::Response((
+                Response {
+                    inherit: false,
+                    body: serde_json::to_vec(&VfsResponse::StorageReport(report)).unwrap(),
+                    metadata,
+                    capabilities: vec![],
+                },
+                None,
+            )))
+            .build()
+            .unwrap()
+            .send(&files.send_to_loop)
+            .await;
+        return Ok(());
+    }
+
     // current prepend to filepaths needs to be: /package_id/drive/path
     let (package_id, drive, rest) = parse_package_and_drive(&request.path, &vfs_path)?;
     // must have prepended `/` here or else it messes up caps downstream, e.g. in run-tests
@@ -351,6 +1106,7 @@ async fn handle_request(
             &send_to_caps_oracle,
             &action,
             &drive,
+            &rest,
             &package_id,
             vfs_path,
         )
@@ -360,10 +1116,144 @@ async fn handle_request(
     let base_drive = join_paths_safely(&vfs_path, &drive);
     let path = join_paths_safely(&base_drive, &rest);
 
+    // the changes this action will make, to be sent to watchers once it succeeds
+    let changes = if files.watchers.is_empty() {
+        vec![]
+    } else {
+        get_changes(&action, &request.path, &path, vfs_path).await
+    };
+
     #[cfg(target_os = "windows")]
     let (path, internal_path) = (internal_path_to_external(&path), path);
 
+    // files on compressed drives are inflated before any access at a position within them,
+    // unless the drive is also encrypted, whose files are only ever accessed whole
+    let compressed = files.compressed_drives.contains(&drive);
+    let encrypted = files.encrypted_drives.contains(&drive);
+    if compressed
+        && !encrypted
+        && matches!(
+            action,
+            VfsAction::OpenFile { .. }
+                | VfsAction::WriteAll
+                | VfsAction::WriteAt { .. }
+                | VfsAction::Append
+                | VfsAction::ReadToEnd
+                | VfsAction::ReadExact { .. }
+                | VfsAction::ReadAt { .. }
+                | VfsAction::ReadToString
+                | VfsAction::Seek(_)
+                | VfsAction::SetLen(_)
+                | VfsAction::Hash
+        )
+    {
+        files.inflate(&path).await?;
+    }
+
+    // enforce the quota of the drive this action grows, if it has one, reserving the
+    // growth so that concurrent actions on the drive can't together exceed it
+    let (quota_growth, reservation) = if files.quotas.limits.is_empty() {
+        (None, None)
+    } else {
+        let blob = km.lazy_load_blob.as_ref().map(|blob| &blob.bytes[..]);
+        let growth = get_growth(&action, &drive, &path, vfs_path, blob).await?;
+        let reservation = match growth {
+            Some((ref growth_drive, growth)) => {
+                files.quotas.reserve(growth_drive, growth, vfs_path).await?
+            }
+            None => None,
+        };
+        (growth, reservation)
+    };
+    // actions whose effect on usage is not known exactly, so usage must be counted again
+    let invalidates_usage = compressed
+        || encrypted
+        || matches!(
+            action,
+            VfsAction::CreateFile
+                | VfsAction::RemoveFile
+                | VfsAction::RemoveDir
+                | VfsAction::RemoveDirAll
+                | VfsAction::Rename { .. }
+                | VfsAction::CopyFile { .. }
+                | VfsAction::CopyDir { .. }
+                | VfsAction::MoveDir { .. }
+                | VfsAction::AddZip
+                | VfsAction::SetCompression(_)
+                | VfsAction::SetEncryption(_)
+                | VfsAction::Snapshot
+                | VfsAction::RestoreSnapshot(_)
+                | VfsAction::RemoveSnapshot(_)
+        );
+
+    let new_drive = match &action {
+        VfsAction::Rename { new_path }
+        | VfsAction::CopyFile { new_path }
+        | VfsAction::CopyDir { new_path, .. }
+        | VfsAction::MoveDir { new_path } => Some(drive_of(new_path, vfs_path)?),
+        _ => None,
+    };
+    // files moved or copied to a drive that stores files differently are conformed to it
+    let conform_new_path = new_drive.as_ref().and_then(|new_drive| {
+        let new_compressed = files.compressed_drives.contains(new_drive);
+        let new_encrypted = files.encrypted_drives.contains(new_drive);
+        ((compressed && !new_compressed) || encrypted != new_encrypted)
+            .then_some((new_compressed, new_encrypted))
+    });
+
+    // actions that change a single file are reindexed for search by that file,
+    // while those that change a tree drop the index, to be rebuilt when next searched
+    let reindexes_file = matches!(
+        action,
+        VfsAction::CreateFile
+            | VfsAction::OpenFile { create: true }
+            | VfsAction::Write
+            | VfsAction::WriteAtomic
+            | VfsAction::WriteAll
+            | VfsAction::WriteAt { .. }
+            | VfsAction::Append
+            | VfsAction::SetLen(_)
+            | VfsAction::RemoveFile
+    );
+    let drops_index = matches!(
+        action,
+        VfsAction::CreateDir
+            | VfsAction::CreateDirAll
+            | VfsAction::RemoveDir
+            | VfsAction::RemoveDirAll
+            | VfsAction::Rename { .. }
+            | VfsAction::CopyFile { .. }
+            | VfsAction::CopyDir { .. }
+            | VfsAction::MoveDir { .. }
+            | VfsAction::AddZip
+            | VfsAction::RestoreSnapshot(_)
+    );
+
     let (response_body, bytes) = match action {
+        action
+            if encrypted
+                && matches!(
+                    action,
+                    VfsAction::Write
+                        | VfsAction::WriteAll
+                        | VfsAction::WriteAt { .. }
+                        | VfsAction::Append
+                        | VfsAction::Read
+                        | VfsAction::ReadToEnd
+                        | VfsAction::ReadExact { .. }
+                        | VfsAction::ReadAt { .. }
+                        | VfsAction::ReadToString
+                        | VfsAction::Seek(_)
+                        | VfsAction::SetLen(_)
+                        | VfsAction::Hash
+                        | VfsAction::Len
+                        | VfsAction::Metadata
+                ) =>
+        {
+            files
+                .encrypted_action(action, &path, compressed, km.lazy_load_blob)
+                .await?
+        }
         VfsAction::CreateDrive => {
             #[cfg(target_os = "windows")]
             let base_drive = internal_path_to_external(&base_drive);
@@ -406,11 +1296,44 @@ async fn handle_request(
             file.write_all(&blob.bytes).await?;
             (VfsResponse::Ok, None)
         }
+        VfsAction::WriteAt { offset } => {
+            let Some(blob) = km.lazy_load_blob else {
+                return Err(VfsError::NoBlob);
+            };
+            let file = files.open_file(&path, false, false).await?;
+            write_at(&*file.lock().await, offset, blob.bytes).await?;
+            (VfsResponse::Ok, None)
+        }
         VfsAction::Write => {
             let Some(blob) = km.lazy_load_blob else {
                 return Err(VfsError::NoBlob);
             };
-            fs::write(&path, &blob.bytes).await?;
+            if compressed {
+                fs::write(&path, compress(&blob.bytes)?).await?;
+            } else {
+                fs::write(&path, &blob.bytes).await?;
+            }
+            (VfsResponse::Ok, None)
+        }
+        VfsAction::WriteAtomic => {
+            let Some(blob) = km.lazy_load_blob else {
+                return Err(VfsError::NoBlob);
+            };
+            let contents = files.encode(&blob.bytes, compressed, encrypted)?;
+            let temp_path = staging_path(&path, "writing");
+            let result: Result<(), std::io::Error> = async {
+                let mut file = fs::File::create(&temp_path).await?;
+                file.write_all(&contents).await?;
+                file.sync_all().await?;
+                fs::rename(&temp_path, &path).await
+            }
+            .await;
+            if let Err(e) = result {
+                let _ = fs::remove_file(&temp_path).await;
+                return Err(e.into());
+            }
+            // any open handle is to the file that was replaced
+            files.remove_file(&path).await?;
             (VfsResponse::Ok, None)
         }
         VfsAction::Append => {
@@ -431,6 +1354,11 @@ async fn handle_request(
         }
         VfsAction::Read => {
             let contents = fs::read(&path).await?;
+            let contents = if compressed {
+                decompress(contents)?
+            } else {
+                contents
+            };
             (VfsResponse::Read, Some(contents))
         }
         VfsAction::ReadToEnd => {
@@ -447,6 +1375,11 @@ async fn handle_request(
             file.read_exact(&mut contents).await?;
             (VfsResponse::Read, Some(contents))
         }
+        VfsAction::ReadAt { offset, length } => {
+            let file = files.open_file(&path, false, false).await?;
+            let contents = read_at(&*file.lock().await, offset, length).await?;
+            (VfsResponse::Read, Some(contents))
+        }
         VfsAction::ReadDir => {
             let mut dir = fs::read_dir(&path).await?;
             let mut entries = Vec::new();
@@ -508,24 +1441,51 @@ async fn handle_request(
             (VfsResponse::Ok, None)
         }
         VfsAction::RemoveDirAll => {
-            fs::remove_dir_all(&path).await?;
+            remove_dir_all(&path).await?;
             (VfsResponse::Ok, None)
         }
         VfsAction::Rename { new_path } => {
             let new_path = join_paths_safely(vfs_path, &new_path);
-            fs::rename(&path, new_path).await?;
+            fs::rename(&path, &new_path).await?;
+            if let Some((compressed, encrypted)) = conform_new_path {
+                files.conform(&new_path, compressed, encrypted).await?;
+            }
             (VfsResponse::Ok, None)
         }
         VfsAction::CopyFile { new_path } => {
             let new_path = join_paths_safely(vfs_path, &new_path);
-            fs::copy(&path, new_path).await?;
+            fs::copy(&path, &new_path).await?;
+            if let Some((compressed, encrypted)) = conform_new_path {
+                files.conform(&new_path, compressed, encrypted).await?;
+            }
+            (VfsResponse::Ok, None)
+        }
+        VfsAction::CopyDir { new_path, progress } => {
+            let progress = progress.then(|| (km.source.clone(), request.path.clone()));
+            let new_path = join_paths_safely(vfs_path, &new_path);
+            files.copy_dir(&path, &new_path, progress).await?;
+            if let Some((compressed, encrypted)) = conform_new_path {
+                files.conform_dir(&new_path, compressed, encrypted).await?;
+            }
+            (VfsResponse::Ok, None)
+        }
+        VfsAction::MoveDir { new_path } => {
+            let new_path = join_paths_safely(vfs_path, &new_path);
+            move_dir(&path, &new_path).await?;
+            if let Some((compressed, encrypted)) = conform_new_path {
+                files.conform_dir(&new_path, compressed, encrypted).await?;
+            }
             (VfsResponse::Ok, None)
         }
         VfsAction::Metadata => {
             let metadata = fs::metadata(&path).await?;
             let file_type = get_file_type(&metadata);
+            let len = match compressed && metadata.is_file() {
+                true => read_compressed_len(&path).await,
+                false => None,
+            };
             let meta = FileMetadata {
-                len: metadata.len(),
+                len: len.unwrap_or(metadata.len()),
                 file_type,
             };
             (VfsResponse::Metadata(meta), None)
@@ -533,7 +1493,14 @@ async fn handle_request(
         VfsAction::Len => {
             let file = files.open_file(&path, false, false).await?;
             let file = file.lock().await;
-            let len = file.metadata().await?.len();
+            let len = match compressed {
+                true => read_compressed_len(&path).await,
+                false => None,
+            };
+            let len = match len {
+                Some(len) => len,
+                None => file.metadata().await?.len(),
+            };
             (VfsResponse::Len(len), None)
         }
         VfsAction::SetLen(len) => {
@@ -559,6 +1526,126 @@ async fn handle_request(
             let hash: [u8; 32] = hasher.finalize().into();
             (VfsResponse::Hash(hash), None)
         }
+        VfsAction::DriveUsage => (
+            VfsResponse::DriveUsage(vec![DriveUsage {
+                bytes: files.quotas.usage(&drive, vfs_path).await?,
+                quota: files.quotas.limits.get(&drive).map(|quota| *quota),
+                drive: drive.clone(),
+            }]),
+            None,
+        ),
+        VfsAction::SetQuota(quota) => {
+            files.quotas.set(&drive, quota).await?;
+            (VfsResponse::Ok, None)
+        }
+        VfsAction::SetCompression(enabled) => {
+            files.compressed_drives.set(&drive, enabled).await?;
+            // files already compressed are stored uncompressed again
+            if !enabled {
+                files.conform_dir(&path, false, encrypted).await?;
+            }
+            (VfsResponse::Ok, None)
+        }
+        VfsAction::SetEncryption(enabled) => {
+            files.encrypted_drives.set(&drive, enabled).await?;
+            files.conform_dir(&path, compressed, enabled).await?;
+            (VfsResponse::Ok, None)
+        }
+        VfsAction::Snapshot => {
+            #[cfg(target_os = "windows")]
+            let base_drive = internal_path_to_external(&base_drive);
+
+            let snapshots = snapshots_path(vfs_path, &drive);
+            let mut id = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            while fs::try_exists(snapshots.join(id.to_string())).await? {
+                id += 1;
+            }
+            let snapshot = snapshots.join(id.to_string());
+            files.copy_dir(&base_drive, &snapshot, None).await?;
+            let bytes = dir_size(&snapshot).await?;
+            // drop the oldest snapshots beyond those kept
+            let mut ids = snapshot_ids(&snapshots).await?;
+            ids.sort_unstable();
+            for old in ids.iter().take(ids.len().saturating_sub(MAX_SNAPSHOTS)) {
+                fs::remove_dir_all(snapshots.join(old.to_string())).await?;
+            }
+            (VfsResponse::Snapshot(VfsSnapshot { id, bytes }), None)
+        }
+        VfsAction::ListSnapshots => {
+            let snapshots_path = snapshots_path(vfs_path, &drive);
+            let mut snapshots = Vec::new();
+            for id in snapshot_ids(&snapshots_path).await? {
+                let bytes = dir_size(&snapshots_path.join(id.to_string())).await?;
+                snapshots.push(VfsSnapshot { id, bytes });
+            }
+            snapshots.sort_by_key(|snapshot| snapshot.id);
+            (VfsResponse::Snapshots(snapshots), None)
+        }
+        VfsAction::RestoreSnapshot(id) => {
+            #[cfg(target_os = "windows")]
+            let base_drive = internal_path_to_external(&base_drive);
+
+            let snapshot = snapshots_path(vfs_path, &drive).join(id.to_string());
+            if !fs::try_exists(&snapshot).await? {
+                return Err(VfsError::NoSnapshot { drive, id });
+            }
+            // copy the snapshot beside the drive, then swap it in
+            let restoring = staging_path(&base_drive, "restoring");
+            files.copy_dir(&snapshot, &restoring, None).await?;
+            files
+                .open_files
+                .retain(|path, _| !path.starts_with(&base_drive));
+            move_dir(&restoring, &base_drive).await?;
+            (VfsResponse::Ok, None)
+        }
+        VfsAction::RemoveSnapshot(id) => {
+            let snapshot = snapshots_path(vfs_path, &drive).join(id.to_string());
+            if !fs::try_exists(&snapshot).await? {
+                return Err(VfsError::NoSnapshot { drive, id });
+            }
+            fs::remove_dir_all(&snapshot).await?;
+            (VfsResponse::Ok, None)
+        }
+        VfsAction::Search { query, full_text } => {
+            #[cfg(target_os = "windows")]
+            let base_drive = internal_path_to_external(&base_drive);
+
+            let results = files.search(&drive, &base_drive, &query, full_text).await?;
+            (VfsResponse::Search(results), None)
+        }
+        VfsAction::StorageReport => {
+            // only reported for every package at once, at `/`
+            return Err(VfsError::ParseError {
+                error: "storage is reported at `/`".to_string(),
+                path: request.path,
+            });
+        }
+        VfsAction::Watch => {
+            #[cfg(target_os = "windows")]
+            let path = internal_path.clone();
+
+            files
+                .watchers
+                .entry(path)
+                .or_default()
+                .insert(km.source.clone());
+            (VfsResponse::Ok, None)
+        }
+        VfsAction::Unwatch => {
+            #[cfg(target_os = "windows")]
+            let path = internal_path.clone();
+
+            if let Some(mut watchers) = files.watchers.get_mut(&path) {
+                watchers.remove(&km.source);
+            }
+            files
+                .watchers
+                .remove_if(&path, |_, watchers| watchers.is_empty());
+            (VfsResponse::Ok, None)
+        }
         VfsAction::AddZip => {
             let Some(blob) = km.lazy_load_blob else {
                 return Err(VfsError::NoBlob);
@@ -587,13 +1674,23 @@ async fn handle_request(
                     let is_dir = file.is_dir();
                     let mut file_contents = Vec::new();
                     if is_file {
-                        file.read_to_end(&mut file_contents)?;
+                        // entries may inflate past the size they declare, which the quota counted
+                        let size = file.size();
+                        (&mut file)
+                            .take(size.saturating_add(1))
+                            .read_to_end(&mut file_contents)?;
+                        if file_contents.len() as u64 > size {
+                            return Err(VfsError::UnzipError);
+                        }
                     };
                     let local_path = path.join(file.name());
                     (is_file, is_dir, local_path, file_contents)
                 };
                 if is_file {
-                    fs::write(&local_path, &file_contents).await?;
+                    // stored the way the drive stores every other file
+                    files
+                        .write_encoded(&local_path, &file_contents, compressed, encrypted)
+                        .await?;
                 } else if is_dir {
                     fs::create_dir_all(&local_path).await?;
                 } else {
@@ -604,6 +1701,33 @@ async fn handle_request(
         }
     };
 
+    if let Some(reservation) = reservation {
+        reservation.commit();
+    }
+    if invalidates_usage {
+        files.quotas.invalidate(&drive);
+        if let Some((ref growth_drive, _)) = quota_growth {
+            files.quotas.invalidate(growth_drive);
+        }
+    } else if let Some((growth_drive, growth)) = quota_growth {
+        // growth was recorded when it was reserved
+        if growth < 0 {
+            files.quotas.record(&growth_drive, growth);
+        }
+    }
+
+    if reindexes_file {
+        let indexed_path = format!("{drive}/{}", rest.display());
+        files.reindex_file(&drive, &indexed_path, &path).await;
+    } else if drops_index {
+        files.search_indices.remove(&drive);
+        if let Some(ref new_drive) = new_drive {
+            files.search_indices.remove(new_drive);
+        }
+    }
+
+    files.notify_watchers(changes).await;
+
     if let Some(target) = km.rsvp.or_else(|| expects_response.map(|_| km.source)) {
         KernelMessage::builder()
             .id(km.id)
@@ -631,6 +1755,500 @@ async fn handle_request(
     Ok(())
 }
 
+/// The changes an action will make, as the internal path changed and the change to
+/// send to its watchers. Whether a write creates or modifies depends on whether the
+/// file exists beforehand, so this must be called before the action is performed.
+async fn get_changes(
+    action: &VfsAction,
+    request_path: &str,
+    path: &Path,
+    vfs_path: &PathBuf,
+) -> Vec<(PathBuf, VfsChange)> {
+    let change = |path: &Path, vfs_path: &str, kind| {
+        (
+            path.to_path_buf(),
+            VfsChange {
+                path: vfs_path.to_string(),
+                kind,
+            },
+        )
+    };
+    let create_or_modify = |path: PathBuf| async move {
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            VfsChangeKind::Modify
+        } else {
+            VfsChangeKind::Create
+        }
+    };
+    match action {
+        VfsAction::CreateDrive
+        | VfsAction::CreateDir
+        | VfsAction::CreateDirAll
+        | VfsAction::CreateFile
+        | VfsAction::AddZip => vec![change(path, request_path, VfsChangeKind::Create)],
+        VfsAction::OpenFile { create: true } | VfsAction::Write | VfsAction::WriteAtomic => {
+            let kind = create_or_modify(path.to_path_buf()).await;
+            vec![change(path, request_path, kind)]
+        }
+        VfsAction::WriteAll
+        | VfsAction::WriteAt { .. }
+        | VfsAction::Append
+        | VfsAction::SetLen(_) => {
+            vec![change(path, request_path, VfsChangeKind::Modify)]
+        }
+        VfsAction::RemoveFile | VfsAction::RemoveDir | VfsAction::RemoveDirAll => {
+            vec![change(path, request_path, VfsChangeKind::Delete)]
+        }
+        VfsAction::Rename { new_path } | VfsAction::MoveDir { new_path } => vec![
+            change(path, request_path, VfsChangeKind::Delete),
+            change(
+                &join_paths_safely(vfs_path, new_path),
+                new_path,
+                VfsChangeKind::Create,
+            ),
+        ],
+        VfsAction::CopyFile { new_path } => {
+            let new_internal_path = join_paths_safely(vfs_path, new_path);
+            let kind = create_or_modify(new_internal_path.clone()).await;
+            vec![change(&new_internal_path, new_path, kind)]
+        }
+        VfsAction::CopyDir { new_path, .. } => vec![change(
+            &join_paths_safely(vfs_path, new_path),
+            new_path,
+            VfsChangeKind::Create,
+        )],
+        VfsAction::RestoreSnapshot(_) => vec![change(path, request_path, VfsChangeKind::Modify)],
+        _ => vec![],
+    }
+}
+
+/// The drive an action grows, and by how many bytes (negative if it shrinks),
+/// if it is one that writes to a drive. Must be called before the action is performed.
+async fn get_growth(
+    action: &VfsAction,
+    drive: &str,
+    path: &Path,
+    vfs_path: &PathBuf,
+    blob: Option<&[u8]>,
+) -> Result<Option<(String, i64)>, VfsError> {
+    let blob_len = blob.map(|blob| blob.len() as i64).unwrap_or_default();
+    let len = |path: PathBuf| async move {
+        fs::metadata(&path)
+            .await
+            .map(|metadata| metadata.len() as i64)
+            .unwrap_or_default()
+    };
+    let growth = match action {
+        VfsAction::Write | VfsAction::WriteAtomic => blob_len - len(path.to_path_buf()).await,
+        VfsAction::WriteAll | VfsAction::Append => blob_len,
+        // a zip grows its drive by the size of its contents once extracted
+        VfsAction::AddZip => match blob {
+            Some(blob) => zip_len(blob)? as i64,
+            None => 0,
+        },
+        VfsAction::WriteAt { offset } => {
+            (*offset as i64 + blob_len - len(path.to_path_buf()).await).max(0)
+        }
+        VfsAction::SetLen(new_len) => *new_len as i64 - len(path.to_path_buf()).await,
+        // a snapshot copies the whole drive, which is at `path`
+        VfsAction::Snapshot => dir_size(path).await? as i64,
+        VfsAction::CopyFile { new_path } | VfsAction::Rename { new_path } => {
+            let (new_package_id, new_drive, _rest) = parse_package_and_drive(new_path, vfs_path)?;
+            let new_drive = format!("/{new_package_id}/{new_drive}");
+            if new_drive == drive && matches!(action, VfsAction::Rename { .. }) {
+                return Ok(None);
+            }
+            let growth =
+                len(path.to_path_buf()).await - len(join_paths_safely(vfs_path, new_path)).await;
+            return Ok(Some((new_drive, growth)));
+        }
+        VfsAction::CopyDir { new_path, .. } | VfsAction::MoveDir { new_path } => {
+            let (new_package_id, new_drive, _rest) = parse_package_and_drive(new_path, vfs_path)?;
+            let new_drive = format!("/{new_package_id}/{new_drive}");
+            if new_drive == drive && matches!(action, VfsAction::MoveDir { .. }) {
+                return Ok(None);
+            }
+            let growth = dir_size(path).await? as i64
+                - dir_size(&join_paths_safely(vfs_path, new_path)).await? as i64;
+            return Ok(Some((new_drive, growth)));
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some((drive.to_string(), growth)))
+}
+
+/// The total uncompressed size of the files in a zip, as its entries declare them.
+fn zip_len(bytes: &[u8]) -> Result<u64, VfsError> {
+    let mut zip =
+        zip::ZipArchive::new(std::io::Cursor::new(bytes)).map_err(|_| VfsError::UnzipError)?;
+    let mut len: u64 = 0;
+    for i in 0..zip.len() {
+        let file = zip.by_index_raw(i).map_err(|_| VfsError::UnzipError)?;
+        len = len.saturating_add(file.size());
+    }
+    Ok(len)
+}
+
+/// Whether a file name is that of a staging path, which is not part of its drive.
+fn is_staging(name: &str) -> bool {
+    name.starts_with('.') && name.contains(".vfs-")
+}
+
+/// A hidden path beside `path`, where a tree is built or torn down out of sight
+/// before being renamed into or out of place.
+fn staging_path(path: &Path, purpose: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.vfs-{purpose}-{}", rand::random::<u32>()))
+}
+
+/// Move the tree at `from` to `to`, replacing any tree already at `to`. The replaced tree
+/// is renamed aside first and restored if the move fails, so `to` is never left partial.
+async fn move_dir(from: &Path, to: &Path) -> Result<(), VfsError> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    if !fs::try_exists(to).await? {
+        fs::rename(from, to).await?;
+        return Ok(());
+    }
+    let replaced = staging_path(to, "replaced");
+    fs::rename(to, &replaced).await?;
+    if let Err(e) = fs::rename(from, to).await {
+        fs::rename(&replaced, to).await?;
+        return Err(e.into());
+    }
+    fs::remove_dir_all(&replaced).await?;
+    Ok(())
+}
+
+/// Remove the tree at `path`, renaming it aside first so that it disappears in one step.
+async fn remove_dir_all(path: &Path) -> Result<(), VfsError> {
+    let removing = staging_path(path, "removing");
+    fs::rename(path, &removing).await?;
+    fs::remove_dir_all(&removing).await?;
+    Ok(())
+}
+
+/// Read up to `length` bytes of a file at `offset`, without moving the cursor
+/// of its shared handle, which other requests for the file read and write from.
+async fn read_at(file: &fs::File, offset: u64, length: u64) -> Result<Vec<u8>, VfsError> {
+    let file = file.try_clone().await?.into_std().await;
+    tokio::task::spawn_blocking(move || -> Result<Vec<u8>, VfsError> {
+        // buffer is only as large as the bytes that can be read
+        let available = file.metadata()?.len().saturating_sub(offset);
+        let mut contents = vec![0; length.min(available) as usize];
+        let mut read = 0;
+        while read < contents.len() {
+            let position = offset + read as u64;
+            #[cfg(unix)]
+            let n = std::os::unix::fs::FileExt::read_at(&file, &mut contents[read..], position)?;
+            #[cfg(target_os = "windows")]
+            let n =
+                std::os::windows::fs::FileExt::seek_read(&file, &mut contents[read..], position)?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        contents.truncate(read);
+        Ok(contents)
+    })
+    .await
+    .map_err(|e| VfsError::IOError(e.to_string()))?
+}
+
+/// Write all of `bytes` to a file at `offset`, without moving the cursor of its shared handle.
+async fn write_at(file: &fs::File, offset: u64, bytes: Vec<u8>) -> Result<(), VfsError> {
+    if offset.checked_add(bytes.len() as u64).is_none() {
+        return Err(VfsError::IOError("write past the largest offset".into()));
+    }
+    let file = file.try_clone().await?.into_std().await;
+    tokio::task::spawn_blocking(move || -> Result<(), VfsError> {
+        let mut written = 0;
+        while written < bytes.len() {
+            let position = offset + written as u64;
+            #[cfg(unix)]
+            let n = std::os::unix::fs::FileExt::write_at(&file, &bytes[written..], position)?;
+            #[cfg(target_os = "windows")]
+            let n = std::os::windows::fs::FileExt::seek_write(&file, &bytes[written..], position)?;
+            if n == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into());
+            }
+            written += n;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| VfsError::IOError(e.to_string()))?
+}
+
+fn compress(bytes: &[u8]) -> Result<Vec<u8>, VfsError> {
+    if bytes.len() as u64 > MAX_DECOMPRESSED_LEN {
+        return Err(VfsError::IOError(format!(
+            "files on compressed drives are at most {MAX_DECOMPRESSED_LEN} bytes"
+        )));
+    }
+    let mut compressed = Vec::with_capacity(COMPRESSED_HEADER_LEN + bytes.len() / 2);
+    compressed.extend_from_slice(&SKIPPABLE_FRAME_MAGIC);
+    compressed.extend_from_slice(&(COMPRESSED_HEADER_LEN as u32 - 8).to_le_bytes());
+    compressed.extend_from_slice(COMPRESSED_TAG);
+    compressed.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    compressed.extend(zstd::bulk::compress(bytes, COMPRESSION_LEVEL)?);
+    Ok(compressed)
+}
+
+/// The uncompressed length of a file of `file_len` bytes starting with `header`,
+/// if it is a compressed file whose header claims a length it could decompress to.
+fn compressed_len_of(header: &[u8], file_len: u64) -> Option<u64> {
+    if header.len() < COMPRESSED_HEADER_LEN
+        || header[..4] != SKIPPABLE_FRAME_MAGIC
+        || &header[8..12] != COMPRESSED_TAG
+    {
+        return None;
+    }
+    let len = u64::from_le_bytes(header[12..20].try_into().unwrap());
+    let frames_len = file_len.saturating_sub(COMPRESSED_HEADER_LEN as u64);
+    (len <= MAX_DECOMPRESSED_LEN && len <= frames_len.saturating_mul(MAX_COMPRESSION_RATIO))
+        .then_some(len)
+}
+
+/// The uncompressed length of some contents, if they are those of a compressed file.
+fn compressed_len(contents: &[u8]) -> Option<u64> {
+    compressed_len_of(contents, contents.len() as u64)
+}
+
+/// Decompress the contents of a file, if they are compressed. The output grows only as
+/// far as the frames really inflate, and must come to exactly the length in the header.
+fn decompress(contents: Vec<u8>) -> Result<Vec<u8>, VfsError> {
+    let Some(len) = compressed_len(&contents) else {
+        return Ok(contents);
+    };
+    let mut decompressed = Vec::new();
+    zstd::stream::read::Decoder::with_buffer(&contents[COMPRESSED_HEADER_LEN..])?
+        .take(len + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() as u64 != len {
+        return Err(VfsError::IOError(
+            "compressed file does not match its header".into(),
+        ));
+    }
+    Ok(decompressed)
+}
+
+/// The uncompressed length of the file at `path`, if it is compressed.
+/// Errors are left for the action to surface, so are treated as uncompressed.
+async fn read_compressed_len(path: &Path) -> Option<u64> {
+    let mut file = fs::File::open(path).await.ok()?;
+    let mut header = [0; COMPRESSED_HEADER_LEN];
+    file.read_exact(&mut header).await.ok()?;
+    compressed_len_of(&header, file.metadata().await.ok()?.len())
+}
+
+/// The length of the plain contents of an encrypted file, if these are its contents.
+fn encrypted_len(contents: &[u8]) -> Option<u64> {
+    if contents.len() < ENCRYPTED_HEADER_LEN || &contents[..8] != ENCRYPTED_MAGIC {
+        return None;
+    }
+    Some(u64::from_le_bytes(contents[8..16].try_into().unwrap()))
+}
+
+/// The length of the plain contents of the file at `path`, once decrypted and decompressed.
+async fn read_plain_len(path: &Path) -> Result<u64, VfsError> {
+    let mut header = Vec::with_capacity(ENCRYPTED_HEADER_LEN);
+    fs::File::open(path)
+        .await?
+        .take(ENCRYPTED_HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .await?;
+    let file_len = fs::metadata(path).await?.len();
+    match encrypted_len(&header).or(compressed_len_of(&header, file_len)) {
+        Some(len) => Ok(len),
+        None => Ok(file_len),
+    }
+}
+
+/// The length a file on an encrypted drive would grow to, if it may grow that large.
+fn encrypted_file_len(len: Option<u64>) -> Result<u64, VfsError> {
+    match len {
+        Some(len) if len <= MAX_ENCRYPTED_LEN => Ok(len),
+        _ => Err(VfsError::IOError(format!(
+            "files on encrypted drives are at most {MAX_ENCRYPTED_LEN} bytes"
+        ))),
+    }
+}
+
+/// The cipher of encrypted drives, keyed from the keyfile's file key.
+fn file_cipher(file_key: &[u8]) -> Aes256Gcm {
+    let salt = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, b"kinode-vfs");
+    let mut key = [0u8; 32];
+    salt.extract(file_key)
+        .expand(&[b"encrypted-drives".as_slice()], ring::hkdf::HKDF_SHA256)
+        .unwrap()
+        .fill(&mut key)
+        .unwrap();
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+/// Where the snapshots of a drive are kept: beside the VFS, rather than in it,
+/// so that they are not themselves part of any drive.
+fn snapshots_path(vfs_path: &PathBuf, drive: &str) -> PathBuf {
+    let snapshots_path = join_paths_safely(&vfs_path.with_file_name("vfs_snapshots"), drive);
+    #[cfg(target_os = "windows")]
+    let snapshots_path = internal_path_to_external(&snapshots_path);
+    snapshots_path
+}
+
+/// The ids of the snapshots in a drive's snapshots directory, skipping any still being taken.
+async fn snapshot_ids(snapshots_path: &Path) -> Result<Vec<u64>, VfsError> {
+    let mut ids = Vec::new();
+    if !fs::try_exists(snapshots_path).await? {
+        return Ok(ids);
+    }
+    let mut entries = fs::read_dir(snapshots_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Ok(id) = entry.file_name().to_string_lossy().parse::<u64>() {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+/// The `/package_id/drive` of a request path.
+fn drive_of(path: &str, vfs_path: &PathBuf) -> Result<String, VfsError> {
+    let (package_id, drive, _rest) = parse_package_and_drive(path, vfs_path)?;
+    Ok(format!("/{package_id}/{drive}"))
+}
+
+/// Total bytes of the files in a directory and all its subdirectories.
+async fn dir_size(path: &Path) -> Result<u64, VfsError> {
+    let mut size = 0;
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+    Ok(size)
+}
+
+/// The bytes used by every drive, and their quotas.
+async fn all_drive_usage(files: &Files, vfs_path: &PathBuf) -> Result<Vec<DriveUsage>, VfsError> {
+    let mut usage = Vec::new();
+    let mut packages = fs::read_dir(&vfs_path).await?;
+    while let Some(package) = packages.next_entry().await? {
+        if !package.metadata().await?.is_dir() {
+            continue;
+        }
+        let mut drives = fs::read_dir(package.path()).await?;
+        while let Some(drive) = drives.next_entry().await? {
+            if !drive.metadata().await?.is_dir() {
+                continue;
+            }
+            let drive = format!(
+                "/{}/{}",
+                package.file_name().to_string_lossy(),
+                drive.file_name().to_string_lossy(),
+            );
+            usage.push(DriveUsage {
+                bytes: files.quotas.usage(&drive, vfs_path).await?,
+                quota: files.quotas.limits.get(&drive).map(|quota| *quota),
+                drive,
+            });
+        }
+    }
+    Ok(usage)
+}
+
+/// The bytes used by each package's drives, and by its sqlite and kv databases,
+/// which are kept beside the VFS, at `<home>/<module>/<package>/<db>`.
+async fn storage_report(
+    files: &Files,
+    vfs_path: &PathBuf,
+) -> Result<Vec<PackageStorage>, VfsError> {
+    let mut packages: BTreeMap<String, PackageStorage> = BTreeMap::new();
+    for usage in all_drive_usage(files, vfs_path).await? {
+        let package_id = usage
+            .drive
+            .split('/')
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+        package_storage(&mut packages, &package_id)
+            .drives
+            .push(usage);
+    }
+
+    let home_directory_path = vfs_path.parent().unwrap_or(vfs_path.as_path());
+    for module in ["sqlite", "kv"] {
+        let mut package_dirs = match fs::read_dir(home_directory_path.join(module)).await {
+            Ok(package_dirs) => package_dirs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(package_dir) = package_dirs.next_entry().await? {
+            if !package_dir.metadata().await?.is_dir() {
+                continue;
+            }
+            let package_id = package_dir.file_name().to_string_lossy().to_string();
+            let mut dbs = fs::read_dir(package_dir.path()).await?;
+            while let Some(db) = dbs.next_entry().await? {
+                if !db.metadata().await?.is_dir() {
+                    continue;
+                }
+                let usage = DbUsage {
+                    db: db.file_name().to_string_lossy().to_string(),
+                    bytes: dir_size(&db.path()).await?,
+                };
+                let package = package_storage(&mut packages, &package_id);
+                match module {
+                    "sqlite" => package.sqlite.push(usage),
+                    _ => package.kv.push(usage),
+                }
+            }
+        }
+    }
+
+    let mut report: Vec<PackageStorage> = packages.into_values().collect();
+    for package in report.iter_mut() {
+        package.drives.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+        package.sqlite.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+        package.kv.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+        package.bytes = package.drives.iter().map(|usage| usage.bytes).sum::<u64>()
+            + package.sqlite.iter().map(|usage| usage.bytes).sum::<u64>()
+            + package.kv.iter().map(|usage| usage.bytes).sum::<u64>();
+    }
+    report.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    Ok(report)
+}
+
+fn package_storage<'a>(
+    packages: &'a mut BTreeMap<String, PackageStorage>,
+    package_id: &str,
+) -> &'a mut PackageStorage {
+    packages
+        .entry(package_id.to_string())
+        .or_insert_with(|| PackageStorage {
+            package_id: package_id.to_string(),
+            bytes: 0,
+            drives: vec![],
+            sqlite: vec![],
+            kv: vec![],
+        })
+}
+
 fn parse_package_and_drive(
     path: &str,
     vfs_path: &PathBuf,
@@ -721,11 +2339,26 @@ async fn check_caps(
     send_to_caps_oracle: &CapMessageSender,
     action: &VfsAction,
     drive: &str,
+    rest: &Path,
     package_id: &PackageId,
     vfs_path: &PathBuf,
 ) -> Result<(), VfsError> {
     let src_package_id = PackageId::new(source.process.package(), source.process.publisher());
 
+    // actions on a whole drive need a capability on the whole drive,
+    // rather than one scoped to a path within it
+    let rest = match action {
+        VfsAction::SetCompression(_)
+        | VfsAction::SetEncryption(_)
+        | VfsAction::Snapshot
+        | VfsAction::ListSnapshots
+        | VfsAction::RestoreSnapshot(_)
+        | VfsAction::RemoveSnapshot(_)
+        | VfsAction::DriveUsage
+        | VfsAction::Search { .. } => Path::new(""),
+        _ => rest,
+    };
+
     // every action is valid if package has vfs root cap, but this should only be
     // checked for *after* non-root caps are checked, because 99% of the time,
     // package will have regular read/write cap regardless of root status.
@@ -736,19 +2369,27 @@ async fn check_caps(
         | VfsAction::OpenFile { .. }
         | VfsAction::CloseFile
         | VfsAction::Write
+        | VfsAction::WriteAtomic
         | VfsAction::WriteAll
+        | VfsAction::WriteAt { .. }
         | VfsAction::Append
         | VfsAction::SyncAll
         | VfsAction::RemoveFile
         | VfsAction::RemoveDir
         | VfsAction::RemoveDirAll
         | VfsAction::AddZip
-        | VfsAction::SetLen(_) => {
+        | VfsAction::SetLen(_)
+        | VfsAction::SetCompression(_)
+        | VfsAction::SetEncryption(_)
+        | VfsAction::Snapshot
+        | VfsAction::RestoreSnapshot(_)
+        | VfsAction::RemoveSnapshot(_) => {
             if &src_package_id == package_id {
                 return Ok(());
             }
             let has_cap =
-                read_capability("write", drive, false, our_node, source, send_to_caps_oracle).await;
+                has_drive_capability("write", drive, rest, our_node, source, send_to_caps_oracle)
+                    .await;
             if !has_cap {
                 // check for root cap
                 if read_capability("", "", true, our_node, source, send_to_caps_oracle).await {
@@ -761,17 +2402,24 @@ async fn check_caps(
         VfsAction::Read
         | VfsAction::ReadDir
         | VfsAction::ReadExact { .. }
+        | VfsAction::ReadAt { .. }
         | VfsAction::ReadToEnd
         | VfsAction::ReadToString
         | VfsAction::Seek(_)
         | VfsAction::Hash
         | VfsAction::Metadata
-        | VfsAction::Len => {
+        | VfsAction::Len
+        | VfsAction::Watch
+        | VfsAction::Unwatch
+        | VfsAction::DriveUsage
+        | VfsAction::ListSnapshots
+        | VfsAction::Search { .. } => {
             if &src_package_id == package_id {
                 return Ok(());
             }
             let has_cap =
-                read_capability("read", drive, false, our_node, source, send_to_caps_oracle).await;
+                has_drive_capability("read", drive, rest, our_node, source, send_to_caps_oracle)
+                    .await;
             if !has_cap {
                 // check for root cap
                 if read_capability("", "", true, our_node, source, send_to_caps_oracle).await {
@@ -781,9 +2429,13 @@ async fn check_caps(
             }
             Ok(())
         }
-        VfsAction::CopyFile { new_path } | VfsAction::Rename { new_path } => {
+        VfsAction::CopyFile { new_path }
+        | VfsAction::Rename { new_path }
+        | VfsAction::CopyDir { new_path, .. }
+        | VfsAction::MoveDir { new_path } => {
             // these have 2 paths to validate
-            let (new_package_id, new_drive, _rest) = parse_package_and_drive(new_path, &vfs_path)?;
+            let (new_package_id, new_drive, new_rest) =
+                parse_package_and_drive(new_path, &vfs_path)?;
 
             let new_drive = format!("/{new_package_id}/{new_drive}");
             // if both new and old path are within the package_id path, ok
@@ -792,15 +2444,9 @@ async fn check_caps(
             }
 
             // otherwise check write caps.
-            let has_cap = read_capability(
-                "write",
-                &drive,
-                false,
-                our_node,
-                source,
-                send_to_caps_oracle,
-            )
-            .await;
+            let has_cap =
+                has_drive_capability("write", &drive, rest, our_node, source, send_to_caps_oracle)
+                    .await;
             if !has_cap {
                 // check for root cap
                 if read_capability("", "", true, our_node, source, send_to_caps_oracle).await {
@@ -809,15 +2455,10 @@ async fn check_caps(
                 return Err(VfsError::NoWriteCap);
             }
 
-            // if they're within the same drive, no need for 2 caps checks
-            if new_drive == drive {
-                return Ok(());
-            }
-
-            let has_cap = read_capability(
+            let has_cap = has_drive_capability(
                 "write",
                 &new_drive,
-                false,
+                &new_rest,
                 our_node,
                 source,
                 send_to_caps_oracle,
@@ -832,6 +2473,12 @@ async fn check_caps(
             }
             Ok(())
         }
+        VfsAction::SetQuota(_) | VfsAction::StorageReport => {
+            if !read_capability("", "", true, our_node, source, send_to_caps_oracle).await {
+                return Err(VfsError::NoWriteCap);
+            }
+            Ok(())
+        }
         VfsAction::CreateDrive => {
             if &src_package_id != package_id {
                 // check for root cap
@@ -854,7 +2501,6 @@ async fn read_capability(
     source: &Address,
     send_to_caps_oracle: &CapMessageSender,
 ) -> bool {
-    let (send_cap_bool, recv_cap_bool) = tokio::sync::oneshot::channel();
     let cap = Capability::new(
         (our_node, VFS_PROCESS_ID.clone()),
         if root {
@@ -863,6 +2509,47 @@ async fn read_capability(
             format!("{{\"kind\": \"{kind}\", \"drive\": \"{drive}\"}}")
         },
     );
+    has_capability(cap, source, send_to_caps_oracle).await
+}
+
+/// Whether a process has a capability of `kind` on a drive, or on a path within the drive
+/// that `rest` is or is under: capabilities can be scoped to a path prefix, as in
+/// `{"kind": "read", "drive": "/package:publisher/data", "path": "/public"}`.
+async fn has_drive_capability(
+    kind: &str,
+    drive: &str,
+    rest: &Path,
+    our_node: &str,
+    source: &Address,
+    send_to_caps_oracle: &CapMessageSender,
+) -> bool {
+    if read_capability(kind, drive, false, our_node, source, send_to_caps_oracle).await {
+        return true;
+    }
+    let mut prefix = String::new();
+    for component in rest.components() {
+        let Component::Normal(component) = component else {
+            continue;
+        };
+        prefix.push('/');
+        prefix.push_str(&component.to_string_lossy());
+        let cap = Capability::new(
+            (our_node, VFS_PROCESS_ID.clone()),
+            serde_json::json!({ "kind": kind, "drive": drive, "path": prefix }).to_string(),
+        );
+        if has_capability(cap, source, send_to_caps_oracle).await {
+            return true;
+        }
+    }
+    false
+}
+
+async fn has_capability(
+    cap: Capability,
+    source: &Address,
+    send_to_caps_oracle: &CapMessageSender,
+) -> bool {
+    let (send_cap_bool, recv_cap_bool) = tokio::sync::oneshot::channel();
     if let Err(_) = send_to_caps_oracle
         .send(CapMessage::Has {
             on: source.process.clone(),
@@ -0,0 +1,536 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key,
+};
+use alloy::dyn_abi::TypedData;
+use alloy::network::eip2718::Encodable2718;
+use alloy::network::{EthereumWallet, TransactionBuilder};
+use alloy::rpc::types::eth::TransactionRequest;
+use alloy::signers::{local::PrivateKeySigner, Signature, Signer};
+use alloy_primitives::{Address as EthAddress, B256};
+use lib::types::core::{
+    Address, CapMessage, CapMessageSender, Capability, KernelMessage, Message, MessageReceiver,
+    MessageSender, PendingSignature, PrintSender, Printout, Response, WalletAction, WalletAddress,
+    WalletCapabilityKind, WalletCapabilityParams, WalletError, WalletResponse, WALLET_PROCESS_ID,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+/// how long a signing request waits for the user before failing
+const PENDING_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// most signing requests waiting at once, so that a process can't bury others' requests
+const MAX_PENDING: usize = 100;
+
+/// The key as kept in `.wallet`: a local key is encrypted with the keyfile's file key.
+#[derive(Serialize, Deserialize)]
+enum StoredKey {
+    /// hex-encoded nonce followed by the encrypted private key
+    Local(String),
+    External(EthAddress),
+}
+
+enum WalletKey {
+    Local(PrivateKeySigner),
+    External(EthAddress),
+}
+
+impl WalletKey {
+    fn address(&self) -> WalletAddress {
+        match self {
+            WalletKey::Local(signer) => WalletAddress {
+                address: signer.address().to_string(),
+                external: false,
+            },
+            WalletKey::External(address) => WalletAddress {
+                address: address.to_string(),
+                external: true,
+            },
+        }
+    }
+}
+
+struct Pending {
+    request: PendingSignature,
+    /// who to answer, with the ID of the request answered
+    target: Address,
+    km_id: u64,
+    expires: Instant,
+}
+
+/// A runtime module that holds the node's EVM key, or the address of one held on a
+/// hardware wallet, and signs with it for local processes with the capability to
+/// ask for each kind of signature. Every signature waits on the user's consent,
+/// given through the settings app, which holds the root capability.
+pub async fn wallet(
+    our: String,
+    send_to_loop: MessageSender,
+    print_tx: PrintSender,
+    mut recv_from_loop: MessageReceiver,
+    send_to_caps_oracle: CapMessageSender,
+    home_directory_path: PathBuf,
+    file_key: Vec<u8>,
+) -> anyhow::Result<()> {
+    let cipher = key_cipher(&file_key);
+    let key_path = home_directory_path.join(".wallet");
+    // a key file that can't be read is left in place, and not overwritten, until
+    // the user removes it
+    let mut key = match load_key(&key_path, &cipher).await {
+        Ok(key) => key,
+        Err(e) => {
+            Printout::new(
+                0,
+                WALLET_PROCESS_ID.clone(),
+                format!(
+                    "wallet: failed to load the key in {}: {e}",
+                    key_path.display()
+                ),
+            )
+            .send(&print_tx)
+            .await;
+            None
+        }
+    };
+    let mut pending: BTreeMap<u64, Pending> = BTreeMap::new();
+    let mut next_id: u64 = 0;
+    let mut expiry_check = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            Some(km) = recv_from_loop.recv() => {
+                // only local processes can ask for signatures
+                if km.source.node != our {
+                    continue;
+                }
+                let Message::Request(ref req) = km.message else {
+                    continue;
+                };
+                let target = km.rsvp.clone().unwrap_or(km.source.clone());
+                let Ok(action) = serde_json::from_slice::<WalletAction>(&req.body) else {
+                    respond(&our, km.id, target, WalletResponse::Err(WalletError::MalformedRequest), &send_to_loop).await;
+                    continue;
+                };
+                let response = match action {
+                    WalletAction::GetAddress => {
+                        WalletResponse::Address(key.as_ref().map(WalletKey::address))
+                    }
+                    WalletAction::SignMessage(_)
+                    | WalletAction::SignTypedData(_)
+                    | WalletAction::SignTransaction(_) => {
+                        match check_sign_request(&our, &km, &action, key.as_ref(), &send_to_caps_oracle).await {
+                            Err(e) => WalletResponse::Err(e),
+                            // refused outright rather than queued behind the others
+                            Ok(()) if pending.len() >= MAX_PENDING => {
+                                WalletResponse::Err(WalletError::Denied)
+                            }
+                            Ok(()) => {
+                                let id = next_id;
+                                next_id += 1;
+                                Printout::new(
+                                    0,
+                                    WALLET_PROCESS_ID.clone(),
+                                    format!(
+                                        "wallet: {} asks for a signature; approve or deny it in Settings",
+                                        km.source.process,
+                                    ),
+                                )
+                                .send(&print_tx)
+                                .await;
+                                pending.insert(
+                                    id,
+                                    Pending {
+                                        request: PendingSignature {
+                                            id,
+                                            process: km.source.process.clone(),
+                                            request: action,
+                                            requested_at: SystemTime::now()
+                                                .duration_since(UNIX_EPOCH)
+                                                .unwrap()
+                                                .as_secs(),
+                                        },
+                                        target,
+                                        km_id: km.id,
+                                        expires: Instant::now() + PENDING_TIMEOUT,
+                                    },
+                                );
+                                // answered once the user approves or denies it
+                                continue;
+                            }
+                        }
+                    }
+                    action => {
+                        if !has_capability(&our, &km.source, "{\"root\":true}".to_string(), &send_to_caps_oracle).await {
+                            WalletResponse::Err(WalletError::NoCap)
+                        } else {
+                            handle_root_action(
+                                &our,
+                                action,
+                                &mut key,
+                                &mut pending,
+                                &key_path,
+                                &cipher,
+                                &send_to_loop,
+                            )
+                            .await
+                        }
+                    }
+                };
+                respond(&our, km.id, target, response, &send_to_loop).await;
+            }
+            _ = expiry_check.tick() => {
+                let now = Instant::now();
+                let expired: Vec<u64> = pending
+                    .iter()
+                    .filter(|(_, p)| p.expires <= now)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in expired {
+                    let p = pending.remove(&id).unwrap();
+                    respond(&our, p.km_id, p.target, WalletResponse::Err(WalletError::TimedOut), &send_to_loop).await;
+                }
+            }
+        }
+    }
+}
+
+/// Check that a signing request can be put to the user: that its sender holds the
+/// capability for it, that a key is set that can make it, and that it is well-formed.
+async fn check_sign_request(
+    our: &str,
+    km: &KernelMessage,
+    action: &WalletAction,
+    key: Option<&WalletKey>,
+    send_to_caps_oracle: &CapMessageSender,
+) -> Result<(), WalletError> {
+    let kind = match action {
+        WalletAction::SignMessage(_) => WalletCapabilityKind::SignMessage,
+        WalletAction::SignTypedData(_) => WalletCapabilityKind::SignTypedData,
+        WalletAction::SignTransaction(_) => WalletCapabilityKind::SignTransaction,
+        _ => return Err(WalletError::MalformedRequest),
+    };
+    let params = serde_json::to_string(&WalletCapabilityParams { kind }).unwrap();
+    if !has_capability(our, &km.source, params, send_to_caps_oracle).await {
+        return Err(WalletError::NoCap);
+    }
+    let Some(key) = key else {
+        return Err(WalletError::NoKey);
+    };
+    match action {
+        WalletAction::SignTypedData(data) => {
+            typed_data_hash(data)?;
+        }
+        WalletAction::SignTransaction(request) => {
+            if let WalletKey::External(_) = key {
+                return Err(WalletError::Unsupported);
+            }
+            serde_json::from_value::<TransactionRequest>(request.clone())
+                .map_err(|_| WalletError::MalformedRequest)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn handle_root_action(
+    our: &str,
+    action: WalletAction,
+    key: &mut Option<WalletKey>,
+    pending: &mut BTreeMap<u64, Pending>,
+    key_path: &Path,
+    cipher: &Aes256Gcm,
+    send_to_loop: &MessageSender,
+) -> WalletResponse {
+    match action {
+        WalletAction::GenerateKey | WalletAction::ImportKey(_) | WalletAction::SetExternal(_)
+            if key.is_some() =>
+        {
+            WalletResponse::Err(WalletError::KeyExists)
+        }
+        WalletAction::GenerateKey => {
+            set_key(
+                key,
+                WalletKey::Local(PrivateKeySigner::random()),
+                key_path,
+                cipher,
+            )
+            .await
+        }
+        WalletAction::ImportKey(private_key) => match PrivateKeySigner::from_str(&private_key) {
+            Ok(signer) => set_key(key, WalletKey::Local(signer), key_path, cipher).await,
+            Err(_) => WalletResponse::Err(WalletError::InvalidKey),
+        },
+        WalletAction::SetExternal(address) => match EthAddress::from_str(&address) {
+            Ok(address) => set_key(key, WalletKey::External(address), key_path, cipher).await,
+            Err(_) => WalletResponse::Err(WalletError::InvalidKey),
+        },
+        WalletAction::RemoveKey => {
+            if let Err(e) = tokio::fs::remove_file(key_path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    return WalletResponse::Err(e.into());
+                }
+            }
+            *key = None;
+            for (_, p) in std::mem::take(pending) {
+                respond(
+                    our,
+                    p.km_id,
+                    p.target,
+                    WalletResponse::Err(WalletError::NoKey),
+                    send_to_loop,
+                )
+                .await;
+            }
+            WalletResponse::Ok
+        }
+        WalletAction::ListPending => {
+            WalletResponse::Pending(pending.values().map(|p| p.request.clone()).collect())
+        }
+        WalletAction::Approve { id, signature } => {
+            let Some(p) = pending.get(&id) else {
+                return WalletResponse::Err(WalletError::NoSuchRequest(id));
+            };
+            let Some(key) = key.as_ref() else {
+                return WalletResponse::Err(WalletError::NoKey);
+            };
+            let signed = sign(key, &p.request.request, signature.as_deref()).await;
+            if let Err(WalletError::InvalidSignature) = signed {
+                // left pending, for the user to sign again
+                return WalletResponse::Err(WalletError::InvalidSignature);
+            }
+            let p = pending.remove(&id).unwrap();
+            let response = match signed {
+                Ok(response) => response,
+                Err(e) => WalletResponse::Err(e),
+            };
+            respond(our, p.km_id, p.target, response, send_to_loop).await;
+            WalletResponse::Ok
+        }
+        WalletAction::Deny(id) => {
+            let Some(p) = pending.remove(&id) else {
+                return WalletResponse::Err(WalletError::NoSuchRequest(id));
+            };
+            respond(
+                our,
+                p.km_id,
+                p.target,
+                WalletResponse::Err(WalletError::Denied),
+                send_to_loop,
+            )
+            .await;
+            WalletResponse::Ok
+        }
+        WalletAction::GetAddress
+        | WalletAction::SignMessage(_)
+        | WalletAction::SignTypedData(_)
+        | WalletAction::SignTransaction(_) => WalletResponse::Err(WalletError::MalformedRequest),
+    }
+}
+
+async fn set_key(
+    key: &mut Option<WalletKey>,
+    new_key: WalletKey,
+    key_path: &Path,
+    cipher: &Aes256Gcm,
+) -> WalletResponse {
+    if let Err(e) = save_key(key_path, &new_key, cipher).await {
+        return WalletResponse::Err(e);
+    }
+    let address = new_key.address();
+    *key = Some(new_key);
+    WalletResponse::Address(Some(address))
+}
+
+/// Make the signature asked for by an approved request. With an external key,
+/// the signature was made elsewhere, so it is checked against the request instead.
+async fn sign(
+    key: &WalletKey,
+    request: &WalletAction,
+    signature: Option<&str>,
+) -> Result<WalletResponse, WalletError> {
+    match (key, request) {
+        (WalletKey::Local(signer), WalletAction::SignMessage(message)) => {
+            let signature = signer
+                .sign_message(message)
+                .await
+                .map_err(|e| WalletError::SigningError(e.to_string()))?;
+            Ok(WalletResponse::Signature(encode_signature(&signature)))
+        }
+        (WalletKey::Local(signer), WalletAction::SignTypedData(data)) => {
+            let signature = signer
+                .sign_hash(&typed_data_hash(data)?)
+                .await
+                .map_err(|e| WalletError::SigningError(e.to_string()))?;
+            Ok(WalletResponse::Signature(encode_signature(&signature)))
+        }
+        (WalletKey::Local(signer), WalletAction::SignTransaction(request)) => {
+            let request: TransactionRequest = serde_json::from_value(request.clone())
+                .map_err(|_| WalletError::MalformedRequest)?;
+            let envelope = request
+                .build(&EthereumWallet::from(signer.clone()))
+                .await
+                .map_err(|e| WalletError::SigningError(e.to_string()))?;
+            Ok(WalletResponse::SignedTransaction(format!(
+                "0x{}",
+                hex::encode(envelope.encoded_2718())
+            )))
+        }
+        (WalletKey::External(address), WalletAction::SignMessage(message)) => {
+            let signature = decode_signature(signature)?;
+            match signature.recover_address_from_msg(message) {
+                Ok(signer) if signer == *address => {
+                    Ok(WalletResponse::Signature(encode_signature(&signature)))
+                }
+                _ => Err(WalletError::InvalidSignature),
+            }
+        }
+        (WalletKey::External(address), WalletAction::SignTypedData(data)) => {
+            let signature = decode_signature(signature)?;
+            match signature.recover_address_from_prehash(&typed_data_hash(data)?) {
+                Ok(signer) if signer == *address => {
+                    Ok(WalletResponse::Signature(encode_signature(&signature)))
+                }
+                _ => Err(WalletError::InvalidSignature),
+            }
+        }
+        (WalletKey::External(_), WalletAction::SignTransaction(_)) => Err(WalletError::Unsupported),
+        _ => Err(WalletError::MalformedRequest),
+    }
+}
+
+fn typed_data_hash(data: &serde_json::Value) -> Result<B256, WalletError> {
+    serde_json::from_value::<TypedData>(data.clone())
+        .map_err(|_| WalletError::MalformedRequest)?
+        .eip712_signing_hash()
+        .map_err(|_| WalletError::MalformedRequest)
+}
+
+fn encode_signature(signature: &Signature) -> String {
+    format!("0x{}", hex::encode(signature.as_bytes()))
+}
+
+fn decode_signature(signature: Option<&str>) -> Result<Signature, WalletError> {
+    let bytes = signature
+        .and_then(|signature| hex::decode(signature.trim_start_matches("0x")).ok())
+        .ok_or(WalletError::InvalidSignature)?;
+    Signature::try_from(bytes.as_slice()).map_err(|_| WalletError::InvalidSignature)
+}
+
+async fn has_capability(
+    our: &str,
+    source: &Address,
+    params: String,
+    send_to_caps_oracle: &CapMessageSender,
+) -> bool {
+    let (send_cap_bool, recv_cap_bool) = tokio::sync::oneshot::channel();
+    if send_to_caps_oracle
+        .send(CapMessage::Has {
+            on: source.process.clone(),
+            cap: Capability::new((our, WALLET_PROCESS_ID.clone()), params),
+            responder: send_cap_bool,
+        })
+        .await
+        .is_err()
+    {
+        return false;
+    }
+    recv_cap_bool.await.unwrap_or(false)
+}
+
+async fn respond(
+    our: &str,
+    km_id: u64,
+    target: Address,
+    response: WalletResponse,
+    send_to_loop: &MessageSender,
+) {
+    KernelMessage::builder()
+        .id(km_id)
+        .source((our, WALLET_PROCESS_ID.clone()))
+        .target(target)
+        .message(Message::Response((
+            Response {
+                inherit: false,
+                body: serde_json::to_vec(&response).unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            },
+            None,
+        )))
+        .build()
+        .unwrap()
+        .send(send_to_loop)
+        .await;
+}
+
+/// The cipher of the stored key, keyed from the keyfile's file key.
+fn key_cipher(file_key: &[u8]) -> Aes256Gcm {
+    let salt = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, b"kinode-wallet");
+    let mut key = [0u8; 32];
+    salt.extract(file_key)
+        .expand(&[b"wallet-key".as_slice()], ring::hkdf::HKDF_SHA256)
+        .unwrap()
+        .fill(&mut key)
+        .unwrap();
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+/// The stored key, or None if there is no key file.
+async fn load_key(key_path: &Path, cipher: &Aes256Gcm) -> Result<Option<WalletKey>, WalletError> {
+    let contents = match tokio::fs::read_to_string(key_path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let stored = serde_json::from_str::<StoredKey>(&contents)
+        .map_err(|e| WalletError::IOError(format!("malformed key file: {e}")))?;
+    match stored {
+        StoredKey::Local(encrypted) => {
+            let encrypted = hex::decode(encrypted)
+                .ok()
+                .filter(|encrypted| encrypted.len() >= 12)
+                .ok_or_else(|| WalletError::IOError("malformed encrypted key".to_string()))?;
+            let (nonce, ciphertext) = encrypted.split_at(12);
+            let private_key = cipher.decrypt(nonce.into(), ciphertext).map_err(|_| {
+                WalletError::IOError("failed to decrypt the key with this keyfile".to_string())
+            })?;
+            PrivateKeySigner::from_slice(&private_key)
+                .map(|signer| Some(WalletKey::Local(signer)))
+                .map_err(|_| WalletError::InvalidKey)
+        }
+        StoredKey::External(address) => Ok(Some(WalletKey::External(address))),
+    }
+}
+
+async fn save_key(key_path: &Path, key: &WalletKey, cipher: &Aes256Gcm) -> Result<(), WalletError> {
+    let stored = match key {
+        WalletKey::Local(signer) => {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let ciphertext = cipher
+                .encrypt(&nonce, signer.to_bytes().as_slice())
+                .map_err(|e| WalletError::SigningError(e.to_string()))?;
+            StoredKey::Local(hex::encode([nonce.as_slice(), &ciphertext].concat()))
+        }
+        WalletKey::External(address) => StoredKey::External(*address),
+    };
+    // never in place of a key file, even one that failed to load
+    let mut file = match tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(key_path)
+        .await
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err(WalletError::KeyExists)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    file.write_all(serde_json::to_string(&stored).unwrap().as_bytes())
+        .await?;
+    file.sync_all().await?;
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// IPC Request format for the backup:distro:sys runtime module, which makes
+/// encrypted backups of the node, as `kinode --backup` does, and keeps them in
+/// the home directory, in `backups/`, from where they can be restored.
+///
+/// Every action requires the root capability, `{"root":true}`, as given to the
+/// settings app.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum BackupAction {
+    /// Back up the keyfile, if `include_keyfile`, the settings files, the apps'
+    /// SQLite databases and the list of installed apps, encrypted with `passphrase`.
+    /// Only one backup is made at a time. While it is made, its [`BackupProgress`]
+    /// is sent to the requester, in requests that expect no response.
+    /// Responds with [`BackupResponse::Created`] once done.
+    Create {
+        passphrase: String,
+        include_keyfile: bool,
+    },
+    /// Responds with [`BackupResponse::Backups`], oldest first.
+    List,
+    /// Check the passphrase of a backup and stage it to be restored when the node
+    /// next boots, before it logs in, replacing the settings and databases it holds,
+    /// and the keyfile if the backup includes it. The passphrase is asked for again
+    /// at boot, unless given with `--backup-passphrase`. Responds with [`BackupResponse::Ok`].
+    Restore { id: u64, passphrase: String },
+    /// Responds with [`BackupResponse::Ok`].
+    Remove(u64),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum BackupResponse {
+    Ok,
+    Created(BackupInfo),
+    Backups(Vec<BackupInfo>),
+    Err(BackupError),
+}
+
+/// A backup, identified by when it was made, in milliseconds since the epoch.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub id: u64,
+    /// size of the encrypted backup
+    pub bytes: u64,
+    /// whether the backup includes the keyfile, so that it can restore the node
+    /// into an empty home
+    pub keyfile: bool,
+    pub settings_files: usize,
+    pub databases: usize,
+    pub apps: usize,
+}
+
+/// How far a backup has got, sent to the process that asked for it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupProgress {
+    pub id: u64,
+    pub stage: BackupStage,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum BackupStage {
+    /// reading the keyfile, the settings files and the list of installed apps
+    Settings,
+    /// copying the `done`th of `total` databases, at `sqlite/<package>/<db>`
+    Database {
+        db: String,
+        done: usize,
+        total: usize,
+    },
+    /// a database left out of the backup, and why
+    SkippedDatabase {
+        db: String,
+        reason: String,
+    },
+    Encrypting,
+    Writing,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Error)]
+pub enum BackupError {
+    #[error("missing the root capability")]
+    NoCap,
+    #[error("backup got a malformed request")]
+    MalformedRequest,
+    #[error("a backup is already being made")]
+    InProgress,
+    #[error("no backup {0}")]
+    NoBackup(u64),
+    #[error("wrong passphrase, or the backup is damaged")]
+    WrongPassphrase,
+    #[error("backup failed: {0}")]
+    Failed(String),
+    #[error("IO error: {0}")]
+    IOError(String),
+}
+
+impl From<std::io::Error> for BackupError {
+    fn from(err: std::io::Error) -> Self {
+        BackupError::IOError(err.to_string())
+    }
+}
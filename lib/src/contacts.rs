@@ -0,0 +1,135 @@
+use crate::core::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// IPC Request format for the contacts:distro:sys runtime module, the address book of
+/// the node, kept for every app that deals in other nodes.
+///
+/// Each action takes a capability issued by contacts:distro:sys, whose params name
+/// it: `"ReadNameOnly"`, `"Read"`, `"Add"`, `"Remove"` or `"Share"`. The root
+/// capability, `{"root":true}`, as given to the contacts app, stands for all of them.
+///
+/// [`ContactsAction::Offer`] is sent between the contacts modules of two nodes, and
+/// takes no capability.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ContactsAction {
+    /// Responds with [`ContactsResponse::Names`]. Takes `"ReadNameOnly"`.
+    GetNames,
+    /// Responds with [`ContactsResponse::Contacts`]. Takes `"Read"`.
+    GetAll,
+    /// Responds with [`ContactsResponse::Contact`]. Takes `"Read"`.
+    Get(NodeId),
+    /// The contacts that have a field, with the given value if any.
+    /// Responds with [`ContactsResponse::Contacts`]. Takes `"Read"`.
+    Query {
+        field: String,
+        value: Option<serde_json::Value>,
+    },
+    /// Add a contact with no fields, unless they already are one.
+    /// Responds with [`ContactsResponse::Ok`]. Takes `"Add"`.
+    Add(NodeId),
+    /// Set a field of a contact, adding the contact if need be.
+    /// Responds with [`ContactsResponse::Ok`]. Takes `"Add"`.
+    SetField {
+        node: NodeId,
+        field: String,
+        value: serde_json::Value,
+    },
+    /// Responds with [`ContactsResponse::Ok`]. Takes `"Remove"`.
+    Remove(NodeId),
+    /// Responds with [`ContactsResponse::Ok`]. Takes `"Remove"`.
+    RemoveField { node: NodeId, field: String },
+    /// Offer one of our contacts to another node, with the given fields, or all of
+    /// them. It is kept there until accepted or rejected, if we are one of their
+    /// contacts. Responds with [`ContactsResponse::Ok`] once sent. Takes `"Share"`.
+    Share {
+        with: NodeId,
+        node: NodeId,
+        fields: Option<Vec<String>>,
+    },
+    /// A contact offered by the contacts module of another node. Expects no response.
+    Offer { node: NodeId, contact: Contact },
+    /// Responds with [`ContactsResponse::Offers`], newest first. Takes `"Read"`.
+    GetOffers,
+    /// Add the fields of an offered contact to ours, over any we have, and forget the
+    /// offer. Responds with [`ContactsResponse::Ok`]. Takes `"Add"`.
+    AcceptOffer(u64),
+    /// Responds with [`ContactsResponse::Ok`]. Takes `"Remove"`.
+    RejectOffer(u64),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ContactsResponse {
+    Ok,
+    Names(Vec<NodeId>),
+    Contacts(BTreeMap<NodeId, Contact>),
+    Contact(Option<Contact>),
+    Offers(Vec<ContactOffer>),
+    Err(ContactsError),
+}
+
+/// The fields of a contact, such as `"nickname"`, set by apps as they please.
+pub type Contact = BTreeMap<String, serde_json::Value>;
+
+/// The capabilities issued by contacts:distro:sys, serialized as their params.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContactsCapability {
+    ReadNameOnly,
+    Read,
+    Add,
+    Remove,
+    Share,
+}
+
+impl ContactsAction {
+    /// The capability the action takes, if any.
+    pub fn capability(&self) -> Option<ContactsCapability> {
+        match self {
+            ContactsAction::GetNames => Some(ContactsCapability::ReadNameOnly),
+            ContactsAction::GetAll
+            | ContactsAction::Get(_)
+            | ContactsAction::Query { .. }
+            | ContactsAction::GetOffers => Some(ContactsCapability::Read),
+            ContactsAction::Add(_)
+            | ContactsAction::SetField { .. }
+            | ContactsAction::AcceptOffer(_) => Some(ContactsCapability::Add),
+            ContactsAction::Remove(_)
+            | ContactsAction::RemoveField { .. }
+            | ContactsAction::RejectOffer(_) => Some(ContactsCapability::Remove),
+            ContactsAction::Share { .. } => Some(ContactsCapability::Share),
+            ContactsAction::Offer { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContactOffer {
+    pub id: u64,
+    /// the node that offered it
+    pub from: NodeId,
+    pub node: NodeId,
+    pub contact: Contact,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Error)]
+pub enum ContactsError {
+    #[error("missing the capability for the action")]
+    NoCap,
+    #[error("contacts got a malformed request")]
+    MalformedRequest,
+    #[error("{0} is not a node name")]
+    InvalidNode(NodeId),
+    #[error("{0} is not a contact")]
+    NoContact(NodeId),
+    #[error("no offer with that ID")]
+    NoOffer,
+    #[error("IO error: {0}")]
+    IOError(String),
+}
+
+impl From<std::io::Error> for ContactsError {
+    fn from(err: std::io::Error) -> Self {
+        ContactsError::IOError(err.to_string())
+    }
+}
@@ -3,9 +3,14 @@ use serde::{Deserialize, Serialize};
 use std::hash::{Hash, Hasher};
 use thiserror::Error;
 
-pub use crate::{fd_manager::*, kernel::*, kv::*, net::*, sqlite::*, state::*, timer::*, vfs::*};
+pub use crate::{
+    backup::*, contacts::*, fd_manager::*, kernel::*, kv::*, net::*, notify::*, replication::*,
+    sqlite::*, state::*, timer::*, vfs::*, wallet::*,
+};
 
 lazy_static::lazy_static! {
+    pub static ref BACKUP_PROCESS_ID: ProcessId = ProcessId::new(Some("backup"), "distro", "sys");
+    pub static ref CONTACTS_PROCESS_ID: ProcessId = ProcessId::new(Some("contacts"), "distro", "sys");
     pub static ref ETH_PROCESS_ID: ProcessId = ProcessId::new(Some("eth"), "distro", "sys");
     pub static ref FD_MANAGER_PROCESS_ID: ProcessId = ProcessId::new(Some("fd-manager"), "distro", "sys");
     pub static ref HTTP_CLIENT_PROCESS_ID: ProcessId = ProcessId::new(Some("http-client"), "distro", "sys");
@@ -13,11 +18,14 @@ lazy_static::lazy_static! {
     pub static ref KERNEL_PROCESS_ID: ProcessId = ProcessId::new(Some("kernel"), "distro", "sys");
     pub static ref KV_PROCESS_ID: ProcessId = ProcessId::new(Some("kv"), "distro", "sys");
     pub static ref NET_PROCESS_ID: ProcessId = ProcessId::new(Some("net"), "distro", "sys");
+    pub static ref NOTIFY_PROCESS_ID: ProcessId = ProcessId::new(Some("notify"), "distro", "sys");
     pub static ref STATE_PROCESS_ID: ProcessId = ProcessId::new(Some("state"), "distro", "sys");
     pub static ref SQLITE_PROCESS_ID: ProcessId = ProcessId::new(Some("sqlite"), "distro", "sys");
     pub static ref TERMINAL_PROCESS_ID: ProcessId = ProcessId::new(Some("terminal"), "terminal", "sys");
+    pub static ref TERMINAL_RUNTIME_PROCESS_ID: ProcessId = ProcessId::new(Some("terminal"), "distro", "sys");
     pub static ref TIMER_PROCESS_ID: ProcessId = ProcessId::new(Some("timer"), "distro", "sys");
     pub static ref VFS_PROCESS_ID: ProcessId = ProcessId::new(Some("vfs"), "distro", "sys");
+    pub static ref WALLET_PROCESS_ID: ProcessId = ProcessId::new(Some("wallet"), "distro", "sys");
 }
 
 //
@@ -608,6 +616,10 @@ pub struct SendError {
 pub enum SendErrorKind {
     Offline,
     Timeout,
+    /// the target's queue was full, so the request was dropped, see [`crate::kernel::QueuePolicy`].
+    /// The WIT has no such kind, so processes are given [`SendErrorKind::Offline`], which
+    /// a local target is otherwise never.
+    QueueFull,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -1189,21 +1201,21 @@ pub fn en_wit_send_error_v1(error: SendError) -> crate::v1::wit::SendError {
 
 pub fn en_wit_send_error_kind(kind: SendErrorKind) -> wit::SendErrorKind {
     match kind {
-        SendErrorKind::Offline => wit::SendErrorKind::Offline,
+        SendErrorKind::Offline | SendErrorKind::QueueFull => wit::SendErrorKind::Offline,
         SendErrorKind::Timeout => wit::SendErrorKind::Timeout,
     }
 }
 
 pub fn en_wit_send_error_kind_v0(kind: SendErrorKind) -> crate::v0::wit::SendErrorKind {
     match kind {
-        SendErrorKind::Offline => crate::v0::wit::SendErrorKind::Offline,
+        SendErrorKind::Offline | SendErrorKind::QueueFull => crate::v0::wit::SendErrorKind::Offline,
         SendErrorKind::Timeout => crate::v0::wit::SendErrorKind::Timeout,
     }
 }
 
 pub fn en_wit_send_error_kind_v1(kind: SendErrorKind) -> crate::v1::wit::SendErrorKind {
     match kind {
-        SendErrorKind::Offline => crate::v1::wit::SendErrorKind::Offline,
+        SendErrorKind::Offline | SendErrorKind::QueueFull => crate::v1::wit::SendErrorKind::Offline,
         SendErrorKind::Timeout => crate::v1::wit::SendErrorKind::Timeout,
     }
 }
@@ -61,6 +61,80 @@ pub enum EthAction {
         method: String,
         params: serde_json::Value,
     },
+    /// Several raw requests on one chain, sent to the provider together, such as the
+    /// `eth_getLogs` calls of an indexer catching up. Answered with an [`EthResponse::Batch`].
+    BatchRequest {
+        chain_id: u64,
+        requests: Vec<BatchCall>,
+    },
+    /// Send a transaction and watch it until it has `confirmations` confirmations.
+    /// Answered with an [`EthResponse::TransactionSent`] once it is first sent. As it
+    /// progresses, the requesting process (or its rsvp) is sent [`TxUpdate`] requests,
+    /// the last of which is a [`TxStatus::Confirmed`] or [`TxStatus::Failed`].
+    /// Only processes on this node can send transactions.
+    SendTransaction {
+        chain_id: u64,
+        tx: TransactionToSend,
+        confirmations: u64,
+    },
+    /// Suggest fees for a transaction on the chain of this ID, from the tips paid in
+    /// recent blocks and the next block's base fee. Answered with an
+    /// [`EthResponse::FeeSuggestion`]. Suggestions are cached for a few seconds.
+    GetFeeSuggestion(u64),
+}
+
+/// A transaction for eth:distro:sys to send.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TransactionToSend {
+    /// A signed transaction, EIP-2718 encoded, as hex. Its nonce and fees are fixed
+    /// by its signature, so it is rebroadcast while pending but never fee-bumped.
+    Signed(String),
+    /// A transaction request, as JSON that can be parsed to
+    /// [`alloy::rpc::types::eth::TransactionRequest`], to be signed by the node's
+    /// wallet, wallet:distro:sys, with its key of the address `key`, which the key is
+    /// known by. The sending process needs the wallet's capability to sign
+    /// transactions, and each signature, those of resends with bumped fees
+    /// included, waits on the user's approval, so the response can take minutes.
+    /// The nonce, gas limit and fees are filled in when not given, and the fees are
+    /// bumped if the transaction is slow to be mined.
+    Unsigned {
+        request: serde_json::Value,
+        key: String,
+    },
+}
+
+/// Incoming `Request` that processes get as a transaction they sent with
+/// [`EthAction::SendTransaction`] progresses.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TxUpdate {
+    /// the ID given in the [`EthResponse::TransactionSent`]
+    pub id: u64,
+    pub status: TxStatus,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TxStatus {
+    /// Resent with higher fees, under a new hash. Any of the hashes sent can be mined.
+    Resent { hash: String },
+    /// Mined in a block, with fewer confirmations than asked for so far.
+    Mined { hash: String, block_number: u64 },
+    /// Mined with the confirmations asked for. `success` is false if it reverted.
+    Confirmed {
+        hash: String,
+        block_number: u64,
+        confirmations: u64,
+        success: bool,
+    },
+    /// Not mined: replaced by another transaction with the same nonce, or never
+    /// mined before giving up on it.
+    Failed(String),
+}
+
+/// One request in an [`EthAction::BatchRequest`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchCall {
+    pub method: String,
+    pub params: serde_json::Value,
 }
 
 /// Incoming `Request` containing subscription updates or errors that processes will receive.
@@ -96,6 +170,39 @@ pub enum EthResponse {
     /// Value will be a JSON-RPC standard item
     Response(serde_json::Value),
     Err(EthError),
+    /// Response to an [`EthAction::BatchRequest`]: a `Response` or `Err` for
+    /// each request, in the order they were made
+    Batch(Vec<EthResponse>),
+    /// Response to an [`EthAction::SendTransaction`]: the transaction was sent with this hash,
+    /// and [`TxUpdate`]s about it will carry this ID
+    TransactionSent {
+        id: u64,
+        hash: String,
+    },
+    /// Response to an [`EthAction::GetFeeSuggestion`]
+    FeeSuggestion(FeeSuggestion),
+}
+
+/// Fees to offer for a transaction to be mined slowly, in the usual time, or quickly.
+/// All amounts are in wei per gas.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FeeSuggestion {
+    pub chain_id: u64,
+    /// the latest block the suggestion is made from
+    pub block_number: u64,
+    /// the base fee of the next block, or `None` on chains without EIP-1559,
+    /// where the fee levels' `max_fee_per_gas` is the gas price to offer
+    pub base_fee_per_gas: Option<u128>,
+    pub slow: FeeLevel,
+    pub standard: FeeLevel,
+    pub fast: FeeLevel,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct FeeLevel {
+    /// leaves room for the base fee to double before the transaction is mined
+    pub max_fee_per_gas: u128,
+    pub max_priority_fee_per_gas: u128,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -151,6 +258,21 @@ pub enum EthConfigAction {
     GetAccessSettings,
     /// Get the state of calls and subscriptions. Used for debugging.
     GetState,
+    /// Get the error rate, latency and sync status of each provider.
+    GetProviderHealth,
+    /// Set how long responses to an RPC method are cached, in milliseconds.
+    /// `None` restores the default for the method, and `Some(0)` stops caching it.
+    SetCacheTtl { method: String, ttl_ms: Option<u64> },
+    /// Get the cache TTL of each RPC method and how often the cache is hit.
+    GetCacheStats,
+    /// Drop all cached responses.
+    ClearCache,
+    /// Benchmark an RPC URL, saved or not, for a chain.
+    /// The tuple is (chain_id, rpc_url).
+    TestProvider((u64, String)),
+    /// Get the latest benchmark of each saved RPC URL. Saved RPC URLs are
+    /// benchmarked periodically.
+    GetProviderTests,
 }
 
 /// Response type from an [`EthConfigAction`] request.
@@ -170,6 +292,71 @@ pub enum EthConfigResponse {
         active_subscriptions: HashMap<crate::core::Address, HashMap<u64, Option<String>>>, // None if local, Some(node_provider_name) if remote
         outstanding_requests: HashSet<u64>,
     },
+    /// Response from a GetProviderHealth request
+    ProviderHealth(Vec<ProviderHealth>),
+    /// Response from a GetCacheStats request
+    CacheStats(CacheStats),
+    /// Response from a TestProvider request
+    ProviderTest(ProviderTest),
+    /// Response from a GetProviderTests request
+    ProviderTests(Vec<ProviderTest>),
+}
+
+/// The state of the cache of RPC responses.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CacheStats {
+    /// responses currently cached
+    pub entries: usize,
+    /// requests answered from the cache since boot
+    pub hits: u64,
+    /// cacheable requests that went to a provider since boot
+    pub misses: u64,
+    /// how long responses to each RPC method are cached, in milliseconds,
+    /// where 0 means never
+    pub ttls_ms: HashMap<String, u64>,
+}
+
+/// How a provider has been doing, as seen by our node since boot.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProviderHealth {
+    pub chain_id: u64,
+    /// RPC URL or node name
+    pub provider: String,
+    /// requests sent to the provider
+    pub requests: u64,
+    /// requests the provider failed to answer, by timing out, dropping the
+    /// connection or answering malformed. RPC errors such as reverts are answers.
+    pub errors: u64,
+    /// share of recent requests that failed, weighted toward the most recent
+    pub error_rate: f64,
+    /// average time to answer recent requests
+    pub latency_ms: Option<u64>,
+    /// latest block the provider reported, checked periodically for RPC URLs
+    pub block_number: Option<u64>,
+    /// blocks behind the most synced provider for the chain
+    pub blocks_behind: Option<u64>,
+    /// whether the provider has been demoted for failing or falling behind,
+    /// so it is only tried once the others have failed
+    pub demoted: bool,
+}
+
+/// A benchmark of an RPC URL, made over a connection of its own.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProviderTest {
+    pub chain_id: u64,
+    pub url: String,
+    /// when the benchmark was made, in seconds since the epoch
+    pub tested_at: u64,
+    /// why the benchmark failed, if the URL could not be reached, or serves
+    /// another chain
+    pub error: Option<String>,
+    /// median time to answer a request for the latest block
+    pub latency_ms: Option<u64>,
+    /// latest block the provider reported
+    pub block_number: Option<u64>,
+    /// whether the provider serves state from early blocks, as archive nodes
+    /// do. `None` if it didn't answer in time.
+    pub archive: Option<bool>,
 }
 
 /// Settings for our ETH provider
@@ -1,3 +1,4 @@
+use crate::core::ProcessId;
 use crate::http::server_types::{HttpResponse, WsMessageType};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -13,6 +14,10 @@ pub enum HttpClientAction {
         url: String,
         headers: HashMap<String, String>,
         channel_id: u32,
+        /// if set, reconnect whenever the connection drops without
+        /// the process closing it, as the policy describes
+        #[serde(default)]
+        reconnect: Option<ReconnectPolicy>,
     },
     WebSocketPush {
         channel_id: u32,
@@ -21,13 +26,50 @@ pub enum HttpClientAction {
     WebSocketClose {
         channel_id: u32,
     },
+    /// Send the HTTP requests of `process`, or of every process without a proxy of
+    /// its own if None, through the proxy at `url`, which may be `http://`, `https://`,
+    /// `socks5://` or `socks5h://`, or directly if `url` is None. WebSocket connections
+    /// go through it too, except through an `https://` one, which they fail to open.
+    /// Responds with [`HttpClientResponse::Proxies`].
+    /// Only accepted from `settings:settings:sys`.
+    SetProxy {
+        process: Option<ProcessId>,
+        url: Option<String>,
+    },
+    /// Responds with [`HttpClientResponse::Proxies`].
+    /// Only accepted from `settings:settings:sys`.
+    ListProxies,
+}
+
+/// An outbound proxy set with [`HttpClientAction::SetProxy`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProxyInfo {
+    /// the process whose requests use the proxy, or None for every process
+    /// without a proxy of its own
+    pub process: Option<ProcessId>,
+    pub url: String,
+}
+
+/// How to reconnect a WebSocket opened with [`HttpClientAction::WebSocketOpen`].
+/// While reconnecting, pushes to the connection fail, and closing it cancels
+/// the reconnection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReconnectPolicy {
+    /// the number of attempts to make before giving up and closing the
+    /// connection, or None to keep trying
+    pub max_attempts: Option<u32>,
+    /// how long to wait before the first attempt, doubling with each attempt after it
+    pub initial_backoff_ms: u64,
+    /// the longest to wait before any attempt
+    pub max_backoff_ms: u64,
 }
 
 /// HTTP Request type contained in [`HttpClientAction::Http`].
 ///
-/// BODY is stored in the lazy_load_blob, as bytes
+/// BODY is stored in the lazy_load_blob, as bytes, unless built from a `form`
 ///
-/// TIMEOUT is stored in the message's `expects_response` value
+/// TIMEOUT is stored in the message's `expects_response` value, and covers
+/// every attempt made under the `retry` policy
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OutgoingHttpRequest {
     /// must parse to [`http::Method`]
@@ -37,11 +79,67 @@ pub struct OutgoingHttpRequest {
     /// must parse to [`url::Url`]
     pub url: String,
     pub headers: HashMap<String, String>,
+    /// if set, retry the request as the policy describes when it fails
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    /// if set, a response body larger than this many bytes is not returned in
+    /// the lazy_load_blob of the Response, which is then an
+    /// [`HttpClientResponse::HttpStream`], but streamed to the requesting process
+    /// in [`HttpClientRequest::HttpBodyChunk`]s
+    #[serde(default)]
+    pub stream_threshold: Option<u64>,
+    /// if set, the body is encoded from this form, and the `Content-Type`
+    /// header set to match
+    #[serde(default)]
+    pub form: Option<RequestForm>,
+}
+
+/// A form to send as the body of an [`OutgoingHttpRequest`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RequestForm {
+    /// Fields sent as `application/x-www-form-urlencoded`. The lazy_load_blob is ignored.
+    UrlEncoded(Vec<(String, String)>),
+    /// Parts sent as `multipart/form-data`. The contents of the parts are stored in
+    /// the lazy_load_blob, one after another in the order the parts are given.
+    Multipart(Vec<FormPart>),
+}
+
+/// A part of a [`RequestForm::Multipart`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FormPart {
+    /// the name of the form field
+    pub name: String,
+    /// if set, the part is sent as a file with this name
+    pub filename: Option<String>,
+    /// the `Content-Type` of the part, if any, such as `image/png`
+    pub content_type: Option<String>,
+    /// the length of the part's contents in the lazy_load_blob, in bytes
+    pub length: u64,
+}
+
+/// How to retry an [`OutgoingHttpRequest`]. A request is retried if it could not
+/// be sent, or if the response has one of the `retry_on_status` codes. Retries
+/// are made regardless of method, so only set a policy on requests that are
+/// safe to repeat.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// the number of retries to make after the first attempt
+    pub max_retries: u32,
+    /// how long to wait before the first retry, doubling with each retry after it
+    pub initial_backoff_ms: u64,
+    /// the longest to wait before any retry
+    pub max_backoff_ms: u64,
+    /// response status codes to retry on, such as 429 and 503. A `Retry-After`
+    /// header on such a response is honored, up to `max_backoff_ms`.
+    #[serde(default)]
+    pub retry_on_status: Vec<u16>,
 }
 
 /// Request that comes from an open WebSocket client connection in the
 /// `http-client:distro:sys` service. Be prepared to receive these after
 /// using a [`HttpClientAction::WebSocketOpen`] to open a connection.
+/// Also the requests in which a response body is streamed, after an
+/// [`HttpClientResponse::HttpStream`].
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum HttpClientRequest {
     WebSocketPush {
@@ -51,6 +149,25 @@ pub enum HttpClientRequest {
     WebSocketClose {
         channel_id: u32,
     },
+    /// A connection opened with a [`ReconnectPolicy`] dropped, and is being reconnected.
+    WebSocketDisconnected {
+        channel_id: u32,
+    },
+    /// A connection that dropped has been reconnected, and can be pushed to again.
+    WebSocketReconnected {
+        channel_id: u32,
+    },
+    /// The next part of a streamed response body, in the lazy_load_blob.
+    /// `last` is true for the final part, after which the stream is done.
+    HttpBodyChunk {
+        stream_id: u64,
+        last: bool,
+    },
+    /// The streamed response body could not be read to the end, so the
+    /// parts received so far are incomplete.
+    HttpBodyAbort {
+        stream_id: u64,
+    },
 }
 
 /// Response type received from the `http-client:distro:sys` service after
@@ -58,7 +175,16 @@ pub enum HttpClientRequest {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum HttpClientResponse {
     Http(HttpResponse),
+    /// Sent in place of [`HttpClientResponse::Http`] when the response body is
+    /// larger than the `stream_threshold` of the request. The body follows in
+    /// [`HttpClientRequest::HttpBodyChunk`]s with this `stream_id`.
+    HttpStream {
+        response: HttpResponse,
+        stream_id: u64,
+    },
     WebSocketAck,
+    /// The proxies now set, sorted by process.
+    Proxies(Vec<ProxyInfo>),
 }
 
 #[derive(Clone, Debug, Error, Serialize, Deserialize)]
@@ -72,6 +198,8 @@ pub enum HttpClientError {
     BadUrl { url: String },
     #[error("http version not supported: {version}")]
     BadVersion { version: String },
+    #[error("form could not be encoded: {0}")]
+    BadForm(String),
     #[error("client failed to build request: {0}")]
     BuildRequestFailed(String),
     #[error("client failed to execute request: {0}")]
@@ -88,4 +216,10 @@ pub enum HttpClientError {
     WsPushBadText,
     #[error("failed to close connection {channel_id} because it was not open")]
     WsCloseFailed { channel_id: u32 },
+
+    // proxy errors
+    #[error("proxy URL could not be used: {url}")]
+    BadProxy { url: String },
+    #[error("proxies can only be set by settings:settings:sys")]
+    ProxyForbidden,
 }
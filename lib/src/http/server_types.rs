@@ -1,4 +1,4 @@
-use crate::core::LazyLoadBlob;
+use crate::core::{LazyLoadBlob, ProcessId};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use thiserror::Error;
@@ -25,6 +25,22 @@ pub enum HttpServerRequest {
     /// Receiving will indicate that the client closed the socket. Can be sent to close
     /// from the server-side, as [`type@HttpServerAction::WebSocketClose`].
     WebSocketClose(u32),
+    /// Processes will receive this kind of request when a client opens an event stream
+    /// on a path bound with [`HttpServerAction::EventStreamBind`]. If the client is
+    /// reconnecting, `last_event_id` is the ID of the last event it received.
+    EventStreamOpen {
+        path: String,
+        channel_id: u32,
+        last_event_id: Option<String>,
+    },
+    /// Receiving will indicate that the client closed the event stream. Can be sent to
+    /// close from the server-side, as [`type@HttpServerAction::EventStreamClose`].
+    EventStreamClose(u32),
+    /// Processes registered as middleware with [`HttpServerAction::SetMiddleware`]
+    /// receive this for each request to the paths they cover, after it has been
+    /// authenticated but before it is handled. BODY is stored in the lazy_load_blob.
+    /// Respond with a [`MiddlewareResponse`].
+    Middleware(IncomingHttpRequest),
 }
 
 /// An HTTP request routed to a process as a result of a binding.
@@ -56,12 +72,120 @@ pub struct HttpResponse {
     pub headers: HashMap<String, String>,
 }
 
+/// Response to an [`HttpServerRequest::Middleware`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MiddlewareResponse {
+    /// Let the request be handled as usual, with these changes.
+    Continue {
+        /// Headers to add to the request, replacing any the client sent.
+        #[serde(default)]
+        set_request_headers: HashMap<String, String>,
+        /// Headers to add to the response, replacing any it has.
+        #[serde(default)]
+        set_response_headers: HashMap<String, String>,
+    },
+    /// Answer the request with this response, whose body is the lazy_load_blob,
+    /// without it reaching the process that bound the path.
+    Respond(HttpResponse),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RpcResponseBody {
     pub body: Vec<u8>,
     pub lazy_load_blob: Option<LazyLoadBlob>,
 }
 
+fn compress_by_default() -> bool {
+    true
+}
+
+/// Where and how [`HttpServerAction::BindProxy`] forwards requests.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ProxyConfig {
+    /// Base URL of the upstream service, such as `http://localhost:3000/api`.
+    /// A request to `{bound path}/x?y` is forwarded to `{upstream}/x?y`.
+    pub upstream: String,
+    /// Headers to add to each forwarded request, replacing any sent by the client.
+    #[serde(default)]
+    pub set_request_headers: HashMap<String, String>,
+    /// Headers sent by the client to leave out of forwarded requests.
+    #[serde(default)]
+    pub remove_request_headers: Vec<String>,
+    /// Headers to add to each response from the upstream, replacing any it sent.
+    #[serde(default)]
+    pub set_response_headers: HashMap<String, String>,
+}
+
+/// Requirements a client must meet to open a WebSocket on a bound path, on top of
+/// being logged in, so that a login cookie alone does not open every app's channels.
+/// Rebind the path to change them; connections that are already open are unaffected.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct WsAuthorization {
+    /// If not empty, the client must present one of these tokens as the
+    /// `channel_token` query parameter of the handshake, since browsers cannot
+    /// set headers on it. The process that bound the path hands them out to the
+    /// clients it wants to admit, such as through its own authenticated HTTP API.
+    #[serde(default)]
+    pub channel_tokens: Vec<String>,
+    /// If true, the login cookie is not accepted: the client must present an API
+    /// token whose scopes cover this path, in the `Authorization` header.
+    #[serde(default)]
+    pub require_api_token: bool,
+}
+
+/// A token-bucket rate limit, applied to each client IP address separately.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RateLimit {
+    /// Requests a client may make per minute, on average. If 0, unlimited.
+    pub per_minute: u32,
+    /// Requests a client may make at once, after being idle.
+    pub burst: u32,
+}
+
+/// A CORS policy for a bound path, which the http-server enforces on behalf of the
+/// app that bound it: it answers preflight requests itself, and adds the CORS headers
+/// to responses to requests from allowed origins.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct CorsPolicy {
+    /// Origins allowed to make requests, such as `https://example.com`, or `*` for any.
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in requests. If empty, only `GET`, `HEAD`, and `POST`.
+    pub allowed_methods: Vec<String>,
+    /// Headers allowed in requests beyond the CORS-safelisted ones, or `*` for any.
+    pub allowed_headers: Vec<String>,
+    /// Response headers that the requesting frontend may read.
+    pub exposed_headers: Vec<String>,
+    /// Whether requests may carry credentials, such as an `Authorization` header.
+    /// Can't be combined with allowing any origin with `*`. Requests that change state and are authenticated by cookie must still pass
+    /// the CSRF check, so frontends on other origins should use `Authorization`.
+    pub allow_credentials: bool,
+    /// How many seconds browsers may cache the answer to a preflight request.
+    pub max_age: Option<u64>,
+}
+
+impl CorsPolicy {
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.trim_end_matches('/') == origin)
+    }
+
+    /// Whether the policy may be bound: one that lets any origin make requests
+    /// with credentials would let every site act as the logged-in user.
+    pub fn is_valid(&self) -> bool {
+        !(self.allow_credentials && self.allowed_origins.iter().any(|allowed| allowed == "*"))
+    }
+
+    pub fn allows_method(&self, method: &str) -> bool {
+        if self.allowed_methods.is_empty() {
+            return matches!(method, "GET" | "HEAD" | "POST");
+        }
+        self.allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+}
+
 /// Request type sent to `http-server:distro:sys` in order to configure it.
 ///
 /// If a response is expected, all actions will return a Response
@@ -80,6 +204,19 @@ pub enum HttpServerAction {
         /// Set whether to bind the lazy_load_blob statically to this path. That is, take the
         /// lazy_load_blob bytes and serve them as the response to any request to this path.
         cache: bool,
+        /// Set whether responses from this path may be compressed, if the client accepts it
+        /// and they are large enough to benefit. On unless set to false. Responses that set
+        /// cookies or are marked `Cache-Control: no-store` are never compressed.
+        #[serde(default = "compress_by_default")]
+        compress: bool,
+        /// Set which other origins' frontends may call this path, and how. If None,
+        /// browsers only allow requests from frontends served by this node.
+        #[serde(default)]
+        cors: Option<CorsPolicy>,
+        /// Set a limit on requests to this path from each client, on top of the
+        /// node-wide limit. Requests over it are answered with `429 Too Many Requests`.
+        #[serde(default)]
+        rate_limit: Option<RateLimit>,
     },
     /// SecureBind expects a lazy_load_blob if and only if `cache` is TRUE. The lazy_load_blob should
     /// be the static file to serve at this path.
@@ -95,21 +232,62 @@ pub enum HttpServerAction {
         /// Set whether to bind the lazy_load_blob statically to this path. That is, take the
         /// lazy_load_blob bytes and serve them as the response to any request to this path.
         cache: bool,
+        /// Set whether responses from this path may be compressed, if the client accepts it
+        /// and they are large enough to benefit. On unless set to false. Responses that set
+        /// cookies or are marked `Cache-Control: no-store` are never compressed.
+        #[serde(default = "compress_by_default")]
+        compress: bool,
+        /// Set which other origins' frontends may call this path, and how. If None,
+        /// browsers only allow requests from frontends served by this node.
+        #[serde(default)]
+        cors: Option<CorsPolicy>,
+        /// Set a limit on requests to this path from each client, on top of the
+        /// node-wide limit. Requests over it are answered with `429 Too Many Requests`.
+        #[serde(default)]
+        rate_limit: Option<RateLimit>,
+    },
+    /// Bind a path, and every path under it, to an upstream HTTP service rather than
+    /// to the process itself. http-server forwards requests on these paths to the
+    /// upstream, replacing the bound path with the upstream URL, and returns its
+    /// responses directly. WebSocket connections on these paths are passed through
+    /// to the upstream as well. Unbind with [`HttpServerAction::Unbind`].
+    BindProxy {
+        path: String,
+        authenticated: bool,
+        local_only: bool,
+        proxy: ProxyConfig,
     },
     /// Unbind a previously-bound HTTP path
     Unbind { path: String },
+    /// Pass requests to `path`, and every path under it, to the `middleware` process
+    /// before they are handled, so that it can add headers to them and their responses,
+    /// or answer them itself. Only covers paths of the process that sends this.
+    /// Set `middleware` to None to stop.
+    SetMiddleware {
+        path: String,
+        middleware: Option<ProcessId>,
+    },
     /// Bind a path to receive incoming WebSocket connections.
     /// Doesn't need a cache since does not serve assets.
     WebSocketBind {
         path: String,
         authenticated: bool,
         extension: bool,
+        /// Set further requirements for opening a connection, checked at upgrade time.
+        #[serde(default)]
+        authorization: Option<WsAuthorization>,
     },
     /// SecureBind is the same as Bind, except that it forces new connections to be made
     /// from the unique subdomain of the process that bound the path. These are *always*
     /// authenticated. Since the subdomain is unique, it will require the user to be
     /// logged in separately to the general domain authentication.
-    WebSocketSecureBind { path: String, extension: bool },
+    WebSocketSecureBind {
+        path: String,
+        extension: bool,
+        /// Set further requirements for opening a connection, checked at upgrade time.
+        #[serde(default)]
+        authorization: Option<WsAuthorization>,
+    },
     /// Unbind a previously-bound WebSocket path
     WebSocketUnbind { path: String },
     /// Processes will RECEIVE this kind of request when a client connects to them.
@@ -143,6 +321,124 @@ pub enum HttpServerAction {
     },
     /// Sending will close a socket the process controls.
     WebSocketClose(u32),
+    /// Bind a path to serve Server-Sent Events: a one-way stream of events to the
+    /// browser, over a plain HTTP response, that passes through proxies which would
+    /// break a WebSocket. http-server keeps idle streams alive with periodic comments.
+    /// Clients open a stream with a GET request accepting `text/event-stream`, such as
+    /// from an `EventSource`, so the path may also be bound with [`HttpServerAction::Bind`].
+    EventStreamBind { path: String, authenticated: bool },
+    /// Unbind a previously-bound event stream path
+    EventStreamUnbind { path: String },
+    /// When sent, expects a lazy_load_blob containing the UTF-8 data of the event
+    /// to send. If `event` is set, the event is dispatched to listeners of that type,
+    /// rather than `message`. If `id` is set, a reconnecting client reports it back
+    /// as `last_event_id` in [`HttpServerRequest::EventStreamOpen`].
+    EventStreamPush {
+        channel_id: u32,
+        event: Option<String>,
+        id: Option<String>,
+    },
+    /// Sending will close an event stream the process controls.
+    EventStreamClose(u32),
+    /// Mint a revocable API token called `name`. The token authenticates requests to
+    /// authenticated paths covered by one of `scopes`, each either a path prefix such as
+    /// `/main:app-store:sys/apps` or a process ID, which covers every path that process
+    /// binds. If `read_only`, the token only authenticates GET and HEAD requests.
+    /// The token is returned as the lazy_load_blob of the Response, and cannot be
+    /// retrieved again. Only accepted from `settings:settings:sys`.
+    CreateApiToken {
+        name: String,
+        scopes: Vec<String>,
+        read_only: bool,
+    },
+    /// Revoke the API token called `name`. Only accepted from `settings:settings:sys`.
+    RevokeApiToken { name: String },
+    /// List all API tokens. The Response lazy_load_blob contains a JSON-serialized
+    /// `Vec<ApiTokenInfo>`. Only accepted from `settings:settings:sys`.
+    ListApiTokens,
+    /// Rotate the secret that auth tokens are signed with, logging out every
+    /// browser and paired client. API tokens are unaffected and must be revoked
+    /// separately. Only accepted from `settings:settings:sys`.
+    RotateJwtSecret,
+    /// Serve the paths of `process` at `host`, such as `chess.mynode.example`, or at
+    /// every subdomain of a wildcard such as `*.chess.example`: a request for `/x`
+    /// there is handled as a request for `/{process}/x`. Replaces any existing
+    /// configuration of `host`. If a `certificate` is given, `host` is also served
+    /// with it over TLS, when the node is booted with `--https-port`.
+    /// Only accepted from `settings:settings:sys`.
+    SetDomain {
+        host: String,
+        process: ProcessId,
+        certificate: Option<TlsCertificate>,
+    },
+    /// Stop serving a host set with [`HttpServerAction::SetDomain`].
+    /// Only accepted from `settings:settings:sys`.
+    RemoveDomain { host: String },
+    /// List the hosts set with [`HttpServerAction::SetDomain`]. The Response
+    /// lazy_load_blob contains a JSON-serialized `Vec<DomainInfo>`.
+    /// Only accepted from `settings:settings:sys`.
+    ListDomains,
+    /// Read the access log kept when the node is booted with `--http-access-log`,
+    /// oldest entry first. Only entries at or after `since`, a UNIX timestamp in
+    /// milliseconds, and for paths starting with `path_prefix` are returned, and of
+    /// those only the latest `limit`. The Response lazy_load_blob contains a
+    /// JSON-serialized `Vec<AccessLogEntry>`. Only accepted from `settings:settings:sys`.
+    QueryAccessLog {
+        since: Option<u64>,
+        path_prefix: Option<String>,
+        limit: Option<u32>,
+    },
+}
+
+/// A certificate chain and its private key, both PEM-encoded.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TlsCertificate {
+    pub cert_chain: String,
+    pub private_key: String,
+}
+
+impl std::fmt::Debug for TlsCertificate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never log the private key
+        f.debug_struct("TlsCertificate").finish_non_exhaustive()
+    }
+}
+
+/// Description of a host set with [`HttpServerAction::SetDomain`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DomainInfo {
+    pub host: String,
+    pub process: ProcessId,
+    /// whether the host is served over TLS with its own certificate
+    pub tls: bool,
+}
+
+/// A request served by the HTTP server, as recorded in its access log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AccessLogEntry {
+    /// UNIX timestamp in milliseconds at which the response was sent
+    pub timestamp: u64,
+    pub method: String,
+    pub host: Option<String>,
+    pub path: String,
+    pub status: u16,
+    /// time taken to respond, in milliseconds
+    pub latency_ms: u64,
+    pub source_ip: Option<String>,
+    /// who the request was authenticated as: the node, for requests with a valid
+    /// auth token, or `api-token:{name}` for requests with an API token
+    pub user: Option<String>,
+}
+
+/// Description of an API token minted with [`HttpServerAction::CreateApiToken`].
+/// The token itself is never stored, only its hash.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiTokenInfo {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub read_only: bool,
+    /// UNIX timestamp in seconds at which the token was minted
+    pub created: u64,
 }
 
 /// Whether the WebSocketPush is a request or a response.
@@ -175,10 +471,28 @@ pub enum HttpServerError {
     NoBlob,
     #[error("path binding error: invalid source process")]
     InvalidSourceProcess,
+    #[error("path binding error: a CORS policy can't allow credentials from any origin")]
+    InvalidCorsPolicy,
     #[error("WebSocket error: ping/pong message too long")]
     WsPingPongTooLong,
     #[error("WebSocket error: channel not found")]
     WsChannelNotFound,
+    #[error("event stream error: channel not found")]
+    EventStreamChannelNotFound,
+    #[error("API token error: a token with this name already exists")]
+    ApiTokenExists,
+    #[error("API token error: no token with this name")]
+    ApiTokenNotFound,
+    #[error("JWT secret could not be rotated")]
+    JwtSecretNotRotated,
+    #[error("domain error: not a valid hostname")]
+    InvalidDomain,
+    #[error("domain error: no such domain")]
+    DomainNotFound,
+    #[error("domain error: certificate chain or private key could not be parsed")]
+    InvalidCertificate,
+    #[error("access log error: the node was not booted with --http-access-log")]
+    AccessLogDisabled,
 }
 
 /// Structure sent from client websocket to this server upon opening a new connection.
@@ -195,6 +509,57 @@ pub struct WsRegisterResponse {
     pub channel_id: u32,
 }
 
+/// Pairing payload returned from `GET /pair` to a logged-in user. The JSON
+/// serialization of this struct is the content of the pairing QR code that a
+/// companion client scans, returned as an SVG image for `GET /pair?qr=svg`.
+/// The token is single-use and short-lived.
+/// `GET /pair?subdomain={process}` scopes the pairing to a process.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PairingPayload {
+    pub node: String,
+    /// base URL the companion client should use to reach this node
+    pub url: String,
+    pub token: String,
+    /// UNIX timestamp in seconds after which the token can no longer be redeemed
+    pub expiration: u64,
+    /// If set, the auth token issued for this pairing is scoped to the secure
+    /// subdomain of this process, exactly as with a subdomain login.
+    pub subdomain: Option<String>,
+}
+
+/// Sent by a companion client to `POST /pair` in order to redeem a pairing token.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PairingRequest {
+    pub token: String,
+    /// human-readable name of the client, shown in the terminal on pairing
+    pub client_name: String,
+}
+
+/// Returned from `POST /pair` on success. The client should authenticate all
+/// further requests with an `Authorization: Bearer {auth_token}` header, or
+/// else attach `{cookie_name}={auth_token}` as a cookie.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PairingResponse {
+    pub node: String,
+    pub cookie_name: String,
+    pub auth_token: String,
+}
+
+/// Sent by a logged-in user to `POST /login/password` to change the node password.
+/// Passwords are hashed as for a login.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PasswordChange {
+    /// confirms the change, as does `totp` if a TOTP second factor has been enrolled
+    pub old_password_hash: String,
+    pub new_password_hash: String,
+    #[serde(default)]
+    pub totp: Option<String>,
+    /// If set, every other auth token issued so far stops working,
+    /// logging out other browsers and paired clients.
+    #[serde(default)]
+    pub log_out_everywhere: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JwtClaims {
     pub username: String,
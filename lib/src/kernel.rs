@@ -62,6 +62,10 @@ pub struct ImportKeyfileInfo {
 pub struct LoginInfo {
     pub password_hash: String,
     pub subdomain: Option<String>,
+    /// code from an authenticator app, or a recovery code,
+    /// required if a TOTP second factor has been enrolled
+    #[serde(default)]
+    pub totp: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -437,18 +441,36 @@ pub enum KernelCommand {
     RunProcess(ProcessId),
     /// Kill a running process immediately. This may result in the dropping / mishandling of messages!
     KillProcess(ProcessId),
+    /// Stop delivering messages to a running process, holding them until it is
+    /// resumed. The process itself keeps running, and will wait in `receive()`.
+    /// Runtime processes cannot be suspended.
+    SuspendProcess(ProcessId),
+    /// Deliver the messages held for a suspended process, and resume delivery.
+    ResumeProcess(ProcessId),
     /// RUNTIME ONLY: notify the kernel that the runtime is shutting down and it
     /// should gracefully stop and persist the running processes.
     Shutdown,
     /// Ask kernel to produce debugging information
     Debug(KernelPrint),
+    /// Restart a running process from its Wasm, with the capabilities it holds,
+    /// as on reboot. Messages it has not yet received are dropped.
+    /// Runtime processes cannot be restarted.
+    RestartProcess(ProcessId),
+    /// Start a process left unstarted on boot because its package asked for it
+    /// to start on demand (see [`StartMode::OnDemand`]), as if it had been messaged.
+    WakeProcess(ProcessId),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum KernelPrint {
     ProcessMap,
     Process(ProcessId),
-    HasCap { on: ProcessId, cap: Capability },
+    HasCap {
+        on: ProcessId,
+        cap: Capability,
+    },
+    /// Get the live metrics of every running process
+    Metrics,
 }
 
 /// IPC format for all KernelCommand responses
@@ -459,7 +481,17 @@ pub enum KernelResponse {
     StartedProcess,
     RunProcessError,
     KilledProcess(ProcessId),
+    SuspendedProcess(ProcessId),
+    ResumedProcess(ProcessId),
+    /// The process is not running, or is a runtime process
+    SuspendProcessError,
     Debug(KernelPrintResponse),
+    RestartedProcess(ProcessId),
+    /// The process is not running, is a runtime process, or its Wasm could not be read
+    RestartProcessError,
+    WokeProcess(ProcessId),
+    /// The process is not one waiting to start on demand
+    WakeProcessError,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -467,6 +499,28 @@ pub enum KernelPrintResponse {
     ProcessMap(UserspaceProcessMap),
     Process(Option<UserspacePersistedProcess>),
     HasCap(Option<bool>),
+    Metrics(HashMap<ProcessId, ProcessMetrics>),
+}
+
+/// Live metrics the kernel keeps for a running process
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProcessMetrics {
+    /// messages delivered to the process that it has not yet received
+    pub queue_depth: usize,
+    /// messages delivered to the process since it started
+    pub messages_received: u64,
+    /// messages sent by the process since it started
+    pub messages_sent: u64,
+    /// unix time, in seconds, at which the process last sent or was sent a message
+    pub last_activity: Option<u64>,
+    /// bytes of Wasm linear memory the process has; none for runtime processes
+    pub memory_bytes: Option<u64>,
+    /// whether messages to the process are being held (see `KernelCommand::SuspendProcess`)
+    pub suspended: bool,
+    /// the most messages that may wait for the process; none for runtime processes
+    pub queue_limit: Option<usize>,
+    /// requests to the process dropped or rejected because its queue was full
+    pub dropped_messages: u64,
 }
 
 #[derive(Debug)]
@@ -638,4 +692,52 @@ pub struct PackageManifestEntry {
     pub request_capabilities: Vec<serde_json::Value>,
     pub grant_capabilities: Vec<serde_json::Value>,
     pub public: bool,
+    #[serde(default)]
+    pub start: StartMode,
+    #[serde(default)]
+    pub queue: QueuePolicy,
+}
+
+/// How many messages may wait for a process to receive them, and what becomes of
+/// a request sent to it when that many are waiting. Responses are always let in,
+/// since they answer requests the process made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueuePolicy {
+    /// at most 10,000; a larger limit is lowered to it
+    pub limit: usize,
+    pub on_full: OnFullQueue,
+}
+
+impl Default for QueuePolicy {
+    fn default() -> Self {
+        QueuePolicy {
+            limit: 100,
+            on_full: OnFullQueue::Block,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OnFullQueue {
+    /// drop the oldest request that the process has not yet been handed, to
+    /// make room for the new one; if it has been handed every one, drop the new one
+    DropOldest,
+    /// drop the new request, answering it, if it expects a response, with a
+    /// queue-full error; a request from another node gets no answer at all
+    Reject,
+    /// hold up the kernel until the process makes room, and so every other
+    /// message being routed with it; while the process is suspended, as `Reject`
+    Block,
+}
+
+/// When the kernel starts a process of a package after the node boots
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StartMode {
+    /// along with every other process
+    #[default]
+    OnBoot,
+    /// when the first message for it arrives, or the first HTTP request for a
+    /// path of its, so that an app that is rarely used takes no memory until then
+    OnDemand,
 }
@@ -1,4 +1,4 @@
-use crate::types::core::PackageId;
+use crate::types::core::{PackageId, ReplicationRole, ReplicationStatus};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -37,6 +37,9 @@ pub enum KvAction {
     /// # Parameters
     /// * `key` - The key as a byte vector
     /// * `tx_id` - Optional transaction ID if this operation is part of a transaction
+    /// * `ttl_ms` - Optional time to live: the key expires this many milliseconds
+    ///   after it is set, after which it is gone as if deleted. Setting a key
+    ///   without one makes it last until deleted, whatever it had before.
     /// * blob: [`Vec<u8>`] - Byte vector to store for the key
     ///
     /// Using this action requires the sender to have the write capability
@@ -44,7 +47,11 @@ pub enum KvAction {
     ///
     /// A successful set will respond with [`KvResponse::Ok`]. Any error will be
     /// contained in the [`KvResponse::Err`] variant.
-    Set { key: Vec<u8>, tx_id: Option<u64> },
+    Set {
+        key: Vec<u8>,
+        tx_id: Option<u64>,
+        ttl_ms: Option<u64>,
+    },
     /// Deletes a key-value pair from the database.
     ///
     /// # Parameters
@@ -82,12 +89,111 @@ pub enum KvAction {
     /// A successful commit will respond with [`KvResponse::Ok`]. Any error will be
     /// contained in the [`KvResponse::Err`] variant.
     Commit { tx_id: u64 },
+    /// Lists the keys starting with a prefix, with their values, in order of their
+    /// bytes, a page at a time.
+    ///
+    /// # Parameters
+    /// * `prefix` - The prefix of the keys listed, empty for all keys
+    /// * `cursor` - The `next_cursor` of the previous page, or `None` for the first
+    /// * `limit` - The maximum number of entries in a page, and at most 1000
+    ///
+    /// Using this action requires the sender to have the read capability
+    /// for the database.
+    ///
+    /// A successful scan will respond with [`KvResponse::Scan`], where the response
+    /// blob contains the entries of the page as a JSON array of [`KvEntry`].
+    /// Any error will be contained in the [`KvResponse::Err`] variant.
+    Scan {
+        prefix: Vec<u8>,
+        cursor: Option<Vec<u8>>,
+        limit: u64,
+    },
+    /// Sets the value of a key only if its current value is the one expected,
+    /// atomically, so that concurrent writers don't overwrite each other.
+    ///
+    /// # Parameters
+    /// * `key` - The key as a byte vector
+    /// * `expected` - The value the key must have, or `None` if it must not exist
+    /// * `ttl_ms` - Optional time to live, as for [`KvAction::Set`]
+    /// * blob: [`Vec<u8>`] - Byte vector to store for the key. If absent, the key is deleted.
+    ///
+    /// Using this action requires the sender to have the write capability
+    /// for the database.
+    ///
+    /// A successful compare-and-swap will respond with [`KvResponse::CompareAndSwap`],
+    /// where the response blob contains the value of the key if it was not swapped
+    /// and has one. Any error will be contained in the [`KvResponse::Err`] variant.
+    CompareAndSwap {
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        ttl_ms: Option<u64>,
+    },
+    /// Opens an existing encrypted key-value database or creates a new one, encrypted,
+    /// if it doesn't exist, as [`KvAction::Open`] otherwise does. Values are encrypted
+    /// at rest with a key derived from the node's keyfile and the package ID, and
+    /// decrypted as they are read. Keys are NOT encrypted: they are stored in
+    /// plaintext, so that they can be scanned in order, and anyone with access to the
+    /// disk can read them, so they must hold nothing sensitive. Only processes of the
+    /// package that created an encrypted database can access it, whatever
+    /// capabilities others are given.
+    ///
+    /// A successful open will respond with [`KvResponse::Ok`]. If the database exists
+    /// and is not encrypted, this fails with [`KvError::NotEncrypted`]. Any error will
+    /// be contained in the [`KvResponse::Err`] variant.
+    OpenEncrypted,
+    /// Sets the part the database plays in replication, or stops replicating it if
+    /// `None`. A leader logs each batch of writes to the database, as a set or
+    /// delete or a committed transaction, and streams the log to the follower in
+    /// order, resuming after the last batch the follower applied if either node
+    /// restarts or the two lose touch. Becoming leader logs all keys the database
+    /// holds first, so the follower starts with a copy of it.
+    /// Requires `package_id` in [`KvRequest`] to match the package ID of the sender.
+    ///
+    /// A successful set will respond with [`KvResponse::Ok`]. Following fails with
+    /// [`KvError::NotEmpty`] if the database holds any keys. Any error will be
+    /// contained in the [`KvResponse::Err`] variant.
+    SetReplication(Option<ReplicationRole>),
+    /// Gets the part the database plays in replication, and how far it has got.
+    ///
+    /// Using this action requires the sender to have the read capability
+    /// for the database.
+    ///
+    /// A successful get will respond with [`KvResponse::Replication`]. Any error will
+    /// be contained in the [`KvResponse::Err`] variant.
+    GetReplication,
+    /// Batches of writes streamed from a leader to its follower, in order. Only
+    /// accepted from the `kv:distro:sys` of the node the database follows.
+    ///
+    /// Responds with [`KvResponse::Replicated`]. Any error will be contained in the
+    /// [`KvResponse::Err`] variant.
+    Replicate(Vec<KvReplicatedBatch>),
+}
+
+/// A batch of writes to a database, applied together, as replicated to a follower.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KvReplicatedBatch {
+    pub seq: u64,
+    pub writes: Vec<KvWrite>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum KvWrite {
+    /// `expires_at` is in milliseconds since the unix epoch
+    Set {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+    },
+    Delete {
+        key: Vec<u8>,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum KvResponse {
     /// Indicates successful completion of an operation.
-    /// Sent in response to actions Open, RemoveDb, Set, Delete, and Commit.
+    /// Sent in response to actions Open, RemoveDb, Set, Delete, Commit, OpenEncrypted,
+    /// and SetReplication.
     Ok,
     /// Returns the transaction ID for a newly created transaction.
     ///
@@ -102,6 +208,31 @@ pub enum KvResponse {
     Get(Vec<u8>),
     /// Indicates an error occurred during the operation.
     Err(KvError),
+    /// Returns a page of the keys scanned.
+    ///
+    /// # Fields
+    /// * `next_cursor` - The cursor of the next page, or `None` if this is the last
+    /// * blob: JSON array of [`KvEntry`] - The entries of the page
+    Scan { next_cursor: Option<Vec<u8>> },
+    /// Returns whether the value was swapped.
+    ///
+    /// # Fields
+    /// * `swapped` - Whether the key had the value expected, and so was set
+    /// * blob: [`Vec<u8>`] - The value of the key, if it was not swapped and has one
+    CompareAndSwap { swapped: bool },
+    /// Returns the part the database plays in replication, and how far it has got.
+    Replication(ReplicationStatus),
+    /// Returns the sequence number of the last batch of writes the follower has applied.
+    Replicated { applied_seq: u64 },
+}
+
+/// A key and its value, as listed by [`KvAction::Scan`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KvEntry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    /// when the key expires, in milliseconds since the unix epoch, if it has a time to live
+    pub expires_at: Option<u64>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Error)]
@@ -126,6 +257,18 @@ pub enum KvError {
     RocksDBError(String),
     #[error("IO error: {0}")]
     IOError(String),
+    #[error("db exists and is not encrypted")]
+    NotEncrypted,
+    #[error("db is encrypted, and only accessible to the package that created it")]
+    EncryptedDb,
+    #[error("failed to decrypt value")]
+    DecryptionFailed,
+    #[error("db follows another node, and only takes writes from it")]
+    Follower,
+    #[error("db does not follow the node that sent writes to it")]
+    UnexpectedReplication,
+    #[error("db must be empty to follow another node")]
+    NotEmpty,
 }
 
 /// The JSON parameters contained in all capabilities issued by `kv:distro:sys`.
@@ -1,3 +1,5 @@
+mod backup;
+mod contacts;
 pub mod core;
 pub mod eth;
 mod fd_manager;
@@ -5,10 +7,13 @@ mod http;
 mod kernel;
 mod kv;
 mod net;
+mod notify;
+mod replication;
 mod sqlite;
 mod state;
 mod timer;
 mod vfs;
+mod wallet;
 
 pub mod types {
     pub use crate::core;
@@ -29,6 +29,56 @@ pub enum NetAction {
     /// the PKI, will not verify.
     /// **the `from` [`Address`] will always be prepended to the payload**
     Verify { from: Address, signature: Vec<u8> },
+    /// close any connection with the given node, and refuse to connect or
+    /// exchange messages with it until unblocked.
+    /// **only accepted from our own node**
+    BlockPeer(String),
+    /// **only accepted from our own node**
+    UnblockPeer(String),
+    /// allow the given node to connect with us in allowlist-only mode.
+    /// **only accepted from our own node**
+    AllowPeer(String),
+    /// **only accepted from our own node**
+    DisallowPeer(String),
+    /// if true, only connect and exchange messages with allowed nodes and our
+    /// own routers, closing any connection with other nodes.
+    /// **only accepted from our own node**
+    SetAllowlistOnly(bool),
+    /// get the [`PeerAccess`] settings
+    GetPeerAccess,
+    /// get the [`PeerStats`] of every peer we have exchanged messages with,
+    /// or tried to, since boot
+    GetPeerStats,
+    /// Received over a routed connection from an indirect node that wants to replace
+    /// it with a direct one. Contains the public address the sender will connect to
+    /// us from, as one of its routers observed it. We should respond (if we desire)
+    /// with [`NetResponse::HolePunch`] and then connect to that address, while the
+    /// sender connects to ours.
+    HolePunch(ObservedAddress),
+    /// get the [`RouterScore`] of every router we have probed or routed through
+    GetRouterScores,
+    /// hold requests that expect no response for nodes we can't reach, and
+    /// retry them until they are delivered or expire. None turns this off,
+    /// failing any held requests as offline.
+    /// **only accepted from our own node**
+    SetOfflineQueue(Option<OfflineQueueConfig>),
+    /// get the [`OfflineQueue`] settings and contents
+    GetOfflineQueue,
+    /// Simulate a bad network between us and a node, or None to stop.
+    /// Only nodes built in simulation mode take this; others ignore it.
+    /// **only accepted from our own node**
+    SetNetworkConditions(NodeId, Option<NetworkConditions>),
+    /// get the [`NetworkConditions`] we simulate for each node
+    GetNetworkConditions,
+    /// check whether we can be reached: that our ports are open if we are a
+    /// direct node, that we are connected to our routers if indirect, and that
+    /// our KNS record matches how we are running
+    RunDiagnostics,
+    /// save the routers we go online with, or none to go online as a direct
+    /// node, taking effect when the node next boots. Our KNS record should be
+    /// updated to match first, as routing is checked against it at boot.
+    /// **only accepted from `settings:settings:sys`**
+    SetRouters(Vec<NodeId>),
 }
 
 /// Must be parsed from message pack vector
@@ -51,6 +101,169 @@ pub enum NetResponse {
     /// cannot be found in our representation of PKI, this will return false,
     /// because we cannot find the networking public key to verify with.
     Verified(bool),
+    /// response to [`NetAction::BlockPeer`], [`NetAction::UnblockPeer`],
+    /// [`NetAction::AllowPeer`], [`NetAction::DisallowPeer`],
+    /// [`NetAction::SetAllowlistOnly`] and [`NetAction::GetPeerAccess`],
+    /// containing the settings after the action.
+    PeerAccess(PeerAccess),
+    /// response to [`NetAction::GetPeerStats`], sorted by peer name
+    PeerStats(Vec<PeerStats>),
+    /// response to [`NetAction::HolePunch`], containing the public address
+    /// the responder will connect from, as one of its routers observed it
+    HolePunch(ObservedAddress),
+    /// response to [`NetAction::GetRouterScores`], healthiest first
+    RouterScores(Vec<RouterScore>),
+    /// response to [`NetAction::SetOfflineQueue`] and [`NetAction::GetOfflineQueue`]
+    OfflineQueue(OfflineQueue),
+    /// Sent over a connection that has gone quiet, containing the UNIX time in
+    /// milliseconds it was sent at. Answered with a [`NetResponse::KeepalivePong`]
+    /// by the other end of the connection, never passed on to the kernel.
+    KeepalivePing(u64),
+    /// response to [`NetResponse::KeepalivePing`], echoing its time
+    KeepalivePong(u64),
+    /// Sent by each end of a new connection, offering to take compressed messages.
+    /// Messages over the larger of the two thresholds are then sent compressed,
+    /// with the first of the sender's algorithms the receiver offered.
+    CompressionOffer {
+        /// in order of preference
+        algorithms: Vec<CompressionAlgorithm>,
+        /// serialized size in bytes of the smallest message worth compressing
+        threshold: u64,
+    },
+    /// A serialized [`crate::core::KernelMessage`], compressed. Unpacked by the
+    /// other end of the connection, which passes on the message inside.
+    Compressed {
+        algorithm: CompressionAlgorithm,
+        /// uncompressed size in bytes
+        len: u64,
+        data: Vec<u8>,
+    },
+    /// response to [`NetAction::SetNetworkConditions`] and [`NetAction::GetNetworkConditions`]
+    NetworkConditions(BTreeMap<NodeId, NetworkConditions>),
+    /// response to [`NetAction::RunDiagnostics`]
+    DiagnosticsReport(Vec<DiagnosticCheck>),
+    /// response to [`NetAction::SetRouters`], with why the routers could not
+    /// be saved, if they couldn't
+    SetRouters(Result<(), String>),
+}
+
+/// How messages between nodes may be compressed, see [`NetResponse::CompressionOffer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionAlgorithm {
+    Zstd,
+    Lz4,
+}
+
+/// Traffic with a peer since boot. Counts cover every connection with the peer,
+/// not just the current one.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PeerStats {
+    pub name: NodeId,
+    /// whether we have a connection with the peer now
+    pub connected: bool,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// UNIX timestamp in seconds of the last message sent or received, or 0 if none
+    pub last_activity: u64,
+    /// attempts to connect to the peer that failed
+    pub connection_failures: u64,
+    /// connections with the peer closed by an error sending or receiving,
+    /// or by the peer no longer answering keepalive pings
+    pub connection_errors: u64,
+    /// smoothed round trip time in milliseconds of keepalive pings, or None
+    /// if the peer has never answered one
+    pub rtt_ms: Option<u64>,
+}
+
+/// The public address of a node behind NAT, as seen by a router that it connected
+/// to, and signed by that router. A node punching through NAT only connects to an
+/// address observed by one of the routers the PKI lists for the other node, so
+/// that it can't be told to connect to anywhere else.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ObservedAddress {
+    pub address: String,
+    pub observer: NodeId,
+    /// the observer's signature of `address`, prefixed with `observe-address`
+    pub signature: Vec<u8>,
+}
+
+/// How well a router has worked for us since boot. Routers with higher scores
+/// are tried first, both when connecting to our own routers and when
+/// connecting to an indirect node through one of theirs.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RouterScore {
+    pub name: NodeId,
+    /// smoothed time in milliseconds to connect to the router and hear back,
+    /// or None if we never have
+    pub latency_ms: Option<u64>,
+    /// probes and connections through the router that worked
+    pub successes: u64,
+    /// probes and connections through the router that failed
+    pub failures: u64,
+    /// failures since the last success
+    pub consecutive_failures: u64,
+    /// UNIX timestamp in seconds of the last probe, or 0 if none
+    pub last_probed: u64,
+    /// from 0 to 1, higher is healthier
+    pub score: f64,
+}
+
+/// Conditions simulated for the messages we send to a node, for testing how
+/// processes cope with a bad network. Applied in simulation mode only, and
+/// forgotten on reboot.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NetworkConditions {
+    /// milliseconds each message is held back before it is sent
+    pub latency_ms: u64,
+    /// up to this many milliseconds are added to or taken from the latency of
+    /// each message at random, so messages may arrive out of order
+    pub jitter_ms: u64,
+    /// chance from 0 to 1 that a message is dropped without an error
+    pub loss: f64,
+    /// treat the node as offline: our connection with them is closed, messages
+    /// to them fail as offline, and their connections to us are refused
+    pub disconnected: bool,
+}
+
+/// One check made by [`NetAction::RunDiagnostics`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DiagnosticCheck {
+    /// what was checked, such as `ws port 9000 reachable`
+    pub name: String,
+    pub passed: bool,
+    /// what was found, and if the check failed, what to do about it
+    pub detail: String,
+}
+
+/// Limits on the requests held for nodes we can't reach.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OfflineQueueConfig {
+    /// the most requests held for any one node; past it, requests fail as offline
+    pub max_per_peer: u32,
+    /// the most requests held in all; past it, requests fail as offline
+    pub max_total: u32,
+    /// seconds after which a held request fails as offline
+    pub ttl: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OfflineQueue {
+    /// None if requests to nodes we can't reach fail right away
+    pub config: Option<OfflineQueueConfig>,
+    /// how many requests are held for each node
+    pub queued: BTreeMap<NodeId, u64>,
+}
+
+/// Which nodes we connect and exchange messages with.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PeerAccess {
+    /// nodes we never connect or exchange messages with
+    pub blocked: Vec<String>,
+    /// nodes we connect with in allowlist-only mode
+    pub allowed: Vec<String>,
+    pub allowlist_only: bool,
 }
 
 //
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// IPC Request format for the notify:distro:sys runtime module, which turns events
+/// on the node into notifications, and delivers them through the channels picked in
+/// the settings app: the homepage's inbox, notifications:homepage:sys, where they
+/// are listed under the settings app, email, and a webhook.
+///
+/// Reporting an event takes only the messaging capability, as given to the App
+/// Store. Every other action requires the root capability, `{"root":true}`, as
+/// given to the settings app.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum NotifyAction {
+    /// Report an event, which is notified if its kind is enabled. Expects no response.
+    Event(NotifyEvent),
+    /// Responds with [`NotifyResponse::Settings`]. The SMTP password is left out.
+    GetSettings,
+    /// Replace the settings. An empty SMTP password keeps the saved one.
+    /// Responds with [`NotifyResponse::Ok`].
+    SetSettings(NotifySettings),
+    /// Send a test notification by email and webhook, if set, without keeping it.
+    /// Responds with [`NotifyResponse::Tested`].
+    Test,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum NotifyResponse {
+    Ok,
+    Settings(NotifySettings),
+    Tested(Vec<ChannelTest>),
+    Err(NotifyError),
+}
+
+/// An event that may be notified.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NotifyEvent {
+    /// a newer version of an installed app was published, and is not installed
+    /// automatically
+    UpdateAvailable {
+        package_id: String,
+        version: String,
+        /// what is new in the version, if its publisher said
+        #[serde(default)]
+        notes: Option<String>,
+    },
+    /// an automatic update of an app failed to download or to install
+    AutoUpdateFailed {
+        package_id: String,
+        version_hash: String,
+        error: String,
+    },
+    /// the disk holding the home directory has less free space than set in
+    /// [`NotifySettings::low_disk_bytes`]; checked by notify:distro:sys itself
+    LowDisk {
+        available_bytes: u64,
+        total_bytes: u64,
+    },
+    /// a login to the web UI, as reported by http-server:distro:sys, notified
+    /// only from IPs not logged in from before
+    Login { ip: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum NotifyEventKind {
+    UpdateAvailable,
+    AutoUpdateFailed,
+    LowDisk,
+    NewLoginIp,
+}
+
+impl NotifyEvent {
+    pub fn kind(&self) -> NotifyEventKind {
+        match self {
+            NotifyEvent::UpdateAvailable { .. } => NotifyEventKind::UpdateAvailable,
+            NotifyEvent::AutoUpdateFailed { .. } => NotifyEventKind::AutoUpdateFailed,
+            NotifyEvent::LowDisk { .. } => NotifyEventKind::LowDisk,
+            NotifyEvent::Login { .. } => NotifyEventKind::NewLoginIp,
+        }
+    }
+
+    /// A short heading for the notification of the event, as shown in the inbox.
+    pub fn title(&self) -> &'static str {
+        match self {
+            NotifyEvent::UpdateAvailable { .. } => "Update available",
+            NotifyEvent::AutoUpdateFailed { .. } => "Auto-update failed",
+            NotifyEvent::LowDisk { .. } => "Low disk space",
+            NotifyEvent::Login { .. } => "Login from a new IP",
+        }
+    }
+}
+
+impl std::fmt::Display for NotifyEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NotifyEvent::UpdateAvailable {
+                package_id,
+                version,
+                notes,
+            } => {
+                write!(f, "version {version} of {package_id} is available")?;
+                match notes {
+                    Some(notes) => write!(f, ". what's new:\n{notes}"),
+                    None => Ok(()),
+                }
+            }
+            NotifyEvent::AutoUpdateFailed {
+                package_id,
+                version_hash,
+                error,
+            } => write!(
+                f,
+                "failed to auto-update {package_id} to the version with hash {version_hash}: {error}"
+            ),
+            NotifyEvent::LowDisk {
+                available_bytes,
+                total_bytes,
+            } => write!(
+                f,
+                "low on disk space: {} MB free of {} MB",
+                available_bytes / 1_000_000,
+                total_bytes / 1_000_000
+            ),
+            NotifyEvent::Login { ip } => write!(f, "login from a new IP, {ip}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotifySettings {
+    /// the kinds of events that are notified; others are dropped
+    pub events: Vec<NotifyEventKind>,
+    /// whether notifications are posted to the homepage's inbox, which counts the
+    /// unread ones on the settings app's icon
+    pub badge: bool,
+    pub email: Option<EmailSettings>,
+    /// a URL each notification is POSTed to, as JSON
+    pub webhook: Option<String>,
+    /// free space on the disk of the home directory below which it is low
+    pub low_disk_bytes: u64,
+}
+
+impl Default for NotifySettings {
+    fn default() -> Self {
+        Self {
+            events: vec![
+                NotifyEventKind::UpdateAvailable,
+                NotifyEventKind::AutoUpdateFailed,
+                NotifyEventKind::LowDisk,
+                NotifyEventKind::NewLoginIp,
+            ],
+            badge: true,
+            email: None,
+            webhook: None,
+            low_disk_bytes: 1_000_000_000,
+        }
+    }
+}
+
+/// An SMTP server to send notifications through, over TLS from the start on
+/// port 465, or upgraded to TLS with STARTTLS on any other port.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EmailSettings {
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Notification {
+    /// when it was made, in milliseconds since the epoch
+    pub id: u64,
+    pub event: NotifyEvent,
+    /// the event, described
+    pub message: String,
+}
+
+/// How sending a test notification through a channel went.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChannelTest {
+    /// `email` or `webhook`
+    pub channel: String,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Error)]
+pub enum NotifyError {
+    #[error("missing the root capability")]
+    NoCap,
+    #[error("notify got a malformed request")]
+    MalformedRequest,
+    #[error("IO error: {0}")]
+    IOError(String),
+}
+
+impl From<std::io::Error> for NotifyError {
+    fn from(err: std::io::Error) -> Self {
+        NotifyError::IOError(err.to_string())
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// The part a kv or sqlite database plays in replication, which streams the writes
+/// to a database on one node, the leader, to the database of the same package and
+/// name on another, the follower, such as a second node of the same user kept as
+/// a warm standby. Set by the package that created the database, on both nodes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplicationRole {
+    /// Stream the writes to the database to `follower`, starting with all it holds.
+    Leader { follower: String },
+    /// Take writes to the database only from `leader`: local processes can only
+    /// read it. Following must start with an empty database.
+    Follower { leader: String },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplicationStatus {
+    pub role: Option<ReplicationRole>,
+    /// the sequence number of the last batch of writes logged, as leader,
+    /// or applied, as follower
+    pub last_seq: u64,
+    /// as leader, the sequence number of the last batch the follower has applied
+    pub acked_seq: u64,
+}
@@ -1,4 +1,4 @@
-use crate::types::core::PackageId;
+use crate::types::core::{PackageId, ReplicationRole, ReplicationStatus};
 use rusqlite::types::{FromSql, FromSqlError, ToSql, ValueRef};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -84,14 +84,195 @@ pub enum SqliteAction {
     /// A successful commit will respond with [`SqliteResponse::Ok`]. Any error will be
     /// contained in the [`SqliteResponse::Err`] variant.
     Commit { tx_id: u64 },
+    /// Copies the database to a file, with SQLite's online backup API, so that the
+    /// copy is consistent and writes to the database carry on while it is made.
+    ///
+    /// # Parameters
+    /// * `dest_path` - VFS path of the file to copy to, in a drive of the sender's
+    ///   package, e.g. `/my-app:publisher.os/backups/my.db`. Any file there is replaced.
+    ///   It is written through the VFS, so the drive's quota, encryption and
+    ///   compression apply to it.
+    ///
+    /// Using this action requires the sender to have the read capability
+    /// for the database.
+    ///
+    /// A successful backup will respond with [`SqliteResponse::Ok`]. Any error will be
+    /// contained in the [`SqliteResponse::Err`] variant.
+    Backup { dest_path: String },
+    /// Replaces the contents of the database with those of a backup made with
+    /// [`SqliteAction::Backup`]. Other actions on the database wait until it is done.
+    ///
+    /// # Parameters
+    /// * `src_path` - VFS path of the backup, in a drive of the sender's package
+    ///
+    /// Using this action requires the sender to have the write capability
+    /// for the database.
+    ///
+    /// A successful restore will respond with [`SqliteResponse::Ok`]. Any error will be
+    /// contained in the [`SqliteResponse::Err`] variant.
+    Restore { src_path: String },
+    /// Brings the database's schema up to date by applying the migrations it has not
+    /// had yet, in order, in one transaction: either all of them are applied, or none.
+    ///
+    /// # Parameters
+    /// * `migrations` - SQL scripts of one or more statements each, in the order they
+    ///   are applied. The version of the database is the number of them applied, kept
+    ///   in its `user_version`, so once released, a migration must never be edited or
+    ///   removed: changes are made by appending new ones.
+    ///
+    /// Using this action requires the sender to have the write capability
+    /// for the database.
+    ///
+    /// A successful migration will respond with [`SqliteResponse::Migrated`]. Any error
+    /// will be contained in the [`SqliteResponse::Err`] variant.
+    Migrate { migrations: Vec<String> },
+    /// Creates an FTS5 full-text search index, if it does not exist, for [`SqliteAction::Search`].
+    ///
+    /// # Parameters
+    /// * `index` - Name of the index, a virtual table that can also be written to and
+    ///   queried as any other
+    /// * `columns` - Names of the columns indexed
+    /// * `content` - A table of the database to index the `columns` of, kept in sync
+    ///   by triggers as its rows are inserted, updated and deleted. Rows already in
+    ///   it are indexed on creation. If `None`, rows are written to the index itself.
+    /// * `tokenizer` - FTS5 tokenizer and its arguments, e.g. `porter unicode61`
+    ///   to match words with the same stem. Defaults to `unicode61`.
+    ///
+    /// Using this action requires the sender to have the write capability
+    /// for the database.
+    ///
+    /// A successful creation will respond with [`SqliteResponse::Ok`]. Any error will be
+    /// contained in the [`SqliteResponse::Err`] variant.
+    CreateSearchIndex {
+        index: String,
+        columns: Vec<String>,
+        content: Option<String>,
+        tokenizer: Option<String>,
+    },
+    /// Searches an index made with [`SqliteAction::CreateSearchIndex`], best matches first.
+    ///
+    /// # Parameters
+    /// * `index` - Name of the index
+    /// * `query` - FTS5 query, e.g. `sqlite AND (search OR index)`, `title:kinode`
+    ///   or `"exact phrase"`. Text entered by users should be quoted as a phrase,
+    ///   doubling any `"` in it.
+    /// * `limit` - Maximum number of rows returned, all if `None`
+    /// * `offset` - Number of best rows skipped, for paging through results
+    ///
+    /// Using this action requires the sender to have the read capability
+    /// for the database.
+    ///
+    /// A successful search will respond with [`SqliteResponse::Read`], where the
+    /// response blob contains the matching rows, each with its `rowid`, its columns,
+    /// and its `rank`, lower for better matches. Any error will be contained in the
+    /// [`SqliteResponse::Err`] variant.
+    Search {
+        index: String,
+        query: String,
+        limit: Option<u64>,
+        offset: Option<u64>,
+    },
+    /// Discards all operations in the specified transaction, none of which are applied.
+    ///
+    /// # Parameters
+    /// * `tx_id` - The ID of the transaction to roll back
+    ///
+    /// Using this action requires the sender to have the write capability
+    /// for the database.
+    ///
+    /// A successful rollback will respond with [`SqliteResponse::Ok`]. Any error will be
+    /// contained in the [`SqliteResponse::Err`] variant.
+    Rollback { tx_id: u64 },
+    /// Executes many write statements, in order, in one transaction: either all of
+    /// them are applied, or none. Each distinct statement is prepared once, so a
+    /// statement repeated with different parameters, as in inserting many rows, is
+    /// only parsed the first time.
+    ///
+    /// * `statements` - SQL statements to execute, each as for [`SqliteAction::Write`]
+    /// * `tx_id` - Optional transaction ID, to add the statements to instead
+    /// * blob: Vec<Vec<SqlValue>> - Parameters for each of the statements, in the
+    ///   same order, as for [`SqliteAction::Write`]. If absent, no statement has any.
+    ///
+    /// Using this action requires the sender to have the write capability
+    /// for the database.
+    ///
+    /// A successful write will respond with [`SqliteResponse::Ok`]. Any error will be
+    /// contained in the [`SqliteResponse::Err`] variant.
+    WriteBatch {
+        statements: Vec<String>,
+        tx_id: Option<u64>,
+    },
+    /// Opens an existing encrypted database or creates a new one, encrypted, if it
+    /// doesn't exist, as [`SqliteAction::Open`] otherwise does. The database is
+    /// encrypted at rest with SQLCipher, with a key derived from the node's keyfile
+    /// and the package ID, and decrypted as it is read. Only processes of the package
+    /// that created an encrypted database can access it, whatever capabilities others
+    /// are given. Its backups, made with [`SqliteAction::Backup`], are encrypted too.
+    ///
+    /// A successful open will respond with [`SqliteResponse::Ok`]. If the database
+    /// exists and is not encrypted, this fails with [`SqliteError::NotEncrypted`].
+    /// Any error will be contained in the [`SqliteResponse::Err`] variant.
+    OpenEncrypted,
+    /// Sets the part the database plays in replication, or stops replicating it if
+    /// `None`. A leader logs each batch of writes to the database, as a write or a
+    /// committed transaction, a migration or the creation of a search index, and
+    /// streams the log to the follower in order, resuming after the last batch the
+    /// follower applied if either node restarts or the two lose touch. Becoming
+    /// leader logs the schema and all rows of the database first, so the follower
+    /// starts with a copy of it. The log is kept in tables of the database whose
+    /// names start with `_kinode_replication`.
+    ///
+    /// Statements are replayed on the follower as they were executed on the leader,
+    /// so those that give different results each time they are run, as with
+    /// `random()` or `CURRENT_TIMESTAMP`, should be given their values as parameters.
+    /// On a follower, [`SqliteAction::Migrate`] only succeeds once the migrations
+    /// have been streamed to it, and [`SqliteAction::Restore`] can be used on neither.
+    /// Requires `package_id` in [`SqliteRequest`] to match the package ID of the sender.
+    ///
+    /// A successful set will respond with [`SqliteResponse::Ok`]. Following fails with
+    /// [`SqliteError::NotEmpty`] if the database has any tables. Any error will be
+    /// contained in the [`SqliteResponse::Err`] variant.
+    SetReplication(Option<ReplicationRole>),
+    /// Gets the part the database plays in replication, and how far it has got.
+    ///
+    /// Using this action requires the sender to have the read capability
+    /// for the database.
+    ///
+    /// A successful get will respond with [`SqliteResponse::Replication`]. Any error
+    /// will be contained in the [`SqliteResponse::Err`] variant.
+    GetReplication,
+    /// Batches of writes streamed from a leader to its follower, in order. Only
+    /// accepted from the `sqlite:distro:sys` of the node the database follows.
+    ///
+    /// Responds with [`SqliteResponse::Replicated`]. Any error will be contained in
+    /// the [`SqliteResponse::Err`] variant.
+    Replicate(Vec<SqliteReplicatedBatch>),
+}
+
+/// A batch of writes to a database, applied in one transaction, as replicated to a follower.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SqliteReplicatedBatch {
+    pub seq: u64,
+    pub writes: Vec<SqliteWrite>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SqliteWrite {
+    Statement {
+        statement: String,
+        params: Vec<SqlValue>,
+    },
+    /// statements without parameters, as of a migration
+    Script(String),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum SqliteResponse {
     /// Indicates successful completion of an operation.
-    /// Sent in response to actions Open, RemoveDb, Write, Query, BeginTx, and Commit.
+    /// Sent in response to actions Open, RemoveDb, Write, Commit, Backup, Restore,
+    /// CreateSearchIndex, Rollback, WriteBatch, OpenEncrypted, and SetReplication.
     Ok,
-    /// Returns the results of a query.
+    /// Returns the results of a query or search.
     ///
     /// * blob: Vec<Vec<SqlValue>> - Array of rows, where each row contains SqlValue types:
     ///   - null
@@ -108,6 +289,13 @@ pub enum SqliteResponse {
     BeginTx { tx_id: u64 },
     /// Indicates an error occurred during the operation.
     Err(SqliteError),
+    /// Returns the versions of the database before and after a migration,
+    /// which are the same if it was up to date.
+    Migrated { from_version: u64, to_version: u64 },
+    /// Returns the part the database plays in replication, and how far it has got.
+    Replication(ReplicationStatus),
+    /// Returns the sequence number of the last batch of writes the follower has applied.
+    Replicated { applied_seq: u64 },
 }
 
 /// Used in blobs to represent array row values in SQLite.
@@ -147,6 +335,32 @@ pub enum SqliteError {
     RusqliteError(String),
     #[error("IO error: {0}")]
     IOError(String),
+    #[error("backup path is not a file in a drive of the sender's package")]
+    InvalidBackupPath,
+    #[error("migration to version {version} failed: {error}")]
+    MigrationFailed { version: u64, error: String },
+    #[error("db is at version {0}, newer than the migrations given")]
+    NewerThanMigrations(u64),
+    #[error("invalid name for a table or column: {0}")]
+    InvalidIdentifier(String),
+    #[error("invalid search query: {0}")]
+    InvalidSearchQuery(String),
+    #[error("db exists and is not encrypted")]
+    NotEncrypted,
+    #[error("db is encrypted, and only accessible to the package that created it")]
+    EncryptedDb,
+    #[error("db follows another node, and only takes writes from it")]
+    Follower,
+    #[error("db does not follow the node that sent writes to it")]
+    UnexpectedReplication,
+    #[error("db must have no tables to follow another node")]
+    NotEmpty,
+    #[error("db is replicated, and must stop being so to be restored")]
+    Replicated,
+    #[error("db stayed locked by another connection")]
+    Busy,
+    #[error("this node was built without SQLCipher, so cannot open encrypted dbs")]
+    EncryptionUnsupported,
 }
 
 /// The JSON parameters contained in all capabilities issued by `sqlite:distro:sys`.
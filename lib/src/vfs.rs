@@ -17,25 +17,77 @@ pub enum VfsAction {
     OpenFile { create: bool },
     CloseFile,
     Write,
+    // write the blob to a temporary file beside the path, then rename it over the path,
+    // so that a crash mid-write leaves the previous contents rather than a partial file
+    WriteAtomic,
     WriteAll,
+    // write the blob starting at `offset`, without regard to the cursor
+    WriteAt { offset: u64 },
     Append,
     SyncAll,
     Read,
     ReadDir,
     ReadToEnd,
     ReadExact { length: u64 },
+    // read up to `length` bytes starting at `offset`, fewer at the end of the file,
+    // without regard to the cursor: for reading large files in bounded chunks
+    ReadAt { offset: u64, length: u64 },
     ReadToString,
     Seek(SeekFrom),
     RemoveFile,
     RemoveDir,
+    // remove the directory tree at the path. it is renamed out of sight before it is
+    // deleted, so no process sees it partially removed.
     RemoveDirAll,
     Rename { new_path: String },
     Metadata,
     AddZip,
     CopyFile { new_path: String },
+    // copy the directory tree at the path to `new_path`, which must not exist. the copy
+    // is built out of sight and renamed into place, so no process sees it partially copied.
+    // if `progress`, the requester is sent a `VfsProgress` request as files are copied.
+    CopyDir { new_path: String, progress: bool },
+    // move the directory tree at the path to `new_path` in one step, replacing any tree
+    // already there. if the move fails, the replaced tree is restored.
+    MoveDir { new_path: String },
     Len,
     SetLen(u64),
     Hash,
+    // receive a `VfsChange` request whenever the path, or anything under it,
+    // is created, modified, or deleted through the VFS. requires read capability.
+    Watch,
+    // stop receiving changes for a path previously watched
+    Unwatch,
+    // bytes used by the drive at the path, or by every drive if the path is `/`,
+    // which requires root capability
+    DriveUsage,
+    // set or, if None, remove the storage quota in bytes of the drive at the path.
+    // requires root capability
+    SetQuota(Option<u64>),
+    // store the files of the drive at the path zstd-compressed, or not. this is transparent:
+    // reads return, and lengths count, the uncompressed contents. a compressed file is
+    // stored uncompressed once it is accessed at a position within it, until next written.
+    SetCompression(bool),
+    // store the files of the drive at the path encrypted with a key derived from the
+    // keyfile, or not. this is transparent, but files are only ever decrypted in memory, so
+    // whole: actions that use a cursor (`Seek`, `WriteAll`, `ReadExact`) are not supported,
+    // and `ReadToEnd` and `ReadToString` read from the start of the file.
+    SetEncryption(bool),
+    // snapshot the drive at the path, as it is stored, so that it can be restored later.
+    // only the newest few snapshots of a drive are kept, and they count toward its quota
+    Snapshot,
+    ListSnapshots,
+    // replace the contents of the drive at the path with those of one of its snapshots, by id.
+    // the snapshot is kept.
+    RestoreSnapshot(u64),
+    RemoveSnapshot(u64),
+    // find the files and directories of the drive at the path whose names contain `query`,
+    // ignoring case, and if `full_text`, the text files containing every word of `query`.
+    // the drive is indexed when first searched, and the index kept up to date after.
+    Search { query: String, full_text: bool },
+    // bytes used by each package: by its drives, and by its sqlite and kv databases,
+    // the packages using the most first. only at the path `/`, which requires root capability
+    StorageReport,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -65,6 +117,62 @@ pub struct DirEntry {
     pub file_type: FileType,
 }
 
+/// A change to a watched path, sent by the VFS as a request to each process watching it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VfsChange {
+    pub path: String,
+    pub kind: VfsChangeKind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum VfsChangeKind {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// Progress of a long-running tree operation, sent by the VFS as a request to the
+/// process that asked for it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VfsProgress {
+    pub path: String,
+    pub done: u64,
+    pub total: u64,
+}
+
+/// A snapshot of a drive, identified by when it was taken, in milliseconds since the epoch.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VfsSnapshot {
+    pub id: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DriveUsage {
+    /// `/package_id/drive`
+    pub drive: String,
+    pub bytes: u64,
+    pub quota: Option<u64>,
+}
+
+/// The disk used by a package, as reported by [`VfsAction::StorageReport`]:
+/// its drives and databases, each largest first.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PackageStorage {
+    pub package_id: String,
+    /// bytes used by all its drives and databases
+    pub bytes: u64,
+    pub drives: Vec<DriveUsage>,
+    pub sqlite: Vec<DbUsage>,
+    pub kv: Vec<DbUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DbUsage {
+    pub db: String,
+    pub bytes: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum VfsResponse {
     Ok,
@@ -76,6 +184,11 @@ pub enum VfsResponse {
     Metadata(FileMetadata),
     Len(u64),
     Hash([u8; 32]),
+    DriveUsage(Vec<DriveUsage>),
+    Snapshot(VfsSnapshot),
+    Snapshots(Vec<VfsSnapshot>),
+    Search(Vec<DirEntry>),
+    StorageReport(Vec<PackageStorage>),
 }
 
 #[derive(Error, Debug, Serialize, Deserialize)]
@@ -96,6 +209,14 @@ pub enum VfsError {
     IOError(String),
     #[error("non-file non-dir in zip")]
     UnzipError,
+    #[error("drive {drive} would exceed its quota of {quota} bytes")]
+    QuotaExceeded { drive: String, quota: u64 },
+    #[error("action uses a cursor, which files on encrypted drives do not support")]
+    EncryptedDrive,
+    #[error("failed to decrypt file")]
+    DecryptionFailed,
+    #[error("no snapshot {id} of drive {drive}")]
+    NoSnapshot { drive: String, id: u64 },
 }
 
 impl From<std::io::Error> for VfsError {
@@ -0,0 +1,138 @@
+use crate::types::core::ProcessId;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// IPC Request format for the wallet:distro:sys runtime module, which holds the
+/// node's EVM key, or the address of a key held elsewhere, such as on a hardware
+/// wallet, and signs with it for local processes.
+///
+/// Signing requires the capability of its kind (see [`WalletCapabilityParams`])
+/// and, for every signature, the user's consent: a signing request is held as
+/// a [`PendingSignature`] until the user approves or denies it, and is answered
+/// only then. Requests left unanswered for 5 minutes fail with
+/// [`WalletError::TimedOut`].
+///
+/// Managing the key and answering pending requests requires the root
+/// capability, `{"root":true}`, as given to the settings app.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum WalletAction {
+    /// Get the address signatures are made with, if a key is set.
+    /// Responds with [`WalletResponse::Address`].
+    GetAddress,
+    /// Sign a message as `personal_sign` does (EIP-191).
+    /// Requires the `sign-message` capability.
+    /// Responds with [`WalletResponse::Signature`] once approved.
+    SignMessage(Vec<u8>),
+    /// Sign EIP-712 typed data, given as JSON in the format of `eth_signTypedData_v4`.
+    /// Requires the `sign-typed-data` capability.
+    /// Responds with [`WalletResponse::Signature`] once approved.
+    SignTypedData(serde_json::Value),
+    /// Sign a transaction, given as an RPC transaction request with every field
+    /// needed to sign it set, including nonce, gas limit, fees and chain ID.
+    /// Requires the `sign-transaction` capability. Not supported with an external key.
+    /// Responds with [`WalletResponse::SignedTransaction`] once approved.
+    SignTransaction(serde_json::Value),
+    /// Generate a new key. Fails with [`WalletError::KeyExists`] if one is set.
+    /// Requires the root capability. Responds with [`WalletResponse::Address`].
+    GenerateKey,
+    /// Import a hex-encoded private key. Fails with [`WalletError::KeyExists`] if one is set.
+    /// Requires the root capability. Responds with [`WalletResponse::Address`].
+    ImportKey(String),
+    /// Sign with a key held elsewhere, such as on a hardware wallet, at the given address.
+    /// Approvals then carry the signature, made where the key is.
+    /// Fails with [`WalletError::KeyExists`] if one is set.
+    /// Requires the root capability. Responds with [`WalletResponse::Address`].
+    SetExternal(String),
+    /// Forget the key, failing every pending request.
+    /// Requires the root capability. Responds with [`WalletResponse::Ok`].
+    RemoveKey,
+    /// Requires the root capability. Responds with [`WalletResponse::Pending`].
+    ListPending,
+    /// Approve a pending request. With an external key, `signature` is the hex-encoded
+    /// signature made by it, which is checked against the request before it is sent on.
+    /// Requires the root capability. Responds with [`WalletResponse::Ok`].
+    Approve { id: u64, signature: Option<String> },
+    /// Deny a pending request, which fails with [`WalletError::Denied`].
+    /// Requires the root capability. Responds with [`WalletResponse::Ok`].
+    Deny(u64),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum WalletResponse {
+    Ok,
+    Address(Option<WalletAddress>),
+    /// hex-encoded 65-byte signature
+    Signature(String),
+    /// hex-encoded EIP-2718 transaction, ready for `eth_sendRawTransaction`
+    SignedTransaction(String),
+    Pending(Vec<PendingSignature>),
+    Err(WalletError),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WalletAddress {
+    pub address: String,
+    /// whether the key is held elsewhere, so that approvals must carry its signature
+    pub external: bool,
+}
+
+/// A signing request awaiting the user's consent.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingSignature {
+    pub id: u64,
+    pub process: ProcessId,
+    /// one of the `Sign*` variants of [`WalletAction`]
+    pub request: WalletAction,
+    /// unix timestamp in seconds
+    pub requested_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Error)]
+pub enum WalletError {
+    #[error("no key set")]
+    NoKey,
+    #[error("a key is already set")]
+    KeyExists,
+    #[error("invalid key or address")]
+    InvalidKey,
+    #[error("missing the capability for this request")]
+    NoCap,
+    #[error("the user denied the request")]
+    Denied,
+    #[error("the user did not answer the request in time")]
+    TimedOut,
+    #[error("no pending request {0}")]
+    NoSuchRequest(u64),
+    #[error("signature does not match the request and key")]
+    InvalidSignature,
+    #[error("not supported with an external key")]
+    Unsupported,
+    #[error("wallet got a malformed request")]
+    MalformedRequest,
+    #[error("signing failed: {0}")]
+    SigningError(String),
+    #[error("IO error: {0}")]
+    IOError(String),
+}
+
+/// The JSON parameters of the signing capabilities issued by `wallet:distro:sys`,
+/// which a package requests in its manifest, e.g.
+/// `{"process": "wallet:distro:sys", "params": {"kind": "sign-message"}}`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WalletCapabilityParams {
+    pub kind: WalletCapabilityKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WalletCapabilityKind {
+    SignMessage,
+    SignTypedData,
+    SignTransaction,
+}
+
+impl From<std::io::Error> for WalletError {
+    fn from(err: std::io::Error) -> Self {
+        WalletError::IOError(err.to_string())
+    }
+}